@@ -101,6 +101,7 @@ class MainService : Service() {
                     put("width",SCREEN_INFO.width)
                     put("height",SCREEN_INFO.height)
                     put("scale",SCREEN_INFO.scale)
+                    put("rotation",screenRotation())
                 }.toString()
             }
             "is_start" -> {
@@ -255,6 +256,18 @@ class MainService : Service() {
     }
 
     private var isHalfScale: Boolean? = null;
+    // Degrees, clockwise, the same as the rotation of the displays on the other platforms.
+    private fun screenRotation(): Int {
+        val windowManager = getSystemService(Context.WINDOW_SERVICE) as WindowManager
+        @Suppress("DEPRECATION")
+        return when (windowManager.defaultDisplay.rotation) {
+            Surface.ROTATION_90 -> 90
+            Surface.ROTATION_180 -> 180
+            Surface.ROTATION_270 -> 270
+            else -> 0
+        }
+    }
+
     private fun updateScreenInfo(orientation: Int) {
         var w: Int
         var h: Int
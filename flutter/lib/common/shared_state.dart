@@ -51,6 +51,30 @@ class BlockInputState {
   static RxBool find(String id) => Get.find<RxBool>(tag: tag(id));
 }
 
+// Drawing the annotations on the display of the peer.
+class AnnotateState {
+  static String tag(String id) => 'annotate_$id';
+
+  static void init(String id) {
+    final key = tag(id);
+    if (!Get.isRegistered<RxBool>(tag: key)) {
+      final RxBool state = false.obs;
+      Get.put<RxBool>(state, tag: key);
+    } else {
+      Get.find<RxBool>(tag: key).value = false;
+    }
+  }
+
+  static void delete(String id) {
+    final key = tag(id);
+    if (Get.isRegistered<RxBool>(tag: key)) {
+      Get.delete<RxBool>(tag: key);
+    }
+  }
+
+  static RxBool find(String id) => Get.find<RxBool>(tag: tag(id));
+}
+
 class CurrentDisplayState {
   static String tag(String id) => 'current_display_$id';
 
@@ -335,6 +359,7 @@ class UnreadChatCountState {
 initSharedStates(String id) {
   PrivacyModeState.init(id);
   BlockInputState.init(id);
+  AnnotateState.init(id);
   CurrentDisplayState.init(id);
   KeyboardEnabledState.init(id);
   ShowRemoteCursorState.init(id);
@@ -349,6 +374,7 @@ initSharedStates(String id) {
 removeSharedStates(String id) {
   PrivacyModeState.delete(id);
   BlockInputState.delete(id);
+  AnnotateState.delete(id);
   CurrentDisplayState.delete(id);
   ShowRemoteCursorState.delete(id);
   ShowRemoteCursorLockState.delete(id);
@@ -79,3 +79,41 @@ class AudioInput extends StatelessWidget {
     );
   }
 }
+
+// The output device of the voice call, '' for the default one.
+class AudioOutput extends StatelessWidget {
+  final AudioInputBuilder builder;
+  final bool isCm;
+
+  const AudioOutput({Key? key, required this.builder, required this.isCm})
+      : super(key: key);
+
+  static String getDefault() => translate('Default');
+
+  static Future<void> setDevice(String device, bool isCm) async {
+    if (device == getDefault()) device = '';
+    await bind.setVoiceCallOutputDevice(isCm: isCm, device: device);
+  }
+
+  static Future<Map<String, Object>> getDevicesInfo(bool isCm) async {
+    List<String> devices = (await bind.mainGetSoundOutputs()).toList();
+    devices.insert(0, getDefault());
+    String current = await bind.getVoiceCallOutputDevice(isCm: isCm);
+    if (current.isEmpty) current = getDefault();
+    return {'devices': devices, 'current': current};
+  }
+
+  @override
+  Widget build(BuildContext context) {
+    return futureBuilder(
+      future: getDevicesInfo(isCm),
+      hasData: (data) {
+        String currentDevice = data['current'];
+        List<String> devices = data['devices'] as List<String>;
+        return builder(devices, currentDevice, (device) {
+          setDevice(device, isCm);
+        });
+      },
+    );
+  }
+}
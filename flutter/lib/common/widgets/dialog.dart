@@ -1302,6 +1302,38 @@ void showRestartRemoteDevice(PeerInfo pi, String id, SessionID sessionId,
   if (res == true) bind.sessionRestartRemoteDevice(sessionId: sessionId);
 }
 
+// The host asks to confirm the power action, it runs only if the user accepts.
+void showPowerActionConfirm(SessionID sessionId, String action,
+    OverlayDialogManager dialogManager) async {
+  final res = await dialogManager
+      .show<bool>((setState, close, context) => CustomAlertDialog(
+            title: Row(children: [
+              Icon(Icons.warning_rounded, color: Colors.redAccent, size: 28),
+              Flexible(
+                  child: Text(translate("Power action"))
+                      .paddingOnly(left: 10)),
+            ]),
+            content: Text(
+                "${translate('power-action-confirm-tip')}\n${translate(action)}"),
+            actions: [
+              dialogButton(
+                "Cancel",
+                icon: Icon(Icons.close_rounded),
+                onPressed: close,
+                isOutline: true,
+              ),
+              dialogButton(
+                "OK",
+                icon: Icon(Icons.done_rounded),
+                onPressed: () => close(true),
+              ),
+            ],
+            onCancel: close,
+            onSubmit: () => close(true),
+          ));
+  bind.sessionConfirmPowerAction(sessionId: sessionId, confirmed: res == true);
+}
+
 showSetOSPassword(
   SessionID sessionId,
   bool login,
@@ -1549,6 +1581,73 @@ void showConfirmSwitchSidesDialog(
   });
 }
 
+// The quick actions defined on the peer, an action runs with one click, its output is shown below.
+void showQuickActionsDialog(FFI ffi) {
+  final model = ffi.quickActionsModel;
+  model.request();
+  ffi.dialogManager.show((setState, close, context) {
+    return CustomAlertDialog(
+      title: Text(translate('Quick actions')),
+      content: Obx(() => Column(
+            mainAxisSize: MainAxisSize.min,
+            crossAxisAlignment: CrossAxisAlignment.start,
+            children: [
+              if (model.error.isNotEmpty)
+                Text(translate(model.error.value),
+                    style: const TextStyle(color: Colors.red)),
+              ...model.actions.map((name) => ListTile(
+                    dense: true,
+                    title: Text(name),
+                    trailing: IconButton(
+                      icon: const Icon(Icons.play_arrow_rounded),
+                      tooltip: translate('Run'),
+                      onPressed: model.done.isTrue ? () => model.run(name) : null,
+                    ),
+                  )),
+              if (model.running.isNotEmpty) ...[
+                const Divider(),
+                Text(model.done.isTrue
+                    ? '${model.running.value}: ${translate('Exit code')} ${model.exitCode.value}'
+                    : '${model.running.value}: ${translate('Running')}'),
+                Container(
+                  constraints: const BoxConstraints(maxHeight: 240),
+                  child: SingleChildScrollView(
+                    child: SelectableText(model.output.value,
+                        style: const TextStyle(
+                            fontFamily: 'monospace', fontSize: 12)),
+                  ),
+                ),
+                if (model.truncated.isTrue)
+                  Text(translate('The output is truncated')),
+              ],
+            ],
+          )),
+      actions: [dialogButton('Close', onPressed: close)],
+      onCancel: close,
+    );
+  });
+}
+
+void showQuickActionConfirmDialog(FFI ffi, String name, String token) {
+  ffi.dialogManager.show((setState, close, context) {
+    submit() {
+      ffi.quickActionsModel.run(name, token: token);
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Quick actions')),
+      content: Text('${translate('quick-action-confirm-tip')}\n$name'),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
 customImageQualityDialog(SessionID sessionId, String id, FFI ffi) async {
   double initQuality = kDefaultQuality;
   double initFps = kDefaultFps;
@@ -552,6 +552,13 @@ class QualityMonitor extends StatelessWidget {
   final QualityMonitorModel qualityMonitorModel;
   QualityMonitor(this.qualityMonitorModel);
 
+  static const _frameDropLabels = {
+    'bandwidth': 'Dropped (Network)',
+    'encoder_overrun': 'Dropped (Encoder)',
+    'queue_full': 'Dropped (Queue)',
+    'paused': 'Dropped (Paused)',
+  };
+
   Widget _row(String info, String? value, {Color? rightColor}) {
     return Row(
       children: [
@@ -596,6 +603,13 @@ class QualityMonitor extends StatelessWidget {
                       _row(
                           "Codec", qualityMonitorModel.data.codecFormat ?? '-'),
                       _row("Chroma", qualityMonitorModel.data.chroma ?? '-'),
+                      if (qualityMonitorModel.data.cameraFrames != null)
+                        _row("Camera frames",
+                            qualityMonitorModel.data.cameraFrames!),
+                      ...qualityMonitorModel.data.frameDrops.entries
+                          .where((e) => e.value > 0)
+                          .map((e) => _row(_frameDropLabels[e.key] ?? e.key,
+                              e.value.toString())),
                     ],
                   ),
                 )
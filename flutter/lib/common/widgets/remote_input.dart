@@ -83,7 +83,6 @@ class _RawTouchGestureDetectorRegionState
   Offset _cacheLongPressPosition = Offset(0, 0);
   // Timestamp of the last long press event.
   int _cacheLongPressPositionTs = 0;
-  double _mouseScrollIntegral = 0; // mouse scroll speed controller
   double _scale = 1;
 
   // Workaround tap down event when two fingers are used to scale(mobile)
@@ -424,18 +423,16 @@ class _RawTouchGestureDetectorRegionState
   }
 
   get onHoldDragCancel => null;
+  get onThreeFingerVerticalDragStart => ffi.ffiModel.isPeerAndroid
+      ? null
+      : (d) => inputModel.scrollGesture('begin');
   get onThreeFingerVerticalDragUpdate => ffi.ffiModel.isPeerAndroid
       ? null
-      : (d) {
-          _mouseScrollIntegral += d.delta.dy / 4;
-          if (_mouseScrollIntegral > 1) {
-            inputModel.scroll(1);
-            _mouseScrollIntegral = 0;
-          } else if (_mouseScrollIntegral < -1) {
-            inputModel.scroll(-1);
-            _mouseScrollIntegral = 0;
-          }
-        };
+      : (d) => inputModel.scrollGesture('update', dy: d.delta.dy);
+  get onThreeFingerVerticalDragEnd => ffi.ffiModel.isPeerAndroid
+      ? null
+      : (d) => inputModel.scrollGesture('end',
+          velocityY: d.velocity.pixelsPerSecond.dy);
 
   makeGestures(BuildContext context) {
     return <Type, GestureRecognizerFactory>{
@@ -491,7 +488,9 @@ class _RawTouchGestureDetectorRegionState
           ..onTwoFingerScaleStart = onTwoFingerScaleStart
           ..onTwoFingerScaleUpdate = onTwoFingerScaleUpdate
           ..onTwoFingerScaleEnd = onTwoFingerScaleEnd
-          ..onThreeFingerVerticalDragUpdate = onThreeFingerVerticalDragUpdate;
+          ..onThreeFingerVerticalDragStart = onThreeFingerVerticalDragStart
+          ..onThreeFingerVerticalDragUpdate = onThreeFingerVerticalDragUpdate
+          ..onThreeFingerVerticalDragEnd = onThreeFingerVerticalDragEnd;
       }),
     };
   }
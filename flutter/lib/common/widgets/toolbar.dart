@@ -1,5 +1,6 @@
 import 'dart:async';
 import 'dart:convert';
+import 'dart:typed_data';
 
 import 'package:flutter/material.dart';
 import 'package:flutter/services.dart';
@@ -231,6 +232,16 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
               showRestartRemoteDevice(pi, id, sessionId, ffi.dialogManager)),
     );
   }
+  // quick actions
+  if (isDefaultConn &&
+      perms['quick_actions'] != false &&
+      pi.platform != kPeerPlatformAndroid) {
+    v.add(
+      TTextMenu(
+          child: Text(translate('Quick actions')),
+          onPressed: () => showQuickActionsDialog(ffi)),
+    );
+  }
   // insertLock
   if (isDefaultConn && !ffiModel.viewOnly && ffi.ffiModel.keyboard) {
     v.add(
@@ -239,6 +250,52 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
           onPressed: () => bind.sessionLockScreen(sessionId: sessionId)),
     );
   }
+  // requestClipboard
+  if (isDefaultConn &&
+      pi.isManualClipboard &&
+      perms['clipboard'] != false) {
+    v.add(
+      TTextMenu(
+          child: Text(translate('Send clipboard')),
+          onPressed: () =>
+              bind.sessionRequestClipboard(sessionId: sessionId)),
+    );
+  }
+  // controlToken
+  if (isDefaultConn && pi.isExclusiveControl && ffi.ffiModel.keyboard) {
+    final holding = ffi.ffiModel.controlTokenHolding;
+    final queued = ffi.ffiModel.controlTokenQueued;
+    final holder = ffi.ffiModel.controlTokenHolder;
+    var text = translate(holding
+        ? 'Release control'
+        : queued
+            ? 'Cancel control request'
+            : 'Request control');
+    if (!holding && holder.isNotEmpty) {
+      text += ' ($holder)';
+    }
+    v.add(
+      TTextMenu(
+          child: Text(text),
+          onPressed: () => bind.sessionRequestControlToken(
+              sessionId: sessionId, request: !holding && !queued)),
+    );
+  }
+  // hostTextScale
+  if (isDefaultConn && pi.textScale > 0 && ffi.ffiModel.keyboard) {
+    final enlarged = ffi.ffiModel.hostTextEnlarged;
+    v.add(
+      TTextMenu(
+          child: Text(translate(
+              enlarged ? 'Restore host text size' : 'Enlarge host text')),
+          onPressed: () {
+            ffi.ffiModel.hostTextEnlarged = !enlarged;
+            // 0 restores the original scaling of the peer.
+            bind.sessionSetHostTextScale(
+                sessionId: sessionId, percent: enlarged ? 0 : 150);
+          }),
+    );
+  }
   // blockUserInput
   if (isDefaultConn &&
       ffi.ffiModel.keyboard &&
@@ -256,6 +313,29 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
           blockInput.value = !blockInput.value;
         }));
   }
+  // annotate
+  if (isDefaultConn &&
+      isDesktop &&
+      ffi.ffiModel.permissions['annotation'] != false &&
+      pi.platform != kPeerPlatformAndroid &&
+      pi.currentDisplay != kAllDisplayValue) {
+    final annotate = AnnotateState.find(id);
+    v.add(TTextMenu(
+        child: Obx(() => Text(
+            translate(annotate.value ? 'Stop annotating' : 'Annotate'))),
+        onPressed: () => annotate.value = !annotate.value));
+    v.add(TTextMenu(
+        child: Text(translate('Clear annotations')),
+        onPressed: () => bind.sessionSendAnnotation(
+            sessionId: sessionId,
+            display: pi.currentDisplay,
+            shape: kAnnotationShapeClear,
+            points: Int32List(0),
+            color: 0,
+            width: 0,
+            text: '',
+            fontSize: 0)));
+  }
   // switchSides
   if (isDefaultConn &&
       isDesktop &&
@@ -331,6 +411,14 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
               },
       ));
     }
+    // The screenshot and the log of the host for the bug reports.
+    if (perms['file'] != false && pi.currentDisplay != kAllDisplayValue) {
+      v.add(TTextMenu(
+        child: Text(translate('Diagnostic capture')),
+        onPressed: () => bind.sessionRequestDiagnosticCapture(
+            sessionId: sessionId, display: pi.currentDisplay),
+      ));
+    }
   }
   // fingerprint
   if (!(isDesktop || isWebDesktop)) {
@@ -339,6 +427,17 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
       onPressed: () => onCopyFingerprint(FingerprintState.find(id).value),
     ));
   }
+  // To compare with the one shown on the host, e.g. on the phone.
+  v.add(TTextMenu(
+    child: Text(translate('Session fingerprint')),
+    onPressed: () => msgBox(
+        sessionId,
+        'custom-nook-nocancel-hasclose-info',
+        'Session fingerprint',
+        bind.sessionGetFingerprint(sessionId: sessionId),
+        '',
+        ffi.dialogManager),
+  ));
   return v;
 }
 
@@ -13,6 +13,11 @@ const int kMainWindowId = 0;
 
 const kAllDisplayValue = -1;
 
+// Annotation.Shape of the message.
+const kAnnotationShapePath = 0;
+const kAnnotationShapeClear = 4;
+const kAnnotationMaxPoints = 4096;
+
 const kKeyLegacyMode = 'legacy';
 const kKeyMapMode = 'map';
 const kKeyTranslateMode = 'translate';
@@ -26,6 +31,13 @@ const String kPlatformAdditionsRustDeskVirtualDisplays =
 const String kPlatformAdditionsAmyuniVirtualDisplays =
     "amyuni_virtual_displays";
 const String kPlatformAdditionsHasFileClipboard = "has_file_clipboard";
+const String kPlatformAdditionsManualClipboard = "manual_clipboard";
+const String kPlatformAdditionsExclusiveControl = "exclusive_control";
+const String kPlatformAdditionsTextScale = "text_scale";
+const String kPlatformAdditionsDisplayDpis = "display_dpis";
+const String kPlatformAdditionsCaptureEnv = "capture_env";
+const String kPlatformAdditionsIsRoot = "is_root";
+const String kPlatformAdditionsIsService = "is_service";
 const String kPlatformAdditionsSupportedPrivacyModeImpl =
     "supported_privacy_mode_impl";
 
@@ -94,6 +106,7 @@ const String kOptionDirectAccessPort = "direct-access-port";
 const String kOptionAllowAutoDisconnect = "allow-auto-disconnect";
 const String kOptionAutoDisconnectTimeout = "auto-disconnect-timeout";
 const String kOptionEnableHwcodec = "enable-hwcodec";
+const String kOptionAudioCaptureExclusions = "audio-capture-exclusions";
 const String kOptionAllowAutoRecordIncoming = "allow-auto-record-incoming";
 const String kOptionAllowAutoRecordOutgoing = "allow-auto-record-outgoing";
 const String kOptionVideoSaveDirectory = "video-save-directory";
@@ -110,6 +123,7 @@ const String kOptionTerminalPersistent = "terminal-persistent";
 const String kOptionEnableTunnel = "enable-tunnel";
 const String kOptionEnableRemoteRestart = "enable-remote-restart";
 const String kOptionEnableBlockInput = "enable-block-input";
+const String kOptionAllowVirtualMic = "allow-virtual-mic";
 const String kOptionAllowRemoteConfigModification =
     "allow-remote-config-modification";
 const String kOptionVerificationMethod = "verification-method";
@@ -188,6 +202,10 @@ const int kWindowMainId = 0;
 
 const String kPointerEventKindTouch = "touch";
 const String kPointerEventKindMouse = "mouse";
+const String kPointerEventKindScroll = "scroll";
+
+// The wheel units (120 for one notch) of a pixel of a scroll gesture.
+const double kScrollGestureUnitsPerPixel = 30;
 
 const String kMouseEventTypeDefault = "";
 const String kMouseEventTypePanStart = "pan_start";
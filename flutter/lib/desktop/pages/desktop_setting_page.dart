@@ -418,6 +418,7 @@ class _GeneralState extends State<_General> {
         _Card(title: 'Language', children: [language()]),
         if (!isWeb) hwcodec(),
         if (!isWeb) audio(context),
+        if (!isWeb) audioCaptureExclusions(context),
         if (!isWeb) record(context),
         if (!isWeb) WaylandCard(),
         other()
@@ -643,6 +644,62 @@ class _GeneralState extends State<_General> {
     return AudioInput(builder: builder, isCm: false, isVoiceCall: false);
   }
 
+  Widget audioCaptureExclusions(BuildContext context) {
+    if (bind.isOutgoingOnly()) {
+      return const Offstage();
+    }
+    return futureBuilder(future: () async {
+      try {
+        return jsonDecode(await bind.mainGetAudioCaptureExclusions())
+            as Map<String, dynamic>;
+      } catch (_) {
+        return <String, dynamic>{};
+      }
+    }(), hasData: (data) {
+      final value = data as Map<String, dynamic>;
+      final supported = value['supported'] == true;
+      final names = (value['names'] as List<dynamic>?) ?? [];
+      final controller = TextEditingController(text: names.join(', '));
+      final applyEnabled = false.obs;
+      final enabled =
+          supported && !isOptionFixed(kOptionAudioCaptureExclusions);
+      return _Card(title: 'Audio capture exclusions', children: [
+        Row(children: [
+          Expanded(
+            child: TextField(
+              controller: controller,
+              enabled: enabled,
+              onChanged: (_) => applyEnabled.value = true,
+              decoration: InputDecoration(
+                hintText: translate('audio-capture-exclusions-tip'),
+                contentPadding:
+                    EdgeInsets.symmetric(vertical: 12, horizontal: 12),
+              ),
+            ).workaroundFreezeLinuxMint().marginOnly(right: 15),
+          ),
+          Obx(() => ElevatedButton(
+                onPressed: applyEnabled.value && enabled
+                    ? () async {
+                        applyEnabled.value = false;
+                        await bind.mainSetAudioCaptureExclusions(
+                            names: controller.text);
+                        setState(() {});
+                      }
+                    : null,
+                child: Text(
+                  translate('Apply'),
+                ),
+              ))
+        ]).marginOnly(left: _kContentHMargin),
+        if (!supported)
+          Text(
+            translate('audio-capture-exclusions-unsupported-tip'),
+            style: TextStyle(color: Theme.of(context).hintColor),
+          ).marginOnly(left: _kContentHMargin, top: 8),
+      ]);
+    });
+  }
+
   Widget record(BuildContext context) {
     final showRootDir = isWindows && bind.mainIsInstalled();
     return futureBuilder(future: () async {
@@ -1011,6 +1068,10 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
                 enabled: enabled, fakeValue: fakeValue),
             _OptionCheckBox(context, 'Enable camera', kOptionEnableCamera,
                 enabled: enabled, fakeValue: fakeValue),
+            if (isWindows || isLinux)
+              _OptionCheckBox(
+                  context, 'Allow virtual microphone', kOptionAllowVirtualMic,
+                  enabled: enabled, fakeValue: fakeValue),
             _OptionCheckBox(context, 'Enable terminal', kOptionEnableTerminal,
                 enabled: enabled, fakeValue: fakeValue),
             _OptionCheckBox(
@@ -1,4 +1,6 @@
 import 'dart:async';
+import 'dart:typed_data';
+import 'dart:ui' as ui;
 
 import 'package:desktop_multi_window/desktop_multi_window.dart';
 import 'package:flutter/material.dart';
@@ -180,6 +182,7 @@ class _RemotePageState extends State<RemotePage>
       _rawKeyFocusNode.unfocus();
     }
     stateGlobal.isFocused.value = false;
+    bind.sessionSetClientFocus(sessionId: sessionId, focused: false);
   }
 
   @override
@@ -190,6 +193,7 @@ class _RemotePageState extends State<RemotePage>
       _isWindowBlur = false;
     }
     stateGlobal.isFocused.value = true;
+    bind.sessionSetClientFocus(sessionId: sessionId, focused: true);
   }
 
   @override
@@ -203,6 +207,8 @@ class _RemotePageState extends State<RemotePage>
     if (!isLinux) {
       WakelockPlus.enable();
     }
+    bind.sessionSetClientVisibility(
+        sessionId: sessionId, visible: true, minimized: false);
   }
 
   // When the window is unminimized, onWindowMaximize or onWindowRestore can be called when the old state was maximized or not.
@@ -212,6 +218,8 @@ class _RemotePageState extends State<RemotePage>
     if (!isLinux) {
       WakelockPlus.enable();
     }
+    bind.sessionSetClientVisibility(
+        sessionId: sessionId, visible: true, minimized: false);
   }
 
   @override
@@ -220,6 +228,8 @@ class _RemotePageState extends State<RemotePage>
     if (!isLinux) {
       WakelockPlus.disable();
     }
+    bind.sessionSetClientVisibility(
+        sessionId: sessionId, visible: false, minimized: true);
   }
 
   @override
@@ -525,6 +535,9 @@ class _RemotePageState extends State<RemotePage>
                   zoomCursor: _zoomCursor,
                 )));
     }
+    paints.add(Obx(() => AnnotateState.find(widget.id).isFalse
+        ? Offstage()
+        : Positioned.fill(child: AnnotationLayer(ffi: _ffi))));
     paints.add(
       Positioned(
         top: 10,
@@ -825,6 +838,96 @@ class _ImagePaintState extends State<ImagePaint> {
   }
 }
 
+// Takes the pointer while annotating, the freehand paths are sent to the peer when the button is
+// released, or when they have the most points of one annotation.
+class AnnotationLayer extends StatefulWidget {
+  final FFI ffi;
+
+  const AnnotationLayer({Key? key, required this.ffi}) : super(key: key);
+
+  @override
+  State<AnnotationLayer> createState() => _AnnotationLayerState();
+}
+
+class _AnnotationLayerState extends State<AnnotationLayer> {
+  static const _color = 0xFF0000;
+  static const _width = 4;
+  final List<Offset> _stroke = [];
+  final List<int> _points = [];
+
+  void _add(PointerEvent e) {
+    final p = widget.ffi.inputModel.annotationPoint(e.position);
+    if (p == null) {
+      return;
+    }
+    final n = _points.length;
+    if (n >= 2 && _points[n - 2] == p.x && _points[n - 1] == p.y) {
+      return;
+    }
+    _points.addAll([p.x, p.y]);
+    setState(() => _stroke.add(e.localPosition));
+    if (_points.length >= kAnnotationMaxPoints * 2) {
+      _send();
+      _points.addAll([p.x, p.y]);
+    }
+  }
+
+  void _send() {
+    final display = widget.ffi.ffiModel.pi.currentDisplay;
+    if (_points.isNotEmpty && display != kAllDisplayValue) {
+      bind.sessionSendAnnotation(
+          sessionId: widget.ffi.sessionId,
+          display: display,
+          shape: kAnnotationShapePath,
+          points: Int32List.fromList(_points),
+          color: _color,
+          width: _width,
+          text: '',
+          fontSize: 0);
+    }
+    _points.clear();
+  }
+
+  @override
+  Widget build(BuildContext context) {
+    return MouseRegion(
+      cursor: SystemMouseCursors.precise,
+      child: Listener(
+        behavior: HitTestBehavior.opaque,
+        onPointerDown: (e) {
+          if (e.buttons == kPrimaryMouseButton) _add(e);
+        },
+        onPointerMove: (e) {
+          if (e.buttons == kPrimaryMouseButton) _add(e);
+        },
+        onPointerUp: (e) {
+          _send();
+          setState(() => _stroke.clear());
+        },
+        child: CustomPaint(painter: _StrokePainter(_stroke)),
+      ),
+    );
+  }
+}
+
+class _StrokePainter extends CustomPainter {
+  final List<Offset> stroke;
+
+  _StrokePainter(this.stroke);
+
+  @override
+  void paint(Canvas canvas, Size size) {
+    final paint = Paint()
+      ..color = Colors.red
+      ..strokeWidth = 2
+      ..style = PaintingStyle.stroke;
+    canvas.drawPoints(ui.PointMode.polygon, stroke, paint);
+  }
+
+  @override
+  bool shouldRepaint(covariant CustomPainter oldDelegate) => true;
+}
+
 class CursorPaint extends StatelessWidget {
   final String id;
   final RxBool zoomCursor;
@@ -529,6 +529,14 @@ class _CmHeaderState extends State<_CmHeader>
                       style: TextStyle(color: Colors.white70, fontSize: 12),
                     ),
                   ),
+                // To compare with the one shown on the client, e.g. on the phone.
+                if (client.fingerprint.isNotEmpty)
+                  FittedBox(
+                    child: Text(
+                      "${translate('Session fingerprint')}: ${translate(client.fingerprint)}",
+                      style: TextStyle(color: Colors.white70, fontSize: 12),
+                    ),
+                  ),
                 SizedBox(height: 10.0),
                 FittedBox(
                     child: Row(
@@ -791,7 +799,36 @@ class _PrivilegeBoardState extends State<_PrivilegeBoard> {
                             });
                           },
                           translate('Enable blocking user input'),
-                        )
+                        ),
+                      if (isWindows || isLinux)
+                        buildPermissionIcon(
+                          client.annotation,
+                          Icons.draw_rounded,
+                          (enabled) {
+                            bind.cmSwitchPermission(
+                                connId: client.id,
+                                name: "annotation",
+                                enabled: enabled);
+                            setState(() {
+                              client.annotation = enabled;
+                            });
+                          },
+                          translate('Enable annotation'),
+                        ),
+                      buildPermissionIcon(
+                        client.quickActions,
+                        Icons.bolt_rounded,
+                        (enabled) {
+                          bind.cmSwitchPermission(
+                              connId: client.id,
+                              name: "quick_actions",
+                              enabled: enabled);
+                          setState(() {
+                            client.quickActions = enabled;
+                          });
+                        },
+                        translate('Enable quick actions'),
+                      ),
                     ],
             ),
           ),
@@ -826,6 +863,21 @@ class _CmControlPanel extends StatelessWidget {
     return Column(
       mainAxisAlignment: MainAxisAlignment.end,
       children: [
+        Offstage(
+          offstage: !client.virtualMic,
+          child: Row(
+            children: [
+              Icon(Icons.mic, color: Colors.red, size: 14),
+              Expanded(
+                child: Text(
+                  translate('virtual_mic_live_tip'),
+                  style: TextStyle(color: Colors.red),
+                  overflow: TextOverflow.ellipsis,
+                ).marginOnly(left: 4),
+              ),
+            ],
+          ).marginOnly(bottom: 4),
+        ),
         Offstage(
           offstage: !client.inVoiceCall,
           child: Row(
@@ -850,41 +902,51 @@ class _CmControlPanel extends StatelessWidget {
                   }
 
                   String currentDevice = devicesInfo['current'] as String;
+                  final outputsInfo = await AudioOutput.getDevicesInfo(true);
+                  final outputs = outputsInfo['devices'] as List<String>;
+                  final currentOutput = outputsInfo['current'] as String;
                   final x = details.globalPosition.dx;
                   final y = details.globalPosition.dy;
                   final position = RelativeRect.fromLTRB(x, y, x, y);
+                  PopupMenuItem<String> deviceItem(String d, String current,
+                      void Function(String) setDevice) {
+                    return PopupMenuItem<String>(
+                      value: d,
+                      height: 18,
+                      padding: EdgeInsets.zero,
+                      onTap: () => setDevice(d),
+                      child: IgnorePointer(
+                          child: RadioMenuButton(
+                        value: d,
+                        groupValue: current,
+                        onChanged: (v) {
+                          if (v != null) setDevice(v);
+                        },
+                        child: Container(
+                          child: Text(
+                            d,
+                            overflow: TextOverflow.ellipsis,
+                            maxLines: 1,
+                          ),
+                          constraints: BoxConstraints(
+                              maxWidth:
+                                  kConnectionManagerWindowSizeClosedChat.width -
+                                      80),
+                        ),
+                      )),
+                    );
+                  }
+
                   showMenu(
                     context: context,
                     position: position,
-                    items: devices
-                        .map((d) => PopupMenuItem<String>(
-                              value: d,
-                              height: 18,
-                              padding: EdgeInsets.zero,
-                              onTap: () => AudioInput.setDevice(d, true, true),
-                              child: IgnorePointer(
-                                  child: RadioMenuButton(
-                                value: d,
-                                groupValue: currentDevice,
-                                onChanged: (v) {
-                                  if (v != null)
-                                    AudioInput.setDevice(v, true, true);
-                                },
-                                child: Container(
-                                  child: Text(
-                                    d,
-                                    overflow: TextOverflow.ellipsis,
-                                    maxLines: 1,
-                                  ),
-                                  constraints: BoxConstraints(
-                                      maxWidth:
-                                          kConnectionManagerWindowSizeClosedChat
-                                                  .width -
-                                              80),
-                                ),
-                              )),
-                            ))
-                        .toList(),
+                    items: <PopupMenuEntry<String>>[
+                      ...devices.map((d) => deviceItem(d, currentDevice,
+                          (v) => AudioInput.setDevice(v, true, true))),
+                      PopupMenuDivider(),
+                      ...outputs.map((d) => deviceItem(d, currentOutput,
+                          (v) => AudioOutput.setDevice(v, true))),
+                    ],
                   );
                 },
                     icon: Icon(
@@ -1818,7 +1818,7 @@ class _ChatMenuState extends State<_ChatMenu> {
           ffi: widget.ffi,
           color: _ToolbarTheme.blueColor,
           hoverColor: _ToolbarTheme.hoverBlueColor,
-          menuChildrenGetter: () => [textChat(), voiceCall()]);
+          menuChildrenGetter: () => [textChat(), voiceCall(), virtualMic()]);
     }
   }
 
@@ -1861,6 +1861,20 @@ class _ChatMenuState extends State<_ChatMenu> {
           bind.sessionRequestVoiceCall(sessionId: widget.ffi.sessionId),
     );
   }
+
+  // The remote side plays the voice call into its virtual microphone, if it
+  // allows it.
+  virtualMic() {
+    const option = 'voice-call-virtual-mic';
+    final sessionId = widget.ffi.sessionId;
+    return CkbMenuButton(
+      value: bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option),
+      onChanged: (_) =>
+          bind.sessionToggleOption(sessionId: sessionId, value: option),
+      child: Text(translate('Voice call into the virtual microphone')),
+      ffi: widget.ffi,
+    );
+  }
 }
 
 class _VoiceCallMenu extends StatelessWidget {
@@ -1875,34 +1889,40 @@ class _VoiceCallMenu extends StatelessWidget {
   @override
   Widget build(BuildContext context) {
     menuChildrenGetter() {
-      final audioInput = AudioInput(
-        builder: (devices, currentDevice, setDevice) {
-          return Column(
-            children: devices
-                .map((d) => RdoMenuButton<String>(
-                      child: Container(
-                        child: Text(
-                          d,
-                          overflow: TextOverflow.ellipsis,
-                        ),
-                        constraints: BoxConstraints(maxWidth: 250),
+      Widget devicesBuilder(List<String> devices, String currentDevice,
+          AudioINputSetDevice setDevice) {
+        return Column(
+          children: devices
+              .map((d) => RdoMenuButton<String>(
+                    child: Container(
+                      child: Text(
+                        d,
+                        overflow: TextOverflow.ellipsis,
                       ),
-                      value: d,
-                      groupValue: currentDevice,
-                      onChanged: (v) {
-                        if (v != null) setDevice(v);
-                      },
-                      ffi: ffi,
-                    ))
-                .toList(),
-          );
-        },
+                      constraints: BoxConstraints(maxWidth: 250),
+                    ),
+                    value: d,
+                    groupValue: currentDevice,
+                    onChanged: (v) {
+                      if (v != null) setDevice(v);
+                    },
+                    ffi: ffi,
+                  ))
+              .toList(),
+        );
+      }
+
+      final audioInput = AudioInput(
+        builder: devicesBuilder,
         isCm: false,
         isVoiceCall: true,
       );
+      final audioOutput = AudioOutput(builder: devicesBuilder, isCm: false);
       return [
         audioInput,
         Divider(),
+        audioOutput,
+        Divider(),
         MenuButton(
           child: Text(translate('End call')),
           onPressed: () => bind.sessionCloseVoiceCall(sessionId: ffi.sessionId),
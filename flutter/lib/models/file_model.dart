@@ -107,6 +107,14 @@ class FileModel {
     fileFetcher.tryCompleteTask(evt['value'], evt['is_local']);
   }
 
+  void receiveFileDirPage(Map<String, dynamic> evt) {
+    final fd = fileFetcher.tryAddPage(evt['value'],
+        int.tryParse(evt['offset'] ?? '') ?? 0, evt['done'] == 'true');
+    if (fd != null) {
+      remoteController.initDirAndHomeWith(fd);
+    }
+  }
+
   void receiveEmptyDirs(Map<String, dynamic> evt) {
     fileFetcher.tryCompleteEmptyDirsTask(evt['value'], evt['is_local']);
   }
@@ -475,7 +483,14 @@ class FileController {
   // TODO deprecated this
   void initDirAndHome(Map<String, dynamic> evt) {
     try {
-      final fd = FileDirectory.fromJson(jsonDecode(evt['value']));
+      initDirAndHomeWith(FileDirectory.fromJson(jsonDecode(evt['value'])));
+    } catch (e) {
+      debugPrint("initDirAndHome err=$e");
+    }
+  }
+
+  void initDirAndHomeWith(FileDirectory fd) {
+    try {
       fd.format(options.value.isWindows, sort: sortBy.value);
       if (fd.id > 0) {
         final jobIndex = jobController.getJob(fd.id);
@@ -1140,6 +1155,8 @@ class FileFetcher {
   Map<String, Completer<FileDirectory>> remoteTasks = {};
   Map<String, Completer<List<FileDirectory>>> remoteEmptyDirsTasks = {};
   Map<int, Completer<FileDirectory>> readRecursiveTasks = {};
+  // The pages received of the remote directories being listed, by path.
+  Map<String, FileDirectory> remotePages = {};
 
   final GetSessionID getSessionID;
   SessionID get sessionId => getSessionID();
@@ -1172,13 +1189,49 @@ class FileFetcher {
     }
     final c = Completer<FileDirectory>();
     tasks[path] = c;
+    _timeoutReadTask(path, c, 0);
+    return c.future;
+  }
 
+  // A large directory comes in pages, it's waited for while they keep coming.
+  void _timeoutReadTask(
+      String path, Completer<FileDirectory> c, int received) {
     Timer(Duration(seconds: 2), () {
-      tasks.remove(path);
       if (c.isCompleted) return;
+      final now = remotePages[path]?.entries.length ?? 0;
+      if (now > received) {
+        _timeoutReadTask(path, c, now);
+        return;
+      }
+      remoteTasks.remove(path);
+      remotePages.remove(path);
       c.completeError("Failed to read dir, timeout");
     });
-    return c.future;
+  }
+
+  /// Adds a page of a remote directory, returns the directory when the last page is received.
+  FileDirectory? tryAddPage(String? msg, int offset, bool done) {
+    if (msg == null) return null;
+    try {
+      final page = FileDirectory.fromJson(jsonDecode(msg));
+      final pages = remotePages[page.path];
+      if (offset == 0) {
+        remotePages[page.path] = page;
+      } else if (pages != null) {
+        pages.entries.addAll(page.entries);
+      } else {
+        return null;
+      }
+      if (!done) return null;
+      final fd = remotePages.remove(page.path);
+      if (fd != null) {
+        remoteTasks.remove(page.path)?.complete(fd);
+      }
+      return fd;
+    } catch (e) {
+      debugPrint("tryAddPage err: $e");
+      return null;
+    }
   }
 
   Future<FileDirectory> registerReadRecursiveTask(int actID) {
@@ -822,6 +822,22 @@ class InputModel {
             .encode(modify({'id': id, 'type': 'wheel', 'y': y.toString()})));
   }
 
+  /// Send a scroll gesture, [phase] is 'begin', 'update', 'end' or 'cancel'.
+  /// [dy] and [velocityY] are in pixels, the host scrolls smoothly and flicks
+  /// if it supports it, otherwise the whole wheel notches are sent.
+  Future<void> scrollGesture(String phase,
+      {double dy = 0, double velocityY = 0}) async {
+    if (isViewCamera) return;
+    await bind.sessionSendPointer(
+        sessionId: sessionId,
+        msg: json.encode(PointerEventToRust(kPointerEventKindScroll, phase, {
+          'dx': 0,
+          'dy': (dy * kScrollGestureUnitsPerPixel).round(),
+          'vx': 0,
+          'vy': (velocityY * kScrollGestureUnitsPerPixel).round(),
+        }).toJson()));
+  }
+
   /// Reset key modifiers to false, including [shift], [ctrl], [alt] and [command].
   void resetModifiers() {
     shift = ctrl = alt = command = false;
@@ -1419,6 +1435,24 @@ class InputModel {
         buttons: buttons);
   }
 
+  /// The point in the pixels of the current display of the peer, for the annotations.
+  Point<int>? annotationPoint(Offset position) {
+    final rect = parent.target!.ffiModel.rect;
+    final pos = _handlePointerDevicePos(
+      kPointerEventKindMouse,
+      position.dx - CanvasModel.leftToEdge,
+      position.dy - CanvasModel.topToEdge,
+      false,
+      CanvasCoords.fromCanvasModel(parent.target!.canvasModel),
+      rect,
+      kMouseEventTypeDefault,
+    );
+    if (pos == null || rect == null) {
+      return null;
+    }
+    return Point((pos.x - rect.left).toInt(), (pos.y - rect.top).toInt());
+  }
+
   static Point<double>? getPointInRemoteRect(
       bool isLocalDesktop,
       String? peerPlatform,
@@ -113,6 +113,14 @@ class FfiModel with ChangeNotifier {
   Timer? _timer;
   var _reconnects = 1;
   bool _viewOnly = false;
+  // The capture environment of the host is shown, until it's ok again.
+  bool _captureEnvShown = false;
+  // The control token of the exclusive control mode.
+  bool controlTokenHolding = false;
+  bool controlTokenQueued = false;
+  String controlTokenHolder = '';
+  // The text scaling of the peer is raised for the session.
+  bool hostTextEnlarged = false;
   WeakReference<FFI> parent;
   late final SessionID sessionId;
 
@@ -204,6 +212,9 @@ class FfiModel with ChangeNotifier {
     // Only inited at remote page
     if (parent.target?.connType == ConnType.defaultConn) {
       KeyboardEnabledState.find(id).value = _permissions['keyboard'] != false;
+      if (_permissions['annotation'] == false) {
+        AnnotateState.find(id).value = false;
+      }
     }
     debugPrint('updatePermission: $_permissions');
     notifyListeners();
@@ -216,6 +227,10 @@ class FfiModel with ChangeNotifier {
     _secure = null;
     _direct = null;
     _inputBlocked = false;
+    controlTokenHolding = false;
+    controlTokenQueued = false;
+    controlTokenHolder = '';
+    hostTextEnlarged = false;
     _timer?.cancel();
     _timer = null;
     clearPermissions();
@@ -316,6 +331,8 @@ class FfiModel with ChangeNotifier {
         parent.target?.routeTerminalResponse(evt);
       } else if (name == 'file_dir') {
         parent.target?.fileModel.receiveFileDir(evt);
+      } else if (name == 'file_dir_page') {
+        parent.target?.fileModel.receiveFileDirPage(evt);
       } else if (name == 'empty_dirs') {
         parent.target?.fileModel.receiveEmptyDirs(evt);
       } else if (name == 'job_progress') {
@@ -399,6 +416,14 @@ class FfiModel with ChangeNotifier {
         if (isDesktop) {
           gFFI.cmFileModel.onFileTransferLog(evt);
         }
+      } else if (name == "cm_process_kill_request") {
+        if (isDesktop) {
+          parent.target?.serverModel.onProcessKillRequest(evt);
+        }
+      } else if (name == "cm_sensitive_event") {
+        if (isDesktop) {
+          parent.target?.serverModel.onSensitiveEvent(evt);
+        }
       } else if (name == 'sync_peer_option') {
         _handleSyncPeerOption(evt, peerId);
       } else if (name == 'follow_current_display') {
@@ -423,6 +448,17 @@ class FfiModel with ChangeNotifier {
         _handlePrinterRequest(evt, sessionId, peerId);
       } else if (name == 'screenshot') {
         _handleScreenshot(evt, sessionId, peerId);
+      } else if (name == 'quick_actions') {
+        parent.target?.quickActionsModel.onList(evt);
+      } else if (name == 'quick_action') {
+        parent.target?.quickActionsModel.onResponse(evt);
+      } else if (name == 'quick_action_output') {
+        parent.target?.quickActionsModel.onOutput(evt);
+      } else if (name == 'control_token') {
+        controlTokenHolding = evt['holding'] == true;
+        controlTokenQueued = evt['queued'] == true;
+        controlTokenHolder = evt['holder'] ?? '';
+        notifyListeners();
       } else {
         debugPrint('Event is not handled in the fixed branch: $name');
       }
@@ -841,8 +877,11 @@ class FfiModel with ChangeNotifier {
         type == 'session-login-password') {
       enterUserLoginAndPasswordDialog(sessionId, dialogManager);
     } else if (type == 'restarting') {
-      showMsgBox(sessionId, type, title, text, link, false, dialogManager,
+      final hasRetry = evt['hasRetry'] == 'true';
+      showMsgBox(sessionId, type, title, text, link, hasRetry, dialogManager,
           hasCancel: false);
+    } else if (type == 'power-action-confirm') {
+      showPowerActionConfirm(sessionId, text, dialogManager);
     } else if (type == 'wait-remote-accept-nook') {
       showWaitAcceptDialog(sessionId, type, title, text, dialogManager);
     } else if (type == 'on-uac' || type == 'on-foreground-elevated') {
@@ -1127,6 +1166,9 @@ class FfiModel with ChangeNotifier {
           debugPrint('Failed to decode platformAdditions $e');
         }
       }
+      if (!isCache) {
+        _checkCaptureEnv(sessionId);
+      }
     }
 
     _pi.isSet.value = true;
@@ -1359,6 +1401,7 @@ class FfiModel with ChangeNotifier {
     if (updateData.isEmpty) {
       _pi.platformAdditions.remove(kPlatformAdditionsRustDeskVirtualDisplays);
       _pi.platformAdditions.remove(kPlatformAdditionsAmyuniVirtualDisplays);
+      _pi.platformAdditions.remove(kPlatformAdditionsCaptureEnv);
     } else {
       try {
         final updateJson = json.decode(updateData) as Map<String, dynamic>;
@@ -1373,6 +1416,9 @@ class FfiModel with ChangeNotifier {
         if (!updateJson.containsKey(kPlatformAdditionsAmyuniVirtualDisplays)) {
           _pi.platformAdditions.remove(kPlatformAdditionsAmyuniVirtualDisplays);
         }
+        if (!updateJson.containsKey(kPlatformAdditionsCaptureEnv)) {
+          _pi.platformAdditions.remove(kPlatformAdditionsCaptureEnv);
+        }
       } catch (e) {
         debugPrint('Failed to decode platformAdditions $e');
       }
@@ -1380,6 +1426,51 @@ class FfiModel with ChangeNotifier {
 
     cachedPeerData.peerInfo['platform_additions'] =
         json.encode(_pi.platformAdditions);
+    _checkCaptureEnv(sessionId);
+  }
+
+  // The host can't capture its screen, tell the user why, and offer to plug in a virtual display
+  // if the host suggests it.
+  void _checkCaptureEnv(SessionID sessionId) {
+    final env = _pi.platformAdditions[kPlatformAdditionsCaptureEnv];
+    if (env is! Map) {
+      _captureEnvShown = false;
+      return;
+    }
+    if (_captureEnvShown) {
+      return;
+    }
+    _captureEnvShown = true;
+    final remediations =
+        (env['remediations'] as List?)?.map((e) => e.toString()).toList() ??
+            [];
+    final text = [env['status'], ...remediations]
+        .map((e) => translate('capture-env-$e-tip'))
+        .join('\n\n');
+    final canEnableVirtualDisplay =
+        remediations.contains('enable-virtual-display') &&
+            (_pi.isRustDeskIdd || _pi.isAmyuniIdd);
+    parent.target?.dialogManager.show((setState, close, context) {
+      enableVirtualDisplay() {
+        bind.sessionToggleVirtualDisplay(
+            sessionId: sessionId, index: _pi.isRustDeskIdd ? 1 : 0, on: true);
+        close();
+      }
+
+      return CustomAlertDialog(
+        title: Text(translate('Screen capture unavailable')),
+        content: Text(text),
+        actions: [
+          if (canEnableVirtualDisplay)
+            dialogButton('Enable virtual display',
+                onPressed: enableVirtualDisplay),
+          dialogButton('Close',
+              onPressed: close, isOutline: canEnableVirtualDisplay),
+        ],
+        onSubmit: canEnableVirtualDisplay ? enableVirtualDisplay : close,
+        onCancel: close,
+      );
+    }, tag: '$sessionId-capture-env');
   }
 
   handleFollowCurrentDisplay(
@@ -2727,6 +2818,10 @@ class QualityMonitorData {
   String? targetBitrate;
   String? codecFormat;
   String? chroma;
+  // "passthrough" or "transcode", null if it's not a camera.
+  String? cameraFrames;
+  // Reason -> video frames dropped by the peer, empty if the peer doesn't count them.
+  Map<String, int> frameDrops = {};
 }
 
 class QualityMonitorModel with ChangeNotifier {
@@ -2782,6 +2877,22 @@ class QualityMonitorModel with ChangeNotifier {
           (evt['target_bitrate'] as String).isNotEmpty) {
         _data.targetBitrate = evt['target_bitrate'];
       }
+      // Per display split of the target bitrate when all displays are shown.
+      if (evt.containsKey('display_target_bitrates') &&
+          (evt['display_target_bitrates'] as String).isNotEmpty) {
+        final bitrates =
+            jsonDecode(evt['display_target_bitrates']) as Map<String, dynamic>;
+        final pi = parent.target?.ffiModel.pi;
+        if (pi != null &&
+            pi.currentDisplay == kAllDisplayValue &&
+            bitrates.length > 1) {
+          final bitrateList = [];
+          for (var i = 0; i < pi.displays.length; i++) {
+            bitrateList.add((bitrates[i.toString()] ?? 0).toString());
+          }
+          _data.targetBitrate = bitrateList.join(' ');
+        }
+      }
       if (evt.containsKey('codec_format') &&
           (evt['codec_format'] as String).isNotEmpty) {
         _data.codecFormat = evt['codec_format'];
@@ -2789,6 +2900,16 @@ class QualityMonitorModel with ChangeNotifier {
       if (evt.containsKey('chroma') && (evt['chroma'] as String).isNotEmpty) {
         _data.chroma = evt['chroma'];
       }
+      if (evt.containsKey('camera_frames') &&
+          (evt['camera_frames'] as String).isNotEmpty) {
+        _data.cameraFrames = evt['camera_frames'];
+      }
+      if (evt.containsKey('frame_drops') &&
+          (evt['frame_drops'] as String).isNotEmpty) {
+        final drops = jsonDecode(evt['frame_drops']) as Map<String, dynamic>;
+        _data.frameDrops =
+            drops.map((k, v) => MapEntry(k, (v as num).toInt()));
+      }
       notifyListeners();
     } catch (e) {
       //
@@ -2835,6 +2956,71 @@ class ElevationModel with ChangeNotifier {
   onPortableServiceRunning(bool running) => _running = running;
 }
 
+// The quick actions defined on the peer and the output of the last one run, see
+// `showQuickActionsDialog`.
+class QuickActionsModel {
+  WeakReference<FFI> parent;
+  QuickActionsModel(this.parent);
+  final RxList<String> actions = <String>[].obs;
+  final RxString error = ''.obs;
+  final RxString running = ''.obs;
+  final RxBool done = true.obs;
+  final RxString output = ''.obs;
+  final RxBool truncated = false.obs;
+  final RxInt exitCode = 0.obs;
+
+  void request() {
+    error.value = '';
+    bind.sessionRequestQuickActions(sessionId: parent.target!.sessionId);
+  }
+
+  void run(String name, {String token = '0'}) {
+    error.value = '';
+    bind.sessionRunQuickAction(
+        sessionId: parent.target!.sessionId, name: name, token: token);
+  }
+
+  onList(Map<String, dynamic> evt) {
+    actions.value = (evt['actions'] as List? ?? [])
+        .map((e) => (e['name'] ?? '') as String)
+        .toList();
+    error.value = evt['error'] ?? '';
+  }
+
+  // The peer answers an action to confirm with a token, it runs when it's sent back.
+  onResponse(Map<String, dynamic> evt) {
+    final String name = evt['name'] ?? '';
+    final String err = evt['error'] ?? '';
+    if (err.isNotEmpty) {
+      error.value = err;
+    } else if (evt['started'] == true) {
+      running.value = name;
+      done.value = false;
+      output.value = '';
+      truncated.value = false;
+    } else if ((evt['token'] ?? '0') != '0') {
+      final ffi = parent.target;
+      if (ffi != null) {
+        showQuickActionConfirmDialog(ffi, name, evt['token']);
+      }
+    }
+  }
+
+  onOutput(Map<String, dynamic> evt) {
+    if (evt['name'] != running.value) {
+      return;
+    }
+    output.value += evt['data'] ?? '';
+    if (evt['truncated'] == true) {
+      truncated.value = true;
+    }
+    if (evt['done'] == true) {
+      exitCode.value = evt['exit_code'] ?? -1;
+      done.value = true;
+    }
+  }
+}
+
 // The index values of `ConnType` are same as rust protobuf.
 enum ConnType {
   defaultConn,
@@ -2872,6 +3058,7 @@ class FFI {
   late final RecordingModel recordingModel; // session
   late final InputModel inputModel; // session
   late final ElevationModel elevationModel; // session
+  late final QuickActionsModel quickActionsModel; // session
   late final CmFileModel cmFileModel; // cm
   late final TextureModel textureModel; //session
   late final Peers recentPeersModel; // global
@@ -2901,6 +3088,7 @@ class FFI {
     recordingModel = RecordingModel(WeakReference(this));
     inputModel = InputModel(WeakReference(this));
     elevationModel = ElevationModel(WeakReference(this));
+    quickActionsModel = QuickActionsModel(WeakReference(this));
     cmFileModel = CmFileModel(WeakReference(this));
     textureModel = TextureModel(WeakReference(this));
     recentPeersModel = Peers(
@@ -3308,6 +3496,17 @@ class PeerInfo with ChangeNotifier {
       platformAdditions[kPlatformAdditionsRustDeskVirtualDisplays] ?? []);
   int get amyuniVirtualDisplayCount =>
       platformAdditions[kPlatformAdditionsAmyuniVirtualDisplays] ?? 0;
+  bool get isManualClipboard =>
+      platformAdditions[kPlatformAdditionsManualClipboard] == true;
+  bool get isExclusiveControl =>
+      platformAdditions[kPlatformAdditionsExclusiveControl] == true;
+  // The text scaling of the peer in percent, 0 if unknown.
+  int get textScale => platformAdditions[kPlatformAdditionsTextScale] ?? 0;
+  List<int> get displayDpis =>
+      List<int>.from(platformAdditions[kPlatformAdditionsDisplayDpis] ?? []);
+  // Whether the host runs elevated and as the service, null if the host doesn't tell it.
+  bool? get isRoot => platformAdditions[kPlatformAdditionsIsRoot];
+  bool? get isService => platformAdditions[kPlatformAdditionsIsService];
 
   bool get isSupportMultiDisplay =>
       (isDesktop || isWebDesktop) && isSupportMultiUiSession;
@@ -812,6 +812,108 @@ class ServerModel with ChangeNotifier {
     }
   }
 
+  // The kill of a process by the peer, denied if it's not approved in time.
+  void onProcessKillRequest(Map<String, dynamic> evt) {
+    final id = int.tryParse(evt['id'].toString()) ?? -1;
+    final pid = int.tryParse(evt['pid'].toString()) ?? -1;
+    final name = evt['name'].toString();
+    final index = _clients.indexWhere((client) => client.id == id);
+    if (index == -1 || pid < 0) return;
+    final client = _clients[index];
+    windowOnTop(null);
+    final tag = 'process-kill-$id-$pid';
+    parent.target?.dialogManager.show((setState, close, context) {
+      handle(bool approved) {
+        bind.cmHandleProcessKill(id: id, pid: pid, approved: approved);
+        close();
+      }
+
+      return CustomAlertDialog(
+        title: Text(translate('Kill process')),
+        content: Column(
+          mainAxisSize: MainAxisSize.min,
+          crossAxisAlignment: CrossAxisAlignment.start,
+          children: [
+            ClientInfo(client),
+            Text(translate(
+                '{${client.name}} wants to kill the process {$name} ({$pid})')),
+            Text(translate('Approve it within {$_kProcessKillApprovalSecs} '
+                'seconds, or it will be denied')),
+          ],
+        ),
+        actions: [
+          dialogButton('Dismiss',
+              onPressed: () => handle(false), isOutline: true),
+          dialogButton('Allow', onPressed: () => handle(true)),
+        ],
+        onCancel: () => handle(false),
+      );
+    }, tag: tag);
+    // The connection denies it by itself.
+    Future.delayed(Duration(seconds: _kProcessKillApprovalSecs),
+        () => parent.target?.dialogManager.dismissByTag(tag));
+  }
+
+  // A sensitive feature turned on by the peer, undone if it's not confirmed in time.
+  void onSensitiveEvent(Map<String, dynamic> evt) {
+    final id = int.tryParse(evt['id'].toString()) ?? -1;
+    final event = evt['event'].toString();
+    final confirmSecs = int.tryParse(evt['confirm_secs'].toString()) ?? 0;
+    final index = _clients.indexWhere((client) => client.id == id);
+    if (index == -1) return;
+    final client = _clients[index];
+    windowOnTop(null);
+    if (confirmSecs <= 0) return;
+    final text = _kSensitiveEventTexts[event] ?? event;
+    final tag = 'sensitive-event-$id-$event';
+    var answered = false;
+    // The connection turns off the permission to block the input when it's undone.
+    onUndone() {
+      if (event == 'input-block') {
+        client.blockInput = false;
+        notifyListeners();
+      }
+    }
+
+    parent.target?.dialogManager.show((setState, close, context) {
+      confirm(bool confirmed) {
+        answered = true;
+        bind.cmConfirmSensitiveEvent(
+            id: id, event: event, confirmed: confirmed);
+        if (!confirmed) onUndone();
+        close();
+      }
+
+      return CustomAlertDialog(
+        title: Text(translate('Sensitive feature used')),
+        content: Column(
+          mainAxisSize: MainAxisSize.min,
+          crossAxisAlignment: CrossAxisAlignment.start,
+          children: [
+            ClientInfo(client),
+            Text(translate(text.replaceFirst('{}', '{${client.name}}'))),
+            Text(translate(
+                'Confirm it within {$confirmSecs} seconds, or it will be undone')),
+          ],
+        ),
+        actions: [
+          dialogButton('Undo',
+              onPressed: () => confirm(false), isOutline: true),
+          dialogButton('Allow', onPressed: () => confirm(true)),
+        ],
+        onSubmit: () => confirm(true),
+        onCancel: () => confirm(false),
+      );
+    }, tag: tag);
+    // The connection undoes it by itself.
+    Future.delayed(Duration(seconds: confirmSecs), () {
+      if (!answered) {
+        onUndone();
+        parent.target?.dialogManager.dismissByTag(tag);
+      }
+    });
+  }
+
   void updateVoiceCallState(Map<String, dynamic> evt) {
     try {
       final client = Client.fromJson(jsonDecode(evt["client"]));
@@ -819,6 +921,7 @@ class ServerModel with ChangeNotifier {
       if (index != -1) {
         _clients[index].inVoiceCall = client.inVoiceCall;
         _clients[index].incomingVoiceCall = client.incomingVoiceCall;
+        _clients[index].virtualMic = client.virtualMic;
         if (client.incomingVoiceCall) {
           if (isAndroid) {
             showVoiceCallDialog(client);
@@ -883,10 +986,14 @@ class Client {
   bool restart = false;
   bool recording = false;
   bool blockInput = false;
+  bool annotation = false;
+  bool quickActions = false;
   bool disconnected = false;
   bool fromSwitch = false;
   bool inVoiceCall = false;
   bool incomingVoiceCall = false;
+  bool virtualMic = false;
+  String fingerprint = '';
 
   RxInt unreadChatMessageCount = 0.obs;
 
@@ -910,10 +1017,14 @@ class Client {
     restart = json['restart'];
     recording = json['recording'];
     blockInput = json['block_input'];
+    annotation = json['annotation'] ?? false;
+    quickActions = json['quick_actions'] ?? false;
     disconnected = json['disconnected'];
     fromSwitch = json['from_switch'];
     inVoiceCall = json['in_voice_call'];
     incomingVoiceCall = json['incoming_voice_call'];
+    virtualMic = json['virtual_mic'] ?? false;
+    fingerprint = json['fingerprint'] ?? '';
   }
 
   Map<String, dynamic> toJson() {
@@ -933,10 +1044,14 @@ class Client {
     data['restart'] = restart;
     data['recording'] = recording;
     data['block_input'] = blockInput;
+    data['annotation'] = annotation;
+    data['quick_actions'] = quickActions;
     data['disconnected'] = disconnected;
     data['from_switch'] = fromSwitch;
     data['in_voice_call'] = inVoiceCall;
     data['incoming_voice_call'] = incomingVoiceCall;
+    data['virtual_mic'] = virtualMic;
+    data['fingerprint'] = fingerprint;
     return data;
   }
 
@@ -955,6 +1070,19 @@ class Client {
   }
 }
 
+// The same as `process::APPROVAL_TIMEOUT`.
+const _kProcessKillApprovalSecs = 60;
+
+// The same texts as `sensitive_events::Event::text`.
+const _kSensitiveEventTexts = {
+  'privacy-mode': '{} turned on the privacy mode',
+  'input-block': '{} blocked the input of this device',
+  'elevated-terminal': '{} opened a terminal with the administrator rights',
+  'credential-injection':
+      '{} elevated the session with a username and a password',
+  'elevation': '{} elevated the session',
+};
+
 String getLoginDialogTag(int id) {
   return kLoginDialogTag + id.toString();
 }
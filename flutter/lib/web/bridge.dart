@@ -398,6 +398,42 @@ class RustdeskImpl {
     return Future(() => js.context.callMethod('setByName', ['lock_screen']));
   }
 
+  Future<void> sessionRequestClipboard(
+      {required UuidValue sessionId, dynamic hint}) {
+    throw UnimplementedError("sessionRequestClipboard");
+  }
+
+  Future<void> sessionRequestControlToken(
+      {required UuidValue sessionId, required bool request, dynamic hint}) {
+    throw UnimplementedError("sessionRequestControlToken");
+  }
+
+  Future<void> sessionSetClientVisibility(
+      {required UuidValue sessionId,
+      required bool visible,
+      required bool minimized,
+      dynamic hint}) {
+    throw UnimplementedError("sessionSetClientVisibility");
+  }
+
+  Future<void> sessionSetClientFocus(
+      {required UuidValue sessionId, required bool focused, dynamic hint}) {
+    throw UnimplementedError("sessionSetClientFocus");
+  }
+
+  Future<void> sessionSetHostTextScale(
+      {required UuidValue sessionId, required int percent, dynamic hint}) {
+    throw UnimplementedError("sessionSetHostTextScale");
+  }
+
+  Future<void> sessionCaptureWindow(
+      {required UuidValue sessionId,
+      required int id,
+      required String title,
+      dynamic hint}) {
+    throw UnimplementedError("sessionCaptureWindow");
+  }
+
   Future<void> sessionCtrlAltDel({required UuidValue sessionId, dynamic hint}) {
     return Future(() => js.context.callMethod('setByName', ['ctrl_alt_del']));
   }
@@ -726,6 +762,10 @@ class RustdeskImpl {
     throw UnimplementedError("mainGetSoundInputs");
   }
 
+  Future<List<String>> mainGetSoundOutputs({dynamic hint}) {
+    throw UnimplementedError("mainGetSoundOutputs");
+  }
+
   Future<String?> mainGetDefaultSoundInput({dynamic hint}) {
     throw UnimplementedError("mainGetDefaultSoundInput");
   }
@@ -776,6 +816,15 @@ class RustdeskImpl {
   }
 
   // get server settings
+  Future<String> mainGetAudioCaptureExclusions({dynamic hint}) {
+    throw UnimplementedError("mainGetAudioCaptureExclusions");
+  }
+
+  Future<String> mainSetAudioCaptureExclusions(
+      {required String names, dynamic hint}) {
+    throw UnimplementedError("mainSetAudioCaptureExclusions");
+  }
+
   Future<String> mainGetOptions({dynamic hint}) {
     return Future(() => mainGetOptionsSync());
   }
@@ -1125,6 +1174,19 @@ class RustdeskImpl {
     throw UnimplementedError("cmHandleIncomingVoiceCall");
   }
 
+  Future<void> cmHandleProcessKill(
+      {required int id, required int pid, required bool approved, dynamic hint}) {
+    throw UnimplementedError("cmHandleProcessKill");
+  }
+
+  Future<void> cmConfirmSensitiveEvent(
+      {required int id,
+      required String event,
+      required bool confirmed,
+      dynamic hint}) {
+    throw UnimplementedError("cmConfirmSensitiveEvent");
+  }
+
   Future<void> cmCloseVoiceCall({required int id, dynamic hint}) {
     throw UnimplementedError("cmCloseVoiceCall");
   }
@@ -1277,6 +1339,67 @@ class RustdeskImpl {
     return Future(() => js.context.callMethod('setByName', ['restart']));
   }
 
+  Future<void> sessionPowerAction(
+      {required UuidValue sessionId, required String action, dynamic hint}) {
+    throw UnimplementedError("sessionPowerAction");
+  }
+
+  Future<void> sessionConfirmPowerAction(
+      {required UuidValue sessionId, required bool confirmed, dynamic hint}) {
+    throw UnimplementedError("sessionConfirmPowerAction");
+  }
+
+  Future<void> sessionRequestProcessList(
+      {required UuidValue sessionId,
+      required int page,
+      required int pageSize,
+      dynamic hint}) {
+    throw UnimplementedError("sessionRequestProcessList");
+  }
+
+  Future<void> sessionRequestDiagnosticCapture(
+      {required UuidValue sessionId, required int display, dynamic hint}) {
+    throw UnimplementedError("sessionRequestDiagnosticCapture");
+  }
+
+  Future<void> sessionSendAnnotation(
+      {required UuidValue sessionId,
+      required int display,
+      required int shape,
+      required List<int> points,
+      required int color,
+      required int width,
+      required String text,
+      required int fontSize,
+      dynamic hint}) {
+    throw UnimplementedError("sessionSendAnnotation");
+  }
+
+  String sessionGetFingerprint({required UuidValue sessionId, dynamic hint}) {
+    return 'unverified';
+  }
+
+  Future<void> sessionKillProcess(
+      {required UuidValue sessionId,
+      required int pid,
+      required String token,
+      dynamic hint}) {
+    throw UnimplementedError("sessionKillProcess");
+  }
+
+  Future<void> sessionRequestQuickActions(
+      {required UuidValue sessionId, dynamic hint}) {
+    throw UnimplementedError("sessionRequestQuickActions");
+  }
+
+  Future<void> sessionRunQuickAction(
+      {required UuidValue sessionId,
+      required String name,
+      required String token,
+      dynamic hint}) {
+    throw UnimplementedError("sessionRunQuickAction");
+  }
+
   String sessionGetAuditServerSync(
       {required UuidValue sessionId, required String typ, dynamic hint}) {
     return js.context.callMethod('getByName', ['audit_server', typ]);
@@ -1806,6 +1929,15 @@ class RustdeskImpl {
     throw UnimplementedError("setVoiceCallInputDevice");
   }
 
+  Future<String> getVoiceCallOutputDevice({required bool isCm, dynamic hint}) {
+    throw UnimplementedError("getVoiceCallOutputDevice");
+  }
+
+  Future<void> setVoiceCallOutputDevice(
+      {required bool isCm, required String device, dynamic hint}) {
+    throw UnimplementedError("setVoiceCallOutputDevice");
+  }
+
   bool isPresetPasswordMobileOnly({dynamic hint}) {
     throw UnimplementedError("isPresetPasswordMobileOnly");
   }
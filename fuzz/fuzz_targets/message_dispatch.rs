@@ -0,0 +1,47 @@
+#![no_main]
+// Fuzz the inbound message path of the connection: parse with the limits, then decode the
+// accepted messages the way the handlers in `Connection::on_message` do. The limits count the
+// decompressed sizes with a streaming decoder, the handlers use `decompress`, so the target
+// checks that what the handlers hold stays in the limits, whatever the peer sends.
+//
+// cargo +nightly fuzz run message_dispatch
+
+use hbb_common::{compress::decompress, message_proto::*};
+use libfuzzer_sys::fuzz_target;
+use librustdesk::message_limits::*;
+
+fuzz_target!(|data: &[u8]| {
+    let limits = MessageLimits::default();
+    if let Ok(msg) = limits.parse(data) {
+        dispatch(&msg);
+    }
+});
+
+// The size the handler of a clipboard holds.
+fn clipboard_size(cb: &Clipboard) -> usize {
+    if cb.compress {
+        decompress(&cb.content).len()
+    } else {
+        cb.content.len()
+    }
+}
+
+fn dispatch(msg: &Message) {
+    match &msg.union {
+        Some(message::Union::Clipboard(cb)) => {
+            assert!(clipboard_size(cb) <= MAX_CLIPBOARD_SIZE);
+        }
+        Some(message::Union::MultiClipboards(mcb)) => {
+            let total: usize = mcb.clipboards.iter().map(clipboard_size).sum();
+            assert!(total <= MAX_CLIPBOARDS_TOTAL_SIZE);
+        }
+        Some(message::Union::FileResponse(fr)) => {
+            if let Some(file_response::Union::Block(block)) = &fr.union {
+                if block.compressed {
+                    assert!(decompress(&block.data).len() <= DEFAULT_MAX_FILE_BLOCK_SIZE);
+                }
+            }
+        }
+        _ => {}
+    }
+}
@@ -183,6 +183,13 @@ fn get_size() -> Option<(u16, u16, u16)> {
     None
 }
 
+// The rotation of the screen in degrees, clockwise, as the virtual display is captured in it.
+pub fn screen_rotation() -> Option<i32> {
+    let res = call_main_service_get_by_name("screen_size").ok()?;
+    let json = serde_json::from_str::<HashMap<String, Value>>(&res).ok()?;
+    Some(json.get("rotation")?.as_i64()? as _)
+}
+
 pub fn is_start() -> Option<bool> {
     let res = call_main_service_get_by_name("is_start").ok()?;
     Some(res == "true")
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     io,
     sync::{Arc, Mutex},
 };
@@ -7,7 +8,7 @@ use std::{
 use nokhwa::{
     pixel_format::RgbAFormat,
     query,
-    utils::{ApiBackend, CameraIndex, RequestedFormat, RequestedFormatType},
+    utils::{ApiBackend, CameraIndex, FrameFormat, RequestedFormat, RequestedFormatType},
     Camera,
 };
 
@@ -22,6 +23,8 @@ use crate::{Frame, PixelBuffer, Pixfmt, TraitCapturer};
 pub const PRIMARY_CAMERA_IDX: usize = 0;
 lazy_static::lazy_static! {
     static ref SYNC_CAMERA_DISPLAYS: Arc<Mutex<Vec<DisplayInfo>>> = Arc::new(Mutex::new(Vec::new()));
+    // Index -> whether the camera outputs MJPEG, known once the camera is created.
+    static ref MJPEG_CAMERAS: Mutex<HashMap<usize, bool>> = Default::default();
 }
 
 #[cfg(not(any(target_os = "windows", target_os = "linux")))]
@@ -131,11 +134,27 @@ impl Cameras {
             RequestedFormat::new::<RgbAFormat>(format_type),
         );
         match result {
-            Ok(camera) => Ok(camera),
+            Ok(camera) => {
+                if let CameraIndex::Index(i) = index {
+                    let mjpeg = camera.camera_format().format() == FrameFormat::MJPEG;
+                    MJPEG_CAMERAS.lock().unwrap().insert(*i as usize, mjpeg);
+                }
+                Ok(camera)
+            }
             Err(e) => bail!("create camera{} error:  {}", index, e),
         }
     }
 
+    /// Whether the camera outputs MJPEG, the frames can be passed through without decoding.
+    pub fn is_mjpeg(index: usize) -> bool {
+        MJPEG_CAMERAS
+            .lock()
+            .unwrap()
+            .get(&index)
+            .cloned()
+            .unwrap_or(false)
+    }
+
     pub fn get_camera_resolution(index: usize) -> ResultType<Resolution> {
         let index = CameraIndex::Index(index as u32);
         let camera = Self::create_camera(&index)?;
@@ -154,6 +173,10 @@ impl Cameras {
     pub fn get_capturer(current: usize) -> ResultType<Box<dyn TraitCapturer>> {
         Ok(Box::new(CameraCapturer::new(current)?))
     }
+
+    pub fn get_mjpeg_capturer(current: usize) -> ResultType<MjpegCapturer> {
+        MjpegCapturer::new(current)
+    }
 }
 
 #[cfg(not(any(target_os = "windows", target_os = "linux")))]
@@ -177,6 +200,14 @@ impl Cameras {
     pub fn get_capturer(current: usize) -> ResultType<Box<dyn TraitCapturer>> {
         bail!(CAMERA_NOT_SUPPORTED);
     }
+
+    pub fn is_mjpeg(index: usize) -> bool {
+        false
+    }
+
+    pub fn get_mjpeg_capturer(current: usize) -> ResultType<MjpegCapturer> {
+        bail!(CAMERA_NOT_SUPPORTED);
+    }
 }
 
 #[cfg(any(target_os = "windows", target_os = "linux"))]
@@ -281,3 +312,68 @@ impl TraitCapturer for CameraCapturer {
     #[cfg(feature = "vram")]
     fn set_output_texture(&mut self, _texture: bool) {}
 }
+
+// The capturer of the cameras outputting MJPEG, the frames are sent as they are without decoding.
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+pub struct MjpegCapturer {
+    camera: Camera,
+    data: Vec<u8>,
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+pub struct MjpegCapturer;
+
+impl MjpegCapturer {
+    #[cfg(any(target_os = "windows", target_os = "linux"))]
+    fn new(current: usize) -> ResultType<Self> {
+        let index = CameraIndex::Index(current as u32);
+        let camera = Cameras::create_camera(&index)?;
+        if camera.camera_format().format() != FrameFormat::MJPEG {
+            bail!("Camera {} doesn't output MJPEG", current);
+        }
+        Ok(MjpegCapturer {
+            camera,
+            data: Vec::new(),
+        })
+    }
+
+    /// The jpeg of the frame and its size, `WouldBlock` if it is the same as the last one.
+    #[cfg(any(target_os = "windows", target_os = "linux"))]
+    pub fn frame(&mut self) -> io::Result<(&[u8], usize, usize)> {
+        if !self.camera.is_stream_open() {
+            if let Err(e) = self.camera.open_stream() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Camera open stream error: {}", e),
+                ));
+            }
+        }
+        let buffer = self.camera.frame().map_err(|e| {
+            io::Error::new(io::ErrorKind::Other, format!("Camera frame error: {}", e))
+        })?;
+        if buffer.source_frame_format() != FrameFormat::MJPEG {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Camera frame is not MJPEG".to_owned(),
+            ));
+        }
+        if buffer.buffer() == self.data.as_slice() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.data = buffer.buffer().to_vec();
+        let resolution = buffer.resolution();
+        Ok((
+            &self.data,
+            resolution.width() as usize,
+            resolution.height() as usize,
+        ))
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    pub fn frame(&mut self) -> io::Result<(&[u8], usize, usize)> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            CAMERA_NOT_SUPPORTED.to_string(),
+        ))
+    }
+}
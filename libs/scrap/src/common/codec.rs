@@ -191,12 +191,31 @@ impl Encoder {
             decodings.len() > 0 && decodings.iter().all(|(_, s)| s.ability_h264 > 0);
         let _all_support_h265_decoding =
             decodings.len() > 0 && decodings.iter().all(|(_, s)| s.ability_h265 > 0);
+        // The hardware encoders may crash as the check process did, use the software encoders.
+        #[cfg(all(
+            feature = "hwcodec",
+            not(any(target_os = "android", target_os = "ios"))
+        ))]
+        let _hw_healthy = {
+            let healthy = crate::hwcodec::is_check_healthy();
+            if !healthy && enable_hwcodec_option() {
+                log::warn!(
+                    "The hwcodec check process is unhealthy, fall back to software encoding"
+                );
+            }
+            healthy
+        };
+        #[cfg(not(all(
+            feature = "hwcodec",
+            not(any(target_os = "android", target_os = "ios"))
+        )))]
+        let _hw_healthy = true;
         #[allow(unused_mut)]
         let mut h264vram_encoding = false;
         #[allow(unused_mut)]
         let mut h265vram_encoding = false;
         #[cfg(feature = "vram")]
-        if enable_vram_option(true) {
+        if enable_vram_option(true) && _hw_healthy {
             if _all_support_h264_decoding {
                 if VRamEncoder::available(CodecFormat::H264).len() > 0 {
                     h264vram_encoding = true;
@@ -213,7 +232,7 @@ impl Encoder {
         #[allow(unused_mut)]
         let mut h265hw_encoding: Option<String> = None;
         #[cfg(feature = "hwcodec")]
-        if enable_hwcodec_option() {
+        if enable_hwcodec_option() && _hw_healthy {
             if _all_support_h264_decoding {
                 h264hw_encoding =
                     HwRamEncoder::try_get(CodecFormat::H264).map_or(None, |c| Some(c.name));
@@ -351,6 +370,16 @@ impl Encoder {
         USABLE_ENCODING.lock().unwrap().clone()
     }
 
+    /// Whether all the connections decode the MJPEG frames passed through from the cameras, it
+    /// doesn't change the negotiated codec of the other sources.
+    pub fn support_mjpeg(conn_ids: &[i32]) -> bool {
+        let decodings = PEER_DECODINGS.lock().unwrap();
+        !conn_ids.is_empty()
+            && conn_ids
+                .iter()
+                .all(|id| decodings.get(id).map_or(false, |d| d.ability_mjpeg > 0))
+    }
+
     pub fn set_fallback(config: &EncoderCfg) {
         let format = match config {
             EncoderCfg::VPX(vpx) => match vpx.codec {
@@ -427,6 +456,7 @@ impl Decoder {
             ability_vp8: 1,
             ability_vp9: 1,
             ability_av1: if disable_av1() { 0 } else { 1 },
+            ability_mjpeg: 1,
             i444: Some(CodecAbility {
                 vp9: true,
                 av1: true,
@@ -485,6 +515,7 @@ impl Decoder {
                 CodecFormat::AV1 => decoding.ability_av1 = 0,
                 CodecFormat::H264 => decoding.ability_h264 = 0,
                 CodecFormat::H265 => decoding.ability_h265 = 0,
+                CodecFormat::MJPEG => decoding.ability_mjpeg = 0,
                 _ => {}
             }
         }
@@ -580,6 +611,8 @@ impl Decoder {
                     valid = h265_media_codec.is_some();
                 }
             }
+            // Decoded by the frame, see `mjpeg::decode_frames`.
+            CodecFormat::MJPEG => valid = true,
             CodecFormat::Unknown => {
                 log::error!("unknown codec format, cannot create decoder");
             }
@@ -651,6 +684,10 @@ impl Decoder {
                     bail!("av1 decoder not available");
                 }
             }
+            video_frame::Union::Mjpegs(mjpegs) => {
+                *chroma = None;
+                crate::mjpeg::decode_frames(mjpegs, rgb)
+            }
             #[cfg(any(feature = "hwcodec", feature = "vram"))]
             video_frame::Union::H264s(h264s) => {
                 *chroma = Some(Chroma::I420);
@@ -712,48 +712,153 @@ pub fn check_available_hwcodec() -> String {
     serde_json::to_string(&c).unwrap_or_default()
 }
 
+// The check process may crash or hang in a buggy driver, it is restarted with backoff up to
+// `MAX_CHECK_ATTEMPTS` times. After that the checker is unhealthy, and the hardware encoders are
+// not used until a re-check succeeds.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+const MAX_CHECK_ATTEMPTS: u32 = 3;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+const CHECK_TIMEOUT_SECS: u64 = 30;
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CheckStatus {
+    pub running: bool,
+    pub pid: Option<u32>,
+    pub attempts: u32,
+    // None if not checked yet
+    pub healthy: Option<bool>,
+    pub last_error: String,
+    pub last_time: i64,
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+lazy_static::lazy_static! {
+    static ref CHECK_STATUS: std::sync::Mutex<CheckStatus> = Default::default();
+}
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub fn start_check_process() {
     if !enable_hwcodec_option() || HwCodecConfig::already_set() {
         return;
     }
-    use hbb_common::allow_err;
-    use std::sync::Once;
-    let f = || {
-        if let Ok(exe) = std::env::current_exe() {
-            if let Some(_) = exe.file_name().to_owned() {
-                let arg = "--check-hwcodec-config";
-                if let Ok(mut child) = std::process::Command::new(exe).arg(arg).spawn() {
-                    // wait up to 30 seconds, it maybe slow on windows startup for poorly performing machines
-                    for _ in 0..30 {
-                        std::thread::sleep(std::time::Duration::from_secs(1));
-                        if let Ok(Some(_)) = child.try_wait() {
-                            break;
-                        }
-                    }
-                    allow_err!(child.kill());
-                    std::thread::sleep(std::time::Duration::from_millis(30));
-                    match child.try_wait() {
-                        Ok(Some(status)) => {
-                            log::info!("Check hwcodec config, exit with: {status}")
-                        }
-                        Ok(None) => {
-                            log::info!(
-                                "Check hwcodec config, status not ready yet, let's really wait"
-                            );
-                            let res = child.wait();
-                            log::info!("Check hwcodec config, wait result: {res:?}");
-                        }
-                        Err(e) => {
-                            log::error!("Check hwcodec config, error attempting to wait: {e}")
-                        }
-                    }
-                }
+    start_check_thread();
+}
+
+/// Check again, eg. after a driver update, the cached config is dropped.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn force_recheck() {
+    if !enable_hwcodec_option() {
+        return;
+    }
+    log::info!("Force to check hwcodec config again");
+    *CONFIG_SET_BY_IPC.lock().unwrap() = false;
+    start_check_thread();
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn is_check_healthy() -> bool {
+    CHECK_STATUS.lock().unwrap().healthy != Some(false)
+}
+
+/// The status of the check process and the available codecs in json.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn check_status() -> String {
+    let status = CHECK_STATUS.lock().unwrap().clone();
+    serde_json::json!({
+        "status": status,
+        "config": HwCodecConfig::get(),
+    })
+    .to_string()
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn start_check_thread() {
+    {
+        let mut status = CHECK_STATUS.lock().unwrap();
+        if status.running {
+            return;
+        }
+        status.running = true;
+        status.attempts = 0;
+    }
+    std::thread::spawn(|| {
+        let mut result = Err("not started".to_owned());
+        for attempt in 1..=MAX_CHECK_ATTEMPTS {
+            CHECK_STATUS.lock().unwrap().attempts = attempt;
+            result = run_check_process();
+            if result.is_ok() {
+                break;
             }
-        };
-    };
-    static ONCE: Once = Once::new();
-    ONCE.call_once(|| {
-        std::thread::spawn(f);
+            if attempt < MAX_CHECK_ATTEMPTS {
+                let backoff = 2u64 << attempt;
+                log::warn!(
+                    "Check hwcodec config failed: {:?}, retry in {}s",
+                    result,
+                    backoff
+                );
+                std::thread::sleep(std::time::Duration::from_secs(backoff));
+            }
+        }
+        let mut status = CHECK_STATUS.lock().unwrap();
+        status.running = false;
+        status.pid = None;
+        status.last_time = hbb_common::get_time();
+        match result {
+            Ok(()) => {
+                status.healthy = Some(true);
+                status.last_error.clear();
+            }
+            Err(e) => {
+                log::error!(
+                    "Check hwcodec config failed {} times, last error: {}",
+                    status.attempts,
+                    e
+                );
+                status.healthy = Some(false);
+                status.last_error = e;
+            }
+        }
+        drop(status);
+        crate::codec::Encoder::update(crate::codec::EncodingUpdate::Check);
     });
 }
+
+// The check process sends the config back by ipc, it succeeds only if the config is set.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn run_check_process() -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let mut child = std::process::Command::new(exe)
+        .arg("--check-hwcodec-config")
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    CHECK_STATUS.lock().unwrap().pid = Some(child.id());
+    // wait up to 30 seconds, it maybe slow on windows startup for poorly performing machines
+    let mut exit = None;
+    for _ in 0..CHECK_TIMEOUT_SECS {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        if let Ok(Some(status)) = child.try_wait() {
+            exit = Some(status);
+            break;
+        }
+    }
+    let Some(exit) = exit else {
+        child.kill().ok();
+        let res = child.wait();
+        log::info!("Check hwcodec config, killed after timeout: {res:?}");
+        return Err(format!("no result in {}s", CHECK_TIMEOUT_SECS));
+    };
+    log::info!("Check hwcodec config, exit with: {exit}");
+    // The config may arrive slightly later than the exit.
+    for _ in 0..10 {
+        if HwCodecConfig::already_set() {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    if exit.success() {
+        Err("exited without result".to_owned())
+    } else {
+        Err(format!("crashed, {exit}"))
+    }
+}
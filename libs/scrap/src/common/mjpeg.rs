@@ -0,0 +1,66 @@
+// The MJPEG frames of the cameras, passed through by the host without decoding and encoding them
+// again. Every frame is a key frame, there's no state between the frames.
+
+use crate::{ImageFormat, ImageRgb};
+use hbb_common::{anyhow::anyhow, message_proto::EncodedVideoFrames, ResultType};
+
+// rgb [in/out] fmt and stride must be set in ImageRgb
+pub fn decode_frames(frames: &EncodedVideoFrames, rgb: &mut ImageRgb) -> ResultType<bool> {
+    // The frames are complete images, only the last one is shown.
+    let Some(frame) = frames.frames.last() else {
+        return Ok(false);
+    };
+    let image = image::load_from_memory_with_format(&frame.data, image::ImageFormat::Jpeg)
+        .map_err(|e| anyhow!("Failed to decode the mjpeg frame: {}", e))?
+        .into_rgba8();
+    let (w, h) = (image.width() as usize, image.height() as usize);
+    rgba_to(image.as_raw(), w, h, rgb);
+    Ok(true)
+}
+
+fn rgba_to(rgba: &[u8], w: usize, h: usize, rgb: &mut ImageRgb) {
+    let fmt = rgb.fmt();
+    let bytes_per_pixel = match fmt {
+        ImageFormat::Raw => 3,
+        ImageFormat::ARGB | ImageFormat::ABGR => 4,
+    };
+    let align = rgb.align().max(1);
+    let bytes_per_row = (w * bytes_per_pixel + align - 1) & !(align - 1);
+    rgb.w = w;
+    rgb.h = h;
+    rgb.raw.resize(h * bytes_per_row, 0);
+    for (src, dst) in rgba
+        .chunks_exact(w * 4)
+        .zip(rgb.raw.chunks_exact_mut(bytes_per_row))
+    {
+        for (s, d) in src
+            .chunks_exact(4)
+            .zip(dst.chunks_exact_mut(bytes_per_pixel))
+        {
+            // The names are of libyuv, the byte order is reversed: ARGB is BGRA in the memory.
+            match fmt {
+                ImageFormat::ABGR => d.copy_from_slice(s),
+                ImageFormat::ARGB => d.copy_from_slice(&[s[2], s[1], s[0], s[3]]),
+                ImageFormat::Raw => d.copy_from_slice(&s[..3]),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rgba_to() {
+        let rgba = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut rgb = ImageRgb::new(ImageFormat::ARGB, 16);
+        rgba_to(&rgba, 1, 2, &mut rgb);
+        assert_eq!((rgb.w, rgb.h, rgb.raw.len()), (1, 2, 32));
+        assert_eq!(&rgb.raw[..4], &[3, 2, 1, 4]);
+        assert_eq!(&rgb.raw[16..20], &[7, 6, 5, 8]);
+        let mut rgb = ImageRgb::new(ImageFormat::Raw, 1);
+        rgba_to(&rgba, 2, 1, &mut rgb);
+        assert_eq!(rgb.raw, vec![1, 2, 3, 5, 6, 7]);
+    }
+}
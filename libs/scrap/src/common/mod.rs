@@ -41,6 +41,7 @@ pub mod convert;
 pub mod hwcodec;
 #[cfg(feature = "mediacodec")]
 pub mod mediacodec;
+pub mod mjpeg;
 pub mod vpxcodec;
 #[cfg(feature = "vram")]
 pub mod vram;
@@ -53,6 +54,8 @@ pub mod aom;
 pub mod camera;
 pub mod record;
 mod vpx;
+#[cfg(not(any(target_os = "ios")))]
+pub mod window;
 
 #[repr(usize)]
 #[derive(Debug, Copy, Clone)]
@@ -271,6 +274,9 @@ pub fn is_cursor_embedded() -> bool {
     if is_x11() {
         x11::IS_CURSOR_EMBEDDED
     } else {
+        #[cfg(feature = "wayland")]
+        return crate::wayland::pipewire::is_cursor_embedded();
+        #[cfg(not(feature = "wayland"))]
         false
     }
 }
@@ -299,6 +305,8 @@ pub enum CodecFormat {
     AV1,
     H264,
     H265,
+    // The frames of the cameras passed through, see `camera::MjpegCapturer`.
+    MJPEG,
     Unknown,
 }
 
@@ -310,6 +318,7 @@ impl From<&VideoFrame> for CodecFormat {
             Some(video_frame::Union::Av1s(_)) => CodecFormat::AV1,
             Some(video_frame::Union::H264s(_)) => CodecFormat::H264,
             Some(video_frame::Union::H265s(_)) => CodecFormat::H265,
+            Some(video_frame::Union::Mjpegs(_)) => CodecFormat::MJPEG,
             _ => CodecFormat::Unknown,
         }
     }
@@ -323,6 +332,7 @@ impl From<&video_frame::Union> for CodecFormat {
             video_frame::Union::Av1s(_) => CodecFormat::AV1,
             video_frame::Union::H264s(_) => CodecFormat::H264,
             video_frame::Union::H265s(_) => CodecFormat::H265,
+            video_frame::Union::Mjpegs(_) => CodecFormat::MJPEG,
             _ => CodecFormat::Unknown,
         }
     }
@@ -348,6 +358,7 @@ impl ToString for CodecFormat {
             CodecFormat::AV1 => "AV1".into(),
             CodecFormat::H264 => "H264".into(),
             CodecFormat::H265 => "H265".into(),
+            CodecFormat::MJPEG => "MJPEG".into(),
             CodecFormat::Unknown => "Unknown".into(),
         }
     }
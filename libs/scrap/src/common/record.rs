@@ -188,7 +188,8 @@ impl Recorder {
             bail!("check failed");
         }
         let format = CodecFormat::from(frame);
-        if format == CodecFormat::Unknown {
+        // The passed through camera frames are not recorded.
+        if format == CodecFormat::Unknown || format == CodecFormat::MJPEG {
             bail!("unsupported frame type");
         }
         let res = self.check(w, h, format);
@@ -0,0 +1,264 @@
+// Capture of a single top level window, to share one application instead of the whole screen.
+//
+// The window is drawn with `PrintWindow`, so it is captured even if other windows cover it.
+// The frames keep the size the window has when the capturer is created, the caller follows the
+// moves, the resizes and the close of the window with `Windows::get`.
+
+use std::io;
+
+#[cfg(windows)]
+use winapi::{
+    ctypes::c_void,
+    shared::{
+        minwindef::{BOOL, LPARAM, TRUE},
+        windef::{HBITMAP, HDC, HWND, RECT},
+    },
+    um::{
+        wingdi::{
+            CreateCompatibleDC, CreateDIBSection, DeleteDC, DeleteObject, SelectObject, BITMAPINFO,
+            BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS, HGDI_ERROR,
+        },
+        winuser::{
+            EnumWindows, GetDC, GetWindowRect, GetWindowTextW, IsIconic, IsWindow, IsWindowVisible,
+            PrintWindow, ReleaseDC,
+        },
+    },
+};
+
+#[cfg(feature = "vram")]
+use crate::AdapterDevice;
+
+use crate::common::{bail, ResultType};
+use crate::{Frame, TraitCapturer};
+#[cfg(windows)]
+use crate::{PixelBuffer, Pixfmt};
+
+#[cfg(not(windows))]
+const WINDOW_NOT_SUPPORTED: &str = "This platform doesn't support window capture yet";
+pub const WINDOW_CLOSED: &str = "The window is closed";
+// Include the content drawn by DirectComposition, e.g. the browsers.
+#[cfg(windows)]
+const PW_RENDERFULLCONTENT: u32 = 2;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WindowInfo {
+    pub id: u64,
+    pub title: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: usize,
+    pub height: usize,
+}
+
+pub struct Windows;
+
+impl Windows {
+    /// The first visible window whose title contains `title`, case insensitive.
+    pub fn find(title: &str) -> Option<WindowInfo> {
+        let title = title.to_lowercase();
+        Self::all()
+            .into_iter()
+            .find(|w| w.title.to_lowercase().contains(&title))
+    }
+}
+
+#[cfg(windows)]
+impl Windows {
+    /// The visible top level windows which have a title.
+    pub fn all() -> Vec<WindowInfo> {
+        unsafe extern "system" fn callback(hwnd: HWND, lparam: LPARAM) -> BOOL {
+            let windows = &mut *(lparam as *mut Vec<WindowInfo>);
+            if IsWindowVisible(hwnd) != 0 {
+                if let Some(w) = Windows::get(hwnd as u64) {
+                    if !w.title.is_empty() && w.width > 0 && w.height > 0 {
+                        windows.push(w);
+                    }
+                }
+            }
+            TRUE
+        }
+        let mut windows: Vec<WindowInfo> = Vec::new();
+        unsafe {
+            EnumWindows(Some(callback), &mut windows as *mut _ as LPARAM);
+        }
+        windows
+    }
+
+    /// None if the window is closed.
+    pub fn get(id: u64) -> Option<WindowInfo> {
+        unsafe {
+            let hwnd = id as HWND;
+            if IsWindow(hwnd) == 0 {
+                return None;
+            }
+            let mut rect: RECT = std::mem::zeroed();
+            if GetWindowRect(hwnd, &mut rect) == 0 {
+                return None;
+            }
+            let mut title = [0u16; 512];
+            let n = GetWindowTextW(hwnd, title.as_mut_ptr(), title.len() as _).max(0) as usize;
+            Some(WindowInfo {
+                id,
+                title: String::from_utf16_lossy(&title[..n]),
+                x: rect.left,
+                y: rect.top,
+                width: (rect.right - rect.left).max(0) as _,
+                height: (rect.bottom - rect.top).max(0) as _,
+            })
+        }
+    }
+
+    pub fn get_capturer(id: u64) -> ResultType<Box<dyn TraitCapturer>> {
+        Ok(Box::new(WindowCapturer::new(id)?))
+    }
+}
+
+#[cfg(not(windows))]
+impl Windows {
+    pub fn all() -> Vec<WindowInfo> {
+        vec![]
+    }
+
+    pub fn get(_id: u64) -> Option<WindowInfo> {
+        None
+    }
+
+    pub fn get_capturer(_id: u64) -> ResultType<Box<dyn TraitCapturer>> {
+        bail!(WINDOW_NOT_SUPPORTED);
+    }
+}
+
+#[cfg(windows)]
+pub struct WindowCapturer {
+    hwnd: usize,
+    width: usize,
+    height: usize,
+    dc: HDC,
+    bmp: HBITMAP,
+    bits: *mut c_void,
+    data: Vec<u8>,
+    last_data: Vec<u8>, // for faster compare and copy
+}
+
+#[cfg(not(windows))]
+pub struct WindowCapturer;
+
+#[cfg(windows)]
+impl WindowCapturer {
+    fn new(id: u64) -> ResultType<Self> {
+        let Some(info) = Windows::get(id) else {
+            bail!(WINDOW_CLOSED);
+        };
+        if info.width == 0 || info.height == 0 {
+            bail!("The window {} has no size", id);
+        }
+        unsafe {
+            let screen_dc = GetDC(std::ptr::null_mut());
+            let dc = CreateCompatibleDC(screen_dc);
+            ReleaseDC(std::ptr::null_mut(), screen_dc);
+            if dc.is_null() {
+                bail!("Failed to create dc for window {}", id);
+            }
+            let mut bmi: BITMAPINFO = std::mem::zeroed();
+            bmi.bmiHeader = BITMAPINFOHEADER {
+                biSize: std::mem::size_of::<BITMAPINFOHEADER>() as _,
+                biWidth: info.width as _,
+                // Top-down
+                biHeight: -(info.height as i32),
+                biPlanes: 1,
+                biBitCount: 32,
+                biCompression: BI_RGB,
+                ..std::mem::zeroed()
+            };
+            let mut bits = std::ptr::null_mut();
+            let bmp =
+                CreateDIBSection(dc, &bmi, DIB_RGB_COLORS, &mut bits, std::ptr::null_mut(), 0);
+            if bmp.is_null() || bits.is_null() {
+                DeleteDC(dc);
+                bail!("Failed to create bitmap for window {}", id);
+            }
+            let res = SelectObject(dc, bmp as _);
+            if res.is_null() || res == HGDI_ERROR {
+                DeleteObject(bmp as _);
+                DeleteDC(dc);
+                bail!("Failed to select bitmap for window {}", id);
+            }
+            Ok(Self {
+                hwnd: id as _,
+                width: info.width,
+                height: info.height,
+                dc,
+                bmp,
+                bits,
+                data: Vec::new(),
+                last_data: Vec::new(),
+            })
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for WindowCapturer {
+    fn drop(&mut self) {
+        unsafe {
+            DeleteObject(self.bmp as _);
+            DeleteDC(self.dc);
+        }
+    }
+}
+
+impl TraitCapturer for WindowCapturer {
+    #[cfg(windows)]
+    fn frame<'a>(&'a mut self, _timeout: std::time::Duration) -> io::Result<Frame<'a>> {
+        let hwnd = self.hwnd as HWND;
+        unsafe {
+            if IsWindow(hwnd) == 0 {
+                return Err(io::Error::new(io::ErrorKind::NotFound, WINDOW_CLOSED));
+            }
+            // A minimized window draws nothing, keep the last frame.
+            if IsIconic(hwnd) != 0 {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            if PrintWindow(hwnd, self.dc, PW_RENDERFULLCONTENT) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let size = self.width * self.height * 4;
+            self.data.resize(size, 0);
+            self.data
+                .copy_from_slice(std::slice::from_raw_parts(self.bits as *const u8, size));
+        }
+        crate::would_block_if_equal(&mut self.last_data, &self.data)?;
+        Ok(Frame::PixelBuffer(PixelBuffer::new(
+            &self.data,
+            Pixfmt::BGRA,
+            self.width,
+            self.height,
+        )))
+    }
+
+    #[cfg(not(windows))]
+    fn frame<'a>(&'a mut self, _timeout: std::time::Duration) -> io::Result<Frame<'a>> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            WINDOW_NOT_SUPPORTED.to_string(),
+        ))
+    }
+
+    #[cfg(windows)]
+    fn is_gdi(&self) -> bool {
+        false
+    }
+
+    #[cfg(windows)]
+    fn set_gdi(&mut self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "vram")]
+    fn device(&self) -> AdapterDevice {
+        AdapterDevice::default()
+    }
+
+    #[cfg(feature = "vram")]
+    fn set_output_texture(&mut self, _texture: bool) {}
+}
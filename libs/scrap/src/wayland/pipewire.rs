@@ -2,8 +2,11 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::os::unix::io::AsRawFd;
 use std::process::Command;
-use std::sync::{atomic::AtomicBool, Arc, Mutex};
-use std::time::Duration;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
 use tracing::{debug, trace, warn};
 
 use dbus::{
@@ -298,6 +301,7 @@ impl Recorder for PipeWireRecorder {
             if let Err(..) = crate::would_block_if_equal(&mut self.saved_raw_data, buf.as_slice()) {
                 return Ok(PixelProvider::NONE);
             }
+            on_frame();
             let buf_size = buf.get_size();
             // BGRx is 4 bytes per pixel
             if buf_size != (w * h * 4) {
@@ -509,6 +513,105 @@ pub fn get_available_cursor_modes() -> Result<u32, dbus::Error> {
     portal.available_cursor_modes()
 }
 
+// https://flatpak.github.io/xdg-desktop-portal/docs/doc-org.freedesktop.portal.ScreenCast.html
+const CURSOR_MODE_HIDDEN: u32 = 1;
+const CURSOR_MODE_EMBEDDED: u32 = 2;
+const CURSOR_MODE_METADATA: u32 = 4;
+// Draw the cursor on the peer even if the compositor can embed it, for the compositors which
+// put the pointer on a hardware cursor plane and never into the captured frames.
+pub const OPTION_FORCE_SOFTWARE_CURSOR: &str = "force-software-cursor";
+static CURSOR_EMBEDDED: AtomicBool = AtomicBool::new(false);
+// The embedded cursor was not seen in the stream, the next sessions don't ask for it.
+static CURSOR_MISSING: AtomicBool = AtomicBool::new(false);
+static CURSOR_FALLEN_BACK: AtomicBool = AtomicBool::new(false);
+// A move of the embedded cursor changes the frame, it is missing after these moves without one.
+const CURSOR_MISSED_MOVES: usize = 5;
+const CURSOR_FRAME_WAIT: Duration = Duration::from_millis(500);
+
+#[derive(Default)]
+struct CursorProbe {
+    // The first move which is not followed by a frame yet.
+    pending_move: Option<Instant>,
+    missed: usize,
+}
+
+lazy_static! {
+    static ref CURSOR_PROBE: Mutex<CursorProbe> = Default::default();
+}
+
+/// Whether the cursor is drawn into the frames by the compositor, known after the capture starts.
+#[inline]
+pub fn is_cursor_embedded() -> bool {
+    CURSOR_EMBEDDED.load(Ordering::SeqCst)
+}
+
+/// Called when the pointer is moved by the peer. A compositor which draws the pointer on a
+/// hardware cursor plane doesn't change the frames, then the cursor services take over.
+/// It's not detected while other content of the screen changes all the time.
+pub fn on_pointer_moved() {
+    if !is_cursor_embedded() {
+        return;
+    }
+    let mut probe = CURSOR_PROBE.lock().unwrap();
+    match probe.pending_move {
+        None => probe.pending_move = Some(Instant::now()),
+        Some(t) if t.elapsed() >= CURSOR_FRAME_WAIT => {
+            probe.missed += 1;
+            probe.pending_move = Some(Instant::now());
+            if probe.missed >= CURSOR_MISSED_MOVES {
+                warn!("The embedded cursor is not in the stream, use the software cursor");
+                *probe = Default::default();
+                CURSOR_MISSING.store(true, Ordering::SeqCst);
+                CURSOR_EMBEDDED.store(false, Ordering::SeqCst);
+                CURSOR_FALLEN_BACK.store(true, Ordering::SeqCst);
+            }
+        }
+        Some(_) => {}
+    }
+}
+
+/// Whether the cursor has just fallen back to the software one, the peers need the new display info.
+#[inline]
+pub fn take_cursor_fallen_back() -> bool {
+    CURSOR_FALLEN_BACK.swap(false, Ordering::SeqCst)
+}
+
+fn on_frame() {
+    if is_cursor_embedded() {
+        *CURSOR_PROBE.lock().unwrap() = Default::default();
+    }
+}
+
+// The pointer is embedded if the portal can do it, as before. It is kept out of the frames if
+// the software cursor is forced or the embedded one was not seen in the stream, the cursor
+// services send it to the peer then. The cursor meta of the metadata mode can't be read through
+// pipewiresrc, so it's only asked if the hidden mode is not available.
+fn insert_cursor_mode(portal: &Proxy<&SyncConnection>, args: &mut PropMap) {
+    let available = portal.available_cursor_modes().unwrap_or_else(|e| {
+        warn!("Failed to get the available cursor modes: {}", e);
+        0
+    });
+    let forced = config::Config::get_option(OPTION_FORCE_SOFTWARE_CURSOR) == "Y";
+    let missing = CURSOR_MISSING.load(Ordering::SeqCst);
+    let embedded = !forced && !missing && available & CURSOR_MODE_EMBEDDED != 0;
+    CURSOR_EMBEDDED.store(embedded, Ordering::SeqCst);
+    *CURSOR_PROBE.lock().unwrap() = Default::default();
+    let mode = if embedded {
+        CURSOR_MODE_EMBEDDED
+    } else if available & CURSOR_MODE_HIDDEN != 0 {
+        CURSOR_MODE_HIDDEN
+    } else if available & CURSOR_MODE_METADATA != 0 {
+        CURSOR_MODE_METADATA
+    } else {
+        return;
+    };
+    debug!(
+        "Cursor mode: {}, available: {}, forced software cursor: {}, missing in the stream: {}",
+        mode, available, forced, missing
+    );
+    args.insert("cursor_mode".into(), Variant(Box::new(mode)));
+}
+
 // mostly inspired by https://gitlab.gnome.org/-/snippets/39
 pub fn request_remote_desktop() -> Result<
     (
@@ -663,6 +766,7 @@ fn on_create_session_response(
             // https://flatpak.github.io/xdg-desktop-portal/docs/doc-org.freedesktop.portal.ScreenCast.html
             // args.insert("multiple".into(), Variant(Box::new(true)));
             args.insert("types".into(), Variant(Box::new(1u32))); //| 2u32)));
+            insert_cursor_mode(&portal, &mut args);
 
             let path = portal.select_sources(ses.clone(), args)?;
             handle_response(
@@ -727,6 +831,7 @@ fn on_select_devices_response(
         // https://flatpak.github.io/xdg-desktop-portal/docs/doc-org.freedesktop.portal.ScreenCast.html
         // args.insert("multiple".into(), Variant(Box::new(true)));
         args.insert("types".into(), Variant(Box::new(1u32))); //| 2u32)));
+        insert_cursor_mode(&portal, &mut args);
 
         let session = session.clone();
         let path = portal.select_sources(session.clone(), args)?;
@@ -0,0 +1,79 @@
+#!/usr/bin/env bash
+# Smoke test of `rustdesk --server --headless` on a minimal host.
+#
+# The binary runs in a bare container next to a rendezvous server, without a display, a tray or
+# Flutter. It must register to the rendezvous server, answer the CLI control surface and accept
+# a direct connection on the loopback.
+#
+# usage: res/headless-smoke-test.sh <path of the rustdesk binary>
+
+set -euo pipefail
+
+BIN=$(realpath "${1:?the path of the rustdesk binary}")
+NET=rustdesk-headless-smoke
+HBBS=rustdesk-headless-hbbs
+HOST=rustdesk-headless-host
+# Pinned, so a new release of the server can't break the test.
+HBBS_IMAGE=rustdesk/rustdesk-server:1.1.14
+TIMEOUT=120
+CONFIG=$(mktemp -d)
+
+cleanup() {
+  docker logs "$HOST" 2>&1 | tail -n 50 || true
+  docker rm -f "$HOST" "$HBBS" >/dev/null 2>&1 || true
+  docker network rm "$NET" >/dev/null 2>&1 || true
+  rm -rf "$CONFIG"
+}
+trap cleanup EXIT
+
+cat >"$CONFIG/RustDesk2.toml" <<END
+[options]
+custom-rendezvous-server = '$HBBS'
+direct-server = 'Y'
+END
+
+docker network create "$NET" >/dev/null
+docker run -d --name "$HBBS" --network "$NET" "$HBBS_IMAGE" hbbs >/dev/null
+
+# The binary links libX11 and GTK through rdev, gtk and tao, so their shared libraries are
+# installed. No display server is.
+docker run -d --name "$HOST" --network "$NET" -e RUST_LOG=info \
+  -v "$BIN:/usr/bin/rustdesk:ro" -v "$CONFIG:/root/.config/rustdesk" ubuntu:22.04 bash -c '
+    set -e
+    apt-get -qq update >/dev/null
+    DEBIAN_FRONTEND=noninteractive apt-get -qq install -y --no-install-recommends \
+      libasound2 libgstreamer-plugins-base1.0-0 libgtk-3-0 libpam0g libpulse0 libva2 \
+      libva-drm2 libva-x11-2 libvdpau1 libxcb-randr0 libxcb-shape0 libxcb-xfixes0 libxdo3 \
+      libxfixes3 netcat-openbsd >/dev/null
+    touch /tmp/ready
+    exec rustdesk --server --headless
+  ' >/dev/null
+
+wait_for() {
+  local what=$1
+  shift
+  for _ in $(seq "$TIMEOUT"); do
+    if "$@" >/dev/null 2>&1; then
+      echo "ok: $what"
+      return 0
+    fi
+    sleep 1
+  done
+  echo "failed: $what"
+  return 1
+}
+
+wait_for "started" docker exec "$HOST" test -f /tmp/ready
+wait_for "logging to stdout" sh -c "docker logs $HOST 2>&1 | grep -q 'main start args'"
+wait_for "registered to the rendezvous server" \
+  sh -c "docker logs $HOST 2>&1 | grep -q 'pk registered to'"
+wait_for "the id by the CLI" sh -c "docker exec $HOST rustdesk --get-id | grep -Eq '^[0-9]+$'"
+# The server sends its signed id as soon as a peer connects.
+wait_for "a loopback connection" \
+  sh -c "docker exec $HOST sh -c 'nc -w 3 127.0.0.1 21118 </dev/null | head -c 1 | wc -c | grep -q 1'"
+
+if docker logs "$HOST" 2>&1 | grep -Eq -- '--tray|--cm|check-hwcodec'; then
+  echo "failed: a GUI process was started"
+  exit 1
+fi
+echo "headless smoke test passed"
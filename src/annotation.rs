@@ -0,0 +1,381 @@
+// The annotations of the peer on the screen of the host, the freehand paths, arrows, rectangles
+// and texts drawn by the controlling side to guide the user of the host.
+//
+// The peer sends `Misc.annotation` in the pixels of one of its displays. The connection checks
+// the permission, "enable-annotation", and passes the valid ones with the rect of the display to
+// the connection manager, which runs in the session of the user and draws them in a transparent
+// window on top of the display: a layered window on Windows, a popup of an RGBA visual on X11
+// with a compositor, a layer shell surface on Wayland if libgtk-layer-shell is installed. The
+// window never takes the input, the clicks and the keys go to the windows below.
+//
+// A connection has at most `MAX_PRIMITIVES` and `MAX_CONN_POINTS` points on the screen, the rest
+// is dropped until it clears. The peer is told once if the desktop has no overlay, and the
+// permission is turned off for it.
+// The annotations of a connection are cleared after "annotation-timeout" seconds without a new
+// one, 30 by default, and when it's closed.
+
+use hbb_common::{
+    config::Config,
+    log,
+    message_proto::{annotation, Annotation},
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(windows)]
+mod windows;
+#[cfg(target_os = "linux")]
+use linux as overlay;
+#[cfg(windows)]
+use windows as overlay;
+#[cfg(not(any(windows, target_os = "linux")))]
+mod overlay {
+    pub fn is_supported() -> bool {
+        false
+    }
+
+    pub fn update(_display: usize, _rect: super::Rect, _primitives: Vec<super::Primitive>) {}
+}
+
+pub const OPTION_ENABLE_ANNOTATION: &str = "enable-annotation";
+pub const OPTION_ANNOTATION_TIMEOUT: &str = "annotation-timeout";
+pub const MAX_PRIMITIVES: usize = 256;
+pub const MAX_POINTS: usize = 4096;
+pub const MAX_CONN_POINTS: usize = 16 * MAX_POINTS;
+pub const MAX_TEXT_CHARS: usize = 256;
+const MAX_WIDTH: u32 = 32;
+const MIN_FONT_SIZE: u32 = 8;
+const MAX_FONT_SIZE: u32 = 128;
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+pub type Point = (i32, i32);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "t", content = "c")]
+pub enum Shape {
+    Path(Vec<Point>),
+    Arrow(Point, Point),
+    Rectangle(Point, Point),
+    Text(Point, String, u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Primitive {
+    pub shape: Shape,
+    // 0xRRGGBB
+    pub color: u32,
+    pub width: u32,
+}
+
+/// The rect of a display of the host, x, y, width and height.
+pub type Rect = (i32, i32, i32, i32);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "t", content = "c")]
+pub enum Command {
+    Draw {
+        display: usize,
+        rect: Rect,
+        primitive: Primitive,
+    },
+    Clear,
+}
+
+/// The command of the annotation, `None` if it's invalid. The points are clipped to the display.
+pub fn to_command(a: &Annotation, rect: Rect) -> Option<Command> {
+    let (_, _, w, h) = rect;
+    if w <= 0 || h <= 0 {
+        return None;
+    }
+    let point = |i: usize| {
+        a.points
+            .get(i)
+            .map(|p| (p.x.clamp(0, w - 1), p.y.clamp(0, h - 1)))
+    };
+    let shape = match a.shape.enum_value().ok()? {
+        annotation::Shape::Clear => return Some(Command::Clear),
+        annotation::Shape::Path => {
+            if a.points.is_empty() || a.points.len() > MAX_POINTS {
+                return None;
+            }
+            Shape::Path((0..a.points.len()).filter_map(point).collect())
+        }
+        annotation::Shape::Arrow => Shape::Arrow(point(0)?, point(1)?),
+        annotation::Shape::Rectangle => Shape::Rectangle(point(0)?, point(1)?),
+        annotation::Shape::Text => {
+            let text = a.text.trim();
+            if text.is_empty() || text.chars().count() > MAX_TEXT_CHARS {
+                return None;
+            }
+            let size = a.font_size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+            Shape::Text(point(0)?, text.to_owned(), size)
+        }
+    };
+    Some(Command::Draw {
+        display: a.display as usize,
+        rect,
+        primitive: Primitive {
+            shape,
+            color: a.color & 0xFFFFFF,
+            width: a.width.clamp(1, MAX_WIDTH),
+        },
+    })
+}
+
+/// The two ends of the head of the arrow, in the pixels of the display.
+pub fn arrow_head(from: Point, to: Point, width: u32) -> [(f64, f64); 2] {
+    let (dx, dy) = ((to.0 - from.0) as f64, (to.1 - from.1) as f64);
+    let angle = dy.atan2(dx);
+    let len = (12. + 3. * width as f64).min((dx * dx + dy * dy).sqrt().max(1.));
+    let spread = std::f64::consts::PI / 7.;
+    [angle + spread, angle - spread]
+        .map(|a| (to.0 as f64 - len * a.cos(), to.1 as f64 - len * a.sin()))
+}
+
+struct Drawing {
+    conn_id: i32,
+    display: usize,
+    primitive: Primitive,
+}
+
+impl Primitive {
+    fn points(&self) -> usize {
+        match &self.shape {
+            Shape::Path(points) => points.len(),
+            Shape::Arrow(..) | Shape::Rectangle(..) => 2,
+            Shape::Text(..) => 1,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    drawings: Vec<Drawing>,
+    rects: HashMap<usize, Rect>,
+    last_update: HashMap<i32, Instant>,
+    timer_running: bool,
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Default::default();
+}
+
+fn timeout() -> Duration {
+    let secs = Config::get_option(OPTION_ANNOTATION_TIMEOUT)
+        .parse::<u64>()
+        .unwrap_or(DEFAULT_TIMEOUT_SECS);
+    Duration::from_secs(secs.max(1))
+}
+
+impl State {
+    // False if the connection has too many annotations on the screen.
+    fn push(&mut self, drawing: Drawing) -> bool {
+        let (count, points) = self
+            .drawings
+            .iter()
+            .filter(|d| d.conn_id == drawing.conn_id)
+            .fold((0, 0), |(c, p), d| (c + 1, p + d.primitive.points()));
+        if count >= MAX_PRIMITIVES || points + drawing.primitive.points() > MAX_CONN_POINTS {
+            return false;
+        }
+        self.drawings.push(drawing);
+        true
+    }
+}
+
+/// Called by the connection manager for the annotations of a connection, false if there's no
+/// overlay on this desktop to draw them.
+pub fn handle(conn_id: i32, cmd: Command) -> bool {
+    if !overlay::is_supported() {
+        log::debug!("No annotation overlay on this desktop");
+        return false;
+    }
+    match cmd {
+        Command::Clear => clear(conn_id),
+        Command::Draw {
+            display,
+            rect,
+            primitive,
+        } => {
+            let mut state = STATE.lock().unwrap();
+            if !state.push(Drawing {
+                conn_id,
+                display,
+                primitive,
+            }) {
+                log::debug!("Too many annotations of connection {}, dropped", conn_id);
+                return true;
+            }
+            state.rects.insert(display, rect);
+            state.last_update.insert(conn_id, Instant::now());
+            redraw(&state, display);
+            if !state.timer_running {
+                state.timer_running = true;
+                std::thread::spawn(clear_expired);
+            }
+        }
+    }
+    true
+}
+
+/// Removes the annotations of a connection, e.g. when it's closed.
+pub fn clear(conn_id: i32) {
+    let mut state = STATE.lock().unwrap();
+    state.last_update.remove(&conn_id);
+    let mut displays = state
+        .drawings
+        .iter()
+        .filter(|d| d.conn_id == conn_id)
+        .map(|d| d.display)
+        .collect::<Vec<_>>();
+    if displays.is_empty() {
+        return;
+    }
+    displays.sort();
+    displays.dedup();
+    state.drawings.retain(|d| d.conn_id != conn_id);
+    for display in displays {
+        redraw(&state, display);
+    }
+}
+
+fn redraw(state: &State, display: usize) {
+    let primitives = state
+        .drawings
+        .iter()
+        .filter(|d| d.display == display)
+        .map(|d| d.primitive.clone())
+        .collect::<Vec<_>>();
+    let rect = state.rects.get(&display).copied().unwrap_or_default();
+    overlay::update(display, rect, primitives);
+}
+
+fn clear_expired() {
+    loop {
+        std::thread::sleep(Duration::from_secs(1));
+        let expired = {
+            let mut state = STATE.lock().unwrap();
+            if state.last_update.is_empty() {
+                state.timer_running = false;
+                return;
+            }
+            let timeout = timeout();
+            state
+                .last_update
+                .iter()
+                .filter(|(_, t)| t.elapsed() >= timeout)
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>()
+        };
+        for conn_id in expired {
+            clear(conn_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbb_common::message_proto::AnnotationPoint;
+
+    fn annotation(shape: annotation::Shape, points: &[Point]) -> Annotation {
+        Annotation {
+            display: 1,
+            shape: shape.into(),
+            points: points
+                .iter()
+                .map(|(x, y)| AnnotationPoint {
+                    x: *x,
+                    y: *y,
+                    ..Default::default()
+                })
+                .collect(),
+            color: 0xFFFF0000,
+            width: 100,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_to_command() {
+        let rect = (1920, 0, 1280, 720);
+        let a = annotation(annotation::Shape::Arrow, &[(-5, 10), (2000, 700)]);
+        assert_eq!(
+            to_command(&a, rect),
+            Some(Command::Draw {
+                display: 1,
+                rect,
+                primitive: Primitive {
+                    shape: Shape::Arrow((0, 10), (1279, 700)),
+                    color: 0xFF0000,
+                    width: MAX_WIDTH,
+                },
+            })
+        );
+        assert_eq!(
+            to_command(&annotation(annotation::Shape::Rectangle, &[(1, 1)]), rect),
+            None
+        );
+        let many = vec![(1, 1); MAX_POINTS + 1];
+        assert_eq!(
+            to_command(&annotation(annotation::Shape::Path, &many), rect),
+            None
+        );
+        let mut text = annotation(annotation::Shape::Text, &[(10, 10)]);
+        assert_eq!(to_command(&text, rect), None);
+        text.text = "Click here".to_owned();
+        assert!(matches!(
+            to_command(&text, rect),
+            Some(Command::Draw {
+                primitive: Primitive {
+                    shape: Shape::Text(_, _, MIN_FONT_SIZE),
+                    ..
+                },
+                ..
+            })
+        ));
+        assert_eq!(
+            to_command(&annotation(annotation::Shape::Clear, &[]), rect),
+            Some(Command::Clear)
+        );
+    }
+
+    #[test]
+    fn test_conn_limits() {
+        let path = |n: usize| Primitive {
+            shape: Shape::Path(vec![(1, 1); n]),
+            color: 0,
+            width: 1,
+        };
+        let drawing = |conn_id: i32, primitive: Primitive| Drawing {
+            conn_id,
+            display: 0,
+            primitive,
+        };
+        let mut state = State::default();
+        for _ in 0..MAX_CONN_POINTS / MAX_POINTS {
+            assert!(state.push(drawing(1, path(MAX_POINTS))));
+        }
+        assert!(!state.push(drawing(1, path(1))));
+        assert!(state.push(drawing(2, path(MAX_POINTS))));
+        let mut state = State::default();
+        for _ in 0..MAX_PRIMITIVES {
+            assert!(state.push(drawing(1, path(1))));
+        }
+        assert!(!state.push(drawing(1, path(1))));
+    }
+
+    #[test]
+    fn test_arrow_head() {
+        let [a, b] = arrow_head((0, 0), (100, 0), 1);
+        assert!(a.0 < 100. && b.0 < 100.);
+        assert!((a.1 + b.1).abs() < 1e-9);
+        assert!((a.1 - b.1).abs() > 1.);
+    }
+}
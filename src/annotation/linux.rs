@@ -0,0 +1,226 @@
+// A popup window per display in the gtk main loop of the connection manager, of the RGBA visual,
+// with an empty input shape so the input goes to the windows below. X11 needs a compositor for
+// the transparency. Wayland needs the layer shell to place the window on the display and on top,
+// libgtk-layer-shell is loaded if it's installed, the annotations are ignored otherwise.
+
+use super::{arrow_head, Primitive, Rect, Shape};
+use gtk::{cairo, gdk, glib, prelude::*};
+use hbb_common::{dlopen::symbor::Library, log};
+use std::{cell::RefCell, collections::HashMap, ffi::c_void, os::raw::c_int, rc::Rc};
+
+const LIB_LAYER_SHELL: &str = "libgtk-layer-shell.so.0";
+// GtkLayerShellLayer and GtkLayerShellEdge
+const LAYER_OVERLAY: c_int = 3;
+const EDGES: [c_int; 4] = [0, 1, 2, 3];
+
+type Shared = Rc<RefCell<(Rect, Vec<Primitive>)>>;
+
+lazy_static::lazy_static! {
+    static ref HAS_LAYER_SHELL: bool = Library::open(LIB_LAYER_SHELL).is_ok();
+}
+
+thread_local! {
+    static WINDOWS: RefCell<HashMap<usize, (gtk::Window, Shared)>> = Default::default();
+    static LAYER_SHELL: Option<LayerShell> = LayerShell::load();
+}
+
+pub fn is_supported() -> bool {
+    crate::platform::linux::is_x11() || *HAS_LAYER_SHELL
+}
+
+pub fn update(display: usize, rect: Rect, primitives: Vec<Primitive>) {
+    glib::MainContext::default().invoke(move || update_(display, rect, primitives));
+}
+
+fn update_(display: usize, rect: Rect, primitives: Vec<Primitive>) {
+    WINDOWS.with(|windows| {
+        let mut windows = windows.borrow_mut();
+        if primitives.is_empty() {
+            if let Some((window, _)) = windows.remove(&display) {
+                window.close();
+            }
+            return;
+        }
+        if !windows.contains_key(&display) {
+            let Some(created) = create(rect) else {
+                return;
+            };
+            windows.insert(display, created);
+        }
+        if let Some((window, shared)) = windows.get(&display) {
+            *shared.borrow_mut() = (rect, primitives);
+            // The layer shell fills the monitor.
+            if crate::platform::linux::is_x11() {
+                window.move_(rect.0, rect.1);
+                window.resize(rect.2, rect.3);
+            }
+            window.queue_draw();
+        }
+    });
+}
+
+fn create(rect: Rect) -> Option<(gtk::Window, Shared)> {
+    let x11 = crate::platform::linux::is_x11();
+    let layer_shell = !x11 && LAYER_SHELL.with(|l| l.is_some());
+    let window = gtk::Window::new(if layer_shell {
+        gtk::WindowType::Toplevel
+    } else {
+        gtk::WindowType::Popup
+    });
+    let screen = WidgetExt::screen(&window)?;
+    let Some(visual) = screen.rgba_visual().filter(|_| screen.is_composited()) else {
+        log::warn!("No compositor, no annotation overlay");
+        return None;
+    };
+    if !x11 && !layer_shell {
+        log::warn!("No layer shell, no annotation overlay on Wayland");
+        return None;
+    }
+    window.set_visual(Some(&visual));
+    window.set_app_paintable(true);
+    window.set_decorated(false);
+    window.set_accept_focus(false);
+    window.set_focus_on_map(false);
+    window.set_keep_above(true);
+    window.set_skip_taskbar_hint(true);
+    window.set_skip_pager_hint(true);
+    if x11 {
+        window.move_(rect.0, rect.1);
+        window.set_default_size(rect.2, rect.3);
+    } else {
+        let monitor = gdk::Display::default()
+            .and_then(|d| d.monitor_at_point(rect.0 + rect.2 / 2, rect.1 + rect.3 / 2));
+        LAYER_SHELL.with(|l| {
+            if let Some(l) = l {
+                l.place(&window, monitor.as_ref());
+            }
+        });
+    }
+    let shared: Shared = Default::default();
+    let s = shared.clone();
+    window.connect_draw(move |widget, cr| {
+        cr.set_operator(cairo::Operator::Source);
+        cr.set_source_rgba(0., 0., 0., 0.);
+        cr.paint().ok();
+        cr.set_operator(cairo::Operator::Over);
+        let (rect, primitives) = &*s.borrow();
+        // The size of the window is in the logical pixels with the scaling of the desktop.
+        if rect.2 > 0 && rect.3 > 0 {
+            cr.scale(
+                widget.allocated_width() as f64 / rect.2 as f64,
+                widget.allocated_height() as f64 / rect.3 as f64,
+            );
+        }
+        for p in primitives.iter() {
+            paint(cr, p);
+        }
+        glib::Propagation::Stop
+    });
+    window.show_all();
+    // Empty, never takes the input.
+    window.input_shape_combine_region(Some(&cairo::Region::create()));
+    Some((window, shared))
+}
+
+fn paint(cr: &cairo::Context, p: &Primitive) {
+    cr.set_source_rgb(
+        ((p.color >> 16) & 0xFF) as f64 / 255.,
+        ((p.color >> 8) & 0xFF) as f64 / 255.,
+        (p.color & 0xFF) as f64 / 255.,
+    );
+    cr.set_line_width(p.width as f64);
+    cr.set_line_cap(cairo::LineCap::Round);
+    cr.set_line_join(cairo::LineJoin::Round);
+    match &p.shape {
+        Shape::Path(points) => {
+            let (x, y) = points[0];
+            cr.move_to(x as _, y as _);
+            for (x, y) in points.iter() {
+                cr.line_to(*x as _, *y as _);
+            }
+        }
+        Shape::Arrow(from, to) => {
+            cr.move_to(from.0 as _, from.1 as _);
+            cr.line_to(to.0 as _, to.1 as _);
+            for (x, y) in arrow_head(*from, *to, p.width) {
+                cr.move_to(to.0 as _, to.1 as _);
+                cr.line_to(x, y);
+            }
+        }
+        Shape::Rectangle(a, b) => {
+            cr.rectangle(
+                a.0.min(b.0) as _,
+                a.1.min(b.1) as _,
+                (a.0 - b.0).abs() as _,
+                (a.1 - b.1).abs() as _,
+            );
+        }
+        Shape::Text((x, y), text, size) => {
+            cr.select_font_face("Sans", cairo::FontSlant::Normal, cairo::FontWeight::Bold);
+            cr.set_font_size(*size as _);
+            cr.move_to(*x as _, (*y + *size as i32) as _);
+            cr.show_text(text).ok();
+            return;
+        }
+    }
+    cr.stroke().ok();
+}
+
+struct LayerShell {
+    _lib: Library,
+    init_for_window: unsafe extern "C" fn(*mut c_void),
+    set_layer: unsafe extern "C" fn(*mut c_void, c_int),
+    set_anchor: unsafe extern "C" fn(*mut c_void, c_int, c_int),
+    set_exclusive_zone: unsafe extern "C" fn(*mut c_void, c_int),
+    set_keyboard_interactivity: unsafe extern "C" fn(*mut c_void, c_int),
+    set_monitor: unsafe extern "C" fn(*mut c_void, *mut c_void),
+}
+
+impl LayerShell {
+    // In the gtk main loop, after gtk is initialized.
+    fn load() -> Option<Self> {
+        let lib = Library::open(LIB_LAYER_SHELL).ok()?;
+        unsafe {
+            let is_supported = *lib
+                .symbol::<unsafe extern "C" fn() -> c_int>("gtk_layer_is_supported")
+                .ok()?;
+            if is_supported() == 0 {
+                log::info!("The layer shell is not supported by the compositor");
+                return None;
+            }
+            let init_for_window = *lib.symbol("gtk_layer_init_for_window").ok()?;
+            let set_layer = *lib.symbol("gtk_layer_set_layer").ok()?;
+            let set_anchor = *lib.symbol("gtk_layer_set_anchor").ok()?;
+            let set_exclusive_zone = *lib.symbol("gtk_layer_set_exclusive_zone").ok()?;
+            let set_keyboard_interactivity =
+                *lib.symbol("gtk_layer_set_keyboard_interactivity").ok()?;
+            let set_monitor = *lib.symbol("gtk_layer_set_monitor").ok()?;
+            Some(Self {
+                _lib: lib,
+                init_for_window,
+                set_layer,
+                set_anchor,
+                set_exclusive_zone,
+                set_keyboard_interactivity,
+                set_monitor,
+            })
+        }
+    }
+
+    // Fills the monitor on the overlay layer, before the window is realized.
+    fn place(&self, window: &gtk::Window, monitor: Option<&gdk::Monitor>) {
+        let w = window.as_ptr() as *mut c_void;
+        unsafe {
+            (self.init_for_window)(w);
+            (self.set_layer)(w, LAYER_OVERLAY);
+            for edge in EDGES {
+                (self.set_anchor)(w, edge, 1);
+            }
+            (self.set_exclusive_zone)(w, -1);
+            (self.set_keyboard_interactivity)(w, 0);
+            if let Some(monitor) = monitor {
+                (self.set_monitor)(w, monitor.as_ptr() as *mut c_void);
+            }
+        }
+    }
+}
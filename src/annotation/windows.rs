@@ -0,0 +1,288 @@
+// A layered topmost window per display, the color key is transparent and it's transparent to
+// the mouse, so it only shows the annotations. Every window has its own thread and message loop.
+
+use super::{arrow_head, Primitive, Rect, Shape};
+use hbb_common::log;
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    os::windows::ffi::OsStrExt,
+    ptr::null_mut,
+    sync::{mpsc, Mutex},
+};
+use winapi::{
+    shared::{
+        minwindef::{LPARAM, LRESULT, UINT, WPARAM},
+        windef::{HDC, HWND, POINT, RECT},
+    },
+    um::{
+        libloaderapi::GetModuleHandleW,
+        wingdi::{
+            CreateFontW, CreateSolidBrush, DeleteObject, Ellipse, ExtCreatePen, GetStockObject,
+            LineTo, MoveToEx, Polyline, Rectangle, SelectObject, SetBkMode, SetTextColor, TextOutW,
+            BS_SOLID, CLIP_DEFAULT_PRECIS, DEFAULT_CHARSET, DEFAULT_PITCH, FW_BOLD, LOGBRUSH,
+            NONANTIALIASED_QUALITY, NULL_BRUSH, OUT_DEFAULT_PRECIS, PS_ENDCAP_ROUND, PS_GEOMETRIC,
+            PS_JOIN_ROUND, PS_SOLID, RGB, TRANSPARENT,
+        },
+        winuser::*,
+    },
+};
+
+const CLASS_NAME: &str = "RustDeskAnnotationOverlay";
+const WM_UPDATE: UINT = WM_APP + 1;
+// Transparent, the colors of the annotations are moved off it.
+const KEY_COLOR: u32 = 0x00010203;
+
+struct Overlay {
+    hwnd: usize,
+    rect: Rect,
+    primitives: Vec<Primitive>,
+}
+
+lazy_static::lazy_static! {
+    static ref OVERLAYS: Mutex<HashMap<usize, Overlay>> = Default::default();
+}
+
+pub fn is_supported() -> bool {
+    true
+}
+
+/// Called in order, under the lock of the annotations.
+pub fn update(display: usize, rect: Rect, primitives: Vec<Primitive>) {
+    {
+        let mut overlays = OVERLAYS.lock().unwrap();
+        if primitives.is_empty() {
+            if let Some(overlay) = overlays.remove(&display) {
+                unsafe { PostMessageW(overlay.hwnd as _, WM_CLOSE, 0, 0) };
+            }
+            return;
+        }
+        if let Some(overlay) = overlays.get_mut(&display) {
+            overlay.rect = rect;
+            overlay.primitives = primitives;
+            unsafe { PostMessageW(overlay.hwnd as _, WM_UPDATE, 0, 0) };
+            return;
+        }
+    }
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || run(rect, tx));
+    match rx.recv() {
+        Ok(hwnd) if hwnd != 0 => {
+            OVERLAYS.lock().unwrap().insert(
+                display,
+                Overlay {
+                    hwnd,
+                    rect,
+                    primitives,
+                },
+            );
+            unsafe { PostMessageW(hwnd as _, WM_UPDATE, 0, 0) };
+        }
+        _ => log::error!(
+            "Failed to create the annotation overlay of display {}",
+            display
+        ),
+    }
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+fn run(rect: Rect, tx: mpsc::Sender<usize>) {
+    unsafe {
+        let class_name = wide(CLASS_NAME);
+        let hinstance = GetModuleHandleW(null_mut());
+        let wc = WNDCLASSEXW {
+            cbSize: std::mem::size_of::<WNDCLASSEXW>() as _,
+            lpfnWndProc: Some(wnd_proc),
+            hInstance: hinstance,
+            lpszClassName: class_name.as_ptr(),
+            ..std::mem::zeroed()
+        };
+        // Fails if it's registered by the window of another display.
+        RegisterClassExW(&wc);
+        let (x, y, w, h) = rect;
+        let hwnd = CreateWindowExW(
+            WS_EX_LAYERED | WS_EX_TRANSPARENT | WS_EX_TOPMOST | WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE,
+            class_name.as_ptr(),
+            class_name.as_ptr(),
+            WS_POPUP,
+            x,
+            y,
+            w,
+            h,
+            null_mut(),
+            null_mut(),
+            hinstance,
+            null_mut(),
+        );
+        if hwnd.is_null() {
+            log::error!(
+                "Failed to create the annotation window: {}",
+                std::io::Error::last_os_error()
+            );
+            tx.send(0).ok();
+            return;
+        }
+        SetLayeredWindowAttributes(hwnd, KEY_COLOR, 255, LWA_COLORKEY as _);
+        ShowWindow(hwnd, SW_SHOWNOACTIVATE);
+        tx.send(hwnd as usize).ok();
+        let mut msg: MSG = std::mem::zeroed();
+        while GetMessageW(&mut msg, null_mut(), 0, 0) > 0 {
+            TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+    }
+}
+
+unsafe extern "system" fn wnd_proc(
+    hwnd: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        // Never takes the mouse or the focus.
+        WM_NCHITTEST => HTTRANSPARENT as _,
+        WM_MOUSEACTIVATE => MA_NOACTIVATE as _,
+        WM_ERASEBKGND => 1,
+        WM_UPDATE => {
+            let rect = OVERLAYS
+                .lock()
+                .unwrap()
+                .values()
+                .find(|o| o.hwnd == hwnd as usize)
+                .map(|o| o.rect);
+            if let Some((x, y, w, h)) = rect {
+                SetWindowPos(
+                    hwnd,
+                    HWND_TOPMOST,
+                    x,
+                    y,
+                    w,
+                    h,
+                    SWP_NOACTIVATE | SWP_SHOWWINDOW,
+                );
+            }
+            InvalidateRect(hwnd, null_mut(), 0);
+            0
+        }
+        WM_PAINT => {
+            let mut ps: PAINTSTRUCT = std::mem::zeroed();
+            let hdc = BeginPaint(hwnd, &mut ps);
+            let mut client: RECT = std::mem::zeroed();
+            GetClientRect(hwnd, &mut client);
+            let background = CreateSolidBrush(KEY_COLOR);
+            FillRect(hdc, &client, background);
+            DeleteObject(background as _);
+            let primitives = OVERLAYS
+                .lock()
+                .unwrap()
+                .values()
+                .find(|o| o.hwnd == hwnd as usize)
+                .map(|o| o.primitives.clone())
+                .unwrap_or_default();
+            for p in primitives.iter() {
+                paint(hdc, p);
+            }
+            EndPaint(hwnd, &ps);
+            0
+        }
+        WM_CLOSE => {
+            DestroyWindow(hwnd);
+            0
+        }
+        WM_DESTROY => {
+            PostQuitMessage(0);
+            0
+        }
+        _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+}
+
+fn color_ref(color: u32) -> u32 {
+    let c = RGB((color >> 16) as u8, (color >> 8) as u8, color as u8);
+    if c == KEY_COLOR {
+        c + 1
+    } else {
+        c
+    }
+}
+
+unsafe fn paint(hdc: HDC, p: &Primitive) {
+    let color = color_ref(p.color);
+    let brush = LOGBRUSH {
+        lbStyle: BS_SOLID as _,
+        lbColor: color,
+        lbHatch: 0,
+    };
+    let pen = ExtCreatePen(
+        (PS_GEOMETRIC | PS_SOLID | PS_ENDCAP_ROUND | PS_JOIN_ROUND) as _,
+        p.width,
+        &brush,
+        0,
+        null_mut(),
+    );
+    let old_pen = SelectObject(hdc, pen as _);
+    let old_brush = SelectObject(hdc, GetStockObject(NULL_BRUSH as _));
+    match &p.shape {
+        Shape::Path(points) if points.len() == 1 => {
+            let (x, y) = points[0];
+            let r = (p.width as i32 / 2).max(1);
+            let dot = CreateSolidBrush(color);
+            SelectObject(hdc, dot as _);
+            Ellipse(hdc, x - r, y - r, x + r, y + r);
+            SelectObject(hdc, GetStockObject(NULL_BRUSH as _));
+            DeleteObject(dot as _);
+        }
+        Shape::Path(points) => {
+            let points = points
+                .iter()
+                .map(|(x, y)| POINT { x: *x, y: *y })
+                .collect::<Vec<_>>();
+            Polyline(hdc, points.as_ptr(), points.len() as _);
+        }
+        Shape::Arrow(from, to) => {
+            MoveToEx(hdc, from.0, from.1, null_mut());
+            LineTo(hdc, to.0, to.1);
+            for (x, y) in arrow_head(*from, *to, p.width) {
+                MoveToEx(hdc, to.0, to.1, null_mut());
+                LineTo(hdc, x.round() as _, y.round() as _);
+            }
+        }
+        Shape::Rectangle(a, b) => {
+            Rectangle(hdc, a.0.min(b.0), a.1.min(b.1), a.0.max(b.0), a.1.max(b.1));
+        }
+        Shape::Text((x, y), text, size) => {
+            let face = wide("Segoe UI");
+            // Not antialiased, the blended edges would show the color key.
+            let font = CreateFontW(
+                -(*size as i32),
+                0,
+                0,
+                0,
+                FW_BOLD as _,
+                0,
+                0,
+                0,
+                DEFAULT_CHARSET as _,
+                OUT_DEFAULT_PRECIS as _,
+                CLIP_DEFAULT_PRECIS as _,
+                NONANTIALIASED_QUALITY as _,
+                DEFAULT_PITCH as _,
+                face.as_ptr(),
+            );
+            let old_font = SelectObject(hdc, font as _);
+            SetBkMode(hdc, TRANSPARENT as _);
+            SetTextColor(hdc, color);
+            let text = text.encode_utf16().collect::<Vec<_>>();
+            TextOutW(hdc, *x, *y, text.as_ptr(), text.len() as _);
+            SelectObject(hdc, old_font);
+            DeleteObject(font as _);
+        }
+    }
+    SelectObject(hdc, old_brush);
+    SelectObject(hdc, old_pen);
+    DeleteObject(pen as _);
+}
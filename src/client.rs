@@ -29,7 +29,10 @@ use uuid::Uuid;
 
 use crate::{
     check_port,
-    common::input::{MOUSE_BUTTON_LEFT, MOUSE_BUTTON_RIGHT, MOUSE_TYPE_DOWN, MOUSE_TYPE_UP},
+    common::{
+        input::{MOUSE_BUTTON_LEFT, MOUSE_BUTTON_RIGHT, MOUSE_TYPE_DOWN, MOUSE_TYPE_UP},
+        Capabilities, Capability, LineEnding,
+    },
     create_symmetric_key_msg, decode_id_pk, get_rs_pk, is_keyboard_mode_supported,
     kcp_stream::KcpStream,
     secure_tcp,
@@ -59,7 +62,7 @@ use hbb_common::{
     rendezvous_proto::*,
     sha2::{Digest, Sha256},
     socket_client::{connect_tcp, connect_tcp_local, ipv4_to_ipv6, new_direct_udp_for},
-    sodiumoxide::{base64, crypto::sign},
+    sodiumoxide::{base64, crypto::{secretbox, sign}},
     timeout,
     tokio::{
         self,
@@ -99,7 +102,6 @@ pub const SEC30: Duration = Duration::from_secs(30);
 pub const VIDEO_QUEUE_SIZE: usize = 120;
 const MAX_DECODE_FAIL_COUNTER: usize = 3;
 
-#[cfg(target_os = "linux")]
 pub const LOGIN_MSG_DESKTOP_NOT_INITED: &str = "Desktop env is not inited";
 pub const LOGIN_MSG_DESKTOP_SESSION_NOT_READY: &str = "Desktop session not ready";
 pub const LOGIN_MSG_DESKTOP_XSESSION_FAILED: &str = "Desktop xsession failed";
@@ -117,6 +119,7 @@ pub const LOGIN_MSG_2FA_WRONG: &str = "Wrong 2FA Code";
 pub const REQUIRE_2FA: &'static str = "2FA Required";
 pub const LOGIN_MSG_NO_PASSWORD_ACCESS: &str = "No Password Access";
 pub const LOGIN_MSG_OFFLINE: &str = "Offline";
+pub const LOGIN_MSG_ENCRYPTION_REQUIRED: &str = "Encryption Required";
 pub const LOGIN_SCREEN_WAYLAND: &str = "Wayland login screen is not supported";
 #[cfg(target_os = "linux")]
 pub const SCRAP_UBUNTU_HIGHER_REQUIRED: &str = "Wayland requires Ubuntu 21.04 or higher version.";
@@ -211,6 +214,14 @@ impl Client {
         }
     }
 
+    /// Connect to the direct access port of the peer, through the TLS tunnel if configured.
+    async fn connect_direct(target: String) -> ResultType<Stream> {
+        if crate::tls_tunnel::is_dial_enabled() {
+            return crate::tls_tunnel::connect(&target, CONNECT_TIMEOUT).await;
+        }
+        connect_tcp_local(target, None, CONNECT_TIMEOUT).await
+    }
+
     /// Start a new connection.
     async fn _start(
         peer: &str,
@@ -229,8 +240,11 @@ impl Client {
         if hbb_common::is_ip_str(peer) {
             return Ok((
                 (
-                    connect_tcp_local(check_port(peer, RELAY_PORT + 1), None, CONNECT_TIMEOUT)
-                        .await?,
+                    Self::connect_direct(crate::common::constrain_direct_target(check_port(
+                        peer,
+                        RELAY_PORT + 1,
+                    ))?)
+                    .await?,
                     true,
                     None,
                     None,
@@ -242,7 +256,8 @@ impl Client {
         if hbb_common::is_domain_port_str(peer) {
             return Ok((
                 (
-                    connect_tcp_local(peer, None, CONNECT_TIMEOUT).await?,
+                    Self::connect_direct(crate::common::constrain_direct_target(peer.to_owned())?)
+                        .await?,
                     true,
                     None,
                     None,
@@ -476,8 +491,14 @@ impl Client {
                         let mut conn = conn?;
                         feedback = rr.feedback;
                         log::info!("{:?} used to establish {typ} connection", start.elapsed());
-                        let pk =
-                            Self::secure_connection(peer, signed_id_pk, key, &mut conn).await?;
+                        let pk = Self::secure_connection(
+                            peer,
+                            signed_id_pk,
+                            key,
+                            &mut conn,
+                            &interface.get_lch(),
+                        )
+                        .await?;
                         return Ok(((conn, false, pk, kcp), (feedback, rendezvous_server)));
                     }
                     _ => {
@@ -580,14 +601,23 @@ impl Client {
         let start = std::time::Instant::now();
 
         let mut connect_futures = Vec::new();
-        let fut = connect_tcp_local(peer, Some(local_addr), connect_timeout);
-        connect_futures.push(
-            async move {
-                let conn = fut.await?;
-                Ok((conn, None, "TCP"))
-            }
-            .boxed(),
-        );
+        let ip_family = crate::common::get_direct_ip_family();
+        if ip_family.matches(&peer) {
+            let fut = connect_tcp_local(peer, Some(local_addr), connect_timeout);
+            connect_futures.push(
+                async move {
+                    let conn = fut.await?;
+                    Ok((conn, None, "TCP"))
+                }
+                .boxed(),
+            );
+        } else {
+            log::info!(
+                "Skip direct TCP to {}, direct ip family: {:?}",
+                peer,
+                ip_family
+            );
+        }
         if let Some(udp_socket_nat) = udp_socket_nat {
             connect_futures.push(udp_nat_connect(udp_socket_nat, "UDP").boxed());
         }
@@ -595,9 +625,13 @@ impl Client {
             connect_futures.push(udp_nat_connect(udp_socket_v6, "IPv6").boxed());
         }
         // Run all connection attempts concurrently, return the first successful one
-        let (mut conn, kcp, mut typ) = match select_ok(connect_futures).await {
-            Ok(conn) => (Ok(conn.0 .0), conn.0 .1, conn.0 .2),
-            Err(e) => (Err(e), None, ""),
+        let (mut conn, kcp, mut typ) = if connect_futures.is_empty() {
+            (Err(anyhow!("No direct connection candidate")), None, "")
+        } else {
+            match select_ok(connect_futures).await {
+                Ok(conn) => (Ok(conn.0 .0), conn.0 .1, conn.0 .2),
+                Err(e) => (Err(e), None, ""),
+            }
         };
 
         let mut direct = !conn.is_err();
@@ -631,7 +665,9 @@ impl Client {
         }
         let mut conn = conn?;
         log::info!("{:?} used to establish {typ} connection", start.elapsed());
-        let pk = Self::secure_connection(peer_id, signed_id_pk, key, &mut conn).await?;
+        let pk =
+            Self::secure_connection(peer_id, signed_id_pk, key, &mut conn, &interface.get_lch())
+                .await?;
         Ok((conn, direct, pk, kcp))
     }
 
@@ -641,7 +677,13 @@ impl Client {
         signed_id_pk: Vec<u8>,
         key: &str,
         conn: &mut Stream,
+        lc: &Arc<RwLock<LoginConfigHandler>>,
     ) -> ResultType<Option<Vec<u8>>> {
+        {
+            let mut lc = lc.write().unwrap();
+            lc.early_challenge = None;
+            lc.session_fingerprint = None;
+        }
         let rs_pk = get_rs_pk(if key.is_empty() {
             config::RS_PUB_KEY
         } else {
@@ -679,6 +721,11 @@ impl Client {
                             if id == peer_id {
                                 let (asymmetric_value, symmetric_value, key) =
                                     create_symmetric_key_msg(their_pk_b);
+                                let fingerprint = crate::session_fingerprint::fingerprint(
+                                    &sign_pk.0,
+                                    &their_pk_b,
+                                    &asymmetric_value,
+                                );
                                 let mut msg_out = Message::new();
                                 msg_out.set_public_key(PublicKey {
                                     asymmetric_value,
@@ -687,6 +734,16 @@ impl Client {
                                 });
                                 timeout(CONNECT_TIMEOUT, conn.send(&msg_out)).await??;
                                 conn.set_key(key);
+                                lc.write().unwrap().session_fingerprint = Some(fingerprint);
+                                // An old host sends neither the version nor the challenge.
+                                if !si.version.is_empty() {
+                                    let mut lc = lc.write().unwrap();
+                                    lc.peer_capabilities =
+                                        Capabilities::negotiate(si.capabilities, &si.version);
+                                    if !si.challenge.is_empty() {
+                                        lc.early_challenge = Some(si.challenge);
+                                    }
+                                }
                             } else {
                                 log::error!("Handshake failed: sign failure");
                                 conn.send(&Message::new()).await?;
@@ -698,6 +755,14 @@ impl Client {
                             msg_out.set_public_key(PublicKey::new());
                             conn.send(&msg_out).await?;
                         }
+                    } else if let Some(message::Union::LoginResponse(LoginResponse {
+                        union: Some(login_response::Union::Error(err)),
+                        error_code,
+                        ..
+                    })) = msg_in.union
+                    {
+                        // The host refuses to go on without encryption.
+                        bail!(crate::error_code::canonical_text(error_code, &err));
                     } else {
                         log::error!("Handshake failed: invalid message type");
                         conn.send(&Message::new()).await?;
@@ -805,6 +870,35 @@ impl Client {
         Ok(conn)
     }
 
+    /// Connect to the peer through another relay for the running session, the peer has connected
+    /// to the relay with the uuid. Only the transport moves, there's no new login, the stream is
+    /// returned after the peer takes it, see `join_relay_migration`.
+    pub async fn migrate_relay(
+        lc: Arc<RwLock<LoginConfigHandler>>,
+        key: &str,
+        migration: RelayMigration,
+        conn_type: ConnType,
+    ) -> ResultType<Stream> {
+        let (peer, key) = {
+            let lc = lc.read().unwrap();
+            match lc.other_server.as_ref() {
+                Some((peer, _, key)) => (peer.clone(), key.clone()),
+                None => (lc.id.clone(), key.to_owned()),
+            }
+        };
+        let mut conn = Self::create_relay(
+            &peer,
+            migration.uuid.clone(),
+            migration.relay_server,
+            &key,
+            conn_type,
+            true,
+        )
+        .await?;
+        join_relay_migration(&mut conn, &migration.uuid, &migration.key).await?;
+        Ok(conn)
+    }
+
     #[inline]
     #[cfg(feature = "flutter")]
     #[cfg(not(target_os = "ios"))]
@@ -1071,6 +1165,12 @@ pub struct AudioHandler {
     device_channel: u16,
     #[cfg(not(target_os = "linux"))]
     ready: Arc<std::sync::Mutex<bool>>,
+    // Play on the output device of the voice call, restart the playback when it changes.
+    voice_call: bool,
+    output_device_seq: usize,
+    format: Option<AudioFormat>,
+    // The samples per channel of the last decoded frame, the length of a concealed gap.
+    last_frame_samples: usize,
 }
 
 #[cfg(not(target_os = "linux"))]
@@ -1211,11 +1311,17 @@ impl AudioHandler {
             bail!("Invalid audio format");
         }
 
+        // The devices are selected by the description, as the sound inputs.
+        let device = self
+            .get_voice_call_output_device()
+            .map(|desc| crate::platform::linux::get_pa_sink_name(&desc))
+            .filter(|name| !name.is_empty());
+        log::info!("Using output device: {:?}", device);
         self.simple = Some(Simple::new(
             None,                   // Use the default server
             &crate::get_app_name(), // Our application’s name
             Direction::Playback,    // We want a playback stream
-            None,                   // Use the default device
+            device.as_deref(),      // None means the default device
             "playback",             // Description of our stream
             &spec,                  // Our sample format
             None,                   // Use default channel map
@@ -1228,11 +1334,9 @@ impl AudioHandler {
     /// Start the audio playback.
     #[cfg(not(target_os = "linux"))]
     fn start_audio(&mut self, format0: AudioFormat) -> ResultType<()> {
-        let device = AUDIO_HOST
-            .default_output_device()
-            .with_context(|| "Failed to get default output device")?;
+        let device = self.get_output_device()?;
         log::info!(
-            "Using default output device: \"{}\"",
+            "Using output device: \"{}\"",
             device.name().unwrap_or("".to_owned())
         );
         let config = device.default_output_config().map_err(|e| anyhow!(e))?;
@@ -1276,8 +1380,48 @@ impl AudioHandler {
         Ok(())
     }
 
+    #[inline]
+    fn get_voice_call_output_device(&self) -> Option<String> {
+        if !self.voice_call {
+            return None;
+        }
+        #[cfg(not(target_os = "ios"))]
+        return crate::audio_service::get_voice_call_output_device().filter(|x| !x.is_empty());
+        #[cfg(target_os = "ios")]
+        None
+    }
+
+    // Changed when the output device of the voice call is changed.
+    #[inline]
+    fn voice_call_output_device_seq() -> usize {
+        #[cfg(not(target_os = "ios"))]
+        return crate::audio_service::get_voice_call_output_device_seq();
+        #[cfg(target_os = "ios")]
+        0
+    }
+
+    /// The output device of the voice call, or the default one.
+    #[cfg(not(target_os = "linux"))]
+    fn get_output_device(&self) -> ResultType<Device> {
+        if let Some(name) = self.get_voice_call_output_device() {
+            if let Ok(mut devices) = AUDIO_HOST.output_devices() {
+                if let Some(device) = devices.find(|d| d.name().ok().as_ref() == Some(&name)) {
+                    return Ok(device);
+                }
+            }
+            log::warn!("Output device \"{}\" not found, use the default one", name);
+        }
+        AUDIO_HOST
+            .default_output_device()
+            .with_context(|| "Failed to get default output device")
+    }
+
     /// Handle audio format and create an audio decoder.
     pub fn handle_format(&mut self, f: AudioFormat) {
+        self.format = Some(f.clone());
+        if self.voice_call {
+            self.output_device_seq = Self::voice_call_output_device_seq();
+        }
         match AudioDecoder::new(f.sample_rate, if f.channels > 1 { Stereo } else { Mono }) {
             Ok(d) => {
                 let buffer = vec![0.; f.sample_rate as usize * f.channels as usize];
@@ -1294,6 +1438,14 @@ impl AudioHandler {
     /// Handle audio frame and play it.
     #[inline]
     pub fn handle_frame(&mut self, frame: AudioFrame) {
+        if self.voice_call
+            && self.output_device_seq != Self::voice_call_output_device_seq()
+        {
+            if let Some(f) = self.format.clone() {
+                log::info!("Voice call output device changed, restart the playback");
+                self.handle_format(f);
+            }
+        }
         #[cfg(not(target_os = "linux"))]
         if self.audio_stream.is_none() || !self.ready.lock().unwrap().clone() {
             return;
@@ -1303,42 +1455,52 @@ impl AudioHandler {
             log::debug!("PulseAudio simple binding does not exists");
             return;
         }
-        self.audio_decoder.as_mut().map(|(d, buffer)| {
-            if let Ok(n) = d.decode_float(&frame.data, buffer, false) {
-                let channels = self.channels;
-                let n = n * (channels as usize);
-                #[cfg(not(target_os = "linux"))]
-                {
-                    let sample_rate0 = self.sample_rate.0;
-                    let sample_rate = self.sample_rate.1;
-                    let mut buffer = buffer[0..n].to_owned();
-                    if sample_rate != sample_rate0 {
-                        buffer = crate::audio_resample(
-                            &buffer[0..n],
-                            sample_rate0,
-                            sample_rate,
-                            channels,
-                        );
-                    }
-                    if self.channels != self.device_channel {
-                        buffer = crate::audio_rechannel(
-                            buffer,
-                            sample_rate,
-                            sample_rate,
-                            self.channels,
-                            self.device_channel,
-                        );
-                    }
-                    self.audio_buffer.append_pcm(&buffer);
-                }
-                #[cfg(target_os = "linux")]
-                {
-                    let data_u8 =
-                        unsafe { std::slice::from_raw_parts::<u8>(buffer.as_ptr() as _, n * 4) };
-                    self.simple.as_mut().map(|x| x.write(data_u8));
-                }
+        let Some((mut decoder, mut buffer)) = self.audio_decoder.take() else {
+            return;
+        };
+        // The host dropped the frames before this one on a congested link, the decoder conceals
+        // the gap with about a frame instead of playing the frames around it back to back.
+        if frame.discontinuity && self.last_frame_samples > 0 {
+            let len = (self.last_frame_samples * self.channels as usize).min(buffer.len());
+            if let Ok(n) = decoder.decode_float(&[], &mut buffer[..len], false) {
+                self.play(&buffer, n);
             }
-        });
+        }
+        if let Ok(n) = decoder.decode_float(&frame.data, &mut buffer, false) {
+            self.last_frame_samples = n;
+            self.play(&buffer, n);
+        }
+        self.audio_decoder = Some((decoder, buffer));
+    }
+
+    // n samples per channel of the decoded buffer
+    fn play(&mut self, buffer: &[f32], n: usize) {
+        let channels = self.channels;
+        let n = n * (channels as usize);
+        #[cfg(not(target_os = "linux"))]
+        {
+            let sample_rate0 = self.sample_rate.0;
+            let sample_rate = self.sample_rate.1;
+            let mut buffer = buffer[0..n].to_owned();
+            if sample_rate != sample_rate0 {
+                buffer = crate::audio_resample(&buffer[0..n], sample_rate0, sample_rate, channels);
+            }
+            if self.channels != self.device_channel {
+                buffer = crate::audio_rechannel(
+                    buffer,
+                    sample_rate,
+                    sample_rate,
+                    self.channels,
+                    self.device_channel,
+                );
+            }
+            self.audio_buffer.append_pcm(&buffer);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            let data_u8 = unsafe { std::slice::from_raw_parts::<u8>(buffer.as_ptr() as _, n * 4) };
+            self.simple.as_mut().map(|x| x.write(data_u8));
+        }
     }
 
     /// Build audio output stream for current device.
@@ -1612,15 +1774,24 @@ pub struct LoginConfigHandler {
     id: String,
     pub conn_type: ConnType,
     hash: Hash,
+    // The challenge sent with the signed id by a newer host, see `early_hash`.
+    early_challenge: Option<String>,
+    // See `session_fingerprint`, None if the connection is not encrypted.
+    pub session_fingerprint: Option<String>,
     password: Vec<u8>, // remember password for reconnect
     pub remember: bool,
     config: PeerConfig,
     pub port_forward: (String, i32),
     pub version: i64,
     features: Option<Features>,
+    peer_capabilities: Capabilities,
     pub session_id: u64, // used for local <-> server communication
     pub supported_encoding: SupportedEncoding,
     pub restarting_remote_device: bool,
+    // The seconds before the remote device can be connected again after a power action.
+    pub power_action_downtime_secs: u32,
+    // (action, token), the power action waiting for the user to confirm it.
+    pub power_action_pending: Option<(String, u64)>,
     pub force_relay: bool,
     pub direct: Option<bool>,
     pub received: bool,
@@ -1727,6 +1898,8 @@ impl LoginConfigHandler {
         self.session_id = sid;
         self.supported_encoding = Default::default();
         self.restarting_remote_device = false;
+        self.power_action_downtime_secs = 0;
+        self.power_action_pending = None;
         self.force_relay =
             config::option2bool("force-always-relay", &self.get_option("force-always-relay"))
                 || force_relay;
@@ -1956,7 +2129,7 @@ impl LoginConfigHandler {
             .into();
         } else if name == keys::OPTION_TERMINAL_PERSISTENT {
             config.terminal_persistent.v = !config.terminal_persistent.v;
-	    option.terminal_persistent = (if config.terminal_persistent.v {
+            option.terminal_persistent = (if config.terminal_persistent.v {
                 BoolOption::Yes
             } else {
                 BoolOption::No
@@ -1984,6 +2157,13 @@ impl LoginConfigHandler {
             option.block_input = BoolOption::No.into();
         } else if name == "show-quality-monitor" {
             config.show_quality_monitor.v = !config.show_quality_monitor.v;
+            // The dropped frames are only counted by the peer for the quality monitor.
+            option.frame_drop_stats = (if config.show_quality_monitor.v {
+                BoolOption::Yes
+            } else {
+                BoolOption::No
+            })
+            .into();
         } else if name == "allow_swap_key" {
             config.allow_swap_key.v = !config.allow_swap_key.v;
         } else if name == "view-only" {
@@ -2008,6 +2188,14 @@ impl LoginConfigHandler {
                 option.enable_file_transfer = f(self.config.enable_file_copy_paste.v);
                 option.lock_after_session_end = f(self.config.lock_after_session_end.v);
             }
+        } else if name == "low-delay-audio" {
+            let on = !self.get_toggle_option(&name);
+            if on {
+                config.options.insert(name.clone(), "Y".to_owned());
+            } else {
+                config.options.remove(&name);
+            }
+            option.low_delay_audio = (if on { BoolOption::Yes } else { BoolOption::No }).into();
         } else {
             let is_set = self
                 .options
@@ -2112,9 +2300,19 @@ impl LoginConfigHandler {
         if self.get_toggle_option("disable-audio") {
             msg.disable_audio = BoolOption::Yes.into();
         }
+        if self.get_toggle_option("low-delay-audio") {
+            msg.low_delay_audio = BoolOption::Yes.into();
+        }
+        if self.get_toggle_option("show-quality-monitor") {
+            msg.frame_drop_stats = BoolOption::Yes.into();
+        }
         if !view_only && self.get_toggle_option(keys::OPTION_ENABLE_FILE_COPY_PASTE) {
             msg.enable_file_transfer = BoolOption::Yes.into();
         }
+        let line_ending = LineEnding::from_option(&self.get_option("clipboard-line-ending"));
+        if line_ending != LineEnding::Preserve {
+            msg.clipboard_line_ending = line_ending.to_i32();
+        }
         if view_only || self.get_toggle_option("disable-clipboard") {
             msg.disable_clipboard = BoolOption::Yes.into();
         }
@@ -2294,6 +2492,20 @@ impl LoginConfigHandler {
         msg_out
     }
 
+    /// Set the line ending normalization of the clipboard text, "" (preserve), "lf", "crlf" or "auto".
+    pub fn set_clipboard_line_ending(&mut self, value: String) -> Message {
+        let line_ending = LineEnding::from_option(&value);
+        let mut misc = Misc::new();
+        misc.set_option(OptionMessage {
+            clipboard_line_ending: line_ending.to_i32(),
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.set_option("clipboard-line-ending".to_owned(), value);
+        msg_out
+    }
+
     pub fn get_option(&self, k: &str) -> String {
         if let Some(v) = self.config.options.get(k) {
             v.clone()
@@ -2341,6 +2553,12 @@ impl LoginConfigHandler {
         };
     }
 
+    /// Check if both sides support the optional feature, valid after the peer info is received.
+    #[inline]
+    pub fn supports(&self, cap: Capability) -> bool {
+        self.peer_capabilities.supports(cap)
+    }
+
     /// Handle peer info.
     ///
     /// # Arguments
@@ -2352,6 +2570,12 @@ impl LoginConfigHandler {
             self.version = hbb_common::get_version_number(&pi.version);
         }
         self.features = pi.features.clone().into_option();
+        let peer_capabilities =
+            serde_json::from_str::<HashMap<String, serde_json::Value>>(&pi.platform_additions)
+                .ok()
+                .and_then(|x| x.get("capabilities").and_then(|v| v.as_u64()))
+                .unwrap_or_default();
+        self.peer_capabilities = Capabilities::negotiate(peer_capabilities, &pi.version);
         let serde = PeerInfoSerde {
             username: pi.username.clone(),
             hostname: pi.hostname.clone(),
@@ -2521,6 +2745,8 @@ impl LoginConfigHandler {
             option: self.get_option_message(true).into(),
             session_id: self.session_id,
             version: crate::VERSION.to_string(),
+            capabilities: Capabilities::local().bits(),
+            msg_versions: crate::common::local_msg_versions(),
             os_login: Some(OSLogin {
                 username: os_username,
                 password: os_password,
@@ -2580,6 +2806,73 @@ impl LoginConfigHandler {
         msg_out
     }
 
+    pub fn power_action_request(&self, action: &str, token: u64) -> Message {
+        let mut misc = Misc::new();
+        misc.set_power_action_request(PowerActionRequest {
+            action: action.to_owned(),
+            token,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        msg_out
+    }
+
+    pub fn process_list_request(&self, page: u32, page_size: u32) -> Message {
+        let mut misc = Misc::new();
+        misc.set_process_list_request(ProcessListRequest {
+            page,
+            page_size,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        msg_out
+    }
+
+    pub fn diagnostic_capture_request(&self, display: i32) -> Message {
+        let mut misc = Misc::new();
+        misc.set_diagnostic_capture_request(DiagnosticCaptureRequest {
+            display,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        msg_out
+    }
+
+    pub fn kill_process_request(&self, pid: u32, token: u64) -> Message {
+        let mut misc = Misc::new();
+        misc.set_kill_process_request(KillProcessRequest {
+            pid,
+            token,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        msg_out
+    }
+
+    pub fn quick_action_list_request(&self) -> Message {
+        let mut misc = Misc::new();
+        misc.set_quick_action_list_request(QuickActionListRequest::new());
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        msg_out
+    }
+
+    pub fn quick_action_request(&self, name: &str, token: u64) -> Message {
+        let mut misc = Misc::new();
+        misc.set_quick_action_request(QuickActionRequest {
+            name: name.to_owned(),
+            token,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        msg_out
+    }
+
     pub fn get_conn_token(&self) -> Option<String> {
         if self.password.is_empty() {
             return None;
@@ -2595,6 +2888,23 @@ impl LoginConfigHandler {
     pub fn get_id(&self) -> &str {
         &self.id
     }
+
+    /// The hash to log in with before the hash of the host comes, only the challenge is sent
+    /// with the signed id in plain text. It needs a password which is already salted, i.e.
+    /// remembered or saved for the peer, the others wait for the salt.
+    pub fn early_hash(&mut self, password_preset: &str) -> Option<Hash> {
+        let challenge = self.early_challenge.clone()?;
+        let salted = !self.password.is_empty()
+            || (password_preset.is_empty() && !self.config.password.is_empty());
+        if !salted || self.switch_uuid.is_some() || self.shared_password.is_some() {
+            self.early_challenge = None;
+            return None;
+        }
+        Some(Hash {
+            challenge,
+            ..Default::default()
+        })
+    }
 }
 
 /// Media data.
@@ -2769,9 +3079,21 @@ pub fn start_video_thread<F, T>(
 /// Start an audio thread
 /// Return a audio [`MediaSender`]
 pub fn start_audio_thread() -> MediaSender {
+    start_audio_thread_(false)
+}
+
+/// Start an audio thread playing on the output device of the voice call.
+pub fn start_voice_call_audio_thread() -> MediaSender {
+    start_audio_thread_(true)
+}
+
+fn start_audio_thread_(voice_call: bool) -> MediaSender {
     let (audio_sender, audio_receiver) = mpsc::channel::<MediaData>();
     std::thread::spawn(move || {
-        let mut audio_handler = AudioHandler::default();
+        let mut audio_handler = AudioHandler {
+            voice_call,
+            ..Default::default()
+        };
         loop {
             if let Ok(data) = audio_receiver.recv() {
                 match data {
@@ -3143,6 +3465,12 @@ lazy_static::lazy_static! {
             text: "Please wait for the remote side to accept your session request...",
             link: "",
             try_again: true,
+        }), (LOGIN_MSG_ENCRYPTION_REQUIRED, LoginErrorMsgBox{
+            msgtype: "error",
+            title: "Login Error",
+            text: "encryption-required-tip",
+            link: "",
+            try_again: false,
         })]);
         Arc::new(map)
     };
@@ -3333,13 +3661,54 @@ async fn send_login(
     password: Vec<u8>,
     peer: &mut Stream,
 ) {
-    let msg_out = lc
+    send_login_with_plain_password(lc, os_username, os_password, password, String::new(), peer)
+        .await;
+}
+
+async fn send_login_with_plain_password(
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    os_username: String,
+    os_password: String,
+    password: Vec<u8>,
+    plain_password: String,
+    peer: &mut Stream,
+) {
+    let mut msg_out = lc
         .read()
         .unwrap()
         .create_login_msg(os_username, os_password, password);
+    if !plain_password.is_empty() {
+        msg_out.mut_login_request().plain_password = plain_password;
+    }
     allow_err!(peer.send(&msg_out).await);
 }
 
+/// Take the stream through the new relay for the session, see `Client::migrate_relay`. The uuid
+/// is sent on it encrypted by the key, both came over the current encrypted connection, and the
+/// host replies when it has switched to the new stream.
+pub async fn join_relay_migration(conn: &mut Stream, uuid: &str, key: &[u8]) -> ResultType<()> {
+    let Some(key) = secretbox::Key::from_slice(key) else {
+        bail!("Invalid key of the relay migration");
+    };
+    conn.set_key(key);
+    let mut misc = Misc::new();
+    misc.set_relay_migration(RelayMigration {
+        uuid: uuid.to_owned(),
+        ..Default::default()
+    });
+    let mut msg_out = Message::new();
+    msg_out.set_misc(misc);
+    timeout(CONNECT_TIMEOUT, conn.send(&msg_out)).await??;
+    let Some(res) = timeout(READ_TIMEOUT, conn.next()).await? else {
+        bail!("Reset by the peer");
+    };
+    let msg_in = Message::parse_from_bytes(&res?)?;
+    if !msg_in.misc().has_relay_migration() {
+        bail!("The peer didn't take the new relay");
+    }
+    Ok(())
+}
+
 /// Handle login request made from ui.
 ///
 /// # Arguments
@@ -3358,6 +3727,16 @@ pub async fn handle_login_from_ui(
     remember: bool,
     peer: &mut Stream,
 ) {
+    // The host with an auth provider checks the typed password itself, it's only sent if the
+    // connection is encrypted.
+    let plain_password = {
+        let lc = lc.read().unwrap();
+        if lc.hash.plain_password && lc.session_fingerprint.is_some() {
+            password.clone()
+        } else {
+            String::new()
+        }
+    };
     let mut hash_password = if password.is_empty() {
         let mut password2 = lc.read().unwrap().password.clone();
         if password2.is_empty() {
@@ -3382,7 +3761,15 @@ pub async fn handle_login_from_ui(
     hasher2.update(&lc.read().unwrap().hash.challenge);
     hash_password = hasher2.finalize()[..].to_vec();
 
-    send_login(lc.clone(), os_username, os_password, hash_password, peer).await;
+    send_login_with_plain_password(
+        lc.clone(),
+        os_username,
+        os_password,
+        hash_password,
+        plain_password,
+        peer,
+    )
+    .await;
 }
 
 async fn send_switch_login_request(
@@ -3511,6 +3898,7 @@ pub enum Data {
     ResetDecoder(Option<usize>),
     RenameFile((i32, String, String, bool)),
     TakeScreenshot((i32, String)),
+    ReadRemoteDir(ReadDir),
 }
 
 /// Keycode for key events.
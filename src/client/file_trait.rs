@@ -64,15 +64,11 @@ pub trait FileManager: Interface {
     }
 
     fn read_remote_dir(&self, path: String, include_hidden: bool) {
-        let mut msg_out = Message::new();
-        let mut file_action = FileAction::new();
-        file_action.set_read_dir(ReadDir {
+        self.send(Data::ReadRemoteDir(ReadDir {
             path,
             include_hidden,
             ..Default::default()
-        });
-        msg_out.set_file_action(file_action);
-        self.send(Data::Message(msg_out));
+        }));
     }
 
     fn remove_file(&self, id: i32, path: String, file_num: i32, is_remote: bool) {
@@ -11,8 +11,14 @@ pub struct QualityStatus {
     pub fps: HashMap<usize, i32>,
     pub delay: Option<i32>,
     pub target_bitrate: Option<i32>,
+    // Display index -> target bitrate, when several displays are received.
+    pub display_target_bitrates: HashMap<usize, i32>,
+    // Reason -> video frames dropped by the peer since the connection, if it's counted.
+    pub frame_drops: HashMap<String, u64>,
     pub codec_format: Option<CodecFormat>,
     pub chroma: Option<String>,
+    // "passthrough" or "transcode", the frames of the cameras.
+    pub camera_frames: Option<String>,
 }
 
 #[inline]
@@ -35,3 +41,13 @@ pub fn new_voice_call_response(request_timestamp: i64, accepted: bool) -> Messag
     msg.set_voice_call_response(resp);
     msg
 }
+
+/// The host is in another voice call, the request waits at the position in the queue.
+#[inline]
+pub fn new_voice_call_busy_response(request_timestamp: i64, queue_position: i32) -> Message {
+    let mut msg = new_voice_call_response(request_timestamp, false);
+    let resp = msg.mut_voice_call_response();
+    resp.busy = true;
+    resp.queue_position = queue_position;
+    msg
+}
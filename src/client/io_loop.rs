@@ -7,7 +7,7 @@ use crate::{
         self, new_voice_call_request, Client, Data, Interface, MediaData, MediaSender,
         QualityStatus, MILLI1, SEC30,
     },
-    common::get_default_sound_input,
+    common::{get_default_sound_input, Capability},
     ui_session_interface::{InvokeUiSession, Session},
 };
 #[cfg(feature = "unix-file-copy-paste")]
@@ -70,13 +70,34 @@ pub struct Remote<T: InvokeUiSession> {
     first_frame: bool,
     #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
     client_conn_id: i32, // used for file clipboard
+    #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
+    file_list_assembler: crate::clipboard_file::FileListAssembler,
     data_count: Arc<AtomicUsize>,
+    // The code sent by the host before the close reason.
+    close_reason_code: i32,
     video_format: CodecFormat,
     elevation_requested: bool,
     peer_info: ParsedPeerInfo,
     video_threads: HashMap<usize, VideoThread>,
     chroma: Arc<RwLock<Option<Chroma>>>,
     last_record_state: bool,
+    dir_listing: Option<DirListing>,
+    // The relay the peer asks to move the session to.
+    pending_relay_migration: Option<RelayMigration>,
+    // The new stream is being connected, the current one may end before it's ready.
+    relay_migrating: bool,
+    // The cursor positions to replay, after the first batch of them.
+    cursor_replay: Option<mpsc::UnboundedSender<CursorPosition>>,
+    clipboard_delta: crate::clipboard_delta::Decoder,
+}
+
+const OPTION_DIR_LISTING_PAGE_SIZE: &str = "dir-listing-page-size";
+const DEFAULT_DIR_LISTING_PAGE_SIZE: u32 = 1000;
+
+// The paged listing of a remote directory in progress, the pages are requested one by one and
+// passed to the ui as they come, the ui shows the directory when the last one is received.
+struct DirListing {
+    request: ReadDir,
 }
 
 #[derive(Default)]
@@ -116,7 +137,10 @@ impl<T: InvokeUiSession> Remote<T> {
             first_frame: false,
             #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
             client_conn_id: 0,
+            #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
+            file_list_assembler: Default::default(),
             data_count: Arc::new(AtomicUsize::new(0)),
+            close_reason_code: 0,
             video_format: CodecFormat::Unknown,
             stop_voice_call_sender: None,
             voice_call_request_timestamp: None,
@@ -125,6 +149,11 @@ impl<T: InvokeUiSession> Remote<T> {
             video_threads: Default::default(),
             chroma: Default::default(),
             last_record_state: false,
+            dir_listing: None,
+            pending_relay_migration: None,
+            relay_migrating: false,
+            cursor_replay: None,
+            clipboard_delta: Default::default(),
         }
     }
 
@@ -216,10 +245,34 @@ impl<T: InvokeUiSession> Remote<T> {
                 let mut fps_instant = Instant::now();
 
                 let _keep_it = client::hc_connection(feedback, rendezvous_server, token).await;
+                let (tx_relay_migration, mut rx_relay_migration) = mpsc::unbounded_channel();
+
+                // The challenge came with the signed id, log in without waiting for the hash.
+                let early_hash = self
+                    .handler
+                    .lc
+                    .write()
+                    .unwrap()
+                    .early_hash(&self.handler.password);
+                if let Some(hash) = early_hash {
+                    self.handler
+                        .handle_hash(&self.handler.password.clone(), hash, &mut peer)
+                        .await;
+                }
 
                 loop {
                     tokio::select! {
                         res = peer.next() => {
+                            // The host has switched to the new stream and dropped this one.
+                            if self.relay_migrating && !matches!(res, Some(Ok(_))) {
+                                self.relay_migrating = false;
+                                if let Some(Ok(stream)) = rx_relay_migration.recv().await {
+                                    log::info!("The session is moved to the new relay");
+                                    peer = stream;
+                                    self.on_relay_migrated();
+                                    continue;
+                                }
+                            }
                             if let Some(res) = res {
                                 match res {
                                     Err(err) => {
@@ -236,12 +289,28 @@ impl<T: InvokeUiSession> Remote<T> {
                                         if !self.handle_msg_from_peer(bytes, &mut peer).await {
                                             break
                                         }
+                                        if let Some(migration) = self.pending_relay_migration.take() {
+                                            let lc = self.handler.lc.clone();
+                                            let key = key.to_owned();
+                                            let tx = tx_relay_migration.clone();
+                                            self.relay_migrating = true;
+                                            tokio::spawn(async move {
+                                                let res = Client::migrate_relay(lc, &key, migration, conn_type).await;
+                                                tx.send(res).ok();
+                                            });
+                                        }
                                     }
                                 }
                             } else {
                                 if self.handler.is_restarting_remote_device() {
                                     log::info!("Restart remote device");
-                                    self.handler.msgbox("restarting", "Restarting remote device", "remote_restarting_tip", "");
+                                    let downtime = self.handler.lc.read().unwrap().power_action_downtime_secs;
+                                    if downtime > 0 {
+                                        // Retry after the remote device is expected to be back.
+                                        self.handler.ui_handler.msgbox("restarting", "Restarting remote device", "remote_restarting_tip", "", true);
+                                    } else {
+                                        self.handler.msgbox("restarting", "Restarting remote device", "remote_restarting_tip", "");
+                                    }
                                 } else {
                                     log::info!("Reset by the peer");
                                     self.handler.msgbox("error", "Connection Error", "Reset by the peer", "");
@@ -256,6 +325,20 @@ impl<T: InvokeUiSession> Remote<T> {
                                 }
                             }
                         }
+                        Some(res) = rx_relay_migration.recv() => {
+                            self.relay_migrating = false;
+                            match res {
+                                Ok(stream) => {
+                                    log::info!("The session is moved to the new relay");
+                                    // The old connection is dropped by the host.
+                                    peer = stream;
+                                    self.on_relay_migrated();
+                                }
+                                Err(err) => {
+                                    log::warn!("Failed to move the session to the new relay, stay on the current one: {}", err);
+                                }
+                            }
+                        }
                         _msg = rx_clip_client.recv() => {
                             #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
                             self.handle_local_clipboard_msg(&mut peer, _msg).await;
@@ -305,11 +388,19 @@ impl<T: InvokeUiSession> Remote<T> {
                             } else {
                                 Some(self.video_format.clone())
                             };
+                            let camera_frames = match codec_format {
+                                Some(CodecFormat::MJPEG) => Some("passthrough".to_owned()),
+                                Some(_) if self.handler.is_view_camera() => {
+                                    Some("transcode".to_owned())
+                                }
+                                _ => None,
+                            };
                             self.handler.update_quality_status(QualityStatus {
                                 speed: Some(speed),
                                 fps,
                                 chroma,
                                 codec_format,
+                                camera_frames,
                                 ..Default::default()
                             });
                         }
@@ -387,7 +478,15 @@ impl<T: InvokeUiSession> Remote<T> {
                         };
                         log::debug!("Send system clipboard message to remote");
                         let msg = crate::clipboard_file::clip_2_msg(clip);
-                        allow_err!(peer.send(&msg).await);
+                        let chunked = self
+                            .handler
+                            .lc
+                            .read()
+                            .unwrap()
+                            .supports(Capability::ChunkedClipboardFileList);
+                        for msg in crate::clipboard_file::split_file_list(msg, chunked) {
+                            allow_err!(peer.send(&msg).await);
+                        }
                     }
                 }
             },
@@ -828,6 +927,39 @@ impl<T: InvokeUiSession> Remote<T> {
                 let _ = fs::remove_job(id, &mut self.read_jobs);
                 self.remove_jobs.remove(&id);
             }
+            Data::ReadRemoteDir(mut rd) => {
+                if let Some(listing) = self.dir_listing.take() {
+                    // Stop reading the previous directory on the peer.
+                    let mut msg_out = Message::new();
+                    let mut file_action = FileAction::new();
+                    file_action.set_cancel(FileTransferCancel {
+                        id: listing.request.id,
+                        ..Default::default()
+                    });
+                    msg_out.set_file_action(file_action);
+                    allow_err!(peer.send(&msg_out).await);
+                }
+                if self
+                    .handler
+                    .lc
+                    .read()
+                    .unwrap()
+                    .supports(Capability::PagedDirListing)
+                {
+                    rd.id = fs::get_next_job_id();
+                    rd.page_size = config::Config::get_option(OPTION_DIR_LISTING_PAGE_SIZE)
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|x| *x > 0)
+                        .unwrap_or(DEFAULT_DIR_LISTING_PAGE_SIZE);
+                    self.dir_listing = Some(DirListing { request: rd.clone() });
+                }
+                let mut msg_out = Message::new();
+                let mut file_action = FileAction::new();
+                file_action.set_read_dir(rd);
+                msg_out.set_file_action(file_action);
+                allow_err!(peer.send(&msg_out).await);
+            }
             Data::RemoveDir((id, path)) => {
                 let mut msg_out = Message::new();
                 let mut file_action = FileAction::new();
@@ -933,7 +1065,14 @@ impl<T: InvokeUiSession> Remote<T> {
                 self.elevation_requested = true;
             }
             Data::NewVoiceCall => {
-                let msg = new_voice_call_request(true);
+                let mut msg = new_voice_call_request(true);
+                // Ask the host to play the call into its virtual microphone.
+                msg.mut_voice_call_request().virtual_mic = self
+                    .handler
+                    .lc
+                    .read()
+                    .unwrap()
+                    .get_toggle_option("voice-call-virtual-mic");
                 // Save the voice call request timestamp for the further validation.
                 self.voice_call_request_timestamp = Some(
                     NonZeroI64::new(msg.voice_call_request().req_timestamp)
@@ -1090,7 +1229,9 @@ impl<T: InvokeUiSession> Remote<T> {
         use video_frame::Union::*;
         match &vf.union {
             Some(vf) => match vf {
-                Vp8s(f) | Vp9s(f) | Av1s(f) | H264s(f) | H265s(f) => f.frames.iter().any(|e| e.key),
+                Vp8s(f) | Vp9s(f) | Av1s(f) | H264s(f) | H265s(f) | Mjpegs(f) => {
+                    f.frames.iter().any(|e| e.key)
+                }
                 _ => false,
             },
             None => false,
@@ -1247,6 +1388,46 @@ impl<T: InvokeUiSession> Remote<T> {
         return false;
     }
 
+    // The session goes on as it is, the frames lost while switching are recovered by key frames.
+    fn on_relay_migrated(&mut self) {
+        for display in self.video_threads.keys() {
+            self.handler.refresh_video(*display as _);
+        }
+    }
+
+    // The positions of a batch (`CursorPosition.earlier`) are replayed with their intervals, the
+    // cursor moves smoothly instead of jumping to the latest one. After the first batch, all the
+    // positions go through the replay to keep the order.
+    fn on_cursor_position(&mut self, cp: CursorPosition) {
+        if self.cursor_replay.is_none() {
+            if cp.earlier.is_empty() {
+                self.handler.set_cursor_position(cp);
+                return;
+            }
+            let (tx, mut rx) = mpsc::unbounded_channel::<CursorPosition>();
+            let handler = self.handler.clone();
+            tokio::spawn(async move {
+                while let Some(mut cp) = rx.recv().await {
+                    let earlier = std::mem::take(&mut cp.earlier);
+                    let mut ms_before = earlier.first().map_or(0, |p| p.ms_before);
+                    for mut p in earlier {
+                        let interval = ms_before.saturating_sub(p.ms_before);
+                        time::sleep(Duration::from_millis(interval as _)).await;
+                        ms_before = p.ms_before;
+                        p.ms_before = 0;
+                        handler.set_cursor_position(p);
+                    }
+                    time::sleep(Duration::from_millis(ms_before as _)).await;
+                    handler.set_cursor_position(cp);
+                }
+            });
+            self.cursor_replay = Some(tx);
+        }
+        if let Some(tx) = self.cursor_replay.as_ref() {
+            tx.send(cp).ok();
+        }
+    }
+
     async fn handle_msg_from_peer(&mut self, data: &[u8], peer: &mut Stream) -> bool {
         if let Ok(msg_in) = Message::parse_from_bytes(&data) {
             match msg_in.union {
@@ -1283,12 +1464,23 @@ impl<T: InvokeUiSession> Remote<T> {
                     }
                 }
                 Some(message::Union::Hash(hash)) => {
-                    self.handler
-                        .handle_hash(&self.handler.password.clone(), hash, peer)
-                        .await;
+                    // Logged in with the challenge of the signed id, keep the salt for a retry.
+                    let early_challenge = self.handler.lc.write().unwrap().early_challenge.take();
+                    if self.is_connected {
+                        // The host asks for the password again, it's typed by the user for the
+                        // new challenge, see "reauth-required-tip".
+                        self.handler.lc.write().unwrap().hash.challenge = hash.challenge;
+                    } else if early_challenge.as_ref() == Some(&hash.challenge) {
+                        self.handler.lc.write().unwrap().hash = hash;
+                    } else {
+                        self.handler
+                            .handle_hash(&self.handler.password.clone(), hash, peer)
+                            .await;
+                    }
                 }
                 Some(message::Union::LoginResponse(lr)) => match lr.union {
                     Some(login_response::Union::Error(err)) => {
+                        let err = crate::error_code::canonical_text(lr.error_code, &err);
                         if err == client::REQUIRE_2FA {
                             self.handler.lc.write().unwrap().enable_trusted_devices =
                                 lr.enable_trusted_devices;
@@ -1388,7 +1580,7 @@ impl<T: InvokeUiSession> Remote<T> {
                     self.handler.set_cursor_id(id.to_string());
                 }
                 Some(message::Union::CursorPosition(cp)) => {
-                    self.handler.set_cursor_position(cp);
+                    self.on_cursor_position(cp);
                 }
                 Some(message::Union::Clipboard(cb)) => {
                     if !self.handler.lc.read().unwrap().disable_clipboard.v {
@@ -1409,8 +1601,11 @@ impl<T: InvokeUiSession> Remote<T> {
                         crate::clipboard::handle_msg_clipboard(cb);
                     }
                 }
-                Some(message::Union::MultiClipboards(_mcb)) => {
-                    if !self.handler.lc.read().unwrap().disable_clipboard.v {
+                Some(message::Union::MultiClipboards(mut _mcb)) => {
+                    if !self.clipboard_delta.decode(&mut _mcb.clipboards) {
+                        log::warn!("Failed to apply the clipboard delta, asking for the whole one");
+                        self.handler.request_clipboard();
+                    } else if !self.handler.lc.read().unwrap().disable_clipboard.v {
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
                         update_clipboard(_mcb.clipboards, ClipboardSide::Client);
                         #[cfg(target_os = "android")]
@@ -1437,6 +1632,42 @@ impl<T: InvokeUiSession> Remote<T> {
                                     fs::transform_windows_path(&mut entries);
                                 }
                             }
+                            if let Some(listing) = self
+                                .dir_listing
+                                .as_ref()
+                                .filter(|l| fd.id != 0 && l.request.id == fd.id)
+                            {
+                                let done = fd.next_token.is_empty();
+                                log::debug!(
+                                    "Listing {}: {}/{}",
+                                    fd.path,
+                                    fd.offset as usize + entries.len(),
+                                    fd.total
+                                );
+                                let mut rd = listing.request.clone();
+                                self.handler.update_folder_files_page(
+                                    &entries,
+                                    fd.path,
+                                    fd.offset as _,
+                                    fd.total as _,
+                                    done,
+                                );
+                                if done {
+                                    self.dir_listing = None;
+                                } else {
+                                    rd.token = fd.next_token;
+                                    let mut msg_out = Message::new();
+                                    let mut file_action = FileAction::new();
+                                    file_action.set_read_dir(rd);
+                                    msg_out.set_file_action(file_action);
+                                    allow_err!(peer.send(&msg_out).await);
+                                }
+                                return true;
+                            }
+                            if fd.id == 0 {
+                                // The peer doesn't page the listing.
+                                self.dir_listing = None;
+                            }
                             self.handler
                                 .update_folder_files(fd.id, &entries, fd.path, false, false);
                             if let Some(job) = fs::get_job(fd.id, &mut self.write_jobs) {
@@ -1692,6 +1923,12 @@ impl<T: InvokeUiSession> Remote<T> {
                             Ok(Permission::BlockInput) => {
                                 self.handler.set_permission("block_input", p.enabled);
                             }
+                            Ok(Permission::Annotation) => {
+                                self.handler.set_permission("annotation", p.enabled);
+                            }
+                            Ok(Permission::QuickActions) => {
+                                self.handler.set_permission("quick_actions", p.enabled);
+                            }
                             _ => {}
                         }
                     }
@@ -1711,7 +1948,43 @@ impl<T: InvokeUiSession> Remote<T> {
                             );
                         }
                     }
+                    Some(misc::Union::RelayMigration(m)) => {
+                        if !m.relay_server.is_empty() && !self.relay_migrating {
+                            log::info!(
+                                "The peer asks to move the session to relay {}",
+                                m.relay_server
+                            );
+                            self.pending_relay_migration = Some(m);
+                        }
+                    }
+                    Some(misc::Union::ServiceMsgVersion(v)) => {
+                        log::info!("The messages of {} are of version {}", v.kind, v.version);
+                    }
+                    Some(misc::Union::ClipboardBlocked(b)) => {
+                        log::info!(
+                            "Clipboard blocked by the policy, outgoing: {}, format: {}, reason: {}, size: {}, max size: {}",
+                            b.outgoing,
+                            b.format,
+                            b.reason,
+                            b.size,
+                            b.max_size
+                        );
+                        let text = if b.reason == "size" {
+                            "clipboard-blocked-size-tip"
+                        } else {
+                            "clipboard-blocked-format-tip"
+                        };
+                        self.handler
+                            .msgbox("custom-nook-nocancel-hasclose", "Clipboard", text, "");
+                    }
+                    Some(misc::Union::CloseReasonCode(code)) => {
+                        self.close_reason_code = code;
+                    }
                     Some(misc::Union::CloseReason(c)) => {
+                        let c = crate::error_code::canonical_text(
+                            std::mem::take(&mut self.close_reason_code),
+                            &c,
+                        );
                         self.handler.msgbox("error", "Connection Error", &c, "");
                         return false;
                     }
@@ -1795,6 +2068,77 @@ impl<T: InvokeUiSession> Remote<T> {
                                 .msgbox("elevation-error", "Elevation Error", &err, "");
                         }
                     }
+                    Some(misc::Union::ControlTokenState(s)) => {
+                        self.handler
+                            .update_control_token(s.holding, s.holder, s.queued);
+                    }
+                    Some(misc::Union::SessionReport(report)) => {
+                        log::info!("Session report of the peer: {}", report);
+                    }
+                    Some(misc::Union::PowerActionResponse(r)) => {
+                        if !r.error.is_empty() {
+                            self.handler
+                                .msgbox("custom-nocancel", "Power action", &r.error, "");
+                        } else if r.done {
+                            log::info!("Remote power action {} is being executed", r.action);
+                            let mut lc = self.handler.lc.write().unwrap();
+                            lc.restarting_remote_device =
+                                r.action == "reboot" || r.action == "reboot-to-firmware";
+                            lc.power_action_downtime_secs = r.estimated_downtime_secs;
+                        } else if r.token != 0 {
+                            // The token goes back only after the user confirms it.
+                            self.handler.lc.write().unwrap().power_action_pending =
+                                Some((r.action.clone(), r.token));
+                            self.handler
+                                .msgbox("power-action-confirm", "Power action", &r.action, "");
+                        }
+                    }
+                    Some(misc::Union::ProcessList(list)) => {
+                        self.handler.update_process_list(list);
+                    }
+                    Some(misc::Union::SessionFingerprint(fingerprint)) => {
+                        if fingerprint != self.handler.get_session_fingerprint() {
+                            log::error!("The session fingerprint of the peer doesn't match");
+                            self.handler.msgbox(
+                                "custom-error",
+                                "Session fingerprint",
+                                "session-fingerprint-mismatch-tip",
+                                "",
+                            );
+                        }
+                    }
+                    Some(misc::Union::DiagnosticCaptureResponse(r)) => {
+                        if !r.error.is_empty() {
+                            self.handler.msgbox(
+                                "custom-nocancel",
+                                "Diagnostic capture",
+                                &r.error,
+                                "",
+                            );
+                        } else {
+                            let to = self.handler.receive_diagnostic_capture(r.path);
+                            self.handler.msgbox(
+                                "custom-nook-nocancel-hasclose",
+                                "Diagnostic capture",
+                                &format!("The diagnostic capture is saved to {{{}}}", to),
+                                "",
+                            );
+                        }
+                    }
+                    Some(misc::Union::KillProcessResponse(r)) => {
+                        // The user confirms the kill with the token in the ui.
+                        self.handler.on_kill_process_response(r);
+                    }
+                    Some(misc::Union::QuickActionList(list)) => {
+                        self.handler.update_quick_actions(list);
+                    }
+                    Some(misc::Union::QuickActionResponse(r)) => {
+                        // The user confirms the action with the token in the ui.
+                        self.handler.on_quick_action_response(r);
+                    }
+                    Some(misc::Union::QuickActionOutput(output)) => {
+                        self.handler.on_quick_action_output(output);
+                    }
                     Some(misc::Union::PortableServiceRunning(b)) => {
                         self.handler.portable_service_running(b);
                         if self.elevation_requested && b {
@@ -1924,6 +2268,18 @@ impl<T: InvokeUiSession> Remote<T> {
                                 // The peer accepted the voice call.
                                 self.handler.on_voice_call_started();
                                 self.stop_voice_call_sender = self.start_voice_call();
+                            } else if response.busy {
+                                // The peer is in another call, the request waits for it to end.
+                                self.voice_call_request_timestamp = Some(ts);
+                                self.handler.msgbox(
+                                    "custom-nook-nocancel-hasclose",
+                                    "Voice call",
+                                    &format!(
+                                        "Waiting in the voice call queue, position {{{}}}",
+                                        response.queue_position
+                                    ),
+                                    "",
+                                );
                             } else {
                                 // The peer refused the voice call.
                                 self.handler.on_voice_call_closed("");
@@ -2185,6 +2541,9 @@ impl<T: InvokeUiSession> Remote<T> {
             }
         }
 
+        let Some(clip) = self.file_list_assembler.assemble(clip) else {
+            return;
+        };
         let Some(clip) = crate::clipboard_file::msg_2_clip(clip) else {
             log::warn!("failed to decode cliprdr msg from server peer");
             return;
@@ -2243,7 +2602,15 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
 
                 if let Some(msg) = out_msg {
-                    allow_err!(_peer.send(&msg).await);
+                    let chunked = self
+                        .handler
+                        .lc
+                        .read()
+                        .unwrap()
+                        .supports(Capability::ChunkedClipboardFileList);
+                    for msg in crate::clipboard_file::split_file_list(msg, chunked) {
+                        allow_err!(_peer.send(&msg).await);
+                    }
                 }
             }
         }
@@ -0,0 +1,151 @@
+// The deltas of the large clipboard texts of the host, for the workflows which copy a growing log
+// again and again.
+//
+// A text of at least `MIN_DELTA_SIZE` bytes which shares a prefix with the last text sent to the
+// peer is sent as a `Clipboard` with `delta`: the content replaces the last text from
+// `delta.offset`, and `delta.hash` is the sha256 of the result. The whole text is sent if the
+// delta is not smaller. The peer asks for the whole clipboard with `Misc.request_clipboard` if it
+// can't rebuild the text, then the host forgets the last text. Only the text format is tracked,
+// only from the host to the peers with `Capability::ClipboardDelta`.
+
+use hbb_common::{
+    bytes::Bytes,
+    compress::{compress, decompress},
+    message_proto::{Clipboard, ClipboardDelta, ClipboardFormat},
+};
+use sha2::{Digest, Sha256};
+
+pub const MIN_DELTA_SIZE: usize = 64 * 1024;
+
+fn text_of(cb: &Clipboard) -> Vec<u8> {
+    if cb.compress {
+        decompress(&cb.content)
+    } else {
+        cb.content.to_vec()
+    }
+}
+
+fn is_text(cb: &Clipboard) -> bool {
+    cb.format.enum_value() == Ok(ClipboardFormat::Text)
+}
+
+/// The last text sent to a peer.
+#[derive(Default)]
+pub struct Encoder {
+    last: Option<Vec<u8>>,
+}
+
+impl Encoder {
+    /// Replaces the texts with the deltas where smaller, true if any is replaced.
+    pub fn encode(&mut self, clipboards: &mut [Clipboard]) -> bool {
+        let mut replaced = false;
+        for cb in clipboards.iter_mut().filter(|cb| is_text(cb)) {
+            let text = text_of(cb);
+            if let Some(last) = self.last.as_ref().filter(|_| text.len() >= MIN_DELTA_SIZE) {
+                let offset = last
+                    .iter()
+                    .zip(text.iter())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                let tail = &text[offset..];
+                let compressed = compress(tail);
+                let (content, is_compressed) = if compressed.len() < tail.len() {
+                    (compressed, true)
+                } else {
+                    (tail.to_vec(), false)
+                };
+                if offset > 0 && content.len() < cb.content.len() {
+                    cb.delta = hbb_common::protobuf::MessageField::some(ClipboardDelta {
+                        offset: offset as _,
+                        hash: Sha256::digest(&text).to_vec().into(),
+                        ..Default::default()
+                    });
+                    cb.content = Bytes::from(content);
+                    cb.compress = is_compressed;
+                    replaced = true;
+                }
+            }
+            self.last = Some(text);
+        }
+        replaced
+    }
+
+    /// The peer lost the last text, the next one is sent whole.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+/// The last text received from the host.
+#[derive(Default)]
+pub struct Decoder {
+    last: Option<Vec<u8>>,
+}
+
+impl Decoder {
+    /// Rebuilds the texts of the deltas, false if one can't be rebuilt, then the whole clipboard
+    /// must be asked for.
+    pub fn decode(&mut self, clipboards: &mut [Clipboard]) -> bool {
+        for cb in clipboards.iter_mut().filter(|cb| is_text(cb)) {
+            let mut text = text_of(cb);
+            if let Some(delta) = cb.delta.take() {
+                let offset = delta.offset as usize;
+                let Some(last) = self.last.as_ref().filter(|last| last.len() >= offset) else {
+                    self.last = None;
+                    return false;
+                };
+                let mut rebuilt = last[..offset].to_vec();
+                rebuilt.extend_from_slice(&text);
+                if Sha256::digest(&rebuilt)[..] != delta.hash[..] {
+                    self.last = None;
+                    return false;
+                }
+                text = rebuilt;
+            }
+            cb.content = Bytes::from(text.clone());
+            cb.compress = false;
+            self.last = Some(text);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(s: &[u8]) -> Clipboard {
+        Clipboard {
+            content: Bytes::from(s.to_vec()),
+            format: ClipboardFormat::Text.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_clipboard_delta() {
+        let mut encoder = Encoder::default();
+        let mut decoder = Decoder::default();
+        let log: Vec<u8> = (0..MIN_DELTA_SIZE).map(|i| (i * 7 % 251) as u8).collect();
+        let mut first = vec![text(&log)];
+        assert!(!encoder.encode(&mut first));
+        assert!(decoder.decode(&mut first));
+
+        let mut grown = log.clone();
+        grown.extend_from_slice(b"appended line\n");
+        let mut second = vec![text(&grown)];
+        assert!(encoder.encode(&mut second));
+        assert!(second[0].content.len() < 100);
+        assert!(decoder.decode(&mut second));
+        assert_eq!(second[0].content.to_vec(), grown);
+        assert!(!second[0].delta.is_some());
+
+        // The decoder lost the base, the host is asked for the whole text.
+        let mut third = vec![text(&[grown.clone(), b"more\n".to_vec()].concat())];
+        assert!(encoder.encode(&mut third));
+        let mut lost = Decoder::default();
+        assert!(!lost.decode(&mut third.clone()));
+        third[0].content = Bytes::from_static(b"tampered");
+        assert!(!decoder.decode(&mut third));
+    }
+}
@@ -1,5 +1,16 @@
 use clipboard::ClipboardFile;
-use hbb_common::message_proto::*;
+use hbb_common::{config::Config, log, message_proto::*};
+
+// The file list of the clipboard is a `FILEDESCRIPTORW` pdu of MS-RDPECLIP, the count of the
+// files followed by the descriptors. Copying a folder with thousands of files makes a giant
+// message which stalls the channel, so it is sent in chunks of descriptors to the peers with
+// `Capability::ChunkedClipboardFileList`. Every chunk carries the total count for the progress,
+// the receiver assembles the pdu again before it is handled as usual.
+const FILE_DESCRIPTOR_SIZE: usize = 592;
+// The assembled file list is bounded, a chunk must not make the receiver allocate without limit.
+const MAX_FILE_LIST_COUNT: u32 = 1_000_000;
+pub const OPTION_FILE_LIST_CHUNK_SIZE: &str = "clipboard-file-list-chunk-size";
+pub const DEFAULT_FILE_LIST_CHUNK_SIZE: usize = 256;
 
 pub fn clip_2_msg(clip: ClipboardFile) -> Message {
     match clip {
@@ -190,6 +201,126 @@ pub fn msg_2_clip(msg: Cliprdr) -> Option<ClipboardFile> {
     }
 }
 
+fn file_list_chunk_size() -> usize {
+    Config::get_option(OPTION_FILE_LIST_CHUNK_SIZE)
+        .parse::<usize>()
+        .ok()
+        .filter(|x| *x > 0)
+        .unwrap_or(DEFAULT_FILE_LIST_CHUNK_SIZE)
+}
+
+/// Split the file list of a format data response into chunks if the peer supports them.
+/// Other messages and the file lists which fit in one chunk are returned as they are.
+pub fn split_file_list(msg: Message, chunked: bool) -> Vec<Message> {
+    split_file_list_(msg, chunked, file_list_chunk_size())
+}
+
+fn split_file_list_(msg: Message, chunked: bool, chunk_size: usize) -> Vec<Message> {
+    let data = match &msg.union {
+        Some(message::Union::Cliprdr(Cliprdr {
+            union: Some(cliprdr::Union::FormatDataResponse(data)),
+            ..
+        })) if chunked && data.msg_flags == 1 => &data.format_data,
+        _ => return vec![msg],
+    };
+    if data.len() < 4 || (data.len() - 4) % FILE_DESCRIPTOR_SIZE != 0 {
+        return vec![msg];
+    }
+    let total = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    if (data.len() - 4) / FILE_DESCRIPTOR_SIZE != total as usize || total as usize <= chunk_size {
+        return vec![msg];
+    }
+    data[4..]
+        .chunks(chunk_size * FILE_DESCRIPTOR_SIZE)
+        .enumerate()
+        .map(|(i, chunk)| Message {
+            union: Some(message::Union::Cliprdr(Cliprdr {
+                union: Some(cliprdr::Union::FileListChunk(CliprdrFileListChunk {
+                    total,
+                    offset: (i * chunk_size) as u32,
+                    format_data: chunk.to_vec().into(),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            })),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Assemble the file list sent in chunks by the peer.
+#[derive(Debug, Default)]
+pub struct FileListAssembler {
+    total: u32,
+    descriptors: Vec<u8>,
+}
+
+impl FileListAssembler {
+    // The received and the total count of the files of the file list being assembled.
+    fn progress(&self) -> (u32, u32) {
+        (
+            (self.descriptors.len() / FILE_DESCRIPTOR_SIZE) as u32,
+            self.total,
+        )
+    }
+
+    /// Returns the message to handle, the whole file list as a format data response after the
+    /// last chunk, None while the file list is incomplete. Other messages are returned as they are.
+    pub fn assemble(&mut self, clip: Cliprdr) -> Option<Cliprdr> {
+        let chunk = match clip.union {
+            Some(cliprdr::Union::FileListChunk(chunk)) => chunk,
+            _ => return Some(clip),
+        };
+        if chunk.offset == 0 {
+            *self = Self {
+                total: chunk.total,
+                descriptors: Vec::new(),
+            };
+        }
+        let (received, total) = self.progress();
+        let count = (chunk.format_data.len() / FILE_DESCRIPTOR_SIZE) as u32;
+        if chunk.format_data.len() % FILE_DESCRIPTOR_SIZE != 0
+            || chunk.total != total
+            || total > MAX_FILE_LIST_COUNT
+            || chunk.offset != received
+            || received + count > total
+        {
+            log::error!(
+                "Invalid chunk of the clipboard file list, offset: {}, count: {}, total: {}, received: {}/{}",
+                chunk.offset,
+                count,
+                chunk.total,
+                received,
+                total
+            );
+            *self = Default::default();
+            return None;
+        }
+        self.descriptors.extend_from_slice(&chunk.format_data);
+        log::debug!(
+            "Received {}/{} files of the clipboard file list",
+            received + count,
+            total
+        );
+        if received + count < total {
+            return None;
+        }
+        let mut format_data = total.to_le_bytes().to_vec();
+        format_data.append(&mut std::mem::take(&mut self.descriptors));
+        *self = Default::default();
+        Some(Cliprdr {
+            union: Some(cliprdr::Union::FormatDataResponse(
+                CliprdrServerFormatDataResponse {
+                    msg_flags: 1,
+                    format_data: format_data.into(),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        })
+    }
+}
+
 #[cfg(feature = "unix-file-copy-paste")]
 pub mod unix_file_clip {
     use crate::clipboard::try_empty_clipboard_files;
@@ -392,3 +523,75 @@ pub mod unix_file_clip {
         None
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn file_list(count: u32) -> Vec<u8> {
+        let mut data = count.to_le_bytes().to_vec();
+        for i in 0..count {
+            data.extend(std::iter::repeat(i as u8).take(FILE_DESCRIPTOR_SIZE));
+        }
+        data
+    }
+
+    #[test]
+    fn test_split_file_list() {
+        let data = file_list(10);
+        let msg = clip_2_msg(ClipboardFile::FormatDataResponse {
+            msg_flags: 1,
+            format_data: data.clone(),
+        });
+        assert_eq!(split_file_list_(msg.clone(), false, 3).len(), 1);
+        assert_eq!(split_file_list_(msg.clone(), true, 10).len(), 1);
+        let chunks = split_file_list_(msg, true, 3);
+        assert_eq!(chunks.len(), 4);
+        let mut assembler = FileListAssembler::default();
+        let mut assembled = None;
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let Some(message::Union::Cliprdr(clip)) = chunk.union else {
+                panic!("not a cliprdr message");
+            };
+            assembled = assembler.assemble(clip);
+            assert_eq!(assembled.is_some(), i == 3);
+            if i < 3 {
+                assert_eq!(assembler.progress(), (3 * (i as u32 + 1), 10));
+            }
+        }
+        match assembled.and_then(msg_2_clip) {
+            Some(ClipboardFile::FormatDataResponse {
+                msg_flags,
+                format_data,
+            }) => {
+                assert_eq!(msg_flags, 1);
+                assert_eq!(format_data, data);
+            }
+            _ => panic!("not a format data response"),
+        }
+        assert_eq!(assembler.progress(), (0, 0));
+    }
+
+    #[test]
+    fn test_assemble_out_of_order() {
+        let data = file_list(4);
+        let msg = clip_2_msg(ClipboardFile::FormatDataResponse {
+            msg_flags: 1,
+            format_data: data,
+        });
+        let mut chunks: Vec<Cliprdr> = split_file_list_(msg, true, 2)
+            .into_iter()
+            .filter_map(|m| match m.union {
+                Some(message::Union::Cliprdr(clip)) => Some(clip),
+                _ => None,
+            })
+            .collect();
+        let mut assembler = FileListAssembler::default();
+        let second = chunks.pop().unwrap();
+        // A chunk without its previous ones is dropped.
+        assert!(assembler.assemble(second.clone()).is_none());
+        assert_eq!(assembler.progress(), (0, 0));
+        assert!(assembler.assemble(chunks.pop().unwrap()).is_none());
+        assert!(assembler.assemble(second).is_some());
+    }
+}
@@ -93,6 +93,72 @@ lazy_static::lazy_static! {
     static ref IS_CM: bool = std::env::args().nth(1) == Some("--cm".to_owned()) || std::env::args().nth(1) == Some("--cm-no-ui".to_owned());
 }
 
+const LOG_THROTTLE_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static::lazy_static! {
+    static ref LOG_THROTTLES: Mutex<HashMap<String, LogThrottle>> = Default::default();
+}
+
+struct LogThrottle {
+    msg: String,
+    since: std::time::Instant,
+    suppressed: usize,
+}
+
+// Returns the lines to be logged for `msg`.
+fn throttle_log(
+    throttles: &mut HashMap<String, LogThrottle>,
+    key: &str,
+    msg: String,
+    interval: Duration,
+) -> Vec<String> {
+    let mut lines = vec![];
+    if let Some(t) = throttles.get_mut(key) {
+        if t.msg == msg {
+            if t.since.elapsed() < interval {
+                t.suppressed += 1;
+                return lines;
+            }
+            lines.push(format!(
+                "{} ({} occurrences in {}s)",
+                msg,
+                t.suppressed + 1,
+                t.since.elapsed().as_secs()
+            ));
+            t.suppressed = 0;
+            t.since = std::time::Instant::now();
+            return lines;
+        }
+        if t.suppressed > 0 {
+            lines.push(format!("{} ({} occurrences)", t.msg, t.suppressed));
+        }
+    }
+    lines.push(msg.clone());
+    throttles.insert(
+        key.to_owned(),
+        LogThrottle {
+            msg,
+            since: std::time::Instant::now(),
+            suppressed: 0,
+        },
+    );
+    lines
+}
+
+/// Log in hot error paths, identical messages of the same `key` are collapsed into
+/// a periodic "N occurrences" summary.
+pub fn log_throttled(key: &str, level: log::Level, msg: String) {
+    let lines = throttle_log(
+        &mut LOG_THROTTLES.lock().unwrap(),
+        key,
+        msg,
+        LOG_THROTTLE_INTERVAL,
+    );
+    for line in lines {
+        log::log!(level, "{}", line);
+    }
+}
+
 pub struct SimpleCallOnReturn {
     pub b: bool,
     pub f: Box<dyn Fn() + Send + 'static>,
@@ -153,6 +219,11 @@ pub fn is_support_file_paste_if_macos(ver: &str) -> bool {
     hbb_common::get_version_number(ver) >= hbb_common::get_version_number("1.3.9")
 }
 
+#[inline]
+pub fn is_support_display_rotation(ver: &str) -> bool {
+    hbb_common::get_version_number(ver) >= hbb_common::get_version_number("1.4.1")
+}
+
 #[inline]
 pub fn is_support_screenshot(ver: &str) -> bool {
     is_support_multi_ui_session_num(hbb_common::get_version_number(ver))
@@ -163,6 +234,112 @@ pub fn is_support_screenshot_num(ver: i64) -> bool {
     ver >= hbb_common::get_version_number("1.4.0")
 }
 
+/// Optional features which must be supported by both sides before being used.
+///
+/// The bitmask is exchanged at login, host to client in `platform_additions["capabilities"]`,
+/// client to host in `LoginRequest.capabilities`.
+/// Do not reuse the bits, old peers may still set them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum Capability {
+    TouchInput = 1 << 0,
+    Screenshot = 1 << 1,
+    DisplayRotation = 1 << 2,
+    RemotePrint = 1 << 3,
+    LowDelayAudio = 1 << 4,
+    ClientFocus = 1 << 5,
+    PagedDirListing = 1 << 6,
+    ClientVisibility = 1 << 7,
+    ChunkedClipboardFileList = 1 << 8,
+    RelayMigration = 1 << 9,
+    ScrollGesture = 1 << 10,
+    ClipboardDelta = 1 << 11,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 12] = [
+        Capability::TouchInput,
+        Capability::Screenshot,
+        Capability::DisplayRotation,
+        Capability::RemotePrint,
+        Capability::LowDelayAudio,
+        Capability::ClientFocus,
+        Capability::PagedDirListing,
+        Capability::ClientVisibility,
+        Capability::ChunkedClipboardFileList,
+        Capability::RelayMigration,
+        Capability::ScrollGesture,
+        Capability::ClipboardDelta,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities(u64);
+
+impl Capabilities {
+    /// The capabilities of this build.
+    pub fn local() -> Self {
+        Self(Capability::ALL.iter().fold(0, |bits, c| bits | *c as u64))
+    }
+
+    #[inline]
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Guess the capabilities of a peer which does not send the bitmask.
+    /// Features newer than the bitmask are never assumed.
+    pub fn from_version(ver: &str) -> Self {
+        let mut caps = Self(0);
+        if hbb_common::get_version_number(ver) >= hbb_common::get_version_number("1.2.0") {
+            caps.0 |= Capability::TouchInput as u64;
+        }
+        if is_support_screenshot(ver) {
+            caps.0 |= Capability::Screenshot as u64;
+        }
+        if is_support_display_rotation(ver) {
+            caps.0 |= Capability::DisplayRotation as u64;
+        }
+        if is_support_remote_print(ver) {
+            caps.0 |= Capability::RemotePrint as u64;
+        }
+        caps
+    }
+
+    /// The capabilities both sides support. `peer_bits` is 0 if the peer does not send it.
+    pub fn negotiate(peer_bits: u64, peer_version: &str) -> Self {
+        let peer = if peer_bits == 0 {
+            Self::from_version(peer_version)
+        } else {
+            Self(peer_bits)
+        };
+        Self(Self::local().0 & peer.0)
+    }
+
+    #[inline]
+    pub fn supports(&self, cap: Capability) -> bool {
+        self.0 & cap as u64 != 0
+    }
+}
+
+/// The kinds of the service messages which have versions, see `ServiceTmpl::set_msg_version`.
+/// The client sends the newest version of each kind it supports in `LoginRequest.msg_versions`,
+/// the host sends each subscriber the older one of it and its own.
+pub const MSG_KIND_AUDIO: &str = "audio";
+pub const MSG_KIND_VIDEO: &str = "video";
+/// 1: `AudioFrame.capture_time`.
+pub const AUDIO_MSG_VERSION: u32 = 1;
+/// 1: `VideoFrame.capture_time`.
+pub const VIDEO_MSG_VERSION: u32 = 1;
+
+/// The message versions of this build.
+pub fn local_msg_versions() -> HashMap<String, u32> {
+    HashMap::from([
+        (MSG_KIND_AUDIO.to_owned(), AUDIO_MSG_VERSION),
+        (MSG_KIND_VIDEO.to_owned(), VIDEO_MSG_VERSION),
+    ])
+}
+
 // is server process, with "--server" args
 #[inline]
 pub fn is_server() -> bool {
@@ -1458,10 +1635,42 @@ pub fn decode_id_pk(signed: &[u8], key: &sign::PublicKey) -> ResultType<(String,
     }
 }
 
+// The fixed keys of the secure handshake, so the encrypted path can be tested deterministically.
+// They can't be set in the release builds, see "test-keys" in Cargo.toml.
+#[cfg(all(feature = "test-keys", not(debug_assertions)))]
+compile_error!("\"test-keys\" is only for the tests, it can't be enabled in the release builds");
+
+#[cfg(any(test, feature = "test-keys"))]
+lazy_static::lazy_static! {
+    static ref TEST_KEYS: Mutex<Option<(box_::SecretKey, secretbox::Key)>> = Default::default();
+}
+
+/// Use the fixed box secret key and symmetric key in the handshakes, None restores the random ones.
+#[cfg(any(test, feature = "test-keys"))]
+pub fn set_test_keys(keys: Option<(box_::SecretKey, secretbox::Key)>) {
+    *TEST_KEYS.lock().unwrap() = keys;
+}
+
+pub fn gen_box_keypair() -> (box_::PublicKey, box_::SecretKey) {
+    #[cfg(any(test, feature = "test-keys"))]
+    if let Some((sk, _)) = TEST_KEYS.lock().unwrap().clone() {
+        return (sk.public_key(), sk);
+    }
+    box_::gen_keypair()
+}
+
+fn gen_symmetric_key() -> secretbox::Key {
+    #[cfg(any(test, feature = "test-keys"))]
+    if let Some((_, key)) = TEST_KEYS.lock().unwrap().clone() {
+        return key;
+    }
+    secretbox::gen_key()
+}
+
 pub fn create_symmetric_key_msg(their_pk_b: [u8; 32]) -> (Bytes, Bytes, secretbox::Key) {
     let their_pk_b = box_::PublicKey(their_pk_b);
-    let (our_pk_b, out_sk_b) = box_::gen_keypair();
-    let key = secretbox::gen_key();
+    let (our_pk_b, out_sk_b) = gen_box_keypair();
+    let key = gen_symmetric_key();
     let nonce = box_::Nonce([0u8; box_::NONCEBYTES]);
     let sealed_key = box_::seal(&key.0, &nonce, &their_pk_b, &out_sk_b);
     (Vec::from(our_pk_b.0).into(), sealed_key.into(), key)
@@ -1755,6 +1964,51 @@ pub fn is_udp_disabled() -> bool {
     get_builtin_option(keys::OPTION_DISABLE_UDP) == "Y"
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    Any,
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    pub fn matches(&self, addr: &SocketAddr) -> bool {
+        // IPv4 peers accepted by a dual-stack socket show up as IPv4-mapped IPv6 addresses.
+        let is_ipv4 = match addr {
+            SocketAddr::V4(_) => true,
+            SocketAddr::V6(v6) => v6.ip().to_ipv4_mapped().is_some(),
+        };
+        match self {
+            IpFamily::Any => true,
+            IpFamily::V4 => is_ipv4,
+            IpFamily::V6 => !is_ipv4,
+        }
+    }
+}
+
+/// The address family used for direct (non-relay) connections,
+/// "ipv4" or "ipv6", anything else means no constraint.
+pub fn get_direct_ip_family() -> IpFamily {
+    match Config::get_option("direct-ip-family").as_str() {
+        "ipv4" => IpFamily::V4,
+        "ipv6" => IpFamily::V6,
+        _ => IpFamily::Any,
+    }
+}
+
+/// Resolve `target` to an address of the configured direct ip family.
+pub fn constrain_direct_target(target: String) -> ResultType<String> {
+    let family = get_direct_ip_family();
+    if family == IpFamily::Any {
+        return Ok(target);
+    }
+    let Some(addr) = target.to_socket_addrs()?.find(|x| family.matches(x)) else {
+        bail!("No {:?} address found for {}", family, target);
+    };
+    log::info!("Direct connection to {} uses {}", target, addr);
+    Ok(addr.to_string())
+}
+
 // this crate https://github.com/yoshd/stun-client supports nat type
 async fn stun_ipv6_test(stun_server: &str) -> ResultType<(SocketAddr, String)> {
     use std::net::ToSocketAddrs;
@@ -2020,6 +2274,108 @@ pub async fn get_ipv6_socket() -> Option<(Arc<UdpSocket>, bytes::Bytes)> {
     None
 }
 
+/// Line ending normalization of the clipboard text, selected by the controlling side
+/// for each connection and applied in both directions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Preserve,
+    Lf,
+    Crlf,
+    // The native line ending of the platform receiving the text.
+    Auto,
+}
+
+impl LineEnding {
+    pub fn from_option(v: &str) -> Self {
+        match v {
+            "lf" => Self::Lf,
+            "crlf" => Self::Crlf,
+            "auto" => Self::Auto,
+            _ => Self::Preserve,
+        }
+    }
+
+    // `OptionMessage.clipboard_line_ending`, 0 is not set.
+    pub fn from_i32(v: i32) -> Option<Self> {
+        match v {
+            1 => Some(Self::Preserve),
+            2 => Some(Self::Lf),
+            3 => Some(Self::Crlf),
+            4 => Some(Self::Auto),
+            _ => None,
+        }
+    }
+
+    pub fn to_i32(self) -> i32 {
+        match self {
+            Self::Preserve => 1,
+            Self::Lf => 2,
+            Self::Crlf => 3,
+            Self::Auto => 4,
+        }
+    }
+
+    fn target(self, platform: &str) -> Option<&'static str> {
+        match self {
+            Self::Preserve => None,
+            Self::Lf => Some("\n"),
+            Self::Crlf => Some("\r\n"),
+            Self::Auto if platform.is_empty() => None,
+            Self::Auto if platform == whoami::Platform::Windows.to_string() => Some("\r\n"),
+            Self::Auto => Some("\n"),
+        }
+    }
+}
+
+pub fn normalize_line_endings(text: &str, ending: &str) -> String {
+    let text = text.replace("\r\n", "\n");
+    if ending == "\n" {
+        text
+    } else {
+        text.replace('\n', ending)
+    }
+}
+
+/// Normalize the line endings of the text clipboards sent to `platform`.
+/// Returns false if nothing is changed.
+pub fn normalize_clipboards(
+    clipboards: &mut [Clipboard],
+    line_ending: LineEnding,
+    platform: &str,
+) -> bool {
+    let Some(ending) = line_ending.target(platform) else {
+        return false;
+    };
+    let mut changed = false;
+    for c in clipboards.iter_mut() {
+        if c.format.enum_value() != Ok(ClipboardFormat::Text) {
+            continue;
+        }
+        let data = if c.compress {
+            hbb_common::compress::decompress(&c.content)
+        } else {
+            c.content.to_vec()
+        };
+        let Ok(text) = String::from_utf8(data) else {
+            continue;
+        };
+        let normalized = normalize_line_endings(&text, ending);
+        if normalized == text {
+            continue;
+        }
+        let compressed = hbb_common::compress::compress(normalized.as_bytes());
+        c.compress = compressed.len() < normalized.len();
+        c.content = if c.compress {
+            compressed.into()
+        } else {
+            normalized.into_bytes().into()
+        };
+        changed = true;
+    }
+    changed
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -2050,6 +2406,57 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_normalize_clipboards() {
+        assert_eq!(normalize_line_endings("a\r\nb\nc", "\n"), "a\nb\nc");
+        assert_eq!(normalize_line_endings("a\r\nb\nc", "\r\n"), "a\r\nb\r\nc");
+        let text = |c: &Clipboard| {
+            let data = if c.compress {
+                hbb_common::compress::decompress(&c.content)
+            } else {
+                c.content.to_vec()
+            };
+            String::from_utf8(data).unwrap()
+        };
+        let mut clipboards = vec![
+            Clipboard {
+                content: "a\nb".repeat(100).into_bytes().into(),
+                format: ClipboardFormat::Text.into(),
+                ..Default::default()
+            },
+            Clipboard {
+                content: "<p>a</p>\n".as_bytes().to_vec().into(),
+                format: ClipboardFormat::Html.into(),
+                ..Default::default()
+            },
+        ];
+        assert!(!normalize_clipboards(
+            &mut clipboards,
+            LineEnding::Preserve,
+            "Windows"
+        ));
+        assert!(!normalize_clipboards(&mut clipboards, LineEnding::Auto, ""));
+        assert!(!normalize_clipboards(
+            &mut clipboards,
+            LineEnding::Auto,
+            "Linux"
+        ));
+        assert!(normalize_clipboards(
+            &mut clipboards,
+            LineEnding::Auto,
+            "Windows"
+        ));
+        assert_eq!(text(&clipboards[0]), "a\r\nb".repeat(100));
+        // Only the plain text is changed.
+        assert_eq!(text(&clipboards[1]), "<p>a</p>\n");
+        assert!(normalize_clipboards(
+            &mut clipboards,
+            LineEnding::Lf,
+            "Windows"
+        ));
+        assert_eq!(text(&clipboards[0]), "a\nb".repeat(100));
+    }
+
     // ThrottledInterval tick at the same time as tokio interval, if no sleeps
     #[allow(non_snake_case)]
     #[tokio::test]
@@ -2160,4 +2567,66 @@ mod tests {
             Duration::from_nanos(0)
         );
     }
+
+    #[test]
+    fn test_throttle_log() {
+        let mut throttles = HashMap::new();
+        let interval = Duration::from_secs(60);
+        assert_eq!(
+            throttle_log(&mut throttles, "k", "a".to_owned(), interval),
+            vec!["a"]
+        );
+        assert!(throttle_log(&mut throttles, "k", "a".to_owned(), interval).is_empty());
+        assert!(throttle_log(&mut throttles, "k", "a".to_owned(), interval).is_empty());
+        // Other keys are not affected.
+        assert_eq!(
+            throttle_log(&mut throttles, "k2", "a".to_owned(), interval),
+            vec!["a"]
+        );
+        // A different message flushes the suppressed count.
+        assert_eq!(
+            throttle_log(&mut throttles, "k", "b".to_owned(), interval),
+            vec!["a (2 occurrences)", "b"]
+        );
+        assert!(throttle_log(&mut throttles, "k", "b".to_owned(), interval).is_empty());
+        assert_eq!(
+            throttle_log(&mut throttles, "k", "b".to_owned(), Duration::ZERO),
+            vec!["b (2 occurrences in 0s)"]
+        );
+    }
+
+    #[test]
+    fn test_capabilities_negotiate() {
+        let all = Capabilities::local();
+        let bits = |caps: &[Capability]| caps.iter().fold(0, |b, c| b | *c as u64);
+        // Every capability has its own bit.
+        assert_eq!(all.bits().count_ones() as usize, Capability::ALL.len());
+        // A minimal peer gets nothing optional.
+        let minimal = Capabilities::negotiate(0, "1.1.9");
+        for c in Capability::ALL {
+            assert!(!minimal.supports(c), "{:?}", c);
+        }
+        // Old peers without the bitmask fall back to the version, new features are never assumed.
+        let old = Capabilities::negotiate(0, "1.4.1");
+        assert!(old.supports(Capability::DisplayRotation));
+        assert!(old.supports(Capability::Screenshot));
+        assert!(!old.supports(Capability::LowDelayAudio));
+        assert!(!old.supports(Capability::ClientFocus));
+        // The bitmask wins over the version.
+        let peer = Capabilities::negotiate(
+            bits(&[Capability::TouchInput, Capability::ClientFocus]),
+            "1.4.1",
+        );
+        for c in Capability::ALL {
+            assert_eq!(
+                peer.supports(c),
+                c == Capability::TouchInput || c == Capability::ClientFocus,
+                "{:?}",
+                c
+            );
+        }
+        // Unknown bits from a newer peer are dropped.
+        let newer = Capabilities::negotiate(u64::MAX, "");
+        assert_eq!(newer, all);
+    }
 }
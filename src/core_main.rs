@@ -148,7 +148,15 @@ pub fn core_main() -> Option<Vec<String>> {
             log_name = name;
         }
     }
-    hbb_common::init_log(false, &log_name);
+    let headless = args.len() > 1
+        && args[0] == "--server"
+        && args[1] == crate::server::headless_mode::ARG_HEADLESS;
+    if headless {
+        crate::server::headless_mode::set(true);
+        crate::server::headless_mode::init_log();
+    } else {
+        hbb_common::init_log(false, &log_name);
+    }
     log::info!("main start args: {:?}, env: {:?}", args, std::env::args());
 
     // linux uni (url) go here.
@@ -335,7 +343,7 @@ pub fn core_main() -> Option<Vec<String>> {
         } else if args[0] == "--server" {
             log::info!("start --server with user {}", crate::username());
             #[cfg(target_os = "linux")]
-            {
+            if !headless {
                 hbb_common::allow_err!(crate::platform::check_autostart_config());
                 std::process::Command::new("pkill")
                     .arg("-f")
@@ -351,7 +359,9 @@ pub fn core_main() -> Option<Vec<String>> {
                 crate::start_server(true, false);
             }
             #[cfg(target_os = "macos")]
-            {
+            if headless {
+                crate::start_server(true, false);
+            } else {
                 let handler = std::thread::spawn(move || crate::start_server(true, false));
                 crate::tray::start_tray();
                 // prevent server exit when encountering errors from tray
@@ -453,6 +463,115 @@ pub fn core_main() -> Option<Vec<String>> {
                 println!("Installation and administrative privileges required!");
             }
             return None;
+        } else if args[0] == "--printer-queue" {
+            #[cfg(all(target_os = "windows", feature = "flutter"))]
+            if crate::platform::is_installed() && is_root() {
+                let id = args.get(2).and_then(|x| x.parse::<u64>().ok());
+                let pos = args.get(3).and_then(|x| x.parse::<usize>().ok());
+                match (args.get(1).map(|x| x.as_str()), id, pos) {
+                    (Some("list"), _, _) => match crate::ipc::get_printer_queue() {
+                        Ok(jobs) => {
+                            for job in jobs {
+                                println!(
+                                    "{} peer: {}, size: {}, created: {}, attempts: {}",
+                                    job.id, job.peer_id, job.size, job.created, job.attempts
+                                );
+                            }
+                        }
+                        Err(e) => println!("Failed to get the printer queue: {}", e),
+                    },
+                    (Some("remove"), Some(id), _) => {
+                        hbb_common::allow_err!(crate::ipc::printer_queue_action(id, None));
+                    }
+                    (Some("move"), Some(id), Some(pos)) => {
+                        hbb_common::allow_err!(crate::ipc::printer_queue_action(id, Some(pos)));
+                    }
+                    _ => println!("Usage: --printer-queue <list|remove <id>|move <id> <position>>"),
+                }
+            } else {
+                println!("Installation and administrative privileges required!");
+            }
+            #[cfg(not(all(target_os = "windows", feature = "flutter")))]
+            println!("Remote printer is not supported");
+            return None;
+        } else if args[0] == "--set-clipboard" {
+            // --set-clipboard <text> [--send], with --send the text is sent to the peers too.
+            if crate::platform::is_installed() && is_root() {
+                match args.get(1) {
+                    Some(text) => {
+                        let send = args.iter().skip(2).any(|a| a == "--send");
+                        if let Err(e) = crate::ipc::set_host_clipboard(text.to_owned(), send) {
+                            println!("Failed to set the clipboard: {}", e);
+                        }
+                    }
+                    None => println!("Usage: --set-clipboard <text> [--send]"),
+                }
+            } else {
+                println!("Installation and administrative privileges required!");
+            }
+            return None;
+        } else if args[0] == "--session-reports" {
+            if crate::platform::is_installed() && is_root() {
+                match crate::ipc::get_session_reports() {
+                    Ok(reports) => {
+                        for report in reports {
+                            println!("{}", report);
+                        }
+                    }
+                    Err(e) => println!("Failed to get the session reports: {}", e),
+                }
+            } else {
+                println!("Installation and administrative privileges required!");
+            }
+            return None;
+        } else if args[0] == "--feature-allowlist" {
+            if crate::platform::is_installed() && is_root() {
+                let feature = args.get(1).map(|x| x.as_str()).unwrap_or_default();
+                if args.len() < 3 || !crate::server::ALLOWLIST_FEATURES.contains(&feature) {
+                    println!(
+                        "Usage: --feature-allowlist <{}> <list|add|remove|clear> [id]",
+                        crate::server::ALLOWLIST_FEATURES.join("|")
+                    );
+                    return None;
+                }
+                let key = crate::server::feature_allowlist_option(feature);
+                let options = crate::ipc::get_options();
+                let mut ids = crate::server::parse_id_list(
+                    options.get(&key).map(|x| x.as_str()).unwrap_or(""),
+                );
+                match (args[2].as_str(), args.get(3)) {
+                    ("list", _) => {
+                        for id in ids {
+                            println!("{}", id);
+                        }
+                    }
+                    ("add", Some(id)) => {
+                        if !ids.contains(id) {
+                            ids.push(id.to_owned());
+                            crate::ipc::set_option(&key, &ids.join(","));
+                        }
+                    }
+                    ("remove", Some(id)) => {
+                        ids.retain(|x| x != id);
+                        // An empty value would drop the allowlist, "," keeps it denying everyone.
+                        let value = if ids.is_empty() {
+                            ",".to_owned()
+                        } else {
+                            ids.join(",")
+                        };
+                        crate::ipc::set_option(&key, &value);
+                    }
+                    ("clear", _) => {
+                        crate::ipc::set_option(&key, "");
+                    }
+                    _ => {
+                        println!("Invalid arguments");
+                    }
+                }
+            } else {
+                println!("Installation and administrative privileges required!");
+            }
+            return None;
         } else if args[0] == "--assign" {
             if config::Config::no_register_device() {
                 println!("Cannot assign an unregistrable device!");
@@ -559,6 +678,119 @@ pub fn core_main() -> Option<Vec<String>> {
                 println!("Installation and administrative privileges required!");
             }
             return None;
+        } else if args[0] == "--hwcodec-status" {
+            match crate::ipc::get_hwcodec_status() {
+                Ok(status) => println!("{}", status),
+                Err(e) => println!("Failed to get the hwcodec status: {}", e),
+            }
+            return None;
+        } else if args[0] == "--network-sim" {
+            // --network-sim <conn_id> <rtt_ms> [jitter_ms] [loss_pct] [kbps], for development only.
+            let arg = |i: usize| args.get(i).and_then(|a| a.parse::<u32>().ok()).unwrap_or(0);
+            match args.get(1).and_then(|a| a.parse::<i32>().ok()) {
+                Some(conn_id) => {
+                    if crate::platform::is_installed() && is_root() {
+                        hbb_common::allow_err!(crate::ipc::set_network_sim(
+                            conn_id,
+                            arg(2),
+                            arg(3),
+                            arg(4),
+                            arg(5)
+                        ));
+                    } else {
+                        println!("Installation and administrative privileges required!");
+                    }
+                }
+                None => println!("--network-sim <conn_id> <rtt_ms> [jitter_ms] [loss_pct] [kbps]"),
+            }
+            return None;
+        } else if args[0] == "--inhibitors" {
+            #[cfg(target_os = "linux")]
+            match crate::ipc::get_inhibitors() {
+                Ok(v) if v.is_empty() => println!("No active inhibitors"),
+                Ok(v) => println!("{}", v),
+                Err(e) => println!("Failed to get the inhibitors: {}", e),
+            }
+            return None;
+        } else if args[0] == "--direct-tls-fingerprint" {
+            match crate::ipc::get_direct_tls_fingerprint() {
+                Ok(v) if v.is_empty() => println!("The direct access port is not TLS"),
+                Ok(v) => println!("{}", v),
+                Err(e) => println!("Failed to get the fingerprint: {}", e),
+            }
+            return None;
+        } else if args[0] == "--capture-env" {
+            match crate::ipc::get_capture_env() {
+                Ok(v) => println!("{}", v),
+                Err(e) => println!("Failed to get the capture environment: {}", e),
+            }
+            return None;
+        } else if args[0] == "--pending-auth" {
+            match crate::ipc::get_pending_auth_conns() {
+                Ok(v) => println!("{}", v),
+                Err(e) => println!("Failed to get the pending connections: {}", e),
+            }
+            return None;
+        } else if args[0] == "--server-watchdog" {
+            match crate::ipc::get_server_watchdog() {
+                Ok(v) => println!("{}", v),
+                Err(e) => println!("Failed to get the server watchdog: {}", e),
+            }
+            return None;
+        } else if args[0] == "--relisten-stats" {
+            match crate::ipc::get_relisten_stats() {
+                Ok((listens, retried, failed)) => println!(
+                    "listens: {}, retried: {}, failed: {}",
+                    listens, retried, failed
+                ),
+                Err(e) => println!("Failed to get the relisten stats: {}", e),
+            }
+            return None;
+        } else if args[0] == "--option-journal" {
+            // --option-journal [n], the last n option changes, 20 by default
+            let n = args
+                .get(1)
+                .and_then(|a| a.parse::<usize>().ok())
+                .unwrap_or(20);
+            match crate::ipc::get_option_journal(n) {
+                Ok(v) => println!("{}", v),
+                Err(e) => println!("Failed to get the option journal: {}", e),
+            }
+            return None;
+        } else if args[0] == "--terminate-sessions" {
+            match crate::ipc::terminate_sessions() {
+                Ok(true) => println!("All the sessions are terminated"),
+                Ok(false) => println!("Some sessions are not closed in time"),
+                Err(e) => println!("Failed to terminate the sessions: {}", e),
+            }
+            return None;
+        } else if args[0] == "--migrate-relay" {
+            // --migrate-relay <conn_id> <relay_server>
+            match (args.get(1).and_then(|a| a.parse::<i32>().ok()), args.get(2)) {
+                (Some(conn_id), Some(relay_server)) => {
+                    match crate::ipc::migrate_relay(conn_id, relay_server.to_owned()) {
+                        Ok(true) => println!("The relay migration of {} is started", conn_id),
+                        Ok(false) => println!("No remote connection {}", conn_id),
+                        Err(e) => println!("Failed to migrate the relay: {}", e),
+                    }
+                }
+                _ => println!("--migrate-relay <conn_id> <relay_server>"),
+            }
+            return None;
+        } else if args[0] == "--refresh-audio-devices" {
+            match crate::ipc::refresh_audio_devices() {
+                Ok(true) => println!("The audio devices changed, audio is restarted"),
+                Ok(false) => println!("The audio devices are unchanged"),
+                Err(e) => println!("Failed to refresh the audio devices: {}", e),
+            }
+            return None;
+        } else if args[0] == "--recheck-hwcodec" {
+            if crate::platform::is_installed() && is_root() {
+                hbb_common::allow_err!(crate::ipc::notify_server_to_recheck_hwcodec());
+            } else {
+                println!("Installation and administrative privileges required!");
+            }
+            return None;
         } else if args[0] == "--check-hwcodec-config" {
             #[cfg(feature = "hwcodec")]
             crate::ipc::hwcodec_process();
@@ -0,0 +1,260 @@
+// Stable codes of the login errors and the close reasons sent by the host.
+//
+// The host sends the code in `LoginResponse.error_code` and in a `close_reason_code` misc before
+// the close reason, along with the legacy english text for the old clients. A new client shows the
+// canonical text of the code, which is the key of the translations, so the peers on another
+// version or language get the same message, and it can handle the codes without parsing the text.
+// The codes are never reused, a new error gets a new one. Each error path passes its code with the
+// text, see `CodedError`, the text is never parsed. The audit log, the connection manager and the
+// close requests between the processes use the same codes.
+
+use crate::client::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(i32)]
+pub enum ErrorCode {
+    Unknown = 0,
+    // Login
+    PasswordEmpty = 1,
+    PasswordWrong = 2,
+    Require2fa = 3,
+    Wrong2fa = 4,
+    NoPasswordAccess = 5,
+    // Out of the allowed hours of the host.
+    Offline = 6,
+    EncryptionRequired = 7,
+    TooManyAttempts = 8,
+    TryLater = 9,
+    IpBlocked = 10,
+    PrivacyModeOn = 11,
+    MainWindowNotOpen = 12,
+    // "No permission of ...", the text has the detail.
+    NoPermission = 13,
+    PreviewNotSupported = 14,
+    // "Not allowed to use ...", the text has the detail.
+    FeatureNotAllowed = 15,
+    // The port forward target can't be reached, the text has the detail.
+    PortForwardFailed = 16,
+    DesktopNotInited = 17,
+    DesktopSessionNotReady = 18,
+    DesktopXsessionFailed = 19,
+    DesktopSessionAnotherUser = 20,
+    DesktopXorgNotFound = 21,
+    DesktopNoDesktop = 22,
+    DesktopSessionNotReadyPasswordEmpty = 23,
+    DesktopSessionNotReadyPasswordWrong = 24,
+    WaylandLoginScreen = 25,
+    // The cooldown after the user of the host terminated all the sessions.
+    DoNotDisturb = 26,
+    // Close reasons
+    ClosedByPeer = 100,
+    ClosedByAdmin = 101,
+    ClosedByWebConsole = 102,
+    Inactivity = 103,
+    ProtocolViolation = 104,
+    StopService = 105,
+    HostShutdown = 106,
+    TerminatedByHost = 107,
+    // The user of the host undid a sensitive action of the peer.
+    NotConfirmed = 108,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 35] = [
+        Self::PasswordEmpty,
+        Self::PasswordWrong,
+        Self::Require2fa,
+        Self::Wrong2fa,
+        Self::NoPasswordAccess,
+        Self::Offline,
+        Self::EncryptionRequired,
+        Self::TooManyAttempts,
+        Self::TryLater,
+        Self::IpBlocked,
+        Self::PrivacyModeOn,
+        Self::MainWindowNotOpen,
+        Self::NoPermission,
+        Self::PreviewNotSupported,
+        Self::FeatureNotAllowed,
+        Self::PortForwardFailed,
+        Self::DesktopNotInited,
+        Self::DesktopSessionNotReady,
+        Self::DesktopXsessionFailed,
+        Self::DesktopSessionAnotherUser,
+        Self::DesktopXorgNotFound,
+        Self::DesktopNoDesktop,
+        Self::DesktopSessionNotReadyPasswordEmpty,
+        Self::DesktopSessionNotReadyPasswordWrong,
+        Self::WaylandLoginScreen,
+        Self::DoNotDisturb,
+        Self::ClosedByPeer,
+        Self::ClosedByAdmin,
+        Self::ClosedByWebConsole,
+        Self::Inactivity,
+        Self::ProtocolViolation,
+        Self::StopService,
+        Self::HostShutdown,
+        Self::TerminatedByHost,
+        Self::NotConfirmed,
+    ];
+
+    #[inline]
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    pub fn from_code(code: i32) -> Self {
+        Self::ALL
+            .iter()
+            .find(|c| c.code() == code)
+            .cloned()
+            .unwrap_or(Self::Unknown)
+    }
+
+    /// The legacy text, also the key of the translations. None if the text has a detail, see
+    /// `prefix`.
+    pub fn text(self) -> Option<&'static str> {
+        let text = match self {
+            Self::Unknown
+            | Self::NoPermission
+            | Self::FeatureNotAllowed
+            | Self::PortForwardFailed => return None,
+            Self::PasswordEmpty => LOGIN_MSG_PASSWORD_EMPTY,
+            Self::PasswordWrong => LOGIN_MSG_PASSWORD_WRONG,
+            Self::Require2fa => REQUIRE_2FA,
+            Self::Wrong2fa => LOGIN_MSG_2FA_WRONG,
+            Self::NoPasswordAccess => LOGIN_MSG_NO_PASSWORD_ACCESS,
+            Self::Offline => LOGIN_MSG_OFFLINE,
+            Self::EncryptionRequired => LOGIN_MSG_ENCRYPTION_REQUIRED,
+            Self::TooManyAttempts => "Too many wrong attempts",
+            Self::TryLater => "Please try 1 minute later",
+            Self::IpBlocked => "Your ip is blocked by the peer",
+            Self::PrivacyModeOn => "Someone turns on privacy mode, exit",
+            Self::MainWindowNotOpen => "The main window is not open",
+            Self::PreviewNotSupported => "Preview is not supported",
+            Self::DesktopNotInited => LOGIN_MSG_DESKTOP_NOT_INITED,
+            Self::DesktopSessionNotReady => LOGIN_MSG_DESKTOP_SESSION_NOT_READY,
+            Self::DesktopXsessionFailed => LOGIN_MSG_DESKTOP_XSESSION_FAILED,
+            Self::DesktopSessionAnotherUser => LOGIN_MSG_DESKTOP_SESSION_ANOTHER_USER,
+            Self::DesktopXorgNotFound => LOGIN_MSG_DESKTOP_XORG_NOT_FOUND,
+            Self::DesktopNoDesktop => LOGIN_MSG_DESKTOP_NO_DESKTOP,
+            Self::DesktopSessionNotReadyPasswordEmpty => {
+                LOGIN_MSG_DESKTOP_SESSION_NOT_READY_PASSWORD_EMPTY
+            }
+            Self::DesktopSessionNotReadyPasswordWrong => {
+                LOGIN_MSG_DESKTOP_SESSION_NOT_READY_PASSWORD_WRONG
+            }
+            Self::WaylandLoginScreen => LOGIN_SCREEN_WAYLAND,
+            Self::DoNotDisturb => "The host does not accept connections for a while",
+            Self::ClosedByPeer => "Closed manually by the peer",
+            Self::ClosedByAdmin => "Disconnected by the administrator of the host",
+            Self::ClosedByWebConsole => "Closed manually by web console",
+            Self::Inactivity => "Connection failed due to inactivity",
+            Self::ProtocolViolation => "Protocol violation",
+            Self::StopService => "The host stopped the service",
+            Self::HostShutdown => "The host is shutting down",
+            Self::TerminatedByHost => "Terminated by the user of the host",
+            Self::NotConfirmed => "The user of the remote device didn't confirm it",
+        };
+        Some(text)
+    }
+
+    // The start of the texts with a detail.
+    fn prefix(self) -> Option<&'static str> {
+        match self {
+            Self::NoPermission => Some("No permission of "),
+            Self::FeatureNotAllowed => Some("Not allowed to use "),
+            Self::PortForwardFailed => Some("Failed to access remote "),
+            _ => None,
+        }
+    }
+
+    /// The error of a code whose text has a detail, e.g. the name of the permission.
+    pub fn with_detail(self, detail: &str) -> CodedError {
+        CodedError {
+            code: self,
+            text: format!("{}{}", self.prefix().unwrap_or_default(), detail),
+        }
+    }
+}
+
+/// An error sent to the peer, the code and the legacy text for the old clients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodedError {
+    pub code: ErrorCode,
+    pub text: String,
+}
+
+impl From<ErrorCode> for CodedError {
+    fn from(code: ErrorCode) -> Self {
+        Self {
+            code,
+            text: code.text().unwrap_or_default().to_owned(),
+        }
+    }
+}
+
+/// The text to show for an error of the host, the canonical one of the code if there's one, so it
+/// is translated, or the text sent by the host.
+pub fn canonical_text(code: i32, text: &str) -> String {
+    ErrorCode::from_code(code).text().unwrap_or(text).to_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_codes() {
+        let mut codes = HashSet::new();
+        let mut texts = HashSet::new();
+        for c in ErrorCode::ALL {
+            assert!(codes.insert(c.code()), "duplicated code {:?}", c);
+            assert_eq!(ErrorCode::from_code(c.code()), c);
+            match (c.text(), c.prefix()) {
+                (Some(text), None) => {
+                    assert!(texts.insert(text), "duplicated text {}", text);
+                    assert_eq!(CodedError::from(c).text, text);
+                }
+                (None, Some(prefix)) => {
+                    assert_eq!(c.with_detail("audio").text, format!("{}audio", prefix));
+                }
+                _ => panic!("{:?} has no text", c),
+            }
+        }
+        assert_eq!(ErrorCode::from_code(-1), ErrorCode::Unknown);
+        assert_eq!(ErrorCode::from_code(ErrorCode::Unknown.code()), ErrorCode::Unknown);
+    }
+
+    // The texts stay what the old clients know.
+    #[test]
+    fn test_legacy_texts() {
+        assert_eq!(
+            ErrorCode::NoPermission.with_detail("file transfer").text,
+            "No permission of file transfer"
+        );
+        assert_eq!(
+            ErrorCode::PortForwardFailed
+                .with_detail("RDP, please make sure if it is open")
+                .text,
+            "Failed to access remote RDP, please make sure if it is open"
+        );
+        assert_eq!(
+            CodedError::from(ErrorCode::TryLater).text,
+            "Please try 1 minute later"
+        );
+        assert_eq!(
+            CodedError::from(ErrorCode::ClosedByPeer).text,
+            "Closed manually by the peer"
+        );
+        assert_eq!(
+            canonical_text(ErrorCode::PasswordWrong.code(), "Falsches Passwort"),
+            LOGIN_MSG_PASSWORD_WRONG
+        );
+        assert_eq!(
+            canonical_text(ErrorCode::NoPermission.code(), "No permission of audio"),
+            "No permission of audio"
+        );
+    }
+}
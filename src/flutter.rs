@@ -706,11 +706,32 @@ impl InvokeUiSession for FlutterHandler {
                     "target_bitrate",
                     &status.target_bitrate.map_or(NULL, |it| it.to_string()),
                 ),
+                (
+                    "display_target_bitrates",
+                    &if status.display_target_bitrates.is_empty() {
+                        NULL
+                    } else {
+                        serde_json::ser::to_string(&status.display_target_bitrates)
+                            .unwrap_or(NULL.to_owned())
+                    },
+                ),
+                (
+                    "frame_drops",
+                    &if status.frame_drops.is_empty() {
+                        NULL
+                    } else {
+                        serde_json::ser::to_string(&status.frame_drops).unwrap_or(NULL.to_owned())
+                    },
+                ),
                 (
                     "codec_format",
                     &status.codec_format.map_or(NULL, |it| it.to_string()),
                 ),
                 ("chroma", &status.chroma.map_or(NULL, |it| it.to_string())),
+                (
+                    "camera_frames",
+                    &status.camera_frames.map_or(NULL, |it| it.to_string()),
+                ),
             ],
             &[],
         );
@@ -785,6 +806,26 @@ impl InvokeUiSession for FlutterHandler {
         }
     }
 
+    fn update_folder_files_page(
+        &self,
+        entries: &Vec<FileEntry>,
+        path: String,
+        offset: u64,
+        total: u64,
+        done: bool,
+    ) {
+        self.push_event(
+            "file_dir_page",
+            &[
+                ("value", &crate::common::make_fd_to_json(0, path, entries)),
+                ("offset", &offset.to_string()),
+                ("total", &total.to_string()),
+                ("done", &done.to_string()),
+            ],
+            &[],
+        );
+    }
+
     fn update_empty_dirs(&self, res: ReadEmptyDirsResponse) {
         self.push_event(
             "empty_dirs",
@@ -1084,6 +1125,102 @@ impl InvokeUiSession for FlutterHandler {
         }
     }
 
+    fn update_control_token(&self, holding: bool, holder: String, queued: bool) {
+        self.push_event(
+            "control_token",
+            &[
+                ("holding", json!(holding)),
+                ("holder", json!(holder)),
+                ("queued", json!(queued)),
+            ],
+            &[],
+        );
+    }
+
+    fn update_process_list(&self, list: ProcessList) {
+        let processes: Vec<_> = list
+            .processes
+            .iter()
+            .map(|p| {
+                json!({
+                    "pid": p.pid,
+                    "name": p.name,
+                    "user": p.user,
+                    "cpu_usage": p.cpu_usage,
+                    "memory": p.memory,
+                    "protected": p.protected,
+                })
+            })
+            .collect();
+        self.push_event(
+            "process_list",
+            &[
+                ("page", json!(list.page)),
+                ("total", json!(list.total)),
+                ("processes", json!(processes)),
+                ("error", json!(list.error)),
+            ],
+            &[],
+        );
+    }
+
+    fn on_kill_process_response(&self, res: KillProcessResponse) {
+        self.push_event(
+            "kill_process",
+            &[
+                ("pid", json!(res.pid)),
+                ("name", json!(res.name)),
+                ("token", json!(res.token.to_string())),
+                ("requires_approval", json!(res.requires_approval)),
+                ("pending_approval", json!(res.pending_approval)),
+                ("done", json!(res.done)),
+                ("error", json!(res.error)),
+            ],
+            &[],
+        );
+    }
+
+    fn update_quick_actions(&self, list: QuickActionList) {
+        let actions: Vec<_> = list
+            .actions
+            .iter()
+            .map(|a| json!({ "name": a.name, "confirm": a.confirm }))
+            .collect();
+        self.push_event(
+            "quick_actions",
+            &[("actions", json!(actions)), ("error", json!(list.error))],
+            &[],
+        );
+    }
+
+    fn on_quick_action_response(&self, res: QuickActionResponse) {
+        self.push_event(
+            "quick_action",
+            &[
+                ("name", json!(res.name)),
+                ("token", json!(res.token.to_string())),
+                ("started", json!(res.started)),
+                ("error", json!(res.error)),
+            ],
+            &[],
+        );
+    }
+
+    fn on_quick_action_output(&self, output: QuickActionOutput) {
+        self.push_event(
+            "quick_action_output",
+            &[
+                ("name", json!(output.name)),
+                ("data", json!(String::from_utf8_lossy(&output.data))),
+                ("stderr", json!(output.stderr)),
+                ("truncated", json!(output.truncated)),
+                ("done", json!(output.done)),
+                ("exit_code", json!(output.exit_code)),
+            ],
+            &[],
+        );
+    }
+
     fn update_record_status(&self, start: bool) {
         self.push_event("record_status", &[("start", &start.to_string())], &[]);
     }
@@ -1531,6 +1668,28 @@ pub mod connection_manager {
         fn file_transfer_log(&self, action: &str, log: &str) {
             self.push_event("cm_file_transfer_log", &[(action, log)]);
         }
+
+        fn process_kill_request(&self, id: i32, pid: u32, name: &str) {
+            self.push_event(
+                "cm_process_kill_request",
+                &[
+                    ("id", json!(id)),
+                    ("pid", json!(pid)),
+                    ("name", json!(name)),
+                ],
+            );
+        }
+
+        fn sensitive_event(&self, id: i32, event: &str, confirm_secs: u64) {
+            self.push_event(
+                "cm_sensitive_event",
+                &[
+                    ("id", json!(id)),
+                    ("event", json!(event)),
+                    ("confirm_secs", json!(confirm_secs)),
+                ],
+            );
+        }
     }
 
     impl FlutterHandler {
@@ -1904,6 +2063,33 @@ fn session_send_touch_event(
     }
 }
 
+fn session_send_scroll_gesture(
+    session_id: SessionID,
+    v: &serde_json::Value,
+    alt: bool,
+    ctrl: bool,
+    shift: bool,
+    command: bool,
+) {
+    let (Some(phase), Some(v)) = (v.get("t").and_then(|t| t.as_str()), v.get("v")) else {
+        return;
+    };
+    let get = |k: &str| v.get(k).and_then(|x| x.as_i64()).unwrap_or(0) as i32;
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.send_scroll_gesture(
+            phase,
+            get("dx"),
+            get("dy"),
+            get("vx"),
+            get("vy"),
+            alt,
+            ctrl,
+            shift,
+            command,
+        );
+    }
+}
+
 pub fn session_send_pointer(session_id: SessionID, msg: String) {
     if let Ok(m) = serde_json::from_str::<HashMap<String, serde_json::Value>>(&msg) {
         let alt = m.get("alt").is_some();
@@ -1913,6 +2099,9 @@ pub fn session_send_pointer(session_id: SessionID, msg: String) {
         match (m.get("k"), m.get("v")) {
             (Some(k), Some(v)) => match k.as_str() {
                 Some("touch") => session_send_touch_event(session_id, v, alt, ctrl, shift, command),
+                Some("scroll") => {
+                    session_send_scroll_gesture(session_id, v, alt, ctrl, shift, command)
+                }
                 _ => {}
             },
             _ => {}
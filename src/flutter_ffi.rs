@@ -497,6 +497,12 @@ pub fn session_set_custom_fps(session_id: SessionID, fps: i32) {
     }
 }
 
+pub fn session_set_clipboard_line_ending(session_id: SessionID, value: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_clipboard_line_ending(value);
+    }
+}
+
 pub fn session_get_trackpad_speed(session_id: SessionID) -> Option<i32> {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         Some(session.get_trackpad_speed())
@@ -517,6 +523,43 @@ pub fn session_lock_screen(session_id: SessionID) {
     }
 }
 
+pub fn session_request_clipboard(session_id: SessionID) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.request_clipboard();
+    }
+}
+
+pub fn session_request_control_token(session_id: SessionID, request: bool) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.request_control_token(request);
+    }
+}
+
+pub fn session_set_client_visibility(session_id: SessionID, visible: bool, minimized: bool) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.send_client_visibility(visible, minimized);
+    }
+}
+
+pub fn session_set_client_focus(session_id: SessionID, focused: bool) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.send_client_focus(focused);
+    }
+}
+
+pub fn session_set_host_text_scale(session_id: SessionID, percent: u32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_host_text_scale(percent);
+    }
+}
+
+pub fn session_capture_window(session_id: SessionID, id: u64, title: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.capture_window(id, title);
+    }
+}
+
 pub fn session_ctrl_alt_del(session_id: SessionID) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.ctrl_alt_del();
@@ -622,7 +665,10 @@ pub fn session_open_terminal(session_id: SessionID, terminal_id: i32, rows: u32,
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.open_terminal(terminal_id, rows, cols);
     } else {
-        log::error!("[flutter_ffi] Session not found for session_id: {}", session_id);
+        log::error!(
+            "[flutter_ffi] Session not found for session_id: {}",
+            session_id
+        );
     }
 }
 
@@ -885,6 +931,13 @@ pub fn main_get_sound_inputs() -> Vec<String> {
     vec![String::from("")]
 }
 
+pub fn main_get_sound_outputs() -> Vec<String> {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    return get_sound_outputs();
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    vec![String::from("")]
+}
+
 pub fn main_get_login_device_info() -> SyncReturn<String> {
     SyncReturn(get_login_device_info_json())
 }
@@ -1490,6 +1543,16 @@ pub fn cm_handle_incoming_voice_call(id: i32, accept: bool) {
     crate::ui_cm_interface::handle_incoming_voice_call(id, accept);
 }
 
+pub fn cm_handle_process_kill(id: i32, pid: u32, approved: bool) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    crate::ui_cm_interface::handle_process_kill(id, pid, approved);
+}
+
+pub fn cm_confirm_sensitive_event(id: i32, event: String, confirmed: bool) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    crate::ui_cm_interface::confirm_sensitive_event(id, &event, confirmed);
+}
+
 pub fn cm_close_voice_call(id: i32) {
     crate::ui_cm_interface::close_voice_call(id);
 }
@@ -1517,6 +1580,29 @@ pub fn get_voice_call_input_device(_is_cm: bool) -> String {
     "".to_owned()
 }
 
+pub fn set_voice_call_output_device(_is_cm: bool, _device: String) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if _is_cm {
+        let _ = crate::ipc::set_config("voice-call-output", _device);
+    } else {
+        crate::audio_service::set_voice_call_output_device(Some(_device), true);
+    }
+}
+
+pub fn get_voice_call_output_device(_is_cm: bool) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if _is_cm {
+        match crate::ipc::get_config("voice-call-output") {
+            Ok(Some(device)) => device,
+            _ => "".to_owned(),
+        }
+    } else {
+        crate::audio_service::get_voice_call_output_device().unwrap_or_default()
+    }
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    "".to_owned()
+}
+
 pub fn main_get_last_remote_id() -> String {
     LocalConfig::get_remote_id()
 }
@@ -1696,6 +1782,73 @@ pub fn session_send_mouse(session_id: SessionID, msg: String) {
     }
 }
 
+pub fn session_power_action(session_id: SessionID, action: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.power_action(action);
+    }
+}
+
+pub fn session_confirm_power_action(session_id: SessionID, confirmed: bool) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.confirm_power_action(confirmed);
+    }
+}
+
+pub fn session_request_process_list(session_id: SessionID, page: u32, page_size: u32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.request_process_list(page, page_size);
+    }
+}
+
+pub fn session_request_diagnostic_capture(session_id: SessionID, display: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.request_diagnostic_capture(display);
+    }
+}
+
+pub fn session_send_annotation(
+    session_id: SessionID,
+    display: i32,
+    shape: i32,
+    points: Vec<i32>,
+    color: u32,
+    width: u32,
+    text: String,
+    font_size: u32,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.send_annotation(display, shape, points, color, width, text, font_size);
+    }
+}
+
+pub fn session_get_fingerprint(session_id: SessionID) -> SyncReturn<String> {
+    let res = if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.get_session_fingerprint()
+    } else {
+        crate::session_fingerprint::UNVERIFIED.to_owned()
+    };
+    SyncReturn(res)
+}
+
+// The token is a string, it doesn't fit in the number of js.
+pub fn session_kill_process(session_id: SessionID, pid: u32, token: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.kill_process(pid, token.parse().unwrap_or(0));
+    }
+}
+
+pub fn session_request_quick_actions(session_id: SessionID) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.request_quick_actions();
+    }
+}
+
+pub fn session_run_quick_action(session_id: SessionID, name: String, token: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.run_quick_action(name, token.parse().unwrap_or(0));
+    }
+}
+
 pub fn session_restart_remote_device(session_id: SessionID) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.restart_remote_device();
@@ -1744,6 +1897,18 @@ pub fn session_toggle_virtual_display(session_id: SessionID, index: i32, on: boo
     }
 }
 
+pub fn session_set_virtual_display_mode(
+    session_id: SessionID,
+    index: i32,
+    width: u32,
+    height: u32,
+    refresh_rate: u32,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_virtual_display_mode(index, width, height, refresh_rate);
+    }
+}
+
 pub fn session_printer_response(
     session_id: SessionID,
     id: i32,
@@ -2429,6 +2594,34 @@ pub fn main_clear_trusted_devices() {
     clear_trusted_devices()
 }
 
+pub fn main_get_elevation_grants() -> String {
+    get_elevation_grants()
+}
+
+pub fn main_revoke_elevation_grant(peer_id: String) {
+    revoke_elevation_grant(peer_id)
+}
+
+pub fn main_get_session_restores() -> String {
+    get_session_restores()
+}
+
+pub fn main_clear_session_restore(peer_id: String) {
+    clear_session_restore(peer_id)
+}
+
+pub fn main_get_audio_status() -> String {
+    get_audio_status()
+}
+
+pub fn main_get_audio_capture_exclusions() -> String {
+    audio_capture_exclusions(None)
+}
+
+pub fn main_set_audio_capture_exclusions(names: String) -> String {
+    audio_capture_exclusions(Some(names))
+}
+
 pub fn main_max_encrypt_len() -> SyncReturn<usize> {
     SyncReturn(max_encrypt_len())
 }
@@ -215,6 +215,17 @@ async fn start_hbbs_sync_async() {
                 if !conns.is_empty() {
                     v["conns"] = json!(conns);
                 }
+                let pending_auth = crate::server::pending_auth_count();
+                if pending_auth > 0 {
+                    v["pending_auth"] = json!(pending_auth);
+                }
+                #[cfg(windows)]
+                {
+                    let restarts = crate::server::server_watchdog::restarts();
+                    if restarts > 0 {
+                        v["server_restarts"] = json!(restarts);
+                    }
+                }
                 let modified_at = LocalConfig::get_option("strategy_timestamp").parse::<i64>().unwrap_or(0);
                 v["modified_at"] = json!(modified_at);
                 if let Ok(s) = crate::post_request(url.clone(), v.to_string(), "").await {
@@ -272,7 +283,13 @@ fn handle_config_options(config_options: HashMap<String, String>) {
             }
         })
         .count();
+    let old = Config::get_options();
     Config::set_options(options);
+    crate::server::option_journal::record_options(
+        crate::server::option_journal::SOURCE_STRATEGY,
+        &old,
+        &Config::get_options(),
+    );
 }
 
 #[allow(unused)]
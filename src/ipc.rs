@@ -52,6 +52,19 @@ pub enum FS {
         dir: String,
         include_hidden: bool,
     },
+    ReadDirPaged {
+        conn_id: i32,
+        id: i32,
+        dir: String,
+        include_hidden: bool,
+        page_size: usize,
+        filter: String,
+        token: String,
+    },
+    CancelReadDir {
+        conn_id: i32,
+        id: i32,
+    },
     RemoveDir {
         path: String,
         id: i32,
@@ -203,7 +216,10 @@ pub enum Data {
         restart: bool,
         recording: bool,
         block_input: bool,
+        annotation: bool,
+        quick_actions: bool,
         from_switch: bool,
+        fingerprint: String,
     },
     ChatMessage {
         text: String,
@@ -220,6 +236,13 @@ pub enum Data {
     Close,
     #[cfg(windows)]
     SAS,
+    // From the server to the service, the reply has the restarts of the server, see
+    // `server_watchdog`.
+    #[cfg(windows)]
+    ServerHeartbeat {
+        seq: u64,
+        restarts: u32,
+    },
     UserSid(Option<u32>),
     OnlineStatus(Option<(i64, bool)>),
     Config((String, Option<String>)),
@@ -254,9 +277,12 @@ pub enum Data {
     SwitchSidesBack,
     UrlLink(String),
     VoiceCallIncoming,
+    // The push-to-talk key of the host is held, the voice call is heard while it is.
+    PushToTalk(bool),
     StartVoiceCall,
     VoiceCallResponse(bool),
     CloseVoiceCall(String),
+    VirtualMic(bool),
     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     Plugin(Plugin),
@@ -265,6 +291,11 @@ pub enum Data {
     FileTransferLog((String, String)),
     #[cfg(windows)]
     ControlledSessionCount(usize),
+    #[cfg(windows)]
+    PreviewSessionCount(usize),
+    // The count of the unencrypted sessions, and the fingerprint of the key.
+    #[cfg(windows)]
+    SessionEncryption((usize, String)),
     CmErr(String),
     CheckHwcodec,
     #[cfg(feature = "flutter")]
@@ -272,10 +303,70 @@ pub enum Data {
     // Although the key is not neccessary, it is used to avoid hardcoding the key.
     WaylandScreencastRestoreToken((String, String)),
     HwCodecConfig(Option<String>),
+    // The status of the check process and the available codecs in json.
+    HwCodecStatus(Option<String>),
+    RecheckHwcodec,
+    // Simulate a bad network on the send path of a connection, all zeros to clear it.
+    SetNetworkSim {
+        conn_id: i32,
+        rtt_ms: u32,
+        jitter_ms: u32,
+        loss_pct: u32,
+        kbps: u32,
+    },
+    // The inhibitors held for the active sessions, empty if there's none.
+    #[cfg(target_os = "linux")]
+    Inhibitors(Option<String>),
+    // The certificate fingerprint of the TLS direct access port, empty if it is not TLS.
+    DirectTlsFingerprint(Option<String>),
+    // The capture environment of the host in json, see `capture_env`.
+    CaptureEnv(Option<String>),
+    // The connections waiting for the login in json, see `PendingAuthID`.
+    PendingAuthConns(Option<String>),
+    // The restarts of the server by the Windows service in json, see `server_watchdog`.
+    ServerWatchdog(Option<String>),
+    // The listens of the direct connections, the retried ones and the failed ones, see `relisten`.
+    RelistenStats(Option<(usize, usize, usize)>),
+    // The last n option changes in json, see `option_journal`.
+    OptionJournal((usize, Option<String>)),
+    // Terminate all the sessions by the user of the host, the reply is whether all of them are
+    // closed in time, see `panic_button`.
+    TerminateSessions(Option<bool>),
+    // Move a relayed remote connection to another relay, the reply is whether the connection is
+    // found. It's also sent to the connection to start the migration.
+    MigrateRelay {
+        conn_id: i32,
+        relay_server: String,
+        found: Option<bool>,
+    },
+    // Enumerate the audio devices again, the reply is whether the audio service is restarted.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    RefreshAudioDevices(Option<bool>),
+    // Close the connections with the code of the reason before the server is stopped, the reply
+    // is whether all of them exited in time.
+    CloseConnections((i32, Option<bool>)),
+    // Set the host clipboard to the text, and send it to the peers if the flag is set, the reply is
+    // the error, empty on success. See `clipboard_service::set_host_clipboard`.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    SetHostClipboard((String, bool, Option<String>)),
     RemoveTrustedDevices(Vec<Bytes>),
     ClearTrustedDevices,
     #[cfg(all(target_os = "windows", feature = "flutter"))]
-    PrinterData(Vec<u8>),
+    PrinterJob(u64),
+    #[cfg(all(target_os = "windows", feature = "flutter"))]
+    PrinterQueue(Option<Vec<crate::server::printer_queue::JobInfo>>),
+    // (job id, None to remove or Some(position) to move)
+    #[cfg(all(target_os = "windows", feature = "flutter"))]
+    PrinterQueueAction((u64, Option<usize>)),
+    FeatureAllowlistChanged,
+    // (path, reason) of the received files rejected by the scanner
+    FileScanBlocked(Vec<(String, String)>),
+    ControlTokenChanged,
+    SessionReports(Option<Vec<String>>),
+    // Get the access policies in json with None, set them with Some.
+    // The set is replied with `AccessPolicies(None)`, or `AccessPoliciesInvalid` with the error.
+    AccessPolicies(Option<String>),
+    AccessPoliciesInvalid(String),
     InstallOption(Option<(String, String)>),
     #[cfg(all(
         feature = "flutter",
@@ -287,6 +378,48 @@ pub enum Data {
     #[cfg(target_os = "windows")]
     PortForwardSessionCount(Option<usize>),
     SocksWs(Option<Box<(Option<config::Socks5Server>, String)>>),
+    #[cfg(target_os = "windows")]
+    VirtualDisplayStatus(Option<Box<crate::virtual_display_manager::VirtualDisplayStatus>>),
+    // (peer id, remaining seconds)
+    #[cfg(target_os = "windows")]
+    ElevationGrants(Option<Vec<(String, u64)>>),
+    AudioStatus(Option<crate::audio_service::AudioStatus>),
+    // Get with None, set the names with Some, the reply is the current value.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    AudioCaptureExclusions(Option<crate::server::audio_exclusion::CaptureExclusions>),
+    // Empty to revoke all.
+    #[cfg(target_os = "windows")]
+    RevokeElevationGrant(String),
+    // (pid, name), the kill of a process by the peer waiting for the approval in cm.
+    ProcessKillRequest((u32, String)),
+    // (pid, approved)
+    ProcessKillResponse((u32, bool)),
+    // To the connection manager, which draws the annotations of the peer, see `annotation`.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    Annotation(crate::annotation::Command),
+    // From the connection manager, there's no overlay on the desktop to draw the annotations.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    AnnotationUnsupported,
+    // To the connection manager, which runs the quick action as the user of the session, None to
+    // kill the running one, see `quick_actions`.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    QuickAction(Option<crate::server::quick_actions::QuickAction>),
+    // From the connection manager, the output of the running quick action.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    QuickActionOutput(crate::server::quick_actions::Output),
+    // (event, seconds to confirm it, 0 if it needs no confirmation), a sensitive event of the
+    // peer for the connection manager, see `sensitive_events`.
+    SensitiveEvent((crate::server::sensitive_events::Event, u64)),
+    // (event, confirmed)
+    SensitiveEventConfirm((crate::server::sensitive_events::Event, bool)),
+    // The code of the login error or the close reason sent to the peer, see `error_code`.
+    ErrorCode(i32),
+    // (peer id, remaining seconds), see `session_restore`.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    SessionRestores(Option<Vec<(String, u64)>>),
+    // Empty to clear all.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    ClearSessionRestore(String),
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -357,9 +490,18 @@ pub struct CheckIfRestart {
     stop_service: String,
     rendezvous_servers: Vec<String>,
     audio_input: String,
+    audio_min_bitrate: String,
+    audio_cbr: String,
+    #[cfg(target_os = "macos")]
+    audio_system_only: String,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    audio_capture_exclusions: String,
     voice_call_input: String,
+    voice_call_output: String,
     ws: String,
     api_server: String,
+    feature_allowlists: Vec<String>,
+    multi_control_policy: String,
 }
 
 impl CheckIfRestart {
@@ -368,9 +510,22 @@ impl CheckIfRestart {
             stop_service: Config::get_option("stop-service"),
             rendezvous_servers: Config::get_rendezvous_servers(),
             audio_input: Config::get_option("audio-input"),
+            audio_min_bitrate: Config::get_option(crate::audio_service::OPTION_AUDIO_MIN_BITRATE),
+            audio_cbr: Config::get_option(crate::audio_service::OPTION_AUDIO_CBR),
+            #[cfg(target_os = "macos")]
+            audio_system_only: Config::get_option(crate::audio_service::OPTION_AUDIO_SYSTEM_ONLY),
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            audio_capture_exclusions: Config::get_option(
+                crate::server::audio_exclusion::OPTION_AUDIO_CAPTURE_EXCLUSIONS,
+            ),
             voice_call_input: Config::get_option("voice-call-input"),
+            voice_call_output: Config::get_option("voice-call-output"),
             ws: Config::get_option(OPTION_ALLOW_WEBSOCKET),
             api_server: Config::get_option("api-server"),
+            feature_allowlists: crate::server::get_feature_allowlists(),
+            multi_control_policy: Config::get_option(
+                crate::server::input_control::OPTION_MULTI_CONTROL_POLICY,
+            ),
         }
     }
 }
@@ -383,7 +538,23 @@ impl Drop for CheckIfRestart {
         {
             RendezvousMediator::restart();
         }
-        if self.audio_input != Config::get_option("audio-input") {
+        if self.audio_input != Config::get_option("audio-input")
+            || self.audio_min_bitrate
+                != Config::get_option(crate::audio_service::OPTION_AUDIO_MIN_BITRATE)
+            || self.audio_cbr != Config::get_option(crate::audio_service::OPTION_AUDIO_CBR)
+        {
+            crate::audio_service::restart();
+        }
+        #[cfg(target_os = "macos")]
+        if self.audio_system_only
+            != Config::get_option(crate::audio_service::OPTION_AUDIO_SYSTEM_ONLY)
+        {
+            crate::audio_service::restart();
+        }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if self.audio_capture_exclusions
+            != Config::get_option(crate::server::audio_exclusion::OPTION_AUDIO_CAPTURE_EXCLUSIONS)
+        {
             crate::audio_service::restart();
         }
         if self.voice_call_input != Config::get_option("voice-call-input") {
@@ -392,6 +563,20 @@ impl Drop for CheckIfRestart {
                 true,
             )
         }
+        if self.voice_call_output != Config::get_option("voice-call-output") {
+            crate::audio_service::set_voice_call_output_device(
+                Some(Config::get_option("voice-call-output")),
+                true,
+            )
+        }
+        if self.feature_allowlists != crate::server::get_feature_allowlists() {
+            crate::server::on_feature_allowlists_changed();
+        }
+        if self.multi_control_policy
+            != Config::get_option(crate::server::input_control::OPTION_MULTI_CONTROL_POLICY)
+        {
+            crate::server::input_control::reload_policy();
+        }
     }
 }
 
@@ -406,6 +591,38 @@ async fn handle(data: Data, stream: &mut Connection) {
             );
             allow_err!(stream.send(&Data::SystemInfo(Some(info))).await);
         }
+        #[cfg(all(target_os = "windows", feature = "flutter"))]
+        Data::PrinterQueue(_) => {
+            let jobs = crate::server::printer_queue::list();
+            allow_err!(stream.send(&Data::PrinterQueue(Some(jobs))).await);
+        }
+        #[cfg(all(target_os = "windows", feature = "flutter"))]
+        Data::PrinterQueueAction((id, pos)) => {
+            match pos {
+                Some(pos) => crate::server::printer_queue::move_to(id, pos),
+                None => crate::server::printer_queue::remove(id),
+            };
+        }
+        Data::SessionReports(_) => {
+            let reports = crate::server::session_report::get_reports();
+            allow_err!(stream.send(&Data::SessionReports(Some(reports))).await);
+        }
+        Data::AccessPolicies(value) => match value {
+            None => {
+                let v = crate::server::access_policy::get();
+                allow_err!(stream.send(&Data::AccessPolicies(Some(v))).await);
+            }
+            Some(v) => match crate::server::access_policy::set(&v) {
+                Ok(()) => allow_err!(stream.send(&Data::AccessPolicies(None)).await),
+                Err(e) => {
+                    allow_err!(
+                        stream
+                            .send(&Data::AccessPoliciesInvalid(e.to_string()))
+                            .await
+                    )
+                }
+            },
+        },
         Data::ClickTime(_) => {
             let t = crate::server::CLICK_TIME.load(Ordering::SeqCst);
             allow_err!(stream.send(&Data::ClickTime(t)).await);
@@ -421,6 +638,9 @@ async fn handle(data: Data, stream: &mut Connection) {
                 #[cfg(not(target_os = "android"))]
                 crate::server::input_service::fix_key_down_timeout_at_exit();
                 if is_server() {
+                    // No-op if `CloseConnections` is received before.
+                    crate::server::close_host_connections(crate::server::CLOSE_REASON_STOP_SERVICE)
+                        .await;
                     let _ = privacy_mode::turn_off_privacy(0, Some(PrivacyModeState::OffByPeer));
                 }
                 #[cfg(any(target_os = "macos", target_os = "linux"))]
@@ -538,6 +758,8 @@ async fn handle(data: Data, stream: &mut Connection) {
                     };
                 } else if name == "voice-call-input" {
                     value = crate::audio_service::get_voice_call_input_device();
+                } else if name == "voice-call-output" {
+                    value = crate::audio_service::get_voice_call_output_device();
                 } else if name == "unlock-pin" {
                     value = Some(Config::get_unlock_pin());
                 } else if name == "trusted-devices" {
@@ -548,6 +770,13 @@ async fn handle(data: Data, stream: &mut Connection) {
                 allow_err!(stream.send(&Data::Config((name, value))).await);
             }
             Some(value) => {
+                let old = match name.as_str() {
+                    "id" => Some(Config::get_id()),
+                    "permanent-password" => Some(Config::get_permanent_password()),
+                    "salt" => Some(Config::get_salt()),
+                    "unlock-pin" => Some(Config::get_unlock_pin()),
+                    _ => None,
+                };
                 if name == "id" {
                     Config::set_key_confirmed(false);
                     Config::set_id(&value);
@@ -559,12 +788,22 @@ async fn handle(data: Data, stream: &mut Connection) {
                     Config::set_salt(&value);
                 } else if name == "voice-call-input" {
                     crate::audio_service::set_voice_call_input_device(Some(value), true);
+                } else if name == "voice-call-output" {
+                    crate::audio_service::set_voice_call_output_device(Some(value), true);
                 } else if name == "unlock-pin" {
                     Config::set_unlock_pin(&value);
                 } else {
                     return;
                 }
                 log::info!("{} updated", name);
+                if let Some(old) = old {
+                    crate::server::option_journal::record(
+                        crate::server::option_journal::SOURCE_IPC,
+                        &name,
+                        &old,
+                        &value,
+                    );
+                }
             }
         },
         Data::Options(value) => match value {
@@ -578,7 +817,13 @@ async fn handle(data: Data, stream: &mut Connection) {
                 if let Some(v) = value.get("privacy-mode-impl-key") {
                     crate::privacy_mode::switch(v);
                 }
+                let old = Config::get_options();
                 Config::set_options(value);
+                crate::server::option_journal::record_options(
+                    crate::server::option_journal::SOURCE_IPC,
+                    &old,
+                    &Config::get_options(),
+                );
                 allow_err!(stream.send(&Data::Options(None)).await);
             }
         },
@@ -589,8 +834,12 @@ async fn handle(data: Data, stream: &mut Connection) {
         Data::SyncConfig(Some(configs)) => {
             let (config, config2) = *configs;
             let _chk = CheckIfRestart::new();
+            let (old_id, old) = (Config::get_id(), Config::get_options());
             Config::set(config);
             Config2::set(config2);
+            let source = crate::server::option_journal::SOURCE_USER;
+            crate::server::option_journal::record(source, "id", &old_id, &Config::get_id());
+            crate::server::option_journal::record_options(source, &old, &Config::get_options());
             allow_err!(stream.send(&Data::SyncConfig(None)).await);
         }
         Data::SyncConfig(None) => {
@@ -637,6 +886,31 @@ async fn handle(data: Data, stream: &mut Connection) {
                     .await
             );
         }
+        #[cfg(windows)]
+        Data::PreviewSessionCount(_) => {
+            let n = crate::server::AUTHED_CONNS
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|x| x.conn_type == crate::server::AuthConnType::Preview)
+                .count();
+            allow_err!(stream.send(&Data::PreviewSessionCount(n)).await);
+        }
+        #[cfg(windows)]
+        Data::SessionEncryption(_) => {
+            let n = crate::server::AUTHED_CONNS
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|x| !x.encrypted)
+                .count();
+            let fingerprint = crate::common::pk_to_fingerprint(Config::get_key_pair().1);
+            allow_err!(
+                stream
+                    .send(&Data::SessionEncryption((n, fingerprint)))
+                    .await
+            );
+        }
         #[cfg(all(
             feature = "flutter",
             not(any(target_os = "android", target_os = "ios"))
@@ -656,6 +930,126 @@ async fn handle(data: Data, stream: &mut Connection) {
         }
         #[cfg(feature = "hwcodec")]
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        Data::RecheckHwcodec => {
+            scrap::hwcodec::force_recheck();
+        }
+        #[cfg(feature = "hwcodec")]
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        Data::HwCodecStatus(_) => {
+            let status = scrap::hwcodec::check_status();
+            allow_err!(stream.send(&Data::HwCodecStatus(Some(status))).await);
+        }
+        Data::SetNetworkSim {
+            conn_id,
+            rtt_ms,
+            jitter_ms,
+            loss_pct,
+            kbps,
+        } => {
+            crate::server::network_sim::set(
+                conn_id,
+                crate::server::network_sim::NetworkSim {
+                    rtt_ms,
+                    jitter_ms,
+                    loss_pct: loss_pct.min(100),
+                    kbps,
+                },
+            );
+        }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        Data::SetHostClipboard((content, send_to_peers, _)) => {
+            let err = crate::server::clipboard_service::set_host_clipboard(content, send_to_peers)
+                .err()
+                .map(|e| e.to_string())
+                .unwrap_or_default();
+            allow_err!(
+                stream
+                    .send(&Data::SetHostClipboard((
+                        "".to_owned(),
+                        send_to_peers,
+                        Some(err)
+                    )))
+                    .await
+            );
+        }
+        Data::CloseConnections((reason, _)) => {
+            let closed = crate::server::close_host_connections(
+                crate::error_code::ErrorCode::from_code(reason),
+            )
+            .await;
+            allow_err!(
+                stream
+                    .send(&Data::CloseConnections((reason, Some(closed))))
+                    .await
+            );
+        }
+        #[cfg(target_os = "linux")]
+        Data::Inhibitors(_) => {
+            let inhibitors = crate::server::dbus::get_active_inhibitors();
+            allow_err!(stream.send(&Data::Inhibitors(Some(inhibitors))).await);
+        }
+        Data::DirectTlsFingerprint(_) => {
+            let fingerprint = crate::tls_tunnel::get_fingerprint().unwrap_or_default();
+            allow_err!(
+                stream
+                    .send(&Data::DirectTlsFingerprint(Some(fingerprint)))
+                    .await
+            );
+        }
+        Data::CaptureEnv(_) => {
+            let status = crate::server::capture_env::get_status();
+            allow_err!(stream.send(&Data::CaptureEnv(Some(status))).await);
+        }
+        Data::PendingAuthConns(_) => {
+            let status = crate::server::pending_auth_status();
+            allow_err!(stream.send(&Data::PendingAuthConns(Some(status))).await);
+        }
+        Data::OptionJournal((n, _)) => {
+            let changes = crate::server::option_journal::get_last(n);
+            allow_err!(stream.send(&Data::OptionJournal((n, Some(changes)))).await);
+        }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        Data::TerminateSessions(_) => {
+            use crate::server::panic_button;
+            let closed = panic_button::terminate(panic_button::SOURCE_IPC).await;
+            allow_err!(stream.send(&Data::TerminateSessions(Some(closed))).await);
+        }
+        #[cfg(windows)]
+        Data::ServerWatchdog(_) => {
+            let status = crate::server::server_watchdog::get_status();
+            allow_err!(stream.send(&Data::ServerWatchdog(Some(status))).await);
+        }
+        Data::RelistenStats(_) => {
+            let stats = crate::server::relisten_stats();
+            allow_err!(stream.send(&Data::RelistenStats(Some(stats))).await);
+        }
+        Data::MigrateRelay {
+            conn_id,
+            relay_server,
+            ..
+        } => {
+            let found = crate::server::migrate_relay(conn_id, relay_server.clone());
+            allow_err!(
+                stream
+                    .send(&Data::MigrateRelay {
+                        conn_id,
+                        relay_server,
+                        found: Some(found),
+                    })
+                    .await
+            );
+        }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        Data::RefreshAudioDevices(_) => {
+            let restarted = crate::server::audio_device_watch::refresh();
+            allow_err!(
+                stream
+                    .send(&Data::RefreshAudioDevices(Some(restarted)))
+                    .await
+            );
+        }
+        #[cfg(feature = "hwcodec")]
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
         Data::HwCodecConfig(c) => {
             match c {
                 None => {
@@ -749,6 +1143,70 @@ async fn handle(data: Data, stream: &mut Connection) {
                 // Port forward session count is only a get value.
             }
         },
+        #[cfg(target_os = "windows")]
+        Data::VirtualDisplayStatus(s) => match s {
+            None => {
+                let status = crate::virtual_display_manager::get_status();
+                allow_err!(
+                    stream
+                        .send(&Data::VirtualDisplayStatus(Some(Box::new(status))))
+                        .await
+                );
+            }
+            _ => {
+                // Virtual display status is only a get value.
+            }
+        },
+        #[cfg(target_os = "windows")]
+        Data::ElevationGrants(grants) => match grants {
+            None => {
+                let grants = crate::portable_service::client::get_grants();
+                allow_err!(stream.send(&Data::ElevationGrants(Some(grants))).await);
+            }
+            _ => {
+                // Elevation grants are only a get value.
+            }
+        },
+        #[cfg(target_os = "windows")]
+        Data::RevokeElevationGrant(peer_id) => {
+            crate::portable_service::client::revoke_grants(&peer_id);
+        }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        Data::SessionRestores(records) => match records {
+            None => {
+                let records = crate::server::session_restore::list();
+                allow_err!(stream.send(&Data::SessionRestores(Some(records))).await);
+            }
+            _ => {
+                // Session restore records are only a get value.
+            }
+        },
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        Data::ClearSessionRestore(peer_id) => {
+            crate::server::session_restore::clear(&peer_id);
+        }
+        Data::AudioStatus(status) => match status {
+            None => {
+                let status = crate::audio_service::status();
+                allow_err!(stream.send(&Data::AudioStatus(Some(status))).await);
+            }
+            _ => {
+                // Audio status is only a get value.
+            }
+        },
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        Data::AudioCaptureExclusions(value) => {
+            if let Some(value) = value {
+                let _chk = CheckIfRestart::new();
+                crate::server::audio_exclusion::set(value.names);
+            }
+            let value = crate::server::audio_exclusion::get();
+            allow_err!(
+                stream
+                    .send(&Data::AudioCaptureExclusions(Some(value)))
+                    .await
+            );
+        }
         _ => {}
     }
 }
@@ -762,7 +1220,7 @@ pub async fn connect(ms_timeout: u64, postfix: &str) -> ResultType<ConnectionTmp
 #[cfg(target_os = "linux")]
 #[tokio::main(flavor = "current_thread")]
 pub async fn start_pa() {
-    use crate::audio_service::AUDIO_DATA_SIZE_U8;
+    use crate::{audio_service::AUDIO_DATA_SIZE_U8, server::audio_exclusion};
 
     match new_listener("_pa").await {
         Ok(mut incoming) => {
@@ -780,6 +1238,24 @@ pub async fn start_pa() {
                             if !device.is_empty() {
                                 device = crate::platform::linux::get_pa_source_name(&device);
                             }
+                            // Dropped after the record stream, which must not outlive its sink.
+                            let mut _capture = None;
+                            let exclusions = audio_exclusion::get_names();
+                            if device.is_empty() && !exclusions.is_empty() {
+                                if audio_exclusion::is_supported() {
+                                    match audio_exclusion::Capture::new(exclusions) {
+                                        Ok(capture) => {
+                                            device = capture.source();
+                                            _capture = Some(capture);
+                                        }
+                                        Err(err) => {
+                                            log::error!("Failed to exclude audio: {}", err);
+                                        }
+                                    }
+                                } else {
+                                    log::warn!("Audio capture exclusion is not supported");
+                                }
+                            }
                             if device.is_empty() {
                                 device = crate::platform::linux::get_pa_monitor();
                             }
@@ -1129,6 +1605,59 @@ pub async fn get_options() -> HashMap<String, String> {
     get_options_async().await
 }
 
+#[cfg(all(target_os = "windows", feature = "flutter"))]
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_printer_queue() -> ResultType<Vec<crate::server::printer_queue::JobInfo>> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::PrinterQueue(None)).await?;
+    if let Some(Data::PrinterQueue(Some(jobs))) = c.next_timeout(1000).await? {
+        return Ok(jobs);
+    }
+    Ok(vec![])
+}
+
+#[cfg(all(target_os = "windows", feature = "flutter"))]
+#[tokio::main(flavor = "current_thread")]
+pub async fn printer_queue_action(id: u64, pos: Option<usize>) -> ResultType<()> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::PrinterQueueAction((id, pos))).await?;
+    Ok(())
+}
+
+/// The recent session reports of the server process in json.
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_session_reports() -> ResultType<Vec<String>> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::SessionReports(None)).await?;
+    if let Some(Data::SessionReports(Some(reports))) = c.next_timeout(1000).await? {
+        return Ok(reports);
+    }
+    Ok(vec![])
+}
+
+/// The access policies of the server process in json, without the plain passwords.
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_access_policies() -> ResultType<String> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::AccessPolicies(None)).await?;
+    if let Some(Data::AccessPolicies(Some(v))) = c.next_timeout(1000).await? {
+        return Ok(v);
+    }
+    bail!("No access policies received")
+}
+
+/// Set the access policies in json, they are rejected if they are inconsistent.
+#[tokio::main(flavor = "current_thread")]
+pub async fn set_access_policies(policies: String) -> ResultType<()> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::AccessPolicies(Some(policies))).await?;
+    match c.next_timeout(1000).await? {
+        Some(Data::AccessPolicies(None)) => Ok(()),
+        Some(Data::AccessPoliciesInvalid(e)) => bail!(e),
+        _ => bail!("No reply of setting the access policies"),
+    }
+}
+
 pub async fn get_option_async(key: &str) -> String {
     if let Some(v) = get_options_async().await.get(key) {
         v.clone()
@@ -1283,6 +1812,205 @@ pub async fn notify_server_to_check_hwcodec() -> ResultType<()> {
     Ok(())
 }
 
+#[tokio::main(flavor = "current_thread")]
+pub async fn notify_server_to_recheck_hwcodec() -> ResultType<()> {
+    connect(1_000, "")
+        .await?
+        .send(&Data::RecheckHwcodec)
+        .await?;
+    Ok(())
+}
+
+/// Close the connections of the server with the reason and wait for them to exit, before the
+/// server process is stopped.
+pub async fn close_connections_async(
+    reason: crate::error_code::ErrorCode,
+) -> ResultType<bool> {
+    let mut c = connect(1_000, "").await?;
+    c.send(&Data::CloseConnections((reason.code(), None))).await?;
+    // Longer than the wait of the server.
+    if let Some(Data::CloseConnections((_, Some(closed)))) = c.next_timeout(3_000).await? {
+        return Ok(closed);
+    }
+    bail!("No reply of closing the connections");
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+#[tokio::main(flavor = "current_thread")]
+pub async fn set_host_clipboard(content: String, send_to_peers: bool) -> ResultType<()> {
+    let mut c = connect(1_000, "").await?;
+    c.send(&Data::SetHostClipboard((content, send_to_peers, None))).await?;
+    match c.next_timeout(1_000).await? {
+        Some(Data::SetHostClipboard((_, _, Some(err)))) if err.is_empty() => Ok(()),
+        Some(Data::SetHostClipboard((_, _, Some(err)))) => bail!(err),
+        _ => bail!("No reply of setting the clipboard"),
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn close_connections(reason: crate::error_code::ErrorCode) -> ResultType<bool> {
+    close_connections_async(reason).await
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_hwcodec_status() -> ResultType<String> {
+    let mut c = connect(1_000, "").await?;
+    c.send(&Data::HwCodecStatus(None)).await?;
+    if let Some(Data::HwCodecStatus(Some(status))) = c.next_timeout(1_000).await? {
+        return Ok(status);
+    }
+    bail!("No hwcodec status");
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn set_network_sim(
+    conn_id: i32,
+    rtt_ms: u32,
+    jitter_ms: u32,
+    loss_pct: u32,
+    kbps: u32,
+) -> ResultType<()> {
+    connect(1_000, "")
+        .await?
+        .send(&Data::SetNetworkSim {
+            conn_id,
+            rtt_ms,
+            jitter_ms,
+            loss_pct,
+            kbps,
+        })
+        .await?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_inhibitors() -> ResultType<String> {
+    let mut c = connect(1_000, "").await?;
+    c.send(&Data::Inhibitors(None)).await?;
+    if let Some(Data::Inhibitors(Some(inhibitors))) = c.next_timeout(1_000).await? {
+        return Ok(inhibitors);
+    }
+    bail!("No inhibitors");
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_direct_tls_fingerprint() -> ResultType<String> {
+    let mut c = connect(1_000, "").await?;
+    c.send(&Data::DirectTlsFingerprint(None)).await?;
+    if let Some(Data::DirectTlsFingerprint(Some(fingerprint))) = c.next_timeout(1_000).await? {
+        return Ok(fingerprint);
+    }
+    bail!("No direct access TLS fingerprint");
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_capture_env() -> ResultType<String> {
+    let mut c = connect(1_000, "").await?;
+    c.send(&Data::CaptureEnv(None)).await?;
+    if let Some(Data::CaptureEnv(Some(status))) = c.next_timeout(1_000).await? {
+        return Ok(status);
+    }
+    bail!("No capture environment");
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_pending_auth_conns() -> ResultType<String> {
+    let mut c = connect(1_000, "").await?;
+    c.send(&Data::PendingAuthConns(None)).await?;
+    if let Some(Data::PendingAuthConns(Some(status))) = c.next_timeout(1_000).await? {
+        return Ok(status);
+    }
+    bail!("No pending connections");
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_server_watchdog() -> ResultType<String> {
+    let mut c = connect(1_000, "").await?;
+    c.send(&Data::ServerWatchdog(None)).await?;
+    if let Some(Data::ServerWatchdog(Some(status))) = c.next_timeout(1_000).await? {
+        return Ok(status);
+    }
+    bail!("No server watchdog");
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_relisten_stats() -> ResultType<(usize, usize, usize)> {
+    let mut c = connect(1_000, "").await?;
+    c.send(&Data::RelistenStats(None)).await?;
+    if let Some(Data::RelistenStats(Some(stats))) = c.next_timeout(1_000).await? {
+        return Ok(stats);
+    }
+    bail!("No relisten stats");
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_option_journal(n: usize) -> ResultType<String> {
+    let mut c = connect(1_000, "").await?;
+    c.send(&Data::OptionJournal((n, None))).await?;
+    if let Some(Data::OptionJournal((_, Some(changes)))) = c.next_timeout(1_000).await? {
+        return Ok(changes);
+    }
+    bail!("No option journal");
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn terminate_sessions() -> ResultType<bool> {
+    let mut c = connect(1_000, "").await?;
+    c.send(&Data::TerminateSessions(None)).await?;
+    // Longer than the wait of closing the connections.
+    if let Some(Data::TerminateSessions(Some(closed))) = c.next_timeout(3_000).await? {
+        return Ok(closed);
+    }
+    bail!("No reply of terminating the sessions");
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn migrate_relay(conn_id: i32, relay_server: String) -> ResultType<bool> {
+    let mut c = connect(1_000, "").await?;
+    c.send(&Data::MigrateRelay {
+        conn_id,
+        relay_server,
+        found: None,
+    })
+    .await?;
+    if let Some(Data::MigrateRelay {
+        found: Some(found), ..
+    }) = c.next_timeout(1_000).await?
+    {
+        return Ok(found);
+    }
+    bail!("No reply of the relay migration");
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+#[tokio::main(flavor = "current_thread")]
+pub async fn refresh_audio_devices() -> ResultType<bool> {
+    let mut c = connect(1_000, "").await?;
+    c.send(&Data::RefreshAudioDevices(None)).await?;
+    if let Some(Data::RefreshAudioDevices(Some(restarted))) = c.next_timeout(1_000).await? {
+        return Ok(restarted);
+    }
+    bail!("No reply of refreshing the audio devices");
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+#[tokio::main(flavor = "current_thread")]
+pub async fn audio_capture_exclusions(
+    names: Option<Vec<String>>,
+) -> ResultType<crate::server::audio_exclusion::CaptureExclusions> {
+    let mut c = connect(1_000, "").await?;
+    let value = names.map(|names| crate::server::audio_exclusion::CaptureExclusions {
+        supported: false,
+        names,
+    });
+    c.send(&Data::AudioCaptureExclusions(value)).await?;
+    if let Some(Data::AudioCaptureExclusions(Some(value))) = c.next_timeout(1_000).await? {
+        return Ok(value);
+    }
+    bail!("No reply of the audio capture exclusions");
+}
+
 #[cfg(target_os = "windows")]
 pub async fn get_port_forward_session_count(ms_timeout: u64) -> ResultType<usize> {
     let mut c = connect(ms_timeout, "").await?;
@@ -1293,6 +2021,54 @@ pub async fn get_port_forward_session_count(ms_timeout: u64) -> ResultType<usize
     bail!("Failed to get port forward session count");
 }
 
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_audio_status() -> ResultType<crate::audio_service::AudioStatus> {
+    let ms_timeout = 1_000;
+    let mut c = connect(ms_timeout, "").await?;
+    c.send(&Data::AudioStatus(None)).await?;
+    if let Some(Data::AudioStatus(Some(status))) = c.next_timeout(ms_timeout).await? {
+        return Ok(status);
+    }
+    bail!("Failed to get audio status");
+}
+
+#[cfg(target_os = "windows")]
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_elevation_grants() -> ResultType<Vec<(String, u64)>> {
+    let ms_timeout = 1_000;
+    let mut c = connect(ms_timeout, "").await?;
+    c.send(&Data::ElevationGrants(None)).await?;
+    if let Some(Data::ElevationGrants(Some(grants))) = c.next_timeout(ms_timeout).await? {
+        return Ok(grants);
+    }
+    bail!("Failed to get elevation grants");
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_session_restores() -> ResultType<Vec<(String, u64)>> {
+    let ms_timeout = 1_000;
+    let mut c = connect(ms_timeout, "").await?;
+    c.send(&Data::SessionRestores(None)).await?;
+    if let Some(Data::SessionRestores(Some(records))) = c.next_timeout(ms_timeout).await? {
+        return Ok(records);
+    }
+    bail!("Failed to get session restore records");
+}
+
+#[cfg(target_os = "windows")]
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_virtual_display_status(
+) -> ResultType<crate::virtual_display_manager::VirtualDisplayStatus> {
+    let ms_timeout = 1_000;
+    let mut c = connect(ms_timeout, "").await?;
+    c.send(&Data::VirtualDisplayStatus(None)).await?;
+    if let Some(Data::VirtualDisplayStatus(Some(status))) = c.next_timeout(ms_timeout).await? {
+        return Ok(*status);
+    }
+    bail!("Failed to get virtual display status");
+}
+
 #[cfg(feature = "hwcodec")]
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 #[tokio::main(flavor = "current_thread")]
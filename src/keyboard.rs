@@ -187,6 +187,25 @@ pub mod client {
         }
     }
 
+    /// The modifiers held on this side, the peer releases the others when the window gains focus.
+    pub fn get_pressed_modifiers() -> Vec<ControlKey> {
+        let modifiers_lock = MODIFIERS_STATE.lock().unwrap();
+        [
+            (Key::ShiftLeft, ControlKey::Shift),
+            (Key::ShiftRight, ControlKey::RShift),
+            (Key::ControlLeft, ControlKey::Control),
+            (Key::ControlRight, ControlKey::RControl),
+            (Key::Alt, ControlKey::Alt),
+            (Key::AltGr, ControlKey::RAlt),
+            (Key::MetaLeft, ControlKey::Meta),
+            (Key::MetaRight, ControlKey::RWin),
+        ]
+        .into_iter()
+        .filter(|(key, _)| modifiers_lock.get(key).cloned().unwrap_or(false))
+        .map(|(_, ck)| ck)
+        .collect()
+    }
+
     #[cfg(target_os = "android")]
     pub fn map_key_to_control_key(key: &rdev::Key) -> Option<ControlKey> {
         match key {
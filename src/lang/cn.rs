@@ -703,5 +703,22 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("Enable terminal", ""),
         ("New tab", ""),
         ("Keep terminal sessions on disconnect", ""),
+        ("capture-recovery-title", "屏幕捕获"),
+        ("capture-recovering-tip", "远端屏幕捕获失败，正在恢复..."),
+        ("capture-recovered-tip", "远端屏幕捕获已恢复。"),
+        ("reauth-required-tip", "对方要求再次输入密码后才能控制。"),
+        ("video-services-limit-tip", "对方限制了同时采集的显示器和摄像头数量，请先关闭其他的显示器或摄像头。"),
+        ("{} unencrypted sessions", "{} 个未加密会话"),
+        ("encryption-required-tip", "对方只接受加密连接，但此连接无法加密。"),
+        ("Send clipboard", "发送剪贴板"),
+        ("Request control", "请求控制"),
+        ("Release control", "释放控制"),
+        ("Cancel control request", "取消控制请求"),
+        ("Enlarge host text", "放大被控端文字"),
+        ("Restore host text size", "恢复被控端文字大小"),
+        ("Window capture", "窗口采集"),
+        ("window-capture-closed-tip", "被采集的窗口已关闭，请切换到显示器或选择其他窗口。"),
+        ("window-capture-failed-tip", "无法采集该窗口，窗口不存在、对方系统不支持或对方限制了可以查看的显示器。"),
+        ("video-capacity-tip", "对方限制了同一显示器或摄像头的观看人数，该显示器或摄像头已满员。"),
     ].iter().cloned().collect();
 }
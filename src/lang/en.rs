@@ -256,5 +256,34 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("download-new-version-failed-tip", "Download failed. You can try again or click the \"Download\" button to download from the release page and upgrade manually."),
         ("update-failed-check-msi-tip", "Installation method check failed. Please click the \"Download\" button to download from the release page and upgrade manually."),
         ("websocket_tip", "When using WebSocket, only relay connections are supported."),
+        ("capture-recovery-title", "Screen capture"),
+        ("capture-recovering-tip", "The screen capture of the remote side failed, recovering..."),
+        ("capture-recovered-tip", "The screen capture of the remote side is recovered."),
+        ("reauth-required-tip", "The remote side requires the password again before it can be controlled."),
+        ("video-services-limit-tip", "The remote side limits the number of displays and cameras captured at the same time, please close another one first."),
+        ("encryption-required-tip", "The remote side only accepts encrypted connections, but this connection could not be encrypted."),
+        ("window-capture-closed-tip", "The captured window is closed, please switch to a display or select another window."),
+        ("window-capture-failed-tip", "The window can't be captured, it does not exist, the remote system doesn't support it, or the displays you may see are limited."),
+        ("video-capacity-tip", "The remote side limits the number of the viewers of a display or camera, it is at capacity."),
+        ("virtual_mic_live_tip", "The voice of the peer is played into the virtual microphone"),
+        ("capture-env-console-disconnected-tip", "The session of the remote desktop is disconnected, e.g. an RDP client closed without signing out, nothing can be captured."),
+        ("capture-env-no-console-tip", "No session of the remote desktop is attached to the console and there's no display, nothing can be captured."),
+        ("capture-env-vm-no-display-tip", "The remote desktop is a virtual machine without a display driver, nothing can be captured."),
+        ("capture-env-xvfb-tip", "The remote screen is a virtual X server (Xvfb), not the desktop of the local user."),
+        ("capture-env-enable-virtual-display-tip", "Enable the virtual display option to capture a virtual display instead."),
+        ("capture-env-reconnect-console-tip", "Reconnect the session to the console, e.g. run \"tscon\" as administrator on the remote side."),
+        ("capture-env-sign-in-console-tip", "Sign in on the console of the remote side."),
+        ("capture-env-install-display-driver-tip", "Install the display driver of the virtual machine on the remote side."),
+        ("capture-env-use-desktop-session-tip", "Start RustDesk in the desktop session of the remote side."),
+        ("clipboard-blocked-format-tip", "The clipboard was not synced, its format is not allowed by the clipboard policy of the remote side."),
+        ("clipboard-blocked-size-tip", "The clipboard was not synced, it is larger than the clipboard policy of the remote side allows."),
+        ("unverified", "Unverified, the connection is not encrypted"),
+        ("session-fingerprint-mismatch-tip", "The session fingerprint of the remote side doesn't match the one of this side, someone may be in the middle of the connection."),
+        ("annotation-unsupported-tip", "The annotations can't be shown on the desktop of the remote side."),
+        ("power-action-confirm-tip", "Are you sure you want to run this power action on the remote device?"),
+        ("quick-action-confirm-tip", "Are you sure you want to run this quick action on the remote device?"),
+        ("audio-capture-exclusions-tip", "Process names, separated by commas, e.g. Teams.exe, slack"),
+        ("audio-capture-exclusions-unsupported-tip", "Excluding processes needs PipeWire on Linux or Windows 10 build 20348 or later, the whole sound output is captured here."),
+        ("service-stopped-tip", "A part of the remote side, e.g. the capture of a display or the audio, stopped working and couldn't be restarted. Reconnect to restore it."),
     ].iter().cloned().collect();
 }
@@ -703,5 +703,22 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("Enable terminal", "Activer le terminal"),
         ("New tab", "Nouvel onglet"),
         ("Keep terminal sessions on disconnect", "Maintenir les sessions du terminal lors de la déconnexion"),
+        ("capture-recovery-title", ""),
+        ("capture-recovering-tip", ""),
+        ("capture-recovered-tip", ""),
+        ("reauth-required-tip", ""),
+        ("video-services-limit-tip", ""),
+        ("{} unencrypted sessions", ""),
+        ("encryption-required-tip", ""),
+        ("Send clipboard", ""),
+        ("Request control", ""),
+        ("Release control", ""),
+        ("Cancel control request", ""),
+        ("Enlarge host text", ""),
+        ("Restore host text size", ""),
+        ("Window capture", ""),
+        ("window-capture-closed-tip", ""),
+        ("window-capture-failed-tip", ""),
+        ("video-capacity-tip", ""),
     ].iter().cloned().collect();
 }
@@ -703,5 +703,22 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("Enable terminal", "Abilita terminale"),
         ("New tab", "Nuova scheda"),
         ("Keep terminal sessions on disconnect", "Quando disconetti mantieni attiva sessione terminale"),
+        ("capture-recovery-title", ""),
+        ("capture-recovering-tip", ""),
+        ("capture-recovered-tip", ""),
+        ("reauth-required-tip", ""),
+        ("video-services-limit-tip", ""),
+        ("{} unencrypted sessions", ""),
+        ("encryption-required-tip", ""),
+        ("Send clipboard", ""),
+        ("Request control", ""),
+        ("Release control", ""),
+        ("Cancel control request", ""),
+        ("Enlarge host text", ""),
+        ("Restore host text size", ""),
+        ("Window capture", ""),
+        ("window-capture-closed-tip", ""),
+        ("window-capture-failed-tip", ""),
+        ("video-capacity-tip", ""),
     ].iter().cloned().collect();
 }
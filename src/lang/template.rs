@@ -703,5 +703,84 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("Enable terminal", ""),
         ("New tab", ""),
         ("Keep terminal sessions on disconnect", ""),
+        ("capture-recovery-title", ""),
+        ("capture-recovering-tip", ""),
+        ("capture-recovered-tip", ""),
+        ("reauth-required-tip", ""),
+        ("video-services-limit-tip", ""),
+        ("{} unencrypted sessions", ""),
+        ("encryption-required-tip", ""),
+        ("Send clipboard", ""),
+        ("Request control", ""),
+        ("Release control", ""),
+        ("Cancel control request", ""),
+        ("Enlarge host text", ""),
+        ("Restore host text size", ""),
+        ("Window capture", ""),
+        ("window-capture-closed-tip", ""),
+        ("window-capture-failed-tip", ""),
+        ("video-capacity-tip", ""),
+        ("virtual_mic_live_tip", ""),
+        ("Screen capture unavailable", ""),
+        ("capture-env-console-disconnected-tip", ""),
+        ("capture-env-no-console-tip", ""),
+        ("capture-env-vm-no-display-tip", ""),
+        ("capture-env-xvfb-tip", ""),
+        ("capture-env-enable-virtual-display-tip", ""),
+        ("capture-env-reconnect-console-tip", ""),
+        ("capture-env-sign-in-console-tip", ""),
+        ("capture-env-install-display-driver-tip", ""),
+        ("capture-env-use-desktop-session-tip", ""),
+        ("Enable virtual display", ""),
+        ("clipboard-blocked-format-tip", ""),
+        ("clipboard-blocked-size-tip", ""),
+        ("Terminate all sessions", ""),
+        ("Sessions terminated", ""),
+        ("All remote sessions are closed", ""),
+        ("All remote sessions are closed, new connections are refused for {} minutes", ""),
+        ("The host does not accept connections for a while", ""),
+        ("Terminated by the user of the host", ""),
+        ("Waiting in the voice call queue, position {}", ""),
+        ("Diagnostic capture", ""),
+        ("{} took a screenshot and the log of this device", ""),
+        ("The diagnostic capture is saved to {}", ""),
+        ("Invalid display", ""),
+        ("Session fingerprint", ""),
+        ("unverified", ""),
+        ("session-fingerprint-mismatch-tip", ""),
+        ("Enable annotation", ""),
+        ("Sensitive feature used", ""),
+        ("{} turned on the privacy mode", ""),
+        ("{} blocked the input of this device", ""),
+        ("{} opened a terminal with the administrator rights", ""),
+        ("{} elevated the session with a username and a password", ""),
+        ("{} elevated the session", ""),
+        ("Confirm it within {} seconds, or it will be undone", ""),
+        ("Undo", ""),
+        ("Allow", ""),
+        ("The user of the remote device didn't confirm it", ""),
+        ("Kill process", ""),
+        ("{} wants to kill the process {} ({})", ""),
+        ("Approve it within {} seconds, or it will be denied", ""),
+        ("Annotate", ""),
+        ("Stop annotating", ""),
+        ("Clear annotations", ""),
+        ("annotation-unsupported-tip", ""),
+        ("Power action", ""),
+        ("power-action-confirm-tip", ""),
+        ("Quick actions", ""),
+        ("Enable quick actions", ""),
+        ("Run", ""),
+        ("Running", ""),
+        ("Exit code", ""),
+        ("The output is truncated", ""),
+        ("quick-action-confirm-tip", ""),
+        ("Audio capture exclusions", ""),
+        ("audio-capture-exclusions-tip", ""),
+        ("audio-capture-exclusions-unsupported-tip", ""),
+        ("Service stopped", ""),
+        ("service-stopped-tip", ""),
+        ("Allow virtual microphone", ""),
+        ("Voice call into the virtual microphone", ""),
     ].iter().cloned().collect();
 }
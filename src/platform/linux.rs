@@ -503,6 +503,14 @@ pub fn start_os_service() {
         }
     }
 
+    if user_server.is_some() || server.is_some() {
+        let reason = if is_system_stopping() {
+            crate::server::CLOSE_REASON_SHUTDOWN
+        } else {
+            crate::server::CLOSE_REASON_STOP_SERVICE
+        };
+        allow_err!(crate::ipc::close_connections(reason));
+    }
     if let Some(ps) = user_server.take().as_mut() {
         allow_err!(ps.kill());
     }
@@ -512,6 +520,15 @@ pub fn start_os_service() {
     log::info!("Exit");
 }
 
+// systemd is shutting down or rebooting the system.
+fn is_system_stopping() -> bool {
+    std::process::Command::new("systemctl")
+        .arg("is-system-running")
+        .output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim() == "stopping")
+        .unwrap_or(false)
+}
+
 #[inline]
 pub fn get_active_user_id_name() -> (String, String) {
     let vec_id_name = get_values_of_seat0(&[1, 2]);
@@ -700,9 +717,35 @@ where
     Ok(Some(task))
 }
 
+// A command in the session of the active user, e.g. gsettings and notify-send need its session
+// bus. The server runs as root, so it's run as the user like `run_as_user`.
+pub fn user_command(program: &str) -> ResultType<Command> {
+    if !is_root() {
+        return Ok(Command::new(program));
+    }
+    let (uid, username) = get_active_user_id_name();
+    if uid.is_empty() {
+        bail!("No active user");
+    }
+    let mut cmd = Command::new("sudo");
+    cmd.args(["-n", "-u", &username, "env"])
+        .arg(format!("XDG_RUNTIME_DIR=/run/user/{}", uid))
+        .arg(format!("DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/{}/bus", uid))
+        .arg(program);
+    Ok(cmd)
+}
+
 pub fn get_pa_monitor() -> String {
-    get_pa_sources()
-        .drain(..)
+    let sources = get_pa_sources();
+    // The monitor of the default sink follows the output the user is listening to.
+    if let Some((sink, _)) = get_default_pa_sink() {
+        let monitor = format!("{}.monitor", sink);
+        if sources.iter().any(|x| x.0 == monitor) {
+            return monitor;
+        }
+    }
+    sources
+        .into_iter()
         .map(|x| x.0)
         .filter(|x| x.contains("monitor"))
         .next()
@@ -739,6 +782,36 @@ pub fn get_pa_sources() -> Vec<(String, String)> {
     out
 }
 
+pub fn get_pa_sink_name(desc: &str) -> String {
+    get_pa_sinks()
+        .drain(..)
+        .filter(|x| x.1 == desc)
+        .map(|x| x.0)
+        .next()
+        .unwrap_or("".to_owned())
+}
+
+pub fn get_pa_sinks() -> Vec<(String, String)> {
+    use pulsectl::controllers::*;
+    let mut out = Vec::new();
+    match SinkController::create() {
+        Ok(mut handler) => {
+            if let Ok(devices) = handler.list_devices() {
+                for dev in devices.clone() {
+                    out.push((
+                        dev.name.unwrap_or("".to_owned()),
+                        dev.description.unwrap_or("".to_owned()),
+                    ));
+                }
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to get_pa_sinks: {:?}", err);
+        }
+    }
+    out
+}
+
 pub fn get_default_pa_source() -> Option<(String, String)> {
     use pulsectl::controllers::*;
     match SourceController::create() {
@@ -757,6 +830,15 @@ pub fn get_default_pa_source() -> Option<(String, String)> {
     None
 }
 
+pub fn get_default_pa_sink() -> Option<(String, String)> {
+    use pulsectl::controllers::*;
+    let dev = SinkController::create().ok()?.get_default_device().ok()?;
+    Some((
+        dev.name.unwrap_or("".to_owned()),
+        dev.description.unwrap_or("".to_owned()),
+    ))
+}
+
 pub fn lock_screen() {
     Command::new("xdg-screensaver").arg("lock").spawn().ok();
 }
@@ -769,6 +851,15 @@ pub fn block_input(_v: bool) -> (bool, String) {
     (true, "".to_owned())
 }
 
+/// Show a notification to the active user with `notify-send`.
+pub fn show_notification(title: &str, text: &str) -> ResultType<()> {
+    let app = crate::get_app_name();
+    user_command("notify-send")?
+        .args(["-a", app.as_str(), title, text])
+        .status()?;
+    Ok(())
+}
+
 pub fn is_installed() -> bool {
     if let Ok(p) = std::env::current_exe() {
         p.to_str().unwrap_or_default().starts_with("/usr")
@@ -991,6 +1082,48 @@ pub fn current_resolution(name: &str) -> ResultType<Resolution> {
     bail!("Failed to find current resolution for {}", name);
 }
 
+const XRANDR_CACHE_TIMEOUT: Duration = Duration::from_secs(3);
+
+lazy_static::lazy_static! {
+    // The displays are checked every 300ms, xrandr is not run that often.
+    static ref XRANDR_QUERY: std::sync::Mutex<Option<(Instant, String)>> = Default::default();
+}
+
+// The orientation of the output in degrees, measured clockwise.
+pub fn current_orientation(name: &str) -> ResultType<i32> {
+    let mut lock = XRANDR_QUERY.lock().unwrap();
+    let output = match lock.as_ref() {
+        Some((t, output)) if t.elapsed() < XRANDR_CACHE_TIMEOUT => output.clone(),
+        _ => {
+            let output = run_cmds("xrandr --query | tr -s ' '")?;
+            *lock = Some((Instant::now(), output.clone()));
+            output
+        }
+    };
+    drop(lock);
+    match parse_xrandr_orientation(&output, name) {
+        Some(orientation) => Ok(orientation),
+        None => bail!("Failed to find current orientation for {}", name),
+    }
+}
+
+// "HDMI-1 connected 1080x1920+0+0 left (normal left inverted right x axis y axis) 527mm x 296mm"
+// xrandr rotates "left" counterclockwise.
+fn parse_xrandr_orientation(xrandr_output: &str, name: &str) -> Option<i32> {
+    let pat = format!(
+        r"(?m)^{}\s+connected(\s+primary)?\s+\d+x\d+\+\d+\+\d+",
+        hbb_common::regex::escape(name)
+    );
+    let re = Regex::new(&(pat + r"(\s+(?P<rotation>left|right|inverted))?")).ok()?;
+    let caps = re.captures(xrandr_output)?;
+    Some(match caps.name("rotation").map(|m| m.as_str()) {
+        Some("right") => 90,
+        Some("inverted") => 180,
+        Some("left") => 270,
+        _ => 0,
+    })
+}
+
 pub fn change_resolution_directly(name: &str, width: usize, height: usize) -> ResultType<()> {
     Command::new("xrandr")
         .args(vec![
@@ -1003,6 +1136,63 @@ pub fn change_resolution_directly(name: &str, width: usize, height: usize) -> Re
     Ok(())
 }
 
+const TEXT_SCALE_KEY: [&str; 2] = ["org.gnome.desktop.interface", "text-scaling-factor"];
+
+/// The text scaling of GNOME in percent, from the output of `gsettings get` or a line of
+/// `gsettings monitor`, e.g. "text-scaling-factor: 1.25".
+pub fn parse_text_scale(s: &str) -> Option<u32> {
+    let factor = s.rsplit(':').next()?.trim().parse::<f64>().ok()?;
+    if factor > 0.0 {
+        Some((factor * 100.0).round() as _)
+    } else {
+        None
+    }
+}
+
+pub fn get_text_scale() -> ResultType<u32> {
+    let output = user_command("gsettings")?
+        .arg("get")
+        .args(TEXT_SCALE_KEY)
+        .output()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    parse_text_scale(&output)
+        .ok_or_else(|| anyhow!("Invalid text scaling factor: {}", output.trim()))
+}
+
+pub fn set_text_scale(percent: u32) -> ResultType<()> {
+    let status = user_command("gsettings")?
+        .arg("set")
+        .args(TEXT_SCALE_KEY)
+        .arg(format!("{:.2}", percent as f64 / 100.0))
+        .status()?;
+    if !status.success() {
+        bail!("Failed to set text scaling factor, {}", status);
+    }
+    Ok(())
+}
+
+/// Calls `f` with the text scaling of GNOME on every change, until `gsettings monitor` exits,
+/// e.g. the user logs out.
+pub fn monitor_text_scale(mut f: impl FnMut(u32)) -> ResultType<()> {
+    use std::io::{BufRead, BufReader};
+    let mut child = user_command("gsettings")?
+        .arg("monitor")
+        .args(TEXT_SCALE_KEY)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    let Some(stdout) = child.stdout.take() else {
+        bail!("No output of gsettings monitor");
+    };
+    for line in BufReader::new(stdout).lines() {
+        if let Some(percent) = parse_text_scale(&line?) {
+            f(percent);
+        }
+    }
+    child.wait().ok();
+    Ok(())
+}
+
 #[inline]
 pub fn is_xwayland_running() -> bool {
     if let Ok(output) = run_cmds("pgrep -a Xwayland") {
@@ -1532,3 +1722,26 @@ pub fn is_selinux_enforcing() -> bool {
         },
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_xrandr_orientation() {
+        let output = "Screen 0: minimum 320 x 200, current 3000 x 1920, maximum 16384 x 16384
+eDP-1 connected primary 1920x1080+0+0 (normal left inverted right x axis y axis) 344mm x 193mm
+ 1920x1080 60.01*+ 59.97
+HDMI-1 connected 1080x1920+1920+0 left (normal left inverted right x axis y axis) 527mm x 296mm
+DP-1 connected 1920x1080+0+1080 inverted (normal left inverted right x axis y axis) 0mm x 0mm
+DP-2 connected 1080x1920+3000+0 right (normal left inverted right x axis y axis) 0mm x 0mm
+DP-3 disconnected (normal left inverted right x axis y axis)
+";
+        assert_eq!(parse_xrandr_orientation(output, "eDP-1"), Some(0));
+        assert_eq!(parse_xrandr_orientation(output, "HDMI-1"), Some(270));
+        assert_eq!(parse_xrandr_orientation(output, "DP-1"), Some(180));
+        assert_eq!(parse_xrandr_orientation(output, "DP-2"), Some(90));
+        assert_eq!(parse_xrandr_orientation(output, "DP-3"), None);
+        assert_eq!(parse_xrandr_orientation(output, "DP"), None);
+    }
+}
@@ -1,9 +1,5 @@
 use super::{linux::*, ResultType};
-use crate::client::{
-    LOGIN_MSG_DESKTOP_NO_DESKTOP, LOGIN_MSG_DESKTOP_SESSION_ANOTHER_USER,
-    LOGIN_MSG_DESKTOP_SESSION_NOT_READY, LOGIN_MSG_DESKTOP_XORG_NOT_FOUND,
-    LOGIN_MSG_DESKTOP_XSESSION_FAILED,
-};
+use crate::error_code::ErrorCode;
 use hbb_common::{allow_err, bail, log, rand::prelude::*, tokio::time};
 use pam;
 use std::{
@@ -64,76 +60,72 @@ pub fn stop_xdesktop() {
     *DESKTOP_MANAGER.lock().unwrap() = None;
 }
 
-fn detect_headless() -> Option<&'static str> {
+fn detect_headless() -> Option<ErrorCode> {
     match run_cmds(&format!("which {}", DesktopManager::get_xorg())) {
         Ok(output) => {
             if output.trim().is_empty() {
-                return Some(LOGIN_MSG_DESKTOP_XORG_NOT_FOUND);
+                return Some(ErrorCode::DesktopXorgNotFound);
             }
         }
         _ => {
-            return Some(LOGIN_MSG_DESKTOP_XORG_NOT_FOUND);
+            return Some(ErrorCode::DesktopXorgNotFound);
         }
     }
 
     match run_cmds("ls /usr/share/xsessions/") {
         Ok(output) => {
             if output.trim().is_empty() {
-                return Some(LOGIN_MSG_DESKTOP_NO_DESKTOP);
+                return Some(ErrorCode::DesktopNoDesktop);
             }
         }
         _ => {
-            return Some(LOGIN_MSG_DESKTOP_NO_DESKTOP);
+            return Some(ErrorCode::DesktopNoDesktop);
         }
     }
 
     None
 }
 
-pub fn try_start_desktop(_username: &str, _passsword: &str) -> String {
+// None if the desktop is ready.
+pub fn try_start_desktop(_username: &str, _passsword: &str) -> Option<ErrorCode> {
     debug_assert!(crate::is_server());
     if _username.is_empty() {
         let username = get_username();
         if username.is_empty() {
-            if let Some(msg) = detect_headless() {
-                msg
-            } else {
-                LOGIN_MSG_DESKTOP_SESSION_NOT_READY
-            }
+            Some(detect_headless().unwrap_or(ErrorCode::DesktopSessionNotReady))
         } else {
-            ""
+            None
         }
-        .to_owned()
     } else {
         let username = get_username();
         if username == _username {
             // No need to verify password here.
-            return "".to_owned();
+            return None;
         }
         if !username.is_empty() {
             // Another user is logged in. No need to start a new xsession.
-            return "".to_owned();
+            return None;
         }
 
-        if let Some(msg) = detect_headless() {
-            return msg.to_owned();
+        if let Some(code) = detect_headless() {
+            return Some(code);
         }
 
         match try_start_x_session(_username, _passsword) {
             Ok((username, x11_ready)) => {
                 if x11_ready {
                     if _username != username {
-                        LOGIN_MSG_DESKTOP_SESSION_ANOTHER_USER.to_owned()
+                        Some(ErrorCode::DesktopSessionAnotherUser)
                     } else {
-                        "".to_owned()
+                        None
                     }
                 } else {
-                    LOGIN_MSG_DESKTOP_SESSION_NOT_READY.to_owned()
+                    Some(ErrorCode::DesktopSessionNotReady)
                 }
             }
             Err(e) => {
                 log::error!("Failed to start xsession {}", e);
-                LOGIN_MSG_DESKTOP_XSESSION_FAILED.to_owned()
+                Some(ErrorCode::DesktopXsessionFailed)
             }
         }
     }
@@ -19,7 +19,9 @@ use core_graphics::{
 };
 use hbb_common::{
     anyhow::anyhow,
-    bail, log,
+    bail,
+    config::Config,
+    log,
     message_proto::{DisplayInfo, Resolution},
     sysinfo::{Pid, Process, ProcessRefreshKind, System},
 };
@@ -61,6 +63,7 @@ extern "C" {
     fn majorVersion() -> u32;
     fn MacGetMode(display: u32, width: *mut u32, height: *mut u32) -> BOOL;
     fn MacSetMode(display: u32, width: u32, height: u32, tryHiDPI: bool) -> BOOL;
+    fn CGDisplayRotation(display: u32) -> f64;
 }
 
 pub fn major_version() -> u32 {
@@ -610,6 +613,25 @@ pub fn run_as_user(arg: Vec<&str>) -> ResultType<Option<std::process::Child>> {
     Ok(Some(task))
 }
 
+// How an incoming connection wakes the mac, "display" (default), "system" or "none".
+const OPTION_WAKE_ON_CONNECT: &str = "macos-wake-on-connect";
+
+/// "display" declares the user active, which turns on the display.
+/// "system" only keeps the system from idle sleep, the display stays off.
+pub fn wake_on_connect() {
+    let arg = match Config::get_option(OPTION_WAKE_ON_CONNECT).as_str() {
+        "none" => return,
+        "system" => "-i",
+        _ => "-u",
+    };
+    std::process::Command::new("/usr/bin/caffeinate")
+        .arg(arg)
+        .arg("-t 5")
+        .spawn()
+        .ok();
+    log::info!("wake up macos, caffeinate {}", arg);
+}
+
 pub fn lock_screen() {
     std::process::Command::new(
         "/System/Library/CoreServices/Menu Extras/User.menu/Contents/Resources/CGSession",
@@ -692,6 +714,24 @@ pub fn block_input(_v: bool) -> (bool, String) {
     (true, "".to_owned())
 }
 
+/// Show a notification to the user, the server runs as the user agent.
+pub fn show_notification(title: &str, text: &str) -> ResultType<()> {
+    // The texts are passed as the arguments, no quoting in the script.
+    Command::new("osascript")
+        .args([
+            "-e",
+            "on run argv",
+            "-e",
+            "display notification (item 2 of argv) with title (item 1 of argv)",
+            "-e",
+            "end run",
+            title,
+            text,
+        ])
+        .status()?;
+    Ok(())
+}
+
 pub fn is_installed() -> bool {
     if let Ok(p) = std::env::current_exe() {
         return p
@@ -955,6 +995,13 @@ pub fn resolutions(name: &str) -> Vec<Resolution> {
     v
 }
 
+// The orientation of the display in degrees, measured clockwise.
+pub fn current_orientation(name: &str) -> ResultType<i32> {
+    let display = name.parse::<u32>().map_err(|e| anyhow!(e))?;
+    let rotation = unsafe { CGDisplayRotation(display) };
+    Ok((rotation.round() as i32).rem_euclid(360))
+}
+
 pub fn current_resolution(name: &str) -> ResultType<Resolution> {
     let display = name.parse::<u32>().map_err(|e| anyhow!(e))?;
     unsafe {
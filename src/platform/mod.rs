@@ -26,6 +26,12 @@ pub mod linux_desktop_manager;
 #[cfg(target_os = "linux")]
 pub mod gtk_sudo;
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod power;
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod process;
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use hbb_common::{
     message_proto::CursorData,
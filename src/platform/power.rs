@@ -0,0 +1,99 @@
+// Power actions requested by the peer.
+//
+// All of them go through the graceful paths of the OS, the applications are asked to close
+// and nothing is forced.
+
+use hbb_common::{bail, log, ResultType};
+
+pub const OPTION_DISABLE_POWER_ACTIONS: &str = "disable-power-actions";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+    Reboot,
+    Shutdown,
+    Sleep,
+    Hibernate,
+    RebootToFirmware,
+}
+
+impl PowerAction {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "reboot" => Some(Self::Reboot),
+            "shutdown" => Some(Self::Shutdown),
+            "sleep" => Some(Self::Sleep),
+            "hibernate" => Some(Self::Hibernate),
+            "reboot-to-firmware" => Some(Self::RebootToFirmware),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Reboot => "reboot",
+            Self::Shutdown => "shutdown",
+            Self::Sleep => "sleep",
+            Self::Hibernate => "hibernate",
+            Self::RebootToFirmware => "reboot-to-firmware",
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Self::RebootToFirmware => cfg!(any(target_os = "windows", target_os = "linux")),
+            _ => true,
+        }
+    }
+
+    /// The estimated seconds before the host can be connected again, so the peer can retry.
+    /// 0 means the host does not come back by itself.
+    pub fn estimated_downtime_secs(&self) -> u32 {
+        match self {
+            Self::Reboot => 90,
+            _ => 0,
+        }
+    }
+}
+
+pub fn is_disabled() -> bool {
+    hbb_common::config::Config::get_option(OPTION_DISABLE_POWER_ACTIONS) == "Y"
+}
+
+pub fn execute(action: PowerAction) -> ResultType<()> {
+    log::info!("Execute power action: {}", action.name());
+    match action {
+        PowerAction::Reboot => system_shutdown::reboot()?,
+        PowerAction::Shutdown => system_shutdown::shutdown()?,
+        PowerAction::Sleep => system_shutdown::sleep()?,
+        PowerAction::Hibernate => system_shutdown::hibernate()?,
+        PowerAction::RebootToFirmware => reboot_to_firmware()?,
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn reboot_to_firmware() -> ResultType<()> {
+    let status = std::process::Command::new("shutdown")
+        .args(["/r", "/fw", "/t", "0"])
+        .status()?;
+    if !status.success() {
+        bail!("shutdown /r /fw failed: {}", status);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn reboot_to_firmware() -> ResultType<()> {
+    let status = std::process::Command::new("systemctl")
+        .args(["reboot", "--firmware-setup"])
+        .status()?;
+    if !status.success() {
+        bail!("systemctl reboot --firmware-setup failed: {}", status);
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn reboot_to_firmware() -> ResultType<()> {
+    bail!("Reboot to firmware is not supported");
+}
@@ -0,0 +1,366 @@
+// The remote task manager, the processes of the host are listed and killed by the peer.
+//
+// The list is paged from a snapshot, the snapshot is refreshed by the request of the first page
+// and at most once every `REFRESH_INTERVAL`, so the peer can't keep the host busy by polling.
+//
+// A kill is requested twice like the power actions, the first request is answered with a token
+// and the process is killed when the token comes back in time. The kill of a process of
+// RustDesk itself or of a critical system process, or of any process if "process-kill-approval"
+// is set, also needs the approval on the host. The server process itself is never killed.
+//
+// The process is looked up again before the kill, it's not killed if its name or its start time
+// changed, e.g. it exited and the pid was reused while the kill waited for the approval.
+
+use hbb_common::{
+    bail,
+    config::Config,
+    log,
+    sysinfo::{Pid, Process, System},
+    ResultType,
+};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+pub const OPTION_ALLOW_PROCESS_MANAGER: &str = "allow-remote-process-manager";
+pub const OPTION_PROCESS_KILL_APPROVAL: &str = "process-kill-approval";
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+// The time the user on the host has to approve a kill.
+pub const APPROVAL_TIMEOUT: Duration = Duration::from_secs(60);
+pub const MAX_PAGE_SIZE: usize = 200;
+
+pub fn is_allowed() -> bool {
+    Config::get_option(OPTION_ALLOW_PROCESS_MANAGER) == "Y"
+}
+
+fn is_approval_required() -> bool {
+    Config::get_option(OPTION_PROCESS_KILL_APPROVAL) == "Y"
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessEntry {
+    pub pid: u32,
+    pub name: String,
+    pub user: String,
+    // 100 is one core.
+    pub cpu_usage: f32,
+    pub memory: u64,
+    // A process of RustDesk or a critical system process.
+    pub protected: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KillState {
+    // Wait for the approval on the host.
+    Approval,
+    Ready,
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingKill {
+    pub pid: u32,
+    pub name: String,
+    // In seconds since the epoch, with the name it tells the process from a later one of the pid.
+    start_time: u64,
+    pub token: u64,
+    pub approval: bool,
+    pub approval_requested: bool,
+    time: Instant,
+}
+
+// The state of the task manager of a connection.
+pub struct ProcessManager {
+    sys: System,
+    exe: PathBuf,
+    snapshot: Vec<ProcessEntry>,
+    last_refresh: Option<Instant>,
+    pending: Option<PendingKill>,
+}
+
+impl Default for ProcessManager {
+    fn default() -> Self {
+        let mut exe = std::env::current_exe().unwrap_or_default();
+        if let Ok(linked) = exe.read_link() {
+            exe = linked;
+        }
+        Self {
+            sys: System::new(),
+            exe,
+            snapshot: vec![],
+            last_refresh: None,
+            pending: None,
+        }
+    }
+}
+
+impl ProcessManager {
+    /// A page of the processes and the total count, the first page refreshes the snapshot.
+    pub fn list(
+        &mut self,
+        page: usize,
+        page_size: usize,
+    ) -> ResultType<(Vec<ProcessEntry>, usize)> {
+        if page == 0 || self.last_refresh.is_none() {
+            if let Some(last) = self.last_refresh {
+                let elapsed = last.elapsed();
+                if elapsed < REFRESH_INTERVAL {
+                    bail!(
+                        "The process list is refreshed too often, retry in {}ms",
+                        (REFRESH_INTERVAL - elapsed).as_millis()
+                    );
+                }
+            }
+            self.refresh();
+        }
+        Ok((
+            paginate(&self.snapshot, page, page_size),
+            self.snapshot.len(),
+        ))
+    }
+
+    fn refresh(&mut self) {
+        if self.last_refresh.is_none() {
+            self.sys.refresh_users_list();
+        }
+        // The cpu usage is measured since the previous refresh, it is 0 in the first snapshot.
+        self.sys.refresh_processes();
+        self.last_refresh = Some(Instant::now());
+        let mut snapshot: Vec<ProcessEntry> = self
+            .sys
+            .processes()
+            .iter()
+            .map(|(pid, p)| ProcessEntry {
+                pid: usize::from(*pid) as _,
+                name: p.name().to_owned(),
+                user: p
+                    .user_id()
+                    .and_then(|uid| self.sys.get_user_by_id(uid))
+                    .map(|u| u.name().to_owned())
+                    .unwrap_or_default(),
+                cpu_usage: p.cpu_usage(),
+                memory: p.memory(),
+                protected: self.is_protected(p),
+            })
+            .collect();
+        snapshot.sort_by_key(|p| p.pid);
+        self.snapshot = snapshot;
+    }
+
+    fn is_protected(&self, p: &Process) -> bool {
+        if usize::from(p.pid()) == std::process::id() as usize {
+            return true;
+        }
+        let mut exe = p.exe().to_path_buf();
+        if let Ok(linked) = exe.read_link() {
+            exe = linked;
+        }
+        if !exe.as_os_str().is_empty() && exe == self.exe {
+            return true;
+        }
+        let app_name = crate::get_app_name().to_lowercase();
+        p.name().to_lowercase().starts_with(&app_name) || is_critical(p)
+    }
+
+    /// The first kill request, returns the pending kill with the token.
+    pub fn request_kill(&mut self, pid: u32) -> ResultType<PendingKill> {
+        self.pending = None;
+        if pid == std::process::id() {
+            bail!("The process {} is the RustDesk server", pid);
+        }
+        let spid = Pid::from(pid as usize);
+        if !self.sys.refresh_process(spid) {
+            bail!("The process {} does not exist", pid);
+        }
+        let Some(p) = self.sys.process(spid) else {
+            bail!("The process {} does not exist", pid);
+        };
+        let pending = PendingKill {
+            pid,
+            name: p.name().to_owned(),
+            start_time: p.start_time(),
+            token: hbb_common::rand::random::<u64>().max(1),
+            approval: self.is_protected(p) || is_approval_required(),
+            approval_requested: false,
+            time: Instant::now(),
+        };
+        self.pending = Some(pending.clone());
+        Ok(pending)
+    }
+
+    /// The kill request with the token, the approval is requested only once.
+    pub fn confirm_kill(&mut self, pid: u32, token: u64) -> ResultType<(PendingKill, KillState)> {
+        let Some(p) = self.pending.as_mut().filter(|p| {
+            p.pid == pid
+                && p.token == token
+                && !p.approval_requested
+                && p.time.elapsed() < CONFIRM_TIMEOUT
+        }) else {
+            self.pending = None;
+            bail!("The kill of the process {} is not confirmed in time", pid);
+        };
+        if p.approval {
+            p.approval_requested = true;
+            p.time = Instant::now();
+            return Ok((p.clone(), KillState::Approval));
+        }
+        let p = p.clone();
+        self.pending = None;
+        Ok((p, KillState::Ready))
+    }
+
+    /// The kill waiting for the approval of the host, if the approval is not too late.
+    pub fn take_approval(&mut self, pid: u32) -> Option<PendingKill> {
+        match self.pending.take() {
+            Some(p)
+                if p.pid == pid && p.approval_requested && p.time.elapsed() < APPROVAL_TIMEOUT =>
+            {
+                Some(p)
+            }
+            p => {
+                self.pending = p;
+                None
+            }
+        }
+    }
+
+    /// The kill whose approval is not given in time, the peer is told once.
+    pub fn take_expired_approval(&mut self) -> Option<PendingKill> {
+        match self.pending.take() {
+            Some(p) if p.approval_requested && p.time.elapsed() >= APPROVAL_TIMEOUT => Some(p),
+            p => {
+                self.pending = p;
+                None
+            }
+        }
+    }
+
+    pub fn kill(&mut self, pending: &PendingKill) -> ResultType<()> {
+        let pid = pending.pid;
+        let spid = Pid::from(pid as usize);
+        if !self.sys.refresh_process(spid) {
+            bail!("The process {} does not exist", pid);
+        }
+        let Some(p) = self.sys.process(spid) else {
+            bail!("The process {} does not exist", pid);
+        };
+        if p.name() != pending.name || p.start_time() != pending.start_time {
+            bail!("The process {} is not {} any more", pid, pending.name);
+        }
+        if !p.kill() {
+            bail!("Failed to kill the process {}", pid);
+        }
+        log::info!("Killed the process {} {}", pid, p.name());
+        Ok(())
+    }
+}
+
+// The idle process, init or the System process, and the services of the session 0 on Windows.
+fn is_critical(p: &Process) -> bool {
+    let pid = usize::from(p.pid());
+    if pid <= 4 {
+        return true;
+    }
+    #[cfg(windows)]
+    if crate::platform::windows::get_session_id_of_process(pid as _) == Some(0) {
+        return true;
+    }
+    false
+}
+
+fn paginate<T: Clone>(items: &[T], page: usize, page_size: usize) -> Vec<T> {
+    let page_size = page_size.clamp(1, MAX_PAGE_SIZE);
+    items
+        .iter()
+        .skip(page.saturating_mul(page_size))
+        .take(page_size)
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_paginate() {
+        let items: Vec<usize> = (0..10).collect();
+        assert_eq!(paginate(&items, 0, 4), vec![0, 1, 2, 3]);
+        assert_eq!(paginate(&items, 2, 4), vec![8, 9]);
+        assert!(paginate(&items, 3, 4).is_empty());
+        assert_eq!(paginate(&items, 0, 0), vec![0]);
+        assert_eq!(paginate(&items, 0, 1000).len(), 10);
+    }
+
+    #[test]
+    fn test_list_and_kill() {
+        let mut pm = ProcessManager::default();
+        let (_, total) = pm.list(0, 10).unwrap();
+        assert!(total > 0);
+        // The next pages come from the snapshot, the refresh is limited.
+        assert!(pm.list(1, 10).is_ok());
+        assert!(pm.list(0, 10).is_err());
+        let me = std::process::id();
+        assert!(pm.snapshot.iter().any(|p| p.pid == me && p.protected));
+        assert!(pm.request_kill(me).is_err());
+        assert!(pm.confirm_kill(me, 1).is_err());
+    }
+
+    #[test]
+    fn test_expired_approval() {
+        let mut pm = ProcessManager::default();
+        let mut pending = PendingKill {
+            pid: 1,
+            name: "test".to_owned(),
+            start_time: 0,
+            token: 1,
+            approval: true,
+            approval_requested: true,
+            time: Instant::now(),
+        };
+        pm.pending = Some(pending.clone());
+        assert!(pm.take_expired_approval().is_none());
+        pending.time = Instant::now() - APPROVAL_TIMEOUT;
+        pm.pending = Some(pending);
+        assert!(pm.take_approval(1).is_none());
+        assert_eq!(pm.take_expired_approval().map(|p| p.pid), Some(1));
+        assert!(pm.pending.is_none());
+    }
+
+    // The pid may be reused by another process while the kill waits.
+    #[cfg(unix)]
+    #[test]
+    fn test_kill_the_same_process() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let mut pm = ProcessManager::default();
+        let pending = pm.request_kill(child.id()).unwrap();
+        let other_start = PendingKill {
+            start_time: pending.start_time + 1,
+            ..pending.clone()
+        };
+        assert!(pm.kill(&other_start).is_err());
+        let other_name = PendingKill {
+            name: "other".to_owned(),
+            ..pending.clone()
+        };
+        assert!(pm.kill(&other_name).is_err());
+        assert!(child.try_wait().unwrap().is_none());
+        assert!(pm.kill(&pending).is_ok());
+        child.wait().ok();
+    }
+
+    #[test]
+    fn test_critical_process_needs_approval() {
+        let mut pm = ProcessManager::default();
+        // init, or the System process on Windows.
+        let pid = if cfg!(windows) { 4 } else { 1 };
+        if let Ok(pending) = pm.request_kill(pid) {
+            assert!(pending.approval);
+        }
+    }
+}
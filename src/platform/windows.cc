@@ -615,6 +615,23 @@ extern "C"
         return nout;
     }
 
+    // The WTS_CONNECTSTATE_CLASS of the session, -1 if failed.
+    int32_t get_session_connect_state(uint32_t id)
+    {
+        int32_t state = -1;
+        WTS_CONNECTSTATE_CLASS *buf = NULL;
+        DWORD n = 0;
+        if (WTSQuerySessionInformationW(WTS_CURRENT_SERVER_HANDLE, id, WTSConnectState, (LPWSTR *)&buf, &n))
+        {
+            if (buf)
+            {
+                state = *buf;
+                WTSFreeMemory(buf);
+            }
+        }
+        return state;
+    }
+
     void get_available_session_ids(PWSTR buf, uint32_t bufSize, BOOL include_rdp) {
         std::vector<std::wstring> sessionIds;
         PWTS_SESSION_INFOA pInfos = NULL;
@@ -29,7 +29,10 @@ use std::{
     },
     path::*,
     ptr::null_mut,
-    sync::{atomic::Ordering, Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 use wallpaper;
@@ -47,7 +50,8 @@ use winapi::{
         minwinbase::STILL_ACTIVE,
         processthreadsapi::{
             GetCurrentProcess, GetCurrentProcessId, GetExitCodeProcess, OpenProcess,
-            OpenProcessToken, ProcessIdToSessionId, PROCESS_INFORMATION, STARTUPINFOW,
+            OpenProcessToken, ProcessIdToSessionId, TerminateProcess, PROCESS_INFORMATION,
+            STARTUPINFOW,
         },
         securitybaseapi::GetTokenInformation,
         shellapi::ShellExecuteW,
@@ -466,6 +470,9 @@ fn fix_cursor_mask(
 
 define_windows_service!(ffi_service_main, service_main);
 
+// The service is stopped because Windows is shutting down, for the close reason of the peers.
+static SERVICE_SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
 fn service_main(arguments: Vec<OsString>) {
     if let Err(e) = run_service(arguments) {
         log::error!("run_service failed: {}", e);
@@ -532,6 +539,12 @@ async fn run_service(_arguments: Vec<OsString>) -> ResultType<()> {
         match control_event {
             ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
             ServiceControl::Stop | ServiceControl::Preshutdown | ServiceControl::Shutdown => {
+                if matches!(
+                    control_event,
+                    ServiceControl::Preshutdown | ServiceControl::Shutdown
+                ) {
+                    SERVICE_SHUTDOWN.store(true, Ordering::SeqCst);
+                }
                 send_close(crate::POSTFIX_SERVICE).ok();
                 ServiceControlHandlerResult::NoError
             }
@@ -563,6 +576,7 @@ async fn run_service(_arguments: Vec<OsString>) -> ResultType<()> {
 
     let mut session_id = unsafe { get_current_session(share_rdp()) };
     log::info!("session id {}", session_id);
+    let mut supervisor = crate::server::server_watchdog::Supervisor::new();
     let mut h_process = launch_server(session_id, true).await.unwrap_or(NULL);
     let mut incoming = ipc::new_listener(crate::POSTFIX_SERVICE).await?;
     let mut stored_usid = None;
@@ -579,6 +593,7 @@ async fn run_service(_arguments: Vec<OsString>) -> ResultType<()> {
                 let count = ipc::get_port_forward_session_count(1000).await.unwrap_or(0);
                 if count == 0 {
                     h_process = launch_server(session_id, true).await.unwrap_or(NULL);
+                    supervisor.on_launched();
                 }
             }
         }
@@ -596,6 +611,15 @@ async fn run_service(_arguments: Vec<OsString>) -> ResultType<()> {
                             ipc::Data::SAS => {
                                 send_sas();
                             }
+                            ipc::Data::ServerHeartbeat { seq, .. } => {
+                                supervisor.on_beat(seq);
+                                let restarts = supervisor.restarts();
+                                allow_err!(
+                                    stream
+                                        .send(&ipc::Data::ServerHeartbeat { seq, restarts })
+                                        .await
+                                );
+                            }
                             ipc::Data::UserSid(usid) => {
                                 if let Some(usid) = usid {
                                     if session_id != usid {
@@ -608,6 +632,7 @@ async fn run_service(_arguments: Vec<OsString>) -> ResultType<()> {
                                         stored_usid = Some(session_id);
                                         h_process =
                                             launch_server(session_id, true).await.unwrap_or(NULL);
+                                        supervisor.on_launched();
                                     }
                                 }
                             }
@@ -635,14 +660,30 @@ async fn run_service(_arguments: Vec<OsString>) -> ResultType<()> {
                         }
                     }
                     let mut exit_code: DWORD = 0;
-                    if h_process.is_null()
-                        || (GetExitCodeProcess(h_process, &mut exit_code) == TRUE
+                    if !h_process.is_null() {
+                        if supervisor.is_hung() {
+                            log::error!("The server sends no heartbeat, terminate it");
+                            TerminateProcess(h_process, 1);
+                            CloseHandle(h_process);
+                            h_process = NULL;
+                            on_server_crash(&mut supervisor, "hung", 1);
+                        } else if GetExitCodeProcess(h_process, &mut exit_code) == TRUE
                             && exit_code != STILL_ACTIVE
-                            && CloseHandle(h_process) == TRUE)
-                    {
+                            && CloseHandle(h_process) == TRUE
+                        {
+                            h_process = NULL;
+                            if exit_code != 0 {
+                                on_server_crash(&mut supervisor, "crash", exit_code);
+                            }
+                        }
+                    }
+                    if h_process.is_null() && supervisor.may_launch() {
                         match launch_server(session_id, !close_sent).await {
                             Ok(ptr) => {
                                 h_process = ptr;
+                                if !ptr.is_null() {
+                                    supervisor.on_launched();
+                                }
                             }
                             Err(err) => {
                                 log::error!("Failed to launch server: {}", err);
@@ -655,6 +696,12 @@ async fn run_service(_arguments: Vec<OsString>) -> ResultType<()> {
     }
 
     if !h_process.is_null() {
+        let reason = if SERVICE_SHUTDOWN.load(Ordering::SeqCst) {
+            crate::server::CLOSE_REASON_SHUTDOWN
+        } else {
+            crate::server::CLOSE_REASON_STOP_SERVICE
+        };
+        ipc::close_connections_async(reason).await.ok();
         send_close_async("").await.ok();
         unsafe { CloseHandle(h_process) };
     }
@@ -672,6 +719,29 @@ async fn run_service(_arguments: Vec<OsString>) -> ResultType<()> {
     Ok(())
 }
 
+fn on_server_crash(
+    supervisor: &mut crate::server::server_watchdog::Supervisor,
+    reason: &str,
+    exit_code: DWORD,
+) {
+    let backoff = supervisor.on_crash();
+    log::error!(
+        "The server exited, reason: {}, exit code: {:#x}, crashes: {}, restart in {:?}",
+        reason,
+        exit_code,
+        supervisor.restarts(),
+        backoff
+    );
+    crate::server::Connection::post_alarm_audit(
+        crate::server::AlarmAuditType::ServerRestarted,
+        serde_json::json!({
+            "reason": reason,
+            "exit_code": exit_code,
+            "crashes": supervisor.restarts(),
+        }),
+    );
+}
+
 async fn launch_server(session_id: DWORD, close_first: bool) -> ResultType<HANDLE> {
     if close_first {
         // in case started some elsewhere
@@ -935,6 +1005,20 @@ pub fn is_physical_console_session() -> Option<bool> {
     None
 }
 
+/// Whether the session of the current process is disconnected, e.g. the RDP client is gone.
+/// Nothing is drawn to the displays of a disconnected session.
+pub fn is_current_session_disconnected() -> bool {
+    extern "C" {
+        fn get_session_connect_state(id: u32) -> i32;
+    }
+    // WTSDisconnected of WTS_CONNECTSTATE_CLASS
+    const WTS_DISCONNECTED: i32 = 4;
+    let Some(sid) = get_current_process_session_id() else {
+        return false;
+    };
+    unsafe { get_session_connect_state(sid) == WTS_DISCONNECTED }
+}
+
 pub fn get_active_username() -> String {
     // get_active_user will give console username higher priority
     if let Some(name) = get_current_session_username() {
@@ -1708,6 +1792,22 @@ pub fn block_input(v: bool) -> (bool, String) {
     }
 }
 
+/// Show a notification to the user of the session the process runs in, it blocks until the user
+/// closes it.
+pub fn show_notification(title: &str, text: &str) -> ResultType<()> {
+    let wide = |s: &str| s.encode_utf16().chain(Some(0)).collect::<Vec<u16>>();
+    let (title, text) = (wide(title), wide(text));
+    unsafe {
+        MessageBoxW(
+            std::ptr::null_mut(),
+            text.as_ptr(),
+            title.as_ptr(),
+            MB_OK | MB_ICONINFORMATION | MB_TOPMOST | MB_SETFOREGROUND,
+        );
+    }
+    Ok(())
+}
+
 pub fn add_recent_document(path: &str) {
     extern "C" {
         fn AddRecentDocument(path: *const u16);
@@ -2223,6 +2323,21 @@ pub fn set_path_permission(dir: &Path, permission: &str) -> ResultType<()> {
     Ok(())
 }
 
+// Only the owner, SYSTEM and the administrators keep the access, the inherited entries are
+// removed, e.g. for a private key.
+pub fn set_path_owner_only(path: &Path) -> ResultType<()> {
+    let status = std::process::Command::new("icacls")
+        .arg(path.as_os_str())
+        .args(["/inheritance:r", "/grant:r"])
+        .args(["*S-1-3-4:F", "*S-1-5-18:F", "*S-1-5-32-544:F"])
+        .creation_flags(CREATE_NO_WINDOW)
+        .status()?;
+    if !status.success() {
+        bail!("Failed to restrict the access to {}: {}", path.display(), status);
+    }
+    Ok(())
+}
+
 #[inline]
 fn str_to_device_name(name: &str) -> [u16; 32] {
     let mut device_name: Vec<u16> = wide_string(name);
@@ -2278,6 +2393,22 @@ pub fn current_resolution(name: &str) -> ResultType<Resolution> {
     }
 }
 
+// The orientation of the display in degrees, measured clockwise.
+pub fn current_orientation(name: &str) -> ResultType<i32> {
+    let device_name = str_to_device_name(name);
+    unsafe {
+        let mut dm: DEVMODEW = std::mem::zeroed();
+        dm.dmSize = std::mem::size_of::<DEVMODEW>() as _;
+        if EnumDisplaySettingsW(device_name.as_ptr(), ENUM_CURRENT_SETTINGS, &mut dm) == 0 {
+            bail!(
+                "failed to get current orientation, error {}",
+                io::Error::last_os_error()
+            );
+        }
+        Ok((dm.u1.s2().dmDisplayOrientation % 4) as i32 * 90)
+    }
+}
+
 pub(super) fn change_resolution_directly(
     name: &str,
     width: usize,
@@ -2888,6 +3019,61 @@ impl Drop for WallPaperRemover {
     }
 }
 
+// "Make text bigger" of the accessibility settings, in percent.
+fn open_accessibility_key() -> ResultType<RegKey> {
+    let (hkcu, sid) = if is_root() {
+        let sid = get_current_process_session_id().ok_or(anyhow!("failed to get sid"))?;
+        (RegKey::predef(HKEY_USERS), format!("{}\\", sid))
+    } else {
+        (RegKey::predef(HKEY_CURRENT_USER), "".to_string())
+    };
+    let (key, _) = hkcu.create_subkey_with_flags(
+        &format!("{}Software\\Microsoft\\Accessibility", sid),
+        KEY_READ | KEY_WRITE,
+    )?;
+    Ok(key)
+}
+
+pub fn get_text_scale() -> ResultType<u32> {
+    // The value is absent until the user changes the text size.
+    Ok(open_accessibility_key()?
+        .get_value::<u32, _>("TextScaleFactor")
+        .unwrap_or(100))
+}
+
+pub fn set_text_scale(percent: u32) -> ResultType<()> {
+    open_accessibility_key()?.set_value("TextScaleFactor", &percent)?;
+    // Tell the running applications, some of them only apply it after a restart.
+    let area = wide_string("WindowMetrics");
+    unsafe {
+        SendMessageTimeoutW(
+            HWND_BROADCAST,
+            WM_SETTINGCHANGE,
+            0,
+            area.as_ptr() as _,
+            SMTO_ABORTIFHUNG,
+            1000,
+            null_mut(),
+        );
+    }
+    Ok(())
+}
+
+// The effective dpi of the monitor at the point, 96 is 100%.
+pub fn get_dpi_at(x: i32, y: i32) -> u32 {
+    use winapi::um::shellscalingapi::{GetDpiForMonitor, MDT_EFFECTIVE_DPI};
+    unsafe {
+        let monitor = MonitorFromPoint(POINT { x, y }, MONITOR_DEFAULTTONEAREST);
+        let (mut dpi_x, mut dpi_y) = (0u32, 0u32);
+        if monitor.is_null()
+            || GetDpiForMonitor(monitor, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y) != S_OK
+        {
+            return 96;
+        }
+        dpi_x
+    }
+}
+
 fn get_uninstall_amyuni_idd() -> String {
     match std::env::current_exe() {
         Ok(path) => format!("\"{}\" --uninstall-amyuni-idd", path.to_str().unwrap_or("")),
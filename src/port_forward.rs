@@ -144,6 +144,7 @@ async fn connect_and_login(
                         }
                         Some(message::Union::LoginResponse(lr)) => match lr.union {
                             Some(login_response::Union::Error(err)) => {
+                                let err = crate::error_code::canonical_text(lr.error_code, &err);
                                 if !interface.handle_login_error(&err) {
                                     return Ok(None);
                                 }
@@ -204,6 +204,15 @@ pub extern "system" fn privacy_mode_hook_keyboard(
 
     unsafe {
         if (*ks).dwExtraInfo != enigo::ENIGO_INPUT_EXTRA_VALUE {
+            // The keys are dropped below, the panic button of the host must still see them.
+            let scan_code = if (*ks).flags & LLKHF_EXTENDED == LLKHF_EXTENDED {
+                0xE000 | (*ks).scanCode
+            } else {
+                (*ks).scanCode
+            };
+            let down = matches!(w_param2, WM_KEYDOWN | WM_SYSKEYDOWN);
+            crate::server::panic_button::on_key(rdev::get_win_key((*ks).vkCode, scan_code), down);
+
             // Disable alt key. Alt + Tab will switch windows.
             if (*ks).flags & LLKHF_ALTDOWN == LLKHF_ALTDOWN {
                 return 1;
@@ -21,13 +21,14 @@ use hbb_common::{
     rendezvous_proto::*,
     sleep,
     socket_client::{self, connect_tcp, is_ipv4, new_direct_udp_for, new_udp_for},
-    tokio::{self, select, sync::Mutex, time::interval},
+    tokio::{self, net::TcpListener, select, sync::Mutex, time::interval},
     udp::FramedSocket,
     AddrMangle, IntoTargetAddr, ResultType, Stream, TargetAddr,
 };
 
 use crate::{
     check_port,
+    common::{get_direct_ip_family, IpFamily},
     server::{check_zombie, new as new_server, ServerPtr},
 };
 
@@ -40,6 +41,9 @@ lazy_static::lazy_static! {
 }
 static SHOULD_EXIT: AtomicBool = AtomicBool::new(false);
 static MANUAL_RESTARTED: AtomicBool = AtomicBool::new(false);
+// The tries to bind the direct server port after the server is restarted by the Windows service.
+#[cfg(windows)]
+const MAX_DIRECT_REBINDS: usize = 30;
 
 #[derive(Clone)]
 pub struct RendezvousMediator {
@@ -70,6 +74,7 @@ impl RendezvousMediator {
         }
         check_zombie();
         let server = new_server();
+        crate::server::set_host_server(&server);
         if config::option2bool("stop-service", &Config::get_option("stop-service")) {
             crate::test_rendezvous_server();
         }
@@ -105,8 +110,12 @@ impl RendezvousMediator {
                 for host in servers.clone() {
                     let server = server.clone();
                     futs.push(tokio::spawn(async move {
-                        if let Err(err) = Self::start(server, host).await {
-                            log::error!("rendezvous mediator error: {err}");
+                        if let Err(err) = Self::start(server, host.clone()).await {
+                            crate::common::log_throttled(
+                                &format!("rendezvous-mediator-{host}"),
+                                log::Level::Error,
+                                format!("rendezvous mediator error: {err}"),
+                            );
                         }
                         // SHOULD_EXIT here is to ensure once one exits, the others also exit.
                         SHOULD_EXIT.store(true, Ordering::SeqCst);
@@ -114,7 +123,10 @@ impl RendezvousMediator {
                 }
                 join_all(futs).await;
             } else {
-                server.write().unwrap().close_connections();
+                server
+                    .write()
+                    .unwrap()
+                    .close_connections(crate::server::CLOSE_REASON_STOP_SERVICE);
             }
             Config::reset_online();
             if !MANUAL_RESTARTED.load(Ordering::SeqCst) {
@@ -274,6 +286,7 @@ impl RendezvousMediator {
                 update_latency();
                 match rpr.result.enum_value() {
                     Ok(register_pk_response::Result::OK) => {
+                        log::info!("pk registered to {}", self.host);
                         Config::set_key_confirmed(true);
                         Config::set_host_key_confirmed(&self.host_prefix, true);
                         *SOLVING_PK_MISMATCH.lock().await = "".to_owned();
@@ -536,7 +549,9 @@ impl RendezvousMediator {
         });
         let bytes = msg_out.write_to_bytes()?;
         socket.send_raw(bytes).await?;
-        crate::accept_connection(server.clone(), socket, peer_addr, true).await;
+        if !crate::accept_connection(server.clone(), socket, peer_addr, true).await {
+            bail!("Failed to listen for the direct connection");
+        }
         Ok(())
     }
 
@@ -577,12 +592,12 @@ impl RendezvousMediator {
         use hbb_common::protobuf::Enum;
         let nat_type = NatType::from_i32(Config::get_nat_type()).unwrap_or(NatType::UNKNOWN_NAT);
         let msg_punch = PunchHoleSent {
-            socket_addr: ph.socket_addr,
+            socket_addr: ph.socket_addr.clone(),
             id: Config::get_id(),
-            relay_server,
+            relay_server: relay_server.clone(),
             nat_type: nat_type.into(),
             version: crate::VERSION.to_owned(),
-            socket_addr_v6,
+            socket_addr_v6: socket_addr_v6.clone(),
             ..Default::default()
         };
         if ph.udp_port > 0 {
@@ -603,7 +618,21 @@ impl RendezvousMediator {
         msg_out.set_punch_hole_sent(msg_punch);
         let bytes = msg_out.write_to_bytes()?;
         socket.send_raw(bytes).await?;
-        crate::accept_connection(server.clone(), socket, peer_addr, true).await;
+        if !crate::accept_connection(server.clone(), socket, peer_addr, true).await {
+            log::info!("Failed to listen for the punched connection, fall back to relay");
+            let uuid = Uuid::new_v4().to_string();
+            return self
+                .create_relay(
+                    ph.socket_addr.into(),
+                    relay_server,
+                    uuid,
+                    server,
+                    true,
+                    true,
+                    socket_addr_v6,
+                )
+                .await;
+        }
         Ok(())
     }
 
@@ -713,9 +742,114 @@ fn get_direct_port() -> i32 {
     port
 }
 
+// With systemd socket activation the listener of the direct server is passed as the first fd, so a
+// privileged port works without running as root. The port of the socket unit is used then, not
+// "direct-access-port". A socket which can't serve the ip family of the direct access is not used.
+#[cfg(target_os = "linux")]
+fn take_activated_listener() -> Option<std::net::TcpListener> {
+    const SD_LISTEN_FDS_START: i32 = 3;
+    let pid = std::env::var("LISTEN_PID").ok()?.parse::<u32>().ok()?;
+    let fds = std::env::var("LISTEN_FDS").ok()?.parse::<i32>().ok()?;
+    if pid != std::process::id() || fds < 1 {
+        return None;
+    }
+    // Not inherited by the child processes.
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+    listener_from_fd(SD_LISTEN_FDS_START)
+}
+
+#[cfg(target_os = "linux")]
+fn listener_from_fd(fd: i32) -> Option<std::net::TcpListener> {
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    match listener.local_addr() {
+        Ok(addr) => {
+            log::info!("Direct server got the activated socket on {}", addr);
+        }
+        Err(err) => {
+            // Not a socket, keep the fd open, it is not ours.
+            log::error!("The activated fd is not a listener: {}", err);
+            let _ = listener.into_raw_fd();
+            return None;
+        }
+    }
+    listener.set_nonblocking(true).ok()?;
+    Some(listener)
+}
+
+#[cfg(target_os = "linux")]
+fn is_v6_only(listener: &std::net::TcpListener) -> bool {
+    use hbb_common::libc;
+    use std::os::unix::io::AsRawFd;
+    let mut v6_only: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            listener.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_V6ONLY,
+            &mut v6_only as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    res == 0 && v6_only != 0
+}
+
+// An IPv4 peer can connect to an IPv4 socket, or to a dual-stack one bound to all the addresses.
+#[cfg(target_os = "linux")]
+fn serves_ip_family(listener: &std::net::TcpListener, ip_family: IpFamily) -> bool {
+    let Ok(addr) = listener.local_addr() else {
+        return false;
+    };
+    match (ip_family, addr) {
+        (IpFamily::Any, _) | (IpFamily::V4, SocketAddr::V4(_)) => true,
+        (IpFamily::V6, SocketAddr::V6(_)) => true,
+        (IpFamily::V6, SocketAddr::V4(_)) => false,
+        (IpFamily::V4, SocketAddr::V6(v6)) => v6.ip().is_unspecified() && !is_v6_only(listener),
+    }
+}
+
+async fn listen_direct(port: u16, ip_family: IpFamily) -> ResultType<TcpListener> {
+    #[cfg(target_os = "linux")]
+    {
+        lazy_static::lazy_static! {
+            static ref ACTIVATED_LISTENER: std::sync::Mutex<Option<std::net::TcpListener>> =
+                std::sync::Mutex::new(take_activated_listener());
+        }
+        if let Some(l) = ACTIVATED_LISTENER.lock().unwrap().as_ref() {
+            if serves_ip_family(l, ip_family) {
+                return Ok(TcpListener::from_std(l.try_clone()?)?);
+            }
+            log::error!(
+                "The activated socket on {:?} can't serve the ip family {:?}, listen on port {}",
+                l.local_addr(),
+                ip_family,
+                port
+            );
+        }
+    }
+    let res = match ip_family {
+        IpFamily::Any => hbb_common::tcp::listen_any(port).await,
+        IpFamily::V4 => {
+            hbb_common::tcp::new_listener(SocketAddr::from(([0u8; 4], port)), true).await
+        }
+        IpFamily::V6 => {
+            hbb_common::tcp::new_listener(SocketAddr::from(([0u16; 8], port)), true).await
+        }
+    };
+    res.map_err(|err| crate::server::listen_error(err, port))
+}
+
 async fn direct_server(server: ServerPtr) {
     let mut listener = None;
     let mut port = 0;
+    let mut ip_family = IpFamily::Any;
+    let mut tls = false;
+    let mut acceptor = None;
+    #[cfg(windows)]
+    let mut rebinds = 0;
     loop {
         let disabled = !option2bool(
             OPTION_DIRECT_SERVER,
@@ -723,12 +857,28 @@ async fn direct_server(server: ServerPtr) {
         ) || option2bool("stop-service", &Config::get_option("stop-service"));
         if !disabled && listener.is_none() {
             port = get_direct_port();
-            match hbb_common::tcp::listen_any(port as _).await {
+            ip_family = get_direct_ip_family();
+            tls = crate::tls_tunnel::is_listen_enabled();
+            acceptor = None;
+            if tls {
+                match crate::tls_tunnel::new_acceptor() {
+                    Ok(a) => acceptor = Some(a),
+                    Err(err) => {
+                        // Never fall back to the plain port, it is what the tunnel hides.
+                        log::error!("Failed to set up the TLS of the direct server: {}", err);
+                        sleep(10.).await;
+                        continue;
+                    }
+                }
+            }
+            match listen_direct(port as _, ip_family).await {
                 Ok(l) => {
                     listener = Some(l);
                     log::info!(
-                        "Direct server listening on: {:?}",
-                        listener.as_ref().map(|l| l.local_addr())
+                        "Direct server listening on: {:?}, ip family: {:?}, tls: {}",
+                        listener.as_ref().map(|l| l.local_addr()),
+                        ip_family,
+                        tls
                     );
                 }
                 Err(err) => {
@@ -738,8 +888,18 @@ async fn direct_server(server: ServerPtr) {
                         port,
                         err
                     );
+                    crate::tls_tunnel::on_listener_closed();
                     loop {
-                        if port != get_direct_port() {
+                        if port != get_direct_port() || ip_family != get_direct_ip_family() {
+                            break;
+                        }
+                        // The port may still be held by the server which crashed.
+                        #[cfg(windows)]
+                        if rebinds < MAX_DIRECT_REBINDS
+                            && crate::server::server_watchdog::wait_restarts().await > 0
+                        {
+                            rebinds += 1;
+                            sleep(1.).await;
                             break;
                         }
                         sleep(1.).await;
@@ -748,27 +908,69 @@ async fn direct_server(server: ServerPtr) {
             }
         }
         if let Some(l) = listener.as_mut() {
-            if disabled || port != get_direct_port() {
+            if disabled
+                || port != get_direct_port()
+                || ip_family != get_direct_ip_family()
+                || tls != crate::tls_tunnel::is_listen_enabled()
+            {
                 log::info!("Exit direct access listen");
                 listener = None;
+                crate::tls_tunnel::on_listener_closed();
                 continue;
             }
             if let Ok(Ok((stream, addr))) = hbb_common::timeout(1000, l.accept()).await {
+                if !ip_family.matches(&addr) {
+                    log::info!(
+                        "Reject direct access from {}, ip family: {:?}",
+                        addr,
+                        ip_family
+                    );
+                    continue;
+                }
                 stream.set_nodelay(true).ok();
                 log::info!("direct access from {}", addr);
                 let local_addr = stream
                     .local_addr()
                     .unwrap_or(Config::get_any_listen_addr(true));
                 let server = server.clone();
+                let acceptor = acceptor.clone();
+                // The TLS handshakes count against the connections waiting for the login, a
+                // peer can't hold the accept slots with handshakes which never finish.
+                let handshake = if acceptor.is_some() {
+                    let ip = hbb_common::try_into_v4(addr).ip().to_string();
+                    let Some(pending) = crate::server::PendingAuthID::try_new_handshake(ip) else {
+                        crate::common::log_throttled(
+                            "pending-auth-limit",
+                            log::Level::Warn,
+                            format!(
+                                "Refuse the direct access from {}, too many connections waiting \
+                                 for the login",
+                                addr
+                            ),
+                        );
+                        continue;
+                    };
+                    Some(pending)
+                } else {
+                    None
+                };
                 tokio::spawn(async move {
+                    let stream = if let Some(acceptor) = acceptor {
+                        let res = crate::tls_tunnel::accept(&acceptor, stream, local_addr).await;
+                        // The connection takes its own slot.
+                        drop(handshake);
+                        match res {
+                            Ok(stream) => stream,
+                            Err(err) => {
+                                log::info!("TLS handshake with {} failed: {}", addr, err);
+                                return;
+                            }
+                        }
+                    } else {
+                        hbb_common::Stream::from(stream, local_addr)
+                    };
                     allow_err!(
-                        crate::server::create_tcp_connection(
-                            server,
-                            hbb_common::Stream::from(stream, local_addr),
-                            addr,
-                            false,
-                        )
-                        .await
+                        crate::server::create_tcp_connection(server, stream, addr, false).await
                     );
                 });
             } else {
@@ -839,3 +1041,54 @@ async fn udp_nat_listen(
     })?;
     Ok(())
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use std::os::unix::io::{AsRawFd, IntoRawFd};
+
+    #[test]
+    fn test_activated_ip_family() {
+        let v4 = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        assert!(serves_ip_family(&v4, IpFamily::Any));
+        assert!(serves_ip_family(&v4, IpFamily::V4));
+        assert!(!serves_ip_family(&v4, IpFamily::V6));
+        // No IPv6 in some sandboxes.
+        if let Ok(v6) = std::net::TcpListener::bind("[::1]:0") {
+            assert!(serves_ip_family(&v6, IpFamily::V6));
+            assert!(!serves_ip_family(&v6, IpFamily::V4));
+        }
+        if let Ok(any) = std::net::TcpListener::bind("[::]:0") {
+            assert!(serves_ip_family(&any, IpFamily::V6));
+            assert_eq!(serves_ip_family(&any, IpFamily::V4), !is_v6_only(&any));
+        }
+    }
+
+    #[test]
+    fn test_listener_from_fd() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = listener_from_fd(listener.into_raw_fd()).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), addr);
+        // The fd of a file is left open.
+        let file = std::fs::File::open("/proc/self/status").unwrap();
+        assert!(listener_from_fd(file.as_raw_fd()).is_none());
+        assert!(file.metadata().is_ok());
+    }
+
+    #[test]
+    fn test_listen_error() {
+        let denied = || {
+            hbb_common::anyhow::Error::from(std::io::Error::from(
+                std::io::ErrorKind::PermissionDenied,
+            ))
+        };
+        let err = crate::server::listen_error(denied(), 80).to_string();
+        assert!(err.contains("CAP_NET_BIND_SERVICE"), "{}", err);
+        let err = crate::server::listen_error(denied(), 8080).to_string();
+        assert!(!err.contains("CAP_NET_BIND_SERVICE"), "{}", err);
+        let in_use = std::io::Error::from(std::io::ErrorKind::AddrInUse);
+        let err = crate::server::listen_error(in_use.into(), 80).to_string();
+        assert!(!err.contains("CAP_NET_BIND_SERVICE"), "{}", err);
+    }
+}
@@ -1,8 +1,11 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     net::SocketAddr,
-    sync::{Arc, Mutex, RwLock, Weak},
-    time::Duration,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, Weak,
+    },
+    time::{Duration, Instant},
 };
 
 use bytes::Bytes;
@@ -24,6 +27,7 @@ use hbb_common::{
     sodiumoxide::crypto::{box_, sign},
     timeout, tokio, ResultType, Stream,
 };
+use crate::error_code::{CodedError, ErrorCode};
 use scrap::camera;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use service::ServiceTmpl;
@@ -32,6 +36,10 @@ use video_service::VideoSource;
 
 use crate::ipc::Data;
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod audio_device_watch;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod audio_exclusion;
 pub mod audio_service;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub mod terminal_service;
@@ -64,14 +72,66 @@ pub mod input_service {
     pub const NAME_WINDOW_FOCUS: &'static str = "";
 }
 
+pub mod access_policy;
+pub mod auth_provider;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod battery_saver;
+pub mod capture_env;
+pub mod client_visibility;
+pub mod clipboard_policy;
 mod connection;
+pub mod cursor_batch;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod diagnostic_capture;
+pub mod dir_listing;
+pub mod display_filter;
 pub mod display_service;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod file_scan;
+pub mod headless_mode;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod host_key_listener;
+pub mod host_priority;
+pub mod input_control;
+pub mod message_limits;
+pub mod network_sim;
+pub mod option_journal;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod panic_button;
 #[cfg(windows)]
 pub mod portable_service;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod preview;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod push_to_talk;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod quick_actions;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod scroll_gesture;
+mod send_lanes;
+pub mod sensitive_events;
+#[cfg(windows)]
+pub mod server_watchdog;
 mod service;
+pub mod service_monitor;
+pub mod session_clock;
+pub mod session_report;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod session_restore;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod session_temp;
+#[cfg(test)]
+mod tests;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod text_scale;
 mod video_qos;
 pub mod video_service;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod virtual_mic;
+pub mod voice_call;
 
+#[cfg(all(target_os = "windows", feature = "flutter"))]
+pub mod printer_queue;
 #[cfg(all(target_os = "windows", feature = "flutter"))]
 pub mod printer_service;
 
@@ -83,6 +143,10 @@ const CONFIG_SYNC_INTERVAL_SECS: f32 = 0.3;
 
 lazy_static::lazy_static! {
     pub static ref CHILD_PROCESS: Childs = Default::default();
+    // The exit status of the child processes added by `track_child`, by pid, they may be reaped by
+    // `check_zombie` or the teardown before they are waited for.
+    static ref CHILD_EXITS: Mutex<HashMap<u32, Option<std::process::ExitStatus>>> = Default::default();
+    static ref MEDIA_CLOCK: Instant = Instant::now();
     // A client server used to provide local services(audio, video, clipboard, etc.)
     // for all initiative connections.
     //
@@ -91,24 +155,182 @@ lazy_static::lazy_static! {
     // Now we use this [`CLIENT_SERVER`] to do following operations:
     // - record local audio, and send to remote
     pub static ref CLIENT_SERVER: ServerPtr = new();
+    // The server of the incoming connections, for the shutdown paths.
+    static ref HOST_SERVER: Mutex<ServerPtrWeak> = Default::default();
 }
 
+// The close reasons shown to the peers when the host ends the connections.
+pub const CLOSE_REASON_STOP_SERVICE: ErrorCode = ErrorCode::StopService;
+pub const CLOSE_REASON_SHUTDOWN: ErrorCode = ErrorCode::HostShutdown;
+pub const CLOSE_REASON_ADMIN: ErrorCode = ErrorCode::ClosedByAdmin;
+pub const CLOSE_REASON_TERMINATED: ErrorCode = ErrorCode::TerminatedByHost;
+// How long the shutdown waits for the connections to send the close reason and exit.
+const CLOSE_WAIT: Duration = Duration::from_secs(2);
+const CLOSE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 pub struct Server {
     connections: ConnMap,
     services: HashMap<String, Box<dyn Service>>,
     id_count: i32,
+    // The connections which passed the re-authentication, it lasts for the session.
+    reauth_granted: HashSet<i32>,
+    // The privileged services waiting for the re-authentication of the connection.
+    reauth_pending: HashMap<i32, (ConnInner, HashSet<String>)>,
+    // The monitors the connections are restricted to, see `display_filter`.
+    allowed_displays: HashMap<i32, Vec<usize>>,
+    // The reason sent to the peers by `close_connections`.
+    close_reason: ErrorCode,
 }
 
 pub type ServerPtr = Arc<RwLock<Server>>;
 pub type ServerPtrWeak = Weak<RwLock<Server>>;
 
+/// The capture time of the audio and video frames in microseconds. One monotonic clock for both,
+/// so the peer can align them. It starts from 1, 0 in a frame means it is not provided.
+pub fn media_capture_time() -> i64 {
+    MEDIA_CLOCK.elapsed().as_micros() as i64 + 1
+}
+
+// Comma separated names of the services which require a re-authentication to be subscribed,
+// e.g. "mouse_cursor,mouse_pos". Empty means none.
+const OPTION_REAUTH_SERVICES: &str = "reauth-services";
+
+fn is_reauth_service(name: &str) -> bool {
+    Config::get_option(OPTION_REAUTH_SERVICES)
+        .split(',')
+        .any(|x| !x.trim().is_empty() && x.trim() == name)
+}
+
+// The max number of the video services (displays and cameras) running at the same time,
+// 0 or empty means unlimited.
+const OPTION_MAX_VIDEO_SERVICES: &str = "max-video-services";
+// The max number of the connections subscribed to one video service (display or camera),
+// 0 or empty means unlimited.
+const OPTION_MAX_VIDEO_SUBSCRIBERS: &str = "max-video-subscribers";
+// Refuse the connections whose stream is not encrypted, e.g. the key pair is broken
+// or the peer skips the key exchange. The secure handshake fails closed instead of
+// falling back to plain text.
+pub const OPTION_REQUIRE_ENCRYPTION: &str = "require-encryption";
+// Comma separated names of the services in the order a new connection subscribes to them,
+// "video" stands for the video services. The services not listed follow in the default order.
+const OPTION_SERVICE_ORDER: &str = "service-order";
+// The display service goes first, see `WAIT_READY_SERVICES`.
+const DEFAULT_SERVICE_ORDER: &[&str] = &[
+    "display",
+    "video",
+    "audio",
+    "clipboard",
+    "file-clipboard",
+    "mouse_cursor",
+    "mouse_pos",
+    "window_focus",
+];
+
+// A new connection waits for these services to take its snapshot before the next ones are
+// subscribed, so the input doesn't target a display which is not enumerated yet, e.g. on the
+// first connection on Wayland. The order alone doesn't do it, the services run in their threads.
+const WAIT_READY_SERVICES: &[&str] = &[display_service::NAME];
+const READY_TIMEOUT_MS: u64 = 1_000;
+
+/// Sort the service names by `order`, then by `DEFAULT_SERVICE_ORDER`, then by the name.
+fn sort_services(names: &mut [String], order: &str) {
+    let mut ranks: Vec<&str> = order
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .collect();
+    for x in DEFAULT_SERVICE_ORDER {
+        if !ranks.contains(x) {
+            ranks.push(x);
+        }
+    }
+    names.sort_by_cached_key(|name| {
+        let key = if Server::is_video_service_name(name) {
+            "video"
+        } else {
+            name.as_str()
+        };
+        let rank = ranks.iter().position(|x| *x == key).unwrap_or(ranks.len());
+        (rank, name.clone())
+    });
+}
+
+/// The services of a new connection which are left to subscribe after `name` is ready,
+/// see `subscribe_pending`.
+pub struct PendingSubscribes {
+    name: String,
+    ready: tokio::sync::oneshot::Receiver<()>,
+    names: Vec<String>,
+}
+
+/// Wait for the service the connection is blocked on without the lock of the server, then
+/// subscribe the next services, until all of them are subscribed.
+pub async fn subscribe_pending(
+    server: ServerPtrWeak,
+    conn: ConnInner,
+    mut pending: PendingSubscribes,
+) {
+    loop {
+        if timeout(READY_TIMEOUT_MS, &mut pending.ready).await.is_err() {
+            log::warn!(
+                "#{} service {} is not ready in {}ms",
+                conn.id(),
+                pending.name,
+                READY_TIMEOUT_MS
+            );
+        }
+        let Some(s) = server.upgrade() else {
+            return;
+        };
+        let next = {
+            let mut lock = s.write().unwrap();
+            // Closed while waiting.
+            if !lock.connections.contains_key(&conn.id()) {
+                return;
+            }
+            let next = lock.subscribe_in_order(&conn, pending.names);
+            // The video services may be among them.
+            let displays = lock.get_subbed_displays(conn.id());
+            video_service::VIDEO_QOS
+                .lock()
+                .unwrap()
+                .user_displays(conn.id(), displays);
+            next
+        };
+        match next {
+            Some(next) => pending = next,
+            None => return,
+        }
+    }
+}
+
+fn get_max_video_services() -> usize {
+    Config::get_option(OPTION_MAX_VIDEO_SERVICES)
+        .parse()
+        .unwrap_or(0)
+}
+
+fn get_max_video_subscribers() -> usize {
+    Config::get_option(OPTION_MAX_VIDEO_SUBSCRIBERS)
+        .parse()
+        .unwrap_or(0)
+}
+
 pub fn new() -> ServerPtr {
     let mut server = Server {
         connections: HashMap::new(),
         services: HashMap::new(),
         id_count: hbb_common::rand::random::<i32>() % 1000 + 1000, // ensure positive
+        reauth_granted: Default::default(),
+        allowed_displays: Default::default(),
+        reauth_pending: Default::default(),
+        close_reason: ErrorCode::ClosedByPeer,
     };
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    std::thread::spawn(session_temp::sweep_orphans);
     server.add_service(Box::new(audio_service::new()));
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    audio_device_watch::start();
     #[cfg(not(target_os = "ios"))]
     {
         server.add_service(Box::new(display_service::new()));
@@ -149,16 +371,104 @@ pub fn new() -> ServerPtr {
         }
     }
     // Terminal service is created per connection, not globally
-    Arc::new(RwLock::new(server))
+    let server = Arc::new(RwLock::new(server));
+    service_monitor::start(Arc::downgrade(&server));
+    server
 }
 
-async fn accept_connection_(server: ServerPtr, socket: Stream, secure: bool) -> ResultType<()> {
-    let local_addr = socket.local_addr();
-    drop(socket);
-    // even we drop socket, below still may fail if not use reuse_addr,
-    // there is TIME_WAIT before socket really released, so sometimes we
-    // see “Only one usage of each socket address is normally permitted” on windows sometimes,
-    let listener = new_listener(local_addr, true).await?;
+// The direct connection is accepted on the local address of the connection to the rendezvous
+// server, which is dropped just before. Even with reuse_addr the address may not be released yet,
+// there is TIME_WAIT, so we see “Only one usage of each socket address is normally permitted” on
+// windows sometimes. The bind is retried with a backoff within the window of the punch hole, then
+// the caller falls back to a relay.
+const RELISTEN_WINDOW: Duration = Duration::from_secs(2);
+const RELISTEN_FIRST_BACKOFF: Duration = Duration::from_millis(20);
+const RELISTEN_MAX_BACKOFF: Duration = Duration::from_millis(320);
+
+// How often the bind is retried and fails, to tell if the retries are enough in the field.
+static RELISTENS: AtomicUsize = AtomicUsize::new(0);
+static RELISTENS_RETRIED: AtomicUsize = AtomicUsize::new(0);
+static RELISTENS_FAILED: AtomicUsize = AtomicUsize::new(0);
+
+/// (listens, listens which needed a retry, listens which failed and fell back to a relay)
+pub fn relisten_stats() -> (usize, usize, usize) {
+    (
+        RELISTENS.load(Ordering::Relaxed),
+        RELISTENS_RETRIED.load(Ordering::Relaxed),
+        RELISTENS_FAILED.load(Ordering::Relaxed),
+    )
+}
+
+fn is_permission_denied(err: &hbb_common::anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .map(|e| e.kind() == std::io::ErrorKind::PermissionDenied)
+        .unwrap_or(false)
+}
+
+// Binding a port below 1024 needs root or CAP_NET_BIND_SERVICE on Linux, the io error only says
+// "Permission denied".
+pub(crate) fn listen_error(err: hbb_common::anyhow::Error, port: u16) -> hbb_common::anyhow::Error {
+    if cfg!(unix) && port < 1024 && is_permission_denied(&err) {
+        return hbb_common::anyhow::anyhow!(
+            "Permission denied to listen on port {}, it needs privilege (root or CAP_NET_BIND_SERVICE) or a port >= 1024",
+            port
+        );
+    }
+    err
+}
+
+async fn relisten(local_addr: SocketAddr) -> ResultType<tokio::net::TcpListener> {
+    RELISTENS.fetch_add(1, Ordering::Relaxed);
+    let start = Instant::now();
+    let mut backoff = RELISTEN_FIRST_BACKOFF;
+    let mut retries = 0;
+    loop {
+        match new_listener(local_addr, true).await {
+            Ok(listener) => {
+                if retries > 0 {
+                    RELISTENS_RETRIED.fetch_add(1, Ordering::Relaxed);
+                    log::info!(
+                        "Listen on {} after {} retries in {:?}",
+                        local_addr,
+                        retries,
+                        start.elapsed()
+                    );
+                }
+                return Ok(listener);
+            }
+            // Retrying can't help.
+            Err(err) if is_permission_denied(&err) => {
+                RELISTENS_FAILED.fetch_add(1, Ordering::Relaxed);
+                return Err(listen_error(err, local_addr.port()));
+            }
+            Err(err) if start.elapsed() + backoff < RELISTEN_WINDOW => {
+                log::debug!("Failed to listen on {}, retry: {}", local_addr, err);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(RELISTEN_MAX_BACKOFF);
+                retries += 1;
+            }
+            Err(err) => {
+                RELISTENS_FAILED.fetch_add(1, Ordering::Relaxed);
+                log::warn!(
+                    "Relisten stats (listens, retried, failed): {:?}",
+                    relisten_stats()
+                );
+                bail!(
+                    "Failed to listen on {} after {} retries: {}",
+                    local_addr,
+                    retries,
+                    err
+                );
+            }
+        }
+    }
+}
+
+async fn accept_connection_(
+    server: ServerPtr,
+    listener: tokio::net::TcpListener,
+    secure: bool,
+) -> ResultType<()> {
     log::info!("Server listening on: {}", &listener.local_addr()?);
     if let Ok((stream, addr)) = timeout(CONNECT_TIMEOUT, listener.accept()).await? {
         stream.set_nodelay(true).ok();
@@ -173,16 +483,64 @@ pub async fn create_tcp_connection(
     stream: Stream,
     addr: SocketAddr,
     secure: bool,
+) -> ResultType<()> {
+    create_tcp_connection_(server, stream, addr, secure, false).await
+}
+
+async fn create_tcp_connection_(
+    server: ServerPtr,
+    stream: Stream,
+    addr: SocketAddr,
+    secure: bool,
+    relay: bool,
 ) -> ResultType<()> {
     let mut stream = stream;
+    // The banned peers are refused before the handshake and taking an id.
+    let ip = hbb_common::try_into_v4(addr).ip().to_string();
+    if let Some(err) = Connection::check_banned(&ip) {
+        let err = CodedError::from(err);
+        log::warn!("Refuse the connection from {}: {}", addr, err.text);
+        send_plain_login_error(&mut stream, &err).await?;
+        bail!("{}", err.text);
+    }
     let id = server.write().unwrap().get_new_id();
+    let Some(pending_auth) = PendingAuthID::try_new(id, ip) else {
+        crate::common::log_throttled(
+            "pending-auth-limit",
+            log::Level::Warn,
+            format!(
+                "Refuse the connection from {}, too many connections waiting for the login",
+                addr
+            ),
+        );
+        bail!("Too many connections waiting for the login");
+    };
+    let handshake_start = Instant::now();
+    let mut key_refreshed = false;
     let (sk, pk) = Config::get_key_pair();
-    if secure && pk.len() == sign::PUBLICKEYBYTES && sk.len() == sign::SECRETKEYBYTES {
+    let require_encryption = Config::get_option(OPTION_REQUIRE_ENCRYPTION) == "Y";
+    let key_pair_ok = pk.len() == sign::PUBLICKEYBYTES && sk.len() == sign::SECRETKEYBYTES;
+    if secure && !key_pair_ok && require_encryption {
+        return refuse_unencrypted(&mut stream, &addr, "the key pair is malformed").await;
+    }
+    let secure = secure && key_pair_ok;
+    let hash = Hash {
+        salt: Config::get_salt(),
+        challenge: Config::get_auto_password(6),
+        // The peer only sends it on the encrypted connection.
+        plain_password: secure && !auth_provider::is_local(),
+        ..Default::default()
+    };
+    let mut fingerprint = None;
+    // Set with the key, a peer may skip the key exchange on a secure connection.
+    let mut encrypted = false;
+    if secure {
+        let host_sign_pk = pk.clone();
         let mut sk_ = [0u8; sign::SECRETKEYBYTES];
         sk_[..].copy_from_slice(&sk);
         let sk = sign::SecretKey(sk_);
         let mut msg_out = Message::new();
-        let (our_pk_b, our_sk_b) = box_::gen_keypair();
+        let (our_pk_b, our_sk_b) = crate::common::gen_box_keypair();
         msg_out.set_signed_id(SignedId {
             id: sign::sign(
                 &IdPk {
@@ -195,62 +553,271 @@ pub async fn create_tcp_connection(
                 &sk,
             )
             .into(),
+            // The early data, a client with a remembered password sends the login request right
+            // after the public key instead of waiting for the hash. The signed id is plain text,
+            // so only the challenge of this connection goes here, the salt is sent encrypted in
+            // the hash after the handshake.
+            challenge: hash.challenge.clone(),
+            capabilities: crate::common::Capabilities::local().bits(),
+            version: crate::VERSION.to_owned(),
             ..Default::default()
         });
         timeout(CONNECT_TIMEOUT, stream.send(&msg_out)).await??;
-        match timeout(CONNECT_TIMEOUT, stream.next()).await? {
-            Some(res) => {
-                let bytes = res?;
-                if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
-                    if let Some(message::Union::PublicKey(pk)) = msg_in.union {
-                        if pk.asymmetric_value.len() == box_::PUBLICKEYBYTES {
-                            stream.set_key(tcp::Encrypt::decode(
-                                &pk.symmetric_value,
-                                &pk.asymmetric_value,
-                                &our_sk_b,
-                            )?);
-                        } else if pk.asymmetric_value.is_empty() {
-                            Config::set_key_confirmed(false);
-                            log::info!("Force to update pk");
-                        } else {
-                            bail!("Handshake failed: invalid public sign key length from peer");
-                        }
-                    } else {
-                        log::error!("Handshake failed: invalid message type");
-                    }
-                } else {
-                    bail!("Handshake failed: invalid message format");
+        let msg_in = next_handshake_msg(&mut stream, &addr).await?;
+        if let Some(message::Union::PublicKey(pk)) = msg_in.union {
+            if pk.asymmetric_value.len() == box_::PUBLICKEYBYTES {
+                stream.set_key(tcp::Encrypt::decode(
+                    &pk.symmetric_value,
+                    &pk.asymmetric_value,
+                    &our_sk_b,
+                )?);
+                encrypted = true;
+                fingerprint = Some(crate::session_fingerprint::fingerprint(
+                    &host_sign_pk,
+                    &our_pk_b.0,
+                    &pk.asymmetric_value,
+                ));
+            } else if pk.asymmetric_value.is_empty() {
+                Config::set_key_confirmed(false);
+                key_refreshed = true;
+                log::info!("Force to update pk");
+                if require_encryption {
+                    return refuse_unencrypted(
+                        &mut stream,
+                        &addr,
+                        "the peer skips the key exchange",
+                    )
+                    .await;
                 }
+            } else {
+                bail!("Handshake failed: invalid public sign key length from peer");
             }
-            None => {
-                bail!("Failed to receive public key");
+        } else {
+            log::error!("Handshake failed: invalid message type");
+            if require_encryption {
+                return refuse_unencrypted(&mut stream, &addr, "the peer skips the key exchange")
+                    .await;
             }
         }
     }
+    on_handshake_done(&addr, handshake_start.elapsed(), encrypted, key_refreshed);
 
     #[cfg(target_os = "macos")]
-    {
-        use std::process::Command;
-        Command::new("/usr/bin/caffeinate")
-            .arg("-u")
-            .arg("-t 5")
-            .spawn()
-            .ok();
-        log::info!("wake up macos");
-    }
-    Connection::start(addr, stream, id, Arc::downgrade(&server)).await;
+    crate::platform::macos::wake_on_connect();
+    Connection::start(
+        addr,
+        stream,
+        id,
+        Arc::downgrade(&server),
+        relay,
+        hash,
+        pending_auth,
+        fingerprint,
+    )
+    .await;
     Ok(())
 }
 
+// The peer gets the reason, it is in the handshake if it waits for the signed id.
+async fn refuse_unencrypted(
+    stream: &mut Stream,
+    addr: &SocketAddr,
+    reason: &str,
+) -> ResultType<()> {
+    log::error!(
+        "Refuse the connection from {}, encryption is required but {}",
+        addr,
+        reason
+    );
+    send_plain_login_error(stream, &ErrorCode::EncryptionRequired.into()).await?;
+    bail!("Encryption is required but {}", reason);
+}
+
+// The login error before the handshake, it's not encrypted.
+async fn send_plain_login_error(stream: &mut Stream, err: &CodedError) -> ResultType<()> {
+    let mut msg_out = Message::new();
+    msg_out.set_login_response(LoginResponse {
+        union: Some(login_response::Union::Error(err.text.clone())),
+        error_code: err.code.code(),
+        ..Default::default()
+    });
+    timeout(CONNECT_TIMEOUT, stream.send(&msg_out)).await??;
+    Ok(())
+}
+
+// The public key message may come split in several frames on a lossy link. The frames are
+// joined until the message is complete, within the handshake timeout and a size bound, while a
+// message which is invalid rather than truncated is rejected at once.
+const MAX_HANDSHAKE_FRAMES: usize = 8;
+const MAX_HANDSHAKE_MSG_SIZE: usize = 4096;
+
+async fn next_handshake_msg(stream: &mut Stream, addr: &SocketAddr) -> ResultType<Message> {
+    let deadline = Instant::now() + Duration::from_millis(CONNECT_TIMEOUT);
+    let mut buf = Vec::new();
+    let mut frames = 0;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let Some(res) = timeout(remaining.as_millis() as _, stream.next()).await? else {
+            bail!("Failed to receive public key");
+        };
+        buf.extend_from_slice(&res?);
+        frames += 1;
+        match Message::parse_from_bytes(&buf) {
+            Ok(msg) => {
+                if frames > 1 {
+                    log::info!(
+                        "Handshake message from {} completed by {} frames, {} bytes",
+                        addr,
+                        frames,
+                        buf.len()
+                    );
+                }
+                return Ok(msg);
+            }
+            Err(e)
+                if is_truncated(&buf)
+                    && frames < MAX_HANDSHAKE_FRAMES
+                    && buf.len() < MAX_HANDSHAKE_MSG_SIZE =>
+            {
+                log::warn!(
+                    "Handshake message from {} is truncated at {} bytes, keep reading: {}",
+                    addr,
+                    buf.len(),
+                    e
+                );
+            }
+            Err(e) => {
+                log::error!(
+                    "Handshake message from {} is invalid, {} bytes in {} frames: {}",
+                    addr,
+                    buf.len(),
+                    frames,
+                    e
+                );
+                bail!("Handshake failed: invalid message format");
+            }
+        }
+    }
+}
+
+// Walks the top level fields of the message on the wire, true if the last one is cut off. A
+// message whose fields are all complete, or which declares a field beyond the size bound, is
+// invalid if it doesn't parse.
+fn is_truncated(buf: &[u8]) -> bool {
+    let mut i = 0;
+    while i < buf.len() {
+        let key = match read_varint(&buf[i..]) {
+            Ok(Some((key, n))) => {
+                i += n;
+                key
+            }
+            Ok(None) => return true,
+            Err(_) => return false,
+        };
+        if key >> 3 == 0 {
+            return false;
+        }
+        let len = match key & 7 {
+            0 | 2 => match read_varint(&buf[i..]) {
+                Ok(Some((v, n))) if key & 7 == 2 => {
+                    i += n;
+                    v as usize
+                }
+                Ok(Some((_, n))) => n,
+                Ok(None) => return true,
+                Err(_) => return false,
+            },
+            1 => 8,
+            5 => 4,
+            _ => return false,
+        };
+        if len > MAX_HANDSHAKE_MSG_SIZE.saturating_sub(i) {
+            return false;
+        }
+        if buf.len() - i < len {
+            return true;
+        }
+        i += len;
+    }
+    false
+}
+
+// Ok(None) if the buffer ends within the varint.
+fn read_varint(buf: &[u8]) -> Result<Option<(u64, usize)>, ()> {
+    let mut v = 0u64;
+    for (i, b) in buf.iter().take(10).enumerate() {
+        v |= ((b & 0x7f) as u64) << (7 * i);
+        if b & 0x80 == 0 {
+            return Ok(Some((v, i + 1)));
+        }
+    }
+    if buf.len() < 10 {
+        Ok(None)
+    } else {
+        Err(())
+    }
+}
+
+// Without a valid key pair every connection falls back to plain text, which is easy to miss.
+fn check_key_pair() {
+    let (sk, pk) = Config::get_key_pair();
+    if pk.len() != sign::PUBLICKEYBYTES || sk.len() != sign::SECRETKEYBYTES {
+        log::error!(
+            "!!! The key pair is malformed (pk: {} bytes, sk: {} bytes), connections are NOT encrypted{} !!!",
+            pk.len(),
+            sk.len(),
+            if Config::get_option(OPTION_REQUIRE_ENCRYPTION) == "Y" {
+                " and will be refused"
+            } else {
+                ""
+            }
+        );
+    } else {
+        log::info!("Key fingerprint: {}", crate::common::pk_to_fingerprint(pk));
+    }
+}
+
+fn on_handshake_done(addr: &SocketAddr, elapsed: Duration, secure: bool, key_refreshed: bool) {
+    log::info!(
+        "Handshake with {} done in {:?}, secure: {}, key refreshed: {}",
+        addr,
+        elapsed,
+        secure,
+        key_refreshed
+    );
+    #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    crate::plugin::handle_listen_event_with_data(
+        crate::plugin::EVENT_ON_HANDSHAKE_SERVER.to_owned(),
+        addr.to_string(),
+        serde_json::json!({
+            "duration_ms": elapsed.as_millis() as u64,
+            "secure": secure,
+            "key_refreshed": key_refreshed,
+        }),
+    );
+}
+
+/// Returns false if the local address can't be listened on, the caller may fall back to a relay.
 pub async fn accept_connection(
     server: ServerPtr,
     socket: Stream,
     peer_addr: SocketAddr,
     secure: bool,
-) {
-    if let Err(err) = accept_connection_(server, socket, secure).await {
+) -> bool {
+    let local_addr = socket.local_addr();
+    drop(socket);
+    let listener = match relisten(local_addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            log::warn!("Failed to accept connection from {}: {}", peer_addr, err);
+            return false;
+        }
+    };
+    if let Err(err) = accept_connection_(server, listener, secure).await {
         log::warn!("Failed to accept connection from {}: {}", peer_addr, err);
     }
+    true
 }
 
 pub async fn create_relay_connection(
@@ -264,12 +831,16 @@ pub async fn create_relay_connection(
     if let Err(err) =
         create_relay_connection_(server, relay_server, uuid.clone(), peer_addr, secure, ipv4).await
     {
-        log::error!(
-            "Failed to create relay connection for {} with uuid {}: {}",
-            peer_addr,
-            uuid,
-            err
+        // The uuid is not in the throttled message, so retries of the same failure are collapsed.
+        crate::common::log_throttled(
+            "create-relay-connection",
+            log::Level::Error,
+            format!(
+                "Failed to create relay connection for {}: {}",
+                peer_addr, err
+            ),
         );
+        log::debug!("Relay connection uuid: {}", uuid);
     }
 }
 
@@ -281,6 +852,13 @@ async fn create_relay_connection_(
     secure: bool,
     ipv4: bool,
 ) -> ResultType<()> {
+    let stream = connect_relay(relay_server, uuid, ipv4).await?;
+    create_tcp_connection_(server, stream, peer_addr, secure, true).await?;
+    Ok(())
+}
+
+// The stream to the relay, which pairs it with the one of the peer by the uuid.
+async fn connect_relay(relay_server: String, uuid: String, ipv4: bool) -> ResultType<Stream> {
     let mut stream = socket_client::connect_tcp(
         socket_client::ipv4_to_ipv6(crate::check_port(relay_server, RELAY_PORT), ipv4),
         CONNECT_TIMEOUT,
@@ -294,14 +872,14 @@ async fn create_relay_connection_(
         ..Default::default()
     });
     stream.send(&msg_out).await?;
-    create_tcp_connection(server, stream, peer_addr, secure).await?;
-    Ok(())
+    Ok(stream)
 }
 
 impl Server {
     fn is_video_service_name(name: &str) -> bool {
         name.starts_with(VideoSource::Monitor.service_name_prefix())
             || name.starts_with(VideoSource::Camera.service_name_prefix())
+            || name.starts_with(VideoSource::Window.service_name_prefix())
     }
 
     pub fn try_add_primary_camera_service(&mut self) {
@@ -342,22 +920,77 @@ impl Server {
         self.connections.insert(conn.id(), conn);
     }
 
-    pub fn add_connection(&mut self, conn: ConnInner, noperms: &Vec<&'static str>) {
+    /// The services after the first one in `WAIT_READY_SERVICES` are returned, they are
+    /// subscribed by `subscribe_pending` when it's ready.
+    pub fn add_connection(
+        &mut self,
+        conn: ConnInner,
+        noperms: &Vec<&'static str>,
+    ) -> Option<PendingSubscribes> {
         let primary_video_service_name = video_service::get_service_name(
             VideoSource::Monitor,
             *display_service::PRIMARY_DISPLAY_IDX,
         );
-        for s in self.services.values() {
-            let name = s.name();
-            if Self::is_video_service_name(&name) && name != primary_video_service_name {
+        let mut names: Vec<String> = self
+            .services
+            .keys()
+            .filter(|name| {
+                !(Self::is_video_service_name(name) && **name != primary_video_service_name)
+                    && !noperms.contains(&name.as_str())
+            })
+            .cloned()
+            .collect();
+        // The services are stored in a map, subscribe in an explicit order.
+        sort_services(&mut names, &Config::get_option(OPTION_SERVICE_ORDER));
+        let pending = self.subscribe_in_order(&conn, names);
+        #[cfg(target_os = "macos")]
+        self.update_enable_retina();
+        self.connections.insert(conn.id(), conn);
+        #[cfg(target_os = "linux")]
+        self.update_session_inhibit();
+        pending
+    }
+
+    // Stops after a service in `WAIT_READY_SERVICES` which has not sent the snapshot yet.
+    fn subscribe_in_order(
+        &mut self,
+        conn: &ConnInner,
+        names: Vec<String>,
+    ) -> Option<PendingSubscribes> {
+        let mut names = names.into_iter();
+        while let Some(name) = names.next() {
+            if !self.is_display_allowed(&name, conn.id()) {
+                continue;
+            }
+            if self.defer_for_reauth(&name, conn) {
                 continue;
             }
-            if !noperms.contains(&(&name as _)) {
+            if self.is_at_capacity(&name, conn) {
+                continue;
+            }
+            if let Some(s) = self.services.get(&name) {
                 s.on_subscribe(conn.clone());
+                if WAIT_READY_SERVICES.contains(&name.as_str()) && !s.is_ready(conn.id()) {
+                    let names: Vec<String> = names.collect();
+                    if names.is_empty() {
+                        return None;
+                    }
+                    return Some(PendingSubscribes {
+                        ready: s.ready(conn.id()),
+                        name,
+                        names,
+                    });
+                }
             }
         }
-        #[cfg(target_os = "macos")]
-        self.update_enable_retina();
+        None
+    }
+
+    // Audio monitor connections only subscribe to the audio service.
+    pub fn add_audio_monitor_connection(&mut self, conn: ConnInner) {
+        if let Some(s) = self.services.get(audio_service::NAME) {
+            s.on_subscribe(conn.clone());
+        }
         self.connections.insert(conn.id(), conn);
     }
 
@@ -366,11 +999,21 @@ impl Server {
             s.on_unsubscribe(conn.id());
         }
         self.connections.remove(&conn.id());
+        self.reauth_granted.remove(&conn.id());
+        self.reauth_pending.remove(&conn.id());
+        self.allowed_displays.remove(&conn.id());
+        self.remove_unused_window_services();
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        session_temp::remove(conn.id());
         #[cfg(target_os = "macos")]
         self.update_enable_retina();
+        #[cfg(target_os = "linux")]
+        self.update_session_inhibit();
     }
 
-    pub fn close_connections(&mut self) {
+    /// Ask all the connections to send the reason to their peers and exit, see `close_and_wait`.
+    pub fn close_connections(&mut self, reason: ErrorCode) {
+        self.close_reason = reason;
         let conn_inners: Vec<_> = self.connections.values_mut().collect();
         for c in conn_inners {
             let mut misc = Misc::new();
@@ -381,9 +1024,15 @@ impl Server {
         }
     }
 
-    fn add_service(&mut self, service: Box<dyn Service>) {
+    #[inline]
+    pub fn close_reason(&self) -> ErrorCode {
+        self.close_reason
+    }
+
+    // The replaced service is returned.
+    fn add_service(&mut self, service: Box<dyn Service>) -> Option<Box<dyn Service>> {
         let name = service.name();
-        self.services.insert(name, service);
+        self.services.insert(name, service)
     }
 
     pub fn contains(&self, name: &str) -> bool {
@@ -391,18 +1040,141 @@ impl Server {
     }
 
     pub fn subscribe(&mut self, name: &str, conn: ConnInner, sub: bool) {
-        if let Some(s) = self.services.get(name) {
-            if s.is_subed(conn.id()) == sub {
+        if !sub {
+            if let Some((c, names)) = self.reauth_pending.get_mut(&conn.id()) {
+                names.remove(name);
+                if names.is_empty() {
+                    c.clear_reauth();
+                }
+            }
+        }
+        // The service is borrowed after the checks, `defer_for_reauth` needs `self` mutable.
+        let Some((subed, ok)) = self
+            .services
+            .get(name)
+            .map(|s| (s.is_subed(conn.id()), s.ok()))
+        else {
+            return;
+        };
+        if subed == sub {
+            return;
+        }
+        if sub && !self.is_display_allowed(name, conn.id()) {
+            log::warn!(
+                "Refuse to subscribe {} of connection {}, the monitor is not allowed",
+                name,
+                conn.id()
+            );
+            return;
+        }
+        if sub && self.defer_for_reauth(name, &conn) {
+            return;
+        }
+        if sub && !ok && Self::is_video_service_name(name) {
+            let max = get_max_video_services();
+            if max > 0 && self.active_video_service_count() >= max {
+                log::warn!(
+                    "Reject to subscribe {} of connection {}, the video services are limited to {}",
+                    name,
+                    conn.id(),
+                    max
+                );
+                let mut msg_out = Message::new();
+                msg_out.set_message_box(MessageBox {
+                    msgtype: "custom-nook-nocancel-hasclose".to_owned(),
+                    title: "Prompt".to_owned(),
+                    text: "video-services-limit-tip".to_owned(),
+                    link: "".to_owned(),
+                    ..Default::default()
+                });
+                conn.clone().send(Arc::new(msg_out));
                 return;
             }
+        }
+        if sub && self.is_at_capacity(name, &conn) {
+            return;
+        }
+        if let Some(s) = self.services.get(name) {
             if sub {
                 s.on_subscribe(conn.clone());
             } else {
                 s.on_unsubscribe(conn.id());
             }
-            #[cfg(target_os = "macos")]
-            self.update_enable_retina();
         }
+        #[cfg(target_os = "macos")]
+        self.update_enable_retina();
+        #[cfg(target_os = "linux")]
+        if Self::is_video_service_name(name) {
+            self.update_session_inhibit();
+        }
+    }
+
+    // The subscription of a privileged service is deferred until the connection is re-authenticated.
+    // The peer is asked for the password once, when the first service is deferred.
+    fn defer_for_reauth(&mut self, name: &str, conn: &ConnInner) -> bool {
+        if self.reauth_granted.contains(&conn.id()) || !is_reauth_service(name) {
+            return false;
+        }
+        let (_, names) = self
+            .reauth_pending
+            .entry(conn.id())
+            .or_insert_with(|| (conn.clone(), HashSet::new()));
+        if names.is_empty() {
+            log::info!("Re-authentication of connection {} required", conn.id());
+            // Only the challenge is new, the peer keeps the salt of the login.
+            let mut msg_out = Message::new();
+            msg_out.set_hash(Hash {
+                challenge: conn.request_reauth(),
+                ..Default::default()
+            });
+            conn.clone().send(Arc::new(msg_out));
+            let mut msg_out = Message::new();
+            msg_out.set_message_box(MessageBox {
+                msgtype: "input-password".to_owned(),
+                title: "Password Required".to_owned(),
+                text: "reauth-required-tip".to_owned(),
+                link: "".to_owned(),
+                ..Default::default()
+            });
+            conn.clone().send(Arc::new(msg_out));
+        }
+        names.insert(name.to_owned());
+        true
+    }
+
+    /// The connection is re-authenticated, subscribe the deferred services.
+    pub fn grant_reauth(&mut self, conn_id: i32) {
+        self.reauth_granted.insert(conn_id);
+        if let Some((conn, names)) = self.reauth_pending.remove(&conn_id) {
+            conn.clear_reauth();
+            log::info!(
+                "Re-authentication of connection {} granted, subscribe {:?}",
+                conn_id,
+                names
+            );
+            for name in names {
+                self.subscribe(&name, conn.clone(), true);
+            }
+        }
+    }
+
+    /// Start a new measurement window of the connection, the counters of the old one are returned.
+    pub fn reset_connection_stats(
+        &self,
+        conn_id: i32,
+    ) -> Option<session_report::ConnStatsSnapshot> {
+        self.connections
+            .get(&conn_id)
+            .map(|conn| conn.stats().reset())
+    }
+
+    pub fn snapshot_connection_stats(
+        &self,
+        conn_id: i32,
+    ) -> Option<session_report::ConnStatsSnapshot> {
+        self.connections
+            .get(&conn_id)
+            .map(|conn| conn.stats().snapshot())
     }
 
     // get a new unique id
@@ -430,18 +1202,46 @@ impl Server {
         }
     }
 
-    fn get_subbed_displays_count(&self, conn_id: i32) -> usize {
+    pub fn is_video_service_running(&self, display: usize) -> bool {
         self.services
-            .keys()
-            .filter(|k| {
-                Self::is_video_service_name(k)
-                    && self
-                        .services
-                        .get(*k)
-                        .map(|s| s.is_subed(conn_id))
-                        .unwrap_or(false)
-            })
-            .count()
+            .get(&video_service::get_service_name(
+                VideoSource::Monitor,
+                display,
+            ))
+            .map(|s| s.ok())
+            .unwrap_or(false)
+    }
+
+    pub fn set_allowed_displays(&mut self, conn_id: i32, allowed: Option<Vec<usize>>) {
+        match allowed {
+            Some(allowed) => {
+                self.allowed_displays.insert(conn_id, allowed);
+            }
+            None => {
+                self.allowed_displays.remove(&conn_id);
+            }
+        }
+    }
+
+    // Only the monitors are restricted, not the cameras.
+    fn is_display_allowed(&self, name: &str, conn_id: i32) -> bool {
+        let Some(allowed) = self.allowed_displays.get(&conn_id) else {
+            return true;
+        };
+        let Some(idx) = name.strip_prefix(VideoSource::Monitor.service_name_prefix()) else {
+            return true;
+        };
+        idx.parse::<usize>()
+            .map(|idx| allowed.contains(&idx))
+            .unwrap_or(false)
+    }
+
+    fn get_subbed_displays(&self, conn_id: i32) -> HashSet<String> {
+        self.services
+            .iter()
+            .filter(|(k, s)| Self::is_video_service_name(k) && s.is_subed(conn_id))
+            .map(|(k, _)| k.clone())
+            .collect()
     }
 
     fn capture_displays(
@@ -456,41 +1256,312 @@ impl Server {
             .iter()
             .map(|d| video_service::get_service_name(source, *d))
             .collect::<Vec<_>>();
+        let keys = self
+            .services
+            .keys()
+            .filter(|name| Self::is_video_service_name(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        // The old displays are unsubscribed first, their services don't count against
+        // "max-video-services" when the new ones are subscribed.
+        if exclude {
+            for name in keys.iter().filter(|name| !displays.contains(name)) {
+                self.subscribe(name, conn.clone(), false);
+            }
+        }
+        if include {
+            for name in keys.iter().filter(|name| displays.contains(name)) {
+                self.subscribe(name, conn.clone(), true);
+            }
+        }
+    }
+
+    /// Stop sending one display to the connection, e.g. the peer closes one of its monitor views.
+    /// The other displays are kept, the capture of the display stops if no one else watches it.
+    /// False if the connection doesn't watch the display.
+    pub fn stop_capture_display(&mut self, conn_id: i32, source: VideoSource, idx: usize) -> bool {
+        let name = video_service::get_service_name(source, idx);
+        let Some(conn) = self.connections.get(&conn_id).cloned() else {
+            return false;
+        };
+        let subed = self
+            .services
+            .get(&name)
+            .map_or(false, |s| s.is_subed(conn_id));
+        if !subed {
+            return false;
+        }
+        self.subscribe(&name, conn, false);
+        if source == VideoSource::Window {
+            self.remove_unused_window_services();
+        }
+        video_service::VIDEO_QOS
+            .lock()
+            .unwrap()
+            .user_displays(conn_id, self.get_subbed_displays(conn_id));
+        log::info!("Stop capturing {} for connection {}", name, conn_id);
+        true
+    }
+
+    // Capture a single window instead of the displays for the connection.
+    // The window service is created on demand, it ends by itself when the window is closed.
+    pub fn capture_window(&mut self, conn: ConnInner, id: u64) -> bool {
+        // The window may be on a monitor which is not allowed.
+        if self.allowed_displays.contains_key(&conn.id()) {
+            log::warn!(
+                "Refuse to capture window {} for connection {}, the monitors are restricted",
+                id,
+                conn.id()
+            );
+            return false;
+        }
+        self.remove_unused_window_services();
+        let name = video_service::get_service_name(VideoSource::Window, id as _);
+        if !self.contains(&name) {
+            self.add_service(Box::new(video_service::new(VideoSource::Window, id as _)));
+        }
         let keys = self.services.keys().cloned().collect::<Vec<_>>();
-        for name in keys.iter() {
-            if Self::is_video_service_name(&name) {
-                if displays.contains(&name) {
-                    if include {
-                        self.subscribe(&name, conn.clone(), true);
-                    }
-                } else {
-                    if exclude {
-                        self.subscribe(&name, conn.clone(), false);
-                    }
-                }
+        for key in keys.iter() {
+            if Self::is_video_service_name(key) && *key != name {
+                self.subscribe(key, conn.clone(), false);
             }
         }
+        self.subscribe(&name, conn, true);
+        true
     }
 
-    #[cfg(target_os = "macos")]
-    fn update_enable_retina(&self) {
-        let mut video_service_count = 0;
-        for (name, service) in self.services.iter() {
-            if Self::is_video_service_name(&name) && service.ok() {
-                video_service_count += 1;
+    // The window services without subscribers, e.g. the window is closed.
+    fn remove_unused_window_services(&mut self) {
+        let unused = self
+            .services
+            .iter()
+            .filter(|(name, s)| {
+                name.starts_with(VideoSource::Window.service_name_prefix()) && !s.ok()
+            })
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for name in unused {
+            if let Some(s) = self.services.remove(&name) {
+                s.join();
             }
         }
-        *scrap::quartz::ENABLE_RETINA.lock().unwrap() = video_service_count < 2;
+    }
+
+    // The video service has the max number of subscribers, the connection is told.
+    fn is_at_capacity(&self, name: &str, conn: &ConnInner) -> bool {
+        let max = get_max_video_subscribers();
+        if max == 0 || !Self::is_video_service_name(name) {
+            return false;
+        }
+        let Some(s) = self.services.get(name) else {
+            return false;
+        };
+        let count = self
+            .connections
+            .keys()
+            .filter(|id| **id != conn.id() && s.is_subed(**id))
+            .count();
+        if count < max {
+            return false;
+        }
+        log::warn!(
+            "Reject to subscribe {} of connection {}, it is at capacity of {} subscribers",
+            name,
+            conn.id(),
+            max
+        );
+        let mut msg_out = Message::new();
+        msg_out.set_message_box(MessageBox {
+            msgtype: "custom-nook-nocancel-hasclose".to_owned(),
+            title: "Prompt".to_owned(),
+            text: "video-capacity-tip".to_owned(),
+            link: "".to_owned(),
+            ..Default::default()
+        });
+        conn.clone().send(Arc::new(msg_out));
+        true
+    }
+
+    // The video services which are running for at least one connection.
+    fn active_video_service_count(&self) -> usize {
+        self.services
+            .iter()
+            .filter(|(name, service)| Self::is_video_service_name(name) && service.ok())
+            .count()
+    }
+
+    #[cfg(target_os = "macos")]
+    fn update_enable_retina(&self) {
+        *scrap::quartz::ENABLE_RETINA.lock().unwrap() = self.active_video_service_count() < 2;
+    }
+
+    // Keep the host awake while any connection is watching the screen.
+    #[cfg(target_os = "linux")]
+    fn update_session_inhibit(&self) {
+        let has_video = self.connections.keys().any(|id| {
+            self.services
+                .iter()
+                .any(|(name, s)| Self::is_video_service_name(name) && s.is_subed(*id))
+        });
+        dbus::set_session_inhibit(has_video);
     }
 }
 
+// Teardown order:
+// 1. Unsubscribe all connections, so no service sends to them any more. The server is only
+//    dropped when no connection holds it, so none is added after this.
+// 2. Join the services other than the cursor ones: video, audio, clipboard, ... The video
+//    services on Wayland use the wayland states until they exit.
+// 3. Join the cursor, position and window focus services, then release the keys the peers left
+//    held down. The keys are not tracked by the services, they are released at the end so
+//    nothing presses them again.
+// 4. Clear the wayland states, no video service uses them any more.
+// 5. Reap the child processes that have exited, the running ones are left as they are.
 impl Drop for Server {
     fn drop(&mut self) {
-        for s in self.services.values() {
-            s.join();
+        let start = std::time::Instant::now();
+        log::info!(
+            "Server teardown, unsubscribe {} connections",
+            self.connections.len()
+        );
+        for id in self.connections.keys() {
+            for s in self.services.values() {
+                s.on_unsubscribe(*id);
+            }
+        }
+        self.connections.clear();
+        #[cfg(target_os = "linux")]
+        dbus::set_session_inhibit(false);
+
+        let is_cursor_service = |name: &str| {
+            [
+                input_service::NAME_CURSOR,
+                input_service::NAME_POS,
+                input_service::NAME_WINDOW_FOCUS,
+            ]
+            .contains(&name)
+        };
+        log::info!("Server teardown, join services, {:?}", start.elapsed());
+        for (name, s) in self.services.iter() {
+            if !is_cursor_service(name) {
+                s.join();
+            }
         }
+        log::info!(
+            "Server teardown, join cursor services, {:?}",
+            start.elapsed()
+        );
+        for (name, s) in self.services.iter() {
+            if is_cursor_service(name) {
+                s.join();
+            }
+        }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        input_service::release_keys_down();
+
         #[cfg(target_os = "linux")]
-        wayland::clear();
+        {
+            log::info!("Server teardown, clear wayland, {:?}", start.elapsed());
+            wayland::clear();
+        }
+
+        let mut children = CHILD_PROCESS.lock().unwrap();
+        let n = children.len();
+        children.retain_mut(|c| match c.try_wait() {
+            Ok(Some(status)) => {
+                on_child_exit(c.id(), status);
+                false
+            }
+            _ => true,
+        });
+        log::info!(
+            "Server teardown done, reaped {} of {} child processes, {:?}",
+            n - children.len(),
+            n,
+            start.elapsed()
+        );
+    }
+}
+
+/// Add the child process to `CHILD_PROCESS`, its exit status is kept for `try_wait_child`.
+pub fn track_child(child: std::process::Child) {
+    let mut children = CHILD_PROCESS.lock().unwrap();
+    CHILD_EXITS.lock().unwrap().insert(child.id(), None);
+    children.push(child);
+}
+
+/// Kill a child process added by `track_child` with `kill`, false if it's already reaped.
+pub fn kill_child(pid: u32, kill: impl FnOnce(&mut std::process::Child)) -> bool {
+    let mut children = CHILD_PROCESS.lock().unwrap();
+    match children.iter_mut().find(|c| c.id() == pid) {
+        Some(c) => {
+            kill(c);
+            true
+        }
+        None => false,
+    }
+}
+
+// Called with `CHILD_PROCESS` locked.
+fn on_child_exit(pid: u32, status: std::process::ExitStatus) {
+    if let Some(s) = CHILD_EXITS.lock().unwrap().get_mut(&pid) {
+        *s = Some(status);
+    }
+}
+
+/// The exit status of a child process added by `track_child`, None if it is still running.
+/// It is forgotten once returned.
+pub fn try_wait_child(pid: u32) -> Option<std::process::ExitStatus> {
+    let mut children = CHILD_PROCESS.lock().unwrap();
+    if let Some(i) = children.iter().position(|c| c.id() == pid) {
+        if let Ok(Some(status)) = children[i].try_wait() {
+            on_child_exit(pid, status);
+            children.remove(i);
+        }
+    }
+    let mut exits = CHILD_EXITS.lock().unwrap();
+    if matches!(exits.get(&pid), Some(Some(_))) {
+        return exits.remove(&pid).flatten();
+    }
+    None
+}
+
+pub fn set_host_server(server: &ServerPtr) {
+    *HOST_SERVER.lock().unwrap() = Arc::downgrade(server);
+}
+
+/// Close the connections and wait a moment for them to deliver the reason, so the peers see why
+/// instead of a reset connection when the process exits. True if all of them exited in time.
+pub async fn close_and_wait(server: &ServerPtr, reason: ErrorCode) -> bool {
+    let count = {
+        let mut s = server.write().unwrap();
+        s.close_connections(reason);
+        s.connections.len()
+    };
+    if count == 0 {
+        return true;
+    }
+    log::info!("Closing {} connections: {:?}", count, reason);
+    let start = Instant::now();
+    while start.elapsed() < CLOSE_WAIT {
+        hbb_common::sleep(CLOSE_POLL_INTERVAL.as_secs_f32()).await;
+        if server.read().unwrap().connections.is_empty() {
+            return true;
+        }
+    }
+    log::warn!(
+        "{} connections are not closed in time",
+        server.read().unwrap().connections.len()
+    );
+    false
+}
+
+/// `close_and_wait` of the host server, before the process exits.
+pub async fn close_host_connections(reason: ErrorCode) -> bool {
+    let server = HOST_SERVER.lock().unwrap().upgrade();
+    match server {
+        Some(server) => close_and_wait(&server, reason).await,
+        None => true,
     }
 }
 
@@ -500,7 +1571,8 @@ pub fn check_zombie() {
         let mut i = 0;
         while i != lock.len() {
             let c = &mut (*lock)[i];
-            if let Ok(Some(_)) = c.try_wait() {
+            if let Ok(Some(status)) = c.try_wait() {
+                on_child_exit(c.id(), status);
                 lock.remove(i);
             } else {
                 i += 1;
@@ -552,7 +1624,7 @@ pub async fn start_server(is_server: bool, no_server: bool) {
         std::thread::spawn(move || {
             if let Err(err) = crate::ipc::start("") {
                 log::error!("Failed to start ipc: {}", err);
-                if crate::is_server() {
+                if crate::is_server() && !headless_mode::is_enabled() {
                     log::error!("ipc is occupied by another process, try kill it");
                     std::thread::spawn(stop_main_window_process).join().ok();
                 }
@@ -560,6 +1632,15 @@ pub async fn start_server(is_server: bool, no_server: bool) {
             }
         });
         input_service::fix_key_down_timeout_loop();
+        // No connection yet, a changed text scaling is left by a crashed server.
+        std::thread::spawn(text_scale::restore);
+        battery_saver::start();
+        #[cfg(windows)]
+        host_priority::start();
+        #[cfg(windows)]
+        server_watchdog::start();
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        panic_button::start();
         #[cfg(target_os = "linux")]
         if input_service::wayland_use_uinput() {
             allow_err!(input_service::setup_uinput(0, 1920, 0, 1080).await);
@@ -569,7 +1650,10 @@ pub async fn start_server(is_server: bool, no_server: bool) {
         #[cfg(target_os = "windows")]
         crate::platform::try_kill_broker();
         #[cfg(feature = "hwcodec")]
-        scrap::hwcodec::start_check_process();
+        if !headless_mode::is_enabled() {
+            scrap::hwcodec::start_check_process();
+        }
+        check_key_pair();
         crate::RendezvousMediator::start_all().await;
     } else {
         match crate::ipc::connect(1000, "").await {
@@ -672,6 +1756,8 @@ async fn sync_and_watch_config_dir() {
                                     let (config, config2) = *configs;
                                     let _chk = crate::ipc::CheckIfRestart::new();
                                     if !config.is_empty() {
+                                        let old_id = Config::get_id();
+                                        let old = Config::get_options();
                                         if cfg0.0 != config {
                                             cfg0.0 = config.clone();
                                             Config::set(config);
@@ -682,6 +1768,11 @@ async fn sync_and_watch_config_dir() {
                                             Config2::set(config2);
                                             log::info!("sync config2 from root");
                                         }
+                                        let source = option_journal::SOURCE_ROOT;
+                                        let id = Config::get_id();
+                                        option_journal::record(source, "id", &old_id, &id);
+                                        let options = Config::get_options();
+                                        option_journal::record_options(source, &old, &options);
                                     }
                                     synced = true;
                                 }
@@ -698,7 +1789,11 @@ async fn sync_and_watch_config_dir() {
                         log::info!("config updated, sync to root");
                         match conn.send(&Data::SyncConfig(Some(cfg.clone().into()))).await {
                             Err(e) => {
-                                log::error!("sync config to root failed: {}", e);
+                                crate::common::log_throttled(
+                                    "sync-config-to-root",
+                                    log::Level::Error,
+                                    format!("sync config to root failed: {}", e),
+                                );
                                 match crate::ipc::connect(1000, "_service").await {
                                     Ok(mut _conn) => {
                                         conn = _conn;
@@ -0,0 +1,415 @@
+// The accept policies per connection type, e.g. anyone with the password A may view, only the
+// password B may control, and the terminal always requires the manual approval.
+//
+// The rules are stored in json in "access-policies", at most one rule for a connection type:
+// - "conn_type": "view", "control", "file" or "terminal". A remote desktop login is checked
+//   against "control" first and "view" next, the session admitted by "view" can't control.
+// - "approve": "password", "click" (the manual approval only) or "both".
+// - "password": the password of the rule, the general passwords are used if it is empty.
+//   Only the salted hash is kept in "password_hash", the rules stop matching if the salt changes.
+// A connection type without a rule is accepted as before, with the approve mode and the general
+// passwords of the host, e.g. with only a "view" rule the general passwords still control. The
+// recent session of a peer reconnecting is admitted by the rule which admitted it first. The rules
+// are set over IPC, they are validated for consistency first, see `validate`.
+
+use hbb_common::{bail, config::Config, log, ResultType};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const OPTION_ACCESS_POLICIES: &str = "access-policies";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnType {
+    View,
+    Control,
+    File,
+    Terminal,
+}
+
+impl ConnType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnType::View => "view",
+            ConnType::Control => "control",
+            ConnType::File => "file",
+            ConnType::Terminal => "terminal",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        [
+            ConnType::View,
+            ConnType::Control,
+            ConnType::File,
+            ConnType::Terminal,
+        ]
+        .into_iter()
+        .find(|t| t.as_str() == s)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Approve {
+    // The order is the strictness.
+    Both,
+    Password,
+    Click,
+}
+
+impl Approve {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Approve::Password => "password",
+            Approve::Click => "click",
+            Approve::Both => "both",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "password" => Some(Approve::Password),
+            "click" => Some(Approve::Click),
+            "both" => Some(Approve::Both),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn allow_password(&self) -> bool {
+        *self != Approve::Click
+    }
+
+    #[inline]
+    pub fn allow_click(&self) -> bool {
+        *self != Approve::Password
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rule {
+    pub conn_type: String,
+    pub approve: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub password: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub password_hash: String,
+}
+
+impl Rule {
+    pub fn conn_type(&self) -> ConnType {
+        ConnType::from_str(&self.conn_type).unwrap_or(ConnType::View)
+    }
+
+    pub fn approve(&self) -> Approve {
+        Approve::from_str(&self.approve).unwrap_or(Approve::Click)
+    }
+}
+
+// The rules of the connection types of a login, in the order they are checked.
+#[derive(Debug, Default)]
+pub struct Policy(Vec<Rule>);
+
+impl Policy {
+    /// The rules of a login, empty if none of `conn_types` has a rule. Otherwise the types without
+    /// a rule get one of `approve` with the general passwords, i.e. as before.
+    pub fn load(conn_types: &[ConnType], approve: Approve) -> Self {
+        Self::with_rules(get_rules(), conn_types, approve)
+    }
+
+    fn with_rules(rules: Vec<Rule>, conn_types: &[ConnType], approve: Approve) -> Self {
+        let find = |t: &ConnType| rules.iter().find(|r| r.conn_type() == *t).cloned();
+        if !conn_types.iter().any(|t| find(t).is_some()) {
+            return Self::default();
+        }
+        Self(
+            conn_types
+                .iter()
+                .map(|t| {
+                    find(t).unwrap_or_else(|| Rule {
+                        conn_type: t.as_str().to_owned(),
+                        approve: approve.as_str().to_owned(),
+                        ..Default::default()
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    /// The current rule of a connection type, for a recent session admitted by it.
+    pub fn rule(&self, conn_type: ConnType) -> Option<Rule> {
+        self.0.iter().find(|r| r.conn_type() == conn_type).cloned()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The first rule accepting the password, `check` validates the salted hash of a password.
+    /// The general passwords are checked by `check_general` for the rules without a password.
+    pub fn match_password(
+        &self,
+        check: impl Fn(&[u8]) -> bool,
+        check_general: impl Fn() -> bool,
+    ) -> Option<Rule> {
+        self.0
+            .iter()
+            .filter(|r| r.approve().allow_password())
+            .find(|r| {
+                if r.password_hash.is_empty() {
+                    check_general()
+                } else {
+                    crate::decode64(&r.password_hash)
+                        .map(|h| check(&h))
+                        .unwrap_or(false)
+                }
+            })
+            .cloned()
+    }
+
+    /// Whether `match_password` calls `check_general`, i.e. a rule without a password comes
+    /// before the first rule whose password is matched by `check`.
+    pub fn needs_general_password(&self, check: impl Fn(&[u8]) -> bool) -> bool {
+        self.0
+            .iter()
+            .filter(|r| r.approve().allow_password())
+            .find(|r| {
+                r.password_hash.is_empty()
+                    || crate::decode64(&r.password_hash)
+                        .map(|h| check(&h))
+                        .unwrap_or(false)
+            })
+            .map_or(false, |r| r.password_hash.is_empty())
+    }
+
+    /// The first rule which may be approved manually.
+    pub fn approval_rule(&self) -> Option<Rule> {
+        self.0.iter().find(|r| r.approve().allow_click()).cloned()
+    }
+
+    pub fn accept_password(&self) -> bool {
+        self.0.iter().any(|r| r.approve().allow_password())
+    }
+}
+
+fn hash_password(password: &str, salt: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(password);
+    hasher.update(salt);
+    crate::encode64(&hasher.finalize()[..])
+}
+
+fn hash_passwords(rules: &mut [Rule]) {
+    let salt = Config::get_salt();
+    for r in rules.iter_mut() {
+        if !r.password.is_empty() {
+            r.password_hash = hash_password(&r.password, &salt);
+            r.password.clear();
+        }
+    }
+}
+
+pub fn get_rules() -> Vec<Rule> {
+    let s = Config::get_option(OPTION_ACCESS_POLICIES);
+    if s.is_empty() {
+        return vec![];
+    }
+    match serde_json::from_str::<Vec<Rule>>(&s) {
+        Ok(mut rules) if validate(&rules).is_ok() => {
+            // The option may be set directly with the plain passwords.
+            hash_passwords(&mut rules);
+            rules
+        }
+        _ => {
+            // Nothing is accepted by a broken policy rather than everything.
+            log::error!("Invalid access policies, only the manual approval is allowed");
+            ["view", "control", "file", "terminal"]
+                .iter()
+                .map(|t| Rule {
+                    conn_type: t.to_string(),
+                    approve: "click".to_owned(),
+                    ..Default::default()
+                })
+                .collect()
+        }
+    }
+}
+
+/// The rules without the plain passwords in json, for IPC.
+pub fn get() -> String {
+    serde_json::to_string(&get_rules()).unwrap_or_default()
+}
+
+/// Validate the rules in json and set them, the plain passwords are replaced by their hashes.
+/// An empty string removes all the rules.
+pub fn set(json: &str) -> ResultType<()> {
+    if json.trim().is_empty() {
+        Config::set_option(OPTION_ACCESS_POLICIES.to_owned(), "".to_owned());
+        log::info!("Access policies are removed");
+        return Ok(());
+    }
+    let mut rules: Vec<Rule> = serde_json::from_str(json)?;
+    validate(&rules)?;
+    hash_passwords(&mut rules);
+    Config::set_option(
+        OPTION_ACCESS_POLICIES.to_owned(),
+        serde_json::to_string(&rules)?,
+    );
+    log::info!("Access policies are updated, {} rules", rules.len());
+    Ok(())
+}
+
+pub fn validate(rules: &[Rule]) -> ResultType<()> {
+    let mut seen = vec![];
+    for r in rules {
+        let Some(t) = ConnType::from_str(&r.conn_type) else {
+            bail!("Unknown connection type \"{}\"", r.conn_type);
+        };
+        if seen.contains(&t) {
+            bail!("More than one rule for \"{}\"", r.conn_type);
+        }
+        seen.push(t);
+        let Some(approve) = Approve::from_str(&r.approve) else {
+            bail!(
+                "Unknown approve mode \"{}\" of \"{}\"",
+                r.approve,
+                r.conn_type
+            );
+        };
+        if approve == Approve::Click && !(r.password.is_empty() && r.password_hash.is_empty()) {
+            bail!("The password of \"{}\" is never used", r.conn_type);
+        }
+    }
+    let find = |t: ConnType| rules.iter().find(|r| r.conn_type() == t);
+    if let (Some(view), Some(control)) = (find(ConnType::View), find(ConnType::Control)) {
+        if control.approve() < view.approve() {
+            bail!("The control can't be approved more easily than the view");
+        }
+        let same_password = if view.password.is_empty() || control.password.is_empty() {
+            view.password.is_empty()
+                && control.password.is_empty()
+                && view.password_hash == control.password_hash
+        } else {
+            view.password == control.password
+        };
+        if same_password && view.approve().allow_password() && control.approve().allow_password() {
+            bail!("The view and the control must have different passwords");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rule(conn_type: &str, approve: &str, password: &str) -> Rule {
+        Rule {
+            conn_type: conn_type.to_owned(),
+            approve: approve.to_owned(),
+            password: password.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&[
+            rule("view", "password", "a"),
+            rule("control", "password", "b"),
+            rule("terminal", "click", ""),
+        ])
+        .is_ok());
+        assert!(validate(&[rule("desktop", "password", "a")]).is_err());
+        assert!(validate(&[rule("file", "password", "a"), rule("file", "click", "")]).is_err());
+        assert!(validate(&[rule("file", "never", "a")]).is_err());
+        assert!(validate(&[rule("terminal", "click", "a")]).is_err());
+        // The approval required for the view but not the control.
+        assert!(validate(&[rule("view", "click", ""), rule("control", "password", "b")]).is_err());
+        assert!(validate(&[rule("view", "password", "a"), rule("control", "both", "b")]).is_err());
+        // A view password would grant the control.
+        assert!(validate(&[
+            rule("view", "password", "a"),
+            rule("control", "password", "a")
+        ])
+        .is_err());
+        assert!(validate(&[
+            rule("view", "password", ""),
+            rule("control", "password", "")
+        ])
+        .is_err());
+        assert!(validate(&[rule("view", "both", ""), rule("control", "click", "")]).is_ok());
+    }
+
+    #[test]
+    fn test_match_password() {
+        let salt = "salt";
+        let mut control = rule("control", "password", "");
+        control.password_hash = hash_password("b", salt);
+        let mut view = rule("view", "password", "");
+        view.password_hash = hash_password("a", salt);
+        let policy = Policy(vec![control, view]);
+        let check = |password: &str| {
+            let expected = crate::decode64(hash_password(password, salt)).unwrap();
+            move |h: &[u8]| h == &expected[..]
+        };
+        assert_eq!(
+            policy
+                .match_password(check("a"), || false)
+                .map(|r| r.conn_type()),
+            Some(ConnType::View)
+        );
+        assert_eq!(
+            policy
+                .match_password(check("b"), || false)
+                .map(|r| r.conn_type()),
+            Some(ConnType::Control)
+        );
+        assert!(policy.match_password(check("c"), || true).is_none());
+        assert!(policy.approval_rule().is_none());
+        assert!(!policy.needs_general_password(check("a")));
+        let general = rule("control", "password", "");
+        let policy = Policy(vec![policy.0[1].clone(), general]);
+        assert!(!policy.needs_general_password(check("a")));
+        assert!(policy.needs_general_password(check("b")));
+    }
+
+    #[test]
+    fn test_load_without_rule() {
+        let types = [ConnType::Control, ConnType::View];
+        let mut view = rule("view", "password", "");
+        view.password_hash = hash_password("a", "salt");
+        let policy = Policy::with_rules(vec![view.clone()], &types, Approve::Both);
+        // The general passwords still control, the view rule doesn't take the control away.
+        assert_eq!(
+            policy.rule(ConnType::Control),
+            Some(rule("control", "both", ""))
+        );
+        assert_eq!(
+            policy
+                .match_password(|_| false, || true)
+                .map(|r| r.conn_type()),
+            Some(ConnType::Control)
+        );
+        assert_eq!(
+            policy.approval_rule().map(|r| r.conn_type()),
+            Some(ConnType::Control)
+        );
+        assert_eq!(
+            policy
+                .match_password(|_| true, || false)
+                .map(|r| r.conn_type()),
+            Some(ConnType::View)
+        );
+        assert!(Policy::with_rules(vec![view], &[ConnType::File], Approve::Both).is_empty());
+        let control = rule("control", "click", "");
+        let policy = Policy::with_rules(vec![control], &types, Approve::Password);
+        assert_eq!(
+            policy
+                .match_password(|_| false, || true)
+                .map(|r| r.conn_type()),
+            Some(ConnType::View)
+        );
+    }
+}
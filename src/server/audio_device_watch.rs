@@ -0,0 +1,158 @@
+// Follow the changes of the audio devices, e.g. a USB headset is plugged in.
+//
+// The device is chosen once when the stream is built, so the audio service is restarted when the
+// default devices change while it is running. The devices are polled, it is only a few names.
+// Plugging a device often fires a storm of changes, the restart waits until the devices are
+// stable and is rate limited, so a flapping device can't cause a restart loop.
+// On Linux the pulseaudio of the user is queried, nothing is detected if it is not reachable from
+// the server process.
+
+use super::audio_service;
+use hbb_common::{config::Config, log};
+use std::{
+    sync::{Mutex, Once},
+    time::{Duration, Instant},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+// The devices must be unchanged for this long before the restart.
+const SETTLE_TIME: Duration = Duration::from_millis(1500);
+const MIN_RESTART_INTERVAL: Duration = Duration::from_secs(10);
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Default::default();
+}
+
+#[derive(Default)]
+struct State {
+    // The devices when the stream was built, None if the audio service is not running.
+    current: Option<String>,
+    // The new devices and since when they are seen.
+    pending: Option<(String, Instant)>,
+    last_restart: Option<Instant>,
+}
+
+pub fn start() {
+    static START: Once = Once::new();
+    START.call_once(|| {
+        std::thread::spawn(|| loop {
+            std::thread::sleep(POLL_INTERVAL);
+            poll();
+        });
+    });
+}
+
+fn poll() {
+    let status = audio_service::status();
+    if !status.running || status.restarting {
+        let mut state = STATE.lock().unwrap();
+        state.current = None;
+        state.pending = None;
+        return;
+    }
+    let devices = devices();
+    let mut state = STATE.lock().unwrap();
+    let Some(current) = state.current.clone() else {
+        state.current = Some(devices);
+        return;
+    };
+    if devices == current {
+        state.pending = None;
+        return;
+    }
+    let since = match &state.pending {
+        Some((pending, since)) if *pending == devices => *since,
+        _ => {
+            state.pending = Some((devices, Instant::now()));
+            return;
+        }
+    };
+    if since.elapsed() < SETTLE_TIME {
+        return;
+    }
+    if state
+        .last_restart
+        .map(|t| t.elapsed() < MIN_RESTART_INTERVAL)
+        .unwrap_or(false)
+    {
+        return;
+    }
+    log::info!("Audio devices changed: {} -> {}", current, devices);
+    restart(&mut state);
+}
+
+fn restart(state: &mut State) {
+    state.current = None;
+    state.pending = None;
+    state.last_restart = Some(Instant::now());
+    audio_service::restart();
+}
+
+/// Enumerate the devices again, restart the audio service if the default devices changed.
+/// Returns whether it is restarted.
+pub fn refresh() -> bool {
+    let status = audio_service::status();
+    if !status.running || status.restarting {
+        return false;
+    }
+    let devices = devices();
+    let mut state = STATE.lock().unwrap();
+    match state.current.as_ref() {
+        Some(current) if *current != devices => {
+            log::info!(
+                "Audio devices changed: {} -> {}, refreshed",
+                current,
+                devices
+            );
+            restart(&mut state);
+            true
+        }
+        Some(_) => false,
+        None => {
+            state.current = Some(devices);
+            false
+        }
+    }
+}
+
+// The devices the audio service would choose.
+#[cfg(not(target_os = "linux"))]
+fn devices() -> String {
+    use cpal::traits::{DeviceTrait, HostTrait};
+    let host = cpal::default_host();
+    let name = |d: Option<cpal::Device>| d.and_then(|d| d.name().ok()).unwrap_or_default();
+    let mut devices = format!(
+        "input: {}, output: {}",
+        name(host.default_input_device()),
+        name(host.default_output_device())
+    );
+    let audio_input = Config::get_option("audio-input");
+    if !audio_input.is_empty() {
+        let present = host
+            .devices()
+            .map(|mut ds| ds.any(|d| d.name().ok().as_ref() == Some(&audio_input)))
+            .unwrap_or(false);
+        devices.push_str(&format!(", {}: {}", audio_input, present));
+    }
+    devices
+}
+
+#[cfg(target_os = "linux")]
+fn devices() -> String {
+    use pulsectl::controllers::*;
+    let sink = crate::platform::linux::get_default_pa_sink()
+        .map(|x| x.0)
+        .unwrap_or_default();
+    let source = SourceController::create()
+        .ok()
+        .and_then(|mut c| c.get_default_device().ok())
+        .and_then(|d| d.name)
+        .unwrap_or_default();
+    let mut devices = format!("input: {}, output: {}", source, sink);
+    let audio_input = Config::get_option("audio-input");
+    if !audio_input.is_empty() {
+        let present = !crate::platform::linux::get_pa_source_name(&audio_input).is_empty();
+        devices.push_str(&format!(", {}: {}", audio_input, present));
+    }
+    devices
+}
@@ -0,0 +1,820 @@
+// Exclude the audio of some processes from the capture, e.g. the notification sounds of a chat
+// program or a music player of the host are not streamed to the peers.
+//
+// "audio-capture-exclusions" is a comma separated list of process names, matched case insensitively
+// against the binary of the audio streams, ".exe" is ignored so the same list works on every
+// platform. It only applies when the output of the host is captured, not an input device.
+// On Linux with PipeWire the output streams of the other processes are linked into a null sink
+// too and its monitor is captured instead of the monitor of the default sink. The graph is
+// watched with `pw-dump --monitor`, so the streams started later are linked as they appear.
+// On Windows 10 build 20348 and later, the process loopback captures the process tree of every
+// audio session which is neither excluded nor started by an excluded process, and the streams are
+// mixed. The new sessions are captured when they are created. The system sounds have no process
+// and are not captured.
+// PulseAudio can't tap a single stream without moving it away from the speakers, so there and on
+// the older Windows the capability is reported as unavailable and the whole output is captured.
+
+use hbb_common::{config::Config, log, ResultType};
+use serde_derive::{Deserialize, Serialize};
+
+pub const OPTION_AUDIO_CAPTURE_EXCLUSIONS: &str = "audio-capture-exclusions";
+
+/// The exclusion list and whether the platform can apply it, for the settings.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CaptureExclusions {
+    pub supported: bool,
+    pub names: Vec<String>,
+}
+
+pub fn get() -> CaptureExclusions {
+    CaptureExclusions {
+        supported: is_supported(),
+        names: get_names(),
+    }
+}
+
+/// Save the exclusion list, the caller restarts the audio service if it is changed, see
+/// `ipc::CheckIfRestart`.
+pub fn set(names: Vec<String>) {
+    let names = parse(&names.join(","));
+    if names == get_names() {
+        return;
+    }
+    Config::set_option(OPTION_AUDIO_CAPTURE_EXCLUSIONS.to_owned(), names.join(","));
+    if !names.is_empty() && !is_supported() {
+        log::warn!("Audio capture exclusion is not supported on this platform");
+    }
+}
+
+pub fn get_names() -> Vec<String> {
+    parse(&Config::get_option(OPTION_AUDIO_CAPTURE_EXCLUSIONS))
+}
+
+fn parse(s: &str) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    for name in s.split(',') {
+        let name = normalize(name);
+        if !name.is_empty() && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+fn normalize(name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    match name.strip_suffix(".exe") {
+        Some(name) => name.to_owned(),
+        None => name,
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn is_supported() -> bool {
+    linux::is_pipewire()
+}
+
+#[cfg(windows)]
+pub fn is_supported() -> bool {
+    win::is_process_loopback_supported()
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn is_supported() -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::Capture;
+#[cfg(windows)]
+pub use win::{Capture, CHANNELS, SAMPLE_RATE};
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use hbb_common::{allow_err, bail};
+    use serde_json::Value;
+    use std::{
+        collections::HashMap,
+        io::BufReader,
+        process::{Child, ChildStdout, Command, Stdio},
+    };
+
+    const SINK_NAME: &str = "rustdesk_capture_sink";
+    const SINK_DESCRIPTION: &str = "RustDesk-Capture-Sink";
+
+    pub fn is_pipewire() -> bool {
+        let tools = ["pw-dump", "pw-link"].iter().all(|tool| {
+            Command::new(tool)
+                .arg("--version")
+                .output()
+                .map(|o| o.status.success())
+                .unwrap_or(false)
+        });
+        tools
+            && Command::new("pactl")
+                .arg("info")
+                .output()
+                .map(|o| String::from_utf8_lossy(&o.stdout).contains("PipeWire"))
+                .unwrap_or(false)
+    }
+
+    /// The null sink which receives the streams of the processes not excluded, it is unloaded on drop.
+    pub struct Capture {
+        module: u32,
+        monitor: Child,
+    }
+
+    impl Capture {
+        pub fn new(exclusions: Vec<String>) -> ResultType<Self> {
+            let output = Command::new("pactl")
+                .args([
+                    "load-module",
+                    "module-null-sink",
+                    &format!("sink_name={}", SINK_NAME),
+                    &format!("sink_properties=device.description={}", SINK_DESCRIPTION),
+                ])
+                .output()?;
+            if !output.status.success() {
+                bail!(
+                    "Failed to load the capture sink: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            let module = String::from_utf8_lossy(&output.stdout).trim().parse()?;
+            // pw-dump prints all the objects, then the changed ones whenever the graph changes.
+            let mut monitor = match Command::new("pw-dump")
+                .args(["--monitor", "--no-colors"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+            {
+                Ok(monitor) => monitor,
+                Err(e) => {
+                    unload(module);
+                    bail!("Failed to watch the audio streams: {}", e);
+                }
+            };
+            if let Some(stdout) = monitor.stdout.take() {
+                std::thread::spawn(move || watch(stdout, exclusions));
+            }
+            Ok(Self { module, monitor })
+        }
+
+        pub fn source(&self) -> String {
+            format!("{}.monitor", SINK_NAME)
+        }
+    }
+
+    impl Drop for Capture {
+        fn drop(&mut self) {
+            allow_err!(self.monitor.kill());
+            allow_err!(self.monitor.wait());
+            unload(self.module);
+        }
+    }
+
+    fn unload(module: u32) {
+        match Command::new("pactl")
+            .args(["unload-module", &module.to_string()])
+            .status()
+        {
+            Ok(status) if status.success() => {}
+            r => log::error!("Failed to unload the capture sink: {:?}", r),
+        }
+    }
+
+    fn watch(stdout: ChildStdout, exclusions: Vec<String>) {
+        log::info!("Audio capture excludes {:?}", exclusions);
+        let mut graph = Graph::default();
+        let updates =
+            serde_json::Deserializer::from_reader(BufReader::new(stdout)).into_iter::<Vec<Value>>();
+        for objects in updates {
+            let objects = match objects {
+                Ok(objects) => objects,
+                Err(e) => {
+                    log::debug!("Failed to read the audio graph: {}", e);
+                    break;
+                }
+            };
+            graph.update(objects);
+            for (out, inp) in graph.missing_links(&exclusions) {
+                match Command::new("pw-link")
+                    .args([out.to_string(), inp.to_string()])
+                    .status()
+                {
+                    Ok(status) if status.success() => {}
+                    r => log::debug!("Failed to link the port {} to {}: {:?}", out, inp, r),
+                }
+            }
+        }
+        log::debug!("Stopped watching the audio streams");
+    }
+
+    /// The objects of the PipeWire graph by id.
+    #[derive(Default)]
+    pub(super) struct Graph(HashMap<u64, Value>);
+
+    struct Port {
+        id: u64,
+        node: u64,
+        output: bool,
+        channel: String,
+    }
+
+    impl Graph {
+        // A removed object comes with a null info, a changed one may carry the changed fields only.
+        pub(super) fn update(&mut self, objects: Vec<Value>) {
+            for o in objects {
+                let Some(id) = o["id"].as_u64() else {
+                    continue;
+                };
+                if o["info"].is_null() {
+                    self.0.remove(&id);
+                } else if let Some(old) = self.0.get_mut(&id) {
+                    merge(old, o);
+                } else {
+                    self.0.insert(id, o);
+                }
+            }
+        }
+
+        // The output ports of the streams which are not excluded, to the input ports of the sink
+        // with the same channel, a mono stream to all of them. The links to the speakers are kept.
+        pub(super) fn missing_links(&self, exclusions: &[String]) -> Vec<(u64, u64)> {
+            let props = |o: &Value, key: &str| {
+                o["info"]["props"][key]
+                    .as_str()
+                    .map(|s| s.to_owned())
+                    .unwrap_or_default()
+            };
+            let mut sink = None;
+            let mut streams = vec![];
+            let mut ports = vec![];
+            let mut links = vec![];
+            for (id, o) in self.0.iter() {
+                match o["type"].as_str().unwrap_or_default() {
+                    "PipeWire:Interface:Node" => {
+                        let class = props(o, "media.class");
+                        if class == "Audio/Sink" && props(o, "node.name") == SINK_NAME {
+                            sink = Some(*id);
+                        } else if class == "Stream/Output/Audio" {
+                            let binary = normalize(&props(o, "application.process.binary"));
+                            if !exclusions.contains(&binary) {
+                                streams.push(*id);
+                            }
+                        }
+                    }
+                    "PipeWire:Interface:Port" => ports.push(Port {
+                        id: *id,
+                        node: o["info"]["props"]["node.id"].as_u64().unwrap_or_default(),
+                        output: o["info"]["direction"].as_str() == Some("output"),
+                        channel: props(o, "audio.channel"),
+                    }),
+                    "PipeWire:Interface:Link" => {
+                        let info = &o["info"];
+                        if let (Some(out), Some(inp)) = (
+                            info["output-port-id"].as_u64(),
+                            info["input-port-id"].as_u64(),
+                        ) {
+                            links.push((out, inp));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            let Some(sink) = sink else {
+                return vec![];
+            };
+            let inputs: Vec<&Port> = ports
+                .iter()
+                .filter(|p| p.node == sink && !p.output)
+                .collect();
+            let mut missing = vec![];
+            for out in ports
+                .iter()
+                .filter(|p| p.output && streams.contains(&p.node))
+            {
+                for inp in inputs
+                    .iter()
+                    .filter(|p| out.channel == "MONO" || p.channel == out.channel)
+                {
+                    if !links.contains(&(out.id, inp.id)) {
+                        missing.push((out.id, inp.id));
+                    }
+                }
+            }
+            missing.sort();
+            missing
+        }
+    }
+
+    fn merge(old: &mut Value, new: Value) {
+        match (old, new) {
+            (Value::Object(old), Value::Object(new)) => {
+                for (k, v) in new {
+                    match old.get_mut(&k) {
+                        Some(o) if o.is_object() && v.is_object() => merge(o, v),
+                        _ => {
+                            old.insert(k, v);
+                        }
+                    }
+                }
+            }
+            (old, new) => *old = new,
+        }
+    }
+}
+
+#[cfg(windows)]
+mod win {
+    use super::*;
+    use hbb_common::anyhow::anyhow;
+    use std::{
+        collections::{HashMap, VecDeque},
+        ffi::OsString,
+        os::windows::ffi::OsStringExt,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc::{channel, Receiver, Sender},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+    use windows::{
+        core::{implement, Interface, IUnknown, Ref, Result as WinResult, HRESULT},
+        Win32::{
+            Foundation::CloseHandle,
+            Media::{Audio::*, Multimedia::WAVE_FORMAT_IEEE_FLOAT},
+            System::{
+                Com::{
+                    CoCreateInstance, CoInitializeEx, CoUninitialize,
+                    StructuredStorage::{
+                        PROPVARIANT, PROPVARIANT_0, PROPVARIANT_0_0, PROPVARIANT_0_0_0,
+                    },
+                    BLOB, CLSCTX_ALL, COINIT_MULTITHREADED,
+                },
+                Diagnostics::ToolHelp::{
+                    CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+                    TH32CS_SNAPPROCESS,
+                },
+                Variant::VT_BLOB,
+            },
+        },
+    };
+
+    pub const SAMPLE_RATE: u32 = 48000;
+    pub const CHANNELS: u16 = 2;
+    const MIN_BUILD: u32 = 20348;
+    const PERIOD: Duration = Duration::from_millis(10);
+    // 100ns units.
+    const BUFFER_DURATION: i64 = 200 * 10_000;
+    const ACTIVATE_TIMEOUT: Duration = Duration::from_secs(3);
+    // A stream which is ahead of the others by more is cut, 200ms.
+    const MAX_QUEUE: usize = SAMPLE_RATE as usize / 5 * CHANNELS as usize;
+
+    pub fn is_process_loopback_supported() -> bool {
+        use winreg::{enums::HKEY_LOCAL_MACHINE, RegKey};
+        RegKey::predef(HKEY_LOCAL_MACHINE)
+            .open_subkey("SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion")
+            .and_then(|k| k.get_value::<String, _>("CurrentBuildNumber"))
+            .ok()
+            .and_then(|b| b.parse::<u32>().ok())
+            .map_or(false, |b| b >= MIN_BUILD)
+    }
+
+    /// The mixed audio of the processes not excluded, 48kHz stereo f32, passed to the callback
+    /// every 10ms. The capture stops on drop.
+    pub struct Capture {
+        stop: Arc<AtomicBool>,
+    }
+
+    impl Capture {
+        pub fn new(
+            exclusions: Vec<String>,
+            mut on_data: impl FnMut(&[f32]) + Send + 'static,
+        ) -> ResultType<Self> {
+            let stop = Arc::new(AtomicBool::new(false));
+            let stop2 = stop.clone();
+            let (tx, rx) = channel();
+            std::thread::spawn(move || {
+                if let Err(e) = unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) }.ok() {
+                    tx.send(Err(anyhow!("Failed to initialize COM: {}", e))).ok();
+                    return;
+                }
+                match Mixer::new(exclusions) {
+                    Ok(mixer) => {
+                        tx.send(Ok(())).ok();
+                        mixer.run(&stop2, &mut on_data);
+                    }
+                    Err(e) => {
+                        tx.send(Err(e)).ok();
+                    }
+                }
+                unsafe { CoUninitialize() };
+            });
+            rx.recv()??;
+            Ok(Self { stop })
+        }
+    }
+
+    impl Drop for Capture {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // The parent and the name of the processes by pid.
+    pub(super) type Processes = HashMap<u32, (u32, String)>;
+
+    fn processes() -> Processes {
+        let mut processes = HashMap::new();
+        unsafe {
+            let Ok(snapshot) = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) else {
+                return processes;
+            };
+            let mut entry: PROCESSENTRY32W = std::mem::zeroed();
+            entry.dwSize = std::mem::size_of::<PROCESSENTRY32W>() as u32;
+            let mut ok = Process32FirstW(snapshot, &mut entry).is_ok();
+            while ok {
+                let exe = &entry.szExeFile;
+                let len = exe.iter().position(|c| *c == 0).unwrap_or(exe.len());
+                let name = OsString::from_wide(&exe[..len]).to_string_lossy().to_string();
+                processes.insert(
+                    entry.th32ProcessID,
+                    (entry.th32ParentProcessID, normalize(&name)),
+                );
+                ok = Process32NextW(snapshot, &mut entry).is_ok();
+            }
+            let _ = CloseHandle(snapshot);
+        }
+        processes
+    }
+
+    // The process and its parents, a loop of the parent ids is cut.
+    fn lineage(pid: u32, processes: &Processes) -> Vec<u32> {
+        let mut pids = vec![];
+        let mut pid = pid;
+        while !pids.contains(&pid) && pids.len() < 64 {
+            pids.push(pid);
+            match processes.get(&pid) {
+                Some((parent, _)) if *parent != 0 => pid = *parent,
+                _ => break,
+            }
+        }
+        pids
+    }
+
+    pub(super) fn is_excluded(pid: u32, processes: &Processes, exclusions: &[String]) -> bool {
+        lineage(pid, processes).iter().any(|p| {
+            processes
+                .get(p)
+                .map_or(false, |(_, name)| exclusions.contains(name))
+        })
+    }
+
+    pub(super) fn is_ancestor(ancestor: u32, pid: u32, processes: &Processes) -> bool {
+        ancestor != pid && lineage(pid, processes).contains(&ancestor)
+    }
+
+    #[implement(IActivateAudioInterfaceCompletionHandler)]
+    struct Activated(Mutex<Sender<()>>);
+
+    impl IActivateAudioInterfaceCompletionHandler_Impl for Activated_Impl {
+        fn ActivateCompleted(
+            &self,
+            _operation: Ref<'_, IActivateAudioInterfaceAsyncOperation>,
+        ) -> WinResult<()> {
+            self.0.lock().unwrap().send(()).ok();
+            Ok(())
+        }
+    }
+
+    #[implement(IAudioSessionNotification)]
+    struct SessionCreated(Mutex<Sender<u32>>);
+
+    impl IAudioSessionNotification_Impl for SessionCreated_Impl {
+        fn OnSessionCreated(&self, session: Ref<'_, IAudioSessionControl>) -> WinResult<()> {
+            let session: IAudioSessionControl2 = session.ok()?.cast()?;
+            let pid = unsafe { session.GetProcessId()? };
+            self.0.lock().unwrap().send(pid).ok();
+            Ok(())
+        }
+    }
+
+    struct Stream {
+        client: IAudioClient,
+        capture: IAudioCaptureClient,
+        queue: VecDeque<f32>,
+    }
+
+    impl Stream {
+        // The loopback of the process tree of `pid`.
+        fn new(pid: u32) -> ResultType<Self> {
+            let mut params = AUDIOCLIENT_ACTIVATION_PARAMS {
+                ActivationType: AUDIOCLIENT_ACTIVATION_TYPE_PROCESS_LOOPBACK,
+                Anonymous: AUDIOCLIENT_ACTIVATION_PARAMS_0 {
+                    ProcessLoopbackParams: AUDIOCLIENT_PROCESS_LOOPBACK_PARAMS {
+                        TargetProcessId: pid,
+                        ProcessLoopbackMode: PROCESS_LOOPBACK_MODE_INCLUDE_TARGET_PROCESS_TREE,
+                    },
+                },
+            };
+            let prop = PROPVARIANT {
+                Anonymous: PROPVARIANT_0 {
+                    Anonymous: std::mem::ManuallyDrop::new(PROPVARIANT_0_0 {
+                        vt: VT_BLOB,
+                        wReserved1: 0,
+                        wReserved2: 0,
+                        wReserved3: 0,
+                        Anonymous: PROPVARIANT_0_0_0 {
+                            blob: BLOB {
+                                cbSize: std::mem::size_of::<AUDIOCLIENT_ACTIVATION_PARAMS>() as u32,
+                                pBlobData: &mut params as *mut _ as *mut u8,
+                            },
+                        },
+                    }),
+                },
+            };
+            let (tx, rx) = channel();
+            let handler: IActivateAudioInterfaceCompletionHandler =
+                Activated(Mutex::new(tx)).into();
+            let operation = unsafe {
+                ActivateAudioInterfaceAsync(
+                    VIRTUAL_AUDIO_DEVICE_PROCESS_LOOPBACK,
+                    &IAudioClient::IID,
+                    Some(&prop),
+                    &handler,
+                )?
+            };
+            rx.recv_timeout(ACTIVATE_TIMEOUT)?;
+            let mut hr = HRESULT(0);
+            let mut activated: Option<IUnknown> = None;
+            unsafe { operation.GetActivateResult(&mut hr, &mut activated)? };
+            hr.ok()?;
+            let client: IAudioClient = activated
+                .ok_or_else(|| anyhow!("No audio client of process {}", pid))?
+                .cast()?;
+            let block_align = CHANNELS * 4;
+            let format = WAVEFORMATEX {
+                wFormatTag: WAVE_FORMAT_IEEE_FLOAT as u16,
+                nChannels: CHANNELS,
+                nSamplesPerSec: SAMPLE_RATE,
+                nAvgBytesPerSec: SAMPLE_RATE * block_align as u32,
+                nBlockAlign: block_align,
+                wBitsPerSample: 32,
+                cbSize: 0,
+            };
+            let capture = unsafe {
+                client.Initialize(
+                    AUDCLNT_SHAREMODE_SHARED,
+                    AUDCLNT_STREAMFLAGS_LOOPBACK | AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM,
+                    BUFFER_DURATION,
+                    0,
+                    &format,
+                    None,
+                )?;
+                let capture: IAudioCaptureClient = client.GetService()?;
+                client.Start()?;
+                capture
+            };
+            Ok(Self {
+                client,
+                capture,
+                queue: VecDeque::new(),
+            })
+        }
+
+        fn read(&mut self) -> ResultType<()> {
+            unsafe {
+                while self.capture.GetNextPacketSize()? > 0 {
+                    let mut data = std::ptr::null_mut();
+                    let mut frames = 0;
+                    let mut flags = 0;
+                    self.capture
+                        .GetBuffer(&mut data, &mut frames, &mut flags, None, None)?;
+                    let len = frames as usize * CHANNELS as usize;
+                    if data.is_null() || flags & AUDCLNT_BUFFERFLAGS_SILENT.0 as u32 != 0 {
+                        self.queue.extend(std::iter::repeat(0.).take(len));
+                    } else {
+                        let samples = std::slice::from_raw_parts(data as *const f32, len);
+                        self.queue.extend(samples.iter().copied());
+                    }
+                    self.capture.ReleaseBuffer(frames)?;
+                }
+            }
+            if self.queue.len() > MAX_QUEUE {
+                let n = self.queue.len() - MAX_QUEUE;
+                self.queue.drain(..n);
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Stream {
+        fn drop(&mut self) {
+            unsafe {
+                let _ = self.client.Stop();
+            }
+        }
+    }
+
+    struct Mixer {
+        exclusions: Vec<String>,
+        manager: IAudioSessionManager2,
+        notification: IAudioSessionNotification,
+        created: Receiver<u32>,
+        streams: HashMap<u32, Stream>,
+    }
+
+    impl Mixer {
+        fn new(exclusions: Vec<String>) -> ResultType<Self> {
+            let manager: IAudioSessionManager2 = unsafe {
+                let enumerator: IMMDeviceEnumerator =
+                    CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+                let device = enumerator.GetDefaultAudioEndpoint(eRender, eConsole)?;
+                device.Activate(CLSCTX_ALL, None)?
+            };
+            let (tx, created) = channel();
+            let notification: IAudioSessionNotification = SessionCreated(Mutex::new(tx)).into();
+            // The sessions must be enumerated once before the new ones are notified.
+            let mut pids = vec![];
+            unsafe {
+                let sessions = manager.GetSessionEnumerator()?;
+                for i in 0..sessions.GetCount()? {
+                    let session: IAudioSessionControl2 = sessions.GetSession(i)?.cast()?;
+                    // The system sounds have no process.
+                    if let Ok(pid) = session.GetProcessId() {
+                        pids.push(pid);
+                    }
+                }
+                manager.RegisterSessionNotification(&notification)?;
+            }
+            log::info!("Audio capture excludes {:?}", exclusions);
+            let mut mixer = Self {
+                exclusions,
+                manager,
+                notification,
+                created,
+                streams: HashMap::new(),
+            };
+            mixer.add(pids);
+            Ok(mixer)
+        }
+
+        fn add(&mut self, pids: Vec<u32>) {
+            let processes = processes();
+            self.streams.retain(|pid, _| processes.contains_key(pid));
+            for pid in pids {
+                if pid == 0
+                    || self.streams.contains_key(&pid)
+                    || is_excluded(pid, &processes, &self.exclusions)
+                {
+                    continue;
+                }
+                // The tree of a captured process has its children already.
+                if self
+                    .streams
+                    .keys()
+                    .any(|p| is_ancestor(*p, pid, &processes))
+                {
+                    continue;
+                }
+                match Stream::new(pid) {
+                    Ok(stream) => {
+                        self.streams
+                            .retain(|p, _| !is_ancestor(pid, *p, &processes));
+                        log::info!("Audio of process {} is captured", pid);
+                        self.streams.insert(pid, stream);
+                    }
+                    Err(e) => log::error!("Failed to capture the audio of process {}: {}", pid, e),
+                }
+            }
+        }
+
+        fn run(mut self, stop: &AtomicBool, on_data: &mut dyn FnMut(&[f32])) {
+            let mut mixed = vec![];
+            while !stop.load(Ordering::SeqCst) {
+                std::thread::sleep(PERIOD);
+                let pids: Vec<u32> = self.created.try_iter().collect();
+                if !pids.is_empty() {
+                    self.add(pids);
+                }
+                self.streams.retain(|pid, stream| match stream.read() {
+                    Ok(()) => true,
+                    Err(e) => {
+                        log::debug!("Stopped capturing the audio of process {}: {}", pid, e);
+                        false
+                    }
+                });
+                let len = self.streams.values().map(|s| s.queue.len()).max();
+                let len = len.unwrap_or_default() / CHANNELS as usize * CHANNELS as usize;
+                if len == 0 {
+                    continue;
+                }
+                mixed.clear();
+                mixed.resize(len, 0f32);
+                for stream in self.streams.values_mut() {
+                    let n = stream.queue.len().min(len);
+                    for (m, v) in mixed.iter_mut().zip(stream.queue.drain(..n)) {
+                        *m += v;
+                    }
+                }
+                for m in mixed.iter_mut() {
+                    *m = m.clamp(-1., 1.);
+                }
+                on_data(&mixed);
+            }
+            unsafe {
+                let _ = self
+                    .manager
+                    .UnregisterSessionNotification(&self.notification);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(" Slack, firefox.EXE,,slack , "),
+            vec!["slack".to_owned(), "firefox".to_owned()]
+        );
+        assert!(parse("").is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_missing_links() {
+        use serde_json::json;
+        let node = |id: u64, props: serde_json::Value| {
+            json!({"id": id, "type": "PipeWire:Interface:Node", "info": {"props": props}})
+        };
+        let stream = |binary: &str| {
+            json!({"media.class": "Stream/Output/Audio", "application.process.binary": binary})
+        };
+        let port = |id: u64, node: u64, direction: &str, channel: &str| {
+            json!({"id": id, "type": "PipeWire:Interface:Port",
+                "info": {"direction": direction,
+                    "props": {"node.id": node, "audio.channel": channel}}})
+        };
+        let mut graph = linux::Graph::default();
+        graph.update(vec![
+            node(
+                1,
+                json!({"media.class": "Audio/Sink", "node.name": "rustdesk_capture_sink"}),
+            ),
+            port(10, 1, "input", "FL"),
+            port(11, 1, "input", "FR"),
+            node(2, stream("firefox")),
+            port(20, 2, "output", "FL"),
+            port(21, 2, "output", "FR"),
+            node(3, stream("Slack")),
+            port(30, 3, "output", "FL"),
+            port(31, 3, "output", "FR"),
+            json!({"id": 5, "type": "PipeWire:Interface:Link",
+                "info": {"output-port-id": 20, "input-port-id": 10}}),
+        ]);
+        let exclusions = parse("slack.exe");
+        assert_eq!(graph.missing_links(&exclusions), vec![(21, 11)]);
+        // A mono stream started later is linked to both channels.
+        graph.update(vec![
+            node(4, stream("mpv")),
+            port(40, 4, "output", "MONO"),
+        ]);
+        assert_eq!(
+            graph.missing_links(&exclusions),
+            vec![(21, 11), (40, 10), (40, 11)]
+        );
+        // Removed, and a change of some fields only.
+        graph.update(vec![
+            json!({"id": 40, "info": null}),
+            json!({"id": 2, "info": {"props": {"application.process.binary": "slack"}}}),
+        ]);
+        assert!(graph.missing_links(&exclusions).is_empty());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_process_tree() {
+        let processes: win::Processes = [
+            (4, (0, "system".to_owned())),
+            (100, (4, "ms-teams".to_owned())),
+            (200, (100, "msedgewebview2".to_owned())),
+            (300, (4, "firefox".to_owned())),
+            (400, (300, "firefox".to_owned())),
+        ]
+        .into_iter()
+        .collect();
+        let exclusions = parse("ms-teams.exe");
+        assert!(win::is_excluded(200, &processes, &exclusions));
+        assert!(!win::is_excluded(400, &processes, &exclusions));
+        assert!(win::is_ancestor(300, 400, &processes));
+        assert!(!win::is_ancestor(400, 300, &processes));
+        assert!(!win::is_ancestor(300, 300, &processes));
+    }
+}
@@ -15,34 +15,94 @@
 use super::*;
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
 use hbb_common::anyhow::anyhow;
-use magnum_opus::{Application::*, Channels::*, Encoder};
-use std::sync::atomic::{AtomicBool, Ordering};
+use magnum_opus::{Application::*, Bitrate, Channels::*, Encoder};
+use serde_derive::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 pub const NAME: &'static str = "audio";
 pub const AUDIO_DATA_SIZE_U8: usize = 960 * 4; // 10ms in 48000 stereo
 static RESTARTING: AtomicBool = AtomicBool::new(false);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+// Increased when the output device of the voice call changes, the playback is restarted on it.
+static VOICE_CALL_OUTPUT_DEVICE_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+// Low delay mode encodes 2.5ms opus frames instead of 10ms ones.
+// The encoder is shared by all subscribers, so it is only on when every opted-in
+// connection has a small enough round trip time, and it reverts once one of them degrades.
+const LOW_DELAY_FRAMES_PER_SECOND: u32 = 400; // 2.5ms
+const LOW_DELAY_MAX_RTT_MS: u32 = 10;
+const LOW_DELAY_REVERT_RTT_MS: u32 = 20;
+static LOW_DELAY: AtomicBool = AtomicBool::new(false);
+static LAST_SENT_LOW_DELAY: AtomicBool = AtomicBool::new(false);
 
 lazy_static::lazy_static! {
     static ref VOICE_CALL_INPUT_DEVICE: Arc::<Mutex::<Option<String>>> = Default::default();
+    static ref VOICE_CALL_OUTPUT_DEVICE: Arc::<Mutex::<Option<String>>> = Default::default();
+    static ref LOW_DELAY_CONNS: Mutex<HashMap<i32, bool>> = Default::default();
+    static ref ENCODE_FORMAT: Mutex<(u32, u16)> = Mutex::new((48000, 2));
+    static ref CURRENT_DEVICE: Mutex<String> = Default::default();
+}
+
+/// A snapshot of the audio service state.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AudioStatus {
+    pub running: bool,
+    // Audio is disabled by the host with "enable-audio".
+    pub muted: bool,
+    // The capturing device, empty if not running or unknown.
+    pub device: String,
+    // The encoded format.
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub restarting: bool,
+    pub low_delay: bool,
+}
+
+/// Get the state of the audio service, only short locks are taken, safe to call from any thread.
+pub fn status() -> AudioStatus {
+    let (sample_rate, channels) = *ENCODE_FORMAT.lock().unwrap();
+    AudioStatus {
+        running: RUNNING.load(Ordering::SeqCst),
+        muted: !hbb_common::config::option2bool(
+            "enable-audio",
+            &Config::get_option("enable-audio"),
+        ),
+        device: CURRENT_DEVICE.lock().unwrap().clone(),
+        sample_rate,
+        channels,
+        restarting: RESTARTING.load(Ordering::SeqCst),
+        low_delay: is_low_delay(),
+    }
+}
+
+fn set_running(device: Option<String>) {
+    RUNNING.store(device.is_some(), Ordering::SeqCst);
+    *CURRENT_DEVICE.lock().unwrap() = device.unwrap_or_default();
+}
+
+fn new_service() -> EmptyExtraFieldService {
+    let svc = EmptyExtraFieldService::new(NAME.to_owned(), true);
+    svc.set_msg_version(crate::common::MSG_KIND_AUDIO, crate::common::AUDIO_MSG_VERSION);
+    svc
 }
 
 #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "ios")))]
 pub fn new() -> GenericService {
-    let svc = EmptyExtraFieldService::new(NAME.to_owned(), true);
+    let svc = new_service();
     GenericService::repeat::<cpal_impl::State, _, _>(&svc.clone(), 33, cpal_impl::run);
     svc.sp
 }
 
 #[cfg(any(target_os = "linux", target_os = "android"))]
 pub fn new() -> GenericService {
-    let svc = EmptyExtraFieldService::new(NAME.to_owned(), true);
+    let svc = new_service();
     GenericService::run(&svc.clone(), pa_impl::run);
     svc.sp
 }
 
 #[cfg(target_os = "ios")]
 pub fn new() -> GenericService {
-    let svc = EmptyExtraFieldService::new(NAME.to_owned(), true);
+    let svc = new_service();
     GenericService::repeat::<ios_impl::State, _, _>(&svc.clone(), 33, ios_impl::run);
     svc.sp
 }
@@ -65,6 +125,125 @@ pub fn set_voice_call_input_device(device: Option<String>, set_if_present: bool)
     restart();
 }
 
+#[inline]
+pub fn get_voice_call_output_device() -> Option<String> {
+    VOICE_CALL_OUTPUT_DEVICE.lock().unwrap().clone()
+}
+
+#[inline]
+pub fn set_voice_call_output_device(device: Option<String>, set_if_present: bool) {
+    if !set_if_present && VOICE_CALL_OUTPUT_DEVICE.lock().unwrap().is_some() {
+        return;
+    }
+
+    if *VOICE_CALL_OUTPUT_DEVICE.lock().unwrap() == device {
+        return;
+    }
+    *VOICE_CALL_OUTPUT_DEVICE.lock().unwrap() = device;
+    VOICE_CALL_OUTPUT_DEVICE_SEQ.fetch_add(1, Ordering::SeqCst);
+}
+
+#[inline]
+pub fn get_voice_call_output_device_seq() -> usize {
+    VOICE_CALL_OUTPUT_DEVICE_SEQ.load(Ordering::SeqCst)
+}
+
+/// Add or remove a connection which opted in to the low delay audio mode.
+pub fn set_low_delay_candidate(conn_id: i32, on: bool) {
+    let mut conns = LOW_DELAY_CONNS.lock().unwrap();
+    if on {
+        // Not eligible until its round trip time is measured.
+        conns.entry(conn_id).or_insert(false);
+    } else {
+        conns.remove(&conn_id);
+    }
+    update_low_delay(&conns);
+}
+
+pub fn on_low_delay_rtt(conn_id: i32, rtt: u32) {
+    let mut conns = LOW_DELAY_CONNS.lock().unwrap();
+    if let Some(eligible) = conns.get_mut(&conn_id) {
+        *eligible = if *eligible {
+            rtt <= LOW_DELAY_REVERT_RTT_MS
+        } else {
+            rtt < LOW_DELAY_MAX_RTT_MS
+        };
+        update_low_delay(&conns);
+    }
+}
+
+fn update_low_delay(conns: &HashMap<i32, bool>) {
+    let on = Config::get_option("allow-low-delay-audio") == "Y"
+        && !conns.is_empty()
+        && conns.values().all(|x| *x);
+    if LOW_DELAY.swap(on, Ordering::SeqCst) != on {
+        log::info!("audio low delay mode: {}", on);
+    }
+}
+
+#[inline]
+fn is_low_delay() -> bool {
+    LOW_DELAY.load(Ordering::SeqCst)
+}
+
+// The minimum bitrate of the audio in kbps, empty or 0 for the default VBR of Opus, which drops to
+// a very low bitrate in the quiet passages. Opus has no floor in its VBR mode, so the constrained
+// VBR, or CBR with "audio-cbr", keeps the bitrate at its target, which is the default bitrate of
+// the encoder or the minimum if it's higher. The minimum never lowers the bitrate. The encoder is
+// restarted on change.
+pub const OPTION_AUDIO_MIN_BITRATE: &str = "audio-min-bitrate";
+pub const OPTION_AUDIO_CBR: &str = "audio-cbr";
+// The range of Opus in kbps.
+const MIN_BITRATE_KBPS: u32 = 6;
+const MAX_BITRATE_KBPS: u32 = 510;
+
+fn get_min_bitrate_kbps() -> Option<u32> {
+    let kbps = Config::get_option(OPTION_AUDIO_MIN_BITRATE)
+        .trim()
+        .parse::<u32>()
+        .unwrap_or(0);
+    if kbps == 0 {
+        return None;
+    }
+    Some(kbps.clamp(MIN_BITRATE_KBPS, MAX_BITRATE_KBPS))
+}
+
+fn configure_encoder(encoder: &mut Encoder) {
+    let Some(kbps) = get_min_bitrate_kbps() else {
+        return;
+    };
+    let cbr = Config::get_option(OPTION_AUDIO_CBR) == "Y";
+    let bps = match encoder.get_bitrate() {
+        Ok(Bitrate::Bits(bps)) => bps,
+        _ => 0,
+    }
+    .max(kbps as i32 * 1000);
+    let res = encoder
+        .set_bitrate(Bitrate::Bits(bps))
+        .and_then(|_| encoder.set_vbr(!cbr))
+        .and_then(|_| encoder.set_vbr_constraint(true));
+    match res {
+        Ok(_) => log::info!(
+            "audio encoder bitrate: {} kbps, at least {} kbps, {}",
+            bps / 1000,
+            kbps,
+            if cbr { "cbr" } else { "constrained vbr" }
+        ),
+        Err(e) => log::error!("Failed to set the audio encoder bitrate: {}", e),
+    }
+}
+
+// Capture the system audio with ScreenCaptureKit loopback only, never open the microphone.
+#[cfg(target_os = "macos")]
+pub const OPTION_AUDIO_SYSTEM_ONLY: &str = "audio-system-only";
+
+#[inline]
+#[cfg(target_os = "macos")]
+fn is_system_audio_only() -> bool {
+    // The voice call still needs the microphone.
+    Config::get_option(OPTION_AUDIO_SYSTEM_ONLY) == "Y" && get_voice_call_input_device().is_none()
+}
+
 #[inline]
 fn get_audio_input() -> String {
     VOICE_CALL_INPUT_DEVICE
@@ -80,6 +259,7 @@ pub fn restart() {
         return;
     }
     RESTARTING.store(true, Ordering::SeqCst);
+    super::session_report::on_audio_restart();
 }
 
 #[cfg(any(target_os = "linux", target_os = "android"))]
@@ -108,6 +288,16 @@ mod pa_impl {
             AUDIO_ZERO_COUNT = 0;
         }
         let mut encoder = Encoder::new(crate::platform::PA_SAMPLE_RATE, Stereo, LowDelay)?;
+        super::configure_encoder(&mut encoder);
+        #[cfg(target_os = "linux")]
+        let device = super::get_audio_input();
+        #[cfg(target_os = "android")]
+        let device = "android".to_owned();
+        super::set_running(Some(device));
+        let _call_on_ret = crate::common::SimpleCallOnReturn {
+            b: true,
+            f: Box::new(|| super::set_running(None)),
+        };
         #[cfg(target_os = "linux")]
         allow_err!(
             stream
@@ -175,6 +365,8 @@ pub fn is_screen_capture_kit_available() -> bool {
 mod cpal_impl {
     use self::service::{Reset, ServiceSwap};
     use super::*;
+    #[cfg(windows)]
+    use crate::server::audio_exclusion;
     use cpal::{
         traits::{DeviceTrait, HostTrait, StreamTrait},
         BufferSize, Device, Host, InputCallbackInfo, StreamConfig, SupportedStreamConfig,
@@ -198,6 +390,7 @@ mod cpal_impl {
     impl super::service::Reset for State {
         fn reset(&mut self) {
             self.stream.take();
+            super::set_running(None);
         }
     }
 
@@ -268,6 +461,13 @@ mod cpal_impl {
 
     #[cfg(feature = "screencapturekit")]
     fn get_device() -> ResultType<(Device, SupportedStreamConfig)> {
+        if super::is_system_audio_only() {
+            if !is_screen_capture_kit_available() {
+                bail!("System audio only mode requires ScreenCaptureKit, which is not available");
+            }
+            log::info!("System audio only mode");
+            return get_loopback_device();
+        }
         let audio_input = super::get_audio_input();
         if !audio_input.is_empty() {
             return get_audio_input(&audio_input);
@@ -275,6 +475,11 @@ mod cpal_impl {
         if !is_screen_capture_kit_available() {
             return get_audio_input("");
         }
+        get_loopback_device()
+    }
+
+    #[cfg(feature = "screencapturekit")]
+    fn get_loopback_device() -> ResultType<(Device, SupportedStreamConfig)> {
         let device = HOST_SCREEN_CAPTURE_KIT
             .as_ref()?
             .default_input_device()
@@ -310,6 +515,10 @@ mod cpal_impl {
 
     #[cfg(not(any(windows, feature = "screencapturekit")))]
     fn get_device() -> ResultType<(Device, SupportedStreamConfig)> {
+        #[cfg(target_os = "macos")]
+        if super::is_system_audio_only() {
+            bail!("System audio only mode requires ScreenCaptureKit, which is not supported");
+        }
         let audio_input = super::get_audio_input();
         get_audio_input(&audio_input)
     }
@@ -355,7 +564,18 @@ mod cpal_impl {
 
     fn play(sp: &GenericService) -> ResultType<(Box<dyn StreamTrait>, Arc<Message>)> {
         use cpal::SampleFormat::*;
+        #[cfg(windows)]
+        if super::get_audio_input().is_empty() {
+            let exclusions = audio_exclusion::get_names();
+            if !exclusions.is_empty() {
+                if audio_exclusion::is_supported() {
+                    return play_excluding(sp, exclusions);
+                }
+                log::warn!("Audio capture exclusion is not supported");
+            }
+        }
         let (device, config) = get_device()?;
+        let device_name = device.name().unwrap_or("".to_owned());
         let sp = sp.clone();
         // Sample rate must be one of 8000, 12000, 16000, 24000, or 48000.
         let sample_rate_0 = config.sample_rate().0;
@@ -385,12 +605,53 @@ mod cpal_impl {
             f => bail!("unsupported audio format: {:?}", f),
         };
         stream.play()?;
+        super::set_running(Some(device_name));
         Ok((
             Box::new(stream),
             Arc::new(create_format_msg(sample_rate, ch as _)),
         ))
     }
 
+    #[cfg(windows)]
+    impl StreamTrait for audio_exclusion::Capture {
+        fn play(&self) -> Result<(), cpal::PlayStreamError> {
+            Ok(())
+        }
+
+        fn pause(&self) -> Result<(), cpal::PauseStreamError> {
+            Ok(())
+        }
+    }
+
+    // The output of the host without the excluded processes, see `audio_exclusion`.
+    #[cfg(windows)]
+    fn play_excluding(
+        sp: &GenericService,
+        exclusions: Vec<String>,
+    ) -> ResultType<(Box<dyn StreamTrait>, Arc<Message>)> {
+        use audio_exclusion::{CHANNELS, SAMPLE_RATE};
+        let sp = sp.clone();
+        let mut encoder = Encoder::new(SAMPLE_RATE, Stereo, LowDelay)?;
+        super::configure_encoder(&mut encoder);
+        unsafe {
+            AUDIO_ZERO_COUNT = 0;
+        }
+        let frame_len = SAMPLE_RATE as usize / 100 * CHANNELS as usize; // 10 ms
+        let mut buffer = std::collections::VecDeque::new();
+        let capture = audio_exclusion::Capture::new(exclusions, move |data: &[f32]| {
+            buffer.extend(data.iter().copied());
+            while buffer.len() >= frame_len {
+                let frame: Vec<f32> = buffer.drain(0..frame_len).collect();
+                send_f32(&frame, &mut encoder, &sp);
+            }
+        })?;
+        super::set_running(Some("Process loopback".to_owned()));
+        Ok((
+            Box::new(capture),
+            Arc::new(create_format_msg(SAMPLE_RATE, CHANNELS)),
+        ))
+    }
+
     fn build_input_stream<T>(
         device: cpal::Device,
         config: &cpal::SupportedStreamConfig,
@@ -403,7 +664,11 @@ mod cpal_impl {
     {
         let err_fn = move |err| {
             // too many UnknownErrno, will improve later
-            log::trace!("an error occurred on stream: {}", err);
+            crate::common::log_throttled(
+                "audio-stream-error",
+                log::Level::Trace,
+                format!("an error occurred on stream: {}", err),
+            );
         };
         let sample_rate_0 = config.sample_rate().0;
         log::debug!("Audio sample rate : {}", sample_rate);
@@ -412,6 +677,7 @@ mod cpal_impl {
         }
         let device_channel = config.channels();
         let mut encoder = Encoder::new(sample_rate, encode_channel, LowDelay)?;
+        super::configure_encoder(&mut encoder);
         // https://www.opus-codec.org/docs/html_api/group__opusencoder.html#gace941e4ef26ed844879fde342ffbe546
         // https://chromium.googlesource.com/chromium/deps/opus/+/1.1.1/include/opus.h
         // Do not set `frame_size = sample_rate as usize / 100;`
@@ -433,6 +699,13 @@ mod cpal_impl {
                 let buffer: Vec<f32> = data.iter().map(|s| T::to_sample(*s)).collect();
                 let mut lock = INPUT_BUFFER.lock().unwrap();
                 lock.extend(buffer);
+                // Do not wait for a whole 10ms frame if it can be split into 2.5ms ones.
+                let rechannel_len =
+                    if is_low_delay() && sample_rate_0 % LOW_DELAY_FRAMES_PER_SECOND == 0 {
+                        rechannel_len / 4
+                    } else {
+                        rechannel_len
+                    };
                 while lock.len() >= rechannel_len {
                     let frame: Vec<f32> = lock.drain(0..rechannel_len).collect();
                     send(
@@ -454,6 +727,7 @@ mod cpal_impl {
 }
 
 fn create_format_msg(sample_rate: u32, channels: u16) -> Message {
+    *ENCODE_FORMAT.lock().unwrap() = (sample_rate, channels);
     let format = AudioFormat {
         sample_rate,
         channels: channels as _,
@@ -471,16 +745,32 @@ fn create_format_msg(sample_rate: u32, channels: u16) -> Message {
 // MAX_AUDIO_ZERO_COUNT=800 is similar as Gate Attack Time 3~5s(Linux) || 6~8s(Windows)
 const MAX_AUDIO_ZERO_COUNT: u16 = 800;
 static mut AUDIO_ZERO_COUNT: u16 = 0;
+// The gate attacks in about 0.5s and takes the near silence as zero when on battery,
+// see `battery_saver`.
+const LOW_POWER_MAX_AUDIO_ZERO_COUNT: u16 = 100;
+const LOW_POWER_SILENCE_LEVEL: f32 = 0.0005;
+static LOW_POWER_GATE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_low_power_gate(on: bool) {
+    if LOW_POWER_GATE.swap(on, Ordering::SeqCst) != on {
+        log::info!("audio low power gate: {}", on);
+    }
+}
 
 fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
-    if data.iter().filter(|x| **x != 0.).next().is_some() {
+    let (max_zero_count, silence_level) = if LOW_POWER_GATE.load(Ordering::SeqCst) {
+        (LOW_POWER_MAX_AUDIO_ZERO_COUNT, LOW_POWER_SILENCE_LEVEL)
+    } else {
+        (MAX_AUDIO_ZERO_COUNT, 0.)
+    };
+    if data.iter().any(|x| x.abs() > silence_level) {
         unsafe {
             AUDIO_ZERO_COUNT = 0;
         }
     } else {
         unsafe {
-            if AUDIO_ZERO_COUNT > MAX_AUDIO_ZERO_COUNT {
-                if AUDIO_ZERO_COUNT == MAX_AUDIO_ZERO_COUNT + 1 {
+            if AUDIO_ZERO_COUNT > max_zero_count {
+                if AUDIO_ZERO_COUNT == max_zero_count + 1 {
                     log::debug!("Audio Zero Gate Attack");
                     AUDIO_ZERO_COUNT += 1;
                 }
@@ -489,6 +779,11 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
             AUDIO_ZERO_COUNT += 1;
         }
     }
+    // The data ends now, the capture time of a frame is the time of its first sample.
+    let (sample_rate, channels) = *ENCODE_FORMAT.lock().unwrap();
+    let samples_per_sec = (sample_rate as i64 * channels as i64).max(1);
+    let duration_us = |samples: usize| samples as i64 * 1_000_000 / samples_per_sec;
+    let mut capture_time = (super::media_capture_time() - duration_us(data.len())).max(1);
     #[cfg(target_os = "android")]
     {
         // the permitted opus data size are 120, 240, 480, 960, 1920, and 2880
@@ -502,16 +797,10 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
                 match encoder
                     .encode_vec_float(&data[i * BATCH_SIZE..(i + 1) * BATCH_SIZE], BATCH_SIZE)
                 {
-                    Ok(data) => {
-                        let mut msg_out = Message::new();
-                        msg_out.set_audio_frame(AudioFrame {
-                            data: data.into(),
-                            ..Default::default()
-                        });
-                        sp.send(msg_out);
-                    }
+                    Ok(data) => send_audio_frame(sp, data, capture_time),
                     Err(_) => {}
                 }
+                capture_time += duration_us(BATCH_SIZE);
             }
         } else {
             log::debug!("invalid audio data size:{} ", input_size);
@@ -520,19 +809,46 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
     }
 
     #[cfg(not(target_os = "android"))]
-    match encoder.encode_vec_float(data, data.len() * 6) {
-        Ok(data) => {
-            let mut msg_out = Message::new();
-            msg_out.set_audio_frame(AudioFrame {
-                data: data.into(),
-                ..Default::default()
-            });
-            sp.send(msg_out);
+    {
+        let low_delay = is_low_delay();
+        if LAST_SENT_LOW_DELAY.swap(low_delay, Ordering::SeqCst) != low_delay {
+            // A fresh format message makes the peers reset their decoders.
+            sp.send(create_format_msg(sample_rate, channels));
         }
+        let frame_len = (sample_rate / LOW_DELAY_FRAMES_PER_SECOND) as usize * channels as usize;
+        if low_delay && frame_len > 0 && data.len() % frame_len == 0 {
+            for chunk in data.chunks(frame_len) {
+                encode_and_send(chunk, encoder, sp, capture_time);
+                capture_time += duration_us(chunk.len());
+            }
+        } else {
+            encode_and_send(data, encoder, sp, capture_time);
+        }
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+fn encode_and_send(data: &[f32], encoder: &mut Encoder, sp: &GenericService, capture_time: i64) {
+    match encoder.encode_vec_float(data, data.len() * 6) {
+        Ok(data) => send_audio_frame(sp, data, capture_time),
         Err(_) => {}
     }
 }
 
+// The capture time is only sent to the peers of the audio message version 1.
+pub(super) fn send_audio_frame(sp: &GenericService, data: Vec<u8>, capture_time: i64) {
+    let data = Bytes::from(data);
+    sp.send_versioned(|version| {
+        let mut msg_out = Message::new();
+        msg_out.set_audio_frame(AudioFrame {
+            data: data.clone(),
+            capture_time: if version >= 1 { capture_time } else { 0 },
+            ..Default::default()
+        });
+        msg_out
+    });
+}
+
 #[cfg(target_os = "ios")]
 mod ios_impl {
     use super::*;
@@ -575,7 +891,10 @@ mod ios_impl {
         // Initialize encoder if needed
         if state.encoder.is_none() {
             match Encoder::new(SAMPLE_RATE, Stereo, LowDelay) {
-                Ok(encoder) => state.encoder = Some(encoder),
+                Ok(mut encoder) => {
+                    super::configure_encoder(&mut encoder);
+                    state.encoder = Some(encoder);
+                }
                 Err(e) => {
                     log::error!("Failed to create Opus encoder: {}", e);
                     return Ok(());
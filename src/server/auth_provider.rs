@@ -0,0 +1,139 @@
+// The providers which verify the password of a session instead of the permanent password of the
+// host, selected with "auth-provider":
+// - "" or "local": the permanent password, as before.
+// - "command": "auth-command" is run without arguments and gets one line of json on stdin, the
+//   peer id and the password typed on the peer. The exit code 0 accepts the password.
+// A provider needs the password itself, the salted hash can't be checked against a directory.
+// The host asks for it in the hash message, and the peer only sends it in the login typed by the
+// user if the connection is encrypted, the host ignores it otherwise. The remembered passwords
+// are hashes, so they only work with the temporary password here.
+// The temporary password is checked locally in any case. Any error, an unknown provider and
+// "auth-timeout" (seconds) running out reject the password, and the rejections are counted as
+// the wrong passwords. The passwords are never logged.
+
+use hbb_common::{bail, config::Config, log, timeout, tokio::io::AsyncWriteExt, ResultType};
+use std::process::Stdio;
+
+pub const OPTION_AUTH_PROVIDER: &str = "auth-provider";
+const OPTION_COMMAND: &str = "auth-command";
+const OPTION_TIMEOUT: &str = "auth-timeout";
+const DEFAULT_TIMEOUT_SECS: u64 = 5;
+const MAX_TIMEOUT_SECS: u64 = 30;
+
+pub struct Request<'a> {
+    pub peer_id: &'a str,
+    pub password: &'a str,
+}
+
+/// The name of the configured provider, it's recorded in the audits.
+pub fn name() -> String {
+    let name = Config::get_option(OPTION_AUTH_PROVIDER);
+    if name.is_empty() {
+        "local".to_owned()
+    } else {
+        name
+    }
+}
+
+#[inline]
+pub fn is_local() -> bool {
+    name() == "local"
+}
+
+fn timeout_secs() -> u64 {
+    Config::get_option(OPTION_TIMEOUT)
+        .parse::<u64>()
+        .map(|v| v.clamp(1, MAX_TIMEOUT_SECS))
+        .unwrap_or(DEFAULT_TIMEOUT_SECS)
+}
+
+/// Verifies the password with the configured provider, false on any error.
+pub async fn verify(req: Request<'_>) -> bool {
+    let name = name();
+    if req.password.is_empty() {
+        return false;
+    }
+    let res = match name.as_str() {
+        "command" => timeout(timeout_secs() * 1000, verify_command(&req)).await,
+        _ => {
+            log::error!("Unknown auth provider {}, the password is rejected", name);
+            return false;
+        }
+    };
+    match res {
+        Ok(Ok(accepted)) => {
+            log::info!(
+                "Auth provider {} {} the password of {}",
+                name,
+                if accepted { "accepted" } else { "rejected" },
+                req.peer_id
+            );
+            accepted
+        }
+        Ok(Err(e)) => {
+            log::error!("Auth provider {} failed for {}: {}", name, req.peer_id, e);
+            false
+        }
+        Err(_) => {
+            log::error!("Auth provider {} timed out for {}", name, req.peer_id);
+            false
+        }
+    }
+}
+
+// The command is killed if the timeout drops the future.
+async fn verify_command(req: &Request<'_>) -> ResultType<bool> {
+    let command = Config::get_option(OPTION_COMMAND);
+    if command.is_empty() {
+        bail!("{} is not set", OPTION_COMMAND);
+    }
+    run_command(&command, req).await
+}
+
+async fn run_command(command: &str, req: &Request<'_>) -> ResultType<bool> {
+    let mut cmd = hbb_common::tokio::process::Command::new(command);
+    cmd.stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+    #[cfg(windows)]
+    cmd.creation_flags(winapi::um::winbase::CREATE_NO_WINDOW);
+    let mut child = cmd.spawn()?;
+    let input = serde_json::json!({
+        "peer_id": req.peer_id,
+        "password": req.password,
+    });
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(format!("{}\n", input).as_bytes()).await?;
+    }
+    Ok(child.wait().await?.success())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use hbb_common::tokio;
+
+    #[tokio::test]
+    async fn test_run_command() {
+        let dir = std::env::temp_dir().join(format!("auth_provider_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("check.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\nread line\necho \"$line\" | grep -q '\"password\":\"secret\"'\n",
+        )
+        .unwrap();
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o700)).unwrap();
+        let script = script.to_string_lossy().to_string();
+        for (password, accepted) in [("secret", true), ("wrong", false)] {
+            let req = Request {
+                peer_id: "123456789",
+                password,
+            };
+            assert_eq!(run_command(&script, &req).await.unwrap(), accepted);
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
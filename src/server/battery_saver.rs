@@ -0,0 +1,109 @@
+// The low power mode of a laptop host, the capture fps is capped and optionally the audio gate
+// closes sooner while the host is on battery, the full rate is back on AC.
+//
+// It is opt-in with "battery-saver", the fps cap is "battery-saver-fps" and the audio gate is
+// "battery-saver-audio-gate". The power source is polled, the option changes take effect at
+// the next poll.
+
+use super::{audio_service, video_qos, video_service::VIDEO_QOS};
+use hbb_common::{config::Config, log};
+use std::time::Duration;
+
+pub const OPTION_BATTERY_SAVER: &str = "battery-saver";
+pub const OPTION_BATTERY_SAVER_FPS: &str = "battery-saver-fps";
+pub const OPTION_BATTERY_SAVER_AUDIO_GATE: &str = "battery-saver-audio-gate";
+const DEFAULT_FPS: u32 = 10;
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+fn battery_fps() -> u32 {
+    Config::get_option(OPTION_BATTERY_SAVER_FPS)
+        .parse::<u32>()
+        .unwrap_or(DEFAULT_FPS)
+        .clamp(video_qos::MIN_FPS, video_qos::MAX_FPS)
+}
+
+pub fn start() {
+    std::thread::spawn(|| {
+        let mut last = None;
+        loop {
+            let state =
+                if Config::get_option(OPTION_BATTERY_SAVER) == "Y" && on_battery() == Some(true) {
+                    Some((
+                        battery_fps(),
+                        Config::get_option(OPTION_BATTERY_SAVER_AUDIO_GATE) == "Y",
+                    ))
+                } else {
+                    None
+                };
+            if state != last {
+                log::info!("battery saver: {:?}", state);
+                VIDEO_QOS
+                    .lock()
+                    .unwrap()
+                    .set_battery_fps(state.map(|(fps, _)| fps));
+                audio_service::set_low_power_gate(state.map(|(_, gate)| gate).unwrap_or(false));
+                last = state;
+            }
+            std::thread::sleep(CHECK_INTERVAL);
+        }
+    });
+}
+
+/// None if the host has no battery or the power source is unknown.
+#[cfg(windows)]
+pub fn on_battery() -> Option<bool> {
+    use winapi::um::winbase::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+    const NO_SYSTEM_BATTERY: u8 = 128;
+
+    let mut status: SYSTEM_POWER_STATUS = unsafe { std::mem::zeroed() };
+    if unsafe { GetSystemPowerStatus(&mut status) } == 0 {
+        return None;
+    }
+    if status.BatteryFlag & NO_SYSTEM_BATTERY != 0 {
+        return None;
+    }
+    match status.ACLineStatus {
+        0 => Some(true),
+        1 => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn on_battery() -> Option<bool> {
+    let read = |dir: &std::path::Path, name: &str| {
+        std::fs::read_to_string(dir.join(name))
+            .map(|s| s.trim().to_owned())
+            .unwrap_or_default()
+    };
+    let mut discharging = None;
+    for entry in std::fs::read_dir("/sys/class/power_supply").ok()?.flatten() {
+        let dir = entry.path();
+        match read(&dir, "type").as_str() {
+            "Mains" | "USB" if read(&dir, "online") == "1" => return Some(false),
+            // The batteries of the mouse, keyboard, etc. are in the scope "Device".
+            "Battery" if read(&dir, "scope") != "Device" => {
+                let d = read(&dir, "status") == "Discharging";
+                discharging = Some(discharging.unwrap_or(false) || d);
+            }
+            _ => {}
+        }
+    }
+    discharging
+}
+
+#[cfg(target_os = "macos")]
+pub fn on_battery() -> Option<bool> {
+    let output = std::process::Command::new("pmset")
+        .args(["-g", "batt"])
+        .output()
+        .ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    if output.contains("'Battery Power'") {
+        Some(true)
+    } else if output.contains("'AC Power'") {
+        Some(false)
+    } else {
+        None
+    }
+}
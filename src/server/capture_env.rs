@@ -0,0 +1,222 @@
+// The environments of the host where the capture can't work, so the peer is told why the screen
+// is black instead of waiting for the frames forever.
+//
+// - Windows: the session of the server is disconnected, e.g. the RDP client is gone, or no session
+//   is attached to the console and there's no real display.
+// - A virtual machine guest without a display driver, only a dummy display or none.
+// - Linux: the X server is Xvfb, the screen is not the one of the local user.
+//
+// The status is sent with the displays in `platform_additions` ("capture_env"), only if it's not
+// ok, and it is in the ipc (`Data::CaptureEnv`) for the diagnostics. The status and the
+// remediations are identifiers, the client translates "capture-env-{id}-tip".
+
+use scrap::Display;
+use serde_json::{json, Value};
+
+pub const REMEDIATION_ENABLE_VIRTUAL_DISPLAY: &str = "enable-virtual-display";
+pub const REMEDIATION_RECONNECT_CONSOLE: &str = "reconnect-console";
+pub const REMEDIATION_SIGN_IN_CONSOLE: &str = "sign-in-console";
+pub const REMEDIATION_INSTALL_DISPLAY_DRIVER: &str = "install-display-driver";
+pub const REMEDIATION_USE_DESKTOP_SESSION: &str = "use-desktop-session";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaptureEnv {
+    #[default]
+    Ok,
+    ConsoleDisconnected,
+    NoConsole,
+    VmNoDisplay,
+    Xvfb,
+}
+
+impl CaptureEnv {
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::ConsoleDisconnected => "console-disconnected",
+            Self::NoConsole => "no-console",
+            Self::VmNoDisplay => "vm-no-display",
+            Self::Xvfb => "xvfb",
+        }
+    }
+
+    /// The suggestions for the user, the virtual display first if the host supports it.
+    pub fn remediations(self, virtual_display: bool) -> Vec<&'static str> {
+        let mut v = vec![];
+        if virtual_display && self != Self::Ok && self != Self::Xvfb {
+            v.push(REMEDIATION_ENABLE_VIRTUAL_DISPLAY);
+        }
+        match self {
+            Self::Ok => {}
+            Self::ConsoleDisconnected => v.push(REMEDIATION_RECONNECT_CONSOLE),
+            Self::NoConsole => v.push(REMEDIATION_SIGN_IN_CONSOLE),
+            Self::VmNoDisplay => v.push(REMEDIATION_INSTALL_DISPLAY_DRIVER),
+            Self::Xvfb => v.push(REMEDIATION_USE_DESKTOP_SESSION),
+        }
+        v
+    }
+
+    fn json(self) -> Value {
+        json!({
+            "status": self.id(),
+            "remediations": self.remediations(is_virtual_display_supported()),
+        })
+    }
+
+    /// The status for `platform_additions`, None if it is ok.
+    pub fn to_json(self) -> Option<Value> {
+        if self == Self::Ok {
+            return None;
+        }
+        Some(self.json())
+    }
+}
+
+fn is_virtual_display_supported() -> bool {
+    #[cfg(windows)]
+    return crate::platform::is_installed()
+        && crate::virtual_display_manager::is_virtual_display_supported();
+    #[cfg(not(windows))]
+    false
+}
+
+pub fn detect(displays: &Vec<Display>) -> CaptureEnv {
+    #[cfg(windows)]
+    {
+        if crate::platform::windows::is_current_session_disconnected() {
+            return CaptureEnv::ConsoleDisconnected;
+        }
+        if !super::display_service::no_displays(displays) {
+            return CaptureEnv::Ok;
+        }
+        if crate::platform::windows::is_physical_console_session().is_none() {
+            return CaptureEnv::NoConsole;
+        }
+    }
+    #[cfg(target_os = "linux")]
+    if is_xvfb() {
+        return CaptureEnv::Xvfb;
+    }
+    #[cfg(not(windows))]
+    if !displays.is_empty() {
+        return CaptureEnv::Ok;
+    }
+    if *IS_VM_GUEST {
+        return CaptureEnv::VmNoDisplay;
+    }
+    CaptureEnv::Ok
+}
+
+/// The status in json for the ipc, detected now.
+pub fn get_status() -> String {
+    // The displays of Wayland are only got with the screencast portal.
+    #[cfg(target_os = "linux")]
+    if !crate::platform::linux::is_x11() {
+        return CaptureEnv::Ok.json().to_string();
+    }
+    let env = Display::all()
+        .map(|displays| detect(&displays))
+        .unwrap_or_default();
+    env.json().to_string()
+}
+
+lazy_static::lazy_static! {
+    static ref IS_VM_GUEST: bool = is_vm_guest();
+}
+
+// The hypervisor bit of cpuid, set by the common hypervisors for their guests.
+// It's also set on a Windows host with the virtualization based security, so it's only checked
+// when there's no display.
+fn is_vm_guest() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    return unsafe { std::arch::x86_64::__cpuid(1) }.ecx & (1 << 31) != 0;
+    #[cfg(target_arch = "x86")]
+    return unsafe { std::arch::x86::__cpuid(1) }.ecx & (1 << 31) != 0;
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    false
+}
+
+#[cfg(target_os = "linux")]
+lazy_static::lazy_static! {
+    // (DISPLAY, is xvfb), the processes are only scanned when DISPLAY changes.
+    static ref XVFB: std::sync::Mutex<Option<(String, bool)>> = Default::default();
+}
+
+#[cfg(target_os = "linux")]
+fn is_xvfb() -> bool {
+    if !crate::platform::linux::is_x11() {
+        return false;
+    }
+    let Ok(display) = std::env::var("DISPLAY") else {
+        return false;
+    };
+    let mut lock = XVFB.lock().unwrap();
+    if let Some((d, v)) = lock.as_ref() {
+        if *d == display {
+            return *v;
+        }
+    }
+    let v = std::fs::read_dir("/proc")
+        .map(|dir| {
+            dir.flatten().any(|e| {
+                std::fs::read(e.path().join("cmdline"))
+                    .map(|cmdline| is_xvfb_cmdline(&cmdline, &display))
+                    .unwrap_or(false)
+            })
+        })
+        .unwrap_or(false);
+    if v {
+        hbb_common::log::warn!("The X server of {} is Xvfb", display);
+    }
+    *lock = Some((display, v));
+    v
+}
+
+// e.g. "Xvfb :99 -screen 0 1024x768x24" of `xvfb-run`, for the DISPLAY ":99" or ":99.0".
+#[cfg(target_os = "linux")]
+fn is_xvfb_cmdline(cmdline: &[u8], display: &str) -> bool {
+    let display = display.split('.').next().unwrap_or(display);
+    let mut args = cmdline
+        .split(|b| *b == 0)
+        .map(|a| String::from_utf8_lossy(a));
+    let is_xvfb = args
+        .next()
+        .map_or(false, |a| a.rsplit('/').next() == Some("Xvfb"));
+    is_xvfb && args.any(|a| a == display)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_remediations() {
+        assert!(CaptureEnv::Ok.to_json().is_none());
+        assert!(CaptureEnv::Ok.remediations(true).is_empty());
+        assert_eq!(
+            CaptureEnv::ConsoleDisconnected.remediations(true),
+            vec![
+                REMEDIATION_ENABLE_VIRTUAL_DISPLAY,
+                REMEDIATION_RECONNECT_CONSOLE
+            ]
+        );
+        assert_eq!(
+            CaptureEnv::Xvfb.remediations(true),
+            vec![REMEDIATION_USE_DESKTOP_SESSION]
+        );
+        assert_eq!(
+            CaptureEnv::VmNoDisplay.remediations(false),
+            vec![REMEDIATION_INSTALL_DISPLAY_DRIVER]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_xvfb_cmdline() {
+        let cmdline = b"/usr/bin/Xvfb\0:99\0-screen\x000\x001024x768x24\0";
+        assert!(is_xvfb_cmdline(cmdline, ":99"));
+        assert!(is_xvfb_cmdline(cmdline, ":99.0"));
+        assert!(!is_xvfb_cmdline(cmdline, ":0"));
+        assert!(!is_xvfb_cmdline(b"/usr/lib/Xorg\0:99\0", ":99"));
+    }
+}
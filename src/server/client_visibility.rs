@@ -0,0 +1,48 @@
+// The window of the controlling side is minimized or hidden, nobody is watching.
+//
+// The connection stops counting in the fps of the video QoS, the capture drops to `HIDDEN_FPS` if
+// all the connections are hidden. The cursor messages are held instead of sent, the latest ones
+// are sent when the window is visible again, together with a refresh of the video for a keyframe.
+// The audio and the clipboard are unaffected.
+
+use hbb_common::message_proto::*;
+use std::{collections::HashMap, sync::Arc};
+
+#[derive(Default)]
+pub struct Hidden {
+    // The cursor images by id, a later `CursorId` may refer to any of them.
+    cursors: HashMap<u64, Arc<Message>>,
+    // The id of the current cursor and the message which selected it.
+    current: Option<(u64, Arc<Message>)>,
+    position: Option<Arc<Message>>,
+}
+
+impl Hidden {
+    /// Hold the message if it is about the cursor, returns true if it is held.
+    pub fn hold(&mut self, msg: &Arc<Message>) -> bool {
+        match &msg.union {
+            Some(message::Union::CursorData(cd)) => {
+                self.cursors.insert(cd.id, msg.clone());
+                self.current = Some((cd.id, msg.clone()));
+            }
+            Some(message::Union::CursorId(id)) => self.current = Some((*id, msg.clone())),
+            Some(message::Union::CursorPosition(_)) => self.position = Some(msg.clone()),
+            _ => return false,
+        }
+        true
+    }
+
+    /// The held messages to send when the window is visible again, in order.
+    pub fn take(mut self) -> Vec<Arc<Message>> {
+        let current = self
+            .current
+            .take()
+            .map(|(id, msg)| self.cursors.remove(&id).unwrap_or(msg));
+        // The current cursor goes last, the peer shows the latest image it receives.
+        self.cursors
+            .into_values()
+            .chain(current)
+            .chain(self.position)
+            .collect()
+    }
+}
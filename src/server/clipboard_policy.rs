@@ -0,0 +1,307 @@
+// The clipboard policies per peer, e.g. the contractors may only exchange text up to 64 KB while
+// the employees have the full clipboard with the images and the files.
+//
+// "clipboard-policies" maps the peer ids to the policies, "*" is for the peers not in the map,
+// e.g. `{"123456789": {"formats": 1, "max_size": 65536}, "*": {"formats": 31}}`.
+// The peer id is only looked up for the peers trusted by 2FA or a trusted device, the claimed id
+// of the others could be anything, so they always get "*".
+// - "formats": the bitmap of the allowed formats, see `FORMAT_TEXT` and the others, all if absent.
+// - "max_size": the max bytes of a clipboard, after the decompression, 0 or absent for no limit.
+//   The files are not limited by size, only by the format.
+// The peers without an entry have the full clipboard.
+//
+// It applies to both directions, the host clipboard is not sent and the peer clipboard is not set.
+// Among the formats of one copy, the ones not allowed are dropped silently, e.g. the html of a
+// text copied in a browser. If nothing is left, the peer gets a `ClipboardBlocked` message, so
+// the user knows why the paste does nothing. The policy is read for every clipboard, an edit
+// applies to the connected peers at once.
+
+use hbb_common::{config::Config, log, message_proto::*};
+use serde_derive::Deserialize;
+use std::{collections::HashMap, io::Read, sync::Mutex};
+
+pub const OPTION_CLIPBOARD_POLICIES: &str = "clipboard-policies";
+
+pub const FORMAT_TEXT: u32 = 1 << 0;
+pub const FORMAT_RTF: u32 = 1 << 1;
+pub const FORMAT_HTML: u32 = 1 << 2;
+pub const FORMAT_IMAGE: u32 = 1 << 3;
+pub const FORMAT_FILES: u32 = 1 << 4;
+// The formats of the applications, `ClipboardFormat::Special`.
+pub const FORMAT_OTHER: u32 = 1 << 5;
+pub const FORMAT_ALL: u32 = (1 << 6) - 1;
+
+pub const REASON_FORMAT: &str = "format";
+pub const REASON_SIZE: &str = "size";
+
+lazy_static::lazy_static! {
+    // The trusted peer ids of the authorized connections, for the clipboard service.
+    static ref PEER_IDS: Mutex<HashMap<i32, Option<String>>> = Default::default();
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    pub formats: u32,
+    pub max_size: usize,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            formats: FORMAT_ALL,
+            max_size: 0,
+        }
+    }
+}
+
+impl Policy {
+    /// The policy of the trusted peer id, or of "*" for an untrusted peer.
+    pub fn from_config(peer_id: Option<&str>) -> Option<Self> {
+        let option = Config::get_option(OPTION_CLIPBOARD_POLICIES);
+        if option.is_empty() {
+            return None;
+        }
+        let map = match serde_json::from_str::<HashMap<String, Policy>>(&option) {
+            Ok(map) => map,
+            Err(e) => {
+                // Fail closed, a broken policy must not let everything through.
+                log::error!(
+                    "Invalid {}: {}, the clipboard is blocked",
+                    OPTION_CLIPBOARD_POLICIES,
+                    e
+                );
+                return Some(Self {
+                    formats: 0,
+                    max_size: 0,
+                });
+            }
+        };
+        peer_id
+            .and_then(|id| map.get(id))
+            .or_else(|| map.get("*"))
+            .cloned()
+    }
+
+    #[inline]
+    pub fn allows_files(&self) -> bool {
+        self.formats & FORMAT_FILES != 0
+    }
+
+    // The reason if the clipboard is not allowed.
+    fn check(&self, cb: &Clipboard) -> Option<&'static str> {
+        if self.formats & format_bit(cb) == 0 {
+            return Some(REASON_FORMAT);
+        }
+        if self.max_size > 0 {
+            // A clipboard which can't be decompressed can't be measured, it's denied.
+            match clipboard_size(cb, self.max_size) {
+                Some(size) if size <= self.max_size => {}
+                _ => return Some(REASON_SIZE),
+            }
+        }
+        None
+    }
+
+    /// Drop the clipboards which are not allowed. If none is left, the message to tell the peer is
+    /// returned. `outgoing` is true for the host clipboard.
+    pub fn retain(&self, clipboards: &mut Vec<Clipboard>, outgoing: bool) -> Option<Message> {
+        let mut blocked = None;
+        clipboards.retain(|cb| match self.check(cb) {
+            Some(reason) => {
+                if blocked.is_none() {
+                    blocked = Some(self.blocked_msg(outgoing, format_name(cb), reason, cb));
+                }
+                false
+            }
+            None => true,
+        });
+        if clipboards.is_empty() {
+            blocked
+        } else {
+            None
+        }
+    }
+
+    pub fn files_blocked_msg(&self, outgoing: bool) -> Message {
+        self.blocked_msg(outgoing, "files", REASON_FORMAT, &Clipboard::default())
+    }
+
+    fn blocked_msg(&self, outgoing: bool, format: &str, reason: &str, cb: &Clipboard) -> Message {
+        let mut misc = Misc::new();
+        misc.set_clipboard_blocked(ClipboardBlocked {
+            outgoing,
+            format: format.to_owned(),
+            reason: reason.to_owned(),
+            size: clipboard_size(cb, self.max_size).unwrap_or_default() as _,
+            max_size: self.max_size as _,
+            ..Default::default()
+        });
+        let mut msg = Message::new();
+        msg.set_misc(misc);
+        msg
+    }
+}
+
+fn format_bit(cb: &Clipboard) -> u32 {
+    match cb.format.enum_value() {
+        Ok(ClipboardFormat::Text) => FORMAT_TEXT,
+        Ok(ClipboardFormat::Rtf) => FORMAT_RTF,
+        Ok(ClipboardFormat::Html) => FORMAT_HTML,
+        Ok(ClipboardFormat::ImageRgba | ClipboardFormat::ImagePng | ClipboardFormat::ImageSvg) => {
+            FORMAT_IMAGE
+        }
+        Ok(ClipboardFormat::FileUrl) => FORMAT_FILES,
+        _ => FORMAT_OTHER,
+    }
+}
+
+fn format_name(cb: &Clipboard) -> &'static str {
+    match format_bit(cb) {
+        FORMAT_TEXT => "text",
+        FORMAT_RTF => "rtf",
+        FORMAT_HTML => "html",
+        FORMAT_IMAGE => "image",
+        FORMAT_FILES => "files",
+        _ => "other",
+    }
+}
+
+// The size after the decompression, which stops after `max_size` + 1 bytes, so a small
+// compressed clipboard can't take the memory. 0 for no limit. None if it can't be decompressed.
+fn clipboard_size(cb: &Clipboard, max_size: usize) -> Option<usize> {
+    if !cb.compress {
+        return Some(cb.content.len());
+    }
+    let decoder = zstd::stream::read::Decoder::new(&cb.content[..]).ok()?;
+    let limit = if max_size == 0 {
+        u64::MAX
+    } else {
+        max_size as u64 + 1
+    };
+    let size = std::io::copy(&mut decoder.take(limit), &mut std::io::sink()).ok()?;
+    Some(size as _)
+}
+
+#[inline]
+pub fn is_configured() -> bool {
+    !Config::get_option(OPTION_CLIPBOARD_POLICIES).is_empty()
+}
+
+pub fn set_peer(conn_id: i32, peer_id: Option<String>) {
+    PEER_IDS.lock().unwrap().insert(conn_id, peer_id);
+}
+
+pub fn remove(conn_id: i32) {
+    PEER_IDS.lock().unwrap().remove(&conn_id);
+}
+
+/// The host clipboard for the peer of the connection and the message to tell the peer what is
+/// blocked. The clipboard is None if nothing is allowed.
+pub fn outgoing(conn_id: i32, mut msg: Message) -> (Option<Message>, Option<Message>) {
+    let peer_id = PEER_IDS.lock().unwrap().get(&conn_id).cloned();
+    let Some(policy) = Policy::from_config(peer_id.as_ref().and_then(|id| id.as_deref())) else {
+        return (Some(msg), None);
+    };
+    // Fail closed, the peer of a connection which is not authorized, or gone, is unknown.
+    if peer_id.is_none() {
+        return (None, None);
+    }
+    let (empty, blocked) = match msg.union.as_mut() {
+        Some(message::Union::MultiClipboards(mcb)) => {
+            let blocked = policy.retain(&mut mcb.clipboards, true);
+            (mcb.clipboards.is_empty(), blocked)
+        }
+        Some(message::Union::Clipboard(cb)) => {
+            let mut clipboards = vec![std::mem::take(cb)];
+            let blocked = policy.retain(&mut clipboards, true);
+            match clipboards.pop() {
+                Some(c) => {
+                    *cb = c;
+                    (false, blocked)
+                }
+                None => (true, blocked),
+            }
+        }
+        _ => (false, None),
+    };
+    ((!empty).then_some(msg), blocked)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn clipboard(format: ClipboardFormat, size: usize) -> Clipboard {
+        Clipboard {
+            format: format.into(),
+            content: vec![0u8; size].into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_retain() {
+        let policy = Policy {
+            formats: FORMAT_TEXT,
+            max_size: 64,
+        };
+        // The html of a text copy is dropped silently.
+        let mut cbs = vec![
+            clipboard(ClipboardFormat::Text, 10),
+            clipboard(ClipboardFormat::Html, 10),
+        ];
+        assert!(policy.retain(&mut cbs, true).is_none());
+        assert_eq!(cbs.len(), 1);
+        assert!(!policy.allows_files());
+
+        let mut cbs = vec![clipboard(ClipboardFormat::ImagePng, 10)];
+        let msg = policy.retain(&mut cbs, false).unwrap();
+        assert!(cbs.is_empty());
+        let blocked = msg.misc().clipboard_blocked();
+        assert_eq!(blocked.format, "image");
+        assert_eq!(blocked.reason, REASON_FORMAT);
+        assert!(!blocked.outgoing);
+
+        let mut cbs = vec![clipboard(ClipboardFormat::Text, 65)];
+        let msg = policy.retain(&mut cbs, true).unwrap();
+        let blocked = msg.misc().clipboard_blocked();
+        assert_eq!(blocked.reason, REASON_SIZE);
+        assert_eq!((blocked.size, blocked.max_size), (65, 64));
+
+        let mut cbs = vec![clipboard(ClipboardFormat::ImageRgba, 1000)];
+        assert!(Policy::default().retain(&mut cbs, true).is_none());
+        assert_eq!(cbs.len(), 1);
+    }
+
+    #[test]
+    fn test_compressed_size() {
+        let mut cb = clipboard(ClipboardFormat::Text, 0);
+        cb.content = hbb_common::compress::compress(&vec![0u8; 1 << 20]).into();
+        cb.compress = true;
+        // The decompression stops right after the limit.
+        assert_eq!(clipboard_size(&cb, 64), Some(65));
+        assert_eq!(clipboard_size(&cb, 0), Some(1 << 20));
+        let policy = Policy {
+            formats: FORMAT_ALL,
+            max_size: 64,
+        };
+        let msg = policy.retain(&mut vec![cb], true).unwrap();
+        assert_eq!(msg.misc().clipboard_blocked().reason, REASON_SIZE);
+    }
+
+    #[test]
+    fn test_corrupt_compressed_size() {
+        let mut cb = clipboard(ClipboardFormat::Text, 10);
+        cb.compress = true;
+        assert_eq!(clipboard_size(&cb, 64), None);
+        let policy = Policy {
+            formats: FORMAT_ALL,
+            max_size: 64,
+        };
+        let msg = policy.retain(&mut vec![cb.clone()], true).unwrap();
+        assert_eq!(msg.misc().clipboard_blocked().reason, REASON_SIZE);
+        // Nothing to measure without a limit.
+        assert!(Policy::default().retain(&mut vec![cb], true).is_none());
+    }
+}
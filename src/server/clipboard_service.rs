@@ -30,6 +30,76 @@ use tokio::runtime::Runtime;
 #[cfg(target_os = "android")]
 static CLIPBOARD_SERVICE_OK: AtomicBool = AtomicBool::new(false);
 
+// In the manual mode the host clipboard is only sent when a peer asks for it, for the hosts whose
+// clipboard managers rewrite the clipboard all the time. The inbound clipboard is not affected.
+pub const OPTION_MANUAL_CLIPBOARD: &str = "manual-clipboard";
+
+lazy_static::lazy_static! {
+    // The connections which asked for the clipboard.
+    static ref SEND_REQUESTS: Mutex<Vec<i32>> = Default::default();
+    // The clipboard set by `set_host_clipboard()` to send to all the peers.
+    static ref SEND_TO_ALL: Mutex<Option<Message>> = Default::default();
+}
+
+#[inline]
+pub fn is_manual() -> bool {
+    Config::get_option(OPTION_MANUAL_CLIPBOARD) == "Y"
+}
+
+/// Send the current clipboard to the connection, it is picked up in the next interval.
+pub fn request_send(conn_id: i32) {
+    let mut requests = SEND_REQUESTS.lock().unwrap();
+    if !requests.contains(&conn_id) {
+        requests.push(conn_id);
+    }
+}
+
+fn take_send_requests() -> Vec<i32> {
+    std::mem::take(&mut *SEND_REQUESTS.lock().unwrap())
+}
+
+/// Set the text of the host clipboard as if it arrived from a peer, for the scripted workflows.
+///
+/// The same size cap and permission as the clipboard of a peer apply. The clipboard is marked as
+/// set by us, so it is not sent back to the peers, unless `send_to_peers`, then it is sent to the
+/// subscribers of the clipboard service in the next interval.
+#[cfg(not(target_os = "android"))]
+pub fn set_host_clipboard(content: String, send_to_peers: bool) -> ResultType<()> {
+    if !super::Connection::permission("enable-clipboard") {
+        bail!("The clipboard is disabled");
+    }
+    let cb = host_clipboard(content)?;
+    if send_to_peers {
+        let mut msg = Message::new();
+        msg.set_multi_clipboards(MultiClipboards {
+            clipboards: vec![cb.clone()],
+            ..Default::default()
+        });
+        *SEND_TO_ALL.lock().unwrap() = Some(msg);
+    }
+    crate::clipboard::update_clipboard(vec![cb], ClipboardSide::Host);
+    Ok(())
+}
+
+#[cfg(not(target_os = "android"))]
+fn host_clipboard(content: String) -> ResultType<Clipboard> {
+    if content.is_empty() {
+        bail!("The clipboard is empty");
+    }
+    if content.len() > super::message_limits::MAX_CLIPBOARD_SIZE {
+        bail!(
+            "The clipboard of {} bytes exceeds {}",
+            content.len(),
+            super::message_limits::MAX_CLIPBOARD_SIZE
+        );
+    }
+    Ok(Clipboard {
+        content: content.into_bytes().into(),
+        format: ClipboardFormat::Text.into(),
+        ..Default::default()
+    })
+}
+
 #[cfg(not(target_os = "android"))]
 struct Handler {
     ctx: Option<ClipboardContext>,
@@ -85,8 +155,11 @@ fn run(sp: EmptyExtraFieldService) -> ResultType<()> {
                     handler.check_clipboard_file();
                     continue;
                 }
-                if let Some(msg) = handler.get_clipboard_msg() {
-                    sp.send(msg);
+                // The requests and the sends to all below are still served in the manual mode.
+                if !is_manual() {
+                    if let Some(msg) = handler.get_clipboard_msg(false) {
+                        send(&sp, msg);
+                    }
                 }
             }
             Ok(CallbackResult::Stop) => {
@@ -102,6 +175,19 @@ fn run(sp: EmptyExtraFieldService) -> ResultType<()> {
                 break;
             }
         }
+        if sp.name() == NAME {
+            if let Some(msg) = SEND_TO_ALL.lock().unwrap().take() {
+                send(&sp, msg);
+            }
+            let requests = take_send_requests();
+            if !requests.is_empty() {
+                if let Some(msg) = handler.get_clipboard_msg(true) {
+                    for conn_id in requests {
+                        send_to(&sp, msg.clone(), conn_id);
+                    }
+                }
+            }
+        }
     }
 
     clipboard_listener::unsubscribe(&sp.name());
@@ -135,7 +221,7 @@ impl Handler {
         }
     }
 
-    fn get_clipboard_msg(&mut self) -> Option<Message> {
+    fn get_clipboard_msg(&mut self, force: bool) -> Option<Message> {
         #[cfg(target_os = "windows")]
         if crate::common::is_server() && crate::platform::is_root() {
             match self.read_clipboard_from_cm_ipc() {
@@ -172,7 +258,7 @@ impl Handler {
             }
         }
 
-        check_clipboard(&mut self.ctx, ClipboardSide::Host, false)
+        check_clipboard(&mut self.ctx, ClipboardSide::Host, force)
     }
 
     // Read clipboard data from cm using ipc.
@@ -263,12 +349,62 @@ impl Handler {
 #[cfg(target_os = "android")]
 fn run(sp: EmptyExtraFieldService) -> ResultType<()> {
     CLIPBOARD_SERVICE_OK.store(sp.ok(), Ordering::SeqCst);
+    // Only the changes can be got on Android, the last one is kept for the manual mode.
+    let mut last_msg = None;
     while sp.ok() {
         if let Some(msg) = crate::clipboard::get_clipboards_msg(false) {
-            sp.send(msg);
+            if is_manual() {
+                last_msg = Some(msg);
+            } else {
+                send(&sp, msg);
+            }
+        }
+        let requests = take_send_requests();
+        if let Some(msg) = last_msg.as_ref() {
+            for conn_id in requests {
+                send_to(&sp, msg.clone(), conn_id);
+            }
         }
         std::thread::sleep(Duration::from_millis(INTERVAL));
     }
     CLIPBOARD_SERVICE_OK.store(false, Ordering::SeqCst);
     Ok(())
 }
+
+// The host clipboard is sent as the clipboard policy of each peer allows, see `clipboard_policy`.
+fn send(sp: &EmptyExtraFieldService, msg: Message) {
+    if !super::clipboard_policy::is_configured() {
+        sp.send(msg);
+        return;
+    }
+    for conn_id in sp.subscriber_ids() {
+        send_to(sp, msg.clone(), conn_id);
+    }
+}
+
+fn send_to(sp: &EmptyExtraFieldService, msg: Message, conn_id: i32) {
+    let (msg, blocked) = super::clipboard_policy::outgoing(conn_id, msg);
+    if let Some(msg) = msg {
+        sp.send_to(msg, conn_id);
+    }
+    if let Some(blocked) = blocked {
+        sp.send_to(blocked, conn_id);
+    }
+}
+
+#[cfg(all(test, not(target_os = "android")))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_host_clipboard() {
+        assert!(host_clipboard("".to_owned()).is_err());
+        let max = super::super::message_limits::MAX_CLIPBOARD_SIZE;
+        assert!(host_clipboard("a".repeat(max + 1)).is_err());
+        let cb = host_clipboard("a".repeat(max)).unwrap();
+        assert_eq!(cb.content.len(), max);
+        let cb = host_clipboard("license-key".to_owned()).unwrap();
+        assert_eq!(&cb.content[..], b"license-key");
+        assert_eq!(cb.format.enum_value(), Ok(ClipboardFormat::Text));
+    }
+}
@@ -1,4 +1,4 @@
-use super::{input_service::*, *};
+use super::{input_service::*, message_limits::MessageLimits, *};
 #[cfg(feature = "unix-file-copy-paste")]
 use crate::clipboard::try_empty_clipboard_files;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -15,9 +15,13 @@ use crate::platform::WallPaperRemover;
 use crate::portable_service::client as portable_client;
 use crate::{
     client::{
-        new_voice_call_request, new_voice_call_response, start_audio_thread, MediaData, MediaSender,
+        new_voice_call_busy_response, new_voice_call_request, new_voice_call_response,
+        start_voice_call_audio_thread, MediaData, MediaSender,
     },
-    display_service, ipc, privacy_mode, video_service, VERSION,
+    common::{normalize_clipboards, Capabilities, Capability, LineEnding},
+    display_service,
+    error_code::{CodedError, ErrorCode},
+    ipc, privacy_mode, video_service, VERSION,
 };
 #[cfg(any(target_os = "android", target_os = "ios"))]
 use crate::{common::DEVICE_NAME, flutter::connection_manager::start_channel};
@@ -30,7 +34,7 @@ use hbb_common::{
     config::{self, keys, Config, TrustedDevice},
     fs::{self, can_enable_overwrite_detection, JobType},
     futures::{SinkExt, StreamExt},
-    get_time, get_version_number,
+    get_version_number,
     message_proto::{option_message::BoolOption, permission_info::Permission},
     password_security::{self as password, ApproveMode},
     sha2::{Digest, Sha256},
@@ -47,12 +51,13 @@ use scrap::android::{call_main_service_key_event, call_main_service_pointer_inpu
 use scrap::camera;
 use serde_derive::Serialize;
 use serde_json::{json, value::Value};
-#[cfg(not(any(target_os = "android", target_os = "ios")))]
-use std::sync::atomic::Ordering;
 use std::{
     num::NonZeroI64,
     path::PathBuf,
-    sync::{atomic::AtomicI64, mpsc as std_mpsc},
+    sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicI64, Ordering},
+        mpsc as std_mpsc,
+    },
 };
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use system_shutdown;
@@ -64,9 +69,12 @@ use std::collections::HashSet;
 pub type Sender = mpsc::UnboundedSender<(Instant, Arc<Message>)>;
 
 lazy_static::lazy_static! {
+    // ip -> (minute by `failure_minute`, failures in the minute, all failures)
     static ref LOGIN_FAILURES: [Arc::<Mutex<HashMap<String, (i32, i32, i32)>>>; 2] = Default::default();
     static ref SESSIONS: Arc::<Mutex<HashMap<SessionKey, Session>>> = Default::default();
     static ref ALIVE_CONNS: Arc::<Mutex<Vec<i32>>> = Default::default();
+    // id -> (accepted time, ip) of the connections not authorized yet.
+    static ref PENDING_AUTH_CONNS: Mutex<HashMap<i32, (Instant, String)>> = Default::default();
     pub static ref AUTHED_CONNS: Arc::<Mutex<Vec<AuthedConn>>> = Default::default();
     static ref SWITCH_SIDES_UUID: Arc::<Mutex<HashMap<String, (Instant, uuid::Uuid)>>> = Default::default();
     static ref WAKELOCK_SENDER: Arc::<Mutex<std::sync::mpsc::Sender<(usize, usize)>>> = Arc::new(Mutex::new(start_wakelock_thread()));
@@ -122,6 +130,20 @@ pub struct ConnInner {
     id: i32,
     tx: Option<Sender>,
     tx_video: Option<Sender>,
+    stats: Arc<session_report::ConnStats>,
+    // The newest message versions of the peer, see `common::local_msg_versions`.
+    msg_versions: Arc<HashMap<String, u32>>,
+    // Shared with the server, see `Server::defer_for_reauth`.
+    reauth: Arc<Reauth>,
+}
+
+// The pending re-authentication of a connection. The flag is checked on every input event,
+// without the lock of the server.
+#[derive(Default)]
+struct Reauth {
+    pending: AtomicBool,
+    // The new challenge sent to the peer, the password of the login is never reused.
+    challenge: Mutex<String>,
 }
 
 enum MessageInput {
@@ -131,6 +153,8 @@ enum MessageInput {
     Key((KeyEvent, bool)),
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     Pointer((PointerDeviceEvent, i32)),
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    FocusGained(Vec<EnumOrUnknown<ControlKey>>),
     BlockOn,
     BlockOff,
     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
@@ -153,6 +177,8 @@ struct Session {
     last_recv_time: Arc<Mutex<Instant>>,
     random_password: String,
     tfa: bool,
+    // The access rule which admitted the session, it admits a reconnection again.
+    access_rule: Option<access_policy::ConnType>,
 }
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -170,6 +196,8 @@ pub enum AuthConnType {
     PortForward,
     ViewCamera,
     Terminal,
+    AudioMonitor,
+    Preview,
 }
 
 pub struct Connection {
@@ -184,6 +212,22 @@ pub struct Connection {
     file_transfer: Option<(String, bool)>,
     view_camera: bool,
     terminal: bool,
+    // Audio only, input/clipboard/video are always blocked.
+    audio_monitor: bool,
+    // (display, interval), only the rate-limited stills are sent.
+    preview: Option<(usize, Duration)>,
+    // (action, token, time), the power action waiting for the confirmation of the peer.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    power_action: Option<(crate::platform::power::PowerAction, u64, Instant)>,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    process_manager: Option<crate::platform::process::ProcessManager>,
+    // (name, token, time), the quick action waiting for the confirmation of the peer.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    quick_action: Option<(String, u64, Instant)>,
+    // The name of the quick action running in the connection manager, one at a time.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    quick_action_running: Option<String>,
+    message_limits: MessageLimits,
     port_forward_socket: Option<Framed<TcpStream, BytesCodec>>,
     port_forward_address: String,
     tx_to_cm: mpsc::UnboundedSender<ipc::Data>,
@@ -196,8 +240,38 @@ pub struct Connection {
     restart: bool,
     recording: bool,
     block_input: bool,
+    annotation: bool,
+    quick_actions: bool,
     last_test_delay: Option<Instant>,
     network_delay: u32,
+    session_stats: session_report::SessionStats,
+    encrypted: bool,
+    // See `session_fingerprint`, `UNVERIFIED` if not encrypted.
+    session_fingerprint: String,
+    // The address of the peer if the connection is relayed, it can be moved to another relay.
+    relay_peer_addr: Option<SocketAddr>,
+    // The stream through the new relay, see `migrate_relay`.
+    tx_relay_migrated: mpsc::UnboundedSender<super::Stream>,
+    // Until the connection is authorized.
+    pending_auth: Option<PendingAuthID>,
+    cursor_batch: cursor_batch::CursorBatch,
+    // When the peer was last told that its clipboard policy blocked a clipboard.
+    clipboard_blocked_time: Option<Instant>,
+    // The login request must be received before it. A connection which sent it and still waits
+    // for the password or the approval keeps running after it, but it no longer holds a slot of
+    // the pending connections.
+    login_deadline: Option<Instant>,
+    login_received: bool,
+    network_sim: network_sim::Link,
+    lanes: send_lanes::Lanes,
+    display_filter: Option<display_filter::DisplayFilter>,
+    // The peer id once the peer proved it with the 2FA of this host, in this session or as a
+    // trusted device. The per-peer policies are keyed on it, never on the claimed `lr.my_id`.
+    trusted_peer_id: Option<String>,
+    // Holding the control token of the exclusive mode.
+    control_token: bool,
+    // Some if the window of the peer is minimized or hidden.
+    client_hidden: Option<client_visibility::Hidden>,
     lock_after_session_end: bool,
     show_remote_cursor: bool,
     // by peer
@@ -208,6 +282,12 @@ pub struct Connection {
     disable_clipboard: bool,
     // by peer
     disable_audio: bool,
+    low_delay_audio: bool,
+    // The dropped video frames are counted and sent with `TestDelay`.
+    frame_drop_stats: bool,
+    // by peer
+    clipboard_line_ending: LineEnding,
+    clipboard_delta: crate::clipboard_delta::Encoder,
     // by peer
     #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
     enable_file_transfer: bool,
@@ -220,6 +300,9 @@ pub struct Connection {
     server_audit_conn: String,
     server_audit_file: String,
     lr: LoginRequest,
+    peer_capabilities: Capabilities,
+    #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
+    file_list_assembler: FileListAssembler,
     session_last_recv_time: Option<Arc<Mutex<Instant>>>,
     chat_unanswered: bool,
     file_transferred: bool,
@@ -228,9 +311,17 @@ pub struct Connection {
     from_switch: bool,
     voice_call_request_timestamp: Option<NonZeroI64>,
     voice_calling: bool,
+    // The push-to-talk key of the host is up in the voice call of this connection.
+    push_to_talk_muted: bool,
+    // The peer asked to play the voice call into the virtual microphone.
+    voice_call_virtual_mic: bool,
+    // The code of the close reason sent to the peer, for the audit.
+    close_code: ErrorCode,
     options_in_login: Option<OptionMessage>,
     #[cfg(not(any(target_os = "ios")))]
     pressed_modifiers: HashSet<rdev::Key>,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    injected_keys: InjectedKeys,
     #[cfg(target_os = "linux")]
     linux_headless_handle: LinuxHeadlessHandle,
     closed: bool,
@@ -246,20 +337,66 @@ pub struct Connection {
     retina: Retina,
     follow_remote_cursor: bool,
     follow_remote_window: bool,
+    // (display name, resolution) changed by this connection, saved for the session restore.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    changed_resolutions: Vec<(String, (i32, i32))>,
     multi_ui_session: bool,
     tx_from_authed: mpsc::UnboundedSender<ipc::Data>,
-    printer_data: Vec<(Instant, String, Vec<u8>)>,
+    // (time, path, data, id in the printer queue)
+    printer_data: Vec<(Instant, String, Vec<u8>, u64)>,
+    // (file job id, id in the printer queue), the print jobs being transferred.
+    printing_jobs: Vec<(i32, u64)>,
     // For post requests that need to be sent sequentially.
     // eg. post_conn_audit
     tx_post_seq: mpsc::UnboundedSender<(String, Value)>,
     terminal_service_id: String,
     terminal_persistent: bool,
     terminal_generic_service: Option<Box<GenericService>>,
+    // The sensitive events waiting for the confirmation of the user of the host.
+    sensitive_pending: sensitive_events::Pending,
 }
 
 impl ConnInner {
     pub fn new(id: i32, tx: Option<Sender>, tx_video: Option<Sender>) -> Self {
-        Self { id, tx, tx_video }
+        Self {
+            id,
+            tx,
+            tx_video,
+            stats: Default::default(),
+            msg_versions: Default::default(),
+            reauth: Default::default(),
+        }
+    }
+
+    #[inline]
+    pub fn stats(&self) -> &session_report::ConnStats {
+        &self.stats
+    }
+
+    pub fn set_msg_versions(&mut self, msg_versions: HashMap<String, u32>) {
+        self.msg_versions = Arc::new(msg_versions);
+    }
+
+    #[inline]
+    pub fn is_reauth_pending(&self) -> bool {
+        self.reauth.pending.load(Ordering::SeqCst)
+    }
+
+    /// Starts the re-authentication with a new challenge, which is returned.
+    pub fn request_reauth(&self) -> String {
+        let challenge = Config::get_auto_password(6);
+        *self.reauth.challenge.lock().unwrap() = challenge.clone();
+        self.reauth.pending.store(true, Ordering::SeqCst);
+        challenge
+    }
+
+    pub fn clear_reauth(&self) {
+        self.reauth.pending.store(false, Ordering::SeqCst);
+        self.reauth.challenge.lock().unwrap().clear();
+    }
+
+    pub(super) fn reauth_challenge(&self) -> Option<String> {
+        Some(self.reauth.challenge.lock().unwrap().clone()).filter(|c| !c.is_empty())
     }
 }
 
@@ -272,15 +409,8 @@ impl Subscriber for ConnInner {
     #[inline]
     fn send(&mut self, msg: Arc<Message>) {
         // Send SwitchDisplay on the same channel as VideoFrame to avoid send order problems.
-        let tx_by_video = match &msg.union {
-            Some(message::Union::VideoFrame(_)) => true,
-            Some(message::Union::Misc(misc)) => match &misc.union {
-                Some(misc::Union::SwitchDisplay(_)) => true,
-                _ => false,
-            },
-            _ => false,
-        };
-        let tx = if tx_by_video {
+        // The writer puts the messages of both channels in the send lanes.
+        let tx = if send_lanes::Lane::of(&msg) == send_lanes::Lane::Video {
             self.tx_video.as_mut()
         } else {
             self.tx.as_mut()
@@ -289,6 +419,11 @@ impl Subscriber for ConnInner {
             allow_err!(tx.send((Instant::now(), msg)));
         });
     }
+
+    #[inline]
+    fn msg_version(&self, kind: &str) -> u32 {
+        self.msg_versions.get(kind).cloned().unwrap_or(0)
+    }
 }
 
 const TEST_DELAY_TIMEOUT: Duration = Duration::from_secs(1);
@@ -298,6 +433,18 @@ const MILLI1: Duration = Duration::from_millis(1);
 const SEND_TIMEOUT_VIDEO: u64 = 12_000;
 const SEND_TIMEOUT_OTHER: u64 = SEND_TIMEOUT_VIDEO * 10;
 const SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+// At most one `ClipboardBlocked` in it, a blocked copy is retried on every clipboard change.
+const CLIPBOARD_BLOCKED_INTERVAL: Duration = Duration::from_secs(10);
+pub const PROTOCOL_VIOLATION: &str = "Protocol violation";
+// The connections not authorized yet are bounded, so a peer can't take all the ids, memory and
+// accept slots with connections which never log in. 0 means no limit.
+pub(super) const OPTION_MAX_PENDING_AUTH_CONNS: &str = "max-pending-auth-conns";
+const DEFAULT_MAX_PENDING_AUTH_CONNS: usize = 50;
+// Seconds to wait for the login request after the handshake, 0 means no deadline.
+// The password and the approval after it are not limited, they may wait for the users, but the
+// connection stops counting as pending at the deadline, so the waits can't exhaust the limit.
+pub(super) const OPTION_PENDING_AUTH_TIMEOUT: &str = "pending-auth-timeout";
+const DEFAULT_PENDING_AUTH_TIMEOUT: u64 = 15;
 
 impl Connection {
     pub async fn start(
@@ -305,13 +452,13 @@ impl Connection {
         stream: super::Stream,
         id: i32,
         server: super::ServerPtrWeak,
+        relay: bool,
+        hash: Hash,
+        pending_auth: PendingAuthID,
+        fingerprint: Option<String>,
     ) {
         let _raii_id = raii::ConnectionID::new(id);
-        let hash = Hash {
-            salt: Config::get_salt(),
-            challenge: Config::get_auto_password(6),
-            ..Default::default()
-        };
+        let encrypted = stream.is_secured();
         let (tx_from_cm_holder, mut rx_from_cm) = mpsc::unbounded_channel::<ipc::Data>();
         // holding tx_from_cm_holder to avoid cpu burning of rx_from_cm.recv when all sender closed
         let tx_from_cm = tx_from_cm_holder.clone();
@@ -320,7 +467,9 @@ impl Connection {
         let (tx_video, mut rx_video) = mpsc::unbounded_channel::<(Instant, Arc<Message>)>();
         let (tx_input, _rx_input) = std_mpsc::channel();
         let (tx_from_authed, mut rx_from_authed) = mpsc::unbounded_channel::<ipc::Data>();
+        let (tx_relay_migrated, mut rx_relay_migrated) = mpsc::unbounded_channel::<super::Stream>();
         let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+        let mut service_rx = super::service_monitor::subscribe();
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         let (tx_cm_stream_ready, _rx_cm_stream_ready) = mpsc::channel(1);
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -336,11 +485,15 @@ impl Connection {
 
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         let tx_cloned = tx.clone();
+        let session_stats = session_report::SessionStats::new(relay);
         let mut conn = Self {
             inner: ConnInner {
                 id,
                 tx: Some(tx),
                 tx_video: Some(tx_video),
+                stats: session_stats.window().clone(),
+                msg_versions: Default::default(),
+                reauth: Default::default(),
             },
             require_2fa: crate::auth_2fa::get_2fa(None),
             display_idx: *display_service::PRIMARY_DISPLAY_IDX,
@@ -353,6 +506,17 @@ impl Connection {
             file_transfer: None,
             view_camera: false,
             terminal: false,
+            audio_monitor: false,
+            preview: None,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            power_action: None,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            process_manager: None,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            quick_action: None,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            quick_action_running: None,
+            message_limits: MessageLimits::new(),
             port_forward_socket: None,
             port_forward_address: "".to_owned(),
             tx_to_cm,
@@ -365,15 +529,40 @@ impl Connection {
             restart: Connection::permission("enable-remote-restart"),
             recording: Connection::permission("enable-record-session"),
             block_input: Connection::permission("enable-block-input"),
+            annotation: Connection::permission("enable-annotation"),
+            quick_actions: Connection::permission("enable-quick-actions"),
             last_test_delay: None,
             network_delay: 0,
+            session_stats,
+            encrypted,
+            session_fingerprint: fingerprint
+                .unwrap_or_else(|| crate::session_fingerprint::UNVERIFIED.to_owned()),
+            relay_peer_addr: relay.then_some(addr),
+            tx_relay_migrated,
+            pending_auth: Some(pending_auth),
+            cursor_batch: Default::default(),
+            clipboard_blocked_time: None,
+            login_deadline: pending_auth_timeout().map(|t| session_clock::instant() + t),
+            login_received: false,
+            network_sim: Default::default(),
+            lanes: Default::default(),
+            display_filter: None,
+            trusted_peer_id: None,
+            control_token: false,
+            client_hidden: None,
             lock_after_session_end: false,
             show_remote_cursor: false,
             follow_remote_cursor: false,
             follow_remote_window: false,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            changed_resolutions: Default::default(),
             multi_ui_session: false,
             ip: "".to_owned(),
             disable_audio: false,
+            low_delay_audio: false,
+            frame_drop_stats: false,
+            clipboard_line_ending: LineEnding::Preserve,
+            clipboard_delta: Default::default(),
             #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
             enable_file_transfer: false,
             disable_clipboard: false,
@@ -383,6 +572,9 @@ impl Connection {
             server_audit_conn: "".to_owned(),
             server_audit_file: "".to_owned(),
             lr: Default::default(),
+            peer_capabilities: Default::default(),
+            #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
+            file_list_assembler: Default::default(),
             session_last_recv_time: None,
             chat_unanswered: false,
             file_transferred: false,
@@ -391,10 +583,15 @@ impl Connection {
             from_switch: false,
             audio_sender: None,
             voice_call_request_timestamp: None,
+            close_code: ErrorCode::Unknown,
             voice_calling: false,
+            push_to_talk_muted: false,
+            voice_call_virtual_mic: false,
             options_in_login: None,
             #[cfg(not(any(target_os = "ios")))]
             pressed_modifiers: Default::default(),
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            injected_keys: Default::default(),
             #[cfg(target_os = "linux")]
             linux_headless_handle,
             closed: false,
@@ -415,11 +612,14 @@ impl Connection {
             retina: Retina::default(),
             tx_from_authed,
             printer_data: Vec::new(),
+            printing_jobs: Vec::new(),
             tx_post_seq,
             terminal_service_id: "".to_owned(),
             terminal_persistent: false,
             terminal_generic_service: None,
+            sensitive_pending: Default::default(),
         };
+        conn.update_cursor_batch();
         let addr = hbb_common::try_into_v4(addr);
         if !conn.on_open(addr).await {
             conn.closed = true;
@@ -454,9 +654,15 @@ impl Connection {
         if !conn.block_input {
             conn.send_permission(Permission::BlockInput, false).await;
         }
+        if !conn.annotation {
+            conn.send_permission(Permission::Annotation, false).await;
+        }
+        if !conn.quick_actions {
+            conn.send_permission(Permission::QuickActions, false).await;
+        }
         let mut test_delay_timer =
             crate::rustdesk_interval(time::interval_at(Instant::now(), TEST_DELAY_TIMEOUT));
-        let mut last_recv_time = Instant::now();
+        let mut last_recv_time = session_clock::instant();
 
         conn.stream.set_send_timeout(
             if conn.file_transfer.is_some() || conn.port_forward_socket.is_some() || conn.terminal {
@@ -508,7 +714,7 @@ impl Connection {
                         ipc::Data::Close => {
                             conn.chat_unanswered = false; // seen
                             conn.file_transferred = false; //seen
-                            conn.send_close_reason_no_retry("").await;
+                            conn.send_close_reason_no_retry(CLOSE_REASON_ADMIN).await;
                             conn.on_close("connection manager", true).await;
                             break;
                         }
@@ -532,7 +738,11 @@ impl Connection {
                         }
                         ipc::Data::SwitchPermission{name, enabled} => {
                             log::info!("Change permission {} -> {}", name, enabled);
-                            if &name == "keyboard" {
+                            if conn.audio_monitor && &name != "audio" {
+                                log::info!("Ignore permission {} of audio monitor connection", name);
+                            } else if conn.preview.is_some() {
+                                log::info!("Ignore permission {} of preview connection", name);
+                            } else if &name == "keyboard" {
                                 conn.keyboard = enabled;
                                 conn.send_permission(Permission::Keyboard, enabled).await;
                                 if let Some(s) = conn.server.upgrade() {
@@ -561,19 +771,7 @@ impl Connection {
                                 conn.audio = enabled;
                                 conn.send_permission(Permission::Audio, enabled).await;
                                 if conn.authorized {
-                                    if let Some(s) = conn.server.upgrade() {
-                                        if conn.is_authed_view_camera_conn() {
-                                            if conn.voice_calling || !conn.audio_enabled() {
-                                                s.write().unwrap().subscribe(
-                                                    super::audio_service::NAME,
-                                                    conn.inner.clone(), conn.audio_enabled());
-                                            }
-                                        } else {
-                                            s.write().unwrap().subscribe(
-                                                super::audio_service::NAME,
-                                                conn.inner.clone(), conn.audio_enabled());
-                                        }
-                                    }
+                                    conn.update_audio_subscription();
                                 }
                             } else if &name == "file" {
                                 conn.file = enabled;
@@ -599,14 +797,53 @@ impl Connection {
                             } else if &name == "block_input" {
                                 conn.block_input = enabled;
                                 conn.send_permission(Permission::BlockInput, enabled).await;
+                            } else if &name == "annotation" {
+                                conn.annotation = enabled;
+                                conn.send_permission(Permission::Annotation, enabled).await;
+                                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                                if !enabled {
+                                    conn.send_to_cm(ipc::Data::Annotation(
+                                        crate::annotation::Command::Clear,
+                                    ));
+                                }
+                            } else if &name == "quick_actions" {
+                                conn.quick_actions = enabled;
+                                conn.send_permission(Permission::QuickActions, enabled).await;
+                                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                                if !enabled && conn.quick_action_running.is_some() {
+                                    conn.send_to_cm(ipc::Data::QuickAction(None));
+                                }
                             }
                         }
                         ipc::Data::RawMessage(bytes) => {
                             allow_err!(conn.stream.send_raw(bytes).await);
                         }
+                        ipc::Data::FileScanBlocked(blocked) => {
+                            for (path, reason) in blocked {
+                                conn.post_file_audit(
+                                    FileAuditType::RemoteReceive,
+                                    &path,
+                                    vec![("".to_owned(), 0)],
+                                    json!({ "blocked": reason }),
+                                );
+                            }
+                        }
                         #[cfg(target_os = "windows")]
                         ipc::Data::ClipboardFile(clip) => {
-                            allow_err!(conn.stream.send(&clip_2_msg(clip)).await);
+                            let blocked = match clip {
+                                clipboard::ClipboardFile::FormatList { .. } => {
+                                    conn.clipboard_files_blocked(true)
+                                }
+                                _ => None,
+                            };
+                            if let Some(msg) = blocked {
+                                conn.send_clipboard_blocked(msg).await;
+                            } else {
+                                let chunked = conn.supports(Capability::ChunkedClipboardFileList);
+                                for msg in split_file_list(clip_2_msg(clip), chunked) {
+                                    allow_err!(conn.stream.send(&msg).await);
+                                }
+                            }
                         }
                         ipc::Data::PrivacyModeState((_, state, impl_key)) => {
                             let msg_out = match state {
@@ -635,6 +872,8 @@ impl Connection {
                         ipc::Data::DataPortableService(ipc::DataPortableService::RequestStart) => {
                             if let Err(e) = portable_client::start_portable_service(portable_client::StartPara::Direct) {
                                 log::error!("Failed to start portable service from cm: {:?}", e);
+                            } else {
+                                conn.portable.elevation_requested = true;
                             }
                         }
                         ipc::Data::SwitchSidesBack => {
@@ -647,6 +886,23 @@ impl Connection {
                         ipc::Data::VoiceCallResponse(accepted) => {
                             conn.handle_voice_call(accepted).await;
                         }
+                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                        ipc::Data::ProcessKillResponse((pid, approved)) => {
+                            conn.handle_process_kill_approval(pid, approved).await;
+                        }
+                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                        ipc::Data::AnnotationUnsupported => {
+                            conn.on_annotation_unsupported().await;
+                        }
+                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                        ipc::Data::QuickActionOutput(output) => {
+                            conn.on_quick_action_output(output).await;
+                        }
+                        ipc::Data::SensitiveEventConfirm((event, confirmed)) => {
+                            if !conn.handle_sensitive_event_confirm(event, confirmed).await {
+                                break;
+                            }
+                        }
                         ipc::Data::CloseVoiceCall(_reason) => {
                             log::debug!("Close the voice call from the ipc.");
                             conn.close_voice_call().await;
@@ -665,9 +921,20 @@ impl Connection {
                                 break;
                             },
                             Ok(bytes) => {
-                                last_recv_time = Instant::now();
-                                conn.session_last_recv_time.as_mut().map(|t| *t.lock().unwrap() = Instant::now());
-                                if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
+                                last_recv_time = session_clock::instant();
+                                conn.session_stats.on_received(bytes.len());
+                                conn.session_last_recv_time.as_mut().map(|t| *t.lock().unwrap() = session_clock::instant());
+                                let msg_in = match conn.message_limits.parse(&bytes) {
+                                    Ok(msg_in) => Some(msg_in),
+                                    Err(err) if err.is::<hbb_common::protobuf::Error>() => None,
+                                    Err(err) => {
+                                        log::warn!("#{} protocol violation: {}", id, err);
+                                        conn.send_close_reason_no_retry(ErrorCode::ProtocolViolation).await;
+                                        conn.on_close(&format!("{}: {}", PROTOCOL_VIOLATION, err), false).await;
+                                        break;
+                                    }
+                                };
+                                if let Some(msg_in) = msg_in {
                                     if !conn.on_message(msg_in).await {
                                         break;
                                     }
@@ -694,6 +961,7 @@ impl Connection {
                                 if !log.is_empty() {
                                     conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), log)));
                                 }
+                                conn.check_printing_jobs();
                             }
                             Err(err) =>  {
                                 conn.on_close(&err.to_string(), false).await;
@@ -706,120 +974,168 @@ impl Connection {
                 }
                 Ok(conns) = hbbs_rx.recv() => {
                     if conns.contains(&id) {
-                        conn.send_close_reason_no_retry("Closed manually by web console").await;
+                        conn.send_close_reason_no_retry(ErrorCode::ClosedByWebConsole).await;
                         conn.on_close("web console", true).await;
                         break;
                     }
                 }
-                Some((instant, value)) = rx_video.recv() => {
-                    if !conn.video_ack_required {
-                        video_service::notify_video_frame_fetched(id, Some(instant.into()));
+                Ok(event) = service_rx.recv() => {
+                    if event.conns.contains(&id) {
+                        conn.on_service_failed(event).await;
                     }
-                    if let Err(err) = conn.stream.send(&value as &Message).await {
-                        conn.on_close(&err.to_string(), false).await;
-                        break;
+                }
+                Some((instant, value)) = rx_video.recv(), if !conn.lanes.is_full(send_lanes::Lane::Video) => {
+                    conn.push_to_lanes(instant, value);
+                    while !conn.lanes.is_full(send_lanes::Lane::Video) {
+                        let Ok((instant, value)) = rx_video.try_recv() else {
+                            break;
+                        };
+                        conn.push_to_lanes(instant, value);
                     }
-                },
-                Some((instant, value)) = rx.recv() => {
-                    let latency = instant.elapsed().as_millis() as i64;
-                    #[allow(unused_mut)]
-                    let mut msg = value;
-
-                    if latency > 1000 {
-                        match &msg.union {
-                            Some(message::Union::AudioFrame(_)) => {
-                                // log::info!("audio frame latency {}", instant.elapsed().as_secs_f32());
-                                continue;
-                            }
-                            _ => {}
-                        }
+                }
+                Some((instant, value)) = rx.recv(), if conn.lanes.can_push_other() => {
+                    conn.push_to_lanes(instant, value);
+                    while conn.lanes.can_push_other() {
+                        let Ok((instant, value)) = rx.try_recv() else {
+                            break;
+                        };
+                        conn.push_to_lanes(instant, value);
                     }
-                    match &msg.union {
-                        Some(message::Union::Misc(m)) => {
-                            match &m.union {
-                                Some(misc::Union::StopService(_)) => {
-                                    conn.send_close_reason_no_retry("").await;
-                                    conn.on_close("stop service", false).await;
-                                    break;
-                                }
-                                _ => {},
-                            }
-                        }
-                        Some(message::Union::PeerInfo(_pi)) => {
-                            conn.refresh_video_display(None);
-                            #[cfg(target_os = "macos")]
-                            conn.retina.set_displays(&_pi.displays);
+                }
+                _ = std::future::ready(()), if !conn.lanes.is_empty() && conn.network_sim.is_free() => {
+                    let Some((lane, instant, value)) = conn.lanes.pop() else {
+                        continue;
+                    };
+                    if lane == send_lanes::Lane::Video {
+                        if !conn.video_ack_required {
+                            let name = match &value.union {
+                                Some(message::Union::VideoFrame(vf)) => Some(video_service::get_service_name(conn.video_source(), vf.display as _)),
+                                _ => None,
+                            };
+                            video_service::notify_video_frame_fetched(id, name.as_deref(), Some(instant.into()));
                         }
-                        Some(message::Union::CursorPosition(pos)) => {
-                            #[cfg(not(any(target_os = "android", target_os = "ios")))]
-                            {
-                                if conn.follow_remote_cursor {
-                                    conn.handle_cursor_switch_display(pos.clone()).await;
-                                }
-                            }
-                            #[cfg(target_os = "macos")]
-                            if let Some(new_msg) = conn.retina.on_cursor_pos(&pos, conn.display_idx) {
-                                msg = Arc::new(new_msg);
-                            }
+                        let value = match conn.display_filter.as_ref() {
+                            Some(filter) => match filter.outgoing(value) {
+                                Some(value) => value,
+                                None => continue,
+                            },
+                            None => value,
+                        };
+                        if !conn.write_msg(instant, &value).await {
+                            break;
                         }
-                        Some(message::Union::MultiClipboards(_multi_clipboards)) => {
-                            #[cfg(not(target_os = "ios"))]
-                            if let Some(msg_out) = crate::clipboard::get_msg_if_not_support_multi_clip(&conn.lr.version, &conn.lr.my_platform, _multi_clipboards) {
-                                if let Err(err) = conn.stream.send(&msg_out).await {
-                                    conn.on_close(&err.to_string(), false).await;
-                                    break;
-                                }
-                                continue;
-                            }
+                    } else if !conn.send_from_lane(instant, value).await {
+                        break;
+                    }
+                },
+                _ = time::sleep_until(conn.cursor_batch.deadline().unwrap_or_else(|| Instant::now() + H1)), if conn.cursor_batch.deadline().is_some() => {
+                    if let Some(batch) = conn.cursor_batch.take() {
+                        if !conn.send_cursor_batch(Instant::now(), batch).await {
+                            break;
                         }
-                        _ => {}
                     }
-
-                    let msg: &Message = &msg;
-                    if let Err(err) = conn.stream.send(msg).await {
-                        conn.on_close(&err.to_string(), false).await;
+                }
+                _ = time::sleep_until(conn.network_sim.deadline().unwrap_or_else(|| Instant::now() + H1)), if conn.network_sim.deadline().is_some() => {
+                    if !conn.send_delivered().await {
                         break;
                     }
-                },
+                }
+                Some(stream) = rx_relay_migrated.recv() => {
+                    conn.on_relay_migrated(stream).await;
+                }
                 Some(data) = rx_from_authed.recv() => {
                     match data {
                         #[cfg(all(target_os = "windows", feature = "flutter"))]
-                        ipc::Data::PrinterData(data) => {
-                            if config::Config::get_bool_option(config::keys::OPTION_ENABLE_REMOTE_PRINTER) {
-                                conn.send_printer_request(data).await;
-                            } else {
+                        ipc::Data::PrinterJob(job_id) => {
+                            // The job stays in the queue, it is sent again on the next connection.
+                            if !config::Config::get_bool_option(config::keys::OPTION_ENABLE_REMOTE_PRINTER) {
+                                conn.send_remote_printing_disallowed().await;
+                            } else if !is_feature_allowed(FEATURE_PRINTER, conn.policy_peer_id()) {
+                                conn.post_feature_denied_audit(FEATURE_PRINTER);
                                 conn.send_remote_printing_disallowed().await;
+                            } else {
+                                conn.send_printer_request(job_id).await;
+                            }
+                        }
+                        ipc::Data::FeatureAllowlistChanged => {
+                            if !conn.on_feature_allowlist_changed().await {
+                                break;
                             }
                         }
+                        ipc::Data::ControlTokenChanged => {
+                            conn.on_control_token_changed().await;
+                        }
+                        ipc::Data::MigrateRelay { relay_server, .. } => {
+                            conn.migrate_relay(relay_server).await;
+                        }
+                        ipc::Data::VoiceCallIncoming => {
+                            conn.offer_waiting_voice_call();
+                        }
+                        ipc::Data::PushToTalk(on) => {
+                            conn.push_to_talk_muted = !on;
+                            conn.update_audio_subscription();
+                        }
                         _ => {}
                     }
                 }
                 _ = second_timer.tick() => {
+                    if deadline_passed(conn.login_deadline) {
+                        conn.login_deadline = None;
+                        if !conn.login_received {
+                            log::warn!("#{} no login request in time", id);
+                            conn.send_close_reason_no_retry(ErrorCode::ProtocolViolation).await;
+                            conn.on_close(&format!("{}: no login request in time", PROTOCOL_VIOLATION), false).await;
+                            break;
+                        }
+                        log::info!("#{} not authorized in time, release its pending slot", id);
+                        conn.pending_auth = None;
+                    }
                     #[cfg(windows)]
                     conn.portable_check();
-                    if let Some((instant, minute)) = conn.auto_disconnect_timer.as_ref() {
-                        if instant.elapsed().as_secs() > minute * 60 {
-                            conn.send_close_reason_no_retry("Connection failed due to inactivity").await;
+                    if let Some(timer) = conn.auto_disconnect_timer.as_ref() {
+                        if idle_timed_out(timer) {
+                            conn.send_close_reason_no_retry(ErrorCode::Inactivity).await;
                             conn.on_close("auto disconnect", true).await;
                             break;
                         }
                     }
                     conn.file_remove_log_control.on_timer().drain(..).map(|x| conn.send_to_cm(x)).count();
+                    if !conn.check_sensitive_events().await {
+                        break;
+                    }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    conn.check_process_kill_approval().await;
                     #[cfg(feature = "hwcodec")]
                     conn.update_supported_encoding();
                 }
                 _ = test_delay_timer.tick() => {
-                    if last_recv_time.elapsed() >= SEC30 {
+                    if heartbeat_timed_out(last_recv_time) {
                         conn.on_close("Timeout", true).await;
                         break;
                     }
                     // The control end will jump out of the loop after receiving LoginResponse and will not reply to the TestDelay
                     if conn.last_test_delay.is_none() && !(conn.port_forward_socket.is_some() && conn.authorized) {
                         conn.last_test_delay = Some(Instant::now());
+                        let (target_bitrate, display_target_bitrates) = {
+                            let video_qos = video_service::VIDEO_QOS.lock().unwrap();
+                            (video_qos.user_bitrate(id), video_qos.user_display_bitrates(id))
+                        };
+                        let frame_drops = if conn.frame_drop_stats {
+                            let stats = conn.inner.stats.snapshot();
+                            session_report::FrameDropReason::ALL
+                                .iter()
+                                .map(|r| (r.id().to_owned(), stats.video_frames_dropped(*r)))
+                                .chain([("audio".to_owned(), stats.audio_frames_dropped)])
+                                .collect()
+                        } else {
+                            Default::default()
+                        };
                         let mut msg_out = Message::new();
                         msg_out.set_test_delay(TestDelay{
                             last_delay: conn.network_delay,
-                            target_bitrate: video_service::VIDEO_QOS.lock().unwrap().bitrate(),
+                            target_bitrate,
+                            display_target_bitrates,
+                            frame_drops,
                             ..Default::default()
                         });
                         conn.send(msg_out.into()).await;
@@ -861,17 +1177,21 @@ impl Connection {
             crate::plugin::EVENT_ON_CONN_CLOSE_SERVER.to_owned(),
             conn.lr.my_id.clone(),
         );
-        video_service::notify_video_frame_fetched(id, None);
+        video_service::notify_video_frame_fetched(id, None, None);
         if conn.authorized {
             password::update_temporary_password();
         }
-        if let Err(err) = conn.try_port_forward_loop(&mut rx_from_cm).await {
+        if let Err(err) = conn
+            .try_port_forward_loop(&mut rx_from_cm, &mut rx_from_authed)
+            .await
+        {
             conn.on_close(&err.to_string(), false).await;
             raii::AuthedConnID::check_remove_session(conn.inner.id(), conn.session_key());
         }
 
         conn.post_conn_audit(json!({
             "action": "close",
+            "code": conn.close_code.code(),
         }));
         if let Some(s) = conn.server.upgrade() {
             let mut s = s.write().unwrap();
@@ -914,6 +1234,9 @@ impl Connection {
                     MessageInput::Pointer((msg, id)) => {
                         handle_pointer(&msg, id);
                     }
+                    MessageInput::FocusGained(modifiers) => {
+                        release_unheld_modifiers(&modifiers);
+                    }
                     MessageInput::BlockOn => {
                         let (ok, msg) = crate::platform::block_input(true);
                         if ok {
@@ -991,6 +1314,7 @@ impl Connection {
     async fn try_port_forward_loop(
         &mut self,
         rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
+        rx_from_authed: &mut mpsc::UnboundedReceiver<Data>,
     ) -> ResultType<()> {
         let mut last_recv_time = Instant::now();
         if let Some(mut forward) = self.port_forward_socket.take() {
@@ -1011,6 +1335,19 @@ impl Connection {
                             _ => {}
                         }
                     }
+                    Some(data) = rx_from_authed.recv() => {
+                        if let ipc::Data::FeatureAllowlistChanged = data {
+                            if !is_feature_allowed(FEATURE_PORT_FORWARD, self.policy_peer_id()) {
+                                log::info!(
+                                    "#{} port-forward is revoked for peer {}",
+                                    self.inner.id(),
+                                    self.lr.my_id
+                                );
+                                self.post_feature_denied_audit(FEATURE_PORT_FORWARD);
+                                bail!("Not allowed to use {}", FEATURE_PORT_FORWARD);
+                            }
+                        }
+                    }
                     res = forward.next() => {
                         if let Some(res) = res {
                             last_recv_time = Instant::now();
@@ -1058,8 +1395,7 @@ impl Connection {
 
     async fn check_privacy_mode_on(&mut self) -> bool {
         if privacy_mode::is_in_privacy_mode() {
-            self.send_login_error("Someone turns on privacy mode, exit")
-                .await;
+            self.send_login_error(ErrorCode::PrivacyModeOn).await;
             false
         } else {
             true
@@ -1084,8 +1420,7 @@ impl Connection {
                 .next()
                 .is_none()
         {
-            self.send_login_error("Your ip is blocked by the peer")
-                .await;
+            self.send_login_error(ErrorCode::IpBlocked).await;
             Self::post_alarm_audit(
                 AlarmAuditType::IpWhitelist, //"ip whitelist",
                 json!({ "ip":addr.ip() }),
@@ -1103,7 +1438,7 @@ impl Connection {
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         if crate::is_server() && Config::get_option("allow-only-conn-window-open") == "Y" {
             if !crate::check_process("", !crate::platform::is_root()) {
-                self.send_login_error("The main window is not open").await;
+                self.send_login_error(ErrorCode::MainWindowNotOpen).await;
                 return false;
             }
         }
@@ -1142,6 +1477,7 @@ impl Connection {
         v["uuid"] = json!(crate::encode64(hbb_common::get_uuid()));
         v["conn_id"] = json!(self.inner.id);
         v["session_id"] = json!(self.lr.session_id);
+        v["auth_provider"] = json!(auth_provider::name());
         allow_err!(self.tx_post_seq.send((url, v)));
     }
 
@@ -1252,10 +1588,20 @@ impl Connection {
                     });
                 }
             });
-            self.send_login_error(crate::client::REQUIRE_2FA).await;
+            self.send_login_error(ErrorCode::Require2fa).await;
             return;
         }
+        if self.require_2fa.is_some() && self.is_recent_session(true) {
+            // The 2FA of this session has been passed by a recent connection.
+            self.trusted_peer_id = Some(self.lr.my_id.clone());
+        }
         self.authorized = true;
+        // Not kept for the whole session.
+        self.lr.plain_password.clear();
+        self.pending_auth = None;
+        self.login_deadline = None;
+        self.display_filter = display_filter::DisplayFilter::from_config(self.policy_peer_id());
+        clipboard_policy::set_peer(self.inner.id(), self.trusted_peer_id.clone());
         let (conn_type, auth_conn_type) = if self.file_transfer.is_some() {
             (1, AuthConnType::FileTransfer)
         } else if self.port_forward_socket.is_some() {
@@ -1264,6 +1610,10 @@ impl Connection {
             (3, AuthConnType::ViewCamera)
         } else if self.terminal {
             (4, AuthConnType::Terminal)
+        } else if self.audio_monitor {
+            (5, AuthConnType::AudioMonitor)
+        } else if self.preview.is_some() {
+            (6, AuthConnType::Preview)
         } else {
             (0, AuthConnType::Remote)
         };
@@ -1273,6 +1623,8 @@ impl Connection {
             self.session_key(),
             self.tx_from_authed.clone(),
             self.lr.clone(),
+            self.trusted_peer_id.as_deref(),
+            self.encrypted,
         ));
         self.session_last_recv_time = SESSIONS
             .lock()
@@ -1301,14 +1653,13 @@ impl Connection {
             pi.hostname = DEVICE_NAME.lock().unwrap().clone();
             pi.platform = "Android".into();
         }
-        #[cfg(all(target_os = "macos", not(feature = "unix-file-copy-paste")))]
-        let platform_additions = serde_json::Map::new();
-        #[cfg(any(
-            target_os = "windows",
-            target_os = "linux",
-            all(target_os = "macos", feature = "unix-file-copy-paste")
-        ))]
         let mut platform_additions = serde_json::Map::new();
+        if self.audio_monitor {
+            platform_additions.insert("audio_monitor".into(), json!(true));
+        }
+        if self.preview.is_some() {
+            platform_additions.insert("preview".into(), json!(true));
+        }
         #[cfg(target_os = "linux")]
         {
             if crate::platform::current_is_wayland() {
@@ -1359,7 +1710,24 @@ impl Connection {
             platform_additions.insert("support_view_camera".into(), json!(true));
         }
 
-        #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
+        #[cfg(not(target_os = "ios"))]
+        if super::clipboard_service::is_manual() {
+            platform_additions.insert("manual_clipboard".into(), json!(true));
+        }
+
+        if input_control::is_exclusive() {
+            platform_additions.insert("exclusive_control".into(), json!(true));
+        }
+
+        platform_additions.insert("capabilities".into(), json!(Capabilities::local().bits()));
+
+        // The privileges of the host, e.g. a host which runs as a user can't show the UAC desktop.
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        {
+            platform_additions.insert("is_root".into(), json!(crate::platform::is_root()));
+            platform_additions.insert("is_service".into(), json!(crate::is_server()));
+        }
+
         if !platform_additions.is_empty() {
             pi.platform_additions = serde_json::to_string(&platform_additions).unwrap_or("".into());
         }
@@ -1429,8 +1797,14 @@ impl Connection {
         #[allow(unused_mut)]
         let mut wait_session_id_confirm = false;
         #[cfg(windows)]
-        self.handle_windows_specific_session(&mut pi, &mut wait_session_id_confirm);
-        if self.file_transfer.is_some() || self.terminal {
+        if !self.audio_monitor && self.preview.is_none() {
+            self.handle_windows_specific_session(&mut pi, &mut wait_session_id_confirm);
+        }
+        if self.file_transfer.is_some()
+            || self.terminal
+            || self.audio_monitor
+            || self.preview.is_some()
+        {
             res.set_peer_info(pi);
         } else if self.view_camera {
             let supported_encoding = scrap::codec::Encoder::supported_encoding();
@@ -1465,6 +1839,9 @@ impl Connection {
             }
 
             try_activate_screen();
+            // Before the displays are got, so they are of the restored environment.
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            self.restore_session_environment();
 
             match super::display_service::update_get_sync_displays_on_login().await {
                 Err(err) => {
@@ -1478,6 +1855,16 @@ impl Connection {
                         self.retina.set_displays(&displays);
                     }
                     pi.displays = displays;
+                    super::display_service::add_sync_displays_to_platform_additions(
+                        &mut pi.platform_additions,
+                    );
+                    if let Some(filter) = self.display_filter.as_ref() {
+                        if !filter.is_allowed(self.display_idx) {
+                            if let Some(d) = filter.first(pi.displays.len()) {
+                                self.display_idx = d;
+                            }
+                        }
+                    }
                     pi.current_display = self.display_idx as _;
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     {
@@ -1491,6 +1878,9 @@ impl Connection {
                         })
                         .into();
                     }
+                    if let Some(filter) = self.display_filter.as_ref() {
+                        filter.filter_peer_info(&mut pi);
+                    }
                     res.set_peer_info(pi);
                     sub_service = true;
 
@@ -1511,6 +1901,12 @@ impl Connection {
         if let Some(o) = self.options_in_login.take() {
             self.update_options(&o).await;
         }
+        // The client checks it against its own, the users compare them by voice.
+        let mut misc = Misc::new();
+        misc.set_session_fingerprint(self.session_fingerprint.clone());
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
         if let Some((dir, show_hidden)) = self.file_transfer.clone() {
             let dir = if !dir.is_empty() && std::path::Path::new(&dir).is_dir() {
                 &dir
@@ -1532,6 +1928,35 @@ impl Connection {
             }
             self.keyboard = false;
             self.send_permission(Permission::Keyboard, false).await;
+        } else if self.audio_monitor {
+            for p in [
+                Permission::Keyboard,
+                Permission::Clipboard,
+                Permission::File,
+                Permission::Restart,
+                Permission::Recording,
+                Permission::BlockInput,
+                Permission::Annotation,
+                Permission::QuickActions,
+            ] {
+                self.send_permission(p, false).await;
+            }
+            self.try_sub_audio_monitor_service();
+        } else if self.preview.is_some() {
+            for p in [
+                Permission::Keyboard,
+                Permission::Clipboard,
+                Permission::Audio,
+                Permission::File,
+                Permission::Restart,
+                Permission::Recording,
+                Permission::BlockInput,
+                Permission::Annotation,
+                Permission::QuickActions,
+            ] {
+                self.send_permission(p, false).await;
+            }
+            self.start_preview();
         } else if sub_service {
             if !wait_session_id_confirm {
                 self.try_sub_monitor_services();
@@ -1545,6 +1970,7 @@ impl Connection {
 
             s.try_add_primary_camera_service();
             s.add_camera_connection(self.inner.clone());
+            self.update_qos_displays(&s);
         }
     }
 
@@ -1554,6 +1980,70 @@ impl Connection {
             && self.port_forward_socket.is_none()
             && !self.view_camera
             && !self.terminal
+            && !self.audio_monitor
+            && self.preview.is_none()
+    }
+
+    fn try_sub_audio_monitor_service(&mut self) {
+        if self.audio_monitor && !self.services_subed {
+            self.services_subed = true;
+            if let Some(s) = self.server.upgrade() {
+                s.write()
+                    .unwrap()
+                    .add_audio_monitor_connection(self.inner.clone());
+            }
+        }
+    }
+
+    fn start_preview(&mut self) {
+        let Some((peer_display, interval)) = self.preview else {
+            return;
+        };
+        // The peer asks by its own display index, it must be one it is allowed to see.
+        let display = match self.display_to_host(peer_display) {
+            Some(d) if d < super::display_service::get_sync_displays().len() => d,
+            _ => {
+                log::warn!(
+                    "#{} preview of invalid display {}, peer: {}",
+                    self.inner.id(),
+                    peer_display,
+                    self.lr.my_id
+                );
+                return;
+            }
+        };
+        let Some(tx) = self.inner.tx.clone() else {
+            return;
+        };
+        log::info!(
+            "#{} preview of display {} every {:?}, peer: {}",
+            self.inner.id(),
+            display,
+            interval,
+            self.lr.my_id
+        );
+        self.post_conn_audit(json!({
+            "action": "preview",
+            "display": display,
+            "interval": interval.as_secs(),
+            "peer": ((&self.lr.my_id, &self.lr.my_name)),
+        }));
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        tokio::spawn(super::preview::run(
+            self.server.clone(),
+            tx,
+            self.lr.my_id.clone(),
+            display,
+            interval,
+        ));
+        #[cfg(any(target_os = "android", target_os = "ios"))]
+        drop(tx);
+    }
+
+    fn is_audio_monitor_peer(id: &str) -> bool {
+        parse_id_list(&Config::get_option("audio-monitor-ids"))
+            .iter()
+            .any(|x| x == id)
     }
 
     fn try_sub_monitor_services(&mut self) {
@@ -1586,7 +2076,30 @@ impl Connection {
                 let _h = try_start_record_cursor_pos();
                 self.auto_disconnect_timer = Self::get_auto_disconenct_timer();
                 s.try_add_primay_video_service();
-                s.add_connection(self.inner.clone(), &noperms);
+                s.set_allowed_displays(
+                    self.inner.id(),
+                    self.display_filter.as_ref().map(|f| f.allowed().to_vec()),
+                );
+                if let Some(pending) = s.add_connection(self.inner.clone(), &noperms) {
+                    tokio::spawn(super::subscribe_pending(
+                        self.server.clone(),
+                        self.inner.clone(),
+                        pending,
+                    ));
+                }
+                // The primary monitor is not allowed, start with the first allowed one.
+                if self.display_idx != *display_service::PRIMARY_DISPLAY_IDX {
+                    let name =
+                        video_service::get_service_name(VideoSource::Monitor, self.display_idx);
+                    if !s.contains(&name) {
+                        s.add_service(Box::new(video_service::new(
+                            VideoSource::Monitor,
+                            self.display_idx,
+                        )));
+                    }
+                    s.subscribe(&name, self.inner.clone(), true);
+                }
+                self.update_qos_displays(&s);
             }
         }
     }
@@ -1639,6 +2152,58 @@ impl Connection {
         }
     }
 
+    // Text from the peer, normalized for this host.
+    fn normalize_received_clipboards(&self, clipboards: &mut [Clipboard]) {
+        normalize_clipboards(
+            clipboards,
+            self.clipboard_line_ending,
+            &hbb_common::whoami::platform().to_string(),
+        );
+    }
+
+    // Drop the clipboards of the peer which its clipboard policy does not allow, returns false if
+    // none is left.
+    async fn retain_received_clipboards(&mut self, clipboards: &mut Vec<Clipboard>) -> bool {
+        let Some(policy) = clipboard_policy::Policy::from_config(self.policy_peer_id()) else {
+            return true;
+        };
+        if let Some(msg) = policy.retain(clipboards, false) {
+            log::info!("Clipboard of the peer is refused by the clipboard policy");
+            self.send_clipboard_blocked(msg).await;
+        }
+        !clipboards.is_empty()
+    }
+
+    // The message to tell the peer if its clipboard policy does not allow the files.
+    #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
+    fn clipboard_files_blocked(&self, outgoing: bool) -> Option<Message> {
+        clipboard_policy::Policy::from_config(self.policy_peer_id())
+            .filter(|policy| !policy.allows_files())
+            .map(|policy| policy.files_blocked_msg(outgoing))
+    }
+
+    fn clipboard_blocked_due(&mut self) -> bool {
+        if self
+            .clipboard_blocked_time
+            .map_or(false, |t| t.elapsed() < CLIPBOARD_BLOCKED_INTERVAL)
+        {
+            return false;
+        }
+        self.clipboard_blocked_time = Some(Instant::now());
+        true
+    }
+
+    async fn send_clipboard_blocked(&mut self, msg: Message) {
+        if self.clipboard_blocked_due() {
+            self.send(msg).await;
+        }
+    }
+
+    #[inline]
+    pub fn supports(&self, cap: Capability) -> bool {
+        self.peer_capabilities.supports(cap)
+    }
+
     fn peer_keyboard_enabled(&self) -> bool {
         self.keyboard && !self.disable_keyboard
     }
@@ -1655,7 +2220,28 @@ impl Connection {
     }
 
     fn audio_enabled(&self) -> bool {
-        self.audio && !self.disable_audio
+        self.audio && !self.disable_audio && !self.push_to_talk_muted
+    }
+
+    // The view camera connections only subscribe the audio service in a voice call.
+    fn update_audio_subscription(&self) {
+        if let Some(s) = self.server.upgrade() {
+            if self.is_authed_view_camera_conn() {
+                if self.voice_calling || !self.audio_enabled() {
+                    s.write().unwrap().subscribe(
+                        super::audio_service::NAME,
+                        self.inner.clone(),
+                        self.audio_enabled(),
+                    );
+                }
+            } else {
+                s.write().unwrap().subscribe(
+                    super::audio_service::NAME,
+                    self.inner.clone(),
+                    self.audio_enabled(),
+                );
+            }
+        }
     }
 
     #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
@@ -1688,7 +2274,10 @@ impl Connection {
             restart: self.restart,
             recording: self.recording,
             block_input: self.block_input,
+            annotation: self.annotation,
+            quick_actions: self.quick_actions,
             from_switch: self.from_switch,
+            fingerprint: self.session_fingerprint.clone(),
         });
     }
 
@@ -1702,15 +2291,22 @@ impl Connection {
         self.send_to_cm(ipc::Data::FS(data));
     }
 
-    async fn send_login_error<T: std::string::ToString>(&mut self, err: T) {
+    async fn send_login_error(&mut self, err: impl Into<CodedError>) {
+        let CodedError { code, text } = err.into();
         let mut msg_out = Message::new();
         let mut res = LoginResponse::new();
-        res.set_error(err.to_string());
-        if err.to_string() == crate::client::REQUIRE_2FA {
+        res.error_code = code.code();
+        if code == ErrorCode::Require2fa {
             res.enable_trusted_devices = Self::enable_trusted_devices();
         }
+        res.set_error(text);
         msg_out.set_login_response(res);
         self.send(msg_out).await;
+        self.send_to_cm(ipc::Data::ErrorCode(code.code()));
+        self.post_conn_audit(json!({
+            "action": "login_error",
+            "code": code.code(),
+        }));
     }
 
     #[inline]
@@ -1760,13 +2356,19 @@ impl Connection {
         let mut hasher = Sha256::new();
         hasher.update(password);
         hasher.update(&self.hash.salt);
+        self.validate_password_hash(&hasher.finalize()[..])
+    }
+
+    // The hash is of the password with the salt.
+    fn validate_password_hash(&self, hash: &[u8]) -> bool {
         let mut hasher2 = Sha256::new();
-        hasher2.update(&hasher.finalize()[..]);
+        hasher2.update(hash);
         hasher2.update(&self.hash.challenge);
         hasher2.finalize()[..] == self.lr.password[..]
     }
 
-    fn validate_password(&mut self) -> bool {
+    // The permanent password is replaced by the auth provider if one is configured.
+    async fn validate_password(&self) -> bool {
         if password::temporary_enabled() {
             let password = password::temporary_password();
             if self.validate_one_password(password.clone()) {
@@ -1779,22 +2381,359 @@ impl Connection {
             }
         }
         if password::permanent_enabled() {
-            if self.validate_one_password(Config::get_permanent_password()) {
-                return true;
+            if auth_provider::is_local() {
+                if self.validate_one_password(Config::get_permanent_password()) {
+                    return true;
+                }
+            } else if let Some(password) = self.plain_password() {
+                return auth_provider::verify(auth_provider::Request {
+                    peer_id: &self.lr.my_id,
+                    password,
+                })
+                .await;
             }
         }
         false
     }
 
-    fn is_recent_session(&mut self, tfa: bool) -> bool {
-        SESSIONS
-            .lock()
-            .unwrap()
-            .retain(|_, s| s.last_recv_time.lock().unwrap().elapsed() < SESSION_TIMEOUT);
-        let session = SESSIONS
-            .lock()
-            .unwrap()
-            .get(&self.session_key())
+    // The password typed on the peer for the auth provider, only taken from an encrypted connection.
+    fn plain_password(&self) -> Option<&str> {
+        if self.encrypted && !self.lr.plain_password.is_empty() {
+            Some(&self.lr.plain_password)
+        } else {
+            None
+        }
+    }
+
+    fn has_password(&self) -> bool {
+        !self.lr.password.is_empty() || self.plain_password().is_some()
+    }
+
+    #[inline]
+    fn push_to_lanes(&mut self, instant: Instant, value: Arc<Message>) {
+        if self.lanes.push(instant, value) {
+            self.inner.stats.on_audio_frame_dropped();
+        }
+    }
+
+    // Send a message of the lanes other than the video, false if the connection is closed.
+    async fn send_from_lane(&mut self, instant: Instant, value: Arc<Message>) -> bool {
+        let latency = instant.elapsed().as_millis() as i64;
+        #[allow(unused_mut)]
+        let mut msg = value;
+
+        if latency > 1000 {
+            match &msg.union {
+                Some(message::Union::AudioFrame(_)) => {
+                    // log::info!("audio frame latency {}", instant.elapsed().as_secs_f32());
+                    self.lanes.on_audio_dropped();
+                    self.inner.stats.on_audio_frame_dropped();
+                    return true;
+                }
+                _ => {}
+            }
+        }
+        match &msg.union {
+            Some(message::Union::Misc(m)) => match &m.union {
+                Some(misc::Union::StopService(_)) => {
+                    let reason = self
+                        .server
+                        .upgrade()
+                        .map(|s| s.read().unwrap().close_reason())
+                        .unwrap_or(ErrorCode::ClosedByPeer);
+                    self.send_close_reason_no_retry(reason).await;
+                    self.on_close(&format!("stop service: {:?}", reason), false)
+                        .await;
+                    return false;
+                }
+                Some(misc::Union::ClipboardBlocked(_)) => {
+                    if !self.clipboard_blocked_due() {
+                        return true;
+                    }
+                }
+                _ => {}
+            },
+            Some(message::Union::PeerInfo(_pi)) => {
+                self.refresh_video_display(None);
+                #[cfg(target_os = "macos")]
+                self.retina.set_displays(&_pi.displays);
+            }
+            Some(message::Union::CursorPosition(pos)) => {
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                {
+                    if self.follow_remote_cursor {
+                        self.handle_cursor_switch_display(pos.clone()).await;
+                    }
+                }
+                #[cfg(target_os = "macos")]
+                if let Some(new_msg) = self.retina.on_cursor_pos(&pos, self.display_idx) {
+                    msg = Arc::new(new_msg);
+                }
+            }
+            Some(message::Union::MultiClipboards(_multi_clipboards)) => {
+                let mut normalized = None;
+                if self.clipboard_line_ending != LineEnding::Preserve {
+                    let mut mcb = _multi_clipboards.clone();
+                    if normalize_clipboards(
+                        &mut mcb.clipboards,
+                        self.clipboard_line_ending,
+                        &self.lr.my_platform,
+                    ) {
+                        normalized = Some(mcb);
+                    }
+                }
+                let _multi_clipboards = normalized.as_ref().unwrap_or(_multi_clipboards);
+                #[cfg(not(target_os = "ios"))]
+                if let Some(msg_out) = crate::clipboard::get_msg_if_not_support_multi_clip(
+                    &self.lr.version,
+                    &self.lr.my_platform,
+                    _multi_clipboards,
+                ) {
+                    if let Err(err) = self.stream.send(&msg_out).await {
+                        self.on_close(&err.to_string(), false).await;
+                        return false;
+                    }
+                    return true;
+                }
+                if self.supports(Capability::ClipboardDelta) {
+                    let mut mcb = _multi_clipboards.clone();
+                    if self.clipboard_delta.encode(&mut mcb.clipboards) {
+                        normalized = Some(mcb);
+                    }
+                }
+                if let Some(mcb) = normalized {
+                    let mut msg_out = Message::new();
+                    msg_out.set_multi_clipboards(mcb);
+                    msg = Arc::new(msg_out);
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(hidden) = self.client_hidden.as_mut() {
+            if hidden.hold(&msg) {
+                return true;
+            }
+        }
+        if let Some(filter) = self.display_filter.as_ref() {
+            match filter.outgoing(msg) {
+                Some(m) => msg = m,
+                None => return true,
+            }
+        }
+        if self.cursor_batch.hold(&msg) {
+            return match self.cursor_batch.take_ready() {
+                Some(batch) => self.send_cursor_batch(instant, batch).await,
+                None => true,
+            };
+        }
+        self.write_msg(instant, &msg).await
+    }
+
+    async fn send_cursor_batch(&mut self, instant: Instant, batch: Message) -> bool {
+        let positions = batch.cursor_position().earlier.len() + 1;
+        self.inner.stats.on_cursor_batch_sent(positions);
+        self.write_msg(instant, &batch).await
+    }
+
+    // The message is encoded once, its size is counted by the stats.
+    async fn write_msg(&mut self, instant: Instant, msg: &Message) -> bool {
+        let bytes = match msg.write_to_bytes() {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                log::error!("Failed to encode the message: {}", err);
+                return true;
+            }
+        };
+        self.session_stats.on_sent(msg, bytes.len());
+        let Some(bytes) = self.network_sim.hold(self.inner.id(), instant, bytes) else {
+            return true;
+        };
+        if let Err(err) = self.stream.send_raw(bytes).await {
+            self.on_close(&err.to_string(), false).await;
+            return false;
+        }
+        true
+    }
+
+    // Send the messages delivered on the simulated link, see `network_sim`.
+    async fn send_delivered(&mut self) -> bool {
+        while let Some(bytes) = self.network_sim.take_delivered() {
+            if let Err(err) = self.stream.send_raw(bytes).await {
+                self.on_close(&err.to_string(), false).await;
+                return false;
+            }
+        }
+        true
+    }
+
+    // The cursor positions are batched on a relay or a slow network, see `cursor_batch`.
+    fn update_cursor_batch(&mut self) {
+        let relay = self.relay_peer_addr.is_some();
+        if self.cursor_batch.update(relay, self.network_delay) {
+            let enabled = self.cursor_batch.is_enabled();
+            log::info!(
+                "#{} cursor batching {}, relay: {}, rtt: {}ms",
+                self.inner.id(),
+                if enabled { "on" } else { "off" },
+                relay,
+                self.network_delay
+            );
+            self.inner.stats.set_cursor_batching(enabled);
+        }
+    }
+
+    // The accept policy of the connection type, None if there's no rule for it.
+    fn access_policy(&self) -> Option<access_policy::Policy> {
+        use access_policy::ConnType;
+        let conn_types = if self.terminal {
+            vec![ConnType::Terminal]
+        } else if self.file_transfer.is_some() {
+            vec![ConnType::File]
+        } else if self.is_remote() {
+            vec![ConnType::Control, ConnType::View]
+        } else {
+            vec![]
+        };
+        let approve = match password::approve_mode() {
+            ApproveMode::Both => access_policy::Approve::Both,
+            ApproveMode::Password => access_policy::Approve::Password,
+            ApproveMode::Click => access_policy::Approve::Click,
+        };
+        let policy = access_policy::Policy::load(&conn_types, approve);
+        (!policy.is_empty()).then_some(policy)
+    }
+
+    async fn login_with_access_policy(
+        &mut self,
+        policy: access_policy::Policy,
+        desktop_ready: bool,
+    ) {
+        let lr = self.lr.clone();
+        if let Some(rule) = self.recent_session_rule(&policy) {
+            self.audit_access_policy(&rule, "recent session");
+            self.apply_access_rule(&rule);
+            if desktop_ready {
+                #[cfg(target_os = "linux")]
+                self.linux_headless_handle.wait_desktop_cm_ready().await;
+                self.send_logon_response().await;
+                self.try_start_cm(lr.my_id, lr.my_name, self.authorized);
+            } else {
+                self.send_login_error(ErrorCode::DesktopSessionNotReady).await;
+            }
+            return;
+        }
+        let mut matched = None;
+        if self.has_password() {
+            let (failure, res) = self.check_failure(0).await;
+            if !res {
+                return;
+            }
+            // The general passwords may ask the auth provider, only if a rule needs them.
+            let general = if policy.needs_general_password(|hash| self.validate_password_hash(hash)) {
+                self.validate_password().await
+            } else {
+                false
+            };
+            matched = policy.match_password(|hash| self.validate_password_hash(hash), || general);
+            self.update_failure(failure, matched.is_some(), 0);
+        }
+        if let Some(rule) = matched {
+            raii::AuthedConnID::set_session_access_rule(self.session_key(), rule.conn_type());
+            self.audit_access_policy(&rule, "password");
+            self.apply_access_rule(&rule);
+            if desktop_ready {
+                #[cfg(target_os = "linux")]
+                self.linux_headless_handle.wait_desktop_cm_ready().await;
+                self.send_logon_response().await;
+                self.try_start_cm(lr.my_id, lr.my_name, self.authorized);
+            } else {
+                self.send_login_error(ErrorCode::DesktopSessionNotReady).await;
+            }
+            return;
+        }
+        // Nobody to click accept in the headless mode.
+        if let Some(rule) = policy
+            .approval_rule()
+            .filter(|_| !super::headless_mode::is_enabled())
+        {
+            self.audit_access_policy(&rule, "approval");
+            self.apply_access_rule(&rule);
+            self.try_start_cm(lr.my_id, lr.my_name, false);
+            if !policy.accept_password() {
+                self.send_login_error(ErrorCode::NoPasswordAccess).await;
+            } else if self.has_password() {
+                self.send_login_error(ErrorCode::PasswordWrong).await;
+            }
+            return;
+        }
+        if !self.has_password() {
+            self.send_login_error(ErrorCode::PasswordEmpty).await;
+        } else {
+            self.post_conn_audit(json!({
+                "action": "access_policy",
+                "result": "denied",
+                "peer": ((&lr.my_id, &lr.my_name)),
+            }));
+            self.send_login_error(ErrorCode::PasswordWrong).await;
+        }
+    }
+
+    // The session admitted by the view rule can't control.
+    fn apply_access_rule(&mut self, rule: &access_policy::Rule) {
+        if rule.conn_type() == access_policy::ConnType::View {
+            self.keyboard = false;
+            self.clipboard = false;
+            self.file = false;
+            self.restart = false;
+            self.block_input = false;
+            self.annotation = false;
+            self.quick_actions = false;
+        }
+    }
+
+    // The current rule which admitted the recent session, None if it was admitted without the
+    // policy, then it's checked again.
+    fn recent_session_rule(
+        &mut self,
+        policy: &access_policy::Policy,
+    ) -> Option<access_policy::Rule> {
+        if !self.is_recent_session(false) {
+            return None;
+        }
+        let rule = SESSIONS
+            .lock()
+            .unwrap()
+            .get(&self.session_key())
+            .and_then(|s| s.access_rule)?;
+        policy.rule(rule)
+    }
+
+    fn audit_access_policy(&self, rule: &access_policy::Rule, by: &str) {
+        log::info!(
+            "Access policy of {} matched by {} for {}",
+            rule.conn_type,
+            by,
+            self.lr.my_id
+        );
+        self.post_conn_audit(json!({
+            "action": "access_policy",
+            "result": by,
+            "rule": rule.conn_type,
+            "approve": rule.approve,
+            "peer": ((&self.lr.my_id, &self.lr.my_name)),
+        }));
+    }
+
+    fn is_recent_session(&mut self, tfa: bool) -> bool {
+        SESSIONS
+            .lock()
+            .unwrap()
+            .retain(|_, s| session_alive(*s.last_recv_time.lock().unwrap()));
+        let session = SESSIONS
+            .lock()
+            .unwrap()
+            .get(&self.session_key())
             .map(|s| s.to_owned());
         // last_recv_time is a mutex variable shared with connection, can be updated lively.
         if let Some(session) = session {
@@ -1839,6 +2778,13 @@ impl Connection {
         }
     }
 
+    /// The id the per-peer policies are looked up by, None for a peer which is not trusted,
+    /// which only gets the default entries of the policies.
+    #[inline]
+    fn policy_peer_id(&self) -> Option<&str> {
+        self.trusted_peer_id.as_deref()
+    }
+
     #[inline]
     fn enable_trusted_devices() -> bool {
         config::option2bool(
@@ -1849,10 +2795,12 @@ impl Connection {
 
     async fn handle_login_request_without_validation(&mut self, lr: &LoginRequest) {
         self.lr = lr.clone();
+        self.peer_capabilities = Capabilities::negotiate(lr.capabilities, &lr.version);
+        self.inner.set_msg_versions(lr.msg_versions.clone());
         if let Some(o) = lr.option.as_ref() {
             self.options_in_login = Some(o.clone());
         }
-        if self.require_2fa.is_some() && !lr.hwid.is_empty() && Self::enable_trusted_devices() {
+        if !lr.hwid.is_empty() && Self::enable_trusted_devices() {
             let devices = Config::get_trusted_devices();
             if let Some(device) = devices.iter().find(|d| d.hwid == lr.hwid) {
                 if !device.outdate()
@@ -1860,8 +2808,11 @@ impl Connection {
                     && device.name == lr.my_name
                     && device.platform == lr.my_platform
                 {
-                    log::info!("2FA bypassed by trusted devices");
-                    self.require_2fa = None;
+                    self.trusted_peer_id = Some(lr.my_id.clone());
+                    if self.require_2fa.is_some() {
+                        log::info!("2FA bypassed by trusted devices");
+                        self.require_2fa = None;
+                    }
                 }
             }
         }
@@ -1870,6 +2821,10 @@ impl Connection {
 
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     fn try_start_cm_ipc(&mut self) {
+        // There is no connection manager in the loopback tests and in the headless mode.
+        if cfg!(test) || super::headless_mode::is_enabled() {
+            return;
+        }
         if let Some(p) = self.start_cm_ipc_para.take() {
             tokio::spawn(async move {
                 #[cfg(windows)]
@@ -1904,14 +2859,43 @@ impl Connection {
 
     async fn on_message(&mut self, msg: Message) -> bool {
         if let Some(message::Union::LoginRequest(lr)) = msg.union {
+            self.login_received = true;
+            if self.authorized && self.inner.is_reauth_pending() {
+                self.handle_reauth(lr.password, lr.plain_password).await;
+                return true;
+            }
             self.handle_login_request_without_validation(&lr).await;
             if self.authorized {
                 return true;
             }
+            if !self.encrypted && Config::get_option(super::OPTION_REQUIRE_ENCRYPTION) == "Y" {
+                log::warn!("Refuse the unencrypted connection from {}", lr.my_id);
+                self.post_conn_audit(json!({
+                    "action": "encryption_required",
+                    "peer": ((&lr.my_id, &lr.my_name)),
+                }));
+                self.send_login_error(ErrorCode::EncryptionRequired).await;
+                sleep(1.).await;
+                return false;
+            }
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            if super::panic_button::is_do_not_disturb() {
+                log::warn!(
+                    "Refuse the connection from {}, the sessions were just terminated",
+                    lr.my_id
+                );
+                self.post_conn_audit(json!({
+                    "action": "do_not_disturb",
+                    "peer": ((&lr.my_id, &lr.my_name)),
+                }));
+                self.send_login_error(ErrorCode::DoNotDisturb).await;
+                sleep(1.).await;
+                return false;
+            }
             match lr.union {
                 Some(login_request::Union::FileTransfer(ft)) => {
                     if !Connection::permission(keys::OPTION_ENABLE_FILE_TRANSFER) {
-                        self.send_login_error("No permission of file transfer")
+                        self.send_login_error(ErrorCode::NoPermission.with_detail("file transfer"))
                             .await;
                         sleep(1.).await;
                         return false;
@@ -1920,7 +2904,7 @@ impl Connection {
                 }
                 Some(login_request::Union::ViewCamera(_vc)) => {
                     if !Connection::permission(keys::OPTION_ENABLE_CAMERA) {
-                        self.send_login_error("No permission of viewing camera")
+                        self.send_login_error(ErrorCode::NoPermission.with_detail("viewing camera"))
                             .await;
                         sleep(1.).await;
                         return false;
@@ -1929,10 +2913,14 @@ impl Connection {
                 }
                 Some(login_request::Union::Terminal(terminal)) => {
                     if !Connection::permission(keys::OPTION_ENABLE_TERMINAL) {
-                        self.send_login_error("No permission of terminal").await;
+                        self.send_login_error(ErrorCode::NoPermission.with_detail("terminal"))
+                            .await;
                         sleep(1.).await;
                         return false;
                     }
+                    if !self.check_feature_allowlist(FEATURE_TERMINAL).await {
+                        return false;
+                    }
                     self.terminal = true;
                     if let Some(o) = self.options_in_login.as_ref() {
                         self.terminal_persistent =
@@ -1942,10 +2930,14 @@ impl Connection {
                 }
                 Some(login_request::Union::PortForward(mut pf)) => {
                     if !Connection::permission("enable-tunnel") {
-                        self.send_login_error("No permission of IP tunneling").await;
+                        self.send_login_error(ErrorCode::NoPermission.with_detail("IP tunneling"))
+                            .await;
                         sleep(1.).await;
                         return false;
                     }
+                    if !self.check_feature_allowlist(FEATURE_PORT_FORWARD).await {
+                        return false;
+                    }
                     let mut is_rdp = false;
                     if pf.host == "RDP" && pf.port == 0 {
                         pf.host = "localhost".to_owned();
@@ -1965,17 +2957,62 @@ impl Connection {
                             if is_rdp {
                                 addr = "RDP".to_owned();
                             }
-                            self.send_login_error(format!(
-                                "Failed to access remote {}, please make sure if it is open",
-                                addr
+                            self.send_login_error(ErrorCode::PortForwardFailed.with_detail(
+                                &format!("{}, please make sure if it is open", addr),
                             ))
                             .await;
                             return false;
                         }
                     }
                 }
+                Some(login_request::Union::Preview(_preview)) => {
+                    #[cfg(any(target_os = "android", target_os = "ios"))]
+                    {
+                        self.send_login_error(ErrorCode::PreviewNotSupported).await;
+                        sleep(1.).await;
+                        return false;
+                    }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    {
+                        if !super::preview::is_allowed() {
+                            self.send_login_error(ErrorCode::NoPermission.with_detail("preview"))
+                                .await;
+                            sleep(1.).await;
+                            return false;
+                        }
+                        self.preview = Some((
+                            _preview.display.max(0) as usize,
+                            super::preview::get_interval(_preview.interval as _),
+                        ));
+                        self.keyboard = false;
+                        self.clipboard = false;
+                        self.audio = false;
+                        self.file = false;
+                        self.restart = false;
+                        self.recording = false;
+                        self.block_input = false;
+                        self.annotation = false;
+                        self.quick_actions = false;
+                    }
+                }
                 _ => {
-                    if !self.check_privacy_mode_on().await {
+                    if Self::is_audio_monitor_peer(&lr.my_id) {
+                        if !Connection::permission("enable-audio") {
+                            self.send_login_error(ErrorCode::NoPermission.with_detail("audio"))
+                                .await;
+                            sleep(1.).await;
+                            return false;
+                        }
+                        self.audio_monitor = true;
+                        self.keyboard = false;
+                        self.clipboard = false;
+                        self.file = false;
+                        self.restart = false;
+                        self.recording = false;
+                        self.block_input = false;
+                        self.annotation = false;
+                        self.quick_actions = false;
+                    } else if !self.check_privacy_mode_on().await {
                         return false;
                     }
                 }
@@ -1985,18 +3022,18 @@ impl Connection {
             self.try_start_cm_ipc();
 
             #[cfg(not(target_os = "linux"))]
-            let err_msg = "".to_owned();
+            let desktop_err: Option<ErrorCode> = None;
             #[cfg(target_os = "linux")]
-            let err_msg = self
+            let desktop_err = self
                 .linux_headless_handle
                 .try_start_desktop(lr.os_login.as_ref());
 
-            // If err is LOGIN_MSG_DESKTOP_SESSION_NOT_READY, just keep this msg and go on checking password.
-            if !err_msg.is_empty() && err_msg != crate::client::LOGIN_MSG_DESKTOP_SESSION_NOT_READY
-            {
-                self.send_login_error(err_msg).await;
+            // If the desktop session is not ready, just keep this error and go on checking password.
+            if let Some(err) = desktop_err.filter(|e| *e != ErrorCode::DesktopSessionNotReady) {
+                self.send_login_error(err).await;
                 return true;
             }
+            let desktop_ready = desktop_err.is_none();
 
             // https://github.com/rustdesk/rustdesk-server-pro/discussions/646
             // `is_logon` is used to check login with `OPTION_ALLOW_LOGON_SCREEN_PASSWORD` == "Y".
@@ -2023,66 +3060,66 @@ impl Connection {
                 && !hbb_common::is_domain_port_str(&lr.username)
                 && lr.username != Config::get_id()
             {
-                self.send_login_error(crate::client::LOGIN_MSG_OFFLINE)
-                    .await;
+                self.send_login_error(ErrorCode::Offline).await;
                 return false;
+            } else if let Some(policy) = self.access_policy() {
+                self.login_with_access_policy(policy, desktop_ready).await;
             } else if (password::approve_mode() == ApproveMode::Click
                 && !(crate::get_builtin_option(keys::OPTION_ALLOW_LOGON_SCREEN_PASSWORD) == "Y"
                     && is_logon()))
                 || password::approve_mode() == ApproveMode::Both && !password::has_valid_password()
             {
+                if super::headless_mode::is_enabled() {
+                    log::info!("No click approval in the headless mode: {}", lr.my_id);
+                    self.send_login_error(ErrorCode::NoPasswordAccess).await;
+                    return false;
+                }
                 self.try_start_cm(lr.my_id, lr.my_name, false);
                 if hbb_common::get_version_number(&lr.version)
                     >= hbb_common::get_version_number("1.2.0")
                 {
-                    self.send_login_error(crate::client::LOGIN_MSG_NO_PASSWORD_ACCESS)
-                        .await;
+                    self.send_login_error(ErrorCode::NoPasswordAccess).await;
                 }
                 return true;
             } else if self.is_recent_session(false) {
-                if err_msg.is_empty() {
+                if desktop_ready {
                     #[cfg(target_os = "linux")]
                     self.linux_headless_handle.wait_desktop_cm_ready().await;
                     self.send_logon_response().await;
                     self.try_start_cm(lr.my_id.clone(), lr.my_name.clone(), self.authorized);
                 } else {
-                    self.send_login_error(err_msg).await;
+                    self.send_login_error(ErrorCode::DesktopSessionNotReady).await;
                 }
-            } else if lr.password.is_empty() {
-                if err_msg.is_empty() {
+            } else if !self.has_password() {
+                if super::headless_mode::is_enabled() {
+                    self.send_login_error(ErrorCode::PasswordEmpty).await;
+                } else if desktop_ready {
                     self.try_start_cm(lr.my_id, lr.my_name, false);
                 } else {
-                    self.send_login_error(
-                        crate::client::LOGIN_MSG_DESKTOP_SESSION_NOT_READY_PASSWORD_EMPTY,
-                    )
-                    .await;
+                    self.send_login_error(ErrorCode::DesktopSessionNotReadyPasswordEmpty).await;
                 }
             } else {
                 let (failure, res) = self.check_failure(0).await;
                 if !res {
                     return true;
                 }
-                if !self.validate_password() {
+                if !self.validate_password().await {
                     self.update_failure(failure, false, 0);
-                    if err_msg.is_empty() {
-                        self.send_login_error(crate::client::LOGIN_MSG_PASSWORD_WRONG)
-                            .await;
+                    if desktop_ready {
+                        self.send_login_error(ErrorCode::PasswordWrong).await;
                         self.try_start_cm(lr.my_id, lr.my_name, false);
                     } else {
-                        self.send_login_error(
-                            crate::client::LOGIN_MSG_DESKTOP_SESSION_NOT_READY_PASSWORD_WRONG,
-                        )
-                        .await;
+                        self.send_login_error(ErrorCode::DesktopSessionNotReadyPasswordWrong).await;
                     }
                 } else {
                     self.update_failure(failure, true, 0);
-                    if err_msg.is_empty() {
+                    if desktop_ready {
                         #[cfg(target_os = "linux")]
                         self.linux_headless_handle.wait_desktop_cm_ready().await;
                         self.send_logon_response().await;
                         self.try_start_cm(lr.my_id, lr.my_name, self.authorized);
                     } else {
-                        self.send_login_error(err_msg).await;
+                        self.send_login_error(ErrorCode::DesktopSessionNotReady).await;
                     }
                 }
             }
@@ -2096,6 +3133,7 @@ impl Connection {
                     if res {
                         self.update_failure(failure, true, 1);
                         self.require_2fa.take();
+                        self.trusted_peer_id = Some(self.lr.my_id.clone());
                         raii::AuthedConnID::set_session_2fa(self.session_key());
                         self.send_logon_response().await;
                         self.try_start_cm(
@@ -2106,7 +3144,7 @@ impl Connection {
                         if !tfa.hwid.is_empty() && Self::enable_trusted_devices() {
                             Config::add_trusted_device(TrustedDevice {
                                 hwid: tfa.hwid,
-                                time: hbb_common::get_time(),
+                                time: session_clock::wall_ms(),
                                 id: self.lr.my_id.clone(),
                                 name: self.lr.my_name.clone(),
                                 platform: self.lr.my_platform.clone(),
@@ -2114,8 +3152,7 @@ impl Connection {
                         }
                     } else {
                         self.update_failure(failure, false, 1);
-                        self.send_login_error(crate::client::LOGIN_MSG_2FA_WRONG)
-                            .await;
+                        self.send_login_error(ErrorCode::Wrong2fa).await;
                     }
                 }
             }
@@ -2133,6 +3170,11 @@ impl Connection {
                         .unwrap()
                         .user_network_delay(self.inner.id(), new_delay);
                     self.network_delay = new_delay;
+                    self.update_cursor_batch();
+                    self.session_stats.on_rtt(new_delay);
+                    if self.low_delay_audio {
+                        super::audio_service::on_low_delay_rtt(self.inner.id(), new_delay);
+                    }
                 }
             }
         } else if let Some(message::Union::SwitchSidesResponse(_s)) = msg.union {
@@ -2164,6 +3206,28 @@ impl Connection {
             if self.port_forward_socket.is_some() {
                 return true;
             }
+            // Only closing is allowed for audio monitor and preview connections, other requests are dropped.
+            if self.audio_monitor || self.preview.is_some() {
+                let is_close = match &msg.union {
+                    Some(message::Union::Misc(misc)) => {
+                        matches!(misc.union, Some(misc::Union::CloseReason(_)))
+                    }
+                    _ => false,
+                };
+                if !is_close {
+                    return true;
+                }
+            }
+            // No control before the re-authentication, see `Server::defer_for_reauth`.
+            if matches!(
+                msg.union,
+                Some(message::Union::MouseEvent(_))
+                    | Some(message::Union::PointerDeviceEvent(_))
+                    | Some(message::Union::KeyEvent(_))
+            ) && self.inner.is_reauth_pending()
+            {
+                return true;
+            }
             match msg.union {
                 #[allow(unused_mut)]
                 Some(message::Union::MouseEvent(mut me)) => {
@@ -2177,13 +3241,24 @@ impl Connection {
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if self.peer_keyboard_enabled() {
                         if is_left_up(&me) {
-                            CLICK_TIME.store(get_time(), Ordering::SeqCst);
+                            CLICK_TIME.store(session_clock::wall_ms(), Ordering::SeqCst);
                         } else {
-                            MOUSE_MOVE_TIME.store(get_time(), Ordering::SeqCst);
+                            MOUSE_MOVE_TIME.store(session_clock::wall_ms(), Ordering::SeqCst);
                         }
                         #[cfg(target_os = "macos")]
                         self.retina.on_mouse_event(&mut me, self.display_idx);
-                        self.input_mouse(me, self.inner.id());
+                        if self.supports(Capability::DisplayRotation) {
+                            input_service::rotate_mouse_event_to_raw(&mut me);
+                        }
+                        let evt_type = me.mask & 0x7;
+                        if evt_type == crate::input::MOUSE_TYPE_UP
+                            || input_control::accept_input(self.inner.id, &self.lr.my_name)
+                        {
+                            if evt_type == crate::input::MOUSE_TYPE_MOVE {
+                                input_control::blend(self.inner.id, &mut me.x, &mut me.y);
+                            }
+                            self.input_mouse(me, self.inner.id());
+                        }
                     }
                     self.update_auto_disconnect_timer();
                 }
@@ -2220,8 +3295,10 @@ impl Connection {
                         log::debug!("call_main_service_pointer_input fail:{}", e);
                     }
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
-                    if self.peer_keyboard_enabled() {
-                        MOUSE_MOVE_TIME.store(get_time(), Ordering::SeqCst);
+                    if self.peer_keyboard_enabled()
+                        && input_control::accept_input(self.inner.id, &self.lr.my_name)
+                    {
+                        MOUSE_MOVE_TIME.store(session_clock::wall_ms(), Ordering::SeqCst);
                         self.input_pointer(pde, self.inner.id());
                     }
                     self.update_auto_disconnect_timer();
@@ -2288,12 +3365,14 @@ impl Connection {
                     if self.is_authed_view_camera_conn() {
                         return true;
                     }
-                    if self.peer_keyboard_enabled() {
+                    if self.peer_keyboard_enabled()
+                        && input_control::accept_input(self.inner.id, &self.lr.my_name)
+                    {
                         if is_enter(&me) {
-                            CLICK_TIME.store(get_time(), Ordering::SeqCst);
+                            CLICK_TIME.store(session_clock::wall_ms(), Ordering::SeqCst);
                         }
                         // https://github.com/rustdesk/rustdesk/issues/8633
-                        MOUSE_MOVE_TIME.store(get_time(), Ordering::SeqCst);
+                        MOUSE_MOVE_TIME.store(session_clock::wall_ms(), Ordering::SeqCst);
 
                         let key = match me.mode.enum_value() {
                             Ok(KeyboardMode::Map) => {
@@ -2327,6 +3406,7 @@ impl Connection {
                                 self.pressed_modifiers.remove(&key);
                             }
                         }
+                        self.injected_keys.on_key_event(&me, is_press);
 
                         if is_press {
                             match me.union {
@@ -2346,6 +3426,12 @@ impl Connection {
                 }
                 Some(message::Union::Clipboard(cb)) => {
                     if self.clipboard {
+                        let mut cbs = vec![cb];
+                        if !self.retain_received_clipboards(&mut cbs).await {
+                            return true;
+                        }
+                        let mut cb = cbs.remove(0);
+                        self.normalize_received_clipboards(std::slice::from_mut(&mut cb));
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
                         update_clipboard(vec![cb], ClipboardSide::Host);
                         // ios as the controlled side is actually not supported for now.
@@ -2372,7 +3458,11 @@ impl Connection {
                         crate::clipboard::handle_msg_clipboard(cb);
                     }
                 }
-                Some(message::Union::MultiClipboards(_mcb)) => {
+                Some(message::Union::MultiClipboards(mut _mcb)) => {
+                    if !self.retain_received_clipboards(&mut _mcb.clipboards).await {
+                        return true;
+                    }
+                    self.normalize_received_clipboards(&mut _mcb.clipboards);
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if self.clipboard {
                         update_clipboard(_mcb.clipboards, ClipboardSide::Host);
@@ -2382,7 +3472,34 @@ impl Connection {
                 }
                 #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
                 Some(message::Union::Cliprdr(clip)) => {
+                    let Some(clip) = self.file_list_assembler.assemble(clip) else {
+                        return true;
+                    };
                     if let Some(clip) = msg_2_clip(clip) {
+                        if matches!(clip, clipboard::ClipboardFile::FormatList { .. })
+                            && super::file_scan::is_enabled()
+                        {
+                            // The pasted files can't be scanned before they are materialized.
+                            log::info!(
+                                "Clipboard files of the peer are refused, file scan is enabled"
+                            );
+                            self.post_file_audit(
+                                FileAuditType::RemoteReceive,
+                                "clipboard",
+                                vec![("".to_owned(), 0)],
+                                json!({ "blocked": super::file_scan::BLOCKED_BY_POLICY }),
+                            );
+                            return true;
+                        }
+                        if matches!(clip, clipboard::ClipboardFile::FormatList { .. }) {
+                            if let Some(msg) = self.clipboard_files_blocked(false) {
+                                log::info!(
+                                    "Clipboard files of the peer are refused by the clipboard policy"
+                                );
+                                self.send_clipboard_blocked(msg).await;
+                                return true;
+                            }
+                        }
                         #[cfg(target_os = "windows")]
                         {
                             self.send_to_cm(ipc::Data::ClipboardFile(clip));
@@ -2421,7 +3538,10 @@ impl Connection {
                             }
 
                             if let Some(msg) = out_msg {
-                                self.send(msg).await;
+                                let chunked = self.supports(Capability::ChunkedClipboardFileList);
+                                for msg in split_file_list(msg, chunked) {
+                                    self.send(msg).await;
+                                }
                             }
                         }
                     }
@@ -2433,6 +3553,28 @@ impl Connection {
                             if JobType::from_proto(s.file_type) == JobType::Printer {
                                 handle_fa = true;
                             }
+                            // The peer reads its diagnostic captures without file transfer session.
+                            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                            if JobType::from_proto(s.file_type) == JobType::Generic
+                                && super::diagnostic_capture::is_capture(self.inner.id(), &s.path)
+                            {
+                                super::diagnostic_capture::add_job(self.inner.id(), s.id);
+                                handle_fa = true;
+                            }
+                        }
+                        // The confirmations and the cancel of the capture jobs.
+                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                        {
+                            let job_id = match fa.union.as_ref() {
+                                Some(file_action::Union::SendConfirm(c)) => Some(c.id),
+                                Some(file_action::Union::Cancel(c)) => Some(c.id),
+                                _ => None,
+                            };
+                            if job_id.map_or(false, |id| {
+                                super::diagnostic_capture::is_job(self.inner.id(), id)
+                            }) {
+                                handle_fa = true;
+                            }
                         }
                     }
                     if handle_fa {
@@ -2473,7 +3615,19 @@ impl Connection {
                                 self.read_empty_dirs(&rd.path, rd.include_hidden);
                             }
                             Some(file_action::Union::ReadDir(rd)) => {
-                                self.read_dir(&rd.path, rd.include_hidden);
+                                if rd.page_size > 0 && self.supports(Capability::PagedDirListing) {
+                                    self.send_fs(ipc::FS::ReadDirPaged {
+                                        conn_id: self.inner.id,
+                                        id: rd.id,
+                                        dir: rd.path,
+                                        include_hidden: rd.include_hidden,
+                                        page_size: rd.page_size as _,
+                                        filter: rd.filter,
+                                        token: rd.token,
+                                    });
+                                } else {
+                                    self.read_dir(&rd.path, rd.include_hidden);
+                                }
                             }
                             Some(file_action::Union::AllFiles(f)) => {
                                 match fs::get_recursive_files(&f.path, f.include_hidden) {
@@ -2494,21 +3648,23 @@ impl Connection {
                                 let path = s.path.clone();
                                 let r#type = JobType::from_proto(s.file_type);
                                 let data_source;
+                                let mut printer_job_id = None;
                                 match r#type {
                                     JobType::Generic => {
                                         data_source =
                                             fs::DataSource::FilePath(PathBuf::from(&path));
                                     }
                                     JobType::Printer => {
-                                        if let Some((_, _, data)) = self
+                                        if let Some((_, _, data, job_id)) = self
                                             .printer_data
                                             .iter()
-                                            .position(|(_, p, _)| *p == path)
+                                            .position(|(_, p, _, _)| *p == path)
                                             .map(|index| self.printer_data.remove(index))
                                         {
                                             data_source = fs::DataSource::MemoryCursor(
                                                 std::io::Cursor::new(data),
                                             );
+                                            printer_job_id = Some(job_id);
                                         } else {
                                             // Ignore this message if the printer data is not found
                                             return true;
@@ -2535,6 +3691,9 @@ impl Connection {
                                         job.is_remote = true;
                                         job.conn_id = self.inner.id();
                                         let job_type = job.r#type;
+                                        if let Some(job_id) = printer_job_id {
+                                            self.printing_jobs.push((id, job_id));
+                                        }
                                         self.read_jobs.push(job);
                                         self.file_timer =
                                             crate::rustdesk_interval(time::interval(MILLI1));
@@ -2559,6 +3718,14 @@ impl Connection {
                                 let od = can_enable_overwrite_detection(get_version_number(
                                     &self.lr.version,
                                 ));
+                                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                                if let Err(err) = session_temp::check_disk_space(
+                                    std::path::Path::new(&r.path),
+                                    r.total_size,
+                                ) {
+                                    self.send(fs::new_error(r.id, err, 0)).await;
+                                    return true;
+                                }
                                 self.send_fs(ipc::FS::NewWrite {
                                     path: r.path.clone(),
                                     id: r.id,
@@ -2571,7 +3738,7 @@ impl Connection {
                                         .collect(),
                                     overwrite_detection: od,
                                     total_size: r.total_size,
-                                    conn_id: self.inner.id(),
+                                    conn_id: self.inner.id,
                                 });
                                 self.post_file_audit(
                                     FileAuditType::RemoteReceive,
@@ -2606,7 +3773,7 @@ impl Connection {
                                     "create_dir".to_string(),
                                     serde_json::to_string(&FileActionLog {
                                         id: c.id,
-                                        conn_id: self.inner.id(),
+                                        conn_id: self.inner.id,
                                         path: c.path,
                                         dir: true,
                                     })
@@ -2615,12 +3782,19 @@ impl Connection {
                             }
                             Some(file_action::Union::Cancel(c)) => {
                                 self.send_fs(ipc::FS::CancelWrite { id: c.id });
+                                self.send_fs(ipc::FS::CancelReadDir {
+                                    conn_id: self.inner.id,
+                                    id: c.id,
+                                });
                                 if let Some(job) = fs::remove_job(c.id, &mut self.read_jobs) {
                                     self.send_to_cm(ipc::Data::FileTransferLog((
                                         "transfer".to_string(),
                                         fs::serialize_transfer_job(&job, false, true, ""),
                                     )));
                                 }
+                                // A cancelled print job is not sent, it stays in the queue for the
+                                // next connection.
+                                self.printing_jobs.retain(|(id, _)| *id != c.id);
                             }
                             Some(file_action::Union::SendConfirm(r)) => {
                                 if let Some(job) = fs::get_job(r.id, &mut self.read_jobs) {
@@ -2636,7 +3810,7 @@ impl Connection {
                                 self.send_to_cm(ipc::Data::FileTransferLog((
                                     "rename".to_string(),
                                     serde_json::to_string(&FileRenameLog {
-                                        conn_id: self.inner.id(),
+                                        conn_id: self.inner.id,
                                         path: r.path,
                                         new_name: r.new_name,
                                     })
@@ -2683,18 +3857,41 @@ impl Connection {
                         self.handle_switch_display(s).await;
                     }
                     Some(misc::Union::CaptureDisplays(displays)) => {
-                        let add = displays.add.iter().map(|d| *d as usize).collect::<Vec<_>>();
-                        let sub = displays.sub.iter().map(|d| *d as usize).collect::<Vec<_>>();
-                        let set = displays.set.iter().map(|d| *d as usize).collect::<Vec<_>>();
+                        let to_host = |v: &[i32]| {
+                            v.iter()
+                                .filter_map(|d| self.display_to_host(*d as usize))
+                                .collect::<Vec<_>>()
+                        };
+                        let add = to_host(&displays.add);
+                        let sub = to_host(&displays.sub);
+                        let set = to_host(&displays.set);
                         self.capture_displays(&add, &sub, &set).await;
                     }
                     #[cfg(windows)]
                     Some(misc::Union::ToggleVirtualDisplay(t)) => {
                         self.toggle_virtual_display(t).await;
                     }
+                    #[cfg(windows)]
+                    Some(misc::Union::VirtualDisplayMode(m)) => {
+                        if let Err(e) = display_service::set_virtual_display_mode(
+                            m.display,
+                            m.width,
+                            m.height,
+                            m.refresh_rate,
+                        ) {
+                            self.send(Self::virtual_display_msg(e)).await;
+                        }
+                    }
                     Some(misc::Union::TogglePrivacyMode(t)) => {
                         self.toggle_privacy_mode(t).await;
                     }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    Some(misc::Union::ClientFocus(f)) => {
+                        self.handle_client_focus(f);
+                    }
+                    Some(misc::Union::ClientVisibility(v)) => {
+                        self.handle_client_visibility(v).await;
+                    }
                     Some(misc::Union::ChatMessage(c)) => {
                         self.send_to_cm(ipc::Data::ChatMessage { text: c.text });
                         self.chat_unanswered = true;
@@ -2712,14 +3909,25 @@ impl Connection {
                         self.update_auto_disconnect_timer();
                     }
                     Some(misc::Union::RefreshVideoDisplay(display)) => {
-                        self.refresh_video_display(Some(display as usize));
+                        if let Some(display) = self.display_to_host(display as usize) {
+                            self.refresh_video_display(Some(display));
+                        }
                         self.update_auto_disconnect_timer();
                     }
                     Some(misc::Union::VideoReceived(_)) => {
-                        video_service::notify_video_frame_fetched(
-                            self.inner.id,
-                            Some(Instant::now().into()),
-                        );
+                        // The ack has no display, it's for the displays of this connection only.
+                        let names = self
+                            .server
+                            .upgrade()
+                            .map(|s| s.read().unwrap().get_subbed_displays(self.inner.id()))
+                            .unwrap_or_default();
+                        for name in names {
+                            video_service::notify_video_frame_fetched(
+                                self.inner.id,
+                                Some(&name),
+                                Some(Instant::now().into()),
+                            );
+                        }
                     }
                     Some(misc::Union::CloseReason(_)) => {
                         self.on_close("Peer close", true).await;
@@ -2746,25 +3954,70 @@ impl Connection {
                             }
                         }
                     }
-                    #[cfg(windows)]
-                    Some(misc::Union::ElevationRequest(r)) => match r.union {
-                        Some(elevation_request::Union::Direct(_)) => {
-                            self.handle_elevation_request(portable_client::StartPara::Direct)
-                                .await;
+                    Some(misc::Union::ControlTokenRequest(request)) => {
+                        if self.peer_keyboard_enabled() && input_control::is_exclusive() {
+                            if request {
+                                input_control::request(self.inner.id, &self.lr.my_name);
+                            } else {
+                                input_control::release(self.inner.id);
+                            }
                         }
-                        Some(elevation_request::Union::Logon(r)) => {
-                            self.handle_elevation_request(portable_client::StartPara::Logon(
-                                r.username, r.password,
-                            ))
-                            .await;
+                    }
+                    #[cfg(not(target_os = "ios"))]
+                    Some(misc::Union::RequestClipboard(_)) => {
+                        // Also asked for if the peer can't apply a delta.
+                        self.clipboard_delta.reset();
+                        if self.can_sub_clipboard_service() {
+                            super::clipboard_service::request_send(self.inner.id());
                         }
-                        _ => {}
-                    },
-                    Some(misc::Union::AudioFormat(format)) => {
-                        if !self.disable_audio {
-                            // Drop the audio sender previously.
+                    }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    Some(misc::Union::PowerActionRequest(r)) => {
+                        self.handle_power_action_request(r).await;
+                    }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    Some(misc::Union::ProcessListRequest(r)) => {
+                        self.handle_process_list_request(r).await;
+                    }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    Some(misc::Union::KillProcessRequest(r)) => {
+                        self.handle_kill_process_request(r).await;
+                    }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    Some(misc::Union::DiagnosticCaptureRequest(r)) => {
+                        self.handle_diagnostic_capture_request(r).await;
+                    }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    Some(misc::Union::Annotation(a)) => {
+                        self.handle_annotation(a);
+                    }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    Some(misc::Union::QuickActionListRequest(_)) => {
+                        self.handle_quick_action_list_request().await;
+                    }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    Some(misc::Union::QuickActionRequest(r)) => {
+                        self.handle_quick_action_request(r).await;
+                    }
+                    #[cfg(windows)]
+                    Some(misc::Union::ElevationRequest(r)) => match r.union {
+                        Some(elevation_request::Union::Direct(_)) => {
+                            self.handle_elevation_request(portable_client::StartPara::Direct)
+                                .await;
+                        }
+                        Some(elevation_request::Union::Logon(r)) => {
+                            self.handle_elevation_request(portable_client::StartPara::Logon(
+                                r.username, r.password,
+                            ))
+                            .await;
+                        }
+                        _ => {}
+                    },
+                    Some(misc::Union::AudioFormat(format)) => {
+                        if !self.disable_audio {
+                            // Drop the audio sender previously.
                             drop(std::mem::replace(&mut self.audio_sender, None));
-                            self.audio_sender = Some(start_audio_thread());
+                            self.audio_sender = Some(start_voice_call_audio_thread());
                             self.audio_sender
                                 .as_ref()
                                 .map(|a| allow_err!(a.send(MediaData::AudioFormat(format))));
@@ -2788,7 +4041,22 @@ impl Connection {
                     Some(misc::Union::ChangeResolution(r)) => self.change_resolution(None, &r),
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     Some(misc::Union::ChangeDisplayResolution(dr)) => {
-                        self.change_resolution(Some(dr.display as _), &dr.resolution)
+                        if let Some(display) = self.display_to_host(dr.display as usize) {
+                            self.change_resolution(Some(display), &dr.resolution)
+                        }
+                    }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    Some(misc::Union::TextScale(percent)) => {
+                        if self.keyboard {
+                            if let Err(e) = text_scale::set(percent) {
+                                log::error!("Failed to change text scaling to {}%: {}", percent, e);
+                            }
+                        }
+                    }
+                    Some(misc::Union::CaptureWindow(w)) => {
+                        if self.is_remote() {
+                            self.capture_window(w.id, &w.title).await;
+                        }
                     }
                     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -2860,10 +4128,19 @@ impl Connection {
                     if request.is_connect {
                         self.voice_call_request_timestamp = Some(
                             NonZeroI64::new(request.req_timestamp)
-                                .unwrap_or(NonZeroI64::new(get_time()).unwrap()),
+                                .unwrap_or(NonZeroI64::new(session_clock::wall_ms()).unwrap()),
                         );
-                        // Notify the connection manager.
-                        self.send_to_cm(Data::VoiceCallIncoming);
+                        self.voice_call_virtual_mic = request.virtual_mic;
+                        match super::voice_call::request(self.inner.id()) {
+                            // Notify the connection manager.
+                            super::voice_call::Request::Offer => {
+                                self.send_to_cm(Data::VoiceCallIncoming)
+                            }
+                            super::voice_call::Request::Busy(pos) => {
+                                let ts = request.req_timestamp;
+                                self.send(new_voice_call_busy_response(ts, pos as _)).await;
+                            }
+                        }
                     } else {
                         self.close_voice_call().await;
                     }
@@ -2872,13 +4149,12 @@ impl Connection {
                     // TODO: Maybe we can do a voice call from cm directly.
                 }
                 Some(message::Union::ScreenshotRequest(request)) => {
-                    if let Some(tx) = self.inner.tx.clone() {
-                        crate::video_service::set_take_screenshot(
-                            request.display as _,
-                            request.sid.clone(),
-                            tx,
-                        );
-                        self.refresh_video_display(Some(request.display as usize));
+                    if let (Some(tx), Some(display)) = (
+                        self.inner.tx.clone(),
+                        self.display_to_host(request.display as usize),
+                    ) {
+                        crate::video_service::set_take_screenshot(display, request.sid.clone(), tx);
+                        self.refresh_video_display(Some(display));
                     }
                 }
                 Some(message::Union::TerminalAction(action)) => {
@@ -2921,35 +4197,602 @@ impl Connection {
             .get(&self.ip)
             .map(|x| x.clone())
             .unwrap_or((0, 0, 0));
-        let time = (get_time() / 60_000) as i32;
-        let res = if failure.2 > 30 {
-            self.send_login_error("Too many wrong attempts").await;
-            Self::post_alarm_audit(
-                AlarmAuditType::ExceedThirtyAttempts,
-                json!({
-                            "ip": self.ip,
-                            "id": self.lr.my_id.clone(),
-                            "name": self.lr.my_name.clone(),
-                }),
-            );
-            false
-        } else if time == failure.0 && failure.1 > 6 {
-            self.send_login_error("Please try 1 minute later").await;
-            Self::post_alarm_audit(
-                AlarmAuditType::SixAttemptsWithinOneMinute,
-                json!({
-                            "ip": self.ip,
-                            "id": self.lr.my_id.clone(),
-                            "name": self.lr.my_name.clone(),
-                }),
-            );
-            false
-        } else {
-            true
+        let time = failure_minute();
+        let res = match failure_ban(failure, time) {
+            Some((err, alarm)) => {
+                self.send_login_error(err).await;
+                Self::post_alarm_audit(
+                    alarm,
+                    json!({
+                                "ip": self.ip,
+                                "id": self.lr.my_id.clone(),
+                                "name": self.lr.my_name.clone(),
+                    }),
+                );
+                false
+            }
+            None => true,
         };
         ((failure, time), res)
     }
 
+    // The first request is answered with a token, the action is executed when the peer
+    // sends it back in time.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn handle_power_action_request(&mut self, r: PowerActionRequest) {
+        use crate::platform::power::{self, PowerAction};
+        const CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+
+        let mut res = PowerActionResponse {
+            action: r.action.clone(),
+            ..Default::default()
+        };
+        let pending = self.power_action.take();
+        match PowerAction::from_name(&r.action) {
+            _ if power::is_disabled() => res.error = "Power actions are disabled".to_owned(),
+            _ if !self.restart => res.error = "No permission of power actions".to_owned(),
+            Some(action) if !action.is_supported() => {
+                res.error = format!("Power action {} is not supported", action.name());
+            }
+            Some(action) if r.token == 0 => {
+                let token = hbb_common::rand::random::<u64>().max(1);
+                self.power_action = Some((action, token, Instant::now()));
+                res.token = token;
+            }
+            Some(action) => match pending {
+                Some((a, token, tm))
+                    if a == action && token == r.token && tm.elapsed() < CONFIRM_TIMEOUT =>
+                {
+                    log::info!("Power action {} by the peer", action.name());
+                    self.post_conn_audit(json!({
+                        "action": "power",
+                        "power_action": action.name(),
+                        "peer": ((&self.lr.my_id, &self.lr.my_name)),
+                    }));
+                    if matches!(action, PowerAction::Reboot | PowerAction::RebootToFirmware) {
+                        self.save_session_restore();
+                    }
+                    res.done = true;
+                    res.estimated_downtime_secs = action.estimated_downtime_secs();
+                    // Let the response reach the peer first.
+                    std::thread::spawn(move || {
+                        std::thread::sleep(std::time::Duration::from_secs(1));
+                        if let Err(e) = power::execute(action) {
+                            log::error!("Failed to execute power action {}: {}", action.name(), e);
+                        }
+                    });
+                }
+                _ => res.error = "The power action is not confirmed in time".to_owned(),
+            },
+            None => res.error = format!("Unknown power action {}", r.action),
+        }
+        let mut misc = Misc::new();
+        misc.set_power_action_response(res);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn process_manager_error(&self) -> Option<&'static str> {
+        if !crate::platform::process::is_allowed() {
+            Some("The remote process manager is disabled")
+        } else if !self.keyboard || !self.is_remote() {
+            Some("No permission of the process manager")
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn handle_process_list_request(&mut self, r: ProcessListRequest) {
+        let mut res = ProcessList {
+            page: r.page,
+            ..Default::default()
+        };
+        if let Some(err) = self.process_manager_error() {
+            res.error = err.to_owned();
+        } else {
+            let pm = self.process_manager.get_or_insert_with(Default::default);
+            match pm.list(r.page as _, r.page_size as _) {
+                Ok((processes, total)) => {
+                    res.total = total as _;
+                    res.processes = processes
+                        .into_iter()
+                        .map(|p| ProcessInfo {
+                            pid: p.pid,
+                            name: p.name,
+                            user: p.user,
+                            cpu_usage: p.cpu_usage,
+                            memory: p.memory,
+                            protected: p.protected,
+                            ..Default::default()
+                        })
+                        .collect();
+                }
+                Err(e) => res.error = e.to_string(),
+            }
+        }
+        let mut misc = Misc::new();
+        misc.set_process_list(res);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+    }
+
+    // Drawn by the connection manager on the display, see `annotation`.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn handle_annotation(&mut self, mut a: Annotation) {
+        if !self.annotation || !self.is_remote() {
+            return;
+        }
+        if a.shape.enum_value() == Ok(message_proto::annotation::Shape::Clear) {
+            self.send_to_cm(ipc::Data::Annotation(crate::annotation::Command::Clear));
+            return;
+        }
+        let Some(display) = self.display_to_host(a.display as usize) else {
+            return;
+        };
+        let Some(info) = display_service::get_display_info(display) else {
+            return;
+        };
+        a.display = display as _;
+        let rect = (info.x, info.y, info.width, info.height);
+        match crate::annotation::to_command(&a, rect) {
+            Some(cmd) => self.send_to_cm(ipc::Data::Annotation(cmd)),
+            None => log::debug!("Invalid annotation of {}", self.lr.my_id),
+        }
+    }
+
+    // A restarted service is subscribed again, the peer only needs to know if it's gone.
+    async fn on_service_failed(&mut self, event: super::service_monitor::ServiceEvent) {
+        log::warn!(
+            "#{} service {} stopped working, restart attempted: {}",
+            self.inner.id(),
+            event.name,
+            event.restart_attempted
+        );
+        if event.restart_attempted {
+            return;
+        }
+        let mut msg_out = Message::new();
+        msg_out.set_message_box(MessageBox {
+            msgtype: "custom-nook-nocancel-hasclose".to_owned(),
+            title: "Service stopped".to_owned(),
+            text: "service-stopped-tip".to_owned(),
+            link: "".to_owned(),
+            ..Default::default()
+        });
+        self.send(msg_out).await;
+    }
+
+    // The peer is told once, its annotations would be dropped silently otherwise.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn on_annotation_unsupported(&mut self) {
+        if !self.annotation {
+            return;
+        }
+        log::info!("#{} no annotation overlay on this desktop", self.inner.id());
+        self.annotation = false;
+        self.send_permission(Permission::Annotation, false).await;
+        let mut msg_out = Message::new();
+        msg_out.set_message_box(MessageBox {
+            msgtype: "custom-nook-nocancel-hasclose".to_owned(),
+            title: "Annotate".to_owned(),
+            text: "annotation-unsupported-tip".to_owned(),
+            link: "".to_owned(),
+            ..Default::default()
+        });
+        self.send(msg_out).await;
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn handle_diagnostic_capture_request(&mut self, r: DiagnosticCaptureRequest) {
+        let send_error = |error: &str| {
+            let mut misc = Misc::new();
+            misc.set_diagnostic_capture_response(DiagnosticCaptureResponse {
+                error: error.to_owned(),
+                ..Default::default()
+            });
+            let mut msg_out = Message::new();
+            msg_out.set_misc(misc);
+            msg_out
+        };
+        if !self.file_transfer_enabled() {
+            self.send(send_error("No permission of file transfer")).await;
+            return;
+        }
+        let Some(display) = self.display_to_host(r.display as usize) else {
+            self.send(send_error("Invalid display")).await;
+            return;
+        };
+        let Some(tx) = self.inner.tx.clone() else {
+            return;
+        };
+        // The running capturer is not refreshed, the last frame it captured is taken.
+        let running = self
+            .server
+            .upgrade()
+            .map(|s| s.read().unwrap().is_video_service_running(display))
+            .unwrap_or(false);
+        let log_bytes = super::diagnostic_capture::log_bytes(r.log_kb);
+        log::info!(
+            "#{} diagnostic capture of display {}, peer: {}",
+            self.inner.id(),
+            display,
+            self.lr.my_id
+        );
+        self.post_conn_audit(json!({
+            "action": "diagnostic_capture",
+            "display": display,
+            "log_bytes": log_bytes,
+            "peer": ((&self.lr.my_id, &self.lr.my_name)),
+        }));
+        let id = self.inner.id();
+        let peer = self.lr.my_name.clone();
+        tokio::spawn(async move {
+            let res = tokio::task::spawn_blocking(move || {
+                super::diagnostic_capture::capture(id, display, running, log_bytes)
+            })
+            .await;
+            let msg_out = match res {
+                Ok(Ok(dir)) => {
+                    let mut misc = Misc::new();
+                    misc.set_diagnostic_capture_response(DiagnosticCaptureResponse {
+                        path: dir.to_string_lossy().to_string(),
+                        ..Default::default()
+                    });
+                    let mut msg_out = Message::new();
+                    msg_out.set_misc(misc);
+                    let title = crate::client::translate("Diagnostic capture".to_owned());
+                    let text = crate::client::translate(format!(
+                        "{{{peer}}} took a screenshot and the log of this device"
+                    ));
+                    std::thread::spawn(move || {
+                        super::headless_mode::show_notification(&title, &text)
+                    });
+                    msg_out
+                }
+                Ok(Err(e)) => {
+                    log::error!("Failed to capture the diagnostics: {}", e);
+                    send_error(&e.to_string())
+                }
+                Err(e) => {
+                    log::error!("Failed to join the diagnostic capture task: {}", e);
+                    send_error(&e.to_string())
+                }
+            };
+            tx.send((Instant::now(), Arc::new(msg_out))).ok();
+        });
+    }
+
+    // The first request is answered with a token, the process is killed when the peer sends it
+    // back in time, and the user on the host approves it if required.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn handle_kill_process_request(&mut self, r: KillProcessRequest) {
+        use crate::platform::process::KillState;
+
+        let mut res = KillProcessResponse {
+            pid: r.pid,
+            ..Default::default()
+        };
+        if let Some(err) = self.process_manager_error() {
+            res.error = err.to_owned();
+        } else {
+            let pm = self.process_manager.get_or_insert_with(Default::default);
+            if r.token == 0 {
+                match pm.request_kill(r.pid) {
+                    Ok(pending) => {
+                        res.name = pending.name;
+                        res.token = pending.token;
+                        res.requires_approval = pending.approval;
+                    }
+                    Err(e) => res.error = e.to_string(),
+                }
+            } else {
+                match pm.confirm_kill(r.pid, r.token) {
+                    Ok((pending, KillState::Approval)) => {
+                        res.name = pending.name.clone();
+                        res.pending_approval = true;
+                        self.send_to_cm(ipc::Data::ProcessKillRequest((pending.pid, pending.name)));
+                    }
+                    Ok((pending, KillState::Ready)) => {
+                        res.name = pending.name.clone();
+                        self.kill_process(&pending, false, &mut res);
+                    }
+                    Err(e) => res.error = e.to_string(),
+                }
+            }
+        }
+        self.send_kill_process_response(res).await;
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn quick_action_error(&self) -> Option<&'static str> {
+        if !super::quick_actions::is_allowed() {
+            Some("Quick actions are disabled")
+        } else if !self.quick_actions || !self.is_remote() {
+            Some("No permission of quick actions")
+        } else {
+            None
+        }
+    }
+
+    // Only the names are listed, the commands stay on the host.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn handle_quick_action_list_request(&mut self) {
+        let mut res = QuickActionList::new();
+        if let Some(err) = self.quick_action_error() {
+            res.error = err.to_owned();
+        } else {
+            res.actions = super::quick_actions::get_actions()
+                .into_iter()
+                .map(|a| QuickActionInfo {
+                    name: a.name,
+                    confirm: a.confirm,
+                    ..Default::default()
+                })
+                .collect();
+        }
+        let mut misc = Misc::new();
+        misc.set_quick_action_list(res);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+    }
+
+    // An action with confirm is answered with a token first, it runs when the peer sends the
+    // token back in time. The connection manager runs it as the user of the session and kills it
+    // when the connection is closed, see `quick_actions`.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn handle_quick_action_request(&mut self, r: QuickActionRequest) {
+        use super::quick_actions::CONFIRM_TIMEOUT;
+
+        let mut res = QuickActionResponse {
+            name: r.name.clone(),
+            ..Default::default()
+        };
+        let pending = self.quick_action.take();
+        let confirmed = |name: &str| {
+            matches!(&pending, Some((n, token, tm))
+                if n == name && *token == r.token && tm.elapsed() < CONFIRM_TIMEOUT)
+        };
+        match super::quick_actions::find(&r.name) {
+            _ if self.quick_action_error().is_some() => {
+                res.error = self.quick_action_error().unwrap_or_default().to_owned();
+            }
+            None => res.error = format!("Unknown quick action {}", r.name),
+            Some(_) if self.quick_action_running.is_some() => {
+                res.error = "Another quick action is running".to_owned();
+            }
+            Some(action) if action.confirm && r.token == 0 => {
+                let token = hbb_common::rand::random::<u64>().max(1);
+                self.quick_action = Some((action.name, token, Instant::now()));
+                res.token = token;
+            }
+            Some(action) if action.confirm && !confirmed(&action.name) => {
+                res.error = "The quick action is not confirmed in time".to_owned();
+            }
+            Some(action) => {
+                log::info!("#{} quick action {} by the peer", self.inner.id(), action.name);
+                self.quick_action_running = Some(action.name.clone());
+                self.send_to_cm(ipc::Data::QuickAction(Some(action)));
+                res.started = true;
+            }
+        }
+        let mut misc = Misc::new();
+        misc.set_quick_action_response(res);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn on_quick_action_output(&mut self, output: super::quick_actions::Output) {
+        use super::quick_actions::Output;
+
+        let Some(name) = self.quick_action_running.clone() else {
+            return;
+        };
+        let mut out = QuickActionOutput {
+            name: name.clone(),
+            ..Default::default()
+        };
+        let mut exit = None;
+        match output {
+            Output::Stdout(data) => out.data = data.into(),
+            Output::Stderr(data) => {
+                out.data = data.into();
+                out.stderr = true;
+            }
+            Output::Truncated => out.truncated = true,
+            Output::Failed(err) => {
+                log::error!("Failed to run quick action {}: {}", name, err);
+                out.data = err.into_bytes().into();
+                out.stderr = true;
+                exit = Some(None);
+            }
+            Output::Exit(code) => exit = Some(code),
+        }
+        if let Some(code) = exit {
+            out.done = true;
+            // -1 if it is killed by a signal or failed to start.
+            out.exit_code = code.unwrap_or(-1);
+            self.quick_action_running = None;
+            self.post_conn_audit(json!({
+                "action": "quick_action",
+                "name": name,
+                "peer": ((&self.lr.my_id, &self.lr.my_name)),
+                "exit_code": code,
+            }));
+        }
+        let mut misc = Misc::new();
+        misc.set_quick_action_output(out);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn check_process_kill_approval(&mut self) {
+        let Some(pending) = self
+            .process_manager
+            .as_mut()
+            .and_then(|pm| pm.take_expired_approval())
+        else {
+            return;
+        };
+        log::info!(
+            "The kill of the process {} {} is not approved in time",
+            pending.pid,
+            pending.name
+        );
+        self.post_conn_audit(json!({
+            "action": "kill_process",
+            "pid": pending.pid,
+            "name": pending.name,
+            "peer": ((&self.lr.my_id, &self.lr.my_name)),
+            "approved": false,
+        }));
+        let res = KillProcessResponse {
+            pid: pending.pid,
+            name: pending.name,
+            error: "The kill of the process is not approved in time".to_owned(),
+            ..Default::default()
+        };
+        self.send_kill_process_response(res).await;
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn handle_process_kill_approval(&mut self, pid: u32, approved: bool) {
+        let Some(pending) = self
+            .process_manager
+            .as_mut()
+            .and_then(|pm| pm.take_approval(pid))
+        else {
+            return;
+        };
+        let mut res = KillProcessResponse {
+            pid,
+            name: pending.name.clone(),
+            ..Default::default()
+        };
+        if approved {
+            self.kill_process(&pending, true, &mut res);
+        } else {
+            log::info!("The kill of the process {} {} is denied", pid, pending.name);
+            self.post_conn_audit(json!({
+                "action": "kill_process",
+                "pid": pid,
+                "name": pending.name,
+                "peer": ((&self.lr.my_id, &self.lr.my_name)),
+                "approved": false,
+            }));
+            res.error = "The kill of the process is denied".to_owned();
+        }
+        self.send_kill_process_response(res).await;
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn kill_process(
+        &mut self,
+        pending: &crate::platform::process::PendingKill,
+        approved: bool,
+        res: &mut KillProcessResponse,
+    ) {
+        let Some(pm) = self.process_manager.as_mut() else {
+            return;
+        };
+        let result = pm.kill(pending);
+        let (pid, name) = (pending.pid, &pending.name);
+        log::info!(
+            "Kill the process {} {} by the peer {}: {:?}",
+            pid,
+            name,
+            self.lr.my_id,
+            result
+        );
+        self.post_conn_audit(json!({
+            "action": "kill_process",
+            "pid": pid,
+            "name": name,
+            "peer": ((&self.lr.my_id, &self.lr.my_name)),
+            "approved": approved,
+            "result": result.as_ref().err().map(|e| e.to_string()).unwrap_or_default(),
+        }));
+        match result {
+            Ok(()) => res.done = true,
+            Err(e) => res.error = e.to_string(),
+        }
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn send_kill_process_response(&mut self, res: KillProcessResponse) {
+        let mut misc = Misc::new();
+        misc.set_kill_process_response(res);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+    }
+
+    // The password is asked again before the privileged services are subscribed. It's checked
+    // with the new challenge, a replay of the hash of the login fails.
+    async fn handle_reauth(&mut self, password: Bytes, plain_password: String) {
+        let Some(challenge) = self.inner.reauth_challenge() else {
+            return;
+        };
+        let (failure, res) = self.check_failure(0).await;
+        if !res {
+            return;
+        }
+        self.hash.challenge = challenge;
+        self.lr.password = password;
+        self.lr.plain_password = plain_password;
+        let validated = self.validate_password().await;
+        self.lr.plain_password.clear();
+        if validated {
+            self.update_failure(failure, true, 0);
+            log::info!("Re-authentication of {} succeeded", self.lr.my_id);
+            if let Some(s) = self.server.upgrade() {
+                s.write().unwrap().grant_reauth(self.inner.id());
+            }
+        } else {
+            self.update_failure(failure, false, 0);
+            self.send_login_error(ErrorCode::PasswordWrong).await;
+        }
+    }
+
+    async fn handle_client_visibility(&mut self, v: ClientVisibility) {
+        let hidden = !v.visible;
+        if hidden == self.client_hidden.is_some() {
+            return;
+        }
+        log::info!(
+            "#{} the window of the peer is {}",
+            self.inner.id(),
+            if v.minimized {
+                "minimized"
+            } else if hidden {
+                "hidden"
+            } else {
+                "visible"
+            }
+        );
+        video_service::VIDEO_QOS
+            .lock()
+            .unwrap()
+            .user_hidden(self.inner.id(), hidden);
+        if hidden {
+            self.client_hidden = Some(Default::default());
+        } else if let Some(held) = self.client_hidden.take() {
+            for msg in held.take() {
+                self.send((*msg).clone()).await;
+            }
+            // A keyframe, the frames while hidden may not have been decoded.
+            self.refresh_video_display(None);
+        }
+    }
+
     fn refresh_video_display(&self, display: Option<usize>) {
         video_service::refresh();
         self.server.upgrade().map(|s| {
@@ -2962,7 +4805,9 @@ impl Connection {
     }
 
     async fn handle_switch_display(&mut self, s: SwitchDisplay) {
-        let display_idx = s.display as usize;
+        let Some(display_idx) = self.display_to_host(s.display as usize) else {
+            return;
+        };
         if self.display_idx != display_idx {
             if let Some(server) = self.server.upgrade() {
                 self.switch_display_to(display_idx, server.clone());
@@ -3019,11 +4864,25 @@ impl Connection {
             lock.subscribe(&old_service_name, self.inner.clone(), false);
         }
         lock.subscribe(&new_service_name, self.inner.clone(), true);
+        self.update_qos_displays(&lock);
         self.display_idx = display_idx;
     }
 
+    // Let video qos split the bandwidth among the displays subscribed by this connection.
+    fn update_qos_displays(&self, server: &Server) {
+        let displays = server.get_subbed_displays(self.inner.id());
+        video_service::VIDEO_QOS
+            .lock()
+            .unwrap()
+            .user_displays(self.inner.id(), displays);
+    }
+
     #[cfg(windows)]
     async fn handle_elevation_request(&mut self, para: portable_client::StartPara) {
+        let event = match para {
+            portable_client::StartPara::Direct => sensitive_events::Event::Elevation,
+            portable_client::StartPara::Logon(..) => sensitive_events::Event::CredentialInjection,
+        };
         let mut err;
         if !self.keyboard {
             err = "No permission".to_string();
@@ -3033,6 +4892,10 @@ impl Connection {
                 err = portable_client::start_portable_service(para)
                     .err()
                     .map_or("".to_string(), |e| e.to_string());
+                self.portable.elevation_requested = err.is_empty();
+                if err.is_empty() {
+                    self.alert_sensitive_event(event);
+                }
             }
         }
 
@@ -3067,7 +4930,8 @@ impl Connection {
             } else {
                 lock.capture_displays(self.inner.clone(), video_source, set, true, true);
             }
-            self.multi_ui_session = lock.get_subbed_displays_count(self.inner.id()) > 1;
+            self.multi_ui_session = lock.get_subbed_displays(self.inner.id()).len() > 1;
+            self.update_qos_displays(&lock);
             if self.follow_remote_window {
                 lock.subscribe(
                     NAME_WINDOW_FOCUS,
@@ -3079,45 +4943,56 @@ impl Connection {
         }
     }
 
-    #[cfg(windows)]
-    async fn toggle_virtual_display(&mut self, t: ToggleVirtualDisplay) {
-        let make_msg = |text: String| {
+    // The window is selected by the id, or by the title if the id is 0.
+    async fn capture_window(&mut self, id: u64, title: &str) {
+        let window = if id != 0 {
+            scrap::window::Windows::get(id)
+        } else {
+            scrap::window::Windows::find(title)
+        };
+        let captured = match (window, self.server.upgrade()) {
+            (Some(window), Some(server)) => {
+                let mut lock = server.write().unwrap();
+                let captured = lock.capture_window(self.inner.clone(), window.id);
+                if captured {
+                    self.multi_ui_session = false;
+                    self.update_qos_displays(&lock);
+                }
+                captured
+            }
+            _ => false,
+        };
+        if !captured {
             let mut msg_out = Message::new();
-            let res = MessageBox {
-                msgtype: "nook-nocancel-hasclose".to_owned(),
-                title: "Virtual display".to_owned(),
-                text,
+            msg_out.set_message_box(MessageBox {
+                msgtype: "custom-nook-nocancel-hasclose".to_owned(),
+                title: "Window capture".to_owned(),
+                text: "window-capture-failed-tip".to_owned(),
                 link: "".to_owned(),
                 ..Default::default()
-            };
-            msg_out.set_message_box(res);
-            msg_out
+            });
+            self.send(msg_out).await;
+        }
+    }
+
+    #[cfg(windows)]
+    fn virtual_display_msg(text: String) -> Message {
+        let mut msg_out = Message::new();
+        let res = MessageBox {
+            msgtype: "nook-nocancel-hasclose".to_owned(),
+            title: "Virtual display".to_owned(),
+            text,
+            link: "".to_owned(),
+            ..Default::default()
         };
+        msg_out.set_message_box(res);
+        msg_out
+    }
 
-        if t.on {
-            if !virtual_display_manager::is_virtual_display_supported() {
-                self.send(make_msg("idd_not_support_under_win10_2004_tip".to_string()))
-                    .await;
-            } else {
-                if let Err(e) = virtual_display_manager::plug_in_monitor(t.display as _, Vec::new())
-                {
-                    log::error!("Failed to plug in virtual display: {}", e);
-                    self.send(make_msg(format!(
-                        "Failed to plug in virtual display: {}",
-                        e
-                    )))
-                    .await;
-                }
-            }
-        } else {
-            if let Err(e) = virtual_display_manager::plug_out_monitor(t.display, false, true) {
-                log::error!("Failed to plug out virtual display {}: {}", t.display, e);
-                self.send(make_msg(format!(
-                    "Failed to plug out virtual displays: {}",
-                    e
-                )))
-                .await;
-            }
+    #[cfg(windows)]
+    async fn toggle_virtual_display(&mut self, t: ToggleVirtualDisplay) {
+        if let Err(e) = display_service::toggle_virtual_display(t.display, t.on) {
+            self.send(Self::virtual_display_msg(e)).await;
         }
     }
 
@@ -3129,6 +5004,192 @@ impl Connection {
         }
     }
 
+    // Tells the user of the host about a sensitive event of the peer, see `sensitive_events`.
+    fn alert_sensitive_event(&mut self, event: sensitive_events::Event) {
+        if !sensitive_events::is_sensitive(event) {
+            return;
+        }
+        let timeout = sensitive_events::confirm_timeout();
+        log::info!(
+            "#{} sensitive event {}, peer: {}",
+            self.inner.id(),
+            event.name(),
+            self.lr.my_id
+        );
+        self.post_conn_audit(json!({
+            "action": "sensitive_event",
+            "event": event.name(),
+            "confirm_timeout": timeout.map(|t| t.as_secs()),
+            "peer": ((&self.lr.my_id, &self.lr.my_name)),
+        }));
+        let title = crate::client::translate("Sensitive feature used".to_owned());
+        let mut text = crate::client::translate(event.text(&self.lr.my_name));
+        if let Some(timeout) = timeout {
+            let secs = timeout.as_secs();
+            text = format!(
+                "{}\n{}",
+                text,
+                crate::client::translate(format!(
+                    "Confirm it within {{{secs}}} seconds, or it will be undone"
+                ))
+            );
+            self.sensitive_pending.add(event, timeout);
+        }
+        std::thread::spawn(move || super::headless_mode::show_notification(&title, &text));
+        let secs = timeout.map_or(0, |t| t.as_secs());
+        self.send_to_cm(ipc::Data::SensitiveEvent((event, secs)));
+    }
+
+    // false if the connection is closed.
+    async fn handle_sensitive_event_confirm(
+        &mut self,
+        event: sensitive_events::Event,
+        confirmed: bool,
+    ) -> bool {
+        let Some(undo) = self.sensitive_pending.answer(event, confirmed) else {
+            return true;
+        };
+        self.post_sensitive_event_audit(event, if confirmed { "confirmed" } else { "denied" });
+        if !undo {
+            return true;
+        }
+        self.undo_sensitive_event(event).await
+    }
+
+    // The sensitive events not confirmed in time are undone, false if the connection is closed.
+    async fn check_sensitive_events(&mut self) -> bool {
+        for event in self.sensitive_pending.take_expired() {
+            self.post_sensitive_event_audit(event, "timeout");
+            if !self.undo_sensitive_event(event).await {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn post_sensitive_event_audit(&self, event: sensitive_events::Event, outcome: &str) {
+        log::info!(
+            "#{} sensitive event {} {}, peer: {}",
+            self.inner.id(),
+            event.name(),
+            outcome,
+            self.lr.my_id
+        );
+        self.post_conn_audit(json!({
+            "action": "sensitive_event",
+            "event": event.name(),
+            "outcome": outcome,
+            "peer": ((&self.lr.my_id, &self.lr.my_name)),
+        }));
+    }
+
+    // false if the connection is closed, the elevation can't be undone without closing it.
+    async fn undo_sensitive_event(&mut self, event: sensitive_events::Event) -> bool {
+        match event {
+            sensitive_events::Event::PrivacyMode => {
+                if privacy_mode::get_privacy_mode_conn_id() == Some(self.inner.id) {
+                    let msg_out = Self::turn_off_privacy_to_msg(self.inner.id);
+                    self.send(msg_out).await;
+                }
+                true
+            }
+            sensitive_events::Event::InputBlock => {
+                self.tx_input.send(MessageInput::BlockOff).ok();
+                if let Some(tx) = &self.inner.tx {
+                    Self::send_block_input_error(
+                        tx,
+                        back_notification::BlockInputState::BlkOffSucceeded,
+                        "".to_owned(),
+                    );
+                }
+                // Or the peer blocks it again at once, the connection manager turns it off too.
+                self.block_input = false;
+                self.send_permission(Permission::BlockInput, false).await;
+                true
+            }
+            sensitive_events::Event::ElevatedTerminal
+            | sensitive_events::Event::CredentialInjection
+            | sensitive_events::Event::Elevation => {
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                if self.terminal {
+                    self.update_terminal_persistence(false).await;
+                    terminal_service::TerminalServiceProxy::new(
+                        self.terminal_service_id.clone(),
+                        Some(false),
+                    )
+                    .on_disconnect();
+                }
+                #[cfg(windows)]
+                if event != sensitive_events::Event::ElevatedTerminal {
+                    if let Some(peer_id) = self.policy_peer_id() {
+                        portable_client::revoke_grants(peer_id);
+                    }
+                }
+                self.send_close_reason_no_retry(ErrorCode::NotConfirmed).await;
+                self.on_close("sensitive event undone", false).await;
+                false
+            }
+        }
+    }
+
+    // The environment of a remote session is restored after the reboot by the peer, see
+    // `session_restore`.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn save_session_restore(&self) {
+        if !self.is_remote() {
+            return;
+        }
+        session_restore::save(
+            &self.lr.my_id,
+            session_restore::Record {
+                // Not the global changed resolutions, they may be of other connections.
+                resolutions: self.changed_resolutions.clone(),
+                #[cfg(windows)]
+                virtual_displays: virtual_display_manager::get_virtual_displays(),
+                ..Default::default()
+            },
+        );
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn restore_session_environment(&mut self) {
+        if !self.is_remote() || !self.keyboard {
+            return;
+        }
+        let Some(record) = session_restore::take(&self.lr.my_id) else {
+            return;
+        };
+        log::info!("Restore the session environment: {:?}", record);
+        #[cfg(windows)]
+        for idx in record.virtual_displays.iter() {
+            if let Err(e) = virtual_display_manager::plug_in_monitor(*idx, vec![]) {
+                log::error!("Failed to restore virtual display {}: {}", idx, e);
+            }
+        }
+        if !record.resolutions.is_empty() {
+            let displays = display_service::try_get_displays().unwrap_or_default();
+            for (name, (width, height)) in record.resolutions.iter() {
+                match displays.iter().position(|d| &d.name() == name) {
+                    Some(idx) => self.change_resolution(
+                        Some(idx),
+                        &Resolution {
+                            width: *width,
+                            height: *height,
+                            ..Default::default()
+                        },
+                    ),
+                    None => log::warn!("Display '{}' to restore the resolution is gone", name),
+                }
+            }
+        }
+        self.post_conn_audit(json!({
+            "action": "session_restore",
+            "peer": ((&self.lr.my_id, &self.lr.my_name)),
+            "virtual_displays": record.virtual_displays,
+            "resolutions": record.resolutions,
+        }));
+    }
+
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     fn change_resolution(&mut self, d: Option<usize>, r: &Resolution) {
         if self.keyboard {
@@ -3166,16 +5227,19 @@ impl Connection {
                             (r.width, r.height),
                         );
                     }
-                    if let Err(e) =
-                        crate::platform::change_resolution(&name, r.width as _, r.height as _)
-                    {
-                        log::error!(
+                    match crate::platform::change_resolution(&name, r.width as _, r.height as _) {
+                        Ok(()) if record_changed => {
+                            self.changed_resolutions.retain(|(n, _)| n != &name);
+                            self.changed_resolutions.push((name, (r.width, r.height)));
+                        }
+                        Ok(()) => {}
+                        Err(e) => log::error!(
                             "Failed to change resolution '{}' to ({},{}): {:?}",
                             &name,
                             r.width,
                             r.height,
                             e
-                        );
+                        ),
                     }
                 }
             }
@@ -3184,6 +5248,8 @@ impl Connection {
 
     pub async fn handle_voice_call(&mut self, accepted: bool) {
         if let Some(ts) = self.voice_call_request_timestamp.take() {
+            // Only the offered call can be accepted.
+            let accepted = accepted && super::voice_call::is_owner(self.inner.id());
             let msg = new_voice_call_response(ts.get(), accepted);
             if accepted {
                 crate::audio_service::set_voice_call_input_device(
@@ -3191,8 +5257,14 @@ impl Connection {
                     false,
                 );
                 self.send_to_cm(Data::StartVoiceCall);
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                {
+                    self.start_virtual_mic();
+                    super::push_to_talk::start(self.inner.id());
+                }
             } else {
                 self.send_to_cm(Data::CloseVoiceCall("".to_owned()));
+                super::voice_call::end(self.inner.id());
             }
             self.send(msg).await;
             self.voice_calling = accepted;
@@ -3210,11 +5282,67 @@ impl Connection {
         }
     }
 
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn start_virtual_mic(&mut self) {
+        if !std::mem::take(&mut self.voice_call_virtual_mic) {
+            return;
+        }
+        if !super::virtual_mic::is_allowed() {
+            log::info!("The virtual microphone is not allowed");
+            return;
+        }
+        match super::virtual_mic::start(self.inner.id()) {
+            Ok(()) => {
+                self.send_to_cm(Data::VirtualMic(true));
+                self.post_conn_audit(json!({
+                    "action": "virtual_mic",
+                    "peer": ((&self.lr.my_id, &self.lr.my_name)),
+                    "live": true,
+                }));
+            }
+            Err(e) => log::error!("Failed to start the virtual microphone: {}", e),
+        }
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn stop_virtual_mic(&mut self) {
+        if super::virtual_mic::stop(self.inner.id()) {
+            self.send_to_cm(Data::VirtualMic(false));
+            self.post_conn_audit(json!({
+                "action": "virtual_mic",
+                "peer": ((&self.lr.my_id, &self.lr.my_name)),
+                "live": false,
+            }));
+        }
+    }
+
+    // The waiting call of the connection is offered after the call before it ended.
+    fn offer_waiting_voice_call(&mut self) {
+        if self.voice_call_request_timestamp.is_some() {
+            self.send_to_cm(Data::VoiceCallIncoming);
+        } else {
+            // The peer has given up.
+            super::voice_call::end(self.inner.id());
+        }
+    }
+
     pub async fn close_voice_call(&mut self) {
-        crate::audio_service::set_voice_call_input_device(None, true);
+        self.voice_call_request_timestamp = None;
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        self.stop_virtual_mic();
+        if super::voice_call::is_owner(self.inner.id()) {
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            super::push_to_talk::stop();
+            crate::audio_service::set_voice_call_input_device(None, true);
+        }
+        super::voice_call::end(self.inner.id());
         // Notify the connection manager that the voice call has been closed.
         self.send_to_cm(Data::CloseVoiceCall("".to_owned()));
         self.voice_calling = false;
+        if self.push_to_talk_muted {
+            self.push_to_talk_muted = false;
+            self.update_audio_subscription();
+        }
         if self.is_authed_view_camera_conn() {
             if let Some(s) = self.server.upgrade() {
                 s.write()
@@ -3276,6 +5404,11 @@ impl Connection {
                 }
             }
         }
+        if let Ok(q) = o.capture_sync.enum_value() {
+            if q != BoolOption::NotSet {
+                display_service::set_capture_sync(self.inner.id(), q == BoolOption::Yes);
+            }
+        }
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         if let Ok(q) = o.follow_remote_cursor.enum_value() {
             if q != BoolOption::NotSet {
@@ -3297,23 +5430,29 @@ impl Connection {
         if let Ok(q) = o.disable_audio.enum_value() {
             if q != BoolOption::NotSet {
                 self.disable_audio = q == BoolOption::Yes;
-                if let Some(s) = self.server.upgrade() {
-                    if self.is_authed_view_camera_conn() {
-                        if self.voice_calling || !self.audio_enabled() {
-                            s.write().unwrap().subscribe(
-                                super::audio_service::NAME,
-                                self.inner.clone(),
-                                self.audio_enabled(),
-                            );
-                        }
-                    } else {
-                        s.write().unwrap().subscribe(
-                            super::audio_service::NAME,
-                            self.inner.clone(),
-                            self.audio_enabled(),
-                        );
-                    }
-                }
+                self.update_audio_subscription();
+            }
+        }
+        if let Some(line_ending) = LineEnding::from_i32(o.clipboard_line_ending) {
+            self.clipboard_line_ending = line_ending;
+        }
+        if let Ok(q) = o.frame_drop_stats.enum_value() {
+            if q != BoolOption::NotSet {
+                self.frame_drop_stats = q == BoolOption::Yes;
+                session_report::set_frame_drop_accounting(
+                    self.inner.id(),
+                    self.frame_drop_stats.then(|| self.inner.stats.clone()),
+                );
+            }
+        }
+        if let Ok(q) = o.low_delay_audio.enum_value() {
+            if q != BoolOption::NotSet {
+                self.low_delay_audio =
+                    q == BoolOption::Yes && self.supports(Capability::LowDelayAudio);
+                super::audio_service::set_low_delay_candidate(
+                    self.inner.id(),
+                    self.low_delay_audio,
+                );
             }
         }
         #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
@@ -3396,6 +5535,7 @@ impl Connection {
                 match q {
                     BoolOption::Yes => {
                         self.tx_input.send(MessageInput::BlockOn).ok();
+                        self.alert_sensitive_event(sensitive_events::Event::InputBlock);
                     }
                     BoolOption::No => {
                         self.tx_input.send(MessageInput::BlockOff).ok();
@@ -3424,6 +5564,7 @@ impl Connection {
     }
 
     async fn turn_on_privacy(&mut self, impl_key: String) {
+        let was_on = privacy_mode::get_privacy_mode_conn_id() == Some(self.inner.id);
         let msg_out = if !privacy_mode::is_privacy_mode_supported() {
             crate::common::make_privacy_mode_msg_with_details(
                 back_notification::PrivacyModeState::PrvNotSupported,
@@ -3500,6 +5641,9 @@ impl Connection {
             }
         };
         self.send(msg_out).await;
+        if !was_on && privacy_mode::get_privacy_mode_conn_id() == Some(self.inner.id) {
+            self.alert_sensitive_event(sensitive_events::Event::PrivacyMode);
+        }
     }
 
     async fn turn_off_privacy(&mut self, impl_key: String) {
@@ -3544,17 +5688,18 @@ impl Connection {
             return;
         }
         self.closed = true;
-        // If voice A,B -> C, and A,B has voice call
-        // B disconnects, C will reset the voice call input.
-        //
-        // It may be acceptable, because it's not a common case,
-        // and it's immediately known when the input device changes.
-        // C can change the input device manually in cm interface.
-        //
-        // We can add a (Vec<conn_id>, input device) to avoid this.
-        // But it's not necessary now and we have to consider two audio services(client, server).
-        crate::audio_service::set_voice_call_input_device(None, true);
+        // Only the connection of the call resets the input device, see `voice_call`.
+        if super::voice_call::is_owner(self.inner.id()) {
+            crate::audio_service::set_voice_call_input_device(None, true);
+        }
+        super::voice_call::end(self.inner.id());
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        self.stop_virtual_mic();
         log::info!("#{} Connection closed: {}", self.inner.id(), reason);
+        self.lanes.log_stats(self.inner.id());
+        if self.authorized {
+            self.post_session_report(reason).await;
+        }
         if lock && self.lock_after_session_end && self.keyboard {
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
             lock_screen().await;
@@ -3571,14 +5716,41 @@ impl Connection {
         self.port_forward_socket.take();
     }
 
-    // The `reason` should be consistent with `check_if_retry` if not empty
-    async fn send_close_reason_no_retry(&mut self, reason: &str) {
-        let mut misc = Misc::new();
-        if reason.is_empty() {
-            misc.set_close_reason("Closed manually by the peer".to_string());
-        } else {
-            misc.set_close_reason(reason.to_string());
+    // The peer may be gone already, so the report is sent with a short timeout.
+    async fn post_session_report(&mut self, reason: &str) {
+        let report =
+            self.session_stats
+                .report(self.inner.id(), &self.lr.my_id, &self.lr.my_name, reason);
+        let Ok(json) = serde_json::to_string(&report) else {
+            return;
+        };
+        self.post_conn_audit(json!({
+            "action": "session_report",
+            "report": json,
+        }));
+        session_report::add_report(report);
+        if session_report::is_send_enabled() {
+            let mut misc = Misc::new();
+            misc.set_session_report(json);
+            let mut msg_out = Message::new();
+            msg_out.set_misc(misc);
+            timeout(10, self.stream.send(&msg_out)).await.ok();
         }
+    }
+
+    // The `reason` should be consistent with `check_if_retry`
+    async fn send_close_reason_no_retry(&mut self, reason: impl Into<CodedError>) {
+        let CodedError { code, text } = reason.into();
+        self.close_code = code;
+        self.send_to_cm(ipc::Data::ErrorCode(code.code()));
+        // Before the close reason, the old clients ignore it.
+        let mut misc = Misc::new();
+        misc.set_close_reason_code(code.code());
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+        let mut misc = Misc::new();
+        misc.set_close_reason(text);
         let mut msg_out = Message::new();
         msg_out.set_misc(misc);
         self.send(msg_out).await;
@@ -3603,7 +5775,38 @@ impl Connection {
 
     #[inline]
     async fn send(&mut self, msg: Message) {
-        allow_err!(self.stream.send(&msg).await);
+        let msg = match self.display_filter.as_ref() {
+            Some(filter) => match filter.outgoing(Arc::new(msg)) {
+                Some(msg) => msg,
+                None => return,
+            },
+            None => Arc::new(msg),
+        };
+        match msg.write_to_bytes() {
+            Ok(bytes) => {
+                self.session_stats.on_sent(&msg, bytes.len());
+                allow_err!(self.stream.send_raw(bytes).await);
+            }
+            Err(err) => log::error!("Failed to encode the message: {}", err),
+        }
+    }
+
+    // The display index from the peer to the one on the host, None if the peer can't see it.
+    #[inline]
+    fn display_to_host(&self, display: usize) -> Option<usize> {
+        match self.display_filter.as_ref() {
+            Some(filter) => filter.to_host(display),
+            None => Some(display),
+        }
+    }
+
+    /// The error of the recent login failures of the ip, it's checked before the handshake.
+    pub fn check_banned(ip: &str) -> Option<ErrorCode> {
+        let time = failure_minute();
+        LOGIN_FAILURES.iter().find_map(|failures| {
+            let failure = failures.lock().unwrap().get(ip).cloned()?;
+            failure_ban(failure, time).map(|(err, _)| err)
+        })
     }
 
     pub fn alive_conns() -> Vec<i32> {
@@ -3621,6 +5824,35 @@ impl Connection {
             ipc::DataPortableService::CmShowElevation(show_elevation),
         ));
         if self.authorized {
+            // The grants are of the trusted devices, not of the ids the peers claim.
+            let grant_id = self.policy_peer_id().map(|id| id.to_owned());
+            if self.portable.last_running.is_none() {
+                if let Some(peer_id) = grant_id.as_deref() {
+                    if portable_client::use_grant(peer_id) {
+                        if running {
+                            self.post_elevation_grant_audit("reuse");
+                        } else {
+                            match portable_client::start_portable_service(
+                                portable_client::StartPara::Direct,
+                            ) {
+                                Ok(()) => {
+                                    self.post_elevation_grant_audit("reelevate");
+                                    self.alert_sensitive_event(sensitive_events::Event::Elevation);
+                                }
+                                Err(e) => log::error!("Failed to elevate with the grant: {}", e),
+                            }
+                        }
+                    }
+                }
+            }
+            if running && self.portable.elevation_requested {
+                self.portable.elevation_requested = false;
+                if let Some(peer_id) = grant_id.as_deref() {
+                    if portable_client::remember_grant(peer_id) {
+                        self.post_elevation_grant_audit("grant");
+                    }
+                }
+            }
             let p = &mut self.portable;
             if Some(running) != p.last_running {
                 p.last_running = Some(running);
@@ -3658,6 +5890,155 @@ impl Connection {
         }
     }
 
+    #[cfg(windows)]
+    fn post_elevation_grant_audit(&self, action: &str) {
+        log::info!(
+            "#{} elevation grant {}, peer: {}",
+            self.inner.id(),
+            action,
+            self.lr.my_id
+        );
+        self.post_conn_audit(json!({
+            "action": "elevation",
+            "elevation": action,
+            "peer": ((&self.lr.my_id, &self.lr.my_name)),
+        }));
+    }
+
+    // The watchdog in `fix_key_down_timeout` releases the keys too late for the peer's
+    // Alt+Tab case, so the keys are released as soon as the peer window loses focus.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn handle_client_focus(&mut self, focus: ClientFocus) {
+        if !self.peer_keyboard_enabled() {
+            return;
+        }
+        if focus.focused {
+            self.tx_input
+                .send(MessageInput::FocusGained(focus.modifiers))
+                .ok();
+        } else {
+            for evt in self.injected_keys.take_releases() {
+                self.input_key(evt, false);
+            }
+            self.release_pressed_modifiers();
+        }
+    }
+
+    // Move the session to another relay, e.g. the current one is congested. Only the transport
+    // moves, the session with its login, permissions and subscriptions goes on. The uuid and a new
+    // key go to the peer over this encrypted connection, both sides connect to the relay with the
+    // uuid and encrypt the new stream by the key, and the peer proves it's the same by sending the
+    // uuid on it. Nothing changes if the migration fails, the peer stays on this connection.
+    async fn migrate_relay(&mut self, relay_server: String) {
+        let Some(peer_addr) = self.relay_peer_addr else {
+            log::warn!(
+                "Skip the relay migration of connection {}, it is not relayed",
+                self.inner.id
+            );
+            return;
+        };
+        if !self.authorized || !self.encrypted {
+            log::warn!(
+                "Skip the relay migration of connection {}, it is not authorized or not encrypted",
+                self.inner.id
+            );
+            return;
+        }
+        if !self.supports(Capability::RelayMigration) {
+            log::warn!(
+                "Skip the relay migration of connection {}, the peer doesn't support it",
+                self.inner.id
+            );
+            return;
+        }
+        let uuid = uuid::Uuid::new_v4().to_string();
+        let key = hbb_common::sodiumoxide::crypto::secretbox::gen_key();
+        log::info!(
+            "Migrate connection {} to relay {}",
+            self.inner.id,
+            relay_server
+        );
+        let mut misc = Misc::new();
+        misc.set_relay_migration(RelayMigration {
+            relay_server: relay_server.clone(),
+            uuid: uuid.clone(),
+            key: key.0.to_vec().into(),
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+        self.post_conn_audit(json!({
+            "action": "relay_migration",
+            "relay_server": relay_server,
+        }));
+        let id = self.inner.id;
+        let tx = self.tx_relay_migrated.clone();
+        tokio::spawn(async move {
+            let res = async {
+                let mut stream =
+                    super::connect_relay(relay_server, uuid.clone(), peer_addr.is_ipv4()).await?;
+                accept_relay_migration(&mut stream, &uuid, key).await?;
+                ResultType::Ok(stream)
+            };
+            match res.await {
+                Ok(stream) => {
+                    tx.send(stream).ok();
+                }
+                Err(err) => log::warn!(
+                    "Failed to migrate connection {} to the new relay, stay on the current one: {}",
+                    id,
+                    err
+                ),
+            }
+        });
+    }
+
+    // The old stream is dropped, the peer switches to the new one on the reply.
+    async fn on_relay_migrated(&mut self, mut stream: super::Stream) {
+        let mut misc = Misc::new();
+        misc.set_relay_migration(RelayMigration::default());
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        if let Err(err) = stream.send(&msg_out).await {
+            log::warn!(
+                "Failed to migrate connection {} to the new relay: {}",
+                self.inner.id,
+                err
+            );
+            return;
+        }
+        stream.set_send_timeout(SEND_TIMEOUT_VIDEO);
+        self.stream = stream;
+        log::info!("Connection {} is moved to the new relay", self.inner.id);
+    }
+
+    async fn on_control_token_changed(&mut self) {
+        if !input_control::is_exclusive() || !self.keyboard {
+            return;
+        }
+        let (holding, holder, queued) = input_control::state(self.inner.id);
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if self.control_token && !holding {
+            // The keys of the previous holder must not stay pressed.
+            for evt in self.injected_keys.take_releases() {
+                self.input_key(evt, false);
+            }
+            self.release_pressed_modifiers();
+        }
+        self.control_token = holding;
+        let mut misc = Misc::new();
+        misc.set_control_token_state(ControlTokenState {
+            holding,
+            holder,
+            queued,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+    }
+
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     fn release_pressed_modifiers(&mut self) {
         for modifier in self.pressed_modifiers.iter() {
@@ -3674,7 +6055,7 @@ impl Connection {
             if minute == 0 {
                 minute = 10;
             }
-            Some((Instant::now(), minute))
+            Some((session_clock::instant(), minute))
         } else {
             None
         }
@@ -3683,7 +6064,7 @@ impl Connection {
     fn update_auto_disconnect_timer(&mut self) {
         self.auto_disconnect_timer
             .as_mut()
-            .map(|t| t.0 = Instant::now());
+            .map(|t| t.0 = session_clock::instant());
     }
 
     #[cfg(feature = "hwcodec")]
@@ -3766,6 +6147,12 @@ impl Connection {
 
     #[cfg(feature = "unix-file-copy-paste")]
     async fn handle_file_clip(&mut self, clip: clipboard::ClipboardFile) {
+        if matches!(clip, clipboard::ClipboardFile::FormatList { .. }) {
+            if let Some(msg) = self.clipboard_files_blocked(true) {
+                self.send_clipboard_blocked(msg).await;
+                return;
+            }
+        }
         let is_stopping_allowed = clip.is_stopping_allowed();
         let is_keyboard_enabled = self.peer_keyboard_enabled();
         let file_transfer_enabled = self.file_transfer_enabled();
@@ -3784,11 +6171,10 @@ impl Connection {
                 // If one way file transfer is enabled, don't send clipboard file to client
             } else {
                 // Maybe we should end the connection, because copy&paste files causes everything to wait.
-                allow_err!(
-                    self.stream
-                        .send(&crate::clipboard_file::clip_2_msg(clip))
-                        .await
-                );
+                let chunked = self.supports(Capability::ChunkedClipboardFileList);
+                for msg in split_file_list(crate::clipboard_file::clip_2_msg(clip), chunked) {
+                    allow_err!(self.stream.send(&msg).await);
+                }
             }
         }
     }
@@ -3800,15 +6186,39 @@ impl Connection {
     }
 
     #[cfg(all(target_os = "windows", feature = "flutter"))]
-    async fn send_printer_request(&mut self, data: Vec<u8>) {
+    async fn send_printer_request(&mut self, job_id: u64) {
+        let data = match printer_queue::read(job_id) {
+            Ok(data) => data,
+            Err(e) => {
+                log::error!("Failed to read the printer job {}: {}", job_id, e);
+                printer_queue::remove(job_id);
+                return;
+            }
+        };
         // This path is only used to identify the printer job.
-        let path = format!("RustDesk://FsJob//Printer/{}", get_time());
+        let path = format!("RustDesk://FsJob//Printer/{}", job_id);
 
         let msg = fs::new_send(0, fs::JobType::Printer, path.clone(), 1, false);
         self.send(msg).await;
         self.printer_data
-            .retain(|(t, _, _)| t.elapsed().as_secs() < 60);
-        self.printer_data.push((Instant::now(), path, data));
+            .retain(|(t, _, _, _)| t.elapsed().as_secs() < 60);
+        self.printer_data.push((Instant::now(), path, data, job_id));
+    }
+
+    // The print jobs which are no longer read are finished, remove them from the queue.
+    fn check_printing_jobs(&mut self) {
+        if self.printing_jobs.is_empty() {
+            return;
+        }
+        let read_jobs = &self.read_jobs;
+        self.printing_jobs.retain(|(_id, _job_id)| {
+            if read_jobs.iter().any(|j| j.id == *_id) {
+                return true;
+            }
+            #[cfg(all(target_os = "windows", feature = "flutter"))]
+            printer_queue::on_sent(*_job_id);
+            false
+        });
     }
 
     #[cfg(all(target_os = "windows", feature = "flutter"))]
@@ -3825,6 +6235,68 @@ impl Connection {
         self.send(msg_out).await;
     }
 
+    fn post_feature_denied_audit(&self, feature: &str) {
+        Self::post_alarm_audit(
+            AlarmAuditType::FeatureNotAllowed,
+            json!({
+                "ip": self.ip,
+                "id": self.lr.my_id.clone(),
+                "name": self.lr.my_name.clone(),
+                "feature": feature,
+            }),
+        );
+    }
+
+    async fn check_feature_allowlist(&mut self, feature: &str) -> bool {
+        if is_feature_allowed(feature, self.policy_peer_id()) {
+            return true;
+        }
+        log::warn!(
+            "#{} peer {} is not in the {} allowlist",
+            self.inner.id(),
+            self.lr.my_id,
+            feature
+        );
+        self.post_feature_denied_audit(feature);
+        self.send_login_error(ErrorCode::FeatureNotAllowed.with_detail(feature)).await;
+        sleep(1.).await;
+        false
+    }
+
+    // Returns false if the connection is closed because its feature is revoked.
+    async fn on_feature_allowlist_changed(&mut self) -> bool {
+        let feature = if self.terminal {
+            FEATURE_TERMINAL
+        } else if self.port_forward_socket.is_some() {
+            FEATURE_PORT_FORWARD
+        } else {
+            return true;
+        };
+        if is_feature_allowed(feature, self.policy_peer_id()) {
+            return true;
+        }
+        log::info!(
+            "#{} {} is revoked for peer {}",
+            self.inner.id(),
+            feature,
+            self.lr.my_id
+        );
+        self.post_feature_denied_audit(feature);
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if self.terminal {
+            // Persistent terminals must not survive the revocation either.
+            self.update_terminal_persistence(false).await;
+            terminal_service::TerminalServiceProxy::new(
+                self.terminal_service_id.clone(),
+                Some(false),
+            )
+            .on_disconnect();
+        }
+        self.send_close_reason_no_retry(ErrorCode::FeatureNotAllowed.with_detail(feature)).await;
+        self.on_close("feature revoked", false).await;
+        false
+    }
+
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     async fn update_terminal_persistence(&mut self, persistent: bool) {
         self.terminal_persistent = persistent;
@@ -3842,6 +6314,9 @@ impl Connection {
         ));
         s.on_subscribe(self.inner.clone());
         self.terminal_generic_service = Some(s);
+        if crate::platform::is_root() {
+            self.alert_sensitive_event(sensitive_events::Event::ElevatedTerminal);
+        }
     }
 
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -3875,6 +6350,61 @@ impl Connection {
     }
 }
 
+// The error and the alarm if the login failures (minute, count in the minute, total) of an ip
+// ban it, for the checks before and after the handshake.
+fn failure_ban(failure: (i32, i32, i32), time: i32) -> Option<(ErrorCode, AlarmAuditType)> {
+    if failure.2 > 30 {
+        Some((
+            ErrorCode::TooManyAttempts,
+            AlarmAuditType::ExceedThirtyAttempts,
+        ))
+    } else if time == failure.0 && failure.1 > 6 {
+        Some((
+            ErrorCode::TryLater,
+            AlarmAuditType::SixAttemptsWithinOneMinute,
+        ))
+    } else {
+        None
+    }
+}
+
+// The minute of the login failures by the session clock, 0 is no failure.
+#[inline]
+fn failure_minute() -> i32 {
+    failure_minute_at(session_clock::now_ms())
+}
+
+// The minute of the login failures at the milliseconds of a clock, 0 is no failure.
+#[inline]
+fn failure_minute_at(ms: i64) -> i32 {
+    (ms / 60_000) as i32 + 1
+}
+
+// The timers of the connections below go by the session clock, see `session_clock`.
+//
+// The pending approval and the confirmations of the sensitive events.
+#[inline]
+fn deadline_passed(deadline: Option<Instant>) -> bool {
+    deadline.map_or(false, |d| session_clock::instant() >= d)
+}
+
+// (last input, minutes) of "auto-disconnect-timeout".
+#[inline]
+fn idle_timed_out(timer: &(Instant, u64)) -> bool {
+    session_clock::elapsed(timer.0).as_secs() > timer.1 * 60
+}
+
+#[inline]
+fn heartbeat_timed_out(last_recv_time: Instant) -> bool {
+    session_clock::elapsed(last_recv_time) >= SEC30
+}
+
+// The grace period of a session after its last message, for the reconnection.
+#[inline]
+fn session_alive(last_recv_time: Instant) -> bool {
+    session_clock::elapsed(last_recv_time) < SESSION_TIMEOUT
+}
+
 pub fn insert_switch_sides_uuid(id: String, uuid: uuid::Uuid) {
     SWITCH_SIDES_UUID
         .lock()
@@ -4040,6 +6570,11 @@ pub enum AlarmAuditType {
     IpWhitelist = 0,
     ExceedThirtyAttempts = 1,
     SixAttemptsWithinOneMinute = 2,
+    FeatureNotAllowed = 3,
+    // The Windows service restarted the server after a crash, see `server_watchdog`.
+    ServerRestarted = 4,
+    // The user of the host terminated all the sessions, see `panic_button`.
+    SessionsTerminated = 5,
 }
 
 pub enum FileAuditType {
@@ -4208,17 +6743,215 @@ fn start_wakelock_thread() -> std::sync::mpsc::Sender<(usize, usize)> {
     tx
 }
 
+pub const FEATURE_TERMINAL: &str = "terminal";
+pub const FEATURE_PORT_FORWARD: &str = "port-forward";
+pub const FEATURE_PRINTER: &str = "printer";
+pub const ALLOWLIST_FEATURES: [&str; 3] = [FEATURE_TERMINAL, FEATURE_PORT_FORWARD, FEATURE_PRINTER];
+
+#[inline]
+pub fn feature_allowlist_option(feature: &str) -> String {
+    format!("{}-allowed-ids", feature)
+}
+
+pub fn parse_id_list(ids: &str) -> Vec<String> {
+    ids.split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_owned())
+        .collect()
+}
+
+/// A feature without an allowlist option is only restricted by the general permissions.
+/// A set allowlist only lets in the trusted ids it lists, so one without any id denies everyone.
+pub fn is_feature_allowed(feature: &str, id: Option<&str>) -> bool {
+    let option = Config::get_option(&feature_allowlist_option(feature));
+    if option.is_empty() {
+        return true;
+    }
+    let Some(id) = id else {
+        return false;
+    };
+    parse_id_list(&option).iter().any(|x| x == id)
+}
+
+pub fn get_feature_allowlists() -> Vec<String> {
+    ALLOWLIST_FEATURES
+        .iter()
+        .map(|f| Config::get_option(&feature_allowlist_option(f)))
+        .collect()
+}
+
+// Let the authorized connections re-check their features against the new allowlists.
+pub fn on_feature_allowlists_changed() {
+    for c in AUTHED_CONNS.lock().unwrap().iter() {
+        c.sender.send(Data::FeatureAllowlistChanged).ok();
+    }
+}
+
+fn max_pending_auth_conns() -> usize {
+    Config::get_option(OPTION_MAX_PENDING_AUTH_CONNS)
+        .parse()
+        .unwrap_or(DEFAULT_MAX_PENDING_AUTH_CONNS)
+}
+
+fn pending_auth_timeout() -> Option<Duration> {
+    let secs = Config::get_option(OPTION_PENDING_AUTH_TIMEOUT)
+        .parse()
+        .unwrap_or(DEFAULT_PENDING_AUTH_TIMEOUT);
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// A connection not authorized yet, from the accept to the login, it's removed from the pending
+/// set when dropped.
+pub struct PendingAuthID(i32);
+
+impl PendingAuthID {
+    /// None if there are too many pending connections.
+    pub fn try_new(id: i32, ip: String) -> Option<Self> {
+        let max = max_pending_auth_conns();
+        let mut lock = PENDING_AUTH_CONNS.lock().unwrap();
+        if max > 0 && lock.len() >= max {
+            return None;
+        }
+        lock.insert(id, (Instant::now(), ip));
+        Some(Self(id))
+    }
+
+    /// The TLS handshake of the direct access port, before the connection has an id, so the
+    /// ids of the handshakes are negative.
+    pub fn try_new_handshake(ip: String) -> Option<Self> {
+        static NEXT_ID: AtomicI32 = AtomicI32::new(-1);
+        Self::try_new(NEXT_ID.fetch_sub(1, Ordering::Relaxed), ip)
+    }
+}
+
+impl Drop for PendingAuthID {
+    fn drop(&mut self) {
+        PENDING_AUTH_CONNS.lock().unwrap().remove(&self.0);
+    }
+}
+
+pub fn pending_auth_count() -> usize {
+    PENDING_AUTH_CONNS.lock().unwrap().len()
+}
+
+/// The pending connections in json for the diagnostics.
+pub fn pending_auth_status() -> String {
+    let conns: Vec<Value> = PENDING_AUTH_CONNS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(id, (since, ip))| json!({"id": id, "ip": ip, "secs": since.elapsed().as_secs()}))
+        .collect();
+    json!({
+        "count": conns.len(),
+        "max": max_pending_auth_conns(),
+        "conns": conns,
+    })
+    .to_string()
+}
+
+// The peer proves the new stream is its own by sending the uuid on it encrypted by the key, both
+// of which only went over the current encrypted connection.
+pub(super) async fn accept_relay_migration(
+    stream: &mut super::Stream,
+    uuid: &str,
+    key: hbb_common::sodiumoxide::crypto::secretbox::Key,
+) -> ResultType<()> {
+    stream.set_key(key);
+    let Some(res) = timeout(CONNECT_TIMEOUT, stream.next()).await? else {
+        bail!("Reset by the peer");
+    };
+    let msg_in = Message::parse_from_bytes(&res?)?;
+    match msg_in.misc().relay_migration().uuid.as_str() {
+        u if !u.is_empty() && u == uuid => Ok(()),
+        _ => bail!("Invalid relay migration from the peer"),
+    }
+}
+
+// Start moving the remote connection to another relay, see `Connection::migrate_relay`.
+// False if there's no such remote connection.
+pub fn migrate_relay(conn_id: i32, relay_server: String) -> bool {
+    let conns = AUTHED_CONNS.lock().unwrap();
+    let Some(c) = conns
+        .iter()
+        .find(|c| c.conn_id == conn_id && c.conn_type == AuthConnType::Remote)
+    else {
+        return false;
+    };
+    c.sender
+        .send(Data::MigrateRelay {
+            conn_id,
+            relay_server,
+            found: None,
+        })
+        .is_ok()
+}
+
+/// Mute or unmute the voice call of the connection by the push-to-talk key of the host.
+pub fn set_push_to_talk(conn_id: i32, on: bool) {
+    if let Some(c) = AUTHED_CONNS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|c| c.conn_id == conn_id)
+    {
+        c.sender.send(Data::PushToTalk(on)).ok();
+    }
+}
+
+/// Offer the waiting voice call of the connection to the user of the host, returns false if the
+/// connection is gone.
+pub fn offer_voice_call(conn_id: i32) -> bool {
+    AUTHED_CONNS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|c| c.conn_id == conn_id)
+        .map_or(false, |c| c.sender.send(Data::VoiceCallIncoming).is_ok())
+}
+
+// Let the controlling connections know who holds the control token now.
+pub fn on_control_token_changed() {
+    for c in AUTHED_CONNS.lock().unwrap().iter() {
+        if c.conn_type == AuthConnType::Remote {
+            c.sender.send(Data::ControlTokenChanged).ok();
+        }
+    }
+}
+
+// The job is spooled first, it is sent when a connection with the printer permission is available.
 #[cfg(all(target_os = "windows", feature = "flutter"))]
 pub fn on_printer_data(data: Vec<u8>) {
-    crate::server::AUTHED_CONNS
+    let peer_id = AUTHED_CONNS
         .lock()
         .unwrap()
+        .iter()
+        .find(|c| c.printer)
+        .map(|c| c.peer_id.clone())
+        .unwrap_or_default();
+    if let Err(e) = printer_queue::push(&data, &peer_id) {
+        log::error!("Failed to spool the printer job: {}", e);
+        return;
+    }
+    dispatch_printer_jobs();
+}
+
+#[cfg(all(target_os = "windows", feature = "flutter"))]
+pub fn dispatch_printer_jobs() {
+    let conns = AUTHED_CONNS.lock().unwrap();
+    let peers: Vec<_> = conns
         .iter()
         .filter(|c| c.printer)
-        .next()
-        .map(|c| {
-            c.sender.send(Data::PrinterData(data)).ok();
-        });
+        .map(|c| (c.conn_id, c.peer_id.clone()))
+        .collect();
+    if peers.is_empty() {
+        return;
+    }
+    while let Some((job_id, conn_id)) = printer_queue::take_next(&peers) {
+        if let Some(c) = conns.iter().find(|c| c.conn_id == conn_id) {
+            c.sender.send(Data::PrinterJob(job_id)).ok();
+        }
+    }
 }
 
 #[cfg(windows)]
@@ -4227,6 +6960,8 @@ pub struct PortableState {
     pub last_foreground_window_elevated: bool,
     pub last_running: Option<bool>,
     pub is_installed: bool,
+    // The helper is being started with the consent for this peer.
+    pub elevation_requested: bool,
 }
 
 #[cfg(windows)]
@@ -4237,6 +6972,7 @@ impl Default for PortableState {
             last_uac: Default::default(),
             last_foreground_window_elevated: Default::default(),
             last_running: Default::default(),
+            elevation_requested: false,
         }
     }
 }
@@ -4275,7 +7011,7 @@ impl LinuxHeadlessHandle {
         }
     }
 
-    pub fn try_start_desktop(&mut self, os_login: Option<&OSLogin>) -> String {
+    pub fn try_start_desktop(&mut self, os_login: Option<&OSLogin>) -> Option<ErrorCode> {
         if self.is_headless_allowed {
             match os_login {
                 Some(os_login) => {
@@ -4284,7 +7020,7 @@ impl LinuxHeadlessHandle {
                 None => linux_desktop_manager::try_start_desktop("", ""),
             }
         } else {
-            "".to_string()
+            None
         }
     }
 
@@ -4364,6 +7100,8 @@ pub struct AuthedConn {
     pub session_key: SessionKey,
     pub sender: mpsc::UnboundedSender<Data>,
     pub printer: bool,
+    pub peer_id: String,
+    pub encrypted: bool,
 }
 
 mod raii {
@@ -4382,8 +7120,17 @@ mod raii {
 
     impl Drop for ConnectionID {
         fn drop(&mut self) {
+            // Before the lock, the other connections are notified if it held the token.
+            crate::server::input_control::remove(self.0);
             let mut active_conns_lock = ALIVE_CONNS.lock().unwrap();
             active_conns_lock.retain(|&c| c != self.0);
+            crate::server::network_sim::remove(self.0);
+            crate::server::clipboard_policy::remove(self.0);
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            crate::server::scroll_gesture::remove(self.0);
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            crate::server::diagnostic_capture::remove(self.0);
+            crate::server::session_report::remove_frame_drop_accounting(self.0);
         }
     }
 
@@ -4396,9 +7143,13 @@ mod raii {
             session_key: SessionKey,
             sender: mpsc::UnboundedSender<Data>,
             lr: LoginRequest,
+            trusted_peer_id: Option<&str>,
+            encrypted: bool,
         ) -> Self {
             let printer = conn_type == crate::server::AuthConnType::Remote
-                && crate::is_support_remote_print(&lr.version)
+                && Capabilities::negotiate(lr.capabilities, &lr.version)
+                    .supports(Capability::RemotePrint)
+                && is_feature_allowed(FEATURE_PRINTER, trusted_peer_id)
                 && lr.my_platform == hbb_common::whoami::Platform::Windows.to_string();
             AUTHED_CONNS.lock().unwrap().push(AuthedConn {
                 conn_id,
@@ -4406,7 +7157,14 @@ mod raii {
                 session_key,
                 sender,
                 printer,
+                peer_id: lr.my_id.clone(),
+                encrypted,
             });
+            #[cfg(all(target_os = "windows", feature = "flutter"))]
+            if printer {
+                printer_queue::on_peer_connected(&lr.my_id);
+                dispatch_printer_jobs();
+            }
             Self::check_wake_lock();
             use std::sync::Once;
             static _ONCE: Once = Once::new();
@@ -4494,7 +7252,8 @@ mod raii {
                     Session {
                         random_password: password.unwrap_or_default(),
                         tfa: tfa.unwrap_or_default(),
-                        last_recv_time: Arc::new(Mutex::new(Instant::now())),
+                        last_recv_time: Arc::new(Mutex::new(session_clock::instant())),
+                        access_rule: None,
                     },
                 );
             }
@@ -4509,14 +7268,22 @@ mod raii {
                 lock.insert(
                     key,
                     Session {
-                        last_recv_time: Arc::new(Mutex::new(Instant::now())),
+                        last_recv_time: Arc::new(Mutex::new(session_clock::instant())),
                         random_password: "".to_owned(),
                         tfa: true,
+                        access_rule: None,
                     },
                 );
             }
         }
 
+        // Only a session of the temporary password is recent, see `is_recent_session`.
+        pub fn set_session_access_rule(key: SessionKey, rule: access_policy::ConnType) {
+            if let Some(session) = SESSIONS.lock().unwrap().get_mut(&key) {
+                session.access_rule = Some(rule);
+            }
+        }
+
         pub fn conn_type(&self) -> AuthConnType {
             self.1
         }
@@ -4530,8 +7297,15 @@ mod raii {
                     .lock()
                     .unwrap()
                     .on_connection_close(self.0);
+                display_service::set_capture_sync(self.0, false);
             }
+            audio_service::set_low_delay_candidate(self.0, false);
             AUTHED_CONNS.lock().unwrap().retain(|c| c.conn_id != self.0);
+            #[cfg(all(target_os = "windows", feature = "flutter"))]
+            {
+                printer_queue::on_conn_closed(self.0);
+                dispatch_printer_jobs();
+            }
             let remote_count = AUTHED_CONNS
                 .lock()
                 .unwrap()
@@ -4545,6 +7319,8 @@ mod raii {
                 }
                 #[cfg(not(any(target_os = "android", target_os = "ios")))]
                 display_service::restore_resolutions();
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                text_scale::restore();
                 #[cfg(windows)]
                 let _ = virtual_display_manager::reset_all();
                 #[cfg(target_os = "linux")]
@@ -4590,4 +7366,53 @@ mod test {
         assert_eq!(pos.x, 510);
         assert_eq!(pos.y, 510);
     }
+
+    #[test]
+    fn test_login_failures_clock_jump() {
+        let ip = "192.0.2.1".to_owned();
+        let banned = Some(ErrorCode::TryLater);
+        let wall_minute = || failure_minute_at(session_clock::wall_ms());
+        let by_wall_clock = (wall_minute(), 7, 7);
+        LOGIN_FAILURES[0]
+            .lock()
+            .unwrap()
+            .insert(ip.clone(), (failure_minute(), 7, 7));
+        assert_eq!(Connection::check_banned(&ip), banned);
+        assert!(failure_ban(by_wall_clock, wall_minute()).is_some());
+        // The wall clock goes an hour forward, the window of a minute is not over, it was by the
+        // wall clock.
+        session_clock::jump_wall_clock(session_clock::HOUR_MS);
+        assert_eq!(Connection::check_banned(&ip), banned);
+        assert!(failure_ban(by_wall_clock, wall_minute()).is_none());
+        session_clock::jump_wall_clock(-session_clock::HOUR_MS);
+        session_clock::advance(Duration::from_secs(60));
+        assert_eq!(Connection::check_banned(&ip), None);
+        LOGIN_FAILURES[0].lock().unwrap().remove(&ip);
+    }
+
+    // The jumps of the wall clock either way don't fire the timers, the time going on does.
+    #[test]
+    fn test_timers_clock_jump() {
+        let start = session_clock::instant();
+        let deadline = Some(start + Duration::from_secs(60));
+        let idle = (start, 10);
+        let fired = || {
+            [
+                deadline_passed(deadline),
+                idle_timed_out(&idle),
+                heartbeat_timed_out(start),
+                !session_alive(start),
+            ]
+        };
+        for ms in [session_clock::HOUR_MS, -2 * session_clock::HOUR_MS] {
+            session_clock::jump_wall_clock(ms);
+            assert_eq!(fired(), [false; 4]);
+        }
+        session_clock::advance(SEC30);
+        assert_eq!(fired(), [false, false, true, true]);
+        session_clock::advance(Duration::from_secs(30));
+        assert_eq!(fired(), [true, false, true, true]);
+        session_clock::advance(Duration::from_secs(10 * 60));
+        assert_eq!(fired(), [true; 4]);
+    }
 }
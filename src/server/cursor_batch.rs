@@ -0,0 +1,127 @@
+// The cursor positions of a relayed or slow connection are sent in batches.
+//
+// The position stream is otherwise thousands of tiny segments, which some relays and mobile
+// networks handle badly and turn into jitter. A batch is sent when it has `MAX_POSITIONS`
+// positions or its first one is `MAX_DELAY` old. It is one `CursorPosition` of the latest
+// position, the earlier ones are in `earlier` with how long before the latest one they were, and
+// the client replays them. An old client ignores `earlier` and jumps to the latest position.
+//
+// A direct connection with a low round trip time keeps a message for each position.
+
+use hbb_common::{
+    message_proto::*,
+    tokio::time::{Duration, Instant},
+};
+
+pub const MAX_POSITIONS: usize = 4;
+pub const MAX_DELAY: Duration = Duration::from_millis(32);
+// A direct connection batches too if the round trip time is above it.
+const RTT_THRESHOLD_MS: u32 = 80;
+
+#[derive(Default)]
+pub struct CursorBatch {
+    enabled: bool,
+    positions: Vec<(Instant, CursorPosition)>,
+}
+
+impl CursorBatch {
+    /// Decide whether to batch, returns true if the decision changes.
+    /// The positions already held are still sent at their deadline.
+    pub fn update(&mut self, relay: bool, rtt_ms: u32) -> bool {
+        let enabled = relay || rtt_ms > RTT_THRESHOLD_MS;
+        let changed = enabled != self.enabled;
+        self.enabled = enabled;
+        changed
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Hold the message if it is a cursor position to batch, returns true if it is held.
+    pub fn hold(&mut self, msg: &Message) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let Some(message::Union::CursorPosition(pos)) = &msg.union else {
+            return false;
+        };
+        self.positions.push((Instant::now(), pos.clone()));
+        true
+    }
+
+    /// When the held positions must be sent.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.positions.first().map(|(t, _)| *t + MAX_DELAY)
+    }
+
+    /// The batch if it is full or due.
+    pub fn take_ready(&mut self) -> Option<Message> {
+        let due = self.deadline().map_or(false, |d| Instant::now() >= d);
+        if self.positions.len() >= MAX_POSITIONS || due {
+            self.take()
+        } else {
+            None
+        }
+    }
+
+    /// The batch of all the held positions, the number of them is its `earlier` plus one.
+    pub fn take(&mut self) -> Option<Message> {
+        let (latest_time, mut latest) = self.positions.pop()?;
+        latest.earlier = self
+            .positions
+            .drain(..)
+            .map(|(t, mut pos)| {
+                pos.ms_before = latest_time.duration_since(t).as_millis() as _;
+                pos
+            })
+            .collect();
+        let mut msg = Message::new();
+        msg.set_cursor_position(latest);
+        Some(msg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn position(x: i32) -> Message {
+        let mut msg = Message::new();
+        msg.set_cursor_position(CursorPosition {
+            x,
+            ..Default::default()
+        });
+        msg
+    }
+
+    #[test]
+    fn test_cursor_batch() {
+        let mut batch = CursorBatch::default();
+        assert!(!batch.update(false, 10));
+        assert!(!batch.hold(&position(0)));
+        assert!(batch.update(true, 10));
+        assert!(!batch.hold(&Message::new()));
+        for x in 0..MAX_POSITIONS as i32 - 1 {
+            assert!(batch.hold(&position(x)));
+            assert!(batch.take_ready().is_none());
+        }
+        assert!(batch.hold(&position(3)));
+        let msg = batch.take_ready().unwrap();
+        let pos = msg.cursor_position();
+        assert_eq!(pos.x, 3);
+        let earlier: Vec<i32> = pos.earlier.iter().map(|p| p.x).collect();
+        assert_eq!(earlier, vec![0, 1, 2]);
+        assert!(pos
+            .earlier
+            .windows(2)
+            .all(|w| w[0].ms_before >= w[1].ms_before));
+        assert!(batch.deadline().is_none());
+        assert!(batch.take().is_none());
+
+        // A high round trip time of a direct connection.
+        assert!(!batch.update(false, RTT_THRESHOLD_MS + 1));
+        assert!(batch.update(false, RTT_THRESHOLD_MS));
+    }
+}
@@ -5,10 +5,18 @@
 /// [Flutter]: handle uni links for linux
 use dbus::blocking::Connection;
 use dbus_crossroads::{Crossroads, IfaceBuilder};
-use hbb_common::log;
+use hbb_common::{config::Config, log};
 #[cfg(feature = "flutter")]
 use std::collections::HashMap;
-use std::{error::Error, fmt, time::Duration};
+use std::{
+    error::Error,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
 
 const DBUS_NAME: &str = "org.rustdesk.rustdesk";
 const DBUS_PREFIX: &str = "/dbus";
@@ -90,3 +98,155 @@ fn handle_client_message(builder: &mut IfaceBuilder<()>) {
         },
     );
 }
+
+// Inhibitors held while there are sessions with video, so the host doesn't suspend, blank or lock
+// in the middle of a session.
+//
+// "linux-inhibit": "sleep" (default) takes the logind sleep inhibitor, "all" takes the logind
+// sleep and idle inhibitors and the screensaver one of the desktop, "none" takes nothing.
+// The screensaver inhibitor is bound to the session bus connection, which is kept until release.
+const OPTION_LINUX_INHIBIT: &str = "linux-inhibit";
+const LOGIND_NAME: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const LOGIND_MANAGER: &str = "org.freedesktop.login1.Manager";
+const SCREENSAVER_NAME: &str = "org.freedesktop.ScreenSaver";
+const SCREENSAVER_PATH: &str = "/org/freedesktop/ScreenSaver";
+const INHIBIT_REASON: &str = "Remote session is active";
+
+lazy_static::lazy_static! {
+    static ref INHIBITORS: Mutex<Option<Inhibitors>> = Default::default();
+}
+static INHIBIT_WANTED: AtomicBool = AtomicBool::new(false);
+static LOGIND_WARNED: AtomicBool = AtomicBool::new(false);
+static SCREENSAVER_WARNED: AtomicBool = AtomicBool::new(false);
+
+struct Inhibitors {
+    what: String,
+    // The inhibitor is released when the fd is closed.
+    logind: Option<dbus::arg::OwnedFd>,
+    screensaver: Option<(Connection, u32)>,
+}
+
+impl Drop for Inhibitors {
+    fn drop(&mut self) {
+        if let Some((conn, cookie)) = self.screensaver.take() {
+            let proxy = conn.with_proxy(SCREENSAVER_NAME, SCREENSAVER_PATH, DBUS_TIMEOUT);
+            let res: Result<(), dbus::Error> =
+                proxy.method_call(SCREENSAVER_NAME, "UnInhibit", (cookie,));
+            if let Err(e) = res {
+                log::warn!("Failed to release the screensaver inhibitor: {}", e);
+            }
+        }
+        log::info!("Inhibitors released: {}", self.what);
+    }
+}
+
+fn inhibit_mode() -> String {
+    let mode = Config::get_option(OPTION_LINUX_INHIBIT);
+    if mode.is_empty() {
+        "sleep".to_owned()
+    } else {
+        mode
+    }
+}
+
+// Only the first failure is logged as a warning, the session bus is often unreachable from the
+// service and there's nothing the user can do with a warning per session.
+fn warn_once(warned: &AtomicBool, what: &str, e: impl fmt::Display) {
+    if !warned.swap(true, Ordering::SeqCst) {
+        log::warn!("Failed to take the {} inhibitor: {}", what, e);
+    } else {
+        log::debug!("Failed to take the {} inhibitor: {}", what, e);
+    }
+}
+
+fn take_logind_inhibitor(what: &str) -> Result<dbus::arg::OwnedFd, dbus::Error> {
+    let conn = Connection::new_system()?;
+    let proxy = conn.with_proxy(LOGIND_NAME, LOGIND_PATH, DBUS_TIMEOUT);
+    let (fd,): (dbus::arg::OwnedFd,) = proxy.method_call(
+        LOGIND_MANAGER,
+        "Inhibit",
+        (what, crate::get_app_name(), INHIBIT_REASON, "block"),
+    )?;
+    Ok(fd)
+}
+
+fn take_screensaver_inhibitor() -> Result<(Connection, u32), dbus::Error> {
+    let conn = Connection::new_session()?;
+    let proxy = conn.with_proxy(SCREENSAVER_NAME, SCREENSAVER_PATH, DBUS_TIMEOUT);
+    let (cookie,): (u32,) = proxy.method_call(
+        SCREENSAVER_NAME,
+        "Inhibit",
+        (crate::get_app_name(), INHIBIT_REASON),
+    )?;
+    Ok((conn, cookie))
+}
+
+fn take_inhibitors(mode: &str) -> Option<Inhibitors> {
+    let (what, screensaver) = match mode {
+        "none" => return None,
+        "all" => ("sleep:idle", true),
+        _ => ("sleep", false),
+    };
+    let logind = match take_logind_inhibitor(what) {
+        Ok(fd) => Some(fd),
+        Err(e) => {
+            warn_once(&LOGIND_WARNED, "logind", e);
+            None
+        }
+    };
+    let screensaver = if screensaver {
+        match take_screensaver_inhibitor() {
+            Ok(v) => Some(v),
+            Err(e) => {
+                warn_once(&SCREENSAVER_WARNED, "screensaver", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut taken = vec![];
+    if logind.is_some() {
+        taken.push(format!("logind({})", what));
+    }
+    if screensaver.is_some() {
+        taken.push("screensaver".to_owned());
+    }
+    if taken.is_empty() {
+        return None;
+    }
+    let what = taken.join(", ");
+    log::info!("Inhibitors taken: {}", what);
+    Some(Inhibitors {
+        what,
+        logind,
+        screensaver,
+    })
+}
+
+/// Take or release the inhibitors, the dbus calls are made in a thread, not to block the caller.
+pub fn set_session_inhibit(on: bool) {
+    if INHIBIT_WANTED.swap(on, Ordering::SeqCst) == on {
+        return;
+    }
+    std::thread::spawn(|| {
+        let mut lock = INHIBITORS.lock().unwrap();
+        let on = INHIBIT_WANTED.load(Ordering::SeqCst);
+        if on && lock.is_none() {
+            *lock = take_inhibitors(&inhibit_mode());
+        } else if !on {
+            lock.take();
+        }
+    });
+}
+
+/// The active inhibitors, empty if there's none.
+pub fn get_active_inhibitors() -> String {
+    INHIBITORS
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|i| i.what.clone())
+        .unwrap_or_default()
+}
@@ -0,0 +1,271 @@
+// Paged listing of the directories, so a directory with a huge number of files doesn't make a
+// giant message or stall the file manager.
+//
+// The peer asks for one page at a time with the token of the previous page, every page carries
+// the total, so the peer can show the progress. The directory is read, filtered and sorted once,
+// the result is kept for a while for the next pages, and read again if it has expired, so a
+// listing can be resumed. A cancelled listing stops reading the directory at the next entry.
+// The peers without `Capability::PagedDirListing` get the whole directory in one message.
+
+use hbb_common::{
+    bail,
+    config::Config,
+    fs::{self, get_string},
+    log,
+    message_proto::*,
+    ResultType,
+};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_PAGE_SIZE: usize = 1000;
+pub const MAX_PAGE_SIZE: usize = 10_000;
+const CACHE_TTL: Duration = Duration::from_secs(60);
+const MAX_CACHED: usize = 8;
+
+type Key = (i32, i32); // (conn id, listing id)
+
+lazy_static::lazy_static! {
+    static ref LISTINGS: Mutex<HashMap<Key, Listing>> = Default::default();
+    static ref CANCELS: Mutex<HashMap<Key, Arc<AtomicBool>>> = Default::default();
+}
+
+struct Listing {
+    path: String,
+    include_hidden: bool,
+    filter: String,
+    entries: Arc<Vec<FileEntry>>,
+    last_used: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct PageRequest {
+    pub conn_id: i32,
+    pub id: i32,
+    pub dir: String,
+    pub include_hidden: bool,
+    pub page_size: usize,
+    // Glob of the names, e.g. "*.log", empty for all.
+    pub filter: String,
+    // The token of the previous page, empty for the first page.
+    pub token: String,
+}
+
+/// Stop the listing, the reading of the directory is stopped at the next entry.
+pub fn cancel(conn_id: i32, id: i32) {
+    if let Some(cancel) = CANCELS.lock().unwrap().remove(&(conn_id, id)) {
+        cancel.store(true, Ordering::SeqCst);
+    }
+    LISTINGS.lock().unwrap().remove(&(conn_id, id));
+}
+
+/// Read one page of the directory, it blocks on the first page.
+/// None if the listing is cancelled.
+pub fn read_page(req: &PageRequest) -> ResultType<Option<FileDirectory>> {
+    let path = if req.dir.is_empty() {
+        Config::get_home()
+    } else {
+        fs::get_path(&req.dir)
+    };
+    let path_str = get_string(&path);
+    let key = (req.conn_id, req.id);
+    let offset = req.token.parse::<usize>().unwrap_or(0);
+    let cached = if req.token.is_empty() {
+        None
+    } else {
+        LISTINGS.lock().unwrap().get_mut(&key).and_then(|l| {
+            if l.path == path_str
+                && l.include_hidden == req.include_hidden
+                && l.filter == req.filter
+            {
+                l.last_used = Instant::now();
+                Some(l.entries.clone())
+            } else {
+                None
+            }
+        })
+    };
+    let entries = match cached {
+        Some(entries) => entries,
+        None => {
+            let cancel = Arc::new(AtomicBool::new(false));
+            CANCELS.lock().unwrap().insert(key, cancel.clone());
+            let res = list(&path, req.include_hidden, &req.filter, &cancel);
+            CANCELS.lock().unwrap().remove(&key);
+            let Some(entries) = res? else {
+                log::info!("Listing {} of {} is cancelled", req.id, path_str);
+                return Ok(None);
+            };
+            let entries = Arc::new(entries);
+            let mut listings = LISTINGS.lock().unwrap();
+            listings.retain(|_, l| l.last_used.elapsed() < CACHE_TTL);
+            if listings.len() >= MAX_CACHED {
+                if let Some(oldest) = listings
+                    .iter()
+                    .min_by_key(|(_, l)| l.last_used)
+                    .map(|(k, _)| *k)
+                {
+                    listings.remove(&oldest);
+                }
+            }
+            listings.insert(
+                key,
+                Listing {
+                    path: path_str.clone(),
+                    include_hidden: req.include_hidden,
+                    filter: req.filter.clone(),
+                    entries: entries.clone(),
+                    last_used: Instant::now(),
+                },
+            );
+            entries
+        }
+    };
+    let page_size = if req.page_size == 0 {
+        DEFAULT_PAGE_SIZE
+    } else {
+        req.page_size.min(MAX_PAGE_SIZE)
+    };
+    let offset = offset.min(entries.len());
+    let end = (offset + page_size).min(entries.len());
+    let next_token = if end < entries.len() {
+        end.to_string()
+    } else {
+        LISTINGS.lock().unwrap().remove(&key);
+        "".to_owned()
+    };
+    Ok(Some(FileDirectory {
+        id: req.id,
+        path: path_str,
+        entries: entries[offset..end].to_vec(),
+        total: entries.len() as _,
+        offset: offset as _,
+        next_token,
+        ..Default::default()
+    }))
+}
+
+// Read the directory like `fs::read_dir`, filtered and sorted, directories first.
+// None if it is cancelled.
+fn list(
+    path: &Path,
+    include_hidden: bool,
+    filter: &str,
+    cancel: &AtomicBool,
+) -> ResultType<Option<Vec<FileEntry>>> {
+    #[cfg(windows)]
+    if get_string(path) == "/" {
+        // The drives.
+        return Ok(Some(fs::read_dir(path, include_hidden)?.entries));
+    }
+    if !path.is_dir() {
+        bail!("{} is not a directory", get_string(path));
+    }
+    let mut entries = Vec::new();
+    for entry in path.read_dir()?.flatten() {
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let p = entry.path();
+        let name = p
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if name.is_empty() || (!filter.is_empty() && !glob_match(filter, &name)) {
+            continue;
+        }
+        let Ok(meta) = std::fs::symlink_metadata(&p) else {
+            continue;
+        };
+        #[cfg(windows)]
+        let is_hidden = {
+            use std::os::windows::fs::MetadataExt;
+            meta.file_attributes() & winapi::um::winnt::FILE_ATTRIBUTE_HIDDEN != 0
+        };
+        #[cfg(not(windows))]
+        let is_hidden = name.starts_with('.');
+        if is_hidden && !include_hidden {
+            continue;
+        }
+        let is_link = meta.file_type().is_symlink();
+        let (entry_type, size) = if p.is_dir() {
+            if is_link {
+                (FileType::DirLink, 0)
+            } else {
+                (FileType::Dir, 0)
+            }
+        } else if is_link {
+            (FileType::FileLink, 0)
+        } else {
+            (FileType::File, meta.len())
+        };
+        let modified_time = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        entries.push(FileEntry {
+            name,
+            entry_type: entry_type.into(),
+            is_hidden,
+            size,
+            modified_time,
+            ..Default::default()
+        });
+    }
+    let is_dir = |e: &FileEntry| {
+        matches!(
+            e.entry_type.enum_value(),
+            Ok(FileType::Dir) | Ok(FileType::DirLink) | Ok(FileType::DirDrive)
+        )
+    };
+    entries.sort_by_cached_key(|e| (!is_dir(e), e.name.to_lowercase()));
+    Ok(Some(entries))
+}
+
+// Case insensitive glob with `*` and `?`.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let n: Vec<char> = name.to_lowercase().chars().collect();
+    let (mut i, mut j) = (0, 0);
+    // The position after the last `*` and the name position it matched up to.
+    let mut star: Option<(usize, usize)> = None;
+    while j < n.len() {
+        if i < p.len() && (p[i] == '?' || p[i] == n[j]) {
+            i += 1;
+            j += 1;
+        } else if i < p.len() && p[i] == '*' {
+            star = Some((i + 1, j));
+            i += 1;
+        } else if let Some((si, sj)) = star {
+            i = si;
+            j = sj + 1;
+            star = Some((si, sj + 1));
+        } else {
+            return false;
+        }
+    }
+    p[i..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.LOG", "a.log"));
+        assert!(glob_match("a?c*", "abcdef"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("*.log", "a.txt"));
+        assert!(!glob_match("a?c", "ac"));
+    }
+}
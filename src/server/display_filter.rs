@@ -0,0 +1,198 @@
+// The monitors a connection is allowed to see.
+//
+// "allowed-displays" maps the peer ids to the indexes of the monitors they may see, e.g.
+// `{"123456789": [0], "*": [0, 1]}`, "*" is for the peers not in the map. The peers without an
+// entry see all the monitors. A peer id entry only applies to a peer which passed the 2FA of the
+// host, the id a peer claims is not trusted otherwise, and the peer gets the "*" entry.
+//
+// It is a hard boundary. The server refuses the subscription of the other monitors whatever the
+// peer asks, and the peer is only told about the allowed monitors. The peer knows them by their
+// position in the allowed list, so the display indexes are translated in both directions.
+
+use hbb_common::{config::Config, log, message_proto::*};
+use std::{collections::HashMap, sync::Arc};
+
+const OPTION_ALLOWED_DISPLAYS: &str = "allowed-displays";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayFilter {
+    // The indexes of the allowed monitors on the host, sorted.
+    allowed: Vec<usize>,
+}
+
+impl DisplayFilter {
+    pub fn from_config(peer_id: Option<&str>) -> Option<Self> {
+        let option = Config::get_option(OPTION_ALLOWED_DISPLAYS);
+        if option.is_empty() {
+            return None;
+        }
+        let map = match serde_json::from_str::<HashMap<String, Vec<usize>>>(&option) {
+            Ok(map) => map,
+            Err(e) => {
+                // Fail closed, a broken boundary must not show everything.
+                log::error!(
+                    "Invalid {}: {}, only the first monitor is allowed",
+                    OPTION_ALLOWED_DISPLAYS,
+                    e
+                );
+                return Some(Self::new(vec![0]));
+            }
+        };
+        peer_id
+            .and_then(|id| map.get(id))
+            .or_else(|| map.get("*"))
+            .map(|allowed| Self::new(allowed.clone()))
+    }
+
+    fn new(mut allowed: Vec<usize>) -> Self {
+        allowed.sort_unstable();
+        allowed.dedup();
+        Self { allowed }
+    }
+
+    pub fn allowed(&self) -> &[usize] {
+        &self.allowed
+    }
+
+    #[inline]
+    pub fn is_allowed(&self, display: usize) -> bool {
+        self.allowed.contains(&display)
+    }
+
+    /// The first allowed monitor which exists, for the peer to start with.
+    pub fn first(&self, count: usize) -> Option<usize> {
+        self.allowed.iter().find(|d| **d < count).cloned()
+    }
+
+    pub fn to_peer(&self, display: usize) -> Option<usize> {
+        self.allowed.iter().position(|d| *d == display)
+    }
+
+    pub fn to_host(&self, display: usize) -> Option<usize> {
+        self.allowed.get(display).cloned()
+    }
+
+    pub fn filter_peer_info(&self, pi: &mut PeerInfo) {
+        pi.displays = pi
+            .displays
+            .drain(..)
+            .enumerate()
+            .filter(|(i, _)| self.is_allowed(*i))
+            .map(|(_, d)| d)
+            .collect();
+        pi.current_display = self.to_peer(pi.current_display as _).unwrap_or_default() as _;
+        if let Ok(mut m) = serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(
+            &pi.platform_additions,
+        ) {
+            if let Some(serde_json::Value::Array(values)) = m.get_mut("display_dpis") {
+                *values = values
+                    .drain(..)
+                    .enumerate()
+                    .filter(|(i, _)| self.is_allowed(*i))
+                    .map(|(_, v)| v)
+                    .collect();
+            }
+            pi.platform_additions = serde_json::to_string(&m).unwrap_or_default();
+        }
+    }
+
+    /// Translate the display indexes of the message to the peer,
+    /// None if the message is about a monitor the peer can't see.
+    pub fn outgoing(&self, msg: Arc<Message>) -> Option<Arc<Message>> {
+        match &msg.union {
+            Some(message::Union::VideoFrame(vf)) => {
+                let display = self.to_peer(vf.display as _)?;
+                if display == vf.display as usize {
+                    return Some(msg);
+                }
+                let mut msg = (*msg).clone();
+                if let Some(message::Union::VideoFrame(vf)) = msg.union.as_mut() {
+                    vf.display = display as _;
+                }
+                Some(Arc::new(msg))
+            }
+            Some(message::Union::PreviewFrame(pf)) => {
+                let display = self.to_peer(pf.display as _)?;
+                if display == pf.display as usize {
+                    return Some(msg);
+                }
+                let mut msg = (*msg).clone();
+                if let Some(message::Union::PreviewFrame(pf)) = msg.union.as_mut() {
+                    pf.display = display as _;
+                }
+                Some(Arc::new(msg))
+            }
+            Some(message::Union::PeerInfo(_)) => {
+                let mut msg = (*msg).clone();
+                if let Some(message::Union::PeerInfo(pi)) = msg.union.as_mut() {
+                    self.filter_peer_info(pi);
+                }
+                Some(Arc::new(msg))
+            }
+            Some(message::Union::Misc(misc)) => match &misc.union {
+                Some(misc::Union::SwitchDisplay(s)) => {
+                    let display = self.to_peer(s.display as _)?;
+                    let mut msg = (*msg).clone();
+                    if let Some(message::Union::Misc(misc)) = msg.union.as_mut() {
+                        if let Some(misc::Union::SwitchDisplay(s)) = misc.union.as_mut() {
+                            s.display = display as _;
+                        }
+                    }
+                    Some(Arc::new(msg))
+                }
+                Some(misc::Union::FollowCurrentDisplay(d)) => {
+                    let display = self.to_peer(*d as _)?;
+                    let mut msg = (*msg).clone();
+                    if let Some(message::Union::Misc(misc)) = msg.union.as_mut() {
+                        misc.set_follow_current_display(display as _);
+                    }
+                    Some(Arc::new(msg))
+                }
+                _ => Some(msg),
+            },
+            _ => Some(msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display_filter() {
+        let filter = DisplayFilter::new(vec![2, 0]);
+        assert_eq!(filter.to_peer(2), Some(1));
+        assert_eq!(filter.to_peer(1), None);
+        assert_eq!(filter.to_host(1), Some(2));
+        assert_eq!(filter.to_host(2), None);
+
+        let mut pi = PeerInfo::new();
+        pi.displays = vec![DisplayInfo::new(); 3];
+        pi.displays[2].name = "2".to_owned();
+        pi.current_display = 2;
+        filter.filter_peer_info(&mut pi);
+        assert_eq!(pi.displays.len(), 2);
+        assert_eq!(pi.displays[1].name, "2");
+        assert_eq!(pi.current_display, 1);
+
+        let mut msg = Message::new();
+        msg.set_video_frame(VideoFrame {
+            display: 1,
+            ..Default::default()
+        });
+        assert!(filter.outgoing(Arc::new(msg)).is_none());
+
+        let preview = |display: i32| {
+            let mut msg = Message::new();
+            msg.set_preview_frame(PreviewFrame {
+                display,
+                ..Default::default()
+            });
+            Arc::new(msg)
+        };
+        assert!(filter.outgoing(preview(1)).is_none());
+        let msg = filter.outgoing(preview(2)).unwrap();
+        assert_eq!(msg.preview_frame().display, 1);
+    }
+}
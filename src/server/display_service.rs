@@ -1,3 +1,4 @@
+use super::capture_env::CaptureEnv;
 use super::*;
 use crate::common::SimpleCallOnReturn;
 #[cfg(target_os = "linux")]
@@ -8,7 +9,11 @@ use crate::virtual_display_manager;
 use hbb_common::get_version_number;
 use hbb_common::protobuf::MessageField;
 use scrap::Display;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
 
 // https://github.com/rustdesk/rustdesk/discussions/6042, avoiding dbus call
 
@@ -16,6 +21,8 @@ pub const NAME: &'static str = "display";
 
 #[cfg(windows)]
 const DUMMY_DISPLAY_SIDE_MAX_SIZE: usize = 1024;
+// The measured capture cost is capped, a slow capturer should not delay the others too much.
+const CAPTURE_SYNC_MAX_OFFSET_MS: u64 = 100;
 
 struct ChangedResolution {
     original: (i32, i32),
@@ -29,19 +36,153 @@ lazy_static::lazy_static! {
     // It should not be updated when displays changed.
     pub static ref PRIMARY_DISPLAY_IDX: usize = get_primary();
     static ref SYNC_DISPLAYS: Arc<Mutex<SyncDisplaysInfo>> = Default::default();
+    static ref CAPTURE_SYNC: Arc<Mutex<CaptureSync>> = Arc::new(Mutex::new(CaptureSync::new()));
+}
+
+// Capture sync aligns the capture ticks of all monitor video services to a single clock,
+// so that the frames of different displays carry the same capture timestamp.
+// It is off by default, because every capture waits for the next tick.
+struct CaptureSync {
+    start: Instant,
+    // The connections which turned on capture sync.
+    conns: HashSet<i32>,
+    // display index -> measured capture cost, the capture starts earlier by this offset.
+    offsets: HashMap<usize, Duration>,
+    // display index -> (tick ms, deviation ms of the captured frame from the tick)
+    deviations: HashMap<usize, (i64, i64)>,
+}
+
+impl CaptureSync {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            conns: Default::default(),
+            offsets: Default::default(),
+            deviations: Default::default(),
+        }
+    }
+}
+
+pub fn set_capture_sync(conn_id: i32, on: bool) {
+    let mut sync = CAPTURE_SYNC.lock().unwrap();
+    let changed = if on {
+        sync.conns.insert(conn_id)
+    } else {
+        sync.conns.remove(&conn_id)
+    };
+    if changed {
+        log::info!(
+            "capture sync of connection {} is {}",
+            conn_id,
+            if on { "on" } else { "off" }
+        );
+    }
+    if sync.conns.is_empty() {
+        sync.offsets.clear();
+        sync.deviations.clear();
+    }
+}
+
+#[inline]
+pub fn is_capture_sync_enabled() -> bool {
+    !CAPTURE_SYNC.lock().unwrap().conns.is_empty()
+}
+
+// Wait for the next capture tick of `display_idx`, and return the tick in ms.
+// The tick is shared by all displays, the wait is shortened by the measured capture cost of the display.
+pub fn capture_sync_wait(display_idx: usize, spf: Duration) -> i64 {
+    let (start, offset) = {
+        let sync = CAPTURE_SYNC.lock().unwrap();
+        (
+            sync.start,
+            sync.offsets.get(&display_idx).cloned().unwrap_or_default(),
+        )
+    };
+    let spf_ms = (spf.as_millis() as u64).max(1);
+    let offset_ms = offset.as_millis() as u64;
+    let elapsed = start.elapsed();
+    let tick = ((elapsed.as_millis() as u64 + offset_ms) / spf_ms + 1) * spf_ms;
+    let wake = Duration::from_millis(tick.saturating_sub(offset_ms));
+    if wake > elapsed {
+        std::thread::sleep(wake - elapsed);
+    }
+    tick as _
+}
+
+// Record the capture cost of a frame captured for `tick`.
+pub fn capture_sync_on_captured(display_idx: usize, tick: i64, cost: Duration) {
+    let mut sync = CAPTURE_SYNC.lock().unwrap();
+    let cost = cost.min(Duration::from_millis(CAPTURE_SYNC_MAX_OFFSET_MS));
+    let offset = match sync.offsets.get(&display_idx) {
+        Some(old) => (*old * 7 + cost) / 8,
+        None => cost,
+    };
+    sync.offsets.insert(display_idx, offset);
+    let deviation = sync.start.elapsed().as_millis() as i64 - tick;
+    sync.deviations.insert(display_idx, (tick, deviation));
+}
+
+// display index -> (capture offset ms, deviation ms of the last frame from its tick)
+pub fn get_capture_sync_stats() -> HashMap<usize, (u64, i64)> {
+    let sync = CAPTURE_SYNC.lock().unwrap();
+    sync.offsets
+        .iter()
+        .map(|(idx, offset)| {
+            let deviation = sync.deviations.get(idx).map(|d| d.1).unwrap_or_default();
+            (*idx, (offset.as_millis() as u64, deviation))
+        })
+        .collect()
 }
 
 // https://github.com/rustdesk/rustdesk/pull/8537
 static TEMP_IGNORE_DISPLAYS_CHANGED: AtomicBool = AtomicBool::new(false);
 
+// The rotations are in degrees, measured clockwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DisplayRotation {
+    // The orientation of the display.
+    pub orientation: i32,
+    // The rotation which is not compensated by the capturer.
+    // The frames are sent as they are, the peer rotates them if it supports the rotation metadata.
+    pub raw: i32,
+}
+
+impl DisplayRotation {
+    #[inline]
+    pub fn of(d: &DisplayInfo) -> Self {
+        Self {
+            orientation: d.rotation,
+            raw: d.raw_rotation,
+        }
+    }
+}
+
+// The hints for the peers which magnify the screen, sent in `platform_additions`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DisplayMetrics {
+    // The text scaling of the host in percent, 0 if unknown.
+    pub text_scale: u32,
+    // The effective dpi of every display, 96 is 100%.
+    pub dpis: Vec<u32>,
+}
+
 #[derive(Default)]
 struct SyncDisplaysInfo {
     displays: Vec<DisplayInfo>,
+    metrics: DisplayMetrics,
+    capture_env: CaptureEnv,
     is_synced: bool,
 }
 
 impl SyncDisplaysInfo {
-    fn check_changed(&mut self, displays: Vec<DisplayInfo>) {
+    fn check_changed(&mut self, displays: Vec<DisplayInfo>, metrics: DisplayMetrics) {
+        // A change of the dpi or the text scaling does not change the displays.
+        if self.metrics != metrics {
+            self.metrics = metrics;
+            if !TEMP_IGNORE_DISPLAYS_CHANGED.load(Ordering::Relaxed) {
+                self.is_synced = false;
+            }
+        }
         if self.displays.len() != displays.len() {
             self.displays = displays;
             if !TEMP_IGNORE_DISPLAYS_CHANGED.load(Ordering::Relaxed) {
@@ -60,12 +201,97 @@ impl SyncDisplaysInfo {
         }
     }
 
-    fn get_update_sync_displays(&mut self) -> Option<Vec<DisplayInfo>> {
+    fn check_capture_env_changed(&mut self, capture_env: CaptureEnv) {
+        if self.capture_env != capture_env {
+            log::info!("Capture environment: {}", capture_env.id());
+            self.capture_env = capture_env;
+            if !TEMP_IGNORE_DISPLAYS_CHANGED.load(Ordering::Relaxed) {
+                self.is_synced = false;
+            }
+        }
+    }
+
+    fn get_update_sync_displays(&mut self) -> Option<(Vec<DisplayInfo>, DisplayMetrics)> {
         if self.is_synced {
             return None;
         }
         self.is_synced = true;
-        Some(self.displays.clone())
+        Some((self.displays.clone(), self.metrics.clone()))
+    }
+}
+
+// The capturers already rotate the frames: dxgi and gdi on Windows, the root window of X11,
+// the display stream of macOS and the virtual display of Android, which is recreated when the
+// screen rotates, are in the orientation of the display. So `raw` is 0 for them.
+fn get_rotation(_display: &Display) -> DisplayRotation {
+    #[cfg(any(windows, target_os = "macos"))]
+    let orientation = crate::platform::current_orientation(&_display.name());
+    #[cfg(target_os = "linux")]
+    let orientation = if is_x11() {
+        crate::platform::current_orientation(&_display.name())
+    } else {
+        Ok(0)
+    };
+    #[cfg(target_os = "android")]
+    let orientation: ResultType<i32> = Ok(scrap::android::screen_rotation().unwrap_or(0));
+    #[cfg(not(any(
+        windows,
+        target_os = "macos",
+        target_os = "linux",
+        target_os = "android"
+    )))]
+    let orientation: ResultType<i32> = Ok(0);
+    DisplayRotation {
+        orientation: orientation.unwrap_or(0),
+        raw: 0,
+    }
+}
+
+fn get_dpi(_display: &Display) -> u32 {
+    #[cfg(windows)]
+    return crate::platform::windows::get_dpi_at(_display.origin().0, _display.origin().1);
+    #[cfg(target_os = "macos")]
+    return (72.0 * _display.scale()).round() as _;
+    #[cfg(not(any(windows, target_os = "macos")))]
+    96
+}
+
+#[inline]
+pub fn get_display_rotation(idx: usize) -> DisplayRotation {
+    SYNC_DISPLAYS
+        .lock()
+        .unwrap()
+        .displays
+        .get(idx)
+        .map(DisplayRotation::of)
+        .unwrap_or_default()
+}
+
+fn metrics_to_json(metrics: &DisplayMetrics, m: &mut serde_json::Map<String, serde_json::Value>) {
+    if metrics.text_scale > 0 {
+        m.insert("text_scale".into(), serde_json::json!(metrics.text_scale));
+    }
+    if !metrics.dpis.is_empty() {
+        m.insert("display_dpis".into(), serde_json::json!(metrics.dpis));
+    }
+}
+
+/// Add the metrics and the capture environment of the displays to the `platform_additions` of the
+/// peer info on login.
+pub(super) fn add_sync_displays_to_platform_additions(platform_additions: &mut String) {
+    let (metrics, capture_env) = {
+        let lock = SYNC_DISPLAYS.lock().unwrap();
+        (lock.metrics.clone(), lock.capture_env)
+    };
+    let mut m =
+        serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(platform_additions)
+            .unwrap_or_default();
+    metrics_to_json(&metrics, &mut m);
+    if let Some(v) = capture_env.to_json() {
+        m.insert("capture_env".into(), v);
+    }
+    if !m.is_empty() {
+        *platform_additions = serde_json::to_string(&m).unwrap_or_default();
     }
 }
 
@@ -177,15 +403,27 @@ pub fn new() -> GenericService {
     svc.sp
 }
 
-fn displays_to_msg(displays: Vec<DisplayInfo>) -> Message {
+fn displays_to_msg(
+    displays: Vec<DisplayInfo>,
+    metrics: DisplayMetrics,
+    capture_env: CaptureEnv,
+) -> Message {
     let mut pi = PeerInfo {
         ..Default::default()
     };
     pi.displays = displays.clone();
 
+    #[allow(unused_mut)]
+    let mut m = serde_json::Map::new();
     #[cfg(windows)]
     if crate::platform::is_installed() {
-        let m = crate::virtual_display_manager::get_platform_additions();
+        m.extend(crate::virtual_display_manager::get_platform_additions());
+    }
+    metrics_to_json(&metrics, &mut m);
+    if let Some(v) = capture_env.to_json() {
+        m.insert("capture_env".into(), v);
+    }
+    if !m.is_empty() {
         pi.platform_additions = serde_json::to_string(&m).unwrap_or_default();
     }
 
@@ -222,11 +460,15 @@ pub fn check_displays_changed() -> ResultType<()> {
 }
 
 fn get_displays_msg() -> Option<Message> {
-    let displays = SYNC_DISPLAYS.lock().unwrap().get_update_sync_displays()?;
-    Some(displays_to_msg(displays))
+    let mut lock = SYNC_DISPLAYS.lock().unwrap();
+    let (displays, metrics) = lock.get_update_sync_displays()?;
+    let capture_env = lock.capture_env;
+    drop(lock);
+    Some(displays_to_msg(displays, metrics, capture_env))
 }
 
 fn run(sp: EmptyExtraFieldService) -> ResultType<()> {
+    let mut last_log_sync_stats = Instant::now();
     while sp.ok() {
         sp.snapshot(|sps| {
             if !TEMP_IGNORE_DISPLAYS_CHANGED.load(Ordering::Relaxed) {
@@ -242,6 +484,12 @@ fn run(sp: EmptyExtraFieldService) -> ResultType<()> {
             sp.send(msg_out);
             log::info!("Displays changed");
         }
+        if last_log_sync_stats.elapsed() > Duration::from_secs(10) {
+            last_log_sync_stats = Instant::now();
+            if is_capture_sync_enabled() {
+                log::info!("capture sync stats: {:?}", get_capture_sync_stats());
+            }
+        }
         std::thread::sleep(Duration::from_millis(300));
     }
 
@@ -320,6 +568,7 @@ pub(super) fn check_update_displays(all: &Vec<Display>) {
                 ((d.width() as f64) / scale).round() as usize,
                 (d.height() as f64 / scale).round() as usize,
             );
+            let rotation = get_rotation(d);
             DisplayInfo {
                 x: d.origin().0 as _,
                 y: d.origin().1 as _,
@@ -330,11 +579,23 @@ pub(super) fn check_update_displays(all: &Vec<Display>) {
                 cursor_embedded: false,
                 original_resolution,
                 scale,
+                rotation: rotation.orientation,
+                raw_rotation: rotation.raw,
                 ..Default::default()
             }
         })
         .collect::<Vec<DisplayInfo>>();
-    SYNC_DISPLAYS.lock().unwrap().check_changed(displays);
+    let metrics = DisplayMetrics {
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        text_scale: super::text_scale::get(),
+        #[cfg(any(target_os = "android", target_os = "ios"))]
+        text_scale: 0,
+        dpis: all.iter().map(get_dpi).collect(),
+    };
+    let capture_env = super::capture_env::detect(all);
+    let mut lock = SYNC_DISPLAYS.lock().unwrap();
+    lock.check_changed(displays, metrics);
+    lock.check_capture_env_changed(capture_env);
 }
 
 pub fn is_inited_msg() -> Option<Message> {
@@ -356,10 +617,69 @@ pub async fn update_get_sync_displays_on_login() -> ResultType<Vec<DisplayInfo>>
     let displays = display_service::try_get_displays();
     #[cfg(windows)]
     let displays = display_service::try_get_displays_add_amyuni_headless();
-    check_update_displays(&displays?);
+    let displays = displays?;
+    // Report the driver error to the peer, instead of showing a black screen.
+    #[cfg(windows)]
+    if displays.is_empty() {
+        if let Some(e) = virtual_display_manager::get_last_error() {
+            bail!(virtual_display_manager::get_actionable_error(&e));
+        }
+    }
+    check_update_displays(&displays);
     Ok(SYNC_DISPLAYS.lock().unwrap().displays.clone())
 }
 
+// Plug in or plug out the virtual display requested by the peer.
+// The returned error is a message that can be shown to the user directly.
+#[cfg(windows)]
+pub fn toggle_virtual_display(display: i32, on: bool) -> Result<(), String> {
+    if !virtual_display_manager::is_virtual_display_supported() {
+        return Err("idd_not_support_under_win10_2004_tip".to_owned());
+    }
+    let res = if on {
+        virtual_display_manager::plug_in_monitor(display as _, Vec::new())
+    } else {
+        virtual_display_manager::plug_out_monitor(display, false, true)
+    };
+    if let Err(e) = res {
+        log::error!(
+            "Failed to plug {} virtual display {}: {}",
+            if on { "in" } else { "out" },
+            display,
+            e
+        );
+        return Err(virtual_display_manager::get_actionable_error(
+            &e.to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Set the mode of a virtual display plugged in, the error is shown to the user like above.
+#[cfg(windows)]
+pub fn set_virtual_display_mode(
+    display: i32,
+    width: u32,
+    height: u32,
+    refresh_rate: u32,
+) -> Result<(), String> {
+    if !virtual_display_manager::is_virtual_display_supported() {
+        return Err("idd_not_support_under_win10_2004_tip".to_owned());
+    }
+    let mode = virtual_display::MonitorMode {
+        width,
+        height,
+        sync: if refresh_rate == 0 { 60 } else { refresh_rate },
+    };
+    if let Err(e) = virtual_display_manager::set_monitor_mode(display as _, mode) {
+        log::error!("Failed to set the mode of virtual display {}: {}", display, e);
+        return Err(virtual_display_manager::get_actionable_error(
+            &e.to_string(),
+        ));
+    }
+    Ok(())
+}
+
 #[inline]
 pub fn get_primary() -> usize {
     #[cfg(target_os = "linux")]
@@ -382,7 +702,7 @@ pub fn get_primary_2(all: &Vec<Display>) -> usize {
 
 #[inline]
 #[cfg(windows)]
-fn no_displays(displays: &Vec<Display>) -> bool {
+pub(super) fn no_displays(displays: &Vec<Display>) -> bool {
     let display_len = displays.len();
     if display_len == 0 {
         true
@@ -0,0 +1,361 @@
+// Scan of the files received from the peer, before they land in the destination.
+//
+// With "file-scan-command" or "file-scan-icap" set, a received file is written to the session
+// temporary directory instead of the destination, it is moved to the destination only after the
+// scanner accepts it, so a partial or a rejected file is never readable there.
+//
+// "file-scan-command" is run with the file, `{file}` in it is replaced with the path, otherwise
+// the path is appended, exit code 0 means clean. "file-scan-icap" is an ICAP service,
+// e.g. "icap://127.0.0.1:1344/avscan", 204 or a response without an infection header means clean.
+// A rejected file is deleted and reported to the peer as "Blocked by policy".
+// "file-scan-timeout" is in seconds, 60 by default. If the scanner fails or times out, the file is
+// rejected unless "file-scan-fail-open" is "Y".
+//
+// The staged files count to the size limit of the session temporary directory: the declared size
+// of a job is checked when it starts, and the bytes written so far on every block, so a peer
+// declaring less than it sends is stopped too. The staging of a connection is removed with it.
+//
+// The files pasted from the clipboard of the peer are materialized by the system on demand and
+// can't be staged, they are refused while the scan is enabled.
+
+use super::session_temp;
+use hbb_common::{
+    bail,
+    config::Config,
+    fs::{get_string, TransferJob},
+    log,
+    message_proto::FileEntry,
+    ResultType,
+};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const OPTION_COMMAND: &str = "file-scan-command";
+const OPTION_ICAP: &str = "file-scan-icap";
+const OPTION_TIMEOUT: &str = "file-scan-timeout";
+const OPTION_FAIL_OPEN: &str = "file-scan-fail-open";
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+const FILE_PLACEHOLDER: &str = "{file}";
+pub const BLOCKED_BY_POLICY: &str = "Blocked by policy";
+
+struct Staged {
+    dir: PathBuf,
+    dest: PathBuf,
+    // The bytes written to `dir` so far.
+    written: u64,
+}
+
+lazy_static::lazy_static! {
+    // (conn id, job id) -> staging
+    static ref STAGED: Mutex<HashMap<(i32, i32), Staged>> = Default::default();
+}
+
+#[inline]
+pub fn is_enabled() -> bool {
+    !Config::get_option(OPTION_COMMAND).is_empty() || !Config::get_option(OPTION_ICAP).is_empty()
+}
+
+/// The directory the job writes to instead of `dest`, if `total_size` fits in the size limit of
+/// the session temporary directory.
+pub fn stage(conn_id: i32, id: i32, dest: &str, total_size: u64) -> ResultType<PathBuf> {
+    let session_dir = session_temp::get_or_create(conn_id)?;
+    session_temp::check_room(&session_dir, total_size)?;
+    let dir = session_dir.join(format!("scan-{}", id));
+    std::fs::create_dir_all(&dir)?;
+    STAGED.lock().unwrap().insert(
+        (conn_id, id),
+        Staged {
+            dir: dir.clone(),
+            dest: PathBuf::from(dest),
+            written: 0,
+        },
+    );
+    Ok(dir)
+}
+
+/// The real destination of a staged job, to compare with the existing files.
+pub fn destination(conn_id: i32, id: i32) -> Option<PathBuf> {
+    STAGED
+        .lock()
+        .unwrap()
+        .get(&(conn_id, id))
+        .map(|s| s.dest.clone())
+}
+
+/// Records the bytes written by a staged job so far, fails if the staged jobs of the connection
+/// exceed the size limit.
+pub fn on_written(conn_id: i32, id: i32, written: u64) -> ResultType<()> {
+    let mut staged = STAGED.lock().unwrap();
+    let Some(job) = staged.get_mut(&(conn_id, id)) else {
+        return Ok(());
+    };
+    job.written = written;
+    let total: u64 = staged
+        .iter()
+        .filter(|((c, _), _)| *c == conn_id)
+        .map(|(_, s)| s.written)
+        .sum();
+    let limit = session_temp::size_limit();
+    if total > limit {
+        bail!(
+            "Session temporary files exceed the limit: {} > {}",
+            total,
+            limit
+        );
+    }
+    Ok(())
+}
+
+pub fn cancel(conn_id: i32, id: i32) {
+    if let Some(s) = STAGED.lock().unwrap().remove(&(conn_id, id)) {
+        std::fs::remove_dir_all(&s.dir).ok();
+    }
+}
+
+/// Removes the staging of a closed connection, with its session temporary directory.
+pub fn remove_conn(conn_id: i32) {
+    let ids = STAGED
+        .lock()
+        .unwrap()
+        .keys()
+        .filter(|(c, _)| *c == conn_id)
+        .map(|(_, id)| *id)
+        .collect::<Vec<_>>();
+    for id in ids {
+        cancel(conn_id, id);
+    }
+    session_temp::remove(conn_id);
+}
+
+/// Scan the received files of the job and move the clean ones to the destination.
+/// Returns the rejected files with the reasons.
+pub fn finish(conn_id: i32, id: i32, files: &[FileEntry]) -> Vec<(String, String)> {
+    let Some(Staged { dir, dest, .. }) = STAGED.lock().unwrap().remove(&(conn_id, id)) else {
+        return vec![];
+    };
+    let mut blocked = vec![];
+    for file in files {
+        let staged = TransferJob::join(&dir, &file.name);
+        if !staged.is_file() {
+            // Skipped, or not received.
+            continue;
+        }
+        let target = TransferJob::join(&dest, &file.name);
+        let res =
+            check(&staged).and_then(|_| move_file(&staged, &target).map_err(|e| e.to_string()));
+        if let Err(reason) = res {
+            log::warn!(
+                "#{} {} is rejected: {}",
+                conn_id,
+                get_string(&target),
+                reason
+            );
+            std::fs::remove_file(&staged).ok();
+            blocked.push((get_string(&target), reason));
+        }
+    }
+    std::fs::remove_dir_all(&dir).ok();
+    blocked
+}
+
+fn move_file(from: &Path, to: &Path) -> ResultType<()> {
+    if let Some(p) = to.parent() {
+        std::fs::create_dir_all(p)?;
+    }
+    if std::fs::rename(from, to).is_err() {
+        // The temporary directory may be on another volume.
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from).ok();
+    }
+    Ok(())
+}
+
+/// Ok if the file may be used, otherwise the reason to reject it.
+pub fn check(path: &Path) -> Result<(), String> {
+    let timeout = Duration::from_secs(
+        Config::get_option(OPTION_TIMEOUT)
+            .parse::<u64>()
+            .unwrap_or(DEFAULT_TIMEOUT_SECS),
+    );
+    let command = Config::get_option(OPTION_COMMAND);
+    let icap = Config::get_option(OPTION_ICAP);
+    let res = if !command.is_empty() {
+        run_command(&command, path, timeout)
+    } else if !icap.is_empty() {
+        scan_icap(&icap, path, timeout)
+    } else {
+        return Ok(());
+    };
+    match res {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(BLOCKED_BY_POLICY.to_owned()),
+        Err(e) => {
+            log::error!("Failed to scan {}: {}", get_string(path), e);
+            if Config::get_option(OPTION_FAIL_OPEN) == "Y" {
+                Ok(())
+            } else {
+                Err(format!("{}, scan failed", BLOCKED_BY_POLICY))
+            }
+        }
+    }
+}
+
+// Ok(true) if it is clean.
+fn run_command(command: &str, path: &Path, timeout: Duration) -> ResultType<bool> {
+    let file = get_string(path);
+    let mut args = split_command(command);
+    if args.iter().any(|a| a.contains(FILE_PLACEHOLDER)) {
+        for a in args.iter_mut() {
+            *a = a.replace(FILE_PLACEHOLDER, &file);
+        }
+    } else {
+        args.push(file);
+    }
+    if args.is_empty() {
+        bail!("Empty command");
+    }
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status.success());
+        }
+        if start.elapsed() > timeout {
+            child.kill().ok();
+            child.wait().ok();
+            bail!("Timeout");
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// Split on the spaces, a part may be quoted with double quotes.
+fn split_command(command: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut cur = String::new();
+    let mut quoted = false;
+    for c in command.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' | '\t' if !quoted => {
+                if !cur.is_empty() {
+                    args.push(std::mem::take(&mut cur));
+                }
+            }
+            _ => cur.push(c),
+        }
+    }
+    if !cur.is_empty() {
+        args.push(cur);
+    }
+    args
+}
+
+// A RESPMOD request with the file as the body of an HTTP response, Ok(true) if it is clean.
+fn scan_icap(url: &str, path: &Path, timeout: Duration) -> ResultType<bool> {
+    let Some(rest) = url.strip_prefix("icap://") else {
+        bail!("Invalid ICAP url: {}", url);
+    };
+    let host = rest.split('/').next().unwrap_or_default();
+    let addr = if host.contains(':') {
+        host.to_owned()
+    } else {
+        format!("{}:1344", host)
+    };
+    let data = std::fs::read(path)?;
+    let mut stream = TcpStream::connect(&addr)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let res_hdr = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+        data.len()
+    );
+    let req = format!(
+        "RESPMOD {} ICAP/1.0\r\nHost: {}\r\nAllow: 204\r\nEncapsulated: res-hdr=0, res-body={}\r\n\r\n",
+        url,
+        host,
+        res_hdr.len()
+    );
+    stream.write_all(req.as_bytes())?;
+    stream.write_all(res_hdr.as_bytes())?;
+    if !data.is_empty() {
+        stream.write_all(format!("{:x}\r\n", data.len()).as_bytes())?;
+        stream.write_all(&data)?;
+        stream.write_all(b"\r\n")?;
+    }
+    stream.write_all(b"0\r\n\r\n")?;
+    let mut buf = vec![0u8; 4096];
+    let mut head = Vec::new();
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    is_icap_clean(&String::from_utf8_lossy(&head))
+}
+
+fn is_icap_clean(head: &str) -> ResultType<bool> {
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|s| s.parse::<u16>().ok());
+    let infected = lines.any(|l| {
+        let l = l.to_lowercase();
+        l.starts_with("x-infection-found:") || l.starts_with("x-virus-id:")
+    });
+    match status {
+        Some(204) => Ok(true),
+        Some(200) => Ok(!infected),
+        Some(s) => bail!("ICAP status {}", s),
+        None => bail!("Invalid ICAP response"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_file_scan_parse() {
+        assert_eq!(
+            split_command(r#""C:\Program Files\av\scan.exe" --quiet {file}"#),
+            vec![r"C:\Program Files\av\scan.exe", "--quiet", "{file}"]
+        );
+        assert!(is_icap_clean("ICAP/1.0 204 No Content\r\n\r\n").unwrap());
+        assert!(!is_icap_clean("ICAP/1.0 200 OK\r\nX-Infection-Found: Type=0\r\n\r\n").unwrap());
+        assert!(is_icap_clean("ICAP/1.0 500 Server Error\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_file_scan_limit() {
+        let conn_id = -(std::process::id() as i32);
+        let dest = get_string(&std::env::temp_dir());
+        let limit = session_temp::size_limit();
+        assert!(stage(conn_id, 1, &dest, limit + 1).is_err());
+        let dir = stage(conn_id, 1, &dest, 0).unwrap();
+        stage(conn_id, 2, &dest, 0).unwrap();
+        assert!(on_written(conn_id, 1, limit / 2).is_ok());
+        // A job writing more than it declared.
+        assert!(on_written(conn_id, 2, limit / 2 + 1).is_err());
+        // Not staged.
+        on_written(conn_id, 3, limit).unwrap();
+        remove_conn(conn_id);
+        assert!(destination(conn_id, 1).is_none());
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,87 @@
+// The headless mode of the server, `--server --headless`, for the minimal hosts which have only
+// the binary, no desktop, no tray and no Flutter. Not to be confused with the headless desktop
+// of Linux, which starts a desktop for the sessions of a host without one.
+//
+// The server is configured by the config file and the CLI control surface, e.g. `--password` and
+// `--option`, which still use the ipc of the server. Nothing else is started besides the server:
+// not the tray, not the connection manager, not the check of the hardware codecs, not the panic
+// button. The log goes to stdout, so to journald under systemd. There's nobody to click accept,
+// the logins are decided by the access policy or the permanent password, the rest is denied.
+// The notifications for the user of the host are logged.
+
+use hbb_common::log;
+use std::{
+    io::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+pub const ARG_HEADLESS: &str = "--headless";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static LOGGER: StdoutLogger = StdoutLogger;
+
+/// Before the server is started.
+pub fn set(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+#[inline]
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Logs to stdout instead of the log files, "RUST_LOG" is the level, info by default.
+pub fn init_log() {
+    let level = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(log::LevelFilter::Info);
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// The notification for the user of the host, only logged in the headless mode.
+pub fn show_notification(title: &str, text: &str) {
+    if is_enabled() {
+        log::info!("notification: {}: {}", title, text);
+        return;
+    }
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Err(e) = crate::platform::show_notification(title, text) {
+        log::error!("Failed to show the notification \"{}\": {}", title, e);
+    }
+}
+
+struct StdoutLogger;
+
+impl log::Log for StdoutLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut out = std::io::stdout().lock();
+        // journald adds the time itself.
+        if std::env::var_os("JOURNAL_STREAM").is_some() {
+            writeln!(out, "[{}] {}", record.level(), record.args()).ok();
+        } else {
+            writeln!(
+                out,
+                "[{}] {} [{}] {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                record.level(),
+                record.module_path().unwrap_or_default(),
+                record.args()
+            )
+            .ok();
+        }
+    }
+
+    fn flush(&self) {
+        std::io::stdout().flush().ok();
+    }
+}
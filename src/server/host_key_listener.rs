@@ -0,0 +1,112 @@
+// The global listener of the keys of the host in the server process, shared by push-to-talk and
+// the panic button, because rdev has only one callback in a process, a second listener would
+// replace the first one.
+//
+// It's started once by the first of them and never stopped. The keys are not grabbed, the other
+// programs still receive them. There's no global listener on Wayland.
+//
+// The keys injected for the peers are dropped before any of them sees the keys, a peer can't press
+// the keys of the host. On Windows and macOS the injected events carry the mark of enigo. On X11
+// they carry nothing, the key presses seen while the server injects the keys of a peer, or within
+// `INJECTED_WINDOW` after it, are taken as injected. A local press at that time is missed and has
+// to be repeated. The releases always pass there, an injected release can only end push-to-talk or
+// reset the hotkey, a local release dropped by mistake would keep push-to-talk on.
+
+use hbb_common::log;
+use rdev::EventType;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Once,
+};
+#[cfg(target_os = "linux")]
+use std::{
+    sync::{atomic::AtomicUsize, Mutex},
+    time::{Duration, Instant},
+};
+
+#[cfg(target_os = "linux")]
+const INJECTED_WINDOW: Duration = Duration::from_millis(100);
+
+// The listener can't be started, e.g. no access to the input devices.
+static LISTEN_FAILED: AtomicBool = AtomicBool::new(false);
+static START_LISTENER: Once = Once::new();
+// The keys of the peers being injected, and when the last injection ended.
+#[cfg(target_os = "linux")]
+static INJECTING: AtomicUsize = AtomicUsize::new(0);
+#[cfg(target_os = "linux")]
+static LAST_INJECTED: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Held while the server injects a key of a peer on Linux, see `is_injected`.
+#[cfg(target_os = "linux")]
+pub struct Injecting;
+
+#[cfg(target_os = "linux")]
+impl Injecting {
+    pub fn new() -> Self {
+        INJECTING.fetch_add(1, Ordering::SeqCst);
+        Self
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Injecting {
+    fn drop(&mut self) {
+        *LAST_INJECTED.lock().unwrap() = Some(Instant::now());
+        INJECTING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+fn is_injected(event: &rdev::Event, _down: bool) -> bool {
+    event.extra_data == enigo::ENIGO_INPUT_EXTRA_VALUE as _
+}
+
+#[cfg(target_os = "linux")]
+fn is_injected(_event: &rdev::Event, down: bool) -> bool {
+    down && (INJECTING.load(Ordering::SeqCst) > 0
+        || LAST_INJECTED
+            .lock()
+            .unwrap()
+            .map_or(false, |t| t.elapsed() < INJECTED_WINDOW))
+}
+
+/// Start the listener if it is not started, returns false if there can't be one.
+pub fn start() -> bool {
+    #[cfg(target_os = "linux")]
+    if !crate::platform::linux::is_x11() {
+        return false;
+    }
+    START_LISTENER.call_once(|| {
+        std::thread::spawn(listen);
+    });
+    !is_failed()
+}
+
+#[inline]
+pub fn is_failed() -> bool {
+    LISTEN_FAILED.load(Ordering::SeqCst)
+}
+
+fn listen() {
+    #[cfg(target_os = "macos")]
+    rdev::set_is_main_thread(false);
+    let callback = move |event: rdev::Event| {
+        let (k, down) = match event.event_type {
+            EventType::KeyPress(k) => (k, true),
+            EventType::KeyRelease(k) => (k, false),
+            _ => return,
+        };
+        // The keys sent by the remote peers are not the keys of the host.
+        if is_injected(&event, down) {
+            return;
+        }
+        super::push_to_talk::on_key(k, down);
+        super::panic_button::on_key(k, down);
+    };
+    if let Err(e) = rdev::listen(callback) {
+        log::error!("Failed to listen to the keys of the host: {:?}", e);
+        LISTEN_FAILED.store(true, Ordering::SeqCst);
+        // Disarm push-to-talk for good, or the host could never be heard.
+        super::push_to_talk::stop();
+    }
+}
@@ -0,0 +1,111 @@
+// The priority between the local user of an attended host and the remote session, the capture and
+// encode compete with the programs of the local user at normal priority.
+//
+// "host-priority" is "local-first" or empty for normal. In the local first mode the video service
+// threads run below the normal priority, and on Windows, where the cpu usage is monitored, the fps
+// and the bitrate are capped while the cpu usage of the last minute is above
+// "host-priority-cpu-threshold" (percent). The thread priority is applied when a video service
+// starts, the throttling at the next poll. Both are in the session report.
+
+use hbb_common::{config::Config, log};
+
+pub const OPTION_HOST_PRIORITY: &str = "host-priority";
+pub const LOCAL_FIRST: &str = "local-first";
+pub const NORMAL: &str = "normal";
+#[cfg(windows)]
+pub const OPTION_CPU_THRESHOLD: &str = "host-priority-cpu-threshold";
+#[cfg(windows)]
+const DEFAULT_CPU_THRESHOLD: f64 = 80.;
+#[cfg(windows)]
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[inline]
+pub fn is_local_first() -> bool {
+    Config::get_option(OPTION_HOST_PRIORITY) == LOCAL_FIRST
+}
+
+/// The effective mode, for the session report.
+pub fn mode() -> &'static str {
+    if is_local_first() {
+        LOCAL_FIRST
+    } else {
+        NORMAL
+    }
+}
+
+/// Lower the priority of the calling thread in the local first mode, called by the capture and
+/// encode threads when they start.
+pub fn apply_to_current_thread() {
+    if !is_local_first() {
+        return;
+    }
+    match lower_current_thread() {
+        Ok(()) => log::info!("{}: the thread runs below normal priority", LOCAL_FIRST),
+        Err(e) => log::error!("Failed to lower the thread priority: {}", e),
+    }
+}
+
+#[cfg(windows)]
+fn lower_current_thread() -> hbb_common::ResultType<()> {
+    use windows::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_BELOW_NORMAL,
+    };
+    unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_BELOW_NORMAL)? };
+    Ok(())
+}
+
+// The nice value of Linux is per thread, the threads created later by this one inherit it.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn lower_current_thread() -> hbb_common::ResultType<()> {
+    use hbb_common::libc;
+    const NICE: libc::c_int = 10;
+    let tid = unsafe { libc::gettid() };
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as _, NICE) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn lower_current_thread() -> hbb_common::ResultType<()> {
+    use hbb_common::libc;
+    let res =
+        unsafe { libc::pthread_set_qos_class_self_np(libc::qos_class_t::QOS_CLASS_UTILITY, 0) };
+    if res != 0 {
+        hbb_common::bail!("pthread_set_qos_class_self_np: {}", res);
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn cpu_threshold() -> f64 {
+    Config::get_option(OPTION_CPU_THRESHOLD)
+        .parse::<f64>()
+        .ok()
+        .filter(|x| *x > 0. && *x <= 100.)
+        .unwrap_or(DEFAULT_CPU_THRESHOLD)
+}
+
+/// Poll the cpu usage and throttle the video while it is high in the local first mode.
+#[cfg(windows)]
+pub fn start() {
+    use super::{session_report, video_service::VIDEO_QOS};
+    std::thread::spawn(|| {
+        let mut last = false;
+        loop {
+            let throttled = is_local_first()
+                && hbb_common::platform::windows::cpu_uage_one_minute()
+                    .map(|usage| usage > cpu_threshold())
+                    .unwrap_or(false);
+            if throttled != last {
+                log::info!("{}: cpu throttling: {}", LOCAL_FIRST, throttled);
+                VIDEO_QOS.lock().unwrap().set_cpu_throttled(throttled);
+                if throttled {
+                    session_report::on_cpu_throttle();
+                }
+                last = throttled;
+            }
+            std::thread::sleep(CHECK_INTERVAL);
+        }
+    });
+}
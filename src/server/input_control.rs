@@ -0,0 +1,285 @@
+// The policy of the input when several connections control the host at the same time.
+//
+// "multi-control-policy":
+// - "last-writer-wins", the default, every input is injected as it comes, as before.
+// - "exclusive", only the holder of the control token is injected. The first input takes the
+//   free token, the others request it and are queued, it is handed to the first in the queue
+//   when the holder releases it, disconnects or is idle for `IDLE_HANDOFF`. The button releases
+//   always pass, so nothing is left pressed.
+// - "blend", the absolute moves of the connections active together are averaged, the other
+//   events are injected as they come.
+// The policy is cached, `reload_policy` is called when the options change and resets the token.
+
+use hbb_common::{config::Config, log};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+pub const OPTION_MULTI_CONTROL_POLICY: &str = "multi-control-policy";
+const IDLE_HANDOFF: Duration = Duration::from_secs(30);
+const BLEND_WINDOW: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    LastWriterWins,
+    Exclusive,
+    Blend,
+}
+
+lazy_static::lazy_static! {
+    // The option is read once and again when the options change, not on every input.
+    static ref POLICY: RwLock<Option<Policy>> = Default::default();
+    static ref TOKEN: Mutex<Token> = Default::default();
+    static ref BLEND: Mutex<Blend> = Default::default();
+}
+
+#[derive(Default)]
+struct Token {
+    holder: Option<(i32, String)>,
+    queue: VecDeque<(i32, String)>,
+    last_input: Option<Instant>,
+}
+
+impl Token {
+    fn grant(&mut self, conn_id: i32, name: String, now: Instant) {
+        log::info!("Control token is granted to connection {}", conn_id);
+        self.queue.retain(|(id, _)| *id != conn_id);
+        self.holder = Some((conn_id, name));
+        self.last_input = Some(now);
+    }
+
+    fn hand_off(&mut self, now: Instant) {
+        match self.queue.pop_front() {
+            Some((id, name)) => self.grant(id, name, now),
+            None => {
+                self.holder = None;
+                self.last_input = None;
+            }
+        }
+    }
+
+    fn holder_id(&self) -> Option<i32> {
+        self.holder.as_ref().map(|(id, _)| *id)
+    }
+
+    fn is_holder_idle(&self, now: Instant) -> bool {
+        self.last_input
+            .map(|t| now.saturating_duration_since(t) >= IDLE_HANDOFF)
+            .unwrap_or(true)
+    }
+
+    // (accepted, whether the token changed)
+    fn accept_input(&mut self, conn_id: i32, name: &str, now: Instant) -> (bool, bool) {
+        match self.holder_id() {
+            Some(id) if id == conn_id => {
+                self.last_input = Some(now);
+                (true, false)
+            }
+            Some(_) if !self.is_holder_idle(now) => (false, false),
+            Some(_) => match self.queue.front().map(|(id, _)| *id) {
+                Some(id) if id != conn_id => {
+                    self.hand_off(now);
+                    (false, true)
+                }
+                _ => {
+                    self.grant(conn_id, name.to_owned(), now);
+                    (true, true)
+                }
+            },
+            None => {
+                self.grant(conn_id, name.to_owned(), now);
+                (true, true)
+            }
+        }
+    }
+
+    fn request(&mut self, conn_id: i32, name: &str, now: Instant) {
+        match self.holder_id() {
+            Some(id) if id == conn_id => {}
+            Some(_) if !self.is_holder_idle(now) => {
+                if !self.queue.iter().any(|(id, _)| *id == conn_id) {
+                    self.queue.push_back((conn_id, name.to_owned()));
+                }
+            }
+            _ => self.grant(conn_id, name.to_owned(), now),
+        }
+    }
+
+    fn release(&mut self, conn_id: i32, now: Instant) {
+        if self.holder_id() == Some(conn_id) {
+            log::info!("Control token is released by connection {}", conn_id);
+            self.hand_off(now);
+        } else {
+            self.queue.retain(|(id, _)| *id != conn_id);
+        }
+    }
+
+    fn is_involved(&self, conn_id: i32) -> bool {
+        self.holder_id() == Some(conn_id) || self.queue.iter().any(|(id, _)| *id == conn_id)
+    }
+}
+
+// The latest absolute moves of the connections.
+#[derive(Default)]
+struct Blend(HashMap<i32, (i32, i32, Instant)>);
+
+impl Blend {
+    fn blend(&mut self, conn_id: i32, x: &mut i32, y: &mut i32, now: Instant) {
+        self.0.insert(conn_id, (*x, *y, now));
+        self.0
+            .retain(|_, (_, _, t)| now.saturating_duration_since(*t) < BLEND_WINDOW);
+        let (sx, sy) = self.0.values().fold((0i64, 0i64), |(sx, sy), (x, y, _)| {
+            (sx + *x as i64, sy + *y as i64)
+        });
+        let n = self.0.len() as i64;
+        *x = (sx / n) as _;
+        *y = (sy / n) as _;
+    }
+}
+
+fn parse_policy(v: &str) -> Policy {
+    match v {
+        "exclusive" => Policy::Exclusive,
+        "blend" => Policy::Blend,
+        _ => Policy::LastWriterWins,
+    }
+}
+
+pub fn policy() -> Policy {
+    if let Some(policy) = *POLICY.read().unwrap() {
+        return policy;
+    }
+    let policy = parse_policy(&Config::get_option(OPTION_MULTI_CONTROL_POLICY));
+    *POLICY.write().unwrap() = Some(policy);
+    policy
+}
+
+/// Called when the options change, the token and the moves of the old policy are dropped.
+pub fn reload_policy() {
+    let old = POLICY.write().unwrap().take();
+    if old == Some(policy()) {
+        return;
+    }
+    BLEND.lock().unwrap().0.clear();
+    *TOKEN.lock().unwrap() = Default::default();
+    super::connection::on_control_token_changed();
+}
+
+#[inline]
+pub fn is_exclusive() -> bool {
+    policy() == Policy::Exclusive
+}
+
+/// Whether the input of the connection is injected, it takes the free token in the exclusive mode.
+pub fn accept_input(conn_id: i32, name: &str) -> bool {
+    if !is_exclusive() {
+        return true;
+    }
+    let (accepted, changed) = TOKEN
+        .lock()
+        .unwrap()
+        .accept_input(conn_id, name, Instant::now());
+    if changed {
+        super::connection::on_control_token_changed();
+    }
+    accepted
+}
+
+/// Request the token, it is granted now if it is free or the holder is idle, otherwise queued.
+pub fn request(conn_id: i32, name: &str) {
+    TOKEN.lock().unwrap().request(conn_id, name, Instant::now());
+    super::connection::on_control_token_changed();
+}
+
+/// Release the token, or leave the queue.
+pub fn release(conn_id: i32) {
+    TOKEN.lock().unwrap().release(conn_id, Instant::now());
+    super::connection::on_control_token_changed();
+}
+
+pub fn remove(conn_id: i32) {
+    BLEND.lock().unwrap().0.remove(&conn_id);
+    let involved = TOKEN.lock().unwrap().is_involved(conn_id);
+    if involved {
+        release(conn_id);
+    }
+}
+
+/// (whether the connection holds the token, the name of the holder, whether it is queued)
+pub fn state(conn_id: i32) -> (bool, String, bool) {
+    let token = TOKEN.lock().unwrap();
+    let holding = token.holder_id() == Some(conn_id);
+    let holder = token
+        .holder
+        .as_ref()
+        .map(|(_, name)| name.clone())
+        .unwrap_or_default();
+    let queued = token.queue.iter().any(|(id, _)| *id == conn_id);
+    (holding, holder, queued)
+}
+
+/// Average the absolute move with the latest moves of the other connections in the blend mode.
+pub fn blend(conn_id: i32, x: &mut i32, y: &mut i32) {
+    if policy() != Policy::Blend {
+        return;
+    }
+    BLEND.lock().unwrap().blend(conn_id, x, y, Instant::now());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(parse_policy(""), Policy::LastWriterWins);
+        assert_eq!(parse_policy("last-writer-wins"), Policy::LastWriterWins);
+        assert_eq!(parse_policy("exclusive"), Policy::Exclusive);
+        assert_eq!(parse_policy("blend"), Policy::Blend);
+    }
+
+    #[test]
+    fn test_exclusive_token() {
+        let now = Instant::now();
+        let mut token = Token::default();
+        // The first input takes the free token.
+        assert_eq!(token.accept_input(1, "a", now), (true, true));
+        assert_eq!(token.accept_input(1, "a", now), (true, false));
+        assert_eq!(token.accept_input(2, "b", now), (false, false));
+        token.request(2, "b", now);
+        token.request(3, "c", now);
+        assert_eq!(token.queue.len(), 2);
+        // Handed to the first in the queue on release.
+        token.release(1, now);
+        assert_eq!(token.holder_id(), Some(2));
+        assert_eq!(token.accept_input(3, "c", now), (false, false));
+        // An idle holder loses the token to the queue, then to any input.
+        let later = now + IDLE_HANDOFF;
+        assert_eq!(token.accept_input(1, "a", later), (false, true));
+        assert_eq!(token.holder_id(), Some(3));
+        let later = later + IDLE_HANDOFF;
+        assert_eq!(token.accept_input(1, "a", later), (true, true));
+        assert!(token.is_involved(1));
+        token.release(1, later);
+        assert_eq!(token.holder_id(), None);
+        assert!(!token.is_involved(1));
+    }
+
+    #[test]
+    fn test_blend() {
+        let now = Instant::now();
+        let mut blend = Blend::default();
+        let (mut x, mut y) = (100, 100);
+        blend.blend(1, &mut x, &mut y, now);
+        assert_eq!((x, y), (100, 100));
+        let (mut x, mut y) = (300, 500);
+        blend.blend(2, &mut x, &mut y, now);
+        assert_eq!((x, y), (200, 300));
+        // The moves out of the window are not blended.
+        let (mut x, mut y) = (300, 500);
+        blend.blend(2, &mut x, &mut y, now + BLEND_WINDOW);
+        assert_eq!((x, y), (300, 500));
+    }
+}
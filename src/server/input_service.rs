@@ -8,7 +8,6 @@ use crate::input::*;
 use dispatch::Queue;
 use enigo::{Enigo, Key, KeyboardControllable, MouseButton, MouseControllable};
 use hbb_common::{
-    get_time,
     message_proto::{pointer_device_event::Union::TouchEvent, touch_event::Union::ScaleUpdate},
     protobuf::EnumOrUnknown,
 };
@@ -29,6 +28,13 @@ use winapi::um::winuser::WHEEL_DELTA;
 
 const INVALID_CURSOR_POS: i32 = i32::MIN;
 const INVALID_DISPLAY_IDX: i32 = -1;
+// The cursor shape changes many times a second while dragging over the windows, each change is a
+// message to the peer, the video lags behind on the slow links. The changes are coalesced for each
+// connection: a change after a quiet time is sent at once, a shape is sent when it has not changed
+// for the settle time, and while it keeps changing, at most one per max wait. The last shape is
+// always sent.
+const CURSOR_SHAPE_SETTLE: Duration = Duration::from_millis(100);
+const CURSOR_SHAPE_MAX_WAIT: Duration = Duration::from_millis(500);
 
 #[derive(Default)]
 struct StateCursor {
@@ -37,6 +43,38 @@ struct StateCursor {
     cached_cursor_data: HashMap<u64, Arc<Message>>,
 }
 
+#[derive(Clone, Default)]
+struct CursorThrottle {
+    // The current shape of the host and when it changed.
+    hcursor: u64,
+    changed: Option<Instant>,
+    // The shape last sent to the peer.
+    sent: u64,
+    last_sent: Option<Instant>,
+}
+
+impl CursorThrottle {
+    // True if the shape is to be sent now.
+    fn on_tick(&mut self, hcursor: u64, now: Instant) -> bool {
+        if hcursor != self.hcursor {
+            self.hcursor = hcursor;
+            self.changed = Some(now);
+        }
+        if self.hcursor == self.sent {
+            return false;
+        }
+        let elapsed = |t: Option<Instant>| t.map(|t| now.saturating_duration_since(t));
+        let quiet = elapsed(self.last_sent).map_or(true, |d| d >= CURSOR_SHAPE_MAX_WAIT);
+        let settled = elapsed(self.changed).map_or(true, |d| d >= CURSOR_SHAPE_SETTLE);
+        if !quiet && !settled {
+            return false;
+        }
+        self.sent = self.hcursor;
+        self.last_sent = Some(now);
+        true
+    }
+}
+
 impl super::service::Reset for StateCursor {
     fn reset(&mut self) {
         *self = Default::default();
@@ -112,6 +150,7 @@ const KEY_CHAR_START: u64 = 9999;
 pub struct MouseCursorSub {
     inner: ConnInner,
     cached: HashMap<u64, Arc<Message>>,
+    throttle: CursorThrottle,
 }
 
 impl From<ConnInner> for MouseCursorSub {
@@ -119,6 +158,7 @@ impl From<ConnInner> for MouseCursorSub {
         Self {
             inner,
             cached: HashMap::new(),
+            throttle: CursorThrottle::default(),
         }
     }
 }
@@ -132,6 +172,10 @@ impl Subscriber for MouseCursorSub {
     #[inline]
     fn send(&mut self, msg: Arc<Message>) {
         if let Some(message::Union::CursorData(cd)) = &msg.union {
+            // The cursor service sends the current shape on every poll.
+            if !self.throttle.on_tick(cd.id, Instant::now()) {
+                return;
+            }
             if let Some(msg) = self.cached.get(&cd.id) {
                 self.inner.send(msg.clone());
             } else {
@@ -359,7 +403,7 @@ fn run_pos(sp: EmptyExtraFieldService, state: &mut StatePos) -> ResultType<()> {
             ..Default::default()
         });
         let exclude = {
-            let now = get_time();
+            let now = session_clock::now_ms();
             let lock = LATEST_PEER_INPUT_CURSOR.lock().unwrap();
             if now - lock.time < 300 {
                 lock.conn
@@ -384,15 +428,75 @@ fn run_pos(sp: EmptyExtraFieldService, state: &mut StatePos) -> ResultType<()> {
     Ok(())
 }
 
+// The cursor of a Wayland session is only known through XWayland, which doesn't see the native
+// windows, so an arrow is sent instead of nothing when the cursor isn't embedded in the frames.
+#[cfg(target_os = "linux")]
+const DEFAULT_CURSOR_ID: u64 = u64::MAX;
+
+#[cfg(target_os = "linux")]
+fn default_cursor_data() -> CursorData {
+    const ARROW: [&str; 16] = [
+        "X           ",
+        "XX          ",
+        "X.X         ",
+        "X..X        ",
+        "X...X       ",
+        "X....X      ",
+        "X.....X     ",
+        "X......X    ",
+        "X.......X   ",
+        "X........X  ",
+        "X.....XXXXX ",
+        "X..X..X     ",
+        "X.X X..X    ",
+        "XX  X..X    ",
+        "X    X..X   ",
+        "     XXXX   ",
+    ];
+    let colors = ARROW
+        .iter()
+        .flat_map(|row| row.chars())
+        .flat_map(|c| match c {
+            'X' => [0, 0, 0, 255],
+            '.' => [255, 255, 255, 255],
+            _ => [0, 0, 0, 0],
+        })
+        .collect::<Vec<u8>>();
+    CursorData {
+        id: DEFAULT_CURSOR_ID,
+        width: ARROW[0].len() as _,
+        height: ARROW.len() as _,
+        colors: colors.into(),
+        ..Default::default()
+    }
+}
+
+fn get_cursor() -> ResultType<Option<u64>> {
+    let hcursor = crate::get_cursor()?;
+    #[cfg(target_os = "linux")]
+    if hcursor.is_none() && !scrap::is_x11() && !super::display_service::capture_cursor_embedded() {
+        return Ok(Some(DEFAULT_CURSOR_ID));
+    }
+    Ok(hcursor)
+}
+
+fn get_cursor_data(hcursor: u64) -> ResultType<CursorData> {
+    #[cfg(target_os = "linux")]
+    if hcursor == DEFAULT_CURSOR_ID {
+        return Ok(default_cursor_data());
+    }
+    crate::get_cursor_data(hcursor)
+}
+
 fn run_cursor(sp: MouseCursorService, state: &mut StateCursor) -> ResultType<()> {
-    if let Some(hcursor) = crate::get_cursor()? {
+    if let Some(hcursor) = get_cursor()? {
         if hcursor != state.hcursor {
             let msg;
             if let Some(cached) = state.cached_cursor_data.get(&hcursor) {
                 super::log::trace!("Cursor data cached, hcursor: {}", hcursor);
                 msg = cached.clone();
             } else {
-                let mut data = crate::get_cursor_data(hcursor)?;
+                let mut data = get_cursor_data(hcursor)?;
                 data.colors = hbb_common::compress::compress(&data.colors[..]).into();
                 let mut tmp = Message::new();
                 tmp.set_cursor_data(data);
@@ -401,10 +505,14 @@ fn run_cursor(sp: MouseCursorService, state: &mut StateCursor) -> ResultType<()>
                 super::log::trace!("Cursor data updated, hcursor: {}", hcursor);
             }
             state.hcursor = hcursor;
-            sp.send_shared(msg.clone());
             state.cursor_data = msg;
         }
     }
+    if state.cursor_data.union.is_some() {
+        // Each peer coalesces the changes with its own `CursorThrottle`, and gets the shapes it
+        // has already as ids only, see `MouseCursorSub`.
+        sp.send_shared(state.cursor_data.clone());
+    }
     sp.snapshot(|sps| {
         sps.send_shared(state.cursor_data.clone());
         Ok(())
@@ -699,7 +807,59 @@ fn get_modifier_state(key: Key, en: &mut Enigo) -> bool {
     }
 }
 
+// The peer which supports the rotation metadata shows the rotated frames,
+// so the position is in the rotated display, and it should be converted back to the raw display.
+pub fn rotate_mouse_event_to_raw(evt: &mut MouseEvent) {
+    let evt_type = evt.mask & 0x7;
+    if evt_type == MOUSE_TYPE_WHEEL || evt_type == MOUSE_TYPE_TRACKPAD {
+        return;
+    }
+    if let Some((x, y)) =
+        rotate_position_to_raw(evt.x, evt.y, &display_service::get_sync_displays())
+    {
+        evt.x = x;
+        evt.y = y;
+    }
+}
+
+// The raw position of `(x, y)` if it is on a display with a rotation the capturer left to the peer.
+fn rotate_position_to_raw(x: i32, y: i32, displays: &[DisplayInfo]) -> Option<(i32, i32)> {
+    for d in displays {
+        if d.raw_rotation == 0 {
+            continue;
+        }
+        let (w, h) = if d.raw_rotation % 180 == 90 {
+            (d.height, d.width)
+        } else {
+            (d.width, d.height)
+        };
+        if x >= d.x && x < d.x + w && y >= d.y && y < d.y + h {
+            let (rx, ry) = rotate_point_to_raw(x - d.x, y - d.y, d.width, d.height, d.raw_rotation);
+            return Some((d.x + rx, d.y + ry));
+        }
+    }
+    None
+}
+
+// `(x, y)` is the position in the frame rotated clockwise by `rotation`.
+// `(w, h)` is the size of the raw frame.
+fn rotate_point_to_raw(x: i32, y: i32, w: i32, h: i32, rotation: i32) -> (i32, i32) {
+    match rotation.rem_euclid(360) {
+        90 => (y, h - 1 - x),
+        180 => (w - 1 - x, h - 1 - y),
+        270 => (w - 1 - y, x),
+        _ => (x, y),
+    }
+}
+
 pub fn handle_mouse(evt: &MouseEvent, conn: i32) {
+    if accessibility::shape_mouse(evt, conn) {
+        return;
+    }
+    dispatch_mouse(evt, conn);
+}
+
+fn dispatch_mouse(evt: &MouseEvent, conn: i32) {
     #[cfg(target_os = "macos")]
     {
         // having GUI (--server has tray, it is GUI too), run main GUI thread, otherwise crash
@@ -713,6 +873,211 @@ pub fn handle_mouse(evt: &MouseEvent, conn: i32) {
     handle_mouse_(evt, conn);
 }
 
+// Input shaping for the users with motor impairments, off unless the options are set.
+//
+// The absolute moves are interpolated from the current position to the target over
+// "accessibility-cursor-smoothing-ms", by a worker which injects the intermediate moves.
+// The buttons wait for the cursor to reach the target, and a button down is held back for
+// "accessibility-click-delay-ms", so a click lands where the user sees the cursor. The waits are
+// on a worker of the connection, its mouse events queue up behind the button in the meantime, so
+// the input thread shared by the connections is not blocked.
+// The shaping happens before the dispatch, so it works the same with the portable service and
+// doesn't block the main queue on macOS.
+mod accessibility {
+    use super::*;
+
+    const OPTION_CURSOR_SMOOTHING_MS: &str = "accessibility-cursor-smoothing-ms";
+    const OPTION_CLICK_DELAY_MS: &str = "accessibility-click-delay-ms";
+    const MAX_CURSOR_SMOOTHING_MS: u64 = 1_000;
+    const MAX_CLICK_DELAY_MS: u64 = 2_000;
+    const STEP: Duration = Duration::from_millis(8);
+    // The worker exits after being idle for a while, it's started again by the next move.
+    const WORKER_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[derive(Default)]
+    struct Smoother {
+        conn: i32,
+        modifiers: Vec<EnumOrUnknown<ControlKey>>,
+        from: (f64, f64),
+        current: (f64, f64),
+        target: (i32, i32),
+        started: Option<Instant>,
+        duration: Duration,
+        // The position is unknown before the first move.
+        initialized: bool,
+        worker_running: bool,
+    }
+
+    impl Smoother {
+        fn is_idle(&self) -> bool {
+            self.started.is_none()
+        }
+    }
+
+    lazy_static::lazy_static! {
+        static ref SMOOTHER: Mutex<Smoother> = Default::default();
+        // The mouse events of the connections from a button on, while the button waits.
+        static ref DEFERRED: Mutex<HashMap<i32, std::collections::VecDeque<MouseEvent>>> =
+            Default::default();
+    }
+
+    fn get_ms(option: &str, max: u64) -> u64 {
+        Config::get_option(option)
+            .parse::<u64>()
+            .unwrap_or(0)
+            .min(max)
+    }
+
+    /// Return true if the event is taken over, false if it should be dispatched now.
+    pub(super) fn shape_mouse(evt: &MouseEvent, conn: i32) -> bool {
+        if defer(evt, conn, false) {
+            return true;
+        }
+        let smoothing = get_ms(OPTION_CURSOR_SMOOTHING_MS, MAX_CURSOR_SMOOTHING_MS);
+        let click_delay = get_ms(OPTION_CLICK_DELAY_MS, MAX_CLICK_DELAY_MS);
+        if smoothing == 0 && click_delay == 0 {
+            return false;
+        }
+        let evt_type = evt.mask & 0x7;
+        if evt_type == MOUSE_TYPE_MOVE {
+            if smoothing == 0 {
+                return false;
+            }
+            move_to(evt, conn, Duration::from_millis(smoothing));
+            return true;
+        }
+        if evt_type == MOUSE_TYPE_DOWN || evt_type == MOUSE_TYPE_UP {
+            return defer(evt, conn, true);
+        }
+        false
+    }
+
+    // Queue the event behind the waiting button of the connection, or as the first one if
+    // `button`. False if there's nothing to queue behind.
+    fn defer(evt: &MouseEvent, conn: i32, button: bool) -> bool {
+        let mut lock = DEFERRED.lock().unwrap();
+        match lock.get_mut(&conn) {
+            Some(queue) => queue.push_back(evt.clone()),
+            None if button => {
+                lock.insert(conn, [evt.clone()].into());
+                std::thread::spawn(move || run_deferred(conn));
+            }
+            None => return false,
+        }
+        true
+    }
+
+    // The event stays in the queue until it's dispatched, so the next ones queue up behind it.
+    fn run_deferred(conn: i32) {
+        loop {
+            let evt = {
+                let mut lock = DEFERRED.lock().unwrap();
+                match lock.get(&conn).and_then(|q| q.front().cloned()) {
+                    Some(evt) => evt,
+                    None => {
+                        lock.remove(&conn);
+                        return;
+                    }
+                }
+            };
+            let smoothing = get_ms(OPTION_CURSOR_SMOOTHING_MS, MAX_CURSOR_SMOOTHING_MS);
+            let click_delay = get_ms(OPTION_CLICK_DELAY_MS, MAX_CLICK_DELAY_MS);
+            let evt_type = evt.mask & 0x7;
+            if evt_type == MOUSE_TYPE_MOVE && smoothing > 0 {
+                move_to(&evt, conn, Duration::from_millis(smoothing));
+            } else {
+                if evt_type == MOUSE_TYPE_DOWN || evt_type == MOUSE_TYPE_UP {
+                    wait_for_target(Duration::from_millis(smoothing) + Duration::from_millis(100));
+                    if evt_type == MOUSE_TYPE_DOWN && click_delay > 0 {
+                        std::thread::sleep(Duration::from_millis(click_delay));
+                    }
+                }
+                dispatch_mouse(&evt, conn);
+            }
+            if let Some(queue) = DEFERRED.lock().unwrap().get_mut(&conn) {
+                queue.pop_front();
+            }
+        }
+    }
+
+    fn move_to(evt: &MouseEvent, conn: i32, duration: Duration) {
+        let mut lock = SMOOTHER.lock().unwrap();
+        if !lock.initialized {
+            lock.initialized = true;
+            lock.current = (evt.x as _, evt.y as _);
+        }
+        lock.conn = conn;
+        lock.modifiers = evt.modifiers.clone();
+        lock.from = lock.current;
+        lock.target = (evt.x, evt.y);
+        lock.started = Some(Instant::now());
+        lock.duration = duration;
+        *LATEST_PEER_INPUT_CURSOR.lock().unwrap() = Input {
+            conn,
+            time: session_clock::now_ms(),
+            x: evt.x,
+            y: evt.y,
+        };
+        if !lock.worker_running {
+            lock.worker_running = true;
+            std::thread::spawn(run_worker);
+        }
+    }
+
+    fn wait_for_target(timeout: Duration) {
+        let start = Instant::now();
+        while !SMOOTHER.lock().unwrap().is_idle() && start.elapsed() < timeout {
+            std::thread::sleep(STEP);
+        }
+    }
+
+    fn run_worker() {
+        let mut idle_since = Instant::now();
+        loop {
+            std::thread::sleep(STEP);
+            let mut lock = SMOOTHER.lock().unwrap();
+            let Some(started) = lock.started else {
+                if idle_since.elapsed() > WORKER_IDLE_TIMEOUT || EXITING.load(Ordering::SeqCst) {
+                    lock.worker_running = false;
+                    break;
+                }
+                continue;
+            };
+            idle_since = Instant::now();
+            let t = if lock.duration.is_zero() {
+                1.0
+            } else {
+                (started.elapsed().as_secs_f64() / lock.duration.as_secs_f64()).min(1.0)
+            };
+            // Ease out, the cursor slows down when it's getting close to the target.
+            let k = 1.0 - (1.0 - t) * (1.0 - t);
+            let (fx, fy) = lock.from;
+            let (tx, ty) = (lock.target.0 as f64, lock.target.1 as f64);
+            lock.current = (fx + (tx - fx) * k, fy + (ty - fy) * k);
+            if t >= 1.0 {
+                lock.current = (tx, ty);
+            }
+            let evt = MouseEvent {
+                mask: MOUSE_TYPE_MOVE,
+                x: lock.current.0.round() as _,
+                y: lock.current.1.round() as _,
+                modifiers: lock.modifiers.clone(),
+                ..Default::default()
+            };
+            let conn = lock.conn;
+            drop(lock);
+            dispatch_mouse(&evt, conn);
+            if t >= 1.0 {
+                // Idle only after the last move is dispatched, unless there's a new target.
+                let mut lock = SMOOTHER.lock().unwrap();
+                if lock.started == Some(started) {
+                    lock.started = None;
+                }
+            }
+        }
+    }
+}
+
 // to-do: merge handle_mouse and handle_pointer
 pub fn handle_pointer(evt: &PointerDeviceEvent, conn: i32) {
     #[cfg(target_os = "macos")]
@@ -741,6 +1106,12 @@ pub fn fix_key_down_timeout_loop() {
     }
 }
 
+/// Release all the keys still held down, without blocking the later input.
+#[inline]
+pub fn release_keys_down() {
+    fix_key_down_timeout(true);
+}
+
 pub fn fix_key_down_timeout_at_exit() {
     if EXITING.load(Ordering::SeqCst) {
         return;
@@ -832,6 +1203,46 @@ fn fix_key_down_timeout(force: bool) {
     }
 }
 
+/// Key down events injected for one connection, so they can be released
+/// immediately when the peer window loses focus.
+#[derive(Default)]
+pub struct InjectedKeys(Vec<KeyEvent>);
+
+impl InjectedKeys {
+    fn is_same_key(a: &KeyEvent, b: &KeyEvent) -> bool {
+        a.mode == b.mode && a.union == b.union
+    }
+
+    pub fn on_key_event(&mut self, evt: &KeyEvent, is_press: bool) {
+        // A press is released right after it is injected.
+        if is_press {
+            return;
+        }
+        self.0.retain(|x| !Self::is_same_key(x, evt));
+        if evt.down {
+            let mut evt = evt.clone();
+            evt.modifiers.clear();
+            self.0.push(evt);
+        }
+    }
+
+    /// Take the key up events of all the keys still held down.
+    pub fn take_releases(&mut self) -> Vec<KeyEvent> {
+        self.0
+            .drain(..)
+            .map(|mut evt| {
+                evt.down = false;
+                evt
+            })
+            .collect()
+    }
+}
+
+/// Release the modifiers which are down on this side but not held by the peer.
+pub fn release_unheld_modifiers(modifiers: &[EnumOrUnknown<ControlKey>]) {
+    fix_modifiers(modifiers, &mut ENIGO.lock().unwrap(), -1);
+}
+
 // e.g. current state of ctrl is down, but ctrl not in modifier, we should change ctrl to up, to make modifier state sync between remote and local
 #[inline]
 fn fix_modifier(
@@ -884,7 +1295,7 @@ fn fix_modifiers(modifiers: &[EnumOrUnknown<ControlKey>], en: &mut Enigo, ck: i3
 pub fn update_latest_input_cursor_time(conn: i32) {
     let mut lock = LATEST_PEER_INPUT_CURSOR.lock().unwrap();
     lock.conn = conn;
-    lock.time = get_time();
+    lock.time = session_clock::now_ms();
 }
 
 #[inline]
@@ -975,10 +1386,57 @@ pub fn handle_pointer_(evt: &PointerDeviceEvent, conn: i32) {
             }
             _ => {}
         },
+        Some(pointer_device_event::Union::ScrollGesture(g)) => {
+            super::scroll_gesture::handle(g, conn);
+        }
         _ => {}
     }
 }
 
+/// Scroll by the steps of a scroll gesture, see `scroll_gesture`.
+pub(super) fn scroll_gesture_steps(x: i32, y: i32) {
+    if x == 0 && y == 0 {
+        return;
+    }
+    let mut en = ENIGO.lock().unwrap();
+    // The same directions as the wheel events.
+    let x = -x;
+    #[cfg(not(windows))]
+    let y = -y;
+    #[cfg(target_os = "macos")]
+    {
+        // In pixels.
+        if y != 0 {
+            en.mouse_scroll_y(y, true);
+        }
+        if x != 0 {
+            en.mouse_scroll_x(x, true);
+        }
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        if y != 0 {
+            en.mouse_scroll_y(y);
+        }
+        if x != 0 {
+            en.mouse_scroll_x(x);
+        }
+    }
+}
+
+/// The steps of the flick of a scroll gesture, from its thread. Returns false if the flick must
+/// stop.
+pub(super) fn scroll_gesture_flick_steps(conn: i32, x: i32, y: i32) -> bool {
+    if EXITING.load(Ordering::SeqCst) || !active_mouse_(conn) {
+        return false;
+    }
+    #[cfg(target_os = "macos")]
+    QUEUE.exec_async(move || scroll_gesture_steps(x, y));
+    #[cfg(not(target_os = "macos"))]
+    scroll_gesture_steps(x, y);
+    true
+}
+
 pub fn handle_mouse_(evt: &MouseEvent, conn: i32) {
     if !active_mouse_(conn) {
         return;
@@ -1020,9 +1478,13 @@ pub fn handle_mouse_(evt: &MouseEvent, conn: i32) {
     match evt_type {
         MOUSE_TYPE_MOVE => {
             en.mouse_move_to(evt.x, evt.y);
+            #[cfg(target_os = "linux")]
+            if !scrap::is_x11() {
+                scrap::wayland::pipewire::on_pointer_moved();
+            }
             *LATEST_PEER_INPUT_CURSOR.lock().unwrap() = Input {
                 conn,
-                time: get_time(),
+                time: session_clock::now_ms(),
                 x: evt.x,
                 y: evt.y,
             };
@@ -1186,6 +1648,8 @@ pub async fn lock_screen() {
 #[inline]
 #[cfg(target_os = "linux")]
 pub fn handle_key(evt: &KeyEvent) {
+    // The injected keys carry no mark on X11, the listener of the host keys is told instead.
+    let _injecting = super::host_key_listener::Injecting::new();
     handle_key_(evt);
 }
 
@@ -1875,3 +2339,141 @@ lazy_static::lazy_static! {
         (ControlKey::Delete, true),
     ].iter().map(|(a, b)| (a.value(), b.clone())).collect();
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key_event(union: key_event::Union, down: bool) -> KeyEvent {
+        KeyEvent {
+            down,
+            mode: KeyboardMode::Map.into(),
+            union: Some(union),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_injected_keys_released_on_focus_loss() {
+        let mut keys = InjectedKeys::default();
+        let ctrl = key_event::Union::ControlKey(ControlKey::Control.into());
+        let a = key_event::Union::Chr(30);
+        let b = key_event::Union::Chr(48);
+        keys.on_key_event(&key_event(ctrl.clone(), true), false);
+        keys.on_key_event(&key_event(a.clone(), true), false);
+        // Repeated key down is tracked only once.
+        keys.on_key_event(&key_event(a.clone(), true), false);
+        keys.on_key_event(&key_event(b.clone(), true), false);
+        keys.on_key_event(&key_event(b, false), false);
+        // A press does not leave the key down.
+        keys.on_key_event(&key_event(key_event::Union::Chr(31), true), true);
+
+        let releases = keys.take_releases();
+        assert_eq!(releases.len(), 2);
+        assert!(releases.iter().all(|x| !x.down));
+        assert_eq!(releases[0].union, Some(ctrl));
+        assert_eq!(releases[1].union, Some(a));
+        assert!(keys.take_releases().is_empty());
+    }
+
+    #[test]
+    fn test_rotate_point_to_raw() {
+        // raw frame 4x2, (1, 0) in the raw frame
+        let (w, h) = (4, 2);
+        assert_eq!(rotate_point_to_raw(1, 0, w, h, 0), (1, 0));
+        // rotated 90: 2x4, the raw (1, 0) is at (h - 1 - 0, 1)
+        assert_eq!(rotate_point_to_raw(1, 1, w, h, 90), (1, 0));
+        // rotated 180: 4x2, the raw (1, 0) is at (w - 1 - 1, h - 1 - 0)
+        assert_eq!(rotate_point_to_raw(2, 1, w, h, 180), (1, 0));
+        // rotated 270: 2x4, the raw (1, 0) is at (0, w - 1 - 1)
+        assert_eq!(rotate_point_to_raw(0, 2, w, h, 270), (1, 0));
+        assert_eq!(rotate_point_to_raw(0, 2, w, h, -90), (1, 0));
+    }
+
+    #[test]
+    fn test_rotate_position_to_raw() {
+        let display = |x, raw_rotation| DisplayInfo {
+            x,
+            y: 0,
+            width: 1920,
+            height: 1080,
+            rotation: 90,
+            raw_rotation,
+            ..Default::default()
+        };
+        // Both displays are in portrait, only the first one is sent unrotated.
+        let displays = [display(0, 90), display(1080, 0)];
+        // The top right of the rotated display is the top left of the raw one.
+        assert_eq!(rotate_position_to_raw(1079, 0, &displays), Some((0, 0)));
+        assert_eq!(
+            rotate_position_to_raw(0, 1919, &displays),
+            Some((1919, 1079))
+        );
+        assert_eq!(rotate_position_to_raw(10, 20, &displays), Some((20, 1069)));
+        // The capturer rotated the second display, the position is already raw.
+        assert_eq!(rotate_position_to_raw(1090, 20, &displays), None);
+        assert_eq!(rotate_position_to_raw(10, 20, &[display(0, 0)]), None);
+    }
+
+    #[test]
+    fn test_rotate_point_to_raw_corners() {
+        let (w, h) = (1920, 1080);
+        for rotation in [0, 90, 180, 270] {
+            let (rw, rh) = if rotation % 180 == 90 { (h, w) } else { (w, h) };
+            let mut raw = vec![];
+            for (x, y) in [(0, 0), (rw - 1, 0), (0, rh - 1), (rw - 1, rh - 1)] {
+                let (x, y) = rotate_point_to_raw(x, y, w, h, rotation);
+                assert!(x >= 0 && x < w && y >= 0 && y < h);
+                raw.push((x, y));
+            }
+            raw.sort();
+            assert_eq!(raw, vec![(0, 0), (0, h - 1), (w - 1, 0), (w - 1, h - 1)]);
+        }
+    }
+
+    #[test]
+    fn test_cursor_throttle_drag() {
+        // A drag over the windows, the shape changes on every poll of the cursor service.
+        let start = Instant::now();
+        let tick = Duration::from_millis(33);
+        let shapes = [11, 12, 13, 14];
+        // The second connection subscribes in the middle of the drag.
+        let mut throttles = [CursorThrottle::default(), CursorThrottle::default()];
+        let mut sent = [vec![], vec![]];
+        let mut changes = 0;
+        let mut poll = |throttles: &mut [CursorThrottle; 2], i: u32, hcursor: u64| {
+            for (conn, throttle) in throttles.iter_mut().enumerate() {
+                if (conn == 0 || i >= 45) && throttle.on_tick(hcursor, start + tick * i) {
+                    sent[conn].push((i, hcursor));
+                }
+            }
+        };
+        for i in 0..90 {
+            changes += 1;
+            poll(&mut throttles, i, shapes[i as usize % shapes.len()]);
+        }
+        // The drag ends on another shape, it is sent soon even if nothing changes after it.
+        let last = 15;
+        for i in 90..100 {
+            poll(&mut throttles, i, last);
+        }
+        for sent in sent.iter() {
+            assert_eq!(sent.last().map(|s| s.1), Some(last));
+            assert!(sent.last().unwrap().0 <= 90 + 4, "{:?}", sent);
+            // At most ~10 a second.
+            for w in sent.windows(2) {
+                assert!(tick * (w[1].0 - w[0].0) >= CURSOR_SHAPE_SETTLE, "{:?}", sent);
+            }
+        }
+        // The new connection gets the shape at once.
+        assert_eq!(sent[1][0], (45, shapes[45 % shapes.len()]));
+        // An order of magnitude less than the changes.
+        assert!(sent[0].len() * 10 <= changes, "{} of {}", sent[0].len(), changes);
+        // Nothing more while the shape is not changed.
+        let now = start + tick * 100;
+        assert!(!throttles[0].on_tick(last, now));
+        assert!(!throttles[0].on_tick(last, now + tick * 10));
+        // A single change after a quiet time is sent at once.
+        assert!(throttles[0].on_tick(11, now + tick * 20));
+    }
+}
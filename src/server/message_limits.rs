@@ -0,0 +1,241 @@
+// Size and count limits of the inbound messages.
+//
+// They are checked right after the message is parsed and before any permission check,
+// so a malformed or malicious peer can't make us hold absurdly large data. Only the file
+// chunks may be legitimately large, their limit can be raised with "max-file-block-size".
+// The compressed clipboards and file chunks are limited by their size after the decompression,
+// which is counted without holding the data, so a small message can't expand to gigabytes in
+// the handlers.
+
+use hbb_common::{bail, config::Config, message_proto::*, protobuf::Message as _, ResultType};
+use std::io::Read;
+
+const OPTION_MAX_FILE_BLOCK_SIZE: &str = "max-file-block-size"; // KB
+pub const DEFAULT_MAX_FILE_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+pub const MAX_CLIPBOARD_SIZE: usize = 32 * 1024 * 1024;
+pub const MAX_CLIPBOARDS_TOTAL_SIZE: usize = 64 * 1024 * 1024;
+pub const MAX_CLIPBOARDS: usize = 16;
+pub const MAX_FILE_ENTRIES: usize = 1_000_000;
+pub const MAX_PATH_LEN: usize = 32 * 1024;
+pub const MAX_CHAT_MESSAGE_LEN: usize = 64 * 1024;
+pub const MAX_KEY_SEQ_LEN: usize = 64 * 1024;
+pub const MAX_TERMINAL_DATA_SIZE: usize = 4 * 1024 * 1024;
+pub const MAX_AUDIO_FRAME_SIZE: usize = 1024 * 1024;
+// Room for the fields other than the payload.
+const MESSAGE_OVERHEAD: usize = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct MessageLimits {
+    max_file_block_size: usize,
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        Self {
+            max_file_block_size: DEFAULT_MAX_FILE_BLOCK_SIZE,
+        }
+    }
+}
+
+impl MessageLimits {
+    pub fn new() -> Self {
+        let max_file_block_size = Config::get_option(OPTION_MAX_FILE_BLOCK_SIZE)
+            .parse::<usize>()
+            .ok()
+            .filter(|x| *x > 0)
+            .map(|x| (x * 1024).max(DEFAULT_MAX_FILE_BLOCK_SIZE))
+            .unwrap_or(DEFAULT_MAX_FILE_BLOCK_SIZE);
+        Self {
+            max_file_block_size,
+        }
+    }
+
+    #[inline]
+    pub fn max_message_size(&self) -> usize {
+        self.max_file_block_size.max(MAX_CLIPBOARDS_TOTAL_SIZE) + MESSAGE_OVERHEAD
+    }
+
+    /// Check the raw size, parse and check the message.
+    pub fn parse(&self, bytes: &[u8]) -> ResultType<Message> {
+        if bytes.len() > self.max_message_size() {
+            bail!(
+                "message of {} bytes exceeds {}",
+                bytes.len(),
+                self.max_message_size()
+            );
+        }
+        let msg = Message::parse_from_bytes(bytes)?;
+        self.check(&msg)?;
+        Ok(msg)
+    }
+
+    pub fn check(&self, msg: &Message) -> ResultType<()> {
+        match &msg.union {
+            Some(message::Union::Clipboard(cb)) => {
+                check_clipboard(cb)?;
+            }
+            Some(message::Union::MultiClipboards(mcb)) => {
+                check_len("clipboards", mcb.clipboards.len(), MAX_CLIPBOARDS)?;
+                let mut total = 0;
+                for cb in mcb.clipboards.iter() {
+                    total += check_clipboard(cb)?;
+                }
+                check_len("clipboards total", total, MAX_CLIPBOARDS_TOTAL_SIZE)?;
+            }
+            Some(message::Union::Cliprdr(clip)) => match &clip.union {
+                Some(cliprdr::Union::FormatDataResponse(r)) => {
+                    check_len("format data", r.format_data.len(), MAX_CLIPBOARD_SIZE)?;
+                }
+                Some(cliprdr::Union::FileContentsResponse(r)) => {
+                    check_len(
+                        "file contents",
+                        r.requested_data.len(),
+                        self.max_file_block_size,
+                    )?;
+                }
+                _ => {}
+            },
+            Some(message::Union::FileAction(fa)) => Self::check_file_action(fa)?,
+            Some(message::Union::FileResponse(fr)) => match &fr.union {
+                Some(file_response::Union::Block(block)) => {
+                    let max = self.max_file_block_size;
+                    check_len("file block", block.data.len(), max)?;
+                    if block.compressed {
+                        check_len("file block", decompressed_len(&block.data, max)?, max)?;
+                    }
+                }
+                Some(file_response::Union::Dir(dir)) => {
+                    check_len("file entries", dir.entries.len(), MAX_FILE_ENTRIES)?;
+                    check_len("path", dir.path.len(), MAX_PATH_LEN)?;
+                }
+                _ => {}
+            },
+            Some(message::Union::KeyEvent(ke)) => {
+                if let Some(key_event::Union::Seq(seq)) = &ke.union {
+                    check_len("key sequence", seq.len(), MAX_KEY_SEQ_LEN)?;
+                }
+            }
+            Some(message::Union::Misc(misc)) => {
+                if let Some(misc::Union::ChatMessage(c)) = &misc.union {
+                    check_len("chat message", c.text.len(), MAX_CHAT_MESSAGE_LEN)?;
+                }
+            }
+            Some(message::Union::TerminalAction(action)) => {
+                if let Some(terminal_action::Union::Data(data)) = &action.union {
+                    check_len("terminal data", data.data.len(), MAX_TERMINAL_DATA_SIZE)?;
+                }
+            }
+            Some(message::Union::AudioFrame(frame)) => {
+                check_len("audio frame", frame.data.len(), MAX_AUDIO_FRAME_SIZE)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn check_file_action(fa: &FileAction) -> ResultType<()> {
+        let path = match &fa.union {
+            Some(file_action::Union::ReadDir(rd)) => &rd.path,
+            Some(file_action::Union::ReadEmptyDirs(rd)) => &rd.path,
+            Some(file_action::Union::AllFiles(f)) => &f.path,
+            Some(file_action::Union::Send(s)) => &s.path,
+            Some(file_action::Union::Receive(r)) => {
+                check_len("file entries", r.files.len(), MAX_FILE_ENTRIES)?;
+                for f in r.files.iter() {
+                    check_len("path", f.name.len(), MAX_PATH_LEN)?;
+                }
+                &r.path
+            }
+            Some(file_action::Union::RemoveDir(d)) => &d.path,
+            Some(file_action::Union::RemoveFile(f)) => &f.path,
+            Some(file_action::Union::Create(c)) => &c.path,
+            Some(file_action::Union::Rename(r)) => {
+                check_len("path", r.new_name.len(), MAX_PATH_LEN)?;
+                &r.path
+            }
+            _ => return Ok(()),
+        };
+        check_len("path", path.len(), MAX_PATH_LEN)
+    }
+}
+
+// The size of the clipboard after the decompression.
+fn check_clipboard(cb: &Clipboard) -> ResultType<usize> {
+    check_len("clipboard", cb.content.len(), MAX_CLIPBOARD_SIZE)?;
+    let len = if cb.compress {
+        decompressed_len(&cb.content, MAX_CLIPBOARD_SIZE)?
+    } else {
+        cb.content.len()
+    };
+    check_len("clipboard", len, MAX_CLIPBOARD_SIZE)?;
+    Ok(len)
+}
+
+/// The size of the zstd data after the decompression, it stops after `max` + 1 bytes.
+pub fn decompressed_len(data: &[u8], max: usize) -> ResultType<usize> {
+    let decoder = zstd::stream::read::Decoder::new(data)?;
+    let len = std::io::copy(&mut decoder.take(max as u64 + 1), &mut std::io::sink())?;
+    Ok(len as _)
+}
+
+#[inline]
+fn check_len(what: &str, len: usize, max: usize) -> ResultType<()> {
+    if len > max {
+        bail!("{} of {} exceeds {}", what, len, max);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_message_limits() {
+        let limits = MessageLimits::default();
+        let mut msg = Message::new();
+        msg.set_multi_clipboards(MultiClipboards {
+            clipboards: vec![Clipboard::default(); MAX_CLIPBOARDS + 1],
+            ..Default::default()
+        });
+        assert!(limits.check(&msg).is_err());
+
+        let mut msg = Message::new();
+        msg.set_file_response(FileResponse {
+            union: Some(file_response::Union::Block(FileTransferBlock {
+                data: vec![0; DEFAULT_MAX_FILE_BLOCK_SIZE].into(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        });
+        let bytes = msg.write_to_bytes().unwrap();
+        assert!(limits.parse(&bytes).is_ok());
+        let limits = MessageLimits {
+            max_file_block_size: DEFAULT_MAX_FILE_BLOCK_SIZE - 1,
+        };
+        assert!(limits.parse(&bytes).is_err());
+
+        assert!(limits.parse(&[0xFF; 16]).is_err());
+
+        // A few KB which expand beyond the limit.
+        let bomb = hbb_common::compress::compress(&vec![0; MAX_CLIPBOARD_SIZE + 1]);
+        assert!(bomb.len() < MAX_CLIPBOARD_SIZE / 1024);
+        let clipboard = |content: Vec<u8>| Clipboard {
+            compress: true,
+            content: content.into(),
+            format: ClipboardFormat::Text.into(),
+            ..Default::default()
+        };
+        let mut msg = Message::new();
+        msg.set_clipboard(clipboard(bomb));
+        assert!(limits.check(&msg).is_err());
+        let mut msg = Message::new();
+        msg.set_clipboard(clipboard(hbb_common::compress::compress(b"text")));
+        assert!(limits.check(&msg).is_ok());
+        msg.set_clipboard(clipboard(b"not zstd".to_vec()));
+        assert!(limits.check(&msg).is_err());
+        assert!(limits
+            .parse(&vec![0; limits.max_message_size() + 1])
+            .is_err());
+    }
+}
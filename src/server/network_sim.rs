@@ -0,0 +1,224 @@
+// Simulation of a bad network on the send path of a connection, for development and for
+// reproducing the reports of the users on poor links.
+//
+// The messages are held by the connection after they are taken from the queues, and the next
+// one is only taken when the simulated link is free, so the frame fetching, the delay test and
+// the video QoS see the same back-pressure as on a real link. A message is serialized at the
+// capped bandwidth when the link is free, then sent after the added rtt and jitter, the
+// deliveries keep the order of the messages. The send loop goes on while they are held.
+// A lost packet is retransmitted by TCP, so the loss adds an rtt to the message instead of
+// dropping it.
+//
+// It can't be enabled in the release builds unless "allow-network-sim" is "Y".
+
+use hbb_common::{
+    config::Config,
+    log,
+    rand::random,
+    tokio::time::Instant,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+const OPTION_ALLOW_NETWORK_SIM: &str = "allow-network-sim";
+// The minimum retransmission timeout of TCP.
+const MIN_RTO: Duration = Duration::from_millis(200);
+
+lazy_static::lazy_static! {
+    static ref SIMS: Mutex<HashMap<i32, NetworkSim>> = Default::default();
+}
+// Skip the lookup when nothing is simulated.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct NetworkSim {
+    pub rtt_ms: u32,
+    pub jitter_ms: u32,
+    pub loss_pct: u32,
+    // 0 means unlimited.
+    pub kbps: u32,
+}
+
+impl NetworkSim {
+    fn is_none(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[inline]
+pub fn is_allowed() -> bool {
+    cfg!(debug_assertions) || Config::get_option(OPTION_ALLOW_NETWORK_SIM) == "Y"
+}
+
+/// Set the simulation of the connection, all zeros to clear it.
+pub fn set(conn_id: i32, sim: NetworkSim) -> bool {
+    if !is_allowed() {
+        log::warn!(
+            "Network simulation is not allowed, set \"{}\" to \"Y\" to enable it",
+            OPTION_ALLOW_NETWORK_SIM
+        );
+        return false;
+    }
+    let mut sims = SIMS.lock().unwrap();
+    if sim.is_none() {
+        sims.remove(&conn_id);
+    } else {
+        sims.insert(conn_id, sim);
+    }
+    log::info!("Network simulation of connection {}: {:?}", conn_id, sim);
+    ACTIVE.store(!sims.is_empty(), Ordering::SeqCst);
+    true
+}
+
+pub fn remove(conn_id: i32) {
+    let mut sims = SIMS.lock().unwrap();
+    if sims.remove(&conn_id).is_some() {
+        ACTIVE.store(!sims.is_empty(), Ordering::SeqCst);
+    }
+}
+
+fn get(conn_id: i32) -> Option<NetworkSim> {
+    if !ACTIVE.load(Ordering::Relaxed) || !is_allowed() {
+        return None;
+    }
+    SIMS.lock().unwrap().get(&conn_id).cloned()
+}
+
+/// The state of the simulated link of a connection.
+#[derive(Default)]
+pub struct Link {
+    free_at: Option<Instant>,
+    last_delivery: Option<Instant>,
+    // (delivery time, the encoded message)
+    held: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl Link {
+    /// Hold the message queued at `queued` on the simulated link, it's returned if it can be sent
+    /// at once.
+    pub fn hold(&mut self, conn_id: i32, queued: Instant, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        let delivery = match get(conn_id) {
+            Some(sim) => self.delivery_time(&sim, queued, bytes.len(), random::<u32>()),
+            None => {
+                self.free_at = None;
+                self.last_delivery = None;
+                // Behind the messages held before the simulation was cleared.
+                match self.held.back() {
+                    Some((delivery, _)) => *delivery,
+                    None => return Some(bytes),
+                }
+            }
+        };
+        self.held.push_back((delivery, bytes));
+        None
+    }
+
+    /// False while the link is busy with the last message, the next one waits in the queues.
+    pub fn is_free(&self) -> bool {
+        self.free_at.map_or(true, |t| t <= Instant::now())
+    }
+
+    /// When the send loop has to wake up, for the next delivery or for the link to be free.
+    pub fn deadline(&self) -> Option<Instant> {
+        let free_at = self.free_at.filter(|t| *t > Instant::now());
+        match (self.held.front().map(|(t, _)| *t), free_at) {
+            (Some(delivery), Some(free_at)) => Some(delivery.min(free_at)),
+            (delivery, free_at) => delivery.or(free_at),
+        }
+    }
+
+    /// Take the next message delivered by now.
+    pub fn take_delivered(&mut self) -> Option<Vec<u8>> {
+        if self.held.front()?.0 > Instant::now() {
+            return None;
+        }
+        self.held.pop_front().map(|(_, bytes)| bytes)
+    }
+
+    fn delivery_time(
+        &mut self,
+        sim: &NetworkSim,
+        queued: Instant,
+        size: usize,
+        rnd: u32,
+    ) -> Instant {
+        let start = self.free_at.map(|t| t.max(queued)).unwrap_or(queued);
+        let sent = if sim.kbps > 0 {
+            start + Duration::from_micros(size as u64 * 8 * 1000 / sim.kbps as u64)
+        } else {
+            start
+        };
+        self.free_at = Some(sent);
+        let rtt = Duration::from_millis(sim.rtt_ms as _);
+        let mut delay = rtt;
+        if sim.jitter_ms > 0 {
+            delay += Duration::from_millis((rnd % (sim.jitter_ms + 1)) as _);
+        }
+        if sim.loss_pct > 0 && (rnd >> 8) % 100 < sim.loss_pct {
+            delay += (rtt * 2).max(MIN_RTO);
+        }
+        let delivery = self
+            .last_delivery
+            .map(|t| t.max(sent + delay))
+            .unwrap_or(sent + delay);
+        self.last_delivery = Some(delivery);
+        delivery
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_delivery_time() {
+        let now = Instant::now();
+        let sim = NetworkSim {
+            rtt_ms: 100,
+            kbps: 80,
+            ..Default::default()
+        };
+        let mut link = Link::default();
+        // 1000 bytes take 100ms at 80kbps.
+        assert_eq!(
+            link.delivery_time(&sim, now, 1000, 0),
+            now + Duration::from_millis(200)
+        );
+        // Queued behind the first one.
+        assert_eq!(
+            link.delivery_time(&sim, now, 1000, 0),
+            now + Duration::from_millis(300)
+        );
+        let sim = NetworkSim {
+            rtt_ms: 100,
+            loss_pct: 100,
+            ..Default::default()
+        };
+        let mut link = Link::default();
+        assert_eq!(
+            link.delivery_time(&sim, now, 1000, 0),
+            now + Duration::from_millis(400)
+        );
+    }
+
+    #[test]
+    fn test_hold_order() {
+        let now = Instant::now();
+        let mut link = Link::default();
+        // Not simulated.
+        assert_eq!(link.hold(-1, now, vec![1]), Some(vec![1]));
+        // Still behind the held message after the simulation is cleared.
+        let delivery = now + Duration::from_secs(60);
+        link.held.push_back((delivery, vec![2]));
+        assert_eq!(link.hold(-1, now, vec![3]), None);
+        assert_eq!(link.take_delivered(), None);
+        assert_eq!(link.deadline(), Some(delivery));
+    }
+}
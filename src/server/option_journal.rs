@@ -0,0 +1,181 @@
+// The journal of the option changes, which option changed, when and from where, for finding out
+// who changed e.g. the port of a managed host.
+//
+// The changes made in the server process are recorded with their source: `SOURCE_IPC` for the
+// local ui and the cli, which set the options over ipc, `SOURCE_STRATEGY` for the strategy of the
+// api server, `SOURCE_ROOT` and `SOURCE_USER` for the config synced between the root and the user
+// process. The last `MAX_CHANGES` are kept in memory and in `FILE`, and can be queried with
+// `--option-journal [n]`.
+//
+// The values of the sensitive options, e.g. the passwords and the keys, are never recorded, not
+// even hashed, a short password could be found from its hash. The change only tells that the
+// option is set, changed or removed.
+
+use hbb_common::{config::Config, get_time, log};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+pub const SOURCE_IPC: &str = "ipc";
+pub const SOURCE_STRATEGY: &str = "strategy";
+pub const SOURCE_ROOT: &str = "root";
+pub const SOURCE_USER: &str = "user";
+
+pub const MAX_CHANGES: usize = 256;
+const FILE: &str = "option_journal.json";
+
+lazy_static::lazy_static! {
+    static ref JOURNAL: Mutex<Option<VecDeque<Change>>> = Default::default();
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    pub time: i64, // ms
+    pub key: String,
+    // None if the option is not set.
+    pub old: Option<String>,
+    pub new: Option<String>,
+    pub source: String,
+}
+
+fn path() -> PathBuf {
+    Config::path(FILE)
+}
+
+fn is_sensitive(key: &str) -> bool {
+    const KEYS: [&str; 6] = ["password", "key", "salt", "pin", "token", "secret"];
+    key.split(|c| c == '-' || c == '_')
+        .any(|part| KEYS.contains(&part))
+}
+
+const REDACTED: &str = "<redacted>";
+
+fn redact(key: &str, value: &str) -> String {
+    if value.is_empty() || !is_sensitive(key) {
+        return value.to_owned();
+    }
+    REDACTED.to_owned()
+}
+
+fn change(source: &str, key: &str, old: Option<&String>, new: Option<&String>) -> Change {
+    Change {
+        time: get_time(),
+        key: key.to_owned(),
+        old: old.map(|v| redact(key, v)),
+        new: new.map(|v| redact(key, v)),
+        source: source.to_owned(),
+    }
+}
+
+/// The changes from `old` to `new`, sorted by the key.
+fn diff(source: &str, old: &HashMap<String, String>, new: &HashMap<String, String>) -> Vec<Change> {
+    let mut keys: Vec<&String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|k| old.get(*k) != new.get(*k))
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .map(|k| change(source, k, old.get(k), new.get(k)))
+        .collect()
+}
+
+fn append(changes: Vec<Change>) {
+    if changes.is_empty() {
+        return;
+    }
+    for c in changes.iter() {
+        log::info!(
+            "Option {} changed from {:?} to {:?} by {}",
+            c.key,
+            c.old,
+            c.new,
+            c.source
+        );
+    }
+    let mut lock = JOURNAL.lock().unwrap();
+    let journal = lock.get_or_insert_with(load);
+    journal.extend(changes);
+    while journal.len() > MAX_CHANGES {
+        journal.pop_front();
+    }
+    let res = serde_json::to_string(&*journal)
+        .map_err(|e| e.to_string())
+        .and_then(|s| std::fs::write(path(), s).map_err(|e| e.to_string()));
+    if let Err(e) = res {
+        log::error!("Failed to save the option journal: {}", e);
+    }
+}
+
+fn load() -> VecDeque<Change> {
+    std::fs::read_to_string(path())
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+/// Record the changes of the options, called with the options before and after they are set.
+pub fn record_options(source: &str, old: &HashMap<String, String>, new: &HashMap<String, String>) {
+    append(diff(source, old, new));
+}
+
+/// Record the change of a config value which is not in the options, e.g. the id.
+pub fn record(source: &str, key: &str, old: &str, new: &str) {
+    if old != new {
+        let (old, new) = (old.to_owned(), new.to_owned());
+        append(vec![change(source, key, Some(&old), Some(&new))]);
+    }
+}
+
+/// The last `n` changes in json, the latest last.
+pub fn get_last(n: usize) -> String {
+    let mut lock = JOURNAL.lock().unwrap();
+    let journal = lock.get_or_insert_with(load);
+    let skip = journal.len().saturating_sub(n);
+    serde_json::to_string(&journal.iter().skip(skip).collect::<Vec<_>>()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let old = HashMap::from([
+            ("direct-access-port".to_owned(), "21118".to_owned()),
+            ("proxy-password".to_owned(), "abc".to_owned()),
+            ("stop-service".to_owned(), "Y".to_owned()),
+        ]);
+        let new = HashMap::from([
+            ("direct-access-port".to_owned(), "21119".to_owned()),
+            ("proxy-password".to_owned(), "abd".to_owned()),
+            ("enable-audio".to_owned(), "N".to_owned()),
+        ]);
+        let changes = diff(SOURCE_IPC, &old, &new);
+        let keys: Vec<_> = changes.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "direct-access-port",
+                "enable-audio",
+                "proxy-password",
+                "stop-service"
+            ]
+        );
+        assert_eq!(changes[0].new.as_deref(), Some("21119"));
+        assert_eq!(changes[1].old, None);
+        assert_eq!(changes[3].new, None);
+        let password = &changes[2];
+        assert_eq!(password.old.as_deref(), Some(REDACTED));
+        assert_eq!(password.new.as_deref(), Some(REDACTED));
+
+        assert!(is_sensitive("key"));
+        assert!(is_sensitive("unlock_pin"));
+        assert!(!is_sensitive("keyboard-mode"));
+        assert!(!is_sensitive("api-server"));
+    }
+}
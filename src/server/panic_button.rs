@@ -0,0 +1,315 @@
+// The panic button of the host, a hotkey which closes all the remote sessions at once, e.g. when
+// the user of the host sees something unexpected done on the screen.
+//
+// The hotkey is "panic-hotkey", ctrl+alt+shift+end by default, "none" to disable it. It's
+// observed by the global listener of the server process, see `host_key_listener`, the keys sent
+// by the peers are ignored. The listener sees the keys while a peer blocks the input of the host,
+// and the hook of the privacy mode, which drops the keys before the listener, passes them on.
+// There's no global hotkey on Wayland, the tray menu and `--terminate-sessions` do the same there
+// and everywhere else.
+//
+// The sessions are closed with `CLOSE_REASON_TERMINATED`, then no connection is accepted for
+// "panic-cooldown" seconds, 300 by default, so the peers can't reconnect at once. The cooldown is
+// not kept over a restart of the server. The termination is audited and the user of the host is
+// notified.
+
+use super::{AlarmAuditType, Connection, CLOSE_REASON_TERMINATED};
+use hbb_common::{config::Config, log, tokio};
+use rdev::Key;
+use serde_json::json;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub const OPTION_PANIC_HOTKEY: &str = "panic-hotkey";
+pub const OPTION_PANIC_COOLDOWN: &str = "panic-cooldown";
+const DEFAULT_HOTKEY: Hotkey = Hotkey {
+    modifiers: MOD_CTRL | MOD_ALT | MOD_SHIFT,
+    key: Key::End,
+};
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(300);
+// The repeats of the hotkey and the double clicks are ignored.
+const MIN_INTERVAL: Duration = Duration::from_secs(2);
+
+pub const SOURCE_HOTKEY: &str = "hotkey";
+pub const SOURCE_IPC: &str = "ipc";
+
+const MOD_CTRL: u8 = 1 << 0;
+const MOD_ALT: u8 = 1 << 1;
+const MOD_SHIFT: u8 = 1 << 2;
+const MOD_META: u8 = 1 << 3;
+
+lazy_static::lazy_static! {
+    static ref RUNTIME: Mutex<Option<tokio::runtime::Handle>> = Default::default();
+    static ref PRESSED: Mutex<Pressed> = Default::default();
+    static ref LAST_TERMINATION: Mutex<Option<Instant>> = Default::default();
+    static ref DO_NOT_DISTURB_UNTIL: Mutex<Option<Instant>> = Default::default();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Hotkey {
+    modifiers: u8,
+    key: Key,
+}
+
+// None if the hotkey is disabled.
+fn get_hotkey() -> Option<Hotkey> {
+    let s = Config::get_option(OPTION_PANIC_HOTKEY);
+    if s.is_empty() {
+        return Some(DEFAULT_HOTKEY);
+    }
+    if s.trim().eq_ignore_ascii_case("none") {
+        return None;
+    }
+    match parse_hotkey(&s) {
+        Some(hotkey) => Some(hotkey),
+        None => {
+            log::warn!("Invalid {}: {}", OPTION_PANIC_HOTKEY, s);
+            Some(DEFAULT_HOTKEY)
+        }
+    }
+}
+
+// e.g. "ctrl+alt+shift+end", matched case insensitively. At least one modifier is required, the
+// key alone would be pressed by accident.
+fn parse_hotkey(s: &str) -> Option<Hotkey> {
+    let mut modifiers = 0;
+    let mut key = None;
+    for part in s.split('+') {
+        match part.trim().to_lowercase().as_str() {
+            "ctrl" | "control" => modifiers |= MOD_CTRL,
+            "alt" | "option" => modifiers |= MOD_ALT,
+            "shift" => modifiers |= MOD_SHIFT,
+            "meta" | "win" | "cmd" | "command" => modifiers |= MOD_META,
+            name => {
+                if key.is_some() {
+                    return None;
+                }
+                key = Some(parse_key(name)?);
+            }
+        }
+    }
+    if modifiers == 0 {
+        return None;
+    }
+    Some(Hotkey {
+        modifiers,
+        key: key?,
+    })
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    let key = match name {
+        "end" => Key::End,
+        "home" => Key::Home,
+        "insert" => Key::Insert,
+        "delete" => Key::Delete,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "pause" => Key::Pause,
+        "scrolllock" => Key::ScrollLock,
+        "escape" | "esc" => Key::Escape,
+        "backspace" => Key::Backspace,
+        "f1" => Key::F1,
+        "f2" => Key::F2,
+        "f3" => Key::F3,
+        "f4" => Key::F4,
+        "f5" => Key::F5,
+        "f6" => Key::F6,
+        "f7" => Key::F7,
+        "f8" => Key::F8,
+        "f9" => Key::F9,
+        "f10" => Key::F10,
+        "f11" => Key::F11,
+        "f12" => Key::F12,
+        _ => return None,
+    };
+    Some(key)
+}
+
+// The modifiers held, left and right apart, so releasing one of them doesn't clear the other.
+#[derive(Debug, Default)]
+struct Pressed(u8);
+
+impl Pressed {
+    fn bit(key: Key) -> Option<u8> {
+        let bit = match key {
+            Key::ControlLeft => 0,
+            Key::ControlRight => 1,
+            Key::Alt => 2,
+            Key::AltGr => 3,
+            Key::ShiftLeft => 4,
+            Key::ShiftRight => 5,
+            Key::MetaLeft => 6,
+            Key::MetaRight => 7,
+            _ => return None,
+        };
+        Some(1 << bit)
+    }
+
+    fn modifiers(&self) -> u8 {
+        let mut modifiers = 0;
+        for (i, m) in [MOD_CTRL, MOD_ALT, MOD_SHIFT, MOD_META]
+            .into_iter()
+            .enumerate()
+        {
+            if self.0 & (0b11 << (i * 2)) != 0 {
+                modifiers |= m;
+            }
+        }
+        modifiers
+    }
+
+    /// Returns true if the key completes the hotkey, the modifiers must be exactly the ones of it.
+    fn on_key(&mut self, key: Key, down: bool, hotkey: impl FnOnce() -> Option<Hotkey>) -> bool {
+        if let Some(bit) = Self::bit(key) {
+            if down {
+                self.0 |= bit;
+            } else {
+                self.0 &= !bit;
+            }
+            return false;
+        }
+        // The option is only read for the keys pressed with a modifier.
+        if !down || self.0 == 0 {
+            return false;
+        }
+        hotkey().map_or(false, |h| h.key == key && h.modifiers == self.modifiers())
+    }
+}
+
+/// Start the hotkey, called on the runtime of the server.
+pub fn start() {
+    *RUNTIME.lock().unwrap() = Some(tokio::runtime::Handle::current());
+    // No keyboard of the host to listen to, `--terminate-sessions` still works.
+    if get_hotkey().is_none() || super::headless_mode::is_enabled() {
+        return;
+    }
+    if !super::host_key_listener::start() {
+        log::warn!("No panic hotkey, the sessions can be terminated in the tray menu");
+    }
+}
+
+/// Called for the keys of the host, by the listener and the hook of the privacy mode.
+pub fn on_key(key: Key, down: bool) {
+    if PRESSED.lock().unwrap().on_key(key, down, get_hotkey) {
+        let handle = RUNTIME.lock().unwrap().clone();
+        match handle {
+            Some(handle) => {
+                handle.spawn(terminate(SOURCE_HOTKEY));
+            }
+            None => log::error!("The panic hotkey is pressed before the server is started"),
+        }
+    }
+}
+
+fn get_cooldown() -> Duration {
+    Config::get_option(OPTION_PANIC_COOLDOWN)
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_COOLDOWN)
+}
+
+/// No connection is accepted until the cooldown after a termination ends.
+pub fn is_do_not_disturb() -> bool {
+    let mut lock = DO_NOT_DISTURB_UNTIL.lock().unwrap();
+    match *lock {
+        Some(until) if Instant::now() < until => true,
+        Some(_) => {
+            *lock = None;
+            log::info!("The do-not-disturb after the termination of the sessions ends");
+            false
+        }
+        None => false,
+    }
+}
+
+/// Close all the sessions and refuse the new ones for the cooldown, returns whether all the
+/// connections are closed in time.
+pub async fn terminate(source: &'static str) -> bool {
+    {
+        let mut last = LAST_TERMINATION.lock().unwrap();
+        if last.map_or(false, |t| t.elapsed() < MIN_INTERVAL) {
+            return true;
+        }
+        *last = Some(Instant::now());
+    }
+    let cooldown = get_cooldown();
+    // Before the sessions are closed, so the peers can't reconnect.
+    if !cooldown.is_zero() {
+        *DO_NOT_DISTURB_UNTIL.lock().unwrap() = Some(Instant::now() + cooldown);
+    }
+    log::warn!(
+        "All the sessions are terminated by the user of the host with the {}, cooldown: {:?}",
+        source,
+        cooldown
+    );
+    let closed = super::close_host_connections(CLOSE_REASON_TERMINATED).await;
+    Connection::post_alarm_audit(
+        AlarmAuditType::SessionsTerminated,
+        json!({
+            "source": source,
+            "closed": closed,
+            "cooldown": cooldown.as_secs(),
+        }),
+    );
+    notify(cooldown);
+    closed
+}
+
+fn notify(cooldown: Duration) {
+    use crate::client::translate;
+    let title = translate("Sessions terminated".to_owned());
+    let text = if cooldown.is_zero() {
+        translate("All remote sessions are closed".to_owned())
+    } else {
+        let minutes = (cooldown.as_secs() + 59) / 60;
+        translate(format!(
+            "All remote sessions are closed, new connections are refused for {{{minutes}}} minutes"
+        ))
+    };
+    std::thread::spawn(move || super::headless_mode::show_notification(&title, &text));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_hotkey() {
+        assert_eq!(parse_hotkey("ctrl+alt+shift+end"), Some(DEFAULT_HOTKEY));
+        assert_eq!(
+            parse_hotkey(" Ctrl + Win + F12 "),
+            Some(Hotkey {
+                modifiers: MOD_CTRL | MOD_META,
+                key: Key::F12,
+            })
+        );
+        assert_eq!(parse_hotkey("end"), None);
+        assert_eq!(parse_hotkey("ctrl+alt"), None);
+        assert_eq!(parse_hotkey("ctrl+end+home"), None);
+        assert_eq!(parse_hotkey("ctrl+a"), None);
+    }
+
+    #[test]
+    fn test_pressed() {
+        let hotkey = || Some(DEFAULT_HOTKEY);
+        let mut p = Pressed::default();
+        assert!(!p.on_key(Key::End, true, hotkey));
+        for k in [Key::ControlRight, Key::Alt, Key::ShiftLeft] {
+            assert!(!p.on_key(k, true, hotkey));
+        }
+        assert!(p.on_key(Key::End, true, hotkey));
+        assert!(!p.on_key(Key::End, false, hotkey));
+        // One of the two shift keys is still held.
+        p.on_key(Key::ShiftRight, true, hotkey);
+        p.on_key(Key::ShiftLeft, false, hotkey);
+        assert!(p.on_key(Key::End, true, hotkey));
+        // A modifier more is another hotkey.
+        p.on_key(Key::MetaLeft, true, hotkey);
+        assert!(!p.on_key(Key::End, true, hotkey));
+        p.on_key(Key::MetaLeft, false, hotkey);
+        assert!(!p.on_key(Key::End, true, || None));
+    }
+}
@@ -521,14 +521,37 @@ pub mod server {
 pub mod client {
     use super::*;
     use crate::display_service;
-    use hbb_common::{anyhow::Context, message_proto::PointerDeviceEvent};
+    use hbb_common::{anyhow::Context, config::Config, message_proto::PointerDeviceEvent};
     use scrap::PixelBuffer;
+    use serde_derive::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    // Hours to remember an elevation granted to a trusted device, 0 to always prompt. The grants
+    // are saved with their expiry, so the peer reconnecting after the helper exited elevates it
+    // again without the prompt of the connection manager, UAC may still ask.
+    const OPTION_ELEVATION_REMEMBER_HOURS: &str = "elevation-remember-hours";
+    const GRANTS_FILE: &str = "elevation_grants.json";
 
     lazy_static::lazy_static! {
         static ref RUNNING: Arc<Mutex<bool>> = Default::default();
         static ref SHMEM: Arc<Mutex<Option<SharedMemory>>> = Default::default();
         static ref SENDER : Mutex<mpsc::UnboundedSender<ipc::Data>> = Mutex::new(client::start_ipc_server());
         static ref QUICK_SUPPORT: Arc<Mutex<bool>> = Default::default();
+        static ref GRANTS_LOCK: Mutex<()> = Default::default();
+    }
+
+    // An elevation granted to a peer, in the wall clock as it's kept across the restarts.
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    struct ElevationGrant {
+        created: i64,   // ms
+        expire_at: i64, // ms
+    }
+
+    impl ElevationGrant {
+        // A grant from the future is of a wall clock moved back, it's not trusted either.
+        fn is_valid(&self, now: i64) -> bool {
+            (self.created..self.expire_at).contains(&now)
+        }
     }
 
     pub enum StartPara {
@@ -642,6 +665,108 @@ pub mod client {
         *QUICK_SUPPORT.lock().unwrap() = v;
     }
 
+    fn get_remember_duration() -> Duration {
+        let hours = Config::get_option(OPTION_ELEVATION_REMEMBER_HOURS)
+            .parse::<u64>()
+            .unwrap_or(0);
+        Duration::from_secs(hours * 3600)
+    }
+
+    fn load_grants() -> HashMap<String, ElevationGrant> {
+        std::fs::read_to_string(Config::path(GRANTS_FILE))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    fn store_grants(grants: &HashMap<String, ElevationGrant>) {
+        let res = serde_json::to_string(grants)
+            .map_err(|e| e.to_string())
+            .and_then(|s| std::fs::write(Config::path(GRANTS_FILE), s).map_err(|e| e.to_string()));
+        if let Err(e) = res {
+            log::error!("Failed to save the elevation grants: {}", e);
+        }
+    }
+
+    // The valid grants, the expired ones are removed from the file.
+    fn load_valid_grants() -> HashMap<String, ElevationGrant> {
+        let mut grants = load_grants();
+        let now = crate::server::session_clock::wall_ms();
+        let len = grants.len();
+        grants.retain(|id, g| {
+            let valid = g.is_valid(now);
+            if !valid {
+                log::info!("Elevation grant for {} expired", id);
+            }
+            valid
+        });
+        if grants.len() != len {
+            store_grants(&grants);
+        }
+        grants
+    }
+
+    /// Remember the elevation granted to `peer_id`, the id of a trusted device.
+    pub fn remember_grant(peer_id: &str) -> bool {
+        let duration = get_remember_duration();
+        if duration.is_zero() {
+            return false;
+        }
+        log::info!(
+            "Remember elevation grant for {}, {}s",
+            peer_id,
+            duration.as_secs()
+        );
+        let _lock = GRANTS_LOCK.lock().unwrap();
+        let mut grants = load_valid_grants();
+        let now = crate::server::session_clock::wall_ms();
+        grants.insert(
+            peer_id.to_owned(),
+            ElevationGrant {
+                created: now,
+                expire_at: now + duration.as_millis() as i64,
+            },
+        );
+        store_grants(&grants);
+        true
+    }
+
+    /// Whether `peer_id`, the id of a trusted device, has a valid grant, so the running helper
+    /// is reused or a new one is started without prompting.
+    pub fn use_grant(peer_id: &str) -> bool {
+        if get_remember_duration().is_zero() {
+            return false;
+        }
+        let _lock = GRANTS_LOCK.lock().unwrap();
+        if load_valid_grants().contains_key(peer_id) {
+            log::info!("Elevation grant for {} is used", peer_id);
+            return true;
+        }
+        false
+    }
+
+    /// Revoke the grant of `peer_id`, or all grants if empty.
+    pub fn revoke_grants(peer_id: &str) {
+        let _lock = GRANTS_LOCK.lock().unwrap();
+        let mut grants = load_grants();
+        if peer_id.is_empty() {
+            log::info!("Revoke all elevation grants: {:?}", grants.keys());
+            grants.clear();
+        } else if grants.remove(peer_id).is_some() {
+            log::info!("Revoke elevation grant for {}", peer_id);
+        }
+        store_grants(&grants);
+    }
+
+    pub fn get_grants() -> Vec<(String, u64)> {
+        let _lock = GRANTS_LOCK.lock().unwrap();
+        let now = crate::server::session_clock::wall_ms();
+        load_valid_grants()
+            .into_iter()
+            .map(|(id, g)| (id, ((g.expire_at - now).max(0) / 1000) as u64))
+            .collect()
+    }
+
     pub struct CapturerPortable {
         width: usize,
         height: usize,
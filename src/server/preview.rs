@@ -0,0 +1,146 @@
+// Rate-limited stills of a display for the "live preview" of the address book.
+//
+// Preview connections never subscribe to the video service. A still is taken from the last frame
+// captured by the running video service, or by a temporary capturer if there is no session,
+// then it is scaled down and encoded as JPEG. At most one still per peer and display is sent
+// in an interval, no matter what the peer requests or how often it reconnects.
+
+use super::*;
+use hbb_common::config::option2bool;
+
+pub const OPTION_ALLOW_PREVIEW: &str = "allow-remote-preview";
+const OPTION_INTERVAL: &str = "preview-interval";
+const MIN_INTERVAL_SECS: u64 = 10;
+const DEFAULT_INTERVAL_SECS: u64 = 60;
+const MAX_WIDTH: u32 = 320;
+const JPEG_QUALITY: u8 = 60;
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(3);
+
+lazy_static::lazy_static! {
+    // (peer id, display) -> the time of the last still.
+    static ref LAST_SENT: Mutex<HashMap<(String, usize), Instant>> = Default::default();
+}
+
+pub fn is_allowed() -> bool {
+    option2bool(
+        OPTION_ALLOW_PREVIEW,
+        &Config::get_option(OPTION_ALLOW_PREVIEW),
+    )
+}
+
+/// The interval between two stills, the larger one of the host option and the peer request,
+/// not less than `MIN_INTERVAL_SECS`.
+pub fn get_interval(requested_secs: u64) -> Duration {
+    let host = Config::get_option(OPTION_INTERVAL)
+        .parse::<u64>()
+        .unwrap_or(DEFAULT_INTERVAL_SECS);
+    Duration::from_secs(host.max(requested_secs).max(MIN_INTERVAL_SECS))
+}
+
+/// Send the stills of the display to the connection until it is closed.
+pub async fn run(
+    server: ServerPtrWeak,
+    tx: Sender,
+    peer_id: String,
+    display: usize,
+    interval: Duration,
+) {
+    let key = (peer_id, display);
+    loop {
+        let wait = LAST_SENT
+            .lock()
+            .unwrap()
+            .get(&key)
+            .map(|t| interval.saturating_sub(t.elapsed()))
+            .unwrap_or_default();
+        tokio::time::sleep(wait).await;
+        if tx.is_closed() {
+            break;
+        }
+        LAST_SENT
+            .lock()
+            .unwrap()
+            .insert(key.clone(), Instant::now());
+        // The running capturer is not refreshed, the last frame it captured is taken.
+        let running = server
+            .upgrade()
+            .map(|s| s.read().unwrap().is_video_service_running(display))
+            .unwrap_or(false);
+        let frame = match tokio::task::spawn_blocking(move || take(display, running)).await {
+            Ok(Ok(frame)) => frame,
+            Ok(Err(e)) => {
+                log::error!("Failed to take the preview of display {}: {}", display, e);
+                continue;
+            }
+            Err(e) => {
+                log::error!("Failed to join the preview task: {}", e);
+                continue;
+            }
+        };
+        let mut msg = Message::new();
+        msg.set_preview_frame(frame);
+        if tx
+            .send((tokio::time::Instant::now(), Arc::new(msg)))
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+fn take(display: usize, running: bool) -> ResultType<PreviewFrame> {
+    let (w, h, rgba) = if running {
+        video_service::take_preview_frame(display, CAPTURE_TIMEOUT)?
+    } else {
+        video_service::capture_preview_frame(display, CAPTURE_TIMEOUT)?
+    };
+    let (width, height, data) = encode_jpeg(w as _, h as _, rgba)?;
+    Ok(PreviewFrame {
+        display: display as _,
+        width: width as _,
+        height: height as _,
+        data: data.into(),
+        ..Default::default()
+    })
+}
+
+fn encode_jpeg(w: u32, h: u32, rgba: Vec<u8>) -> ResultType<(u32, u32, Vec<u8>)> {
+    let Some(image) = image::RgbaImage::from_raw(w, h, rgba) else {
+        bail!("Invalid rgba of {}x{}", w, h);
+    };
+    let image = if w > MAX_WIDTH {
+        let height = (h as u64 * MAX_WIDTH as u64 / w as u64).max(1) as u32;
+        image::imageops::resize(
+            &image,
+            MAX_WIDTH,
+            height,
+            image::imageops::FilterType::Triangle,
+        )
+    } else {
+        image
+    };
+    let rgb = image::DynamicImage::ImageRgba8(image).into_rgb8();
+    let mut jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY).encode(
+        rgb.as_raw(),
+        rgb.width(),
+        rgb.height(),
+        image::ColorType::Rgb8,
+    )?;
+    Ok((rgb.width(), rgb.height(), jpeg))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode_jpeg() {
+        let (w, h, jpeg) = encode_jpeg(640, 480, vec![0x80; 640 * 480 * 4]).unwrap();
+        assert_eq!((w, h), (MAX_WIDTH, 240));
+        assert!(jpeg.starts_with(&[0xFF, 0xD8]));
+        let (w, h, _) = encode_jpeg(100, 50, vec![0; 100 * 50 * 4]).unwrap();
+        assert_eq!((w, h), (100, 50));
+        assert!(encode_jpeg(100, 50, vec![0; 10]).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+// The spool of the intercepted print jobs.
+//
+// A job is written to disk before it is sent, and removed only after it has been transferred,
+// so a job printed while disconnected, or interrupted by a dropped session, is delivered the
+// next time the same peer connects with the printer permission.
+// The queue is bounded by count and size, the oldest jobs are dropped first, and the jobs older
+// than "printer-queue-max-age" hours are purged.
+
+use hbb_common::{bail, config::Config, get_time, log, ResultType};
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+const OPTION_MAX_AGE: &str = "printer-queue-max-age"; // hours
+const DEFAULT_MAX_AGE_HOURS: i64 = 24;
+const MAX_JOBS: usize = 32;
+const MAX_TOTAL_SIZE: u64 = 512 * 1024 * 1024;
+const INDEX_FILE: &str = "queue.json";
+
+lazy_static::lazy_static! {
+    static ref QUEUE: Mutex<Queue> = Mutex::new(Queue::load());
+    // job id -> the connection which the job is being sent to.
+    static ref DISPATCHED: Mutex<HashMap<u64, i32>> = Default::default();
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct JobInfo {
+    pub id: u64,
+    // The peer the job is delivered to, empty for any peer with the printer permission.
+    pub peer_id: String,
+    pub created: i64, // ms
+    pub size: u64,
+    #[serde(default)]
+    pub attempts: u32,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Queue {
+    jobs: Vec<JobInfo>,
+    // The last peer with the printer permission, the jobs printed while disconnected go to it.
+    last_peer_id: String,
+}
+
+impl Queue {
+    fn load() -> Self {
+        let mut queue = std::fs::read_to_string(dir().join(INDEX_FILE))
+            .ok()
+            .and_then(|s| serde_json::from_str::<Queue>(&s).ok())
+            .unwrap_or_default();
+        // Drop the entries whose data is gone.
+        queue.jobs.retain(|j| data_path(j.id).exists());
+        queue
+    }
+
+    fn save(&self) {
+        let res = std::fs::create_dir_all(dir())
+            .map_err(|e| e.to_string())
+            .and_then(|_| serde_json::to_string(self).map_err(|e| e.to_string()))
+            .and_then(|s| std::fs::write(dir().join(INDEX_FILE), s).map_err(|e| e.to_string()));
+        if let Err(e) = res {
+            log::error!("Failed to save the printer queue: {}", e);
+        }
+    }
+
+    fn remove_at(&mut self, index: usize) -> JobInfo {
+        let job = self.jobs.remove(index);
+        std::fs::remove_file(data_path(job.id)).ok();
+        DISPATCHED.lock().unwrap().remove(&job.id);
+        job
+    }
+
+    fn purge(&mut self) -> bool {
+        let max_age = Config::get_option(OPTION_MAX_AGE)
+            .parse::<i64>()
+            .unwrap_or(DEFAULT_MAX_AGE_HOURS);
+        let deadline = get_time() - max_age * 3600 * 1000;
+        let mut changed = false;
+        while let Some(index) = self.jobs.iter().position(|j| j.created < deadline) {
+            let job = self.remove_at(index);
+            log::info!("Purge the expired printer job {}", job.id);
+            changed = true;
+        }
+        changed
+    }
+}
+
+fn dir() -> PathBuf {
+    Config::path("printer_queue")
+}
+
+fn data_path(id: u64) -> PathBuf {
+    dir().join(format!("{}.prn", id))
+}
+
+/// Spool the job for the peer, or the last peer with the printer permission if `peer_id` is empty.
+pub fn push(data: &[u8], peer_id: &str) -> ResultType<u64> {
+    let mut queue = QUEUE.lock().unwrap();
+    queue.purge();
+    let size = data.len() as u64;
+    if size > MAX_TOTAL_SIZE {
+        bail!("The print job of {} bytes is too large", size);
+    }
+    while queue.jobs.len() >= MAX_JOBS
+        || queue.jobs.iter().map(|j| j.size).sum::<u64>() + size > MAX_TOTAL_SIZE
+    {
+        let job = queue.remove_at(0);
+        log::warn!("The printer queue is full, drop the job {}", job.id);
+    }
+    let id = (get_time() as u64).max(queue.jobs.iter().map(|j| j.id + 1).max().unwrap_or(0));
+    std::fs::create_dir_all(dir())?;
+    std::fs::write(data_path(id), data)?;
+    if !peer_id.is_empty() {
+        queue.last_peer_id = peer_id.to_owned();
+    }
+    let peer_id = queue.last_peer_id.clone();
+    queue.jobs.push(JobInfo {
+        id,
+        peer_id,
+        created: get_time(),
+        size,
+        attempts: 0,
+    });
+    queue.save();
+    Ok(id)
+}
+
+pub fn list() -> Vec<JobInfo> {
+    let mut queue = QUEUE.lock().unwrap();
+    if queue.purge() {
+        queue.save();
+    }
+    queue.jobs.clone()
+}
+
+pub fn remove(id: u64) -> bool {
+    let mut queue = QUEUE.lock().unwrap();
+    let Some(index) = queue.jobs.iter().position(|j| j.id == id) else {
+        return false;
+    };
+    queue.remove_at(index);
+    queue.save();
+    true
+}
+
+/// Move the job to `pos`, the jobs are sent in the order of the queue.
+pub fn move_to(id: u64, pos: usize) -> bool {
+    let mut queue = QUEUE.lock().unwrap();
+    let Some(index) = queue.jobs.iter().position(|j| j.id == id) else {
+        return false;
+    };
+    let job = queue.jobs.remove(index);
+    let pos = pos.min(queue.jobs.len());
+    queue.jobs.insert(pos, job);
+    queue.save();
+    true
+}
+
+pub fn on_peer_connected(peer_id: &str) {
+    let mut queue = QUEUE.lock().unwrap();
+    if queue.last_peer_id != peer_id {
+        queue.last_peer_id = peer_id.to_owned();
+        queue.save();
+    }
+}
+
+/// Take the next job which is not being sent, for one of the peers.
+pub fn take_next(peers: &[(i32, String)]) -> Option<(u64, i32)> {
+    let mut queue = QUEUE.lock().unwrap();
+    if queue.purge() {
+        queue.save();
+    }
+    let mut dispatched = DISPATCHED.lock().unwrap();
+    let (index, conn_id) = queue.jobs.iter().enumerate().find_map(|(i, job)| {
+        if dispatched.contains_key(&job.id) {
+            return None;
+        }
+        peers
+            .iter()
+            .find(|(_, p)| job.peer_id.is_empty() || *p == job.peer_id)
+            .map(|(conn_id, _)| (i, *conn_id))
+    })?;
+    let job = &mut queue.jobs[index];
+    job.attempts += 1;
+    let id = job.id;
+    dispatched.insert(id, conn_id);
+    drop(dispatched);
+    queue.save();
+    Some((id, conn_id))
+}
+
+pub fn read(id: u64) -> ResultType<Vec<u8>> {
+    Ok(std::fs::read(data_path(id))?)
+}
+
+/// The job is delivered.
+pub fn on_sent(id: u64) {
+    log::info!("Printer job {} is sent", id);
+    remove(id);
+}
+
+/// The jobs being sent on the connection are sent again on the next connection.
+pub fn on_conn_closed(conn_id: i32) {
+    DISPATCHED.lock().unwrap().retain(|_, c| *c != conn_id);
+}
@@ -0,0 +1,134 @@
+// Push-to-talk of the host in a voice call, the microphone of the host is only sent to the peer
+// while a key is held, so the background noise around the host isn't streamed by accident.
+//
+// It's opt-in with "voice-call-push-to-talk", the key is "voice-call-push-to-talk-key", F8 by
+// default. The key is observed by the global listener of the server process, see
+// `host_key_listener`, started with the first voice call if it is not started yet. The keys
+// injected by the peers are dropped by the listener, a peer can't turn on the microphone.
+// While the key is up the connection of the call is muted like the audio permission is turned
+// off in the cm, its audio service is unsubscribed. There's no global listener on Wayland, it's
+// never armed there, or the host could never be heard.
+
+use hbb_common::{config::Config, log};
+use rdev::Key;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+pub const OPTION_PUSH_TO_TALK: &str = "voice-call-push-to-talk";
+pub const OPTION_PUSH_TO_TALK_KEY: &str = "voice-call-push-to-talk-key";
+const DEFAULT_KEY: Key = Key::F8;
+
+// The connection of the voice call while it is open with push-to-talk on.
+static OWNER: Mutex<Option<i32>> = Mutex::new(None);
+static HELD: AtomicBool = AtomicBool::new(false);
+// The key, kept while it is held.
+static KEY: Mutex<Key> = Mutex::new(DEFAULT_KEY);
+
+#[inline]
+pub fn is_enabled() -> bool {
+    Config::get_option(OPTION_PUSH_TO_TALK) == "Y"
+}
+
+pub fn get_key() -> Key {
+    let name = Config::get_option(OPTION_PUSH_TO_TALK_KEY);
+    if name.is_empty() {
+        return DEFAULT_KEY;
+    }
+    match parse_key(&name) {
+        Some(key) => key,
+        None => {
+            log::warn!("Invalid {}: {}", OPTION_PUSH_TO_TALK_KEY, name);
+            DEFAULT_KEY
+        }
+    }
+}
+
+// The keys which are rarely used by the other programs, matched case insensitively.
+fn parse_key(name: &str) -> Option<Key> {
+    let key = match name.trim().to_lowercase().as_str() {
+        "f1" => Key::F1,
+        "f2" => Key::F2,
+        "f3" => Key::F3,
+        "f4" => Key::F4,
+        "f5" => Key::F5,
+        "f6" => Key::F6,
+        "f7" => Key::F7,
+        "f8" => Key::F8,
+        "f9" => Key::F9,
+        "f10" => Key::F10,
+        "f11" => Key::F11,
+        "f12" => Key::F12,
+        "scrolllock" => Key::ScrollLock,
+        "pause" => Key::Pause,
+        "insert" => Key::Insert,
+        "controlright" => Key::ControlRight,
+        "shiftright" => Key::ShiftRight,
+        "altgr" => Key::AltGr,
+        "metaright" => Key::MetaRight,
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Arm it when the voice call of the connection starts, it's muted until the key is pressed.
+pub fn start(conn_id: i32) {
+    if !is_enabled() {
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    if !crate::platform::linux::is_x11() {
+        log::warn!("Push-to-talk is not supported on Wayland, the microphone is always sent");
+        return;
+    }
+    if !super::host_key_listener::start() {
+        return;
+    }
+    HELD.store(false, Ordering::SeqCst);
+    *OWNER.lock().unwrap() = Some(conn_id);
+    super::set_push_to_talk(conn_id, false);
+    log::info!("Push-to-talk armed, key: {:?}", get_key());
+}
+
+/// Disarm it, the connection of the call is unmuted.
+pub fn stop() {
+    if let Some(conn_id) = OWNER.lock().unwrap().take() {
+        super::set_push_to_talk(conn_id, true);
+        log::info!("Push-to-talk disarmed");
+    }
+}
+
+/// Called by the listener for every key of the host.
+pub(super) fn on_key(k: Key, down: bool) {
+    let Some(conn_id) = *OWNER.lock().unwrap() else {
+        HELD.store(false, Ordering::SeqCst);
+        return;
+    };
+    // The key may be changed between the calls, it is read while nothing is held.
+    let key = if HELD.load(Ordering::SeqCst) {
+        *KEY.lock().unwrap()
+    } else {
+        let key = get_key();
+        *KEY.lock().unwrap() = key;
+        key
+    };
+    if k == key && HELD.swap(down, Ordering::SeqCst) != down {
+        log::debug!("Push-to-talk {}", if down { "on" } else { "off" });
+        super::set_push_to_talk(conn_id, down);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key("F8"), Some(Key::F8));
+        assert_eq!(parse_key(" scrolllock "), Some(Key::ScrollLock));
+        assert_eq!(parse_key("AltGr"), Some(Key::AltGr));
+        assert_eq!(parse_key("a"), None);
+        assert_eq!(parse_key(""), None);
+    }
+}
@@ -0,0 +1,277 @@
+// Quick actions, the commands a technician runs on every host with one click, e.g. a maintenance
+// script, opening a URL or restarting a service.
+//
+// The actions are only defined on the host in "quick-actions", a json list of
+// `{"name", "command", "working_dir", "confirm"}`. The peer picks an action by its name, it can
+// never supply the command text. Running them needs "allow-remote-quick-actions" and the
+// permission of the connection, "enable-quick-actions", an action with "confirm" is requested
+// twice like the power actions.
+// The connection manager runs the command in the shell as the user of the session, in its own
+// process group, and kills the group when the connection is closed or the permission is turned
+// off. Its stdout and stderr are sent to the peer in chunks, the output beyond `MAX_OUTPUT` is
+// dropped, and the run is audited with the exit code.
+
+use hbb_common::{bail, config::Config, log, ResultType};
+use serde_derive::{Deserialize, Serialize};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+use std::{
+    io::Read,
+    process::{Command, Stdio},
+    sync::mpsc,
+    time::Duration,
+};
+
+pub const OPTION_QUICK_ACTIONS: &str = "quick-actions";
+pub const OPTION_ALLOW_QUICK_ACTIONS: &str = "allow-remote-quick-actions";
+pub const CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+const CHUNK_SIZE: usize = 16 * 1024;
+const MAX_OUTPUT: usize = 1024 * 1024;
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QuickAction {
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub working_dir: String,
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Output {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    // The output beyond `MAX_OUTPUT` is dropped.
+    Truncated,
+    // The exit code, None if the process is killed by a signal.
+    Exit(Option<i32>),
+    // The command can't be started, there's no other output.
+    Failed(String),
+}
+
+pub fn is_allowed() -> bool {
+    Config::get_option(OPTION_ALLOW_QUICK_ACTIONS) == "Y"
+}
+
+pub fn get_actions() -> Vec<QuickAction> {
+    parse(&Config::get_option(OPTION_QUICK_ACTIONS))
+}
+
+fn parse(s: &str) -> Vec<QuickAction> {
+    if s.is_empty() {
+        return vec![];
+    }
+    match serde_json::from_str::<Vec<QuickAction>>(s) {
+        Ok(actions) => actions
+            .into_iter()
+            .filter(|a| !a.name.is_empty() && !a.command.is_empty())
+            .collect(),
+        Err(e) => {
+            log::error!("Invalid {}: {}", OPTION_QUICK_ACTIONS, e);
+            vec![]
+        }
+    }
+}
+
+pub fn find(name: &str) -> Option<QuickAction> {
+    get_actions().into_iter().find(|a| a.name == name)
+}
+
+/// Start the command of the action, the output is passed to `on_output` from another thread,
+/// `Output::Exit` is the last one.
+pub fn run<F>(action: &QuickAction, on_output: F) -> ResultType<u32>
+where
+    F: FnMut(Output) + Send + 'static,
+{
+    #[cfg(windows)]
+    let mut cmd = {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", &action.command]);
+        cmd
+    };
+    #[cfg(not(windows))]
+    let mut cmd = {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", &action.command]);
+        cmd
+    };
+    if !action.working_dir.is_empty() {
+        cmd.current_dir(&action.working_dir);
+    }
+    // The group is killed with the shell, see `kill`.
+    #[cfg(unix)]
+    cmd.process_group(0);
+    #[cfg(windows)]
+    cmd.creation_flags(winapi::um::winbase::CREATE_NO_WINDOW);
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+        abort(&mut child);
+        bail!("No output of the quick action");
+    };
+    let pid = child.id();
+    super::track_child(child);
+    log::info!("Quick action {} started, pid: {}", action.name, pid);
+    let (tx, rx) = mpsc::channel();
+    let tx2 = tx.clone();
+    std::thread::spawn(move || read_output(stdout, tx, Output::Stdout));
+    std::thread::spawn(move || read_output(stderr, tx2, Output::Stderr));
+    std::thread::spawn(move || forward_output(pid, rx, on_output));
+    Ok(pid)
+}
+
+/// Kill the command started by `run` with its children, if it's still running.
+pub fn kill(pid: u32) {
+    // The pid is not reused before the child is reaped, which can't happen meanwhile.
+    let killed = super::kill_child(pid, |child| {
+        #[cfg(unix)]
+        unsafe {
+            hbb_common::libc::kill(-(pid as i32), hbb_common::libc::SIGKILL);
+        }
+        #[cfg(windows)]
+        Command::new("taskkill")
+            .args(["/F", "/T", "/PID", &pid.to_string()])
+            .creation_flags(winapi::um::winbase::CREATE_NO_WINDOW)
+            .output()
+            .ok();
+        child.kill().ok();
+    });
+    if killed {
+        log::info!("Quick action {} killed", pid);
+    }
+}
+
+fn abort(child: &mut std::process::Child) {
+    if let Err(e) = child.kill() {
+        log::error!("Failed to kill the quick action: {}", e);
+    }
+    child.wait().ok();
+}
+
+fn read_output<R: Read>(mut r: R, tx: mpsc::Sender<Output>, wrap: fn(Vec<u8>) -> Output) {
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        match r.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                if tx.send(wrap(buf[..n].to_vec())).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// The output of both pipes, then the exit code once the pipes are closed.
+fn forward_output<F: FnMut(Output)>(pid: u32, rx: mpsc::Receiver<Output>, mut on_output: F) {
+    let mut sent = 0;
+    let mut truncated = false;
+    for output in rx {
+        let len = match &output {
+            Output::Stdout(data) | Output::Stderr(data) => data.len(),
+            _ => 0,
+        };
+        if sent + len > MAX_OUTPUT {
+            // Keep reading, the process blocks if its pipes are full.
+            if !truncated {
+                truncated = true;
+                on_output(Output::Truncated);
+            }
+            continue;
+        }
+        sent += len;
+        on_output(output);
+    }
+    let status = loop {
+        if let Some(status) = super::try_wait_child(pid) {
+            break status;
+        }
+        std::thread::sleep(EXIT_POLL_INTERVAL);
+    };
+    log::info!("Quick action {} exited: {}", pid, status);
+    on_output(Output::Exit(status.code()));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let actions = parse(
+            r#"[{"name": "a", "command": "echo a", "confirm": true},
+                {"name": "", "command": "echo b"},
+                {"name": "c", "command": "", "working_dir": "/tmp"},
+                {"name": "d", "command": "echo d", "working_dir": "/tmp"}]"#,
+        );
+        assert_eq!(
+            actions,
+            vec![
+                QuickAction {
+                    name: "a".to_owned(),
+                    command: "echo a".to_owned(),
+                    working_dir: "".to_owned(),
+                    confirm: true,
+                },
+                QuickAction {
+                    name: "d".to_owned(),
+                    command: "echo d".to_owned(),
+                    working_dir: "/tmp".to_owned(),
+                    confirm: false,
+                },
+            ]
+        );
+        assert!(parse("").is_empty());
+        assert!(parse("not json").is_empty());
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_run() {
+        let action = QuickAction {
+            name: "test".to_owned(),
+            command: "echo out; echo err >&2; exit 3".to_owned(),
+            ..Default::default()
+        };
+        let (tx, rx) = mpsc::channel();
+        run(&action, move |output| tx.send(output).unwrap()).unwrap();
+        let mut stdout = vec![];
+        let mut stderr = vec![];
+        let exit = loop {
+            match rx.recv_timeout(Duration::from_secs(10)).unwrap() {
+                Output::Stdout(data) => stdout.extend(data),
+                Output::Stderr(data) => stderr.extend(data),
+                Output::Truncated => panic!("truncated"),
+                Output::Exit(code) => break code,
+            }
+        };
+        assert_eq!(stdout, b"out\n");
+        assert_eq!(stderr, b"err\n");
+        assert_eq!(exit, Some(3));
+    }
+
+    // The output ends when the last process holding the pipes is killed.
+    #[test]
+    #[cfg(not(windows))]
+    fn test_kill() {
+        let action = QuickAction {
+            name: "test".to_owned(),
+            command: "sleep 30 & sleep 30".to_owned(),
+            ..Default::default()
+        };
+        let (tx, rx) = mpsc::channel();
+        let pid = run(&action, move |output| tx.send(output).unwrap()).unwrap();
+        kill(pid);
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(10)).unwrap(),
+            Output::Exit(None)
+        );
+    }
+}
@@ -0,0 +1,224 @@
+// The scroll gestures of the touch and pen clients, turned into the wheel events of the host, for
+// the programs which only scroll by the wheel.
+//
+// A gesture is a stream of deltas in the wheel units, `WHEEL_UNIT` for one notch, from `Begin` to
+// `End`. The deltas are accumulated and only the whole steps the platform can scroll are emitted,
+// the remainder is kept, so a slow drag still scrolls. A step is one unit on Windows, which has
+// the high resolution wheel, a pixel on macOS and a notch elsewhere.
+//
+// After `End` faster than `MIN_FLICK_VELOCITY`, the scrolling goes on with the velocity decaying
+// to `DECAY` of it every second, until it is below `STOP_VELOCITY` or `MAX_FLICK` passes. A new
+// gesture or `Cancel` stops it at once. The velocity is sent by the client with `End`, or
+// estimated from the updates of the last `VELOCITY_WINDOW`. The regular wheel events of the
+// connection are handled as before, they neither stop nor are merged into a gesture.
+
+use hbb_common::message_proto::{scroll_gesture::Phase, ScrollGesture};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub const WHEEL_UNIT: f64 = 120.;
+#[cfg(windows)]
+const UNITS_PER_STEP: f64 = 1.;
+#[cfg(target_os = "macos")]
+const UNITS_PER_STEP: f64 = 3.;
+#[cfg(not(any(windows, target_os = "macos")))]
+const UNITS_PER_STEP: f64 = WHEEL_UNIT;
+
+const FRAME: Duration = Duration::from_millis(16);
+const DECAY: f64 = 0.05;
+const MIN_FLICK_VELOCITY: f64 = 2. * WHEEL_UNIT;
+const STOP_VELOCITY: f64 = WHEEL_UNIT / 4.;
+const MAX_VELOCITY: f64 = 60. * WHEEL_UNIT;
+const MAX_FLICK: Duration = Duration::from_secs(4);
+const VELOCITY_WINDOW: Duration = Duration::from_millis(100);
+
+lazy_static::lazy_static! {
+    static ref GESTURES: Mutex<HashMap<i32, Gesture>> = Default::default();
+}
+
+#[derive(Debug, Default)]
+struct Accumulator {
+    x: f64,
+    y: f64,
+}
+
+impl Accumulator {
+    /// Add the delta in the wheel units, returns the whole steps and keeps the remainder.
+    fn add(&mut self, dx: f64, dy: f64) -> (i32, i32) {
+        self.x += dx / UNITS_PER_STEP;
+        self.y += dy / UNITS_PER_STEP;
+        let (x, y) = (self.x.trunc(), self.y.trunc());
+        self.x -= x;
+        self.y -= y;
+        (x as _, y as _)
+    }
+}
+
+#[derive(Default)]
+struct Gesture {
+    acc: Accumulator,
+    recent: VecDeque<(Instant, f64, f64)>,
+    // Changed to stop the flick.
+    generation: u64,
+}
+
+impl Gesture {
+    fn update(&mut self, dx: f64, dy: f64) -> (i32, i32) {
+        let now = Instant::now();
+        self.recent.push_back((now, dx, dy));
+        while self
+            .recent
+            .front()
+            .map_or(false, |(t, _, _)| now.duration_since(*t) > VELOCITY_WINDOW)
+        {
+            self.recent.pop_front();
+        }
+        self.acc.add(dx, dy)
+    }
+
+    // The velocity in the wheel units per second, from the recent updates.
+    fn velocity(&self) -> (f64, f64) {
+        let (Some(first), Some(last)) = (self.recent.front(), self.recent.back()) else {
+            return (0., 0.);
+        };
+        let span = last.0.duration_since(first.0).as_secs_f64();
+        if span <= 0. {
+            return (0., 0.);
+        }
+        // The first delta is moved before the span.
+        let (x, y) = self
+            .recent
+            .iter()
+            .skip(1)
+            .fold((0., 0.), |(x, y), (_, dx, dy)| (x + dx, y + dy));
+        (x / span, y / span)
+    }
+
+    fn stop(&mut self) {
+        self.generation += 1;
+    }
+}
+
+#[inline]
+fn decay(v: f64, dt: f64) -> f64 {
+    v * DECAY.powf(dt)
+}
+
+pub fn handle(g: &ScrollGesture, conn: i32) {
+    let (dx, dy) = (g.dx as f64, g.dy as f64);
+    let mut flick = None;
+    let steps = {
+        let mut lock = GESTURES.lock().unwrap();
+        let gesture = lock.entry(conn).or_default();
+        match g.phase.enum_value() {
+            Ok(Phase::Begin) => {
+                gesture.stop();
+                gesture.acc = Default::default();
+                gesture.recent.clear();
+                gesture.update(dx, dy)
+            }
+            Ok(Phase::Update) => gesture.update(dx, dy),
+            Ok(Phase::End) => {
+                // A repeated `End` replaces the running flick.
+                gesture.stop();
+                let steps = gesture.update(dx, dy);
+                let (vx, vy) = if g.velocity_x != 0 || g.velocity_y != 0 {
+                    (g.velocity_x as f64, g.velocity_y as f64)
+                } else {
+                    gesture.velocity()
+                };
+                gesture.recent.clear();
+                if vx.hypot(vy) >= MIN_FLICK_VELOCITY {
+                    flick = Some((
+                        gesture.generation,
+                        vx.clamp(-MAX_VELOCITY, MAX_VELOCITY),
+                        vy.clamp(-MAX_VELOCITY, MAX_VELOCITY),
+                    ));
+                }
+                steps
+            }
+            _ => {
+                gesture.stop();
+                (0, 0)
+            }
+        }
+    };
+    super::input_service::scroll_gesture_steps(steps.0, steps.1);
+    if let Some((generation, vx, vy)) = flick {
+        start_flick(conn, generation, vx, vy);
+    }
+}
+
+fn start_flick(conn: i32, generation: u64, mut vx: f64, mut vy: f64) {
+    std::thread::spawn(move || {
+        let start = Instant::now();
+        let mut last = start;
+        loop {
+            std::thread::sleep(FRAME);
+            let now = Instant::now();
+            let dt = now.duration_since(last).as_secs_f64();
+            last = now;
+            vx = decay(vx, dt);
+            vy = decay(vy, dt);
+            if vx.hypot(vy) < STOP_VELOCITY || now.duration_since(start) > MAX_FLICK {
+                break;
+            }
+            let steps = match GESTURES.lock().unwrap().get_mut(&conn) {
+                Some(g) if g.generation == generation => g.acc.add(vx * dt, vy * dt),
+                _ => break,
+            };
+            if !super::input_service::scroll_gesture_flick_steps(conn, steps.0, steps.1) {
+                break;
+            }
+        }
+    });
+}
+
+pub fn remove(conn: i32) {
+    GESTURES.lock().unwrap().remove(&conn);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_accumulator() {
+        let mut acc = Accumulator::default();
+        let mut total = 0;
+        for _ in 0..4 {
+            total += acc.add(0., WHEEL_UNIT / 4.).1;
+        }
+        assert_eq!(total as f64, WHEEL_UNIT / UNITS_PER_STEP);
+        let mut total = 0;
+        for _ in 0..4 {
+            total += acc.add(-WHEEL_UNIT / 4., 0.).0;
+        }
+        assert_eq!(total as f64, -WHEEL_UNIT / UNITS_PER_STEP);
+    }
+
+    #[test]
+    fn test_flick_decay() {
+        let mut v = MAX_VELOCITY;
+        let mut t = 0.;
+        while v >= STOP_VELOCITY {
+            v = decay(v, FRAME.as_secs_f64());
+            t += FRAME.as_secs_f64();
+        }
+        // A fast flick ends in time, faster than `MAX_FLICK` caps.
+        assert!(t < MAX_FLICK.as_secs_f64());
+        assert!((decay(100., 1.) - 100. * DECAY).abs() < 1e-9);
+
+        let mut g = Gesture::default();
+        assert_eq!(g.velocity(), (0., 0.));
+        g.update(0., 10.);
+        g.recent[0].0 -= Duration::from_millis(50);
+        g.update(0., 20.);
+        let (vx, vy) = g.velocity();
+        assert_eq!(vx, 0.);
+        assert!(vy > 20. / 0.06 && vy < 20. / 0.04, "{}", vy);
+    }
+}
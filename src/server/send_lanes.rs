@@ -0,0 +1,257 @@
+// The priority lanes of the send path of a connection, so the small control and input messages are
+// not queued behind a burst of video frames on a slow link.
+//
+// The messages taken from the channels of `ConnInner` are put in a lane by their type, control >
+// audio > video > bulk, and the writer sends the next one picked by a weighted (stride) scheduler.
+// A lane which was empty starts at the current virtual time, so a new control message goes before
+// the queued video, while a busy lane still can't starve the lower ones. The order within a lane
+// is kept, e.g. `SwitchDisplay` and the video frames.
+//
+// The lanes are bounded, the channels are not read while their lanes are full, except the audio
+// lane which drops the oldest frame, a stale audio frame is useless. The next audio frame sent
+// after a drop has `discontinuity`, so the client conceals the gap instead of playing the frames
+// back to back.
+
+use hbb_common::{
+    log,
+    message_proto::{message, misc, AudioFrame, Message},
+    tokio::time::Instant,
+};
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+const LANES: usize = 4;
+const WEIGHTS: [u64; LANES] = [16, 8, 4, 1];
+const CAPACITIES: [usize; LANES] = [256, 16, 4, 256];
+const STRIDE: u64 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    Control = 0,
+    Audio = 1,
+    Video = 2,
+    Bulk = 3,
+}
+
+const ALL: [Lane; LANES] = [Lane::Control, Lane::Audio, Lane::Video, Lane::Bulk];
+
+impl Lane {
+    pub fn of(msg: &Message) -> Self {
+        match &msg.union {
+            Some(message::Union::VideoFrame(_)) => Lane::Video,
+            Some(message::Union::Misc(m))
+                if matches!(m.union, Some(misc::Union::SwitchDisplay(_))) =>
+            {
+                Lane::Video
+            }
+            Some(message::Union::AudioFrame(_)) => Lane::Audio,
+            Some(message::Union::FileResponse(_)) => Lane::Bulk,
+            _ => Lane::Control,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Lane::Control => "control",
+            Lane::Audio => "audio",
+            Lane::Video => "video",
+            Lane::Bulk => "bulk",
+        }
+    }
+}
+
+// The delay from `ConnInner::send` to the writer.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LaneStats {
+    pub sent: u64,
+    pub total_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl LaneStats {
+    pub fn avg_delay(&self) -> Duration {
+        if self.sent == 0 {
+            Duration::ZERO
+        } else {
+            self.total_delay / self.sent as u32
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Lanes {
+    queues: [VecDeque<(Instant, Arc<Message>)>; LANES],
+    pass: [u64; LANES],
+    vtime: u64,
+    stats: [LaneStats; LANES],
+    // An audio frame was dropped since the last one sent.
+    audio_gap: bool,
+}
+
+impl Lanes {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.queues.iter().all(|q| q.is_empty())
+    }
+
+    #[inline]
+    pub fn is_full(&self, lane: Lane) -> bool {
+        lane != Lane::Audio && self.queues[lane as usize].len() >= CAPACITIES[lane as usize]
+    }
+
+    // The channel of the messages other than the video ones may be read.
+    #[inline]
+    pub fn can_push_other(&self) -> bool {
+        !self.is_full(Lane::Control) && !self.is_full(Lane::Bulk)
+    }
+
+    /// True if the oldest audio frame is dropped for it.
+    pub fn push(&mut self, instant: Instant, msg: Arc<Message>) -> bool {
+        let lane = Lane::of(&msg) as usize;
+        let queue = &mut self.queues[lane];
+        let mut dropped = false;
+        if queue.is_empty() {
+            self.pass[lane] = self.pass[lane].max(self.vtime);
+        } else if lane == Lane::Audio as usize && queue.len() >= CAPACITIES[lane] {
+            queue.pop_front();
+            self.audio_gap = true;
+            dropped = true;
+        }
+        queue.push_back((instant, msg));
+        dropped
+    }
+
+    pub fn pop(&mut self) -> Option<(Lane, Instant, Arc<Message>)> {
+        let lane = ALL
+            .iter()
+            .filter(|l| !self.queues[**l as usize].is_empty())
+            .min_by_key(|l| self.pass[**l as usize])
+            .copied()?;
+        let i = lane as usize;
+        let (instant, mut msg) = self.queues[i].pop_front()?;
+        if lane == Lane::Audio && std::mem::take(&mut self.audio_gap) {
+            msg = mark_discontinuity(&msg);
+        }
+        self.vtime = self.pass[i];
+        self.pass[i] += STRIDE / WEIGHTS[i];
+        let delay = instant.elapsed();
+        let stats = &mut self.stats[i];
+        stats.sent += 1;
+        stats.total_delay += delay;
+        stats.max_delay = stats.max_delay.max(delay);
+        Some((lane, instant, msg))
+    }
+
+    /// An audio frame taken from the lane is dropped by the writer, the next one is marked too.
+    #[inline]
+    pub fn on_audio_dropped(&mut self) {
+        self.audio_gap = true;
+    }
+
+    pub fn stats(&self, lane: Lane) -> LaneStats {
+        self.stats[lane as usize]
+    }
+
+    pub fn log_stats(&self, conn_id: i32) {
+        let s = ALL
+            .iter()
+            .filter(|l| self.stats[**l as usize].sent > 0)
+            .map(|l| {
+                let stats = &self.stats[*l as usize];
+                format!(
+                    "{} {} sent, delay avg {}ms max {}ms",
+                    l.name(),
+                    stats.sent,
+                    stats.avg_delay().as_millis(),
+                    stats.max_delay.as_millis()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        if !s.is_empty() {
+            log::info!("#{} send lanes: {}", conn_id, s);
+        }
+    }
+}
+
+fn mark_discontinuity(msg: &Message) -> Arc<Message> {
+    let mut msg_out = Message::new();
+    msg_out.set_audio_frame(AudioFrame {
+        discontinuity: true,
+        ..msg.audio_frame().clone()
+    });
+    Arc::new(msg_out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbb_common::message_proto::*;
+
+    fn video() -> Arc<Message> {
+        let mut msg = Message::new();
+        msg.set_video_frame(VideoFrame::new());
+        Arc::new(msg)
+    }
+
+    fn input() -> Arc<Message> {
+        let mut msg = Message::new();
+        msg.set_cursor_position(CursorPosition::new());
+        Arc::new(msg)
+    }
+
+    fn file() -> Arc<Message> {
+        let mut msg = Message::new();
+        msg.set_file_response(FileResponse::new());
+        Arc::new(msg)
+    }
+
+    #[test]
+    fn test_input_ahead_of_video() {
+        let mut lanes = Lanes::default();
+        for _ in 0..100 {
+            lanes.push(Instant::now(), video());
+        }
+        // Mid-burst.
+        for _ in 0..10 {
+            assert_eq!(lanes.pop().map(|x| x.0), Some(Lane::Video));
+        }
+        lanes.push(Instant::now(), input());
+        assert_eq!(lanes.pop().map(|x| x.0), Some(Lane::Control));
+        assert_eq!(lanes.stats(Lane::Control).sent, 1);
+        assert_eq!(lanes.stats(Lane::Video).sent, 10);
+    }
+
+    #[test]
+    fn test_no_starvation() {
+        let mut lanes = Lanes::default();
+        for _ in 0..100 {
+            lanes.push(Instant::now(), video());
+            lanes.push(Instant::now(), file());
+        }
+        let bulk = (0..50)
+            .filter_map(|_| lanes.pop())
+            .filter(|x| x.0 == Lane::Bulk)
+            .count();
+        // The weights of video and bulk are 4:1.
+        assert_eq!(bulk, 10);
+    }
+
+    #[test]
+    fn test_audio_drop_oldest() {
+        let mut lanes = Lanes::default();
+        let mut dropped = 0;
+        for _ in 0..CAPACITIES[Lane::Audio as usize] + 3 {
+            let mut msg = Message::new();
+            msg.set_audio_frame(AudioFrame::new());
+            if lanes.push(Instant::now(), Arc::new(msg)) {
+                dropped += 1;
+            }
+        }
+        assert!(!lanes.is_full(Lane::Audio));
+        assert_eq!(lanes.queues[Lane::Audio as usize].len(), 16);
+        assert_eq!(dropped, 3);
+        // Only the first frame after the drops is marked.
+        assert!(lanes.pop().unwrap().2.audio_frame().discontinuity);
+        assert!(!lanes.pop().unwrap().2.audio_frame().discontinuity);
+    }
+}
@@ -0,0 +1,226 @@
+// The alerts of the sensitive events, the features the peer turns on in the middle of a session
+// which the user of the host must not miss, e.g. the privacy mode hides the screen from them and
+// the input block takes their keyboard and mouse.
+//
+// "sensitive-events" is a comma separated list of the events which are alerted, the default set
+// if it's empty, "none" for none:
+// - "privacy-mode": the privacy mode is turned on.
+// - "input-block": the input of the host is blocked.
+// - "elevated-terminal": a terminal is opened while the server runs as root or SYSTEM.
+// - "credential-injection": the session is elevated with the username and the password given by
+//   the peer.
+// - "elevation": the session is elevated without a password, not in the default set.
+//
+// An event shows a notification to the user of the host and is sent to the connection manager,
+// which plays a sound if "sensitive-events-sound" is "Y". With "sensitive-events-strict" set to
+// "Y", the user must confirm the event in the connection manager within
+// "sensitive-events-confirm-timeout" seconds, 30 by default, or it's undone: the privacy mode is
+// turned off, the input block is turned off with the permission to block it, the terminals are
+// closed, and the elevation grants of the peer are revoked and the connection is closed. Without
+// the connection manager, e.g. in the headless mode, nobody can confirm, so the strict mode undoes
+// all of them. The alerts, the confirmations and the undos are audited.
+
+use super::session_clock;
+use hbb_common::{
+    config::Config,
+    tokio::time::{Duration, Instant},
+};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const OPTION_SENSITIVE_EVENTS: &str = "sensitive-events";
+pub const OPTION_SENSITIVE_EVENTS_SOUND: &str = "sensitive-events-sound";
+pub const OPTION_SENSITIVE_EVENTS_STRICT: &str = "sensitive-events-strict";
+pub const OPTION_SENSITIVE_EVENTS_CONFIRM_TIMEOUT: &str = "sensitive-events-confirm-timeout";
+const DEFAULT_CONFIRM_TIMEOUT_SECS: u64 = 30;
+const NONE: &str = "none";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Event {
+    PrivacyMode,
+    InputBlock,
+    ElevatedTerminal,
+    CredentialInjection,
+    Elevation,
+}
+
+impl Event {
+    pub const ALL: [Event; 5] = [
+        Event::PrivacyMode,
+        Event::InputBlock,
+        Event::ElevatedTerminal,
+        Event::CredentialInjection,
+        Event::Elevation,
+    ];
+    pub const DEFAULT: [Event; 4] = [
+        Event::PrivacyMode,
+        Event::InputBlock,
+        Event::ElevatedTerminal,
+        Event::CredentialInjection,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::PrivacyMode => "privacy-mode",
+            Event::InputBlock => "input-block",
+            Event::ElevatedTerminal => "elevated-terminal",
+            Event::CredentialInjection => "credential-injection",
+            Event::Elevation => "elevation",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|e| e.name() == name)
+    }
+
+    /// The text of the notification, to be translated with the name of the peer.
+    pub fn text(&self, peer: &str) -> String {
+        match self {
+            Event::PrivacyMode => format!("{{{peer}}} turned on the privacy mode"),
+            Event::InputBlock => format!("{{{peer}}} blocked the input of this device"),
+            Event::ElevatedTerminal => {
+                format!("{{{peer}}} opened a terminal with the administrator rights")
+            }
+            Event::CredentialInjection => {
+                format!("{{{peer}}} elevated the session with a username and a password")
+            }
+            Event::Elevation => format!("{{{peer}}} elevated the session"),
+        }
+    }
+}
+
+fn parse(s: &str) -> Vec<Event> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Event::DEFAULT.to_vec();
+    }
+    if s == NONE {
+        return vec![];
+    }
+    let mut events = vec![];
+    for name in s.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+        match Event::from_name(name) {
+            Some(e) if !events.contains(&e) => events.push(e),
+            Some(_) => {}
+            None => hbb_common::log::warn!("Unknown sensitive event: {}", name),
+        }
+    }
+    events
+}
+
+pub fn is_sensitive(event: Event) -> bool {
+    parse(&Config::get_option(OPTION_SENSITIVE_EVENTS)).contains(&event)
+}
+
+/// The time to confirm the event, `None` if it needs no confirmation.
+pub fn confirm_timeout() -> Option<Duration> {
+    if !Config::get_bool_option(OPTION_SENSITIVE_EVENTS_STRICT) {
+        return None;
+    }
+    let secs = Config::get_option(OPTION_SENSITIVE_EVENTS_CONFIRM_TIMEOUT)
+        .parse::<u64>()
+        .unwrap_or(DEFAULT_CONFIRM_TIMEOUT_SECS);
+    Some(Duration::from_secs(secs.max(1)))
+}
+
+/// The events of a connection waiting for the confirmation of the user of the host, to their
+/// deadlines.
+#[derive(Debug, Default)]
+pub struct Pending(HashMap<Event, Instant>);
+
+impl Pending {
+    pub fn add(&mut self, event: Event, timeout: Duration) {
+        self.0.insert(event, session_clock::instant() + timeout);
+    }
+
+    /// The answer of the user, true if the event is to be undone. None if it's not waiting, e.g.
+    /// it's already undone in time.
+    pub fn answer(&mut self, event: Event, confirmed: bool) -> Option<bool> {
+        self.0.remove(&event).map(|_| !confirmed)
+    }
+
+    /// The events not confirmed in time, they are to be undone.
+    pub fn take_expired(&mut self) -> Vec<Event> {
+        let now = session_clock::instant();
+        let expired = self
+            .0
+            .iter()
+            .filter(|(_, deadline)| now >= **deadline)
+            .map(|(event, _)| *event)
+            .collect::<Vec<_>>();
+        for event in &expired {
+            self.0.remove(event);
+        }
+        expired
+    }
+}
+
+/// The audible cue of an alert, in the session of the user, best effort.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn play_sound() {
+    if !Config::get_bool_option(OPTION_SENSITIVE_EVENTS_SOUND) {
+        return;
+    }
+    #[cfg(windows)]
+    unsafe {
+        winapi::um::winuser::MessageBeep(winapi::um::winuser::MB_ICONWARNING);
+    }
+    #[cfg(target_os = "linux")]
+    std::thread::spawn(|| {
+        let played = std::process::Command::new("canberra-gtk-play")
+            .args(["-i", "dialog-warning"])
+            .status()
+            .map_or(false, |s| s.success());
+        if !played {
+            std::process::Command::new("paplay")
+                .arg("/usr/share/sounds/freedesktop/stereo/dialog-warning.oga")
+                .status()
+                .ok();
+        }
+    });
+    #[cfg(target_os = "macos")]
+    std::thread::spawn(|| {
+        std::process::Command::new("afplay")
+            .arg("/System/Library/Sounds/Sosumi.aiff")
+            .status()
+            .ok();
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(""), Event::DEFAULT.to_vec());
+        assert_eq!(parse(" none "), vec![]);
+        assert_eq!(
+            parse("elevation, privacy-mode,elevation,unknown,"),
+            vec![Event::Elevation, Event::PrivacyMode]
+        );
+        for e in Event::ALL {
+            assert_eq!(Event::from_name(e.name()), Some(e));
+        }
+    }
+
+    #[test]
+    fn test_pending() {
+        let mut pending = Pending::default();
+        pending.add(Event::InputBlock, Duration::from_secs(30));
+        pending.add(Event::PrivacyMode, Duration::from_secs(30));
+        pending.add(Event::Elevation, Duration::from_secs(60));
+        // Confirmed in time, it's kept.
+        assert_eq!(pending.answer(Event::PrivacyMode, true), Some(false));
+        assert_eq!(pending.answer(Event::PrivacyMode, false), None);
+        assert!(pending.take_expired().is_empty());
+        session_clock::advance(Duration::from_secs(31));
+        // Nobody confirmed the input block, it's undone once.
+        assert_eq!(pending.take_expired(), vec![Event::InputBlock]);
+        assert!(pending.take_expired().is_empty());
+        assert_eq!(pending.answer(Event::InputBlock, true), None);
+        // Denied in time.
+        assert_eq!(pending.answer(Event::Elevation, false), Some(true));
+        assert!(pending.0.is_empty());
+    }
+}
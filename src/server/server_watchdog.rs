@@ -0,0 +1,196 @@
+// The supervision of the `--server` worker by the Windows service.
+//
+// The worker sends a `ServerHeartbeat` with an increasing sequence number to the service every
+// `HEARTBEAT_INTERVAL`, the reply has the number of the restarts of the worker by the service.
+// The service restarts the worker if it exits with an error, e.g. a fault of a codec driver, or
+// if it sends no heartbeat for `HEARTBEAT_TIMEOUT` after its first one, then it is hung. The
+// restarts after a crash wait for a backoff, doubled for every crash up to `MAX_BACKOFF`, and
+// reset once a worker runs for `STABLE_PERIOD`, so a worker which crashes right away can't cause
+// a restart loop.
+//
+// A restarted worker registers with the rendezvous server at once and its direct server retries
+// the port, which may still be held for a while by the crashed worker. The restart count is in
+// the heartbeat to the api server and `--server-watchdog`.
+
+use hbb_common::{
+    log, tokio,
+    tokio::time::{Duration, Instant},
+    ResultType,
+};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(20);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const STABLE_PERIOD: Duration = Duration::from_secs(300);
+// How long the worker waits for the first reply of the service after it starts.
+const FIRST_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The worker side, what the service replied to the last heartbeat.
+static RESTARTS: AtomicU32 = AtomicU32::new(0);
+static LAST_SEQ: AtomicU64 = AtomicU64::new(0);
+lazy_static::lazy_static! {
+    static ref STARTED: Instant = Instant::now();
+}
+
+/// The restarts of the worker by the service, 0 if it is not run by the service.
+#[inline]
+pub fn restarts() -> u32 {
+    RESTARTS.load(Ordering::SeqCst)
+}
+
+/// `restarts`, after the first reply of the service, which is only known after the first
+/// heartbeat. It waits for it up to `FIRST_REPLY_TIMEOUT` after the start of the worker.
+pub async fn wait_restarts() -> u32 {
+    if !crate::platform::is_root() {
+        return 0;
+    }
+    let deadline = *STARTED + FIRST_REPLY_TIMEOUT;
+    while LAST_SEQ.load(Ordering::SeqCst) == 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    restarts()
+}
+
+pub fn get_status() -> String {
+    serde_json::json!({
+        "restarts": restarts(),
+        "heartbeat_seq": LAST_SEQ.load(Ordering::SeqCst),
+    })
+    .to_string()
+}
+
+/// Send the heartbeats to the service, called by the worker run by the service.
+pub fn start() {
+    if !crate::platform::is_root() {
+        return;
+    }
+    lazy_static::initialize(&STARTED);
+    // On the runtime of the server, a hung runtime stops the heartbeats.
+    tokio::spawn(async {
+        let mut seq = 0;
+        loop {
+            seq += 1;
+            if let Err(err) = beat(seq).await {
+                log::debug!("Failed to send the heartbeat to the service: {}", err);
+            }
+            tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+        }
+    });
+}
+
+async fn beat(seq: u64) -> ResultType<()> {
+    use crate::ipc::Data;
+    let mut stream = crate::ipc::connect(1_000, crate::POSTFIX_SERVICE).await?;
+    stream
+        .send(&Data::ServerHeartbeat { seq, restarts: 0 })
+        .await?;
+    if let Some(Data::ServerHeartbeat { restarts, .. }) = stream.next_timeout(1_000).await? {
+        LAST_SEQ.store(seq, Ordering::SeqCst);
+        if RESTARTS.swap(restarts, Ordering::SeqCst) != restarts && restarts > 0 {
+            log::warn!(
+                "The server has been restarted by the service {} times",
+                restarts
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The service side, the state of the worker it runs.
+pub struct Supervisor {
+    launched_at: Instant,
+    last_beat: Option<(Instant, u64)>,
+    restarts: u32,
+    // The crashes since the last worker which ran for `STABLE_PERIOD`.
+    consecutive: u32,
+    next_launch: Option<Instant>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self {
+            launched_at: Instant::now(),
+            last_beat: None,
+            restarts: 0,
+            consecutive: 0,
+            next_launch: None,
+        }
+    }
+
+    pub fn on_launched(&mut self) {
+        self.launched_at = Instant::now();
+        self.last_beat = None;
+        self.next_launch = None;
+    }
+
+    /// Returns false if the sequence number is not newer, it is ignored.
+    pub fn on_beat(&mut self, seq: u64) -> bool {
+        if self.last_beat.map_or(false, |(_, last)| seq <= last) {
+            log::warn!("Ignore the stale heartbeat {} of the server", seq);
+            return false;
+        }
+        self.last_beat = Some((Instant::now(), seq));
+        true
+    }
+
+    /// The worker has sent heartbeats, but not for `HEARTBEAT_TIMEOUT`.
+    pub fn is_hung(&self) -> bool {
+        self.last_beat
+            .map_or(false, |(t, _)| t.elapsed() >= HEARTBEAT_TIMEOUT)
+    }
+
+    /// Count the crash of the worker, returns the backoff before it is launched again.
+    pub fn on_crash(&mut self) -> Duration {
+        if self.launched_at.elapsed() >= STABLE_PERIOD {
+            self.consecutive = 0;
+        }
+        self.restarts += 1;
+        self.consecutive += 1;
+        self.last_beat = None;
+        let backoff = MIN_BACKOFF
+            .saturating_mul(1 << (self.consecutive - 1).min(16))
+            .min(MAX_BACKOFF);
+        self.next_launch = Some(Instant::now() + backoff);
+        backoff
+    }
+
+    #[inline]
+    pub fn may_launch(&self) -> bool {
+        self.next_launch.map_or(true, |t| Instant::now() >= t)
+    }
+
+    #[inline]
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_supervisor() {
+        let mut s = Supervisor::new();
+        assert!(!s.is_hung());
+        assert!(s.may_launch());
+        assert!(s.on_beat(1));
+        assert!(!s.on_beat(1));
+        assert!(s.on_beat(2));
+        assert!(!s.is_hung());
+
+        let backoffs: Vec<_> = (0..8).map(|_| s.on_crash()).collect();
+        assert_eq!(backoffs[0], MIN_BACKOFF);
+        assert_eq!(backoffs[1], MIN_BACKOFF * 2);
+        assert_eq!(backoffs[7], MAX_BACKOFF);
+        assert_eq!(s.restarts(), 8);
+        assert!(!s.may_launch());
+
+        // A restarted worker starts its sequence again.
+        s.on_launched();
+        assert!(s.may_launch());
+        assert!(s.on_beat(1));
+    }
+}
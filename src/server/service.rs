@@ -11,6 +11,10 @@ pub trait Service: Send + Sync {
     fn on_subscribe(&self, sub: ConnInner);
     fn on_unsubscribe(&self, id: i32);
     fn is_subed(&self, id: i32) -> bool;
+    // The subscriber has got the snapshot of the service.
+    fn is_ready(&self, id: i32) -> bool;
+    // Resolved when the subscriber has got the snapshot, or dropped if it's unsubscribed before.
+    fn ready(&self, id: i32) -> tokio::sync::oneshot::Receiver<()>;
     fn join(&self);
     fn get_option(&self, opt: &str) -> Option<String>;
     fn set_option(&self, opt: &str, val: &str) -> Option<String>;
@@ -20,6 +24,11 @@ pub trait Service: Send + Sync {
 pub trait Subscriber: Default + Send + Sync + 'static {
     fn id(&self) -> i32;
     fn send(&mut self, msg: Arc<Message>);
+    // The newest version of the messages of the kind which the peer supports,
+    // 0 if the peer does not tell it.
+    fn msg_version(&self, _kind: &str) -> u32 {
+        0
+    }
 }
 
 #[derive(Default)]
@@ -28,9 +37,12 @@ pub struct ServiceInner<T: Subscriber + From<ConnInner>> {
     handle: Option<JoinHandle<()>>,
     subscribes: HashMap<i32, T>,
     new_subscribes: HashMap<i32, T>,
+    ready_waiters: HashMap<i32, tokio::sync::oneshot::Sender<()>>,
     active: bool,
     need_snapshot: bool,
     options: HashMap<String, String>,
+    // The kind and the newest version of the messages of the service.
+    msg_version: Option<(&'static str, u32)>,
 }
 
 pub trait Reset {
@@ -82,6 +94,9 @@ impl<T: Subscriber + From<ConnInner>> ServiceInner<T> {
 
     fn swap_new_subscribes(&mut self) {
         for (_, s) in self.new_subscribes.drain() {
+            if let Some(tx) = self.ready_waiters.remove(&s.id()) {
+                tx.send(()).ok();
+            }
             self.subscribes.insert(s.id(), s);
         }
         debug_assert!(self.new_subscribes.is_empty());
@@ -91,6 +106,60 @@ impl<T: Subscriber + From<ConnInner>> ServiceInner<T> {
     fn has_subscribes(&self) -> bool {
         self.subscribes.len() > 0 || self.new_subscribes.len() > 0
     }
+
+    fn msg_version_of(msg_version: Option<(&'static str, u32)>, s: &T) -> u32 {
+        msg_version.map_or(0, |(kind, version)| version.min(s.msg_version(kind)))
+    }
+
+    // Tell the new subscribers the version of the messages they will get.
+    fn advertise_msg_version(&mut self) {
+        let Some((kind, _)) = self.msg_version else {
+            return;
+        };
+        let msg_version = self.msg_version;
+        for s in self.new_subscribes.values_mut() {
+            let version = Self::msg_version_of(msg_version, s);
+            s.send(Arc::new(msg_version_message(kind, version)));
+        }
+    }
+
+    // The message is encoded once for each version in use.
+    fn send_versioned(
+        &mut self,
+        new_subscribes: bool,
+        encode: impl Fn(u32) -> Message,
+    ) -> HashSet<i32> {
+        let msg_version = self.msg_version;
+        let subscribes = if new_subscribes {
+            &mut self.new_subscribes
+        } else {
+            &mut self.subscribes
+        };
+        let mut encoded: HashMap<u32, Arc<Message>> = HashMap::new();
+        let mut conn_ids = HashSet::new();
+        for s in subscribes.values_mut() {
+            let version = Self::msg_version_of(msg_version, s);
+            let msg = encoded
+                .entry(version)
+                .or_insert_with(|| Arc::new(encode(version)))
+                .clone();
+            s.send(msg);
+            conn_ids.insert(s.id());
+        }
+        conn_ids
+    }
+}
+
+fn msg_version_message(kind: &str, version: u32) -> Message {
+    let mut misc = Misc::new();
+    misc.set_service_msg_version(ServiceMsgVersion {
+        kind: kind.to_owned(),
+        version,
+        ..Default::default()
+    });
+    let mut msg = Message::new();
+    msg.set_misc(misc);
+    msg
 }
 
 impl<T: Subscriber + From<ConnInner>> Service for ServiceTmpl<T> {
@@ -104,6 +173,21 @@ impl<T: Subscriber + From<ConnInner>> Service for ServiceTmpl<T> {
             || self.0.read().unwrap().new_subscribes.get(&id).is_some()
     }
 
+    fn is_ready(&self, id: i32) -> bool {
+        self.0.read().unwrap().subscribes.contains_key(&id)
+    }
+
+    fn ready(&self, id: i32) -> tokio::sync::oneshot::Receiver<()> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let mut lock = self.0.write().unwrap();
+        if lock.new_subscribes.contains_key(&id) {
+            lock.ready_waiters.insert(id, tx);
+        } else if lock.subscribes.contains_key(&id) {
+            tx.send(()).ok();
+        }
+        rx
+    }
+
     fn on_subscribe(&self, sub: ConnInner) {
         let mut lock = self.0.write().unwrap();
         if lock.subscribes.get(&sub.id()).is_some() {
@@ -112,12 +196,18 @@ impl<T: Subscriber + From<ConnInner>> Service for ServiceTmpl<T> {
         if lock.need_snapshot {
             lock.new_subscribes.insert(sub.id(), sub.into());
         } else {
-            lock.subscribes.insert(sub.id(), sub.into());
+            let mut sub: T = sub.into();
+            if let Some((kind, _)) = lock.msg_version {
+                let version = ServiceInner::<T>::msg_version_of(lock.msg_version, &sub);
+                sub.send(Arc::new(msg_version_message(kind, version)));
+            }
+            lock.subscribes.insert(sub.id(), sub);
         }
     }
 
     fn on_unsubscribe(&self, id: i32) {
         let mut lock = self.0.write().unwrap();
+        lock.ready_waiters.remove(&id);
         if let None = lock.subscribes.remove(&id) {
             lock.new_subscribes.remove(&id);
         }
@@ -148,7 +238,9 @@ impl<T: Subscriber + From<ConnInner>> Service for ServiceTmpl<T> {
     #[inline]
     fn ok(&self) -> bool {
         let lock = self.0.read().unwrap();
-        lock.active && lock.has_subscribes()
+        // The thread may have exited on a panic while the service is still active.
+        let finished = lock.handle.as_ref().map_or(false, |h| h.is_finished());
+        lock.active && lock.has_subscribes() && !finished
     }
 }
 
@@ -188,12 +280,31 @@ impl<T: Subscriber + From<ConnInner>> ServiceTmpl<T> {
         self.0.read().unwrap().has_subscribes()
     }
 
+    /// The messages of the service have versions, the subscribers get the version in the
+    /// snapshot and the messages sent with `send_versioned` are encoded for it.
+    pub fn set_msg_version(&self, kind: &'static str, version: u32) {
+        self.0.write().unwrap().msg_version = Some((kind, version));
+    }
+
+    pub fn subscriber_ids(&self) -> Vec<i32> {
+        self.0.read().unwrap().subscribes.keys().cloned().collect()
+    }
+
+    // Drop all the subscribers, e.g. the source of the service is gone.
+    pub fn unsubscribe_all(&self) {
+        let mut lock = self.0.write().unwrap();
+        lock.subscribes.clear();
+        lock.new_subscribes.clear();
+        lock.ready_waiters.clear();
+    }
+
     pub fn snapshot<F>(&self, callback: F) -> ResultType<()>
     where
         F: FnMut(ServiceSwap<T>) -> ResultType<()>,
     {
         if self.0.read().unwrap().new_subscribes.len() > 0 {
             log::info!("Call snapshot of {} service", self.name());
+            self.0.write().unwrap().advertise_msg_version();
             let mut callback = callback;
             callback(ServiceSwap::<T>(self.clone()))?;
         }
@@ -242,6 +353,12 @@ impl<T: Subscriber + From<ConnInner>> ServiceTmpl<T> {
         conn_ids
     }
 
+    /// Send the message encoded by `encode` for the message version of each subscriber,
+    /// the ids of the subscribers are returned.
+    pub fn send_versioned(&self, encode: impl Fn(u32) -> Message) -> HashSet<i32> {
+        self.0.write().unwrap().send_versioned(false, encode)
+    }
+
     pub fn send_without(&self, msg: Message, sub: i32) {
         let mut lock = self.0.write().unwrap();
         let msg = Arc::new(msg);
@@ -345,6 +462,11 @@ impl<T: Subscriber + From<ConnInner>> ServiceSwap<T> {
         (self.0).0.write().unwrap().send_new_subscribes(msg);
     }
 
+    #[inline]
+    pub fn send_versioned(&self, encode: impl Fn(u32) -> Message) {
+        (self.0).0.write().unwrap().send_versioned(true, encode);
+    }
+
     #[inline]
     pub fn has_subscribes(&self) -> bool {
         (self.0).0.read().unwrap().subscribes.len() > 0
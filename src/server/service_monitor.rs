@@ -0,0 +1,157 @@
+// Watch the services of the server, a service which stops working is reported and restarted.
+//
+// `ok()` of a service turning false while connections are still subscribed to it means its thread
+// is gone, e.g. it panicked, the normal unsubscribe of the last connection is not a failure.
+// The known services are created again with the same name and the connections are subscribed to
+// the new one, at most once every `MIN_RESTART_INTERVAL` for a service, so a service which fails
+// right away can't cause a restart loop. Every failure is sent to `subscribe()` as a
+// `ServiceEvent`, with whether a restart is attempted and the subscribed connections, which tell
+// their peers if it's not restarted.
+
+use super::*;
+use hbb_common::tokio::sync::broadcast;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const MIN_RESTART_INTERVAL: Duration = Duration::from_secs(30);
+
+lazy_static::lazy_static! {
+    static ref EVENTS: broadcast::Sender<ServiceEvent> = broadcast::channel(16).0;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceEvent {
+    pub name: String,
+    pub restart_attempted: bool,
+    // The ids of the connections subscribed to the service.
+    pub conns: Vec<i32>,
+}
+
+pub fn subscribe() -> broadcast::Receiver<ServiceEvent> {
+    EVENTS.subscribe()
+}
+
+#[derive(Default)]
+struct Monitor {
+    last_ok: HashMap<String, bool>,
+    last_restart: HashMap<String, Instant>,
+}
+
+/// Poll the services until the server is dropped.
+pub fn start(server: ServerPtrWeak) {
+    std::thread::spawn(move || {
+        let mut monitor = Monitor::default();
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let Some(server) = server.upgrade() else {
+                break;
+            };
+            let events = monitor.check(&mut server.write().unwrap());
+            drop(server);
+            for event in events {
+                EVENTS.send(event).ok();
+            }
+        }
+    });
+}
+
+impl Monitor {
+    fn check(&mut self, server: &mut Server) -> Vec<ServiceEvent> {
+        let mut failed = vec![];
+        for (name, s) in server.services.iter() {
+            let ok = s.ok();
+            let was_ok = self.last_ok.insert(name.clone(), ok).unwrap_or(false);
+            if !was_ok || ok {
+                continue;
+            }
+            let subs: Vec<ConnInner> = server
+                .connections
+                .values()
+                .filter(|c| s.is_subed(c.id()))
+                .cloned()
+                .collect();
+            if !subs.is_empty() {
+                failed.push((name.clone(), subs));
+            }
+        }
+        failed
+            .into_iter()
+            .map(|(name, subs)| {
+                let conns = subs.iter().map(|c| c.id()).collect();
+                let restart_attempted = self.restart(server, &name, subs);
+                log::error!(
+                    "Service {} stopped working, restart attempted: {}",
+                    name,
+                    restart_attempted
+                );
+                ServiceEvent {
+                    name,
+                    restart_attempted,
+                    conns,
+                }
+            })
+            .collect()
+    }
+
+    fn restart(&mut self, server: &mut Server, name: &str, subs: Vec<ConnInner>) -> bool {
+        if self
+            .last_restart
+            .get(name)
+            .map(|t| t.elapsed() < MIN_RESTART_INTERVAL)
+            .unwrap_or(false)
+        {
+            return false;
+        }
+        let Some(service) = new_service(name) else {
+            return false;
+        };
+        self.last_restart.insert(name.to_owned(), Instant::now());
+        for conn in subs {
+            service.on_subscribe(conn);
+        }
+        if let Some(old) = server.add_service(service) {
+            old.join();
+        }
+        true
+    }
+}
+
+// Create the service as `new()` and the video subscriptions do, None if it can't be restarted.
+fn new_service(name: &str) -> Option<Box<dyn Service>> {
+    for source in [VideoSource::Monitor, VideoSource::Camera] {
+        if let Some(idx) = name
+            .strip_prefix(source.service_name_prefix())
+            .and_then(|x| x.parse::<usize>().ok())
+        {
+            return Some(Box::new(video_service::new(source, idx)));
+        }
+    }
+    if name == audio_service::NAME {
+        return Some(Box::new(audio_service::new()));
+    }
+    #[cfg(not(target_os = "ios"))]
+    {
+        if name == display_service::NAME {
+            return Some(Box::new(display_service::new()));
+        }
+        if name == clipboard_service::NAME {
+            return Some(Box::new(clipboard_service::new(name.to_owned())));
+        }
+        #[cfg(feature = "unix-file-copy-paste")]
+        if name == clipboard_service::FILE_NAME {
+            return Some(Box::new(clipboard_service::new(name.to_owned())));
+        }
+    }
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    {
+        if name == input_service::NAME_CURSOR {
+            return Some(Box::new(input_service::new_cursor()));
+        }
+        if name == input_service::NAME_POS {
+            return Some(Box::new(input_service::new_pos()));
+        }
+        if name == input_service::NAME_WINDOW_FOCUS {
+            return Some(Box::new(input_service::new_window_focus()));
+        }
+    }
+    None
+}
@@ -0,0 +1,89 @@
+// The clocks of the sessions. The wall clock jumps with NTP and with a change by hand, so a timer
+// measured by it misfires after a jump, e.g. a jump forward of an hour ends the throttle window
+// of the login failures at once, and a jump backward makes an older quality setting look like the
+// latest one. The timers, the windows and the orders of the sessions use `instant` and `now_ms`,
+// which follow `Instant` and never jump. The wall clock, `wall_ms`, is only for the absolute
+// times: the audits, the trusted devices, TOTP and the times shared with other processes, e.g.
+// `CLICK_TIME`, which the connection manager compares with its own wall clock.
+//
+// The tests move both clocks, `advance` is the time going on and `jump_wall_clock` is a jump.
+
+use hbb_common::tokio::time::{Duration, Instant};
+
+lazy_static::lazy_static! {
+    static ref START: Instant = Instant::now();
+}
+
+#[cfg(test)]
+pub const HOUR_MS: i64 = 3600 * 1000;
+
+// The moves of the clocks by a test, per thread as the tests run in parallel.
+#[cfg(test)]
+thread_local! {
+    static ADVANCE: std::cell::Cell<Duration> = std::cell::Cell::new(Duration::ZERO);
+    static WALL_CLOCK_JUMP: std::cell::Cell<i64> = std::cell::Cell::new(0);
+}
+
+/// The monotonic now for the timers of the sessions.
+#[inline]
+pub fn instant() -> Instant {
+    #[cfg(test)]
+    return Instant::now() + ADVANCE.with(|a| a.get());
+    #[cfg(not(test))]
+    Instant::now()
+}
+
+/// The time since `since` by `instant`.
+#[inline]
+pub fn elapsed(since: Instant) -> Duration {
+    instant().saturating_duration_since(since)
+}
+
+/// The milliseconds since the first use, monotonic, for the timers of the sessions.
+#[inline]
+pub fn now_ms() -> i64 {
+    elapsed(*START).as_millis() as i64
+}
+
+/// The milliseconds since the unix epoch, only for the absolute times.
+#[inline]
+pub fn wall_ms() -> i64 {
+    #[cfg(test)]
+    return hbb_common::get_time()
+        + ADVANCE.with(|a| a.get().as_millis() as i64)
+        + WALL_CLOCK_JUMP.with(|j| j.get());
+    #[cfg(not(test))]
+    hbb_common::get_time()
+}
+
+/// Lets the time go on, both clocks move.
+#[cfg(test)]
+pub fn advance(d: Duration) {
+    ADVANCE.with(|a| a.set(a.get() + d));
+}
+
+/// Moves the wall clock only, like NTP or the user of the host would.
+#[cfg(test)]
+pub fn jump_wall_clock(ms: i64) {
+    WALL_CLOCK_JUMP.with(|j| j.set(j.get() + ms));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wall_clock_jump() {
+        let (now, wall) = (now_ms(), wall_ms());
+        jump_wall_clock(HOUR_MS);
+        assert!(wall_ms() - wall >= HOUR_MS);
+        jump_wall_clock(-2 * HOUR_MS);
+        assert!(wall_ms() < wall);
+        jump_wall_clock(HOUR_MS);
+        let elapsed = now_ms() - now;
+        assert!((0..1000).contains(&elapsed));
+        advance(Duration::from_secs(60));
+        assert!(now_ms() - now >= 60_000);
+        assert!(wall_ms() - wall >= 60_000);
+    }
+}
@@ -0,0 +1,605 @@
+// Quality report of a session, assembled at disconnect.
+//
+// The connection collects the round trip times and the video bytes it sends, the services count
+// the events shared by all connections (codec switches, dropped frames, audio restarts), the
+// report takes the difference of these counters during the session. Building the report is only
+// a sort of the bounded rtt samples, so it doesn't delay the teardown.
+//
+// The connection also counts the bytes, the messages and the frames in a measurement window,
+// `ConnStats`, which the server reads and resets without restarting the session. They are
+// atomics shared with the server, the connection doesn't lock to count, and the sizes are those
+// of the encoded messages it sends.
+//
+// The video frames dropped for a connection are counted by the reason only if the peer asks for
+// them (`OptionMessage.frame_drop_stats`, with the quality monitor), the video services skip the
+// accounting while no connection has it enabled.
+
+use hbb_common::{
+    config::Config,
+    get_time, log,
+    message_proto::{message, Message},
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+pub const OPTION_SEND_SESSION_REPORT: &str = "send-session-report";
+const MAX_REPORTS: usize = 20;
+const MAX_RTT_SAMPLES: usize = 4096;
+const BUCKET: Duration = Duration::from_secs(60);
+const MAX_BUCKETS: usize = 180;
+
+lazy_static::lazy_static! {
+    static ref COUNTERS: Mutex<Counters> = Default::default();
+    static ref REPORTS: Mutex<VecDeque<SessionReport>> = Default::default();
+    static ref FRAME_DROP_STATS: Mutex<HashMap<i32, Arc<ConnStats>>> = Default::default();
+}
+static FRAME_DROP_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Default)]
+struct Counters {
+    codec_switches: u64,
+    audio_restarts: u64,
+    cpu_throttles: u64,
+    frame_drops: HashMap<&'static str, u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionReport {
+    pub conn_id: i32,
+    pub peer_id: String,
+    pub peer_name: String,
+    pub transport: String,
+    pub start_time: i64, // ms
+    pub duration_secs: u64,
+    pub rtt_avg_ms: u32,
+    pub rtt_p95_ms: u32,
+    pub bucket_secs: u64,
+    // The video bitrate of every bucket, the oldest ones are dropped in a very long session.
+    pub bitrate_kbps: Vec<u32>,
+    pub frame_drops: HashMap<String, u64>,
+    pub codec_switches: u64,
+    pub audio_restarts: u64,
+    // "local-first" if the host puts its local user first, see `host_priority`.
+    pub host_priority: String,
+    // The times the video is throttled for the high cpu usage of the host.
+    pub cpu_throttles: u64,
+    pub close_reason: String,
+}
+
+pub fn on_codec_switch() {
+    COUNTERS.lock().unwrap().codec_switches += 1;
+}
+
+pub fn on_audio_restart() {
+    COUNTERS.lock().unwrap().audio_restarts += 1;
+}
+
+pub fn on_cpu_throttle() {
+    COUNTERS.lock().unwrap().cpu_throttles += 1;
+}
+
+pub fn on_frame_dropped(reason: &'static str) {
+    *COUNTERS
+        .lock()
+        .unwrap()
+        .frame_drops
+        .entry(reason)
+        .or_default() += 1;
+}
+
+#[inline]
+pub fn is_send_enabled() -> bool {
+    Config::get_option(OPTION_SEND_SESSION_REPORT) == "Y"
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameDropReason {
+    // The fps is lowered by the qos for the network.
+    Bandwidth,
+    // The capture and the encoding take longer than the frame interval, or the frame is too large.
+    EncoderOverrun,
+    // The frames are not fetched by the connection in time.
+    QueueFull,
+    // The capture is idle or the peer window is hidden.
+    Paused,
+}
+
+impl FrameDropReason {
+    pub const ALL: [Self; 4] = [
+        Self::Bandwidth,
+        Self::EncoderOverrun,
+        Self::QueueFull,
+        Self::Paused,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Bandwidth => "bandwidth",
+            Self::EncoderOverrun => "encoder_overrun",
+            Self::QueueFull => "queue_full",
+            Self::Paused => "paused",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConnStatsSnapshot {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub video_frames_sent: u64,
+    pub audio_frames_sent: u64,
+    #[serde(default)]
+    pub video_frames_dropped_bandwidth: u64,
+    #[serde(default)]
+    pub video_frames_dropped_encoder_overrun: u64,
+    #[serde(default)]
+    pub video_frames_dropped_queue_full: u64,
+    #[serde(default)]
+    pub video_frames_dropped_paused: u64,
+    // The audio frames dropped on a congested connection, the oldest ones of the full send lane
+    // and the ones which waited too long to be sent.
+    #[serde(default)]
+    pub audio_frames_dropped: u64,
+    // Whether the cursor positions are sent in batches, and the positions in the last batch.
+    // They are the state of the connection, not reset with the counters.
+    #[serde(default)]
+    pub cursor_batching: bool,
+    #[serde(default)]
+    pub cursor_batch_size: u64,
+    // The length of the window, since the connection or the last reset.
+    pub elapsed_ms: u64,
+}
+
+impl ConnStatsSnapshot {
+    pub fn video_frames_dropped(&self, reason: FrameDropReason) -> u64 {
+        match reason {
+            FrameDropReason::Bandwidth => self.video_frames_dropped_bandwidth,
+            FrameDropReason::EncoderOverrun => self.video_frames_dropped_encoder_overrun,
+            FrameDropReason::QueueFull => self.video_frames_dropped_queue_full,
+            FrameDropReason::Paused => self.video_frames_dropped_paused,
+        }
+    }
+}
+
+/// The counters of a connection in a measurement window. A reset takes every counter at once
+/// with a swap, so an update is counted in the old window or in the new one, never lost or
+/// doubled, though the bytes and the count of a message may fall in different windows.
+#[derive(Debug)]
+pub struct ConnStats {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    video_frames_sent: AtomicU64,
+    audio_frames_sent: AtomicU64,
+    // By `FrameDropReason`.
+    video_frames_dropped: [AtomicU64; 4],
+    audio_frames_dropped: AtomicU64,
+    cursor_batching: AtomicBool,
+    cursor_batch_size: AtomicU64,
+    // Only locked by a snapshot and a reset.
+    since: Mutex<Instant>,
+}
+
+impl Default for ConnStats {
+    fn default() -> Self {
+        Self {
+            bytes_sent: Default::default(),
+            bytes_received: Default::default(),
+            messages_sent: Default::default(),
+            messages_received: Default::default(),
+            video_frames_sent: Default::default(),
+            audio_frames_sent: Default::default(),
+            video_frames_dropped: Default::default(),
+            audio_frames_dropped: Default::default(),
+            cursor_batching: Default::default(),
+            cursor_batch_size: Default::default(),
+            since: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl ConnStats {
+    pub(super) fn on_sent(&self, msg: &Message, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as _, Ordering::Relaxed);
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        match &msg.union {
+            Some(message::Union::VideoFrame(_)) => {
+                self.video_frames_sent.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(message::Union::AudioFrame(_)) => {
+                self.audio_frames_sent.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    pub(super) fn on_received(&self, bytes: usize) {
+        self.bytes_received.fetch_add(bytes as _, Ordering::Relaxed);
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn on_video_frames_dropped(&self, reason: FrameDropReason, n: u64) {
+        self.video_frames_dropped[reason as usize].fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn on_audio_frame_dropped(&self) {
+        self.audio_frames_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_cursor_batching(&self, enabled: bool) {
+        self.cursor_batching.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn on_cursor_batch_sent(&self, positions: usize) {
+        self.cursor_batch_size.store(positions as _, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ConnStatsSnapshot {
+        let since = *self.since.lock().unwrap();
+        self.take(since, |v| v.load(Ordering::Relaxed))
+    }
+
+    /// Clear the counters and start a new window, the counters of the old one are returned.
+    pub fn reset(&self) -> ConnStatsSnapshot {
+        let since = std::mem::replace(&mut *self.since.lock().unwrap(), Instant::now());
+        self.take(since, |v| v.swap(0, Ordering::Relaxed))
+    }
+
+    fn take(&self, since: Instant, f: impl Fn(&AtomicU64) -> u64) -> ConnStatsSnapshot {
+        let dropped = |reason: FrameDropReason| f(&self.video_frames_dropped[reason as usize]);
+        ConnStatsSnapshot {
+            bytes_sent: f(&self.bytes_sent),
+            bytes_received: f(&self.bytes_received),
+            messages_sent: f(&self.messages_sent),
+            messages_received: f(&self.messages_received),
+            video_frames_sent: f(&self.video_frames_sent),
+            audio_frames_sent: f(&self.audio_frames_sent),
+            video_frames_dropped_bandwidth: dropped(FrameDropReason::Bandwidth),
+            video_frames_dropped_encoder_overrun: dropped(FrameDropReason::EncoderOverrun),
+            video_frames_dropped_queue_full: dropped(FrameDropReason::QueueFull),
+            video_frames_dropped_paused: dropped(FrameDropReason::Paused),
+            audio_frames_dropped: f(&self.audio_frames_dropped),
+            cursor_batching: self.cursor_batching.load(Ordering::Relaxed),
+            cursor_batch_size: self.cursor_batch_size.load(Ordering::Relaxed),
+            elapsed_ms: since.elapsed().as_millis() as _,
+        }
+    }
+}
+
+/// The frames of the interval `spf` requested by a connection that are not captured in
+/// `interval`, and the reason of the delay.
+pub fn frame_drops(
+    interval: Duration,
+    spf: Duration,
+    encode_elapsed: Duration,
+    wait_elapsed: Duration,
+    paused: bool,
+) -> Option<(FrameDropReason, u64)> {
+    let frames = (interval.as_secs_f64() / spf.as_secs_f64()) as u64;
+    let dropped = frames.saturating_sub(1);
+    if dropped == 0 {
+        return None;
+    }
+    let reason = if paused {
+        FrameDropReason::Paused
+    } else if encode_elapsed > spf {
+        FrameDropReason::EncoderOverrun
+    } else if encode_elapsed + wait_elapsed > spf {
+        FrameDropReason::QueueFull
+    } else {
+        FrameDropReason::Bandwidth
+    };
+    Some((reason, dropped))
+}
+
+/// Enable or disable the accounting of the dropped video frames of the connection.
+pub fn set_frame_drop_accounting(conn_id: i32, stats: Option<Arc<ConnStats>>) {
+    let mut lock = FRAME_DROP_STATS.lock().unwrap();
+    match stats {
+        Some(stats) => {
+            lock.insert(conn_id, stats);
+        }
+        None => {
+            lock.remove(&conn_id);
+        }
+    }
+    FRAME_DROP_ACTIVE.store(!lock.is_empty(), Ordering::SeqCst);
+}
+
+pub fn remove_frame_drop_accounting(conn_id: i32) {
+    let mut lock = FRAME_DROP_STATS.lock().unwrap();
+    if lock.remove(&conn_id).is_some() {
+        FRAME_DROP_ACTIVE.store(!lock.is_empty(), Ordering::SeqCst);
+    }
+}
+
+#[inline]
+pub fn is_frame_drop_accounting() -> bool {
+    FRAME_DROP_ACTIVE.load(Ordering::Relaxed)
+}
+
+pub fn on_video_frames_dropped(conn_ids: &[i32], reason: FrameDropReason, n: u64) {
+    if n == 0 || !is_frame_drop_accounting() {
+        return;
+    }
+    let lock = FRAME_DROP_STATS.lock().unwrap();
+    for id in conn_ids {
+        if let Some(stats) = lock.get(id) {
+            stats.on_video_frames_dropped(reason, n);
+        }
+    }
+}
+
+pub struct SessionStats {
+    start: Instant,
+    start_time: i64,
+    relay: bool,
+    rtts: Vec<u32>,
+    rtt_count: usize,
+    buckets: VecDeque<u64>,
+    bucket_start: Instant,
+    baseline: Counters,
+    // Shared with the server, see `Server::reset_connection_stats`.
+    window: Arc<ConnStats>,
+}
+
+impl SessionStats {
+    pub fn new(relay: bool) -> Self {
+        Self {
+            start: Instant::now(),
+            start_time: get_time(),
+            relay,
+            rtts: Vec::new(),
+            rtt_count: 0,
+            buckets: VecDeque::from([0]),
+            bucket_start: Instant::now(),
+            baseline: COUNTERS.lock().unwrap().clone(),
+            window: Default::default(),
+        }
+    }
+
+    #[inline]
+    pub fn window(&self) -> &Arc<ConnStats> {
+        &self.window
+    }
+
+    // The samples wrap around when they are full, the percentiles come from the recent ones.
+    pub fn on_rtt(&mut self, rtt: u32) {
+        if self.rtts.len() < MAX_RTT_SAMPLES {
+            self.rtts.push(rtt);
+        } else {
+            self.rtts[self.rtt_count % MAX_RTT_SAMPLES] = rtt;
+        }
+        self.rtt_count += 1;
+    }
+
+    // `bytes` is the size of the encoded message.
+    pub fn on_sent(&mut self, msg: &Message, bytes: usize) {
+        self.window.on_sent(msg, bytes);
+        if let Some(message::Union::VideoFrame(_)) = &msg.union {
+            self.roll_buckets();
+            if let Some(b) = self.buckets.back_mut() {
+                *b += bytes as u64;
+            }
+        }
+    }
+
+    #[inline]
+    pub fn on_received(&self, bytes: usize) {
+        self.window.on_received(bytes);
+    }
+
+    fn roll_buckets(&mut self) {
+        while self.bucket_start.elapsed() >= BUCKET {
+            self.bucket_start += BUCKET;
+            self.buckets.push_back(0);
+            if self.buckets.len() > MAX_BUCKETS {
+                self.buckets.pop_front();
+            }
+        }
+    }
+
+    pub fn report(
+        &mut self,
+        conn_id: i32,
+        peer_id: &str,
+        peer_name: &str,
+        close_reason: &str,
+    ) -> SessionReport {
+        self.roll_buckets();
+        let (rtt_avg_ms, rtt_p95_ms) = if self.rtts.is_empty() {
+            (0, 0)
+        } else {
+            let mut rtts = self.rtts.clone();
+            rtts.sort_unstable();
+            let avg = rtts.iter().map(|x| *x as u64).sum::<u64>() / rtts.len() as u64;
+            let p95 = rtts[(rtts.len() * 95 / 100).min(rtts.len() - 1)];
+            (avg as u32, p95)
+        };
+        let last = self.buckets.len().saturating_sub(1);
+        let bitrate_kbps = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(i, bytes)| {
+                let secs = if i == last {
+                    self.bucket_start.elapsed().as_secs_f64().max(1.0)
+                } else {
+                    BUCKET.as_secs_f64()
+                };
+                (*bytes as f64 * 8.0 / 1000.0 / secs) as u32
+            })
+            .collect();
+        let counters = COUNTERS.lock().unwrap().clone();
+        let frame_drops = counters
+            .frame_drops
+            .iter()
+            .map(|(reason, n)| {
+                let base = self.baseline.frame_drops.get(reason).cloned().unwrap_or(0);
+                (reason.to_string(), n.saturating_sub(base))
+            })
+            .filter(|(_, n)| *n > 0)
+            .collect();
+        SessionReport {
+            conn_id,
+            peer_id: peer_id.to_owned(),
+            peer_name: peer_name.to_owned(),
+            transport: if self.relay { "relay" } else { "direct" }.to_owned(),
+            start_time: self.start_time,
+            duration_secs: self.start.elapsed().as_secs(),
+            rtt_avg_ms,
+            rtt_p95_ms,
+            bucket_secs: BUCKET.as_secs(),
+            bitrate_kbps,
+            frame_drops,
+            codec_switches: counters
+                .codec_switches
+                .saturating_sub(self.baseline.codec_switches),
+            audio_restarts: counters
+                .audio_restarts
+                .saturating_sub(self.baseline.audio_restarts),
+            host_priority: super::host_priority::mode().to_owned(),
+            cpu_throttles: counters
+                .cpu_throttles
+                .saturating_sub(self.baseline.cpu_throttles),
+            close_reason: close_reason.to_owned(),
+        }
+    }
+}
+
+pub fn add_report(report: SessionReport) {
+    log::info!("Session report: {:?}", report);
+    let mut reports = REPORTS.lock().unwrap();
+    reports.push_back(report);
+    while reports.len() > MAX_REPORTS {
+        reports.pop_front();
+    }
+}
+
+/// The recent reports in json, the latest one is the last.
+pub fn get_reports() -> Vec<String> {
+    REPORTS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|r| serde_json::to_string(r).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_session_report() {
+        let mut stats = SessionStats::new(true);
+        for rtt in 1..=100 {
+            stats.on_rtt(rtt);
+        }
+        let mut msg = Message::new();
+        msg.set_video_frame(Default::default());
+        stats.on_sent(&msg, 1000);
+        on_frame_dropped("test");
+        let report = stats.report(1, "id", "name", "End");
+        assert_eq!(report.transport, "relay");
+        assert_eq!(report.rtt_avg_ms, 50);
+        assert_eq!(report.rtt_p95_ms, 96);
+        assert_eq!(report.bitrate_kbps.len(), 1);
+        assert_eq!(report.frame_drops.get("test"), Some(&1));
+        assert_eq!(stats.window().snapshot().video_frames_sent, 1);
+    }
+
+    #[test]
+    fn test_reset() {
+        let stats = Arc::new(ConnStats::default());
+        let mut msg = Message::new();
+        msg.set_video_frame(Default::default());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let stats = stats.clone();
+                let msg = msg.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        stats.on_sent(&msg, 10);
+                        stats.on_received(5);
+                    }
+                })
+            })
+            .collect();
+        let old = stats.reset();
+        for t in threads {
+            t.join().unwrap();
+        }
+        let new = stats.snapshot();
+        assert_eq!(old.messages_sent + new.messages_sent, 4000);
+        assert_eq!(old.video_frames_sent + new.video_frames_sent, 4000);
+        assert_eq!(old.bytes_sent + new.bytes_sent, 40000);
+        assert_eq!(old.bytes_received + new.bytes_received, 20000);
+        assert_eq!(new.audio_frames_sent, 0);
+    }
+
+    // The same loop of the capture drops the frames of each connection by the fps it asked for.
+    #[test]
+    fn test_frame_drops_per_connection() {
+        let ms = Duration::from_millis;
+        let interval = ms(100);
+        let (fast, slow) = (ms(1000 / 60), ms(100));
+        assert_eq!(
+            frame_drops(interval, fast, ms(5), ms(0), false),
+            Some((FrameDropReason::Bandwidth, 5))
+        );
+        assert_eq!(frame_drops(interval, slow, ms(5), ms(0), false), None);
+        assert_eq!(
+            frame_drops(interval, fast, ms(40), ms(0), false),
+            Some((FrameDropReason::EncoderOverrun, 5))
+        );
+        assert_eq!(
+            frame_drops(interval, fast, ms(10), ms(20), false),
+            Some((FrameDropReason::QueueFull, 5))
+        );
+        assert_eq!(
+            frame_drops(interval, fast, ms(5), ms(0), true),
+            Some((FrameDropReason::Paused, 5))
+        );
+    }
+
+    #[test]
+    fn test_frame_drops() {
+        let stats = Arc::new(ConnStats::default());
+        on_video_frames_dropped(&[1], FrameDropReason::Bandwidth, 3);
+        assert_eq!(
+            stats
+                .snapshot()
+                .video_frames_dropped(FrameDropReason::Bandwidth),
+            0
+        );
+        set_frame_drop_accounting(1, Some(stats.clone()));
+        assert!(is_frame_drop_accounting());
+        on_video_frames_dropped(&[1, 2], FrameDropReason::Bandwidth, 3);
+        on_video_frames_dropped(&[1], FrameDropReason::QueueFull, 1);
+        on_video_frames_dropped(&[2], FrameDropReason::Paused, 5);
+        let s = stats.snapshot();
+        assert_eq!(s.video_frames_dropped(FrameDropReason::Bandwidth), 3);
+        assert_eq!(s.video_frames_dropped(FrameDropReason::QueueFull), 1);
+        assert_eq!(s.video_frames_dropped(FrameDropReason::Paused), 0);
+        remove_frame_drop_accounting(1);
+        assert!(!is_frame_drop_accounting());
+        on_video_frames_dropped(&[1], FrameDropReason::Bandwidth, 3);
+        assert_eq!(
+            stats
+                .reset()
+                .video_frames_dropped(FrameDropReason::Bandwidth),
+            3
+        );
+    }
+}
@@ -0,0 +1,184 @@
+// The restore of the session environment after a reboot by the peer.
+//
+// When the peer reboots the host with a power action, the environment it set up in the session
+// is saved on disk by its id: the virtual displays and the resolutions changed by its connection.
+// The follow remote cursor/window options are not saved, the login of the peer sets them. When
+// the same peer logs on a remote session after the reboot, within "session-restore-window"
+// minutes since the reboot was requested, 30 by default, 0 to disable, the environment is set up
+// again before the displays are sent, so the first frame is of the restored displays. A record is
+// used once, and is dropped when it expires. The records can be listed and cleared through IPC.
+//
+// Only the convenience settings are saved. The permissions, the elevation grants, the privacy
+// mode and the input block are never restored this way, the peer must turn them on again.
+
+use hbb_common::{config::Config, log, sysinfo::System};
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Mutex};
+
+pub const OPTION_SESSION_RESTORE_WINDOW: &str = "session-restore-window"; // minutes
+const DEFAULT_WINDOW_MINUTES: i64 = 30;
+const FILE_NAME: &str = "session_restore.json";
+// The boot time is computed from the uptime, so it may differ by a second or so between calls.
+const BOOT_TIME_TOLERANCE_SECS: u64 = 5;
+
+lazy_static::lazy_static! {
+    static ref LOCK: Mutex<()> = Default::default();
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub created: i64,   // ms
+    pub boot_time: u64, // secs, the boot of the host when the record was saved
+    // (display name, changed resolution)
+    pub resolutions: Vec<(String, (i32, i32))>,
+    // The indices of the virtual displays.
+    pub virtual_displays: Vec<u32>,
+}
+
+impl Record {
+    pub fn is_empty(&self) -> bool {
+        self.resolutions.is_empty() && self.virtual_displays.is_empty()
+    }
+}
+
+fn window_ms() -> i64 {
+    Config::get_option(OPTION_SESSION_RESTORE_WINDOW)
+        .parse::<i64>()
+        .unwrap_or(DEFAULT_WINDOW_MINUTES)
+        .max(0)
+        * 60_000
+}
+
+fn is_expired(record: &Record, now: i64, window: i64) -> bool {
+    // A record from the future is of a wall clock moved back, it's not trusted either.
+    !(0..window).contains(&(now - record.created))
+}
+
+fn is_rebooted(record: &Record, boot_time: u64) -> bool {
+    boot_time > record.boot_time + BOOT_TIME_TOLERANCE_SECS
+}
+
+fn load() -> HashMap<String, Record> {
+    std::fs::read_to_string(Config::path(FILE_NAME))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn store(records: &HashMap<String, Record>) {
+    let path = Config::path(FILE_NAME);
+    if records.is_empty() {
+        if path.exists() {
+            if let Err(e) = std::fs::remove_file(&path) {
+                log::error!("Failed to remove the session restore records: {}", e);
+            }
+        }
+        return;
+    }
+    let res = serde_json::to_string(records)
+        .map_err(|e| e.to_string())
+        .and_then(|s| std::fs::write(&path, s).map_err(|e| e.to_string()));
+    if let Err(e) = res {
+        log::error!("Failed to save the session restore records: {}", e);
+    }
+}
+
+fn load_valid() -> HashMap<String, Record> {
+    let mut records = load();
+    let (now, window) = (super::session_clock::wall_ms(), window_ms());
+    let len = records.len();
+    records.retain(|id, r| {
+        let expired = is_expired(r, now, window);
+        if expired {
+            log::info!("Session restore record of {} expired", id);
+        }
+        !expired
+    });
+    if records.len() != len {
+        store(&records);
+    }
+    records
+}
+
+/// Saves the environment of the peer before the reboot, `created` and `boot_time` are set here.
+pub fn save(peer_id: &str, mut record: Record) {
+    if window_ms() == 0 || record.is_empty() {
+        return;
+    }
+    record.created = super::session_clock::wall_ms();
+    record.boot_time = System::boot_time();
+    let _lock = LOCK.lock().unwrap();
+    let mut records = load_valid();
+    log::info!(
+        "Save the session restore record of {}: {:?}",
+        peer_id,
+        record
+    );
+    records.insert(peer_id.to_owned(), record);
+    store(&records);
+}
+
+/// Takes the record of the peer if the host has been rebooted since it was saved.
+pub fn take(peer_id: &str) -> Option<Record> {
+    if window_ms() == 0 {
+        return None;
+    }
+    let _lock = LOCK.lock().unwrap();
+    let mut records = load_valid();
+    // Not rebooted yet, e.g. the peer reconnects before the reboot, the record is kept.
+    if !is_rebooted(records.get(peer_id)?, System::boot_time()) {
+        return None;
+    }
+    let record = records.remove(peer_id);
+    store(&records);
+    record
+}
+
+/// (peer id, remaining seconds)
+pub fn list() -> Vec<(String, u64)> {
+    let _lock = LOCK.lock().unwrap();
+    let (now, window) = (super::session_clock::wall_ms(), window_ms());
+    load_valid()
+        .into_iter()
+        .map(|(id, r)| (id, ((r.created + window - now).max(0) / 1000) as u64))
+        .collect()
+}
+
+/// Clears the record of the peer, all the records if `peer_id` is empty.
+pub fn clear(peer_id: &str) {
+    let _lock = LOCK.lock().unwrap();
+    let mut records = load();
+    if peer_id.is_empty() {
+        records.clear();
+    } else {
+        records.remove(peer_id);
+    }
+    log::info!("Clear the session restore record of '{}'", peer_id);
+    store(&records);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expiry_and_reboot() {
+        let window = DEFAULT_WINDOW_MINUTES * 60_000;
+        let record = Record {
+            created: 1_000_000,
+            boot_time: 100,
+            virtual_displays: vec![1],
+            ..Default::default()
+        };
+        assert!(!record.is_empty());
+        assert!(Record::default().is_empty());
+        assert!(!is_expired(&record, record.created, window));
+        assert!(!is_expired(&record, record.created + window - 1, window));
+        assert!(is_expired(&record, record.created + window, window));
+        assert!(is_expired(&record, record.created - 1, window));
+        assert!(is_expired(&record, record.created, 0));
+        assert!(!is_rebooted(&record, 100));
+        assert!(!is_rebooted(&record, 100 + BOOT_TIME_TOLERANCE_SECS));
+        assert!(is_rebooted(&record, 200));
+    }
+}
@@ -0,0 +1,147 @@
+// Per-connection temporary directories.
+//
+// Every directory is named `<prefix><pid>-<conn_id>-<uuid>` under the platform temp dir
+// (or the "session-temp-dir" option), so a sweep at startup can remove the ones
+// left by a crashed process, identified by the dead pid.
+
+use super::*;
+use std::path::{Path, PathBuf};
+
+const DIR_PREFIX: &str = "rustdesk-session-";
+const OPTION_TEMP_DIR: &str = "session-temp-dir";
+const OPTION_SIZE_LIMIT_MB: &str = "session-temp-size-limit-mb";
+const DEFAULT_SIZE_LIMIT_MB: u64 = 1024;
+// Keep some free space for the system.
+const MIN_FREE_SPACE: u64 = 100 * 1024 * 1024;
+
+lazy_static::lazy_static! {
+    static ref DIRS: Mutex<HashMap<i32, PathBuf>> = Default::default();
+}
+
+fn get_base_dir() -> PathBuf {
+    let dir = Config::get_option(OPTION_TEMP_DIR);
+    if dir.is_empty() {
+        std::env::temp_dir()
+    } else {
+        PathBuf::from(dir)
+    }
+}
+
+/// The limit of the temporary files of a connection, in bytes.
+pub fn size_limit() -> u64 {
+    Config::get_option(OPTION_SIZE_LIMIT_MB)
+        .parse::<u64>()
+        .unwrap_or(DEFAULT_SIZE_LIMIT_MB)
+        * 1024
+        * 1024
+}
+
+/// Get the temporary directory of the connection, create it if not exists.
+pub fn get_or_create(conn_id: i32) -> ResultType<PathBuf> {
+    let mut dirs = DIRS.lock().unwrap();
+    if let Some(dir) = dirs.get(&conn_id) {
+        if dir.exists() {
+            return Ok(dir.clone());
+        }
+    }
+    let dir = get_base_dir().join(format!(
+        "{}{}-{}-{}",
+        DIR_PREFIX,
+        std::process::id(),
+        conn_id,
+        uuid::Uuid::new_v4().simple()
+    ));
+    std::fs::create_dir_all(&dir)?;
+    log::info!("#{} session temp dir: {:?}", conn_id, dir);
+    dirs.insert(conn_id, dir.clone());
+    Ok(dir)
+}
+
+/// Fails if `len` more bytes in `dir` exceed the per-session size limit or the free disk space.
+pub fn check_room(dir: &Path, len: u64) -> ResultType<()> {
+    let used = dir_size(dir);
+    let limit = size_limit();
+    if used.saturating_add(len) > limit {
+        bail!(
+            "Session temporary files exceed the limit: {} + {} > {}",
+            used,
+            len,
+            limit
+        );
+    }
+    check_disk_space(dir, len)
+}
+
+/// Remove the temporary directory of the connection.
+pub fn remove(conn_id: i32) {
+    if let Some(dir) = DIRS.lock().unwrap().remove(&conn_id) {
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            if dir.exists() {
+                log::error!("Failed to remove session temp dir {:?}: {}", dir, e);
+            }
+        }
+    }
+}
+
+/// Remove the directories left by the processes which are not running.
+pub fn sweep_orphans() {
+    let Ok(entries) = std::fs::read_dir(get_base_dir()) else {
+        return;
+    };
+    use hbb_common::sysinfo::System;
+    let mut sys = System::new();
+    sys.refresh_processes();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(pid) = name
+            .strip_prefix(DIR_PREFIX)
+            .and_then(|x| x.split('-').next())
+            .and_then(|x| x.parse::<usize>().ok())
+        else {
+            continue;
+        };
+        if pid == std::process::id() as usize || sys.process(pid.into()).is_some() {
+            continue;
+        }
+        log::info!("Remove orphaned session temp dir: {}", name);
+        std::fs::remove_dir_all(entry.path()).ok();
+    }
+}
+
+/// Check if there is enough free space to write `len` bytes to `path`.
+pub fn check_disk_space(path: &Path, len: u64) -> ResultType<()> {
+    use hbb_common::sysinfo::Disks;
+    let disks = Disks::new_with_refreshed_list();
+    // The disk with the longest mount point which contains the path.
+    let Some(disk) = disks
+        .iter()
+        .filter(|d| path.starts_with(d.mount_point()))
+        .max_by_key(|d| d.mount_point().as_os_str().len())
+    else {
+        return Ok(());
+    };
+    let available = disk.available_space();
+    if available < len.saturating_add(MIN_FREE_SPACE) {
+        bail!(
+            "Not enough disk space on {:?}: {} available, {} required",
+            disk.mount_point(),
+            available,
+            len
+        );
+    }
+    Ok(())
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|e| match e.metadata() {
+            Ok(m) if m.is_dir() => dir_size(&e.path()),
+            Ok(m) => m.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
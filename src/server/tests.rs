@@ -0,0 +1,1103 @@
+// Loopback tests of the server.
+//
+// A `Connection` is driven through an in-memory stream by a fake peer, and the services of the
+// `Server` are replaced with stubs, so nothing depends on the desktop, the rendezvous server or
+// the connection manager.
+
+use super::service::{EmptyExtraFieldService, Reset};
+use super::*;
+use crate::common::{create_symmetric_key_msg, decode_id_pk, set_test_keys};
+use hbb_common::{
+    password_security as password,
+    sodiumoxide::crypto::secretbox,
+    tokio::{sync::mpsc, task::JoinHandle, time::Instant},
+};
+use sha2::{Digest, Sha256};
+
+const TIMEOUT_MS: u64 = 3_000;
+
+lazy_static::lazy_static! {
+    // The temporary password is updated when an authorized connection is closed,
+    // so the logins must not run in parallel.
+    static ref LOGIN_LOCK: tokio::sync::Mutex<()> = Default::default();
+}
+
+#[derive(Default)]
+struct StubState;
+
+impl Reset for StubState {
+    fn reset(&mut self) {}
+}
+
+// The stubs of the video and audio services send an empty frame in every interval,
+// the others send nothing. The primary video service is added, as the connection would do.
+fn stub_sources(server: &ServerPtr) {
+    let mut lock = server.write().unwrap();
+    let mut names: HashSet<String> = lock.services.keys().cloned().collect();
+    names.insert(primary_video_service_name());
+    for name in names {
+        if let Some(s) = lock.services.remove(&name) {
+            s.join();
+        }
+        let svc = EmptyExtraFieldService::new(name.clone(), false);
+        if Server::is_video_service_name(&name) {
+            GenericService::repeat::<StubState, _, _>(&svc.clone(), 33, |sp, _| {
+                let mut msg = Message::new();
+                msg.set_video_frame(VideoFrame::new());
+                sp.send_video_frame(msg);
+                Ok(())
+            });
+        } else if name == audio_service::NAME {
+            GenericService::repeat::<StubState, _, _>(&svc.clone(), 33, |sp, _| {
+                let mut msg = Message::new();
+                msg.set_audio_frame(AudioFrame::new());
+                sp.send(msg);
+                Ok(())
+            });
+        }
+        lock.add_service(Box::new(svc.sp));
+    }
+}
+
+fn primary_video_service_name() -> String {
+    video_service::get_service_name(VideoSource::Monitor, *display_service::PRIMARY_DISPLAY_IDX)
+}
+
+fn loopback() -> (Stream, Stream) {
+    let (a, b) = tokio::io::duplex(1024 * 1024);
+    let addr: SocketAddr = "127.0.0.1:21118".parse().unwrap();
+    (
+        Stream::Tcp(tcp::FramedStream::from(a, addr)),
+        Stream::Tcp(tcp::FramedStream::from(b, addr)),
+    )
+}
+
+// An option of the global Config set for a test, the old value is restored when it's dropped,
+// also if the test fails. The tests setting options hold `LOGIN_LOCK`.
+struct TestOption {
+    key: String,
+    old: String,
+}
+
+impl TestOption {
+    fn set(key: &str, value: &str) -> Self {
+        let old = Config::get_option(key);
+        Config::set_option(key.to_owned(), value.to_owned());
+        Self {
+            key: key.to_owned(),
+            old,
+        }
+    }
+}
+
+impl Drop for TestOption {
+    fn drop(&mut self) {
+        Config::set_option(self.key.clone(), std::mem::take(&mut self.old));
+    }
+}
+
+// Sets the global headless mode, the old one is restored on drop, even if the test fails.
+struct TestHeadlessMode(bool);
+
+impl TestHeadlessMode {
+    fn set(enabled: bool) -> Self {
+        let old = headless_mode::is_enabled();
+        headless_mode::set(enabled);
+        Self(old)
+    }
+}
+
+impl Drop for TestHeadlessMode {
+    fn drop(&mut self) {
+        headless_mode::set(self.0);
+    }
+}
+
+// Sets the access policies, the old ones are restored on drop, even if the test fails.
+struct TestAccessPolicy(TestOption);
+
+impl TestAccessPolicy {
+    fn set(json: &str) -> Self {
+        let key = access_policy::OPTION_ACCESS_POLICIES;
+        let old = TestOption {
+            key: key.to_owned(),
+            old: Config::get_option(key),
+        };
+        access_policy::set(json).unwrap();
+        Self(old)
+    }
+}
+
+// Uses the fixed keys in the handshakes, the random ones are restored on drop, even if the test
+// fails.
+struct TestKeys;
+
+impl TestKeys {
+    fn set(sk: box_::SecretKey, key: secretbox::Key) -> Self {
+        set_test_keys(Some((sk, key)));
+        Self
+    }
+}
+
+impl Drop for TestKeys {
+    fn drop(&mut self) {
+        set_test_keys(None);
+    }
+}
+
+// The options a login with a password depends on, set to the defaults so the options of the host
+// running the tests don't change the results.
+fn login_options() -> Vec<TestOption> {
+    [
+        "approve-mode",
+        "verification-method",
+        "whitelist",
+        auth_provider::OPTION_AUTH_PROVIDER,
+        access_policy::OPTION_ACCESS_POLICIES,
+        OPTION_REQUIRE_ENCRYPTION,
+        hbb_common::config::keys::OPTION_ENABLE_FILE_TRANSFER,
+    ]
+    .into_iter()
+    .map(|key| TestOption::set(key, ""))
+    .collect()
+}
+
+struct TestPeer {
+    stream: Stream,
+    handle: JoinHandle<()>,
+    // The challenge which came with the signed id.
+    early_challenge: Option<String>,
+    // The messages received, and how many of them before the login request was sent.
+    received: usize,
+    received_before_login: usize,
+}
+
+impl TestPeer {
+    fn connect(server: &ServerPtr) -> Self {
+        Self::connect_with(server, false)
+    }
+
+    fn connect_with(server: &ServerPtr, secure: bool) -> Self {
+        let (stream, peer) = loopback();
+        let addr = "127.0.0.1:21118".parse().unwrap();
+        let server = server.clone();
+        let handle = tokio::spawn(async move {
+            create_tcp_connection(server, stream, addr, secure)
+                .await
+                .ok();
+        });
+        Self {
+            stream: peer,
+            handle,
+            early_challenge: None,
+            received: 0,
+            received_before_login: 0,
+        }
+    }
+
+    async fn next(&mut self) -> Option<Message> {
+        let bytes = timeout(TIMEOUT_MS, self.stream.next()).await.ok()??.ok()?;
+        self.received += 1;
+        Message::parse_from_bytes(&bytes).ok()
+    }
+
+    // Skip the messages before the expected one.
+    async fn wait_for<T>(&mut self, f: impl Fn(Message) -> Option<T>) -> Option<T> {
+        while let Some(msg) = self.next().await {
+            if let Some(v) = f(msg) {
+                return Some(v);
+            }
+        }
+        None
+    }
+
+    // The secure handshake of the client, the stream is encrypted after it.
+    // The box public key of the server is returned.
+    async fn handshake(&mut self) -> Option<[u8; 32]> {
+        let si = self
+            .wait_for(|msg| match msg.union {
+                Some(message::Union::SignedId(si)) => Some(si),
+                _ => None,
+            })
+            .await?;
+        let sign_pk = sign::PublicKey(Config::get_key_pair().1.try_into().ok()?);
+        let (id, their_pk_b) = decode_id_pk(&si.id, &sign_pk).ok()?;
+        if id != Config::get_id() {
+            return None;
+        }
+        let (asymmetric_value, symmetric_value, key) = create_symmetric_key_msg(their_pk_b);
+        let mut msg = Message::new();
+        msg.set_public_key(PublicKey {
+            asymmetric_value,
+            symmetric_value,
+            ..Default::default()
+        });
+        self.stream.send(&msg).await.ok()?;
+        self.stream.set_key(key);
+        self.early_challenge = Some(si.challenge).filter(|c| !c.is_empty());
+        Some(their_pk_b)
+    }
+
+    async fn login(&mut self, password: &str) -> Option<login_response::Union> {
+        self.login_with(
+            password,
+            login_request::Union::FileTransfer(FileTransfer::new()),
+        )
+        .await
+    }
+
+    async fn login_with(
+        &mut self,
+        password: &str,
+        union: login_request::Union,
+    ) -> Option<login_response::Union> {
+        // With the challenge of the signed id, the peer has the salted password remembered.
+        let hash = match self.early_challenge.take() {
+            Some(challenge) => Hash {
+                salt: Config::get_salt(),
+                challenge,
+                ..Default::default()
+            },
+            None => {
+                self.wait_for(|msg| match msg.union {
+                    Some(message::Union::Hash(hash)) => Some(hash),
+                    _ => None,
+                })
+                .await?
+            }
+        };
+        let mut hasher = Sha256::new();
+        hasher.update(password);
+        hasher.update(&hash.salt);
+        let mut hasher2 = Sha256::new();
+        hasher2.update(&hasher.finalize()[..]);
+        hasher2.update(&hash.challenge);
+        let mut msg = Message::new();
+        msg.set_login_request(LoginRequest {
+            username: "127.0.0.1".to_owned(),
+            password: hasher2.finalize()[..].to_vec().into(),
+            my_id: "test".to_owned(),
+            my_name: "test".to_owned(),
+            version: crate::VERSION.to_owned(),
+            union: Some(union),
+            ..Default::default()
+        });
+        self.stream.send(&msg).await.ok()?;
+        self.received_before_login = self.received;
+        self.wait_for(|msg| match msg.union {
+            Some(message::Union::LoginResponse(res)) => res.union,
+            _ => None,
+        })
+        .await
+    }
+
+    // The login request without a password, the peer waits for the approval then.
+    async fn login_without_password(&mut self) -> bool {
+        let mut msg = Message::new();
+        msg.set_login_request(LoginRequest {
+            username: "127.0.0.1".to_owned(),
+            my_id: "test".to_owned(),
+            my_name: "test".to_owned(),
+            version: crate::VERSION.to_owned(),
+            ..Default::default()
+        });
+        self.stream.send(&msg).await.is_ok()
+    }
+
+    // The connection loop must exit after the peer is gone.
+    async fn close(self) -> bool {
+        drop(self.stream);
+        timeout(TIMEOUT_MS, self.handle).await.is_ok()
+    }
+}
+
+fn new_conn(server: &ServerPtr) -> (ConnInner, mpsc::UnboundedReceiver<(Instant, Arc<Message>)>) {
+    let id = server.write().unwrap().get_new_id();
+    let (tx, rx) = mpsc::unbounded_channel();
+    (ConnInner::new(id, Some(tx.clone()), Some(tx)), rx)
+}
+
+async fn recv_until(
+    rx: &mut mpsc::UnboundedReceiver<(Instant, Arc<Message>)>,
+    f: impl Fn(&Message) -> bool,
+) -> bool {
+    while let Ok(Some((_, msg))) = timeout(TIMEOUT_MS, rx.recv()).await {
+        if f(&msg) {
+            return true;
+        }
+    }
+    false
+}
+
+#[tokio::test]
+async fn test_login_success() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let _options = login_options();
+    let server = new();
+    stub_sources(&server);
+    let mut peer = TestPeer::connect(&server);
+    let res = peer.login(&password::temporary_password()).await;
+    assert!(matches!(res, Some(login_response::Union::PeerInfo(_))));
+    assert!(peer.close().await);
+    assert!(server.read().unwrap().connections.is_empty());
+}
+
+#[tokio::test]
+async fn test_login_failure() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let _options = login_options();
+    let server = new();
+    stub_sources(&server);
+    let mut peer = TestPeer::connect(&server);
+    let wrong = format!("{}-wrong", password::temporary_password());
+    match peer.login(&wrong).await {
+        Some(login_response::Union::Error(err)) => {
+            assert_eq!(err, crate::client::LOGIN_MSG_PASSWORD_WRONG)
+        }
+        res => panic!("unexpected login response: {:?}", res),
+    }
+    assert!(peer.close().await);
+}
+
+#[tokio::test]
+async fn test_login_require_encryption() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let server = new();
+    stub_sources(&server);
+    let _options = login_options();
+    let _option = TestOption::set(OPTION_REQUIRE_ENCRYPTION, "Y");
+    let mut peer = TestPeer::connect(&server);
+    let res = peer.login(&password::temporary_password()).await;
+    match res {
+        Some(login_response::Union::Error(err)) => {
+            assert_eq!(err, crate::client::LOGIN_MSG_ENCRYPTION_REQUIRED)
+        }
+        res => panic!("unexpected login response: {:?}", res),
+    }
+    assert!(peer.close().await);
+}
+
+#[tokio::test]
+async fn test_login_access_policy() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let _options = login_options();
+    let server = new();
+    stub_sources(&server);
+    let _policy = TestAccessPolicy::set(
+        r#"[{"conn_type":"file","approve":"password","password":"file-only"}]"#,
+    );
+    let mut peer = TestPeer::connect(&server);
+    let general = peer.login(&password::temporary_password()).await;
+    assert!(peer.close().await);
+    let mut peer = TestPeer::connect(&server);
+    let rule = peer.login("file-only").await;
+    assert!(peer.close().await);
+    match general {
+        Some(login_response::Union::Error(err)) => {
+            assert_eq!(err, crate::client::LOGIN_MSG_PASSWORD_WRONG)
+        }
+        res => panic!("unexpected login response: {:?}", res),
+    }
+    assert!(matches!(rule, Some(login_response::Union::PeerInfo(_))));
+}
+
+// Nobody clicks accept in the headless mode, the peer is denied at once, the password still works.
+#[tokio::test]
+async fn test_login_headless() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let _options = login_options();
+    let server = new();
+    stub_sources(&server);
+    let _headless = TestHeadlessMode::set(true);
+    let approve_mode = TestOption::set("approve-mode", "click");
+    let mut peer = TestPeer::connect(&server);
+    let click = peer.login(&password::temporary_password()).await;
+    let click_closed = peer.close().await;
+    drop(approve_mode);
+    let mut peer = TestPeer::connect(&server);
+    let res = peer.login(&password::temporary_password()).await;
+    match click {
+        Some(login_response::Union::Error(err)) => {
+            assert_eq!(err, crate::client::LOGIN_MSG_NO_PASSWORD_ACCESS)
+        }
+        res => panic!("unexpected login response: {:?}", res),
+    }
+    assert!(click_closed);
+    assert!(matches!(res, Some(login_response::Union::PeerInfo(_))));
+    assert!(peer.close().await);
+}
+
+#[tokio::test]
+async fn test_login_secure() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let _options = login_options();
+    let server = new();
+    stub_sources(&server);
+    let sk = box_::SecretKey([7u8; box_::SECRETKEYBYTES]);
+    let key = secretbox::Key([9u8; secretbox::KEYBYTES]);
+    let _keys = TestKeys::set(sk.clone(), key);
+    let mut peer = TestPeer::connect_with(&server, true);
+    let their_pk_b = peer.handshake().await;
+    let res = peer.login(&password::temporary_password()).await;
+    assert_eq!(their_pk_b, Some(sk.public_key().0));
+    assert!(matches!(res, Some(login_response::Union::PeerInfo(_))));
+    assert!(peer.close().await);
+}
+
+// The login request goes right after the public key with the challenge of the signed id,
+// instead of waiting for the hash, so the login response comes a round trip sooner. The round
+// trips are counted by the messages received before the login request, not timed.
+#[tokio::test]
+async fn test_login_early_data() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let _options = login_options();
+    let server = new();
+    stub_sources(&server);
+    let sk = box_::SecretKey([7u8; box_::SECRETKEYBYTES]);
+    let key = secretbox::Key([9u8; secretbox::KEYBYTES]);
+    let _keys = TestKeys::set(sk, key);
+    let mut results = vec![];
+    for early in [true, false] {
+        let mut peer = TestPeer::connect_with(&server, true);
+        let handshake = peer.handshake().await.is_some() && peer.early_challenge.is_some();
+        if !early {
+            // Like an old client, or one without a remembered password.
+            peer.early_challenge = None;
+        }
+        let res = peer.login(&password::temporary_password()).await;
+        results.push((handshake, res, peer.received_before_login));
+        assert!(peer.close().await);
+    }
+    for (handshake, res, _) in results.iter() {
+        assert!(handshake);
+        assert!(matches!(res, Some(login_response::Union::PeerInfo(_))));
+    }
+    // Only the signed id is received before the login request, not the hash.
+    assert_eq!(results[0].2, 1);
+    assert_eq!(results[1].2, 2);
+}
+
+#[tokio::test]
+async fn test_handshake_require_encryption() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let server = new();
+    stub_sources(&server);
+    let _option = TestOption::set(OPTION_REQUIRE_ENCRYPTION, "Y");
+    let mut peer = TestPeer::connect_with(&server, true);
+    let si = peer
+        .wait_for(|msg| match msg.union {
+            Some(message::Union::SignedId(si)) => Some(si),
+            _ => None,
+        })
+        .await;
+    // The peer skips the key exchange, like a client without the key of the rendezvous server.
+    peer.stream.send(&Message::new()).await.ok();
+    let res = peer
+        .wait_for(|msg| match msg.union {
+            Some(message::Union::LoginResponse(res)) => res.union,
+            _ => None,
+        })
+        .await;
+    assert!(si.is_some());
+    match res {
+        Some(login_response::Union::Error(err)) => {
+            assert_eq!(err, crate::client::LOGIN_MSG_ENCRYPTION_REQUIRED)
+        }
+        res => panic!("unexpected login response: {:?}", res),
+    }
+    // No session is started.
+    assert!(peer.next().await.is_none());
+}
+
+// Many peers stall before the login, the ones over the limit are refused, the others are dropped
+// after the deadline, and the session already established is not affected.
+#[tokio::test]
+async fn test_pending_auth_limit() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let server = new();
+    stub_sources(&server);
+    let mut established = TestPeer::connect(&server);
+    let res = established.login(&password::temporary_password()).await;
+    assert!(matches!(res, Some(login_response::Union::PeerInfo(_))));
+    let _max = TestOption::set(connection::OPTION_MAX_PENDING_AUTH_CONNS, "50");
+    let _timeout = TestOption::set(connection::OPTION_PENDING_AUTH_TIMEOUT, "1");
+    let mut stalled: Vec<TestPeer> = (0..100).map(|_| TestPeer::connect(&server)).collect();
+    let mut accepted = 0;
+    for peer in stalled.iter_mut() {
+        if peer.next().await.is_some() {
+            accepted += 1;
+        }
+    }
+    let pending = pending_auth_count();
+    let start = Instant::now();
+    while pending_auth_count() > 0 && start.elapsed() < Duration::from_millis(TIMEOUT_MS) {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let pending_after_deadline = pending_auth_count();
+    assert_eq!(accepted, 50);
+    assert_eq!(pending, 50);
+    assert_eq!(pending_after_deadline, 0);
+    for peer in stalled {
+        assert!(peer.close().await);
+    }
+    // The established session still answers the test delay and is not removed.
+    let test_delay = |msg: Message| match msg.union {
+        Some(message::Union::TestDelay(t)) => Some(t),
+        _ => None,
+    };
+    let t = established.wait_for(test_delay).await;
+    assert!(t.is_some());
+    let mut msg = Message::new();
+    msg.set_test_delay(t.unwrap());
+    assert!(established.stream.send(&msg).await.is_ok());
+    assert!(established.wait_for(test_delay).await.is_some());
+    assert_eq!(server.read().unwrap().connections.len(), 1);
+    assert!(established.close().await);
+}
+
+// The TLS handshakes of the direct access port take the pending slots too.
+#[tokio::test]
+async fn test_pending_auth_handshake() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let _max = TestOption::set(connection::OPTION_MAX_PENDING_AUTH_CONNS, "2");
+    let first = PendingAuthID::try_new_handshake("127.0.0.1".to_owned());
+    let second = PendingAuthID::try_new_handshake("127.0.0.1".to_owned());
+    assert!(first.is_some() && second.is_some());
+    assert_eq!(pending_auth_count(), 2);
+    assert!(PendingAuthID::try_new_handshake("127.0.0.1".to_owned()).is_none());
+    drop(first);
+    assert!(PendingAuthID::try_new_handshake("127.0.0.1".to_owned()).is_some());
+    drop(second);
+    assert_eq!(pending_auth_count(), 0);
+}
+
+// The peers which log in without a password wait for the approval as long as it takes, but they
+// don't hold the pending slots after the deadline, so they can't lock the other peers out.
+#[tokio::test]
+async fn test_pending_auth_released() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let server = new();
+    stub_sources(&server);
+    let _max = TestOption::set(connection::OPTION_MAX_PENDING_AUTH_CONNS, "2");
+    let _timeout = TestOption::set(connection::OPTION_PENDING_AUTH_TIMEOUT, "1");
+    let mut waiting = vec![TestPeer::connect(&server), TestPeer::connect(&server)];
+    for peer in waiting.iter_mut() {
+        assert!(peer.next().await.is_some());
+        assert!(peer.login_without_password().await);
+    }
+    let start = Instant::now();
+    while pending_auth_count() > 0 && start.elapsed() < Duration::from_millis(TIMEOUT_MS) {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(pending_auth_count(), 0);
+    assert!(waiting.iter().all(|peer| !peer.handle.is_finished()));
+    let mut peer = TestPeer::connect(&server);
+    let res = peer.login(&password::temporary_password()).await;
+    assert!(matches!(res, Some(login_response::Union::PeerInfo(_))));
+    assert!(peer.close().await);
+    for peer in waiting {
+        assert!(peer.close().await);
+    }
+}
+
+#[tokio::test]
+async fn test_subscribe() {
+    let server = new();
+    stub_sources(&server);
+    let (conn, mut rx) = new_conn(&server);
+    let id = conn.id();
+    let video = primary_video_service_name();
+    server
+        .write()
+        .unwrap()
+        .add_connection(conn.clone(), &vec![audio_service::NAME]);
+    {
+        let lock = server.read().unwrap();
+        assert!(lock.services[&video].is_subed(id));
+        assert!(!lock.services[audio_service::NAME].is_subed(id));
+    }
+    assert!(recv_until(&mut rx, |msg| msg.has_video_frame()).await);
+
+    server
+        .write()
+        .unwrap()
+        .subscribe(audio_service::NAME, conn.clone(), true);
+    assert!(server.read().unwrap().services[audio_service::NAME].is_subed(id));
+    assert!(recv_until(&mut rx, |msg| msg.has_audio_frame()).await);
+
+    server
+        .write()
+        .unwrap()
+        .subscribe(audio_service::NAME, conn.clone(), false);
+    assert!(!server.read().unwrap().services[audio_service::NAME].is_subed(id));
+}
+
+// The re-authentication sends a new challenge, the pending flag is shared with the connection.
+#[tokio::test]
+async fn test_reauth_challenge() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let server = new();
+    stub_sources(&server);
+    let _option = TestOption::set(OPTION_REAUTH_SERVICES, audio_service::NAME);
+    let (conn, mut rx) = new_conn(&server);
+    let id = conn.id();
+    server
+        .write()
+        .unwrap()
+        .subscribe(audio_service::NAME, conn.clone(), true);
+    assert!(!server.read().unwrap().services[audio_service::NAME].is_subed(id));
+    assert!(conn.is_reauth_pending());
+    let challenge = conn.reauth_challenge().unwrap();
+    assert!(
+        recv_until(&mut rx, |msg| match &msg.union {
+            Some(message::Union::Hash(hash)) => hash.challenge == challenge,
+            _ => false,
+        })
+        .await
+    );
+    server.write().unwrap().grant_reauth(id);
+    assert!(!conn.is_reauth_pending());
+    assert!(conn.reauth_challenge().is_none());
+    assert!(server.read().unwrap().services[audio_service::NAME].is_subed(id));
+}
+
+// A legacy subscriber and a new one of the same service, each gets the messages of its version.
+#[tokio::test]
+async fn test_msg_versions() {
+    let server = new();
+    let (legacy, mut rx_legacy) = new_conn(&server);
+    let (mut current, mut rx_current) = new_conn(&server);
+    current.set_msg_versions(crate::common::local_msg_versions());
+    let is_version = |version: u32| {
+        move |msg: &Message| match &msg.union {
+            Some(message::Union::Misc(misc)) => match &misc.union {
+                Some(misc::Union::ServiceMsgVersion(v)) => v.version == version,
+                _ => false,
+            },
+            _ => false,
+        }
+    };
+
+    let audio = GenericService::new("audio-test".to_owned(), true);
+    audio.set_msg_version(
+        crate::common::MSG_KIND_AUDIO,
+        crate::common::AUDIO_MSG_VERSION,
+    );
+    audio.on_subscribe(legacy.clone());
+    audio.on_subscribe(current.clone());
+    audio.snapshot(|_| Ok(())).unwrap();
+    audio_service::send_audio_frame(&audio, vec![1, 2, 3], 100);
+    let audio_frame = |capture_time: i64| {
+        move |msg: &Message| {
+            msg.has_audio_frame()
+                && msg.audio_frame().data.as_ref() == [1, 2, 3]
+                && msg.audio_frame().capture_time == capture_time
+        }
+    };
+    assert!(recv_until(&mut rx_legacy, is_version(0)).await);
+    assert!(recv_until(&mut rx_legacy, audio_frame(0)).await);
+    assert!(recv_until(&mut rx_current, is_version(1)).await);
+    assert!(recv_until(&mut rx_current, audio_frame(100)).await);
+
+    let video = GenericService::new("video-test".to_owned(), true);
+    video.set_msg_version(
+        crate::common::MSG_KIND_VIDEO,
+        crate::common::VIDEO_MSG_VERSION,
+    );
+    video.on_subscribe(legacy.clone());
+    video.on_subscribe(current.clone());
+    video.snapshot(|_| Ok(())).unwrap();
+    let mut msg = Message::new();
+    msg.set_video_frame(VideoFrame {
+        display: 1,
+        capture_time: 200,
+        ..Default::default()
+    });
+    let ids = video.send_versioned(|version| video_service::versioned_video_frame(&msg, version));
+    assert_eq!(ids, HashSet::from([legacy.id(), current.id()]));
+    let video_frame = |display: i32, capture_time: i64| {
+        move |msg: &Message| {
+            msg.has_video_frame()
+                && msg.video_frame().display == display
+                && msg.video_frame().capture_time == capture_time
+        }
+    };
+    assert!(recv_until(&mut rx_legacy, is_version(0)).await);
+    assert!(recv_until(&mut rx_legacy, video_frame(1, 0)).await);
+    assert!(recv_until(&mut rx_current, is_version(1)).await);
+    assert!(recv_until(&mut rx_current, video_frame(1, 200)).await);
+}
+
+// The legacy frame is the frame before the versions, only the new fields are cleared.
+#[test]
+fn test_legacy_video_frame() {
+    let mut msg = Message::new();
+    msg.set_video_frame(VideoFrame {
+        display: 1,
+        capture_time: 200,
+        ..Default::default()
+    });
+    let legacy = video_service::versioned_video_frame(&msg, 0);
+    assert_eq!(legacy.video_frame().display, 1);
+    assert_eq!(legacy.video_frame().capture_time, 0);
+    let mut expected = msg.clone();
+    expected.mut_video_frame().capture_time = 0;
+    assert_eq!(legacy, expected);
+    assert_eq!(video_service::versioned_video_frame(&msg, 1), msg);
+}
+
+#[tokio::test]
+async fn test_video_subscriber_limit() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let server = new();
+    stub_sources(&server);
+    let option = TestOption::set(OPTION_MAX_VIDEO_SUBSCRIBERS, "1");
+    let video = primary_video_service_name();
+    let (first, _rx) = new_conn(&server);
+    let (second, mut rx) = new_conn(&server);
+    {
+        let mut lock = server.write().unwrap();
+        lock.add_connection(first.clone(), &vec![]);
+        lock.add_connection(second.clone(), &vec![]);
+    }
+    drop(option);
+    assert!(server.read().unwrap().services[&video].is_subed(first.id()));
+    assert!(!server.read().unwrap().services[&video].is_subed(second.id()));
+    let told = recv_until(&mut rx, |msg| {
+        msg.message_box().text == "video-capacity-tip"
+    })
+    .await;
+    assert!(told);
+}
+
+#[tokio::test]
+async fn test_remove_connection() {
+    let server = new();
+    stub_sources(&server);
+    let (conn, _rx) = new_conn(&server);
+    let id = conn.id();
+    server
+        .write()
+        .unwrap()
+        .add_connection(conn.clone(), &vec![]);
+    server.write().unwrap().remove_connection(&conn);
+    let lock = server.read().unwrap();
+    assert!(lock.connections.is_empty());
+    assert!(lock.services.values().all(|s| !s.is_subed(id)));
+}
+
+#[tokio::test]
+async fn test_connection_stats() {
+    let server = new();
+    stub_sources(&server);
+    let (conn, _rx) = new_conn(&server);
+    let id = conn.id();
+    server
+        .write()
+        .unwrap()
+        .add_connection(conn.clone(), &vec![]);
+    conn.stats().on_received(100);
+    let lock = server.read().unwrap();
+    let snapshot = lock.snapshot_connection_stats(id).unwrap();
+    assert_eq!(snapshot.bytes_received, 100);
+    assert_eq!(lock.reset_connection_stats(id).unwrap().bytes_received, 100);
+    assert_eq!(
+        lock.snapshot_connection_stats(id).unwrap().bytes_received,
+        0
+    );
+    assert!(lock.snapshot_connection_stats(id + 1).is_none());
+}
+
+#[tokio::test]
+async fn test_close_and_wait() {
+    let server = new();
+    stub_sources(&server);
+    let (conn, mut rx) = new_conn(&server);
+    server
+        .write()
+        .unwrap()
+        .add_connection(conn.clone(), &vec![]);
+    // The connection loop exits on StopService.
+    let server_cloned = server.clone();
+    tokio::spawn(async move {
+        let stop = |msg: &Message| match &msg.union {
+            Some(message::Union::Misc(misc)) => {
+                matches!(misc.union, Some(misc::Union::StopService(_)))
+            }
+            _ => false,
+        };
+        if recv_until(&mut rx, stop).await {
+            server_cloned.write().unwrap().remove_connection(&conn);
+        }
+    });
+    assert!(close_and_wait(&server, CLOSE_REASON_SHUTDOWN).await);
+    let lock = server.read().unwrap();
+    assert!(lock.connections.is_empty());
+    assert_eq!(lock.close_reason(), CLOSE_REASON_SHUTDOWN);
+}
+
+#[tokio::test]
+async fn test_stop_capture_display() {
+    let server = new();
+    stub_sources(&server);
+    let (conn, _rx) = new_conn(&server);
+    let id = conn.id();
+    let idx = *display_service::PRIMARY_DISPLAY_IDX;
+    let video = primary_video_service_name();
+    server
+        .write()
+        .unwrap()
+        .add_connection(conn.clone(), &vec![]);
+    assert!(server.read().unwrap().services[&video].ok());
+    let mut lock = server.write().unwrap();
+    assert!(lock.stop_capture_display(id, VideoSource::Monitor, idx));
+    assert!(!lock.services[&video].is_subed(id));
+    // No one else watches it, the capture stops.
+    assert!(!lock.services[&video].ok());
+    assert!(!lock.stop_capture_display(id, VideoSource::Monitor, idx));
+}
+
+// A fake video service replacing the one of the name, like the service monitor restarting it.
+fn recreate_video_service(server: &ServerPtr, name: &str) {
+    let svc = EmptyExtraFieldService::new(name.to_owned(), false);
+    if let Some(old) = server.write().unwrap().add_service(Box::new(svc.sp)) {
+        old.join();
+    }
+}
+
+#[tokio::test]
+async fn test_service_failure_event() {
+    let server = new();
+    stub_sources(&server);
+    let mut events = service_monitor::subscribe();
+    let name = "test_failure".to_owned();
+    let svc = EmptyExtraFieldService::new(name.clone(), false);
+    GenericService::repeat::<StubState, _, _>(&svc.clone(), 33, |sp, _| {
+        if sp.has_subscribes() {
+            std::thread::sleep(std::time::Duration::from_millis(1500));
+            panic!("stub failure");
+        }
+        Ok(())
+    });
+    server.write().unwrap().add_service(Box::new(svc.sp));
+    let (conn, _rx) = new_conn(&server);
+    server
+        .write()
+        .unwrap()
+        .add_connection(conn.clone(), &vec![]);
+    let event = loop {
+        match timeout(TIMEOUT_MS * 2, events.recv()).await {
+            Ok(Ok(event)) if event.name == name => break Some(event),
+            Ok(Ok(_)) => continue,
+            _ => break None,
+        }
+    };
+    // There is no constructor of the stub, it can't be restarted.
+    assert_eq!(
+        event,
+        Some(service_monitor::ServiceEvent {
+            name: name.clone(),
+            restart_attempted: false,
+            conns: vec![conn.id()],
+        })
+    );
+    assert!(!server.read().unwrap().services[&name].ok());
+}
+
+#[test]
+fn test_handshake_msg_truncated() {
+    let mut msg = Message::new();
+    msg.set_public_key(PublicKey {
+        asymmetric_value: vec![1u8; box_::PUBLICKEYBYTES].into(),
+        symmetric_value: vec![2u8; 48].into(),
+        ..Default::default()
+    });
+    let bytes = msg.write_to_bytes().unwrap();
+    for n in 1..bytes.len() {
+        assert!(is_truncated(&bytes[..n]), "{}", n);
+    }
+    assert!(!is_truncated(&bytes));
+    // A varint of more than 10 bytes.
+    assert!(!is_truncated(&[0xff; 16]));
+    // The field number 0, and the deprecated group wire type.
+    assert!(!is_truncated(&[0x02, 0x01]));
+    assert!(!is_truncated(&[0x0b, 0x01]));
+    // A field longer than a handshake message may be.
+    assert!(!is_truncated(&[0x0a, 0xff, 0xff, 0x03]));
+}
+
+#[test]
+fn test_sort_services() {
+    let video = video_service::get_service_name(VideoSource::Monitor, 0);
+    let v = video.as_str();
+    let mut names: Vec<String> = ["mouse_pos", "audio", "zzz", v, "display", "clipboard"]
+        .iter()
+        .map(|x| x.to_string())
+        .collect();
+    sort_services(&mut names, "");
+    assert_eq!(
+        names,
+        vec!["display", v, "audio", "clipboard", "mouse_pos", "zzz"]
+    );
+    sort_services(&mut names, "mouse_pos, video");
+    assert_eq!(
+        names,
+        vec!["mouse_pos", v, "display", "audio", "clipboard", "zzz"]
+    );
+}
+
+// The services after the display service are subscribed when it has taken the snapshot, without
+// holding the lock of the server in the meantime.
+#[tokio::test]
+async fn test_wait_display_ready() {
+    let server = new();
+    stub_sources(&server);
+    let svc = EmptyExtraFieldService::new(display_service::NAME.to_owned(), true);
+    GenericService::repeat::<StubState, _, _>(&svc.clone(), 100, |sp, _| {
+        sp.snapshot(|_| Ok(()))
+    });
+    if let Some(s) = server
+        .write()
+        .unwrap()
+        .add_service(Box::new(svc.sp.clone()))
+    {
+        s.join();
+    }
+    let video = primary_video_service_name();
+    let (conn, _rx) = new_conn(&server);
+    let pending = server
+        .write()
+        .unwrap()
+        .add_connection(conn.clone(), &vec![]);
+    // Returned before the snapshot, unless the service was quicker, the next services are
+    // not subscribed yet.
+    if let Some(pending) = pending {
+        assert!(!server.read().unwrap().services[&video].is_subed(conn.id()));
+        let task = tokio::spawn(subscribe_pending(
+            Arc::downgrade(&server),
+            conn.clone(),
+            pending,
+        ));
+        timeout(TIMEOUT_MS, task).await.unwrap().unwrap();
+    }
+    assert!(svc.sp.is_ready(conn.id()));
+    assert!(server.read().unwrap().services[&video].is_subed(conn.id()));
+}
+
+#[tokio::test]
+async fn test_feature_allowlist() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let key = feature_allowlist_option(FEATURE_PORT_FORWARD);
+    let _option = TestOption::set(&key, "");
+    assert!(is_feature_allowed(FEATURE_PORT_FORWARD, None));
+    let _option = TestOption::set(&key, "123456789");
+    assert!(is_feature_allowed(FEATURE_PORT_FORWARD, Some("123456789")));
+    assert!(!is_feature_allowed(FEATURE_PORT_FORWARD, Some("987654321")));
+    // The claimed id of an untrusted peer does not count.
+    assert!(!is_feature_allowed(FEATURE_PORT_FORWARD, None));
+    let _option = TestOption::set(&key, ",");
+    assert!(!is_feature_allowed(FEATURE_PORT_FORWARD, Some("123456789")));
+}
+
+// The id a peer claims is not verified by the 2FA yet when the login request is checked, so an
+// allowlisted id does not let it in.
+#[tokio::test]
+async fn test_login_feature_allowlist_2fa() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let _options = login_options();
+    let totp = crate::auth_2fa::TOTPInfo {
+        name: "test".to_owned(),
+        secret: vec![7; 20],
+        digits: 6,
+        ..Default::default()
+    };
+    let _2fa = TestOption::set("2fa", &totp.into_string().unwrap());
+    let _tunnel = TestOption::set("enable-tunnel", "");
+    let _access_mode = TestOption::set("access-mode", "");
+    // The id of `TestPeer`.
+    let _allowlist = TestOption::set(&feature_allowlist_option(FEATURE_PORT_FORWARD), "test");
+    let server = new();
+    stub_sources(&server);
+    let mut peer = TestPeer::connect(&server);
+    let pf = PortForward {
+        host: "localhost".to_owned(),
+        port: 3389,
+        ..Default::default()
+    };
+    let res = peer
+        .login_with(
+            &password::temporary_password(),
+            login_request::Union::PortForward(pf),
+        )
+        .await;
+    match res {
+        Some(login_response::Union::Error(err)) => assert_eq!(
+            err,
+            crate::error_code::ErrorCode::FeatureNotAllowed
+                .with_detail(FEATURE_PORT_FORWARD)
+                .text
+        ),
+        res => panic!("unexpected login response: {:?}", res),
+    }
+    assert!(peer.close().await);
+}
+
+// Switching to another display under the cap of the video services, the old one is released
+// before the new one is counted.
+#[tokio::test]
+async fn test_capture_displays_under_cap() {
+    let _lock = LOGIN_LOCK.lock().await;
+    let server = new();
+    stub_sources(&server);
+    let idx = *display_service::PRIMARY_DISPLAY_IDX;
+    let other_idx = idx + 1;
+    let (video, other) = (
+        primary_video_service_name(),
+        video_service::get_service_name(VideoSource::Monitor, other_idx),
+    );
+    recreate_video_service(&server, &other);
+    let _option = TestOption::set(OPTION_MAX_VIDEO_SERVICES, "1");
+    let (conn, _rx) = new_conn(&server);
+    let id = conn.id();
+    let mut lock = server.write().unwrap();
+    lock.add_connection(conn.clone(), &vec![]);
+    lock.capture_displays(conn.clone(), VideoSource::Monitor, &[other_idx], true, true);
+    assert!(!lock.services[&video].is_subed(id));
+    assert!(lock.services[&other].is_subed(id));
+    // Adding a display keeps the old one, the cap rejects it.
+    lock.capture_displays(conn.clone(), VideoSource::Monitor, &[idx], true, false);
+    assert!(!lock.services[&video].is_subed(id));
+    assert!(lock.services[&other].is_subed(id));
+}
+
+// The peer takes the stream through the new relay, a loopback here, by the uuid and the key of
+// the migration, a stream of someone else without them is refused.
+#[tokio::test]
+async fn test_relay_migration_handshake() {
+    let uuid = "8f4c3a1e-0000-4000-8000-000000000001";
+    let key = secretbox::gen_key();
+    for (peer_uuid, peer_key, accepted) in [
+        (uuid, key.clone(), true),
+        ("another", key.clone(), false),
+        (uuid, secretbox::gen_key(), false),
+    ] {
+        let (mut host, mut peer) = loopback();
+        let peer = tokio::spawn(async move {
+            crate::client::join_relay_migration(&mut peer, peer_uuid, &peer_key.0)
+                .await
+                .is_ok()
+        });
+        let res = connection::accept_relay_migration(&mut host, uuid, key.clone()).await;
+        assert_eq!(res.is_ok(), accepted);
+        if accepted {
+            // Switched, the host replies on the new stream.
+            let mut misc = Misc::new();
+            misc.set_relay_migration(RelayMigration::default());
+            let mut msg_out = Message::new();
+            msg_out.set_misc(misc);
+            host.send(&msg_out).await.unwrap();
+        }
+        drop(host);
+        assert_eq!(peer.await.unwrap(), accepted);
+    }
+}
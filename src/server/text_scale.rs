@@ -0,0 +1,174 @@
+// The text scaling of the host, which the peer may raise for the session, e.g. a technician
+// working with a magnifier needs the text larger than the user keeps it.
+//
+// The original scaling is written to a file before the first change. It is restored when the
+// last remote connection is closed, like the resolution, and at the start of the server if the
+// process died with the scaling changed. The scaling is sent to the peers with the displays,
+// see `display_service`, which polls it. On Linux it's followed with `gsettings monitor` in the
+// session of the user instead of running a command on every poll.
+
+use hbb_common::{bail, config::Config, log, ResultType};
+use std::{
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub const MIN_PERCENT: u32 = 100;
+pub const MAX_PERCENT: u32 = 225;
+// Without the monitor, e.g. not GNOME, it's read again after this.
+#[cfg(not(target_os = "linux"))]
+const CACHE_TTL: Duration = Duration::from_secs(2);
+#[cfg(target_os = "linux")]
+const CACHE_TTL: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+struct State {
+    // The scaling in percent and when it was read.
+    cached: Option<(u32, Instant)>,
+    // The cached scaling is updated by the monitor while it runs.
+    monitoring: bool,
+    #[cfg(target_os = "linux")]
+    monitor_started: Option<Instant>,
+}
+
+impl State {
+    fn cached(&self) -> Option<u32> {
+        match self.cached {
+            Some((percent, t)) if self.monitoring || t.elapsed() < CACHE_TTL => Some(percent),
+            _ => None,
+        }
+    }
+
+    // It's started again after `CACHE_TTL` if it exits, e.g. the user logs out.
+    #[cfg(target_os = "linux")]
+    fn try_start_monitor(&mut self) {
+        if self.monitoring || self.monitor_started.map_or(false, |t| t.elapsed() < CACHE_TTL) {
+            return;
+        }
+        self.monitoring = true;
+        self.monitor_started = Some(Instant::now());
+        std::thread::spawn(|| {
+            let res = crate::platform::monitor_text_scale(|percent| {
+                STATE.lock().unwrap().cached = Some((percent, Instant::now()));
+            });
+            if let Err(e) = res {
+                log::debug!("Failed to monitor text scaling: {}", e);
+            }
+            let mut state = STATE.lock().unwrap();
+            state.monitoring = false;
+            state.cached = None;
+        });
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Default::default();
+}
+
+fn restore_file() -> PathBuf {
+    Config::path("text_scale_restore")
+}
+
+/// The text scaling in percent, 0 if it is unknown.
+pub fn get() -> u32 {
+    let mut state = STATE.lock().unwrap();
+    #[cfg(target_os = "linux")]
+    state.try_start_monitor();
+    if let Some(percent) = state.cached() {
+        return percent;
+    }
+    // The monitor only tells the changes.
+    let percent = platform_get().unwrap_or(0);
+    state.cached = Some((percent, Instant::now()));
+    percent
+}
+
+/// Change the text scaling for the session, 0 restores the original one.
+pub fn set(percent: u32) -> ResultType<()> {
+    if percent == 0 {
+        restore();
+        return Ok(());
+    }
+    if !(MIN_PERCENT..=MAX_PERCENT).contains(&percent) {
+        bail!("Invalid text scaling {}%", percent);
+    }
+    let file = restore_file();
+    if !file.exists() {
+        std::fs::write(&file, platform_get()?.to_string())?;
+    }
+    log::info!("Change text scaling to {}%", percent);
+    let res = platform_set(percent);
+    STATE.lock().unwrap().cached = None;
+    res
+}
+
+/// Restore the original text scaling if it is changed, also after a crash.
+pub fn restore() {
+    let file = restore_file();
+    let Ok(original) = std::fs::read_to_string(&file) else {
+        return;
+    };
+    match original.trim().parse::<u32>() {
+        Ok(percent) => {
+            log::info!("Restore text scaling to {}%", percent);
+            if let Err(e) = platform_set(percent) {
+                // Keep the file, it is tried again at the next start.
+                log::error!("Failed to restore text scaling to {}%: {}", percent, e);
+                return;
+            }
+        }
+        Err(_) => log::error!("Invalid original text scaling: {}", original),
+    }
+    std::fs::remove_file(&file).ok();
+    STATE.lock().unwrap().cached = None;
+}
+
+#[cfg(any(windows, target_os = "linux"))]
+fn platform_get() -> ResultType<u32> {
+    crate::platform::get_text_scale()
+}
+
+#[cfg(any(windows, target_os = "linux"))]
+fn platform_set(percent: u32) -> ResultType<()> {
+    crate::platform::set_text_scale(percent)
+}
+
+#[cfg(target_os = "macos")]
+fn platform_get() -> ResultType<u32> {
+    bail!("Text scaling is not supported")
+}
+
+#[cfg(target_os = "macos")]
+fn platform_set(_percent: u32) -> ResultType<()> {
+    bail!("Text scaling is not supported")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cached() {
+        let mut state = State::default();
+        assert_eq!(state.cached(), None);
+        state.cached = Some((125, Instant::now()));
+        assert_eq!(state.cached(), Some(125));
+        state.cached = Some((125, Instant::now() - CACHE_TTL));
+        assert_eq!(state.cached(), None);
+        // The monitor keeps it up to date.
+        state.monitoring = true;
+        assert_eq!(state.cached(), Some(125));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_text_scale() {
+        use crate::platform::parse_text_scale;
+        assert_eq!(parse_text_scale("1.25\n"), Some(125));
+        assert_eq!(parse_text_scale("text-scaling-factor: 1.5"), Some(150));
+        assert_eq!(parse_text_scale("1.0"), Some(100));
+        assert_eq!(parse_text_scale("No such key"), None);
+        assert_eq!(parse_text_scale("0.0"), None);
+    }
+}
@@ -1,7 +1,7 @@
 use super::*;
 use scrap::codec::{Quality, BR_BALANCED, BR_BEST, BR_SPEED};
 use std::{
-    collections::VecDeque,
+    collections::{HashSet, VecDeque},
     time::{Duration, Instant},
 };
 
@@ -19,6 +19,28 @@ b. 3 seconds timeout => update ratio according to network delay
     When network delay < DELAY_THRESHOLD_150MS, increase ratio, max 150kbps;
     When network delay >= DELAY_THRESHOLD_150MS, decrease ratio;
 
+hidden:
+    The connections whose window is minimized or hidden are left out of the fps, if all of them are
+    hidden, fps is HIDDEN_FPS.
+
+ceiling:
+    A connection may have a hard limit of bitrate and fps, "video-max-bitrate" (kbps) and "video-max-fps"
+    when it is opened. The fps never exceeds the lowest fps limit, and the ratio is capped so that the
+    total bitrate of the displays subscribed by the connection doesn't exceed its bitrate limit, even
+    below the minimum ratio.
+
+battery:
+    The fps never exceeds the battery fps cap while the host is on battery, see `battery_saver`.
+
+cpu:
+    The fps and the ratio are capped while the cpu usage of a host putting its local user first is
+    high, see `host_priority`.
+
+multiple displays:
+    The displays subscribed by one connection share the bandwidth of its largest display, in proportion
+    to their resolutions, so the total doesn't grow with the display count. The encoder of a display
+    is shared by the connections, so the smallest share among them is used.
+
 adjust betwen FPS and ratio:
     When network delay < DELAY_THRESHOLD_150MS, fps is always higher than the minimum fps, and ratio is increasing;
     When network delay >= DELAY_THRESHOLD_150MS, fps is always lower than the minimum fps, and ratio is decreasing;
@@ -32,17 +54,22 @@ pub const FPS: u32 = 30;
 pub const MIN_FPS: u32 = 1;
 pub const MAX_FPS: u32 = 120;
 pub const INIT_FPS: u32 = 15;
+pub const HIDDEN_FPS: u32 = 2;
 
 // Bitrate ratio constants for different quality levels
 const BR_MAX: f32 = 40.0; // 2000 * 2 / 100
 const BR_MIN: f32 = 0.2;
 const BR_MIN_HIGH_RESOLUTION: f32 = 0.1; // For high resolution, BR_MIN is still too high, so we set a lower limit
 const MAX_BR_MULTIPLE: f32 = 1.0;
+const CPU_THROTTLE_FPS: u32 = 15;
+const CPU_THROTTLE_RATIO: f32 = BR_SPEED;
 
 const HISTORY_DELAY_LEN: usize = 2;
 const ADJUST_RATIO_INTERVAL: usize = 3; // Adjust quality ratio every 3 seconds
 const DYNAMIC_SCREEN_THRESHOLD: usize = 2; // Allow increase quality ratio if encode more than 2 times in one second
 const DELAY_THRESHOLD_150MS: u32 = 150; // 150ms is the threshold for good network condition
+const OPTION_MAX_BITRATE: &str = "video-max-bitrate"; // kbps
+const OPTION_MAX_FPS: &str = "video-max-fps";
 
 #[derive(Default, Debug, Clone)]
 struct UserDelay {
@@ -90,15 +117,23 @@ impl UserDelay {
 struct UserData {
     auto_adjust_fps: Option<u32>, // reserve for compatibility
     custom_fps: Option<u32>,
-    quality: Option<(i64, Quality)>, // (time, quality)
+    quality: Option<(i64, Quality)>, // (time of the session clock, quality)
     delay: UserDelay,
     record: bool,
+    displays: HashSet<String>, // Subscribed video services
+    max_bitrate: Option<u32>,  // Hard limit of the total bitrate, kbps
+    max_fps: Option<u32>,      // Hard limit of the fps
+    hidden: bool,              // The window of the peer is minimized or hidden
 }
 
 #[derive(Default, Debug, Clone)]
 struct DisplayData {
     send_counter: usize, // Number of times encode during period
     support_changing_quality: bool,
+    idx: usize,
+    pixels: usize,
+    bitrate: u32,
+    bitrate_ratio: f32, // The ratio when the bitrate is stored
 }
 
 // Main QoS controller structure
@@ -107,10 +142,13 @@ pub struct VideoQoS {
     ratio: f32,
     users: HashMap<i32, UserData>,
     displays: HashMap<String, DisplayData>,
-    bitrate_store: u32,
     adjust_ratio_instant: Instant,
     abr_config: bool,
     new_user_instant: Instant,
+    battery_fps: Option<u32>,
+    cpu_throttled: bool,
+    // The clock of the quality settings, the session clock but in the tests.
+    clock: fn() -> i64,
 }
 
 impl Default for VideoQoS {
@@ -120,10 +158,12 @@ impl Default for VideoQoS {
             ratio: BR_BALANCED,
             users: Default::default(),
             displays: Default::default(),
-            bitrate_store: 0,
             adjust_ratio_instant: Instant::now(),
             abr_config: true,
             new_user_instant: Instant::now(),
+            battery_fps: None,
+            cpu_throttled: false,
+            clock: session_clock::now_ms,
         }
     }
 }
@@ -145,24 +185,121 @@ impl VideoQoS {
         }
     }
 
-    // Store bitrate for later use
-    pub fn store_bitrate(&mut self, bitrate: u32) {
-        self.bitrate_store = bitrate;
+    // The interval of the fps requested by the user and allowed by the limits, and whether the
+    // window of the peer is hidden. The frames of the user are dropped if the capture is slower.
+    pub fn user_requested_spf(&self, id: i32) -> Option<(Duration, bool)> {
+        let u = self.users.get(&id)?;
+        let fps = Self::user_fps(u).min(self.max_fps()).clamp(MIN_FPS, MAX_FPS);
+        Some((Duration::from_secs_f32(1. / (fps as f32)), u.hidden))
+    }
+
+    fn all_hidden(&self) -> bool {
+        !self.users.is_empty() && self.users.values().all(|u| u.hidden)
     }
 
-    // Get stored bitrate
+    // Store bitrate of the display for later use
+    pub fn store_bitrate(&mut self, video_service_name: &str, bitrate: u32) {
+        if let Some(display) = self.displays.get_mut(video_service_name) {
+            display.bitrate = bitrate;
+            display.bitrate_ratio = self.ratio;
+        }
+    }
+
+    // Get total stored bitrate of all displays
     pub fn bitrate(&self) -> u32 {
-        self.bitrate_store
+        self.displays.values().map(|d| d.bitrate).sum()
+    }
+
+    // Get total stored bitrate of the displays subscribed by the user
+    pub fn user_bitrate(&self, id: i32) -> u32 {
+        match self.users.get(&id) {
+            Some(u) if !u.displays.is_empty() => u
+                .displays
+                .iter()
+                .filter_map(|n| self.displays.get(n))
+                .map(|d| d.bitrate)
+                .sum(),
+            _ => self.bitrate(),
+        }
+    }
+
+    // Get stored bitrate of each display subscribed by the user, display index -> bitrate
+    pub fn user_display_bitrates(&self, id: i32) -> HashMap<i32, u32> {
+        let Some(u) = self.users.get(&id) else {
+            return Default::default();
+        };
+        u.displays
+            .iter()
+            .filter_map(|n| self.displays.get(n))
+            .map(|d| (d.idx as i32, d.bitrate))
+            .collect()
     }
 
     // Get current bitrate ratio with bounds checking
     pub fn ratio(&mut self) -> f32 {
-        if self.ratio < BR_MIN_HIGH_RESOLUTION || self.ratio > BR_MAX {
+        if self.ratio < self.min_ratio(1.0) || self.ratio > BR_MAX {
             self.ratio = BR_BALANCED;
+            self.apply_ceiling();
         }
         self.ratio
     }
 
+    // Get bitrate ratio of the display, its share of the bandwidth applied
+    pub fn display_ratio(&mut self, video_service_name: &str) -> f32 {
+        let ratio = self.ratio();
+        let share = self.display_share(video_service_name);
+        if share < 1.0 {
+            (ratio * share).max(self.min_ratio(share))
+        } else {
+            ratio
+        }
+    }
+
+    fn display_share(&self, video_service_name: &str) -> f32 {
+        let Some(display) = self.displays.get(video_service_name) else {
+            return 1.0;
+        };
+        // Changing the share needs to recreate the encoder which doesn't support changing quality.
+        if !display.support_changing_quality || display.pixels == 0 {
+            return 1.0;
+        }
+        self.users
+            .values()
+            .filter(|u| u.displays.contains(video_service_name))
+            .map(|u| {
+                let (total, max) = u
+                    .displays
+                    .iter()
+                    .filter_map(|n| self.displays.get(n))
+                    .map(|d| d.pixels)
+                    .fold((0, 0), |(total, max), p| (total + p, max.max(p)));
+                if total == 0 {
+                    1.0
+                } else {
+                    max as f32 / total as f32
+                }
+            })
+            .fold(1.0, f32::min)
+    }
+
+    // Halve the ratio after an encoded frame exceeded the size limit.
+    // Returns false if the ratio is already the minimum.
+    pub fn on_oversized_frame(&mut self) -> bool {
+        let current = self.ratio();
+        let ratio = (current * 0.5).max(self.min_ratio(1.0));
+        if ratio >= current {
+            return false;
+        }
+        log::info!(
+            "lower ratio due to oversized frame: {} -> {}",
+            current,
+            ratio
+        );
+        self.ratio = ratio;
+        self.adjust_ratio_instant = Instant::now();
+        true
+    }
+
     // Check if any user is in recording mode
     pub fn record(&self) -> bool {
         self.users.iter().any(|u| u.1.record)
@@ -184,7 +321,20 @@ impl VideoQoS {
 impl VideoQoS {
     // Initialize new user session
     pub fn on_connection_open(&mut self, id: i32) {
-        self.users.insert(id, UserData::default());
+        let get_limit = |name: &str| {
+            Config::get_option(name)
+                .parse::<u32>()
+                .ok()
+                .filter(|v| *v > 0)
+        };
+        self.users.insert(
+            id,
+            UserData {
+                max_bitrate: get_limit(OPTION_MAX_BITRATE),
+                max_fps: get_limit(OPTION_MAX_FPS),
+                ..Default::default()
+            },
+        );
         self.abr_config = Config::get_option("enable-abr") != "N";
         self.new_user_instant = Instant::now();
     }
@@ -193,7 +343,10 @@ impl VideoQoS {
     pub fn on_connection_close(&mut self, id: i32) {
         self.users.remove(&id);
         if self.users.is_empty() {
-            *self = Default::default();
+            *self = Self {
+                battery_fps: self.battery_fps,
+                ..Default::default()
+            };
         }
     }
 
@@ -229,11 +382,41 @@ impl VideoQoS {
             }
         };
 
-        let quality = Some((hbb_common::get_time(), convert_quality(image_quality)));
+        let quality = Some(((self.clock)(), convert_quality(image_quality)));
         if let Some(user) = self.users.get_mut(&id) {
             user.quality = quality;
             // update ratio directly
             self.ratio = self.latest_quality().ratio();
+            self.apply_ceiling();
+        }
+    }
+
+    pub fn user_displays(&mut self, id: i32, displays: HashSet<String>) {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.displays = displays;
+        }
+    }
+
+    pub fn user_hidden(&mut self, id: i32, hidden: bool) {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.hidden = hidden;
+            self.adjust_fps();
+        }
+    }
+
+    // None when the host is on AC.
+    pub fn set_battery_fps(&mut self, fps: Option<u32>) {
+        if self.battery_fps != fps {
+            self.battery_fps = fps;
+            self.adjust_fps();
+        }
+    }
+
+    pub fn set_cpu_throttled(&mut self, throttled: bool) {
+        if self.cpu_throttled != throttled {
+            self.cpu_throttled = throttled;
+            self.adjust_fps();
+            self.apply_ceiling();
         }
     }
 
@@ -355,6 +538,19 @@ impl VideoQoS {
         self.displays.remove(video_service_name);
     }
 
+    pub fn set_display_info(
+        &mut self,
+        video_service_name: &str,
+        idx: usize,
+        width: usize,
+        height: usize,
+    ) {
+        if let Some(display) = self.displays.get_mut(video_service_name) {
+            display.idx = idx;
+            display.pixels = width * height;
+        }
+    }
+
     pub fn update_display_data(&mut self, video_service_name: &str, send_counter: usize) {
         if let Some(display) = self.displays.get_mut(video_service_name) {
             display.send_counter += send_counter;
@@ -374,30 +570,82 @@ impl VideoQoS {
             }
         } else {
             self.ratio = self.latest_quality().ratio();
+            self.apply_ceiling();
         }
     }
 
     #[inline]
-    fn highest_fps(&self) -> u32 {
-        let user_fps = |u: &UserData| {
-            let mut fps = u.custom_fps.unwrap_or(FPS);
-            if let Some(auto_adjust_fps) = u.auto_adjust_fps {
-                if fps == 0 || auto_adjust_fps < fps {
-                    fps = auto_adjust_fps;
-                }
+    fn user_fps(u: &UserData) -> u32 {
+        let mut fps = u.custom_fps.unwrap_or(FPS);
+        if let Some(auto_adjust_fps) = u.auto_adjust_fps {
+            if fps == 0 || auto_adjust_fps < fps {
+                fps = auto_adjust_fps;
             }
-            fps
-        };
+        }
+        fps
+    }
+
+    fn max_fps(&self) -> u32 {
+        self.users
+            .values()
+            .filter_map(|u| u.max_fps)
+            .chain(self.battery_fps)
+            .chain(self.cpu_throttled.then_some(CPU_THROTTLE_FPS))
+            .min()
+            .unwrap_or(MAX_FPS)
+    }
 
+    fn highest_fps(&self) -> u32 {
         let fps = self
             .users
             .iter()
-            .map(|(_, u)| user_fps(u))
+            .filter(|(_, u)| !u.hidden)
+            .map(|(_, u)| Self::user_fps(u))
             .filter(|u| *u >= MIN_FPS)
             .min()
             .unwrap_or(FPS);
 
-        fps.clamp(MIN_FPS, MAX_FPS)
+        fps.min(self.max_fps()).clamp(MIN_FPS, MAX_FPS)
+    }
+
+    // The bitrate is proportional to the ratio, so the highest ratio allowed by the bitrate limit of
+    // a connection is the limit divided by the bitrate per ratio of its displays.
+    fn ceiling_ratio(&self) -> Option<f32> {
+        self.users
+            .values()
+            .filter_map(|u| {
+                let max = u.max_bitrate?;
+                let bitrate_per_ratio: f32 = self
+                    .displays
+                    .iter()
+                    .filter(|(n, _)| u.displays.is_empty() || u.displays.contains(*n))
+                    .filter(|(_, d)| d.bitrate_ratio > 0.0)
+                    .map(|(_, d)| d.bitrate as f32 / d.bitrate_ratio)
+                    .sum();
+                if bitrate_per_ratio <= 0.0 {
+                    return None;
+                }
+                Some(max as f32 / bitrate_per_ratio)
+            })
+            .chain(self.cpu_throttled.then_some(CPU_THROTTLE_RATIO))
+            .reduce(f32::min)
+    }
+
+    fn apply_ceiling(&mut self) {
+        if let Some(ceiling) = self.ceiling_ratio() {
+            if self.ratio > ceiling {
+                self.ratio = ceiling;
+            }
+        }
+    }
+
+    // The lowest ratio of a display with `share` of the bandwidth. The bitrate limit is a hard
+    // one, so it's below BR_MIN_HIGH_RESOLUTION if the limit needs it.
+    fn min_ratio(&self, share: f32) -> f32 {
+        match self.ceiling_ratio() {
+            Some(ceiling) => BR_MIN_HIGH_RESOLUTION.min(ceiling * share),
+            None => BR_MIN_HIGH_RESOLUTION,
+        }
     }
 
     // Get latest quality settings from all users
@@ -504,21 +752,31 @@ impl VideoQoS {
         }
 
         self.ratio = v.clamp(min, max);
+        self.apply_ceiling();
         self.adjust_ratio_instant = Instant::now();
     }
 
     // Adjust fps based on network delay and user response time
     fn adjust_fps(&mut self) {
         let highest_fps = self.highest_fps();
-        // Get minimum fps from all users
+        if self.all_hidden() {
+            self.fps = HIDDEN_FPS.min(highest_fps);
+            return;
+        }
+        // Get minimum fps from all visible users
         let mut fps = self
             .users
             .iter()
+            .filter(|u| !u.1.hidden)
             .map(|u| u.1.delay.fps.unwrap_or(INIT_FPS))
             .min()
             .unwrap_or(INIT_FPS);
 
-        if self.users.iter().any(|u| u.1.delay.response_delayed) {
+        if self
+            .users
+            .iter()
+            .any(|u| !u.1.hidden && u.1.delay.response_delayed)
+        {
             if fps > MIN_FPS + 1 {
                 fps = MIN_FPS + 1;
             }
@@ -593,3 +851,159 @@ impl RttCalculator {
         None
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hidden_fps() {
+        let mut qos = VideoQoS::default();
+        qos.on_connection_open(1);
+        qos.on_connection_open(2);
+        qos.user_network_delay(1, 20);
+        qos.user_network_delay(2, 20);
+        let visible_fps = qos.fps();
+        qos.user_hidden(1, true);
+        assert!(qos.fps() >= visible_fps);
+        qos.user_hidden(2, true);
+        assert_eq!(qos.fps(), HIDDEN_FPS);
+        qos.user_hidden(2, false);
+        assert!(qos.fps() >= visible_fps);
+    }
+
+    // The frames a capture loop paced like the video service's produces in `window`, the QoS is
+    // read on every frame as the service does.
+    fn paced_frames(qos: &std::sync::Mutex<VideoQoS>, window: Duration) -> usize {
+        let start = Instant::now();
+        let mut frames = 0;
+        while start.elapsed() < window {
+            let spf = qos.lock().unwrap().spf();
+            let now = Instant::now();
+            // The capture and the encoding.
+            std::hint::black_box((0..10_000u64).sum::<u64>());
+            frames += 1;
+            let elapsed = now.elapsed();
+            if elapsed < spf {
+                std::thread::sleep(spf - elapsed);
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn test_hidden_capture_work() {
+        let qos = std::sync::Mutex::new(VideoQoS::default());
+        for id in [1, 2] {
+            let mut qos = qos.lock().unwrap();
+            qos.on_connection_open(id);
+            qos.user_network_delay(id, 20);
+        }
+        let window = Duration::from_secs(1);
+        let visible = paced_frames(&qos, window);
+        for id in [1, 2] {
+            qos.lock().unwrap().user_hidden(id, true);
+        }
+        let hidden = paced_frames(&qos, window);
+        // The frames captured and encoded drop by more than 70%.
+        assert!(
+            hidden * 10 < visible * 3,
+            "visible: {}, hidden: {}",
+            visible,
+            hidden
+        );
+    }
+
+    #[test]
+    fn test_user_requested_spf() {
+        let mut qos = VideoQoS::default();
+        qos.on_connection_open(1);
+        qos.on_connection_open(2);
+        qos.user_custom_fps(1, 60);
+        qos.user_custom_fps(2, 10);
+        qos.user_hidden(2, true);
+        let (fast, hidden) = qos.user_requested_spf(1).unwrap();
+        assert!(!hidden);
+        let (slow, hidden) = qos.user_requested_spf(2).unwrap();
+        assert!(hidden);
+        assert!(fast * 5 < slow);
+        assert!(qos.user_requested_spf(3).is_none());
+    }
+
+    #[test]
+    fn test_battery_fps() {
+        let mut qos = VideoQoS::default();
+        qos.on_connection_open(1);
+        qos.user_custom_fps(1, 60);
+        qos.user_network_delay(1, 20);
+        qos.set_battery_fps(Some(10));
+        assert!(qos.fps() <= 10);
+        qos.set_battery_fps(None);
+        qos.user_network_delay(1, 20);
+        assert!(qos.fps() > 10);
+    }
+
+    #[test]
+    fn test_cpu_throttle() {
+        let mut qos = VideoQoS::default();
+        qos.on_connection_open(1);
+        qos.user_custom_fps(1, 60);
+        qos.user_image_quality(1, ImageQuality::Best.value());
+        qos.set_cpu_throttled(true);
+        assert!(qos.fps() <= CPU_THROTTLE_FPS);
+        assert!(qos.ratio() <= CPU_THROTTLE_RATIO);
+        qos.set_cpu_throttled(false);
+        assert_eq!(qos.highest_fps(), 60);
+    }
+
+    #[test]
+    fn test_ceiling_below_min_ratio() {
+        let mut qos = VideoQoS::default();
+        qos.on_connection_open(1);
+        qos.users.get_mut(&1).unwrap().max_bitrate = Some(50);
+        let names = ["display0".to_owned(), "display1".to_owned()];
+        for (idx, name) in names.iter().enumerate() {
+            qos.new_display(name.clone());
+            qos.set_display_info(name, idx, 1920, 1080);
+            qos.set_support_changing_quality(name, true);
+        }
+        qos.user_displays(1, names.iter().cloned().collect());
+        // 5000kbps per display at the current ratio, the limit is far below the floor.
+        let ratio = qos.ratio();
+        for name in names.iter() {
+            qos.store_bitrate(name, 5000);
+        }
+        qos.user_image_quality(1, ImageQuality::Best.value());
+        let ceiling = 50.0 * ratio / 10_000.0;
+        assert!(ceiling < BR_MIN_HIGH_RESOLUTION);
+        assert!(qos.ratio() <= ceiling * 1.001);
+        for name in names.iter() {
+            assert!(qos.display_ratio(name) <= ceiling * 0.5 * 1.001);
+        }
+        assert!(!qos.on_oversized_frame());
+    }
+
+    #[test]
+    fn test_latest_quality_clock_jump() {
+        // By the wall clock, as before, the older setting wins after the jump.
+        let clocks: [(fn() -> i64, Quality); 2] = [
+            (session_clock::wall_ms, Quality::Best),
+            (session_clock::now_ms, Quality::Low),
+        ];
+        for (clock, latest) in clocks {
+            let mut qos = VideoQoS {
+                clock,
+                ..Default::default()
+            };
+            qos.on_connection_open(1);
+            qos.on_connection_open(2);
+            qos.user_image_quality(1, ImageQuality::Best.value());
+            session_clock::advance(Duration::from_millis(2));
+            // The wall clock goes an hour back before the later setting.
+            session_clock::jump_wall_clock(-session_clock::HOUR_MS);
+            qos.user_image_quality(2, ImageQuality::Low.value());
+            assert_eq!(qos.latest_quality(), latest);
+            session_clock::jump_wall_clock(session_clock::HOUR_MS);
+        }
+    }
+}
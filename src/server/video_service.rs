@@ -33,10 +33,7 @@ use crate::{
 use hbb_common::{
     anyhow::anyhow,
     config,
-    tokio::sync::{
-        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-        Mutex as TokioMutex,
-    },
+    tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
 };
 #[cfg(feature = "hwcodec")]
 use scrap::hwcodec::{HwRamEncoder, HwRamEncoderConfig};
@@ -49,51 +46,112 @@ use scrap::{
     codec::{Encoder, EncoderCfg},
     record::{Recorder, RecorderContext},
     vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
+    window::Windows,
     CodecFormat, Display, EncodeInput, TraitCapturer, TraitPixelBuffer,
 };
 #[cfg(windows)]
 use std::sync::Once;
 use std::{
     collections::HashSet,
+    hash::{Hash, Hasher},
     io::ErrorKind::WouldBlock,
     ops::{Deref, DerefMut},
     time::{self, Duration, Instant},
 };
 
 pub const OPTION_REFRESH: &'static str = "refresh";
+// Seconds without content changes and input before the capture is considered idle, 0 means disabled.
+const OPTION_IDLE_TIMEOUT: &'static str = "video-idle-timeout";
+// "any": content changes or input wake the capture, "input": only input wakes the capture.
+const OPTION_IDLE_WAKE: &'static str = "video-idle-wake";
+const IDLE_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+const IDLE_CAPTURE_INTERVAL: Duration = Duration::from_millis(100);
+// Max size of an encoded frame in KB, 0 means unlimited.
+const OPTION_MAX_ENCODED_FRAME_SIZE: &'static str = "max-encoded-frame-size";
+// "reencode": drop the oversized frame and encode a key frame at lower quality,
+// "degrade": send the oversized frame and lower the quality of the following frames.
+const OPTION_OVERSIZED_FRAME_POLICY: &'static str = "oversized-frame-policy";
+// The key frame is sent after this many drops even if it is still oversized.
+const MAX_OVERSIZED_FRAME_DROPS: usize = 3;
+// The delay before the capturer is rebuilt after a failure, doubled on each failure.
+const CAPTURE_RECOVERY_MIN_DELAY: Duration = Duration::from_millis(200);
+const CAPTURE_RECOVERY_MAX_DELAY: Duration = Duration::from_secs(5);
+// The display is gone, it is not retried quickly, a display change will switch the service anyway.
+const CAPTURE_RECOVERY_DISPLAY_GONE_DELAY: Duration = Duration::from_secs(30);
+// How often the captured window is checked for a move, a resize or the close.
+const WINDOW_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+// "auto": the MJPEG frames of the cameras are passed through if all the peers decode them,
+// "transcode": they are always decoded and encoded with the negotiated codec.
+const OPTION_CAMERA_FRAME_FORMAT: &'static str = "camera-frame-format";
 
 lazy_static::lazy_static! {
-    static ref FRAME_FETCHED_NOTIFIER: (UnboundedSender<(i32, Option<Instant>)>, Arc<TokioMutex<UnboundedReceiver<(i32, Option<Instant>)>>>) = {
-        let (tx, rx) = unbounded_channel();
-        (tx, Arc::new(TokioMutex::new(rx)))
-    };
+    // One per video service, so the frames fetched of a display are not taken by the others.
+    static ref FRAME_FETCHED_NOTIFIERS: Mutex<HashMap<String, UnboundedSender<(i32, Option<Instant>)>>> = Default::default();
     pub static ref VIDEO_QOS: Arc<Mutex<VideoQoS>> = Default::default();
     pub static ref IS_UAC_RUNNING: Arc<Mutex<bool>> = Default::default();
     pub static ref IS_FOREGROUND_WINDOW_ELEVATED: Arc<Mutex<bool>> = Default::default();
     static ref SCREENSHOTS: Mutex<HashMap<usize, Screenshot>> = Default::default();
+    static ref PREVIEWS: Mutex<HashMap<usize, Vec<std::sync::mpsc::Sender<PreviewFrameData>>>> = Default::default();
+    // Display -> (until when its last frame is kept, the last frame), see `take_preview_frame`.
+    static ref LAST_PREVIEW_FRAMES: Mutex<HashMap<usize, (Instant, Option<PreviewFrameData>)>> = Default::default();
+    // Kept across the encoder recreation.
+    static ref OVERSIZED_FRAME_DROPS: Mutex<HashMap<String, usize>> = Default::default();
+    // Service name -> (failures, the time of the first failure), kept across the capturer recreation.
+    static ref CAPTURE_FAILURES: Mutex<HashMap<String, (u32, Instant)>> = Default::default();
 }
 
+// (width, height, rgba)
+type PreviewFrameData = (usize, usize, Vec<u8>);
+
 struct Screenshot {
     sid: String,
     tx: Sender,
     restore_vram: bool,
 }
 
+/// Notify the video service `name` that the connection fetched its frame, all if `name` is None.
 #[inline]
-pub fn notify_video_frame_fetched(conn_id: i32, frame_tm: Option<Instant>) {
-    FRAME_FETCHED_NOTIFIER.0.send((conn_id, frame_tm)).ok();
+pub fn notify_video_frame_fetched(conn_id: i32, name: Option<&str>, frame_tm: Option<Instant>) {
+    let notifiers = FRAME_FETCHED_NOTIFIERS.lock().unwrap();
+    match name {
+        Some(name) => {
+            if let Some(tx) = notifiers.get(name) {
+                tx.send((conn_id, frame_tm)).ok();
+            }
+        }
+        None => {
+            for tx in notifiers.values() {
+                tx.send((conn_id, frame_tm)).ok();
+            }
+        }
+    }
 }
 
 struct VideoFrameController {
+    name: String,
     cur: Instant,
     send_conn_ids: HashSet<i32>,
+    rx: UnboundedReceiver<(i32, Option<Instant>)>,
+}
+
+impl Drop for VideoFrameController {
+    fn drop(&mut self) {
+        FRAME_FETCHED_NOTIFIERS.lock().unwrap().remove(&self.name);
+    }
 }
 
 impl VideoFrameController {
-    fn new() -> Self {
+    fn new(name: String) -> Self {
+        let (tx, rx) = unbounded_channel();
+        FRAME_FETCHED_NOTIFIERS
+            .lock()
+            .unwrap()
+            .insert(name.clone(), tx);
         Self {
+            name,
             cur: Instant::now(),
             send_conn_ids: HashSet::new(),
+            rx,
         }
     }
 
@@ -115,8 +173,7 @@ impl VideoFrameController {
         }
 
         let timeout_dur = Duration::from_millis(timeout_millis as u64);
-        match tokio::time::timeout(timeout_dur, FRAME_FETCHED_NOTIFIER.1.lock().await.recv()).await
-        {
+        match tokio::time::timeout(timeout_dur, self.rx.recv()).await {
             Err(_) => {
                 // break if timeout
                 // log::error!("blocking wait frame receiving timeout {}", timeout_millis);
@@ -134,10 +191,174 @@ impl VideoFrameController {
     }
 }
 
+// Suspend encoding if the display is idle, no content changes and no input.
+struct IdleDetector {
+    timeout: Option<Duration>,
+    wake_on_content: bool,
+    last_active: Instant,
+    last_input_time: i64,
+    last_hash: Option<u64>,
+    last_keepalive: Instant,
+    idle: bool,
+}
+
+impl IdleDetector {
+    fn new(name: &str) -> Self {
+        let timeout = Config::get_option(OPTION_IDLE_TIMEOUT)
+            .parse::<u64>()
+            .unwrap_or(0);
+        let wake_on_content = Config::get_option(OPTION_IDLE_WAKE) != "input";
+        if timeout > 0 {
+            log::info!(
+                "{} idle detection, timeout: {}s, wake on content: {}",
+                name,
+                timeout,
+                wake_on_content
+            );
+        }
+        Self {
+            timeout: if timeout > 0 {
+                Some(Duration::from_secs(timeout))
+            } else {
+                None
+            },
+            wake_on_content,
+            last_active: Instant::now(),
+            last_input_time: Self::get_input_time(),
+            last_hash: None,
+            last_keepalive: Instant::now(),
+            idle: false,
+        }
+    }
+
+    #[inline]
+    fn enabled(&self) -> bool {
+        self.timeout.is_some()
+    }
+
+    fn get_input_time() -> i64 {
+        let t = CLICK_TIME.load(std::sync::atomic::Ordering::SeqCst);
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        let t = t.max(MOUSE_MOVE_TIME.load(std::sync::atomic::Ordering::SeqCst));
+        t
+    }
+
+    // Sampling is enough to find content changes, and it is much cheaper than hashing the whole frame.
+    fn is_content_changed(&mut self, data: &[u8]) -> bool {
+        if data.is_empty() {
+            // The texture cannot be read, it is only captured when the content is changed.
+            return true;
+        }
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        data.len().hash(&mut hasher);
+        let step = (data.len() / 4096).max(1);
+        for b in data.iter().step_by(step) {
+            b.hash(&mut hasher);
+        }
+        let hash = hasher.finish();
+        let changed = self.last_hash != Some(hash);
+        self.last_hash = Some(hash);
+        changed
+    }
+
+    // Returns true if the display is idle.
+    fn update(&mut self, content_changed: bool, name: &str) -> bool {
+        let Some(timeout) = self.timeout else {
+            return false;
+        };
+        let input_time = Self::get_input_time();
+        let input_changed = input_time != self.last_input_time;
+        self.last_input_time = input_time;
+        if input_changed || (content_changed && self.wake_on_content) {
+            self.last_active = Instant::now();
+            if self.idle {
+                self.idle = false;
+                log::info!("{} wakes up from idle", name);
+            }
+        } else if !self.idle && self.last_active.elapsed() > timeout {
+            self.idle = true;
+            self.last_keepalive = Instant::now();
+            log::info!("{} is idle, suspend encoding", name);
+        }
+        self.idle
+    }
+
+    fn need_keepalive(&mut self) -> bool {
+        if self.idle && self.last_keepalive.elapsed() >= IDLE_KEEPALIVE_INTERVAL {
+            self.last_keepalive = Instant::now();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Avoid bursts of huge frames, e.g. complex key frames on relay connections with strict rate limits.
+struct FrameSizeLimit {
+    max: usize,
+    reencode: bool,
+}
+
+impl FrameSizeLimit {
+    fn new(name: &str) -> Self {
+        let max = Config::get_option(OPTION_MAX_ENCODED_FRAME_SIZE)
+            .parse::<usize>()
+            .unwrap_or(0)
+            * 1024;
+        let reencode = Config::get_option(OPTION_OVERSIZED_FRAME_POLICY) != "degrade";
+        if max > 0 {
+            log::info!(
+                "{} max encoded frame size: {}, reencode: {}",
+                name,
+                max,
+                reencode
+            );
+        }
+        Self { max, reencode }
+    }
+
+    // Returns an error "SWITCH" to drop the frame and recreate the encoder,
+    // so that the next frame is a key frame with the lowered quality.
+    fn check(&self, sp: &GenericService, msg: &Message) -> ResultType<()> {
+        if self.max == 0 {
+            return Ok(());
+        }
+        let name = &sp.name();
+        let size = hbb_common::protobuf::Message::compute_size(msg) as usize;
+        let mut drops = OVERSIZED_FRAME_DROPS.lock().unwrap();
+        if size <= self.max {
+            drops.remove(name);
+            return Ok(());
+        }
+        let lowered = VIDEO_QOS.lock().unwrap().on_oversized_frame();
+        let n = drops.entry(name.to_owned()).or_default();
+        if self.reencode && lowered && *n < MAX_OVERSIZED_FRAME_DROPS {
+            *n += 1;
+            super::session_report::on_frame_dropped("oversized");
+            session_report::on_video_frames_dropped(
+                &sp.subscriber_ids(),
+                session_report::FrameDropReason::EncoderOverrun,
+                1,
+            );
+            log::info!(
+                "switch due to oversized frame, {} > {}, drops: {}",
+                size,
+                self.max,
+                n
+            );
+            bail!("SWITCH");
+        }
+        log::debug!("send oversized frame, {} > {}", size, self.max);
+        Ok(())
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum VideoSource {
     Monitor,
     Camera,
+    // A single window, the index is the id of the window.
+    Window,
 }
 
 impl VideoSource {
@@ -145,6 +366,7 @@ impl VideoSource {
         match self {
             VideoSource::Monitor => "monitor",
             VideoSource::Camera => "camera",
+            VideoSource::Window => "window",
         }
     }
 
@@ -155,6 +377,10 @@ impl VideoSource {
     pub fn is_camera(&self) -> bool {
         matches!(self, VideoSource::Camera)
     }
+
+    pub fn is_window(&self) -> bool {
+        matches!(self, VideoSource::Window)
+    }
 }
 
 #[derive(Clone)]
@@ -188,6 +414,10 @@ pub fn new(source: VideoSource, idx: usize) -> GenericService {
         idx,
         source,
     };
+    vs.sp.set_msg_version(
+        crate::common::MSG_KIND_VIDEO,
+        crate::common::VIDEO_MSG_VERSION,
+    );
     GenericService::run(&vs, run);
     vs.sp
 }
@@ -452,6 +682,34 @@ fn get_capturer_camera(current: usize) -> ResultType<CapturerInfo> {
         capturer,
     });
 }
+
+fn get_capturer_window(id: usize) -> ResultType<CapturerInfo> {
+    let Some(window) = Windows::get(id as _) else {
+        bail!(scrap::window::WINDOW_CLOSED);
+    };
+    let capturer = Windows::get_capturer(id as _)?;
+    let privacy_mode_id = get_privacy_mode_conn_id().unwrap_or(INVALID_PRIVACY_MODE_CONN_ID);
+    log::debug!(
+        "window={}, origin: ({}, {}), width={}, height={}, title:{}",
+        id,
+        window.x,
+        window.y,
+        window.width,
+        window.height,
+        window.title,
+    );
+    Ok(CapturerInfo {
+        origin: (window.x, window.y),
+        width: window.width,
+        height: window.height,
+        ndisplay: 1,
+        current: id,
+        privacy_mode_id,
+        _capturer_privacy_mode_id: privacy_mode_id,
+        capturer,
+    })
+}
+
 fn get_capturer(
     source: VideoSource,
     current: usize,
@@ -460,11 +718,42 @@ fn get_capturer(
     match source {
         VideoSource::Monitor => get_capturer_monitor(current, portable_service_running),
         VideoSource::Camera => get_capturer_camera(current),
+        VideoSource::Window => get_capturer_window(current),
     }
 }
 
+fn window_display_info(id: usize) -> Option<DisplayInfo> {
+    let window = Windows::get(id as _)?;
+    Some(DisplayInfo {
+        x: window.x,
+        y: window.y,
+        width: window.width as _,
+        height: window.height as _,
+        name: window.title,
+        online: true,
+        ..Default::default()
+    })
+}
+
+// The window is closed, tell the subscribers and drop them, the source is ended.
+// The server removes the service when the next window is captured or a connection is closed.
+fn end_window_source(sp: &GenericService, id: usize) {
+    log::info!("Window {} is closed, end the capture", id);
+    let mut msg_out = Message::new();
+    msg_out.set_message_box(MessageBox {
+        msgtype: "custom-nook-nocancel-hasclose".to_owned(),
+        title: "Window capture".to_owned(),
+        text: "window-capture-closed-tip".to_owned(),
+        link: "".to_owned(),
+        ..Default::default()
+    });
+    sp.send(msg_out);
+    sp.unsubscribe_all();
+}
+
 fn run(vs: VideoService) -> ResultType<()> {
     let mut _raii = Raii::new(vs.sp.name());
+    super::host_priority::apply_to_current_thread();
     // Wayland only support one video capturer for now. It is ok to call ensure_inited() here.
     //
     // ensure_inited() is needed because clear() may be called.
@@ -487,15 +776,42 @@ fn run(vs: VideoService) -> ResultType<()> {
 
     let display_idx = vs.idx;
     let sp = vs.sp;
-    let mut c = get_capturer(vs.source, display_idx, last_portable_service_running)?;
+    // Before the capturer is created, the camera can't be opened twice.
+    if vs.source.is_camera() && use_camera_passthrough(&sp, display_idx) {
+        return run_camera_passthrough(&sp, display_idx);
+    }
+    let mut c = match get_capturer(vs.source, display_idx, last_portable_service_running) {
+        Ok(c) => c,
+        Err(e) => {
+            if vs.source.is_window() && Windows::get(display_idx as _).is_none() {
+                end_window_source(&sp, display_idx);
+                return Ok(());
+            }
+            on_capture_failed(&sp, vs.source, display_idx, &e);
+            return Err(e);
+        }
+    };
+    // The peer shows the window as the display 0, the capturer is recreated on the resize.
+    let frame_display = if vs.source.is_window() {
+        if let Some(msg_out) =
+            make_display_changed_msg(0, window_display_info(display_idx), vs.source)
+        {
+            sp.send(msg_out);
+        }
+        0
+    } else {
+        display_idx
+    };
+    let mut recovering = CAPTURE_FAILURES.lock().unwrap().contains_key(&sp.name());
     #[cfg(windows)]
     if !scrap::codec::enable_directx_capture() && !c.is_gdi() {
         log::info!("disable dxgi with option, fall back to gdi");
         c.set_gdi();
     }
     let mut video_qos = VIDEO_QOS.lock().unwrap();
+    video_qos.set_display_info(&sp.name(), display_idx, c.width, c.height);
     let mut spf = video_qos.spf();
-    let mut quality = video_qos.ratio();
+    let mut quality = video_qos.display_ratio(&sp.name());
     let record_incoming = config::option2bool(
         "allow-auto-record-incoming",
         &Config::get_option("allow-auto-record-incoming"),
@@ -543,7 +859,10 @@ fn run(vs: VideoService) -> ResultType<()> {
             bail!(e);
         }
     }
-    VIDEO_QOS.lock().unwrap().store_bitrate(encoder.bitrate());
+    VIDEO_QOS
+        .lock()
+        .unwrap()
+        .store_bitrate(&sp.name(), encoder.bitrate());
     VIDEO_QOS
         .lock()
         .unwrap()
@@ -554,10 +873,11 @@ fn run(vs: VideoService) -> ResultType<()> {
         sp.set_option_bool(OPTION_REFRESH, false);
     }
 
-    let mut frame_controller = VideoFrameController::new();
+    let mut frame_controller = VideoFrameController::new(sp.name());
 
     let start = time::Instant::now();
     let mut last_check_displays = time::Instant::now();
+    let mut last_check_window = time::Instant::now();
     #[cfg(windows)]
     let mut try_gdi = 1;
     #[cfg(windows)]
@@ -573,11 +893,23 @@ fn run(vs: VideoService) -> ResultType<()> {
     let repeat_encode_max = 10;
     let mut encode_fail_counter = 0;
     let mut first_frame = true;
+    // The repeated frames have the capture time of the last captured one.
+    let mut capture_time = 0;
     let capture_width = c.width;
     let capture_height = c.height;
     let (mut second_instant, mut send_counter) = (Instant::now(), 0);
+    let capture_sync = vs.source.is_monitor() && display_service::is_capture_sync_enabled();
+    let rotation = if vs.source.is_monitor() {
+        display_service::get_display_rotation(display_idx)
+    } else {
+        Default::default()
+    };
+    let mut idle_detector = IdleDetector::new(&sp.name());
+    let frame_size_limit = FrameSizeLimit::new(&sp.name());
+    let mut idle = false;
 
     while sp.ok() {
+        let iteration_begin = Instant::now();
         #[cfg(windows)]
         check_uac_switch(c.privacy_mode_id, c._capturer_privacy_mode_id)?;
         check_qos(
@@ -602,6 +934,12 @@ fn run(vs: VideoService) -> ResultType<()> {
                 codec_format,
                 Encoder::negotiated_codec()
             );
+            super::session_report::on_codec_switch();
+            bail!("SWITCH");
+        }
+        // Only this camera service is restarted, the monitors of the connections are not touched.
+        if vs.source.is_camera() && use_camera_passthrough(&sp, display_idx) {
+            log::info!("switch camera {} to passthrough", display_idx);
             bail!("SWITCH");
         }
         #[cfg(windows)]
@@ -613,6 +951,11 @@ fn run(vs: VideoService) -> ResultType<()> {
             log::info!("switch due to i444 changed");
             bail!("SWITCH");
         }
+        // The timestamps of the two clocks are different, the encoder must be recreated.
+        if vs.source.is_monitor() && display_service::is_capture_sync_enabled() != capture_sync {
+            log::info!("switch due to capture sync changed");
+            bail!("SWITCH");
+        }
         #[cfg(all(windows, feature = "vram"))]
         if c.is_gdi() && encoder.input_texture() {
             log::info!("changed to gdi when using vram");
@@ -630,22 +973,61 @@ fn run(vs: VideoService) -> ResultType<()> {
                 bail!("Desktop changed");
             }
         }
+        let sync_tick = if capture_sync {
+            Some(display_service::capture_sync_wait(display_idx, spf))
+        } else {
+            None
+        };
         let now = time::Instant::now();
         if vs.source.is_monitor() && last_check_displays.elapsed().as_millis() > 1000 {
             last_check_displays = now;
             // This check may be redundant, but it is better to be safe.
             // The previous check in `sp.is_option_true(OPTION_REFRESH)` block may be enough.
             try_broadcast_display_changed(&sp, display_idx, &c, false)?;
+            // The capturer and the encoder are recreated, the peer will get the new orientation and a key frame.
+            if display_service::get_display_rotation(display_idx) != rotation {
+                log::info!("switch due to display rotation changed");
+                let _ = try_broadcast_display_changed(&sp, display_idx, &c, true);
+                bail!("SWITCH");
+            }
+        }
+
+        if vs.source.is_window() && last_check_window.elapsed() >= WINDOW_CHECK_INTERVAL {
+            last_check_window = now;
+            match Windows::get(display_idx as _) {
+                None => {
+                    end_window_source(&sp, display_idx);
+                    return Ok(());
+                }
+                Some(w) if (w.width, w.height) != (c.width, c.height) => {
+                    log::info!("switch due to window {} resized", display_idx);
+                    bail!("SWITCH");
+                }
+                Some(w) if (w.x, w.y) != c.origin => {
+                    // The peer maps the input with the origin.
+                    c.origin = (w.x, w.y);
+                    if let Some(msg_out) =
+                        make_display_changed_msg(0, window_display_info(display_idx), vs.source)
+                    {
+                        sp.send(msg_out);
+                    }
+                }
+                _ => {}
+            }
         }
 
         frame_controller.reset();
 
         let time = now - start;
-        let ms = (time.as_secs() * 1000 + time.subsec_millis() as u64) as i64;
+        let ms = sync_tick.unwrap_or((time.as_secs() * 1000 + time.subsec_millis() as u64) as i64);
         let res = match c.frame(spf) {
             Ok(frame) => {
                 repeat_encode_counter = 0;
                 if frame.valid() {
+                    capture_time = super::media_capture_time();
+                    if let Some(tick) = sync_tick {
+                        display_service::capture_sync_on_captured(display_idx, tick, now.elapsed());
+                    }
                     let screenshot = SCREENSHOTS.lock().unwrap().remove(&display_idx);
                     if let Some(mut screenshot) = screenshot {
                         let restore_vram = screenshot.restore_vram;
@@ -688,22 +1070,36 @@ fn run(vs: VideoService) -> ResultType<()> {
                             bail!("SWITCH");
                         }
                     }
+                    on_preview_frame(display_idx, &frame);
 
                     let frame = frame.to(encoder.yuvfmt(), &mut yuv, &mut mid_data)?;
-                    let send_conn_ids = handle_one_frame(
-                        display_idx,
-                        &sp,
-                        frame,
-                        ms,
-                        &mut encoder,
-                        recorder.clone(),
-                        &mut encode_fail_counter,
-                        &mut first_frame,
-                        capture_width,
-                        capture_height,
-                    )?;
-                    frame_controller.set_send(now, send_conn_ids);
-                    send_counter += 1;
+                    if idle_detector.enabled() && !first_frame {
+                        let changed = match &frame {
+                            EncodeInput::YUV(data) => idle_detector.is_content_changed(data),
+                            _ => true,
+                        };
+                        idle = idle_detector.update(changed, &sp.name());
+                    }
+                    // When idle, encoding is suspended except the keepalive frames.
+                    // The captured frames are only used to detect content changes.
+                    if !idle || idle_detector.need_keepalive() {
+                        let send_conn_ids = handle_one_frame(
+                            frame_display,
+                            &sp,
+                            frame,
+                            ms,
+                            capture_time,
+                            &mut encoder,
+                            recorder.clone(),
+                            &mut encode_fail_counter,
+                            &mut first_frame,
+                            capture_width,
+                            capture_height,
+                            &frame_size_limit,
+                        )?;
+                        frame_controller.set_send(now, send_conn_ids);
+                        send_counter += 1;
+                    }
                 }
                 #[cfg(windows)]
                 {
@@ -745,21 +1141,45 @@ fn run(vs: VideoService) -> ResultType<()> {
                         }
                     }
                 }
-                if !encoder.latency_free() && yuv.len() > 0 {
+                if idle_detector.enabled() {
+                    idle = idle_detector.update(false, &sp.name());
+                }
+                if idle {
+                    // The last frame is repeated as keepalive, the encoder is almost free for it.
+                    if yuv.len() > 0 && idle_detector.need_keepalive() {
+                        let send_conn_ids = handle_one_frame(
+                            frame_display,
+                            &sp,
+                            EncodeInput::YUV(&yuv),
+                            ms,
+                            capture_time,
+                            &mut encoder,
+                            recorder.clone(),
+                            &mut encode_fail_counter,
+                            &mut first_frame,
+                            capture_width,
+                            capture_height,
+                            &frame_size_limit,
+                        )?;
+                        frame_controller.set_send(now, send_conn_ids);
+                    }
+                } else if !encoder.latency_free() && yuv.len() > 0 {
                     // yun.len() > 0 means the frame is not texture.
                     if repeat_encode_counter < repeat_encode_max {
                         repeat_encode_counter += 1;
                         let send_conn_ids = handle_one_frame(
-                            display_idx,
+                            frame_display,
                             &sp,
                             EncodeInput::YUV(&yuv),
                             ms,
+                            capture_time,
                             &mut encoder,
                             recorder.clone(),
                             &mut encode_fail_counter,
                             &mut first_frame,
                             capture_width,
                             capture_height,
+                            &frame_size_limit,
                         )?;
                         frame_controller.set_send(now, send_conn_ids);
                         send_counter += 1;
@@ -767,6 +1187,10 @@ fn run(vs: VideoService) -> ResultType<()> {
                 }
             }
             Err(err) => {
+                if vs.source.is_window() && err.kind() == std::io::ErrorKind::NotFound {
+                    end_window_source(&sp, display_idx);
+                    return Ok(());
+                }
                 // This check may be redundant, but it is better to be safe.
                 // The previous check in `sp.is_option_true(OPTION_REFRESH)` block may be enough.
                 if vs.source.is_monitor() {
@@ -774,11 +1198,14 @@ fn run(vs: VideoService) -> ResultType<()> {
                 }
 
                 #[cfg(windows)]
-                if !c.is_gdi() {
+                if !vs.source.is_window() && !c.is_gdi() {
                     c.set_gdi();
                     log::info!("dxgi error, fall back to gdi: {:?}", err);
                     continue;
                 }
+                // The capturer and the encoder are recreated by the service loop,
+                // the subscribers get a key frame on recovery.
+                on_capture_failed(&sp, vs.source, display_idx, &err);
                 return Err(err.into());
             }
             _ => {
@@ -786,6 +1213,10 @@ fn run(vs: VideoService) -> ResultType<()> {
                 {
                     would_block_count = 0;
                 }
+                if recovering {
+                    recovering = false;
+                    on_capture_recovered(&sp);
+                }
             }
         }
 
@@ -802,18 +1233,129 @@ fn run(vs: VideoService) -> ResultType<()> {
                 break;
             }
         }
+        let wait_elapsed = wait_begin.elapsed();
 
         let elapsed = now.elapsed();
         // may need to enable frame(timeout)
         log::trace!("{:?} {:?}", time::Instant::now(), elapsed);
-        if elapsed < spf {
+        // `capture_sync_wait()` already waits for the next tick.
+        if !capture_sync && elapsed < spf {
             std::thread::sleep(spf - elapsed);
         }
+        // Capture less frequently to save power, input or content changes can still be detected in time.
+        let spent = if capture_sync {
+            elapsed
+        } else {
+            elapsed.max(spf)
+        };
+        if idle && spent < IDLE_CAPTURE_INTERVAL {
+            std::thread::sleep(IDLE_CAPTURE_INTERVAL - spent);
+        }
+        if session_report::is_frame_drop_accounting() {
+            account_frame_drops(
+                &sp,
+                iteration_begin.elapsed(),
+                wait_begin.duration_since(now),
+                wait_elapsed,
+                idle,
+            );
+        }
     }
 
     Ok(())
 }
 
+// The camera outputs MJPEG, the option allows it, and all the peers of the camera decode it. The
+// recordings need the encoded frames.
+fn use_camera_passthrough(sp: &GenericService, display_idx: usize) -> bool {
+    Config::get_option(OPTION_CAMERA_FRAME_FORMAT) != "transcode"
+        && camera::Cameras::is_mjpeg(display_idx)
+        && !VIDEO_QOS.lock().unwrap().record()
+        && !config::option2bool(
+            "allow-auto-record-incoming",
+            &Config::get_option("allow-auto-record-incoming"),
+        )
+        && Encoder::support_mjpeg(&sp.subscriber_ids())
+}
+
+// The frames of the camera are sent as they are, every one is a key frame. The fps is still
+// limited by the qos, the resolution is the one of the camera.
+fn run_camera_passthrough(sp: &GenericService, display_idx: usize) -> ResultType<()> {
+    let mut c = match camera::Cameras::get_mjpeg_capturer(display_idx) {
+        Ok(c) => c,
+        Err(e) => {
+            on_capture_failed(sp, VideoSource::Camera, display_idx, &e);
+            return Err(e);
+        }
+    };
+    log::info!("camera {} passthrough", display_idx);
+    if sp.is_option_true(OPTION_REFRESH) {
+        sp.set_option_bool(OPTION_REFRESH, false);
+    }
+    let mut frame_controller = VideoFrameController::new(sp.name());
+    let mut recovering = CAPTURE_FAILURES.lock().unwrap().contains_key(&sp.name());
+    let start = time::Instant::now();
+    while sp.ok() {
+        // The new subscribers get the next frame, no switch is needed, but they must decode it.
+        sp.snapshot(|_| Ok(()))?;
+        if !use_camera_passthrough(sp, display_idx) {
+            log::info!("switch camera {} to transcode", display_idx);
+            bail!("SWITCH");
+        }
+        // All the frames are key frames, there's nothing to refresh.
+        if sp.is_option_true(OPTION_REFRESH) {
+            sp.set_option_bool(OPTION_REFRESH, false);
+        }
+        let spf = VIDEO_QOS.lock().unwrap().spf();
+        frame_controller.reset();
+        let now = time::Instant::now();
+        match c.frame() {
+            Ok((data, _, _)) => {
+                let ms = (now - start).as_millis() as i64;
+                let mut vf = VideoFrame::new();
+                vf.set_mjpegs(EncodedVideoFrames {
+                    frames: vec![EncodedVideoFrame {
+                        data: data.to_vec().into(),
+                        key: true,
+                        pts: ms,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                });
+                vf.display = display_idx as _;
+                vf.capture_time = super::media_capture_time();
+                let mut msg = Message::new();
+                msg.set_video_frame(vf);
+                let send_conn_ids =
+                    sp.send_versioned(|version| versioned_video_frame(&msg, version));
+                frame_controller.set_send(now, send_conn_ids);
+                if recovering {
+                    recovering = false;
+                    on_capture_recovered(sp);
+                }
+            }
+            Err(ref e) if e.kind() == WouldBlock => {}
+            Err(e) => {
+                on_capture_failed(sp, VideoSource::Camera, display_idx, &e);
+                return Err(e.into());
+            }
+        }
+        let mut fetched_conn_ids = HashSet::new();
+        let wait_begin = Instant::now();
+        while wait_begin.elapsed() < Duration::from_secs(3) {
+            frame_controller.try_wait_next(&mut fetched_conn_ids, 300);
+            if fetched_conn_ids.len() >= frame_controller.send_conn_ids.len() {
+                break;
+            }
+        }
+        let elapsed = now.elapsed();
+        if elapsed < spf {
+            std::thread::sleep(spf - elapsed);
+        }
+    }
+    Ok(())
+}
+
 struct Raii {
     name: String,
     try_vram: bool,
@@ -884,7 +1426,7 @@ fn get_encoder_config(
     _source: VideoSource,
 ) -> EncoderCfg {
     #[cfg(all(windows, feature = "vram"))]
-    if _portable_service || c.is_gdi() || _source == VideoSource::Camera {
+    if _portable_service || c.is_gdi() || !_source.is_monitor() {
         log::info!("gdi:{}, portable:{}", c.is_gdi(), _portable_service);
         VRamEncoder::set_not_use(_name, true);
     }
@@ -1046,18 +1588,51 @@ fn check_privacy_mode_changed(
     Ok(())
 }
 
+// The frames of the fps requested by each connection that are not captured in the interval of a
+// loop, by the reason of the delay. A connection which asks for fewer frames drops fewer of them.
+fn account_frame_drops(
+    sp: &GenericService,
+    interval: Duration,
+    encode_elapsed: Duration,
+    wait_elapsed: Duration,
+    idle: bool,
+) {
+    let users: Vec<_> = {
+        let video_qos = VIDEO_QOS.lock().unwrap();
+        sp.subscriber_ids()
+            .into_iter()
+            .filter_map(|id| Some((id, video_qos.user_requested_spf(id)?)))
+            .collect()
+    };
+    for (id, (spf, hidden)) in users {
+        let drops = session_report::frame_drops(
+            interval,
+            spf,
+            encode_elapsed,
+            wait_elapsed,
+            idle || hidden,
+        );
+        if let Some((reason, n)) = drops {
+            session_report::on_video_frames_dropped(&[id], reason, n);
+        }
+    }
+}
+
 #[inline]
 fn handle_one_frame(
     display: usize,
     sp: &GenericService,
     frame: EncodeInput,
     ms: i64,
+    // The time of `media_capture_time()`, shared with the audio frames.
+    capture_time: i64,
     encoder: &mut Encoder,
     recorder: Arc<Mutex<Option<Recorder>>>,
     encode_fail_counter: &mut usize,
     first_frame: &mut bool,
     width: usize,
     height: usize,
+    frame_size_limit: &FrameSizeLimit,
 ) -> ResultType<HashSet<i32>> {
     sp.snapshot(|sps| {
         // so that new sub and old sub share the same encoder after switch
@@ -1075,14 +1650,16 @@ fn handle_one_frame(
         Ok(mut vf) => {
             *encode_fail_counter = 0;
             vf.display = display as _;
+            vf.capture_time = capture_time;
             let mut msg = Message::new();
             msg.set_video_frame(vf);
+            frame_size_limit.check(sp, &msg)?;
             recorder
                 .lock()
                 .unwrap()
                 .as_mut()
                 .map(|r| r.write_message(&msg, width, height));
-            send_conn_ids = sp.send_video_frame(msg);
+            send_conn_ids = sp.send_versioned(|version| versioned_video_frame(&msg, version));
         }
         Err(e) => {
             *encode_fail_counter += 1;
@@ -1144,6 +1721,18 @@ fn start_uac_elevation_check() {
     });
 }
 
+// The capture time is only sent to the peers of the video message version 1.
+// The legacy frame is otherwise unchanged, old clients route the frames by `display`.
+pub(super) fn versioned_video_frame(msg: &Message, version: u32) -> Message {
+    let mut msg = msg.clone();
+    if version < 1 {
+        if let Some(message::Union::VideoFrame(vf)) = msg.union.as_mut() {
+            vf.capture_time = 0;
+        }
+    }
+    msg
+}
+
 #[inline]
 fn try_broadcast_display_changed(
     sp: &GenericService,
@@ -1155,6 +1744,13 @@ fn try_broadcast_display_changed(
         // Get display information immediately.
         crate::display_service::check_displays_changed().ok();
     }
+    #[cfg(target_os = "linux")]
+    if !scrap::is_x11() && scrap::wayland::pipewire::take_cursor_fallen_back() {
+        // The same display, only the peers draw the cursor from now on.
+        if let Some(msg_out) = make_display_changed_msg(display_idx, None, VideoSource::Monitor) {
+            sp.send(msg_out);
+        }
+    }
     if let Some(display) = check_display_changed(
         cap.ndisplay,
         cap.current,
@@ -1177,6 +1773,77 @@ fn try_broadcast_display_changed(
     Ok(())
 }
 
+fn make_capture_recovery_msg(msgtype: &str, text: &str) -> Message {
+    let mut msg_out = Message::new();
+    msg_out.set_message_box(MessageBox {
+        msgtype: msgtype.to_owned(),
+        title: "capture-recovery-title".to_owned(),
+        text: text.to_owned(),
+        link: "".to_owned(),
+        ..Default::default()
+    });
+    msg_out
+}
+
+// Tell the subscribers on the first failure, then wait with backoff before the capturer is rebuilt.
+fn on_capture_failed(
+    sp: &GenericService,
+    source: VideoSource,
+    display_idx: usize,
+    err: &dyn std::fmt::Display,
+) {
+    let failures = {
+        let mut lock = CAPTURE_FAILURES.lock().unwrap();
+        let entry = lock.entry(sp.name()).or_insert((0, Instant::now()));
+        entry.0 += 1;
+        entry.0
+    };
+    if failures == 1 {
+        sp.send(make_capture_recovery_msg(
+            "custom-nook-nocancel-hasclose",
+            "capture-recovering-tip",
+        ));
+    }
+    let display_gone = source.is_monitor()
+        && display_service::try_get_displays()
+            .map(|displays| displays.len() <= display_idx)
+            .unwrap_or(false);
+    let delay = if display_gone {
+        CAPTURE_RECOVERY_DISPLAY_GONE_DELAY
+    } else {
+        CAPTURE_RECOVERY_MIN_DELAY
+            .saturating_mul(1 << (failures - 1).min(16))
+            .min(CAPTURE_RECOVERY_MAX_DELAY)
+    };
+    log::warn!(
+        "Capture of {} failed {} time(s), display gone: {}, retry in {:?}: {}",
+        sp.name(),
+        failures,
+        display_gone,
+        delay,
+        err
+    );
+    let start = Instant::now();
+    while sp.ok() && start.elapsed() < delay {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn on_capture_recovered(sp: &GenericService) {
+    if let Some((failures, since)) = CAPTURE_FAILURES.lock().unwrap().remove(&sp.name()) {
+        log::info!(
+            "Capture of {} recovered after {} failure(s) in {:?}",
+            sp.name(),
+            failures,
+            since.elapsed()
+        );
+        sp.send(make_capture_recovery_msg(
+            "custom-success-nook-nocancel-hasclose",
+            "capture-recovered-tip",
+        ));
+    }
+}
+
 pub fn make_display_changed_msg(
     display_idx: usize,
     opt_display: Option<DisplayInfo>,
@@ -1189,6 +1856,7 @@ pub fn make_display_changed_msg(
             VideoSource::Camera => camera::Cameras::get_sync_cameras()
                 .get(display_idx)?
                 .clone(),
+            VideoSource::Window => return None,
         },
     };
     let mut misc = Misc::new();
@@ -1200,7 +1868,7 @@ pub fn make_display_changed_msg(
         height: display.height,
         cursor_embedded: match source {
             VideoSource::Monitor => display_service::capture_cursor_embedded(),
-            VideoSource::Camera => false,
+            VideoSource::Camera | VideoSource::Window => false,
         },
         #[cfg(not(target_os = "android"))]
         resolutions: Some(SupportedResolutions {
@@ -1216,6 +1884,7 @@ pub fn make_display_changed_msg(
                     .ok()
                     .into_iter()
                     .collect(),
+                VideoSource::Window => vec![],
             },
             ..SupportedResolutions::default()
         })
@@ -1239,11 +1908,12 @@ fn check_qos(
 ) -> ResultType<()> {
     let mut video_qos = VIDEO_QOS.lock().unwrap();
     *spf = video_qos.spf();
-    if *ratio != video_qos.ratio() {
-        *ratio = video_qos.ratio();
+    let display_ratio = video_qos.display_ratio(name);
+    if *ratio != display_ratio {
+        *ratio = display_ratio;
         if encoder.support_changing_quality() {
             allow_err!(encoder.set_quality(*ratio));
-            video_qos.store_bitrate(encoder.bitrate());
+            video_qos.store_bitrate(name, encoder.bitrate());
         } else {
             // Now only vaapi doesn't support changing quality
             if !video_qos.in_vbr_state() && !video_qos.latest_quality().is_custom() {
@@ -1276,6 +1946,122 @@ pub fn set_take_screenshot(display_idx: usize, sid: String, tx: Sender) {
     );
 }
 
+// Hand the captured frame to the waiting previews, and keep it as the last frame of the display
+// while the previews want it. Texture frames can't be read back, the waiting side times out.
+fn on_preview_frame(display_idx: usize, frame: &scrap::Frame) {
+    let previews = PREVIEWS.lock().unwrap().remove(&display_idx);
+    let keep = {
+        let mut lock = LAST_PREVIEW_FRAMES.lock().unwrap();
+        match lock.get(&display_idx) {
+            Some((until, _)) if *until < Instant::now() => {
+                lock.remove(&display_idx);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    };
+    if previews.is_none() && !keep {
+        return;
+    }
+    let scrap::Frame::PixelBuffer(f) = frame else {
+        return;
+    };
+    match get_rgba_from_pixelbuf(f) {
+        Ok(rgba) => {
+            let data = (f.width(), f.height(), rgba);
+            for tx in previews.unwrap_or_default() {
+                tx.send(data.clone()).ok();
+            }
+            if let Some((_, last)) = LAST_PREVIEW_FRAMES.lock().unwrap().get_mut(&display_idx) {
+                *last = Some(data);
+            }
+        }
+        Err(e) => log::error!("Failed to convert the preview frame, {}", e),
+    }
+}
+
+/// The last frame captured by the running video service of the display, without forcing the
+/// capturer to refresh. The frames are kept for a while after each call, so the screen which
+/// doesn't change still has a frame. If there's none yet, the next frame is waited for, and a
+/// temporary capturer is used if the screen doesn't change in time.
+pub fn take_preview_frame(display_idx: usize, timeout: Duration) -> ResultType<PreviewFrameData> {
+    // Longer than the default interval of the previews.
+    const KEEP: Duration = Duration::from_secs(180);
+    let (tx, rx) = std::sync::mpsc::channel();
+    {
+        let mut lock = LAST_PREVIEW_FRAMES.lock().unwrap();
+        let now = Instant::now();
+        lock.retain(|_, (until, _)| *until >= now);
+        let (until, last) = lock.entry(display_idx).or_insert((now, None));
+        *until = now + KEEP;
+        if let Some(data) = last.clone() {
+            return Ok(data);
+        }
+        PREVIEWS
+            .lock()
+            .unwrap()
+            .entry(display_idx)
+            .or_default()
+            .push(tx);
+    }
+    match rx.recv_timeout(timeout) {
+        Ok(data) => Ok(data),
+        Err(e) => {
+            PREVIEWS.lock().unwrap().remove(&display_idx);
+            log::debug!(
+                "No frame of display {} in time, {}, capture it directly",
+                display_idx,
+                e
+            );
+            let data = capture_preview_frame(display_idx, timeout)?;
+            if let Some((_, last)) = LAST_PREVIEW_FRAMES.lock().unwrap().get_mut(&display_idx) {
+                *last = Some(data.clone());
+            }
+            Ok(data)
+        }
+    }
+}
+
+/// Capture one frame of the display with a temporary capturer, no encoder is created.
+pub fn capture_preview_frame(
+    display_idx: usize,
+    timeout: Duration,
+) -> ResultType<PreviewFrameData> {
+    // The capturer of wayland asks the user for permission.
+    #[cfg(target_os = "linux")]
+    if !is_x11() {
+        bail!("Preview without an active session is not supported on Wayland");
+    }
+    #[cfg(windows)]
+    let portable_service_running = crate::portable_service::client::running();
+    #[cfg(not(windows))]
+    let portable_service_running = false;
+    let mut c = get_capturer_monitor(display_idx, portable_service_running)?;
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        match c.frame(Duration::from_millis(100)) {
+            Ok(frame) => {
+                if !frame.valid() {
+                    continue;
+                }
+                match &frame {
+                    scrap::Frame::PixelBuffer(f) => {
+                        return Ok((f.width(), f.height(), get_rgba_from_pixelbuf(f)?));
+                    }
+                    scrap::Frame::Texture(_) => bail!("Texture frame is not supported by preview"),
+                }
+            }
+            Err(e) if e.kind() == WouldBlock => {}
+            Err(e) => bail!("Failed to capture the preview frame: {}", e),
+        }
+    }
+    bail!(
+        "Timeout to capture the preview frame of display {}",
+        display_idx
+    );
+}
+
 // We need to this function, because the `stride` may be larger than `width * 4`.
 fn get_rgba_from_pixelbuf<'a>(pixbuf: &scrap::PixelBuffer<'a>) -> ResultType<Vec<u8>> {
     let w = pixbuf.width();
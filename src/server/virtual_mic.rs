@@ -0,0 +1,206 @@
+// The virtual microphone of the host, the audio of the voice call of the peer is played into a
+// virtual audio device instead of the speakers, so the programs on the host can record the peer
+// as a microphone.
+//
+// It is opt-in on both sides, the peer asks for it in the voice call request and the host allows
+// it with "allow-virtual-mic". Only one connection can use it at a time.
+// On Linux a null sink and a source remapped from its monitor are loaded into PulseAudio/PipeWire
+// of the active user when the call starts and unloaded when it ends. On Windows a virtual audio
+// driver must be installed, the device is "virtual-mic-device" or the first one of the known
+// drivers.
+
+use super::audio_service;
+use hbb_common::{bail, config::Config, log, ResultType};
+use std::sync::Mutex;
+
+pub const OPTION_ALLOW_VIRTUAL_MIC: &str = "allow-virtual-mic";
+pub const OPTION_VIRTUAL_MIC_DEVICE: &str = "virtual-mic-device";
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<Option<State>> = Default::default();
+}
+
+struct State {
+    conn_id: i32,
+    // The output device of the voice call before the virtual mic.
+    output_device: Option<String>,
+    device: imp::Device,
+}
+
+pub fn is_allowed() -> bool {
+    Config::get_option(OPTION_ALLOW_VIRTUAL_MIC) == "Y"
+}
+
+pub fn is_live() -> bool {
+    STATE.lock().unwrap().is_some()
+}
+
+/// Play the voice call of the connection into the virtual mic.
+pub fn start(conn_id: i32) -> ResultType<()> {
+    let mut state = STATE.lock().unwrap();
+    if let Some(s) = state.as_ref() {
+        if s.conn_id == conn_id {
+            return Ok(());
+        }
+        bail!("The virtual microphone is used by another connection");
+    }
+    let device = imp::create()?;
+    let output_device = audio_service::get_voice_call_output_device();
+    audio_service::set_voice_call_output_device(Some(device.name.clone()), true);
+    log::info!("Virtual microphone started on {}", device.name);
+    *state = Some(State {
+        conn_id,
+        output_device,
+        device,
+    });
+    Ok(())
+}
+
+/// Returns true if the virtual mic of the connection was live.
+pub fn stop(conn_id: i32) -> bool {
+    let mut state = STATE.lock().unwrap();
+    match state.take() {
+        Some(s) if s.conn_id == conn_id => {
+            audio_service::set_voice_call_output_device(s.output_device, true);
+            imp::destroy(s.device);
+            log::info!("Virtual microphone stopped");
+            true
+        }
+        s => {
+            *state = s;
+            false
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::*;
+    use std::process::Command;
+
+    const SINK_NAME: &str = "rustdesk_virtual_mic_sink";
+    // The voice call output devices are selected by the description on Linux.
+    const SINK_DESCRIPTION: &str = "RustDesk-Virtual-Mic-Sink";
+    const SOURCE_NAME: &str = "rustdesk_virtual_mic";
+    const SOURCE_DESCRIPTION: &str = "RustDesk-Virtual-Microphone";
+
+    pub struct Device {
+        pub name: String,
+        // The loaded modules, the source before the sink.
+        modules: Vec<u32>,
+    }
+
+    pub fn create() -> ResultType<Device> {
+        let sink = load_module(&[
+            "module-null-sink",
+            &format!("sink_name={}", SINK_NAME),
+            &format!("sink_properties=device.description={}", SINK_DESCRIPTION),
+        ])?;
+        let source = match load_module(&[
+            "module-remap-source",
+            &format!("master={}.monitor", SINK_NAME),
+            &format!("source_name={}", SOURCE_NAME),
+            &format!(
+                "source_properties=device.description={}",
+                SOURCE_DESCRIPTION
+            ),
+        ]) {
+            Ok(source) => source,
+            Err(e) => {
+                unload_module(sink);
+                return Err(e);
+            }
+        };
+        Ok(Device {
+            name: SINK_DESCRIPTION.to_owned(),
+            modules: vec![source, sink],
+        })
+    }
+
+    pub fn destroy(device: Device) {
+        for module in device.modules {
+            unload_module(module);
+        }
+    }
+
+    // The server runs as root, the modules are loaded into the sound server of the user.
+    fn pactl() -> ResultType<Command> {
+        crate::platform::linux::user_command("pactl")
+    }
+
+    fn load_module(args: &[&str]) -> ResultType<u32> {
+        let output = pactl()?
+            .arg("load-module")
+            .args(args)
+            .output()?;
+        if !output.status.success() {
+            bail!(
+                "Failed to load {}: {}",
+                args[0],
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().parse()?)
+    }
+
+    fn unload_module(module: u32) {
+        match pactl().and_then(|mut cmd| {
+            Ok(cmd
+                .args(["unload-module", &module.to_string()])
+                .status()?)
+        }) {
+            Ok(status) if status.success() => {}
+            r => log::error!("Failed to unload the module {}: {:?}", module, r),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::*;
+    use cpal::traits::{DeviceTrait, HostTrait};
+
+    // The render endpoints of the common virtual audio drivers, the capture endpoint of the same
+    // driver is the microphone.
+    const KNOWN_DEVICES: &[&str] = &["CABLE Input", "VoiceMeeter Input", "Virtual Audio"];
+
+    pub struct Device {
+        pub name: String,
+    }
+
+    pub fn create() -> ResultType<Device> {
+        let names: Vec<String> = cpal::default_host()
+            .output_devices()?
+            .filter_map(|d| d.name().ok())
+            .collect();
+        let option = Config::get_option(OPTION_VIRTUAL_MIC_DEVICE);
+        let name = if option.is_empty() {
+            names
+                .into_iter()
+                .find(|name| KNOWN_DEVICES.iter().any(|k| name.contains(k)))
+        } else {
+            names.into_iter().find(|name| *name == option)
+        };
+        match name {
+            Some(name) => Ok(Device { name }),
+            None => bail!("No virtual audio driver is installed"),
+        }
+    }
+
+    pub fn destroy(_device: Device) {}
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod imp {
+    use super::*;
+
+    pub struct Device {
+        pub name: String,
+    }
+
+    pub fn create() -> ResultType<Device> {
+        bail!("The virtual microphone is not supported on this platform");
+    }
+
+    pub fn destroy(_device: Device) {}
+}
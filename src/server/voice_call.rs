@@ -0,0 +1,117 @@
+// The voice calls of the connections, only one is on at a time. The host has one input device for
+// the calls, two calls at once mixed the audio of both and restarted the audio service for every
+// change of either.
+//
+// The first request takes the call, it is offered to the user of the host in the cm. The requests
+// of the other connections wait in a queue while the call is offered or on, their peers get a busy
+// response with the position in the queue and keep waiting. When the call ends, it is rejected,
+// closed by either side or the connection is closed, the first waiting one is offered next. Only
+// the connection of the call sets the input device of the calls.
+
+use hbb_common::log;
+use std::{collections::VecDeque, sync::Mutex};
+
+lazy_static::lazy_static! {
+    static ref CALLS: Mutex<Calls> = Default::default();
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    Offer,
+    // The position in the queue, from 1.
+    Busy(usize),
+}
+
+#[derive(Debug, Default)]
+struct Calls {
+    // The connection whose call is offered or on.
+    owner: Option<i32>,
+    waiting: VecDeque<i32>,
+}
+
+impl Calls {
+    fn request(&mut self, conn_id: i32) -> Request {
+        match self.owner {
+            None => {
+                self.owner = Some(conn_id);
+                Request::Offer
+            }
+            Some(owner) if owner == conn_id => Request::Offer,
+            Some(_) => {
+                let pos = match self.waiting.iter().position(|c| *c == conn_id) {
+                    Some(i) => i,
+                    None => {
+                        self.waiting.push_back(conn_id);
+                        self.waiting.len() - 1
+                    }
+                };
+                Request::Busy(pos + 1)
+            }
+        }
+    }
+
+    // Returns the next call to offer if the call of the connection was the one.
+    fn end(&mut self, conn_id: i32) -> Option<i32> {
+        self.waiting.retain(|c| *c != conn_id);
+        if self.owner != Some(conn_id) {
+            return None;
+        }
+        self.owner = self.waiting.pop_front();
+        self.owner
+    }
+}
+
+/// The call request of the connection, it is offered to the user of the host or waits.
+pub fn request(conn_id: i32) -> Request {
+    let res = CALLS.lock().unwrap().request(conn_id);
+    if let Request::Busy(pos) = res {
+        log::info!(
+            "The voice call of connection {} is waiting, position: {}",
+            conn_id,
+            pos
+        );
+    }
+    res
+}
+
+#[inline]
+pub fn is_owner(conn_id: i32) -> bool {
+    CALLS.lock().unwrap().owner == Some(conn_id)
+}
+
+/// The call of the connection ends or it stops waiting, the next waiting call is offered.
+pub fn end(conn_id: i32) {
+    let mut next = CALLS.lock().unwrap().end(conn_id);
+    while let Some(conn_id) = next {
+        if super::offer_voice_call(conn_id) {
+            log::info!("Offer the waiting voice call of connection {}", conn_id);
+            return;
+        }
+        // The connection is gone.
+        next = CALLS.lock().unwrap().end(conn_id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_calls() {
+        let mut calls = Calls::default();
+        assert_eq!(calls.request(1), Request::Offer);
+        assert_eq!(calls.request(1), Request::Offer);
+        assert_eq!(calls.request(2), Request::Busy(1));
+        assert_eq!(calls.request(3), Request::Busy(2));
+        assert_eq!(calls.request(2), Request::Busy(1));
+        // A waiting call ends, the call on is not changed.
+        assert_eq!(calls.end(2), None);
+        assert_eq!(calls.request(4), Request::Busy(2));
+        assert_eq!(calls.end(1), Some(3));
+        assert_eq!(calls.request(3), Request::Offer);
+        assert_eq!(calls.end(3), Some(4));
+        assert_eq!(calls.end(4), None);
+        assert_eq!(calls.owner, None);
+        assert_eq!(calls.request(5), Request::Offer);
+    }
+}
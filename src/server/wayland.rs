@@ -228,7 +228,12 @@ pub(super) async fn get_displays() -> ResultType<Vec<DisplayInfo>> {
         let cap_display_info: *const CapDisplayInfo = addr as _;
         unsafe {
             let cap_display_info = &*cap_display_info;
-            Ok(cap_display_info.displays.clone())
+            let mut displays = cap_display_info.displays.clone();
+            // The cursor may fall back to the software one after the capture starts.
+            for display in displays.iter_mut() {
+                display.cursor_embedded = is_cursor_embedded();
+            }
+            Ok(displays)
         }
     } else {
         bail!("Failed to get capturer display info");
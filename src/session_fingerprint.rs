@@ -0,0 +1,135 @@
+// The fingerprint of an encrypted session, for the users on both sides to compare by voice, e.g.
+// on the phone, if they want to be sure nobody is in the middle.
+//
+// digest = sha256(DOMAIN || host sign pk || host box pk || client box pk)
+// where every key is prefixed with its length in 4 bytes big endian. The host box pk is the one
+// signed in `SignedId`, the client box pk is `PublicKey.asymmetric_value`, so both sides derive
+// it from the same handshake. The first 42 bits of the digest, 6 bits at a time from the most
+// significant, pick 7 of the 64 `EMOJIS`. Someone in the middle has to replace a box key of one
+// side, which changes the fingerprint of the other side, and the 42 bits can't be searched
+// within a handshake. The unencrypted sessions have no fingerprint, `UNVERIFIED` is shown.
+
+use sha2::{Digest, Sha256};
+
+pub const UNVERIFIED: &str = "unverified";
+const DOMAIN: &[u8] = b"RustDesk session fingerprint v1";
+const SYMBOLS: usize = 7;
+
+// Easy to name and to tell apart, do not reorder, the fingerprints would change.
+pub const EMOJIS: [(&str, &str); 64] = [
+    ("🐶", "Dog"),
+    ("🐱", "Cat"),
+    ("🦁", "Lion"),
+    ("🐎", "Horse"),
+    ("🦄", "Unicorn"),
+    ("🐷", "Pig"),
+    ("🐘", "Elephant"),
+    ("🐰", "Rabbit"),
+    ("🐼", "Panda"),
+    ("🐓", "Rooster"),
+    ("🐧", "Penguin"),
+    ("🐢", "Turtle"),
+    ("🐟", "Fish"),
+    ("🐙", "Octopus"),
+    ("🦋", "Butterfly"),
+    ("🌷", "Flower"),
+    ("🌳", "Tree"),
+    ("🌵", "Cactus"),
+    ("🍄", "Mushroom"),
+    ("🌏", "Globe"),
+    ("🌙", "Moon"),
+    ("☁️", "Cloud"),
+    ("🔥", "Fire"),
+    ("🍌", "Banana"),
+    ("🍎", "Apple"),
+    ("🍓", "Strawberry"),
+    ("🌽", "Corn"),
+    ("🍕", "Pizza"),
+    ("🎂", "Cake"),
+    ("❤️", "Heart"),
+    ("😀", "Smiley"),
+    ("🤖", "Robot"),
+    ("🎩", "Hat"),
+    ("👓", "Glasses"),
+    ("🔧", "Spanner"),
+    ("🎅", "Santa"),
+    ("👍", "Thumbs Up"),
+    ("☂️", "Umbrella"),
+    ("⌛", "Hourglass"),
+    ("⏰", "Clock"),
+    ("🎁", "Gift"),
+    ("💡", "Light Bulb"),
+    ("📕", "Book"),
+    ("✏️", "Pencil"),
+    ("📎", "Paperclip"),
+    ("✂️", "Scissors"),
+    ("🔒", "Lock"),
+    ("🔑", "Key"),
+    ("🔨", "Hammer"),
+    ("☎️", "Telephone"),
+    ("🏁", "Flag"),
+    ("🚂", "Train"),
+    ("🚲", "Bicycle"),
+    ("✈️", "Aeroplane"),
+    ("🚀", "Rocket"),
+    ("🏆", "Trophy"),
+    ("⚽", "Ball"),
+    ("🎸", "Guitar"),
+    ("🎺", "Trumpet"),
+    ("🔔", "Bell"),
+    ("⚓", "Anchor"),
+    ("🎧", "Headphones"),
+    ("📁", "Folder"),
+    ("📌", "Pin"),
+];
+
+/// The indexes into `EMOJIS`.
+pub fn derive(host_sign_pk: &[u8], host_box_pk: &[u8], client_box_pk: &[u8]) -> [usize; SYMBOLS] {
+    let mut hasher = Sha256::new();
+    hasher.update(DOMAIN);
+    for key in [host_sign_pk, host_box_pk, client_box_pk] {
+        hasher.update((key.len() as u32).to_be_bytes());
+        hasher.update(key);
+    }
+    let digest = hasher.finalize();
+    let bits = digest[..6]
+        .iter()
+        .fold(0u64, |bits, b| bits << 8 | *b as u64)
+        >> 6;
+    let mut indexes = [0; SYMBOLS];
+    for (i, index) in indexes.iter_mut().enumerate() {
+        *index = (bits >> (6 * (SYMBOLS - 1 - i)) & 63) as usize;
+    }
+    indexes
+}
+
+/// e.g. "🐶 Dog, 🐱 Cat, ...", the same on both sides of the session.
+pub fn fingerprint(host_sign_pk: &[u8], host_box_pk: &[u8], client_box_pk: &[u8]) -> String {
+    derive(host_sign_pk, host_box_pk, client_box_pk)
+        .iter()
+        .map(|i| format!("{} {}", EMOJIS[*i].0, EMOJIS[*i].1))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_derive_vectors() {
+        assert_eq!(
+            derive(&[0; 32], &[1; 32], &[2; 32]),
+            [53, 54, 16, 54, 32, 55, 57]
+        );
+        assert_eq!(
+            derive(&[0; 32], &[1; 32], &[3; 32]),
+            [9, 4, 7, 60, 49, 61, 16]
+        );
+        assert_eq!(derive(&[], &[], &[]), [52, 2, 61, 22, 28, 11, 51]);
+        assert_eq!(
+            fingerprint(&[0; 32], &[1; 32], &[2; 32]),
+            "✈️ Aeroplane, 🚀 Rocket, 🌳 Tree, 🚀 Rocket, 🎩 Hat, 🏆 Trophy, 🎸 Guitar"
+        );
+    }
+}
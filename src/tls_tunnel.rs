@@ -0,0 +1,299 @@
+// TLS tunnel for the direct connections, for the networks which fingerprint and block the
+// handshake of RustDesk.
+//
+// If "direct-access-tls" is "Y", the direct access port only accepts TLS, and the controlling
+// side wraps its direct connections in TLS if "direct-access-tls-dial" is "Y", nothing is
+// detected automatically. The native protocol runs unchanged inside the tunnel, including the
+// signed key handshake, so the tunnel adds no trust.
+//
+// The certificate is "direct-access-tls-cert" and "direct-access-tls-key" (PEM files), or a
+// self-signed one generated once, whose key only the owner can read. The controlling side accepts
+// any certificate unless its sha256 fingerprint is pinned with "direct-access-tls-pin".
+
+use hbb_common::{
+    anyhow::anyhow, bail, config::Config, log, tcp::FramedStream, timeout, tokio::net::TcpStream,
+    ResultType, Stream,
+};
+use sha2::{Digest, Sha256};
+use std::{io::Write, net::SocketAddr, path::Path, sync::Arc};
+use tokio_rustls::{
+    rustls::{
+        self,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{ring, CryptoProvider},
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+        DigitallySignedStruct, SignatureScheme,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+pub const OPTION_DIRECT_ACCESS_TLS: &str = "direct-access-tls";
+pub const OPTION_DIRECT_ACCESS_TLS_DIAL: &str = "direct-access-tls-dial";
+const OPTION_CERT: &str = "direct-access-tls-cert";
+const OPTION_KEY: &str = "direct-access-tls-key";
+// The server name sent in the client hello, the host of the peer by default.
+const OPTION_SNI: &str = "direct-access-tls-sni";
+const OPTION_PIN: &str = "direct-access-tls-pin";
+const SELF_SIGNED_NAME: &str = "localhost";
+const HANDSHAKE_TIMEOUT: u64 = 10_000;
+
+lazy_static::lazy_static! {
+    // The fingerprint of the certificate of the listener.
+    static ref FINGERPRINT: std::sync::Mutex<Option<String>> = Default::default();
+}
+
+#[inline]
+pub fn is_listen_enabled() -> bool {
+    Config::get_option(OPTION_DIRECT_ACCESS_TLS) == "Y"
+}
+
+#[inline]
+pub fn is_dial_enabled() -> bool {
+    Config::get_option(OPTION_DIRECT_ACCESS_TLS_DIAL) == "Y"
+}
+
+/// The sha256 fingerprint of the certificate the direct access port is serving,
+/// None if the port is not TLS.
+pub fn get_fingerprint() -> Option<String> {
+    FINGERPRINT.lock().unwrap().clone()
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn fingerprint(cert: &CertificateDer) -> String {
+    hex::encode(Sha256::digest(cert.as_ref()))
+}
+
+// The pins may be written with colons or spaces, in any case.
+fn normalize_fingerprint(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn load_cert() -> ResultType<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let mut cert_path = Config::get_option(OPTION_CERT);
+    let mut key_path = Config::get_option(OPTION_KEY);
+    if cert_path.is_empty() != key_path.is_empty() {
+        bail!("Both {} and {} are required", OPTION_CERT, OPTION_KEY);
+    }
+    if cert_path.is_empty() {
+        let dir = Config::path("direct_tls");
+        let cert = dir.join("cert.pem");
+        let key = dir.join("key.pem");
+        if !cert.exists() || !key.exists() {
+            log::info!("Generate the self-signed certificate of the direct access port");
+            let rcgen::CertifiedKey { cert: c, key_pair } =
+                rcgen::generate_simple_self_signed(vec![SELF_SIGNED_NAME.to_owned()])?;
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(&cert, c.pem())?;
+            write_private_key(&key, &key_pair.serialize_pem())?;
+        } else if let Err(e) = restrict_private_key(&key) {
+            // Written by an older version.
+            log::warn!("Failed to restrict the access to {}: {}", key.display(), e);
+        }
+        cert_path = cert.to_string_lossy().to_string();
+        key_path = key.to_string_lossy().to_string();
+    }
+    let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(std::fs::File::open(
+        &cert_path,
+    )?))
+    .collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        bail!("No certificate in {}", cert_path);
+    }
+    let key = rustls_pemfile::private_key(&mut std::io::BufReader::new(std::fs::File::open(
+        &key_path,
+    )?))?
+    .ok_or_else(|| anyhow!("No private key in {}", key_path))?;
+    Ok((certs, key))
+}
+
+// Restricted before the key is written, so it's never readable by the others.
+fn write_private_key(path: &Path, pem: &str) -> ResultType<()> {
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    restrict_private_key(path)?;
+    file.write_all(pem.as_bytes())?;
+    Ok(())
+}
+
+fn restrict_private_key(_path: &Path) -> ResultType<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(_path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    #[cfg(windows)]
+    crate::platform::windows::set_path_owner_only(_path)?;
+    Ok(())
+}
+
+/// Build the acceptor of the direct access port, with the configured or self-signed certificate.
+pub fn new_acceptor() -> ResultType<TlsAcceptor> {
+    let (certs, key) = load_cert()?;
+    let fp = fingerprint(&certs[0]);
+    let config = rustls::ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    log::info!("Direct access TLS certificate fingerprint: {}", fp);
+    *FINGERPRINT.lock().unwrap() = Some(fp);
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+pub fn on_listener_closed() {
+    *FINGERPRINT.lock().unwrap() = None;
+}
+
+pub async fn accept(
+    acceptor: &TlsAcceptor,
+    stream: TcpStream,
+    local_addr: SocketAddr,
+) -> ResultType<Stream> {
+    let stream = timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await??;
+    Ok(Stream::Tcp(FramedStream::from(stream, local_addr)))
+}
+
+/// Connect to `target` (host:port) through the tunnel.
+pub async fn connect(target: &str, ms_timeout: u64) -> ResultType<Stream> {
+    let stream = timeout(ms_timeout, TcpStream::connect(target)).await??;
+    stream.set_nodelay(true).ok();
+    let local_addr = stream.local_addr()?;
+    let mut sni = Config::get_option(OPTION_SNI);
+    if sni.is_empty() {
+        sni = host_of(target).to_owned();
+    }
+    let server_name = ServerName::try_from(sni.clone())
+        .map_err(|e| anyhow!("Invalid server name {}: {}", sni, e))?;
+    let pin = normalize_fingerprint(&Config::get_option(OPTION_PIN));
+    let config = rustls::ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinVerifier {
+            pin,
+            provider: provider(),
+        }))
+        .with_no_client_auth();
+    let stream = timeout(
+        HANDSHAKE_TIMEOUT,
+        TlsConnector::from(Arc::new(config)).connect(server_name, stream),
+    )
+    .await??;
+    Ok(Stream::Tcp(FramedStream::from(stream, local_addr)))
+}
+
+fn host_of(target: &str) -> &str {
+    let host = match target.rfind(':') {
+        Some(i) if !target[i + 1..].contains(']') => &target[..i],
+        _ => target,
+    };
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+// The certificate is usually self-signed, the peer is authenticated by the native handshake
+// inside, so only the pinned fingerprint is checked.
+#[derive(Debug)]
+struct PinVerifier {
+    pin: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if !self.pin.is_empty() {
+            let fp = fingerprint(end_entity);
+            if fp != self.pin {
+                log::error!("Direct access TLS certificate fingerprint mismatch: {}", fp);
+                return Err(rustls::Error::General(
+                    "certificate fingerprint mismatch".to_owned(),
+                ));
+            }
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_host_of() {
+        assert_eq!(host_of("example.com:21118"), "example.com");
+        assert_eq!(host_of("1.2.3.4:21118"), "1.2.3.4");
+        assert_eq!(host_of("[::1]:21118"), "::1");
+        assert_eq!(normalize_fingerprint("AB:cd 01"), "abcd01");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_private_key_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("key-{}.pem", std::process::id()));
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        // A key left readable by the others.
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write_private_key(&path, "new").unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        restrict_private_key(&path).unwrap();
+        assert_eq!(mode(&path), 0o600);
+        std::fs::remove_file(&path).ok();
+    }
+}
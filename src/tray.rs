@@ -62,9 +62,13 @@ fn make_tray() -> hbb_common::ResultType<()> {
     let tray_menu = Menu::new();
     let quit_i = MenuItem::new(translate("Stop service".to_owned()), true, None);
     let open_i = MenuItem::new(translate("Open".to_owned()), true, None);
-    tray_menu.append_items(&[&open_i, &quit_i]).ok();
-    let tooltip = |count: usize| {
-        if count == 0 {
+    // The panic button where there's no global hotkey, e.g. Wayland.
+    let terminate_i = MenuItem::new(translate("Terminate all sessions".to_owned()), true, None);
+    tray_menu
+        .append_items(&[&open_i, &terminate_i, &quit_i])
+        .ok();
+    let tooltip = |count: usize, previews: usize, encryption: &(usize, String)| {
+        let mut tip = if count == 0 {
             format!(
                 "{} {}",
                 crate::get_app_name(),
@@ -77,8 +81,29 @@ fn make_tray() -> hbb_common::ResultType<()> {
                 translate("Ready".to_owned()),
                 translate("{".to_string() + &format!("{count}") + "} sessions"),
             )
+        };
+        if previews > 0 {
+            tip += &format!("\n{}", translate("Live preview is active".to_owned()));
         }
+        if count > 0 {
+            let (unencrypted, fingerprint) = encryption;
+            if *unencrypted > 0 {
+                tip += &format!(
+                    "\n{}",
+                    translate(
+                        "{".to_string() + &format!("{unencrypted}") + "} unencrypted sessions"
+                    )
+                );
+            } else if !fingerprint.is_empty() {
+                tip += &format!("\n{}: {}", translate("Fingerprint".to_owned()), fingerprint);
+            }
+        }
+        tip
     };
+    #[cfg(windows)]
+    let (mut session_count, mut preview_count) = (0, 0);
+    #[cfg(windows)]
+    let mut encryption = (0, String::new());
     let mut _tray_icon: Arc<Mutex<Option<TrayIcon>>> = Default::default();
 
     let menu_channel = MenuEvent::receiver();
@@ -133,7 +158,7 @@ fn make_tray() -> hbb_common::ResultType<()> {
             // to prevent issues like https://github.com/tauri-apps/tray-icon/issues/90
             let tray = TrayIconBuilder::new()
                 .with_menu(Box::new(tray_menu.clone()))
-                .with_tooltip(tooltip(0))
+                .with_tooltip(tooltip(0, 0, &Default::default()))
                 .with_icon(icon.clone())
                 .with_icon_as_template(true) // mac only
                 .build();
@@ -168,6 +193,12 @@ fn make_tray() -> hbb_common::ResultType<()> {
                 }
             } else if event.id == open_i.id() {
                 open_func();
+            } else if event.id == terminate_i.id() {
+                std::thread::spawn(|| {
+                    if let Err(err) = crate::ipc::terminate_sessions() {
+                        log::error!("Failed to terminate the sessions: {}", err);
+                    }
+                });
             }
         }
 
@@ -196,15 +227,16 @@ fn make_tray() -> hbb_common::ResultType<()> {
         #[cfg(windows)]
         if let Ok(data) = ipc_receiver.try_recv() {
             match data {
-                Data::ControlledSessionCount(count) => {
-                    _tray_icon
-                        .lock()
-                        .unwrap()
-                        .as_mut()
-                        .map(|t| t.set_tooltip(Some(tooltip(count))));
-                }
-                _ => {}
+                Data::ControlledSessionCount(count) => session_count = count,
+                Data::PreviewSessionCount(count) => preview_count = count,
+                Data::SessionEncryption(v) => encryption = v,
+                _ => return,
             }
+            _tray_icon
+                .lock()
+                .unwrap()
+                .as_mut()
+                .map(|t| t.set_tooltip(Some(tooltip(session_count, preview_count, &encryption))));
         }
     });
 }
@@ -213,6 +245,8 @@ fn make_tray() -> hbb_common::ResultType<()> {
 #[tokio::main(flavor = "current_thread")]
 async fn start_query_session_count(sender: std::sync::mpsc::Sender<Data>) {
     let mut last_count = 0;
+    let mut last_preview_count = 0;
+    let mut last_encryption = (0, String::new());
     loop {
         if let Ok(mut c) = crate::ipc::connect(1000, "").await {
             let mut timer = crate::rustdesk_interval(tokio::time::interval(Duration::from_secs(1)));
@@ -231,12 +265,26 @@ async fn start_query_session_count(sender: std::sync::mpsc::Sender<Data>) {
                                     sender.send(Data::ControlledSessionCount(count)).ok();
                                 }
                             }
+                            Ok(Some(Data::PreviewSessionCount(count))) => {
+                                if count != last_preview_count {
+                                    last_preview_count = count;
+                                    sender.send(Data::PreviewSessionCount(count)).ok();
+                                }
+                            }
+                            Ok(Some(Data::SessionEncryption(v))) => {
+                                if v != last_encryption {
+                                    last_encryption = v.clone();
+                                    sender.send(Data::SessionEncryption(v)).ok();
+                                }
+                            }
                             _ => {}
                         }
                     }
 
                     _ = timer.tick() => {
                         c.send(&Data::ControlledSessionCount(0)).await.ok();
+                        c.send(&Data::PreviewSessionCount(0)).await.ok();
+                        c.send(&Data::SessionEncryption(Default::default())).await.ok();
                     }
                 }
             }
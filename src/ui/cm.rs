@@ -67,6 +67,21 @@ impl InvokeUiCM for SciterHandler {
     }
 
     fn file_transfer_log(&self, _action: &str, _log: &str) {}
+
+    // There is no approval dialog here, so the kill is denied at once.
+    fn process_kill_request(&self, id: i32, pid: u32, _name: &str) {
+        crate::ui_cm_interface::handle_process_kill(id, pid, false);
+    }
+
+    // The notification is shown by the connection, only the events to confirm are shown here.
+    fn sensitive_event(&self, id: i32, event: &str, confirm_secs: u64) {
+        if confirm_secs > 0 {
+            self.call(
+                "sensitiveEvent",
+                &make_args!(id, event.to_owned(), confirm_secs as i32),
+            );
+        }
+    }
 }
 
 impl SciterHandler {
@@ -151,6 +166,23 @@ impl SciterConnectionManager {
     fn get_option(&self, key: String) -> String {
         crate::ui_interface::get_option(key)
     }
+
+    fn sensitive_event_text(&self, event: String, name: String, secs: i32) -> String {
+        let Some(e) = crate::server::sensitive_events::Event::from_name(&event) else {
+            return event;
+        };
+        format!(
+            "{}\n{}",
+            crate::client::translate(e.text(&name)),
+            crate::client::translate(format!(
+                "Confirm it within {{{secs}}} seconds, or it will be undone"
+            ))
+        )
+    }
+
+    fn confirm_sensitive_event(&self, id: i32, event: String, confirmed: bool) {
+        crate::ui_cm_interface::confirm_sensitive_event(id, &event, confirmed);
+    }
 }
 
 impl sciter::EventHandler for SciterConnectionManager {
@@ -172,5 +204,7 @@ impl sciter::EventHandler for SciterConnectionManager {
         fn can_elevate();
         fn elevate_portable(i32);
         fn get_option(String);
+        fn sensitive_event_text(String, String, i32);
+        fn confirm_sensitive_event(i32, String, bool);
     }
 }
@@ -228,6 +228,19 @@ impl InvokeUiSession for SciterHandler {
         self.call("updateFolderFiles", &make_args!(m));
     }
 
+    fn update_folder_files_page(
+        &self,
+        entries: &Vec<FileEntry>,
+        path: String,
+        offset: u64,
+        _total: u64,
+        done: bool,
+    ) {
+        let mut m = make_fd(0, entries, false);
+        m.set_item("path", path);
+        self.call("updateFolderFilesPage", &make_args!(m, offset as f64, done));
+    }
+
     fn update_transfer_list(&self) {
         self.call("updateTransferList", &make_args!());
     }
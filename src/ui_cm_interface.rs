@@ -59,9 +59,15 @@ pub struct Client {
     pub restart: bool,
     pub recording: bool,
     pub block_input: bool,
+    pub annotation: bool,
+    pub quick_actions: bool,
     pub from_switch: bool,
     pub in_voice_call: bool,
     pub incoming_voice_call: bool,
+    // The voice call of the peer is played into the virtual microphone.
+    pub virtual_mic: bool,
+    // See `session_fingerprint`, for the users to compare by voice.
+    pub fingerprint: String,
     #[serde(skip)]
     #[cfg(not(any(target_os = "ios")))]
     tx: UnboundedSender<Data>,
@@ -76,6 +82,8 @@ struct IpcTaskRunner<T: InvokeUiCM> {
     close: bool,
     running: bool,
     conn_id: i32,
+    // The pid of the running quick action of the connection.
+    quick_action: Option<u32>,
     #[cfg(target_os = "windows")]
     file_transfer_enabled: bool,
     #[cfg(target_os = "windows")]
@@ -109,6 +117,10 @@ pub trait InvokeUiCM: Send + Clone + 'static + Sized {
     fn update_voice_call_state(&self, client: &Client);
 
     fn file_transfer_log(&self, action: &str, log: &str);
+
+    fn process_kill_request(&self, id: i32, pid: u32, name: &str);
+
+    fn sensitive_event(&self, id: i32, event: &str, confirm_secs: u64);
 }
 
 impl<T: InvokeUiCM> Deref for ConnectionManager<T> {
@@ -143,7 +155,10 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
         restart: bool,
         recording: bool,
         block_input: bool,
+        annotation: bool,
+        quick_actions: bool,
         from_switch: bool,
+        fingerprint: String,
         #[cfg(not(any(target_os = "ios")))] tx: mpsc::UnboundedSender<Data>,
     ) {
         let client = Client {
@@ -163,11 +178,15 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
             restart,
             recording,
             block_input,
+            annotation,
+            quick_actions,
             from_switch,
             #[cfg(not(any(target_os = "ios")))]
             tx,
             in_voice_call: false,
             incoming_voice_call: false,
+            virtual_mic: false,
+            fingerprint,
         };
         CLIENTS
             .write()
@@ -251,6 +270,15 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
         if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
             client.incoming_voice_call = false;
             client.in_voice_call = false;
+            client.virtual_mic = false;
+            self.ui_handler.update_voice_call_state(client);
+        }
+    }
+
+    #[cfg(not(target_os = "ios"))]
+    fn virtual_mic_changed(&self, id: i32, live: bool) {
+        if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+            client.virtual_mic = live;
             self.ui_handler.update_voice_call_state(client);
         }
     }
@@ -408,9 +436,9 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                         }
                         Ok(Some(data)) => {
                             match data {
-                                Data::Login{id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, file_transfer_enabled: _file_transfer_enabled, restart, recording, block_input, from_switch} => {
+                                Data::Login{id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, file_transfer_enabled: _file_transfer_enabled, restart, recording, block_input, annotation, quick_actions, from_switch, fingerprint} => {
                                     log::debug!("conn_id: {}", id);
-                                    self.cm.add_connection(id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, restart, recording, block_input, from_switch, self.tx.clone());
+                                    self.cm.add_connection(id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, restart, recording, block_input, annotation, quick_actions, from_switch, fingerprint, self.tx.clone());
                                     self.conn_id = id;
                                     #[cfg(target_os = "windows")]
                                     {
@@ -501,6 +529,27 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                 Data::CloseVoiceCall(reason) => {
                                     self.cm.voice_call_closed(self.conn_id, reason.as_str());
                                 }
+                                Data::VirtualMic(live) => {
+                                    self.cm.virtual_mic_changed(self.conn_id, live);
+                                }
+                                Data::ProcessKillRequest((pid, name)) => {
+                                    self.cm.process_kill_request(self.conn_id, pid, &name);
+                                }
+                                Data::Annotation(cmd) => {
+                                    if !crate::annotation::handle(self.conn_id, cmd) {
+                                        allow_err!(self.stream.send(&Data::AnnotationUnsupported).await);
+                                    }
+                                }
+                                Data::QuickAction(action) => {
+                                    self.run_quick_action(action);
+                                }
+                                Data::ErrorCode(code) => {
+                                    log::info!("cm ipc connection {} error: {:?}", self.conn_id, crate::error_code::ErrorCode::from_code(code));
+                                }
+                                Data::SensitiveEvent((event, secs)) => {
+                                    crate::server::sensitive_events::play_sound();
+                                    self.cm.sensitive_event(self.conn_id, event.name(), secs);
+                                }
                                 #[cfg(target_os = "windows")]
                                 Data::ClipboardNonFile(_) => {
                                     match crate::clipboard::check_clipboard_cm() {
@@ -603,6 +652,30 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
         }
     }
 
+    // Run in the session of the user, the output goes back to the connection.
+    fn run_quick_action(&mut self, action: Option<crate::server::quick_actions::QuickAction>) {
+        use crate::server::quick_actions::{self, Output};
+
+        if let Some(pid) = self.quick_action.take() {
+            quick_actions::kill(pid);
+        }
+        let Some(action) = action else {
+            return;
+        };
+        let tx = self.tx.clone();
+        let on_output = move |output| {
+            tx.send(Data::QuickActionOutput(output)).ok();
+        };
+        match quick_actions::run(&action, on_output) {
+            Ok(pid) => self.quick_action = Some(pid),
+            Err(e) => {
+                self.tx
+                    .send(Data::QuickActionOutput(Output::Failed(e.to_string())))
+                    .ok();
+            }
+        }
+    }
+
     async fn ipc_task(stream: Connection, cm: ConnectionManager<T>) {
         log::debug!("ipc task begin");
         let (tx, rx) = mpsc::unbounded_channel::<Data>();
@@ -614,6 +687,7 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
             close: true,
             running: true,
             conn_id: 0,
+            quick_action: None,
             #[cfg(target_os = "windows")]
             file_transfer_enabled: false,
             #[cfg(target_os = "windows")]
@@ -623,7 +697,12 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
         while task_runner.running {
             task_runner.run().await;
         }
+        if let Some(pid) = task_runner.quick_action.take() {
+            crate::server::quick_actions::kill(pid);
+        }
         if task_runner.conn_id > 0 {
+            crate::annotation::clear(task_runner.conn_id);
+            crate::server::file_scan::remove_conn(task_runner.conn_id);
             task_runner
                 .cm
                 .remove_connection(task_runner.conn_id, task_runner.close);
@@ -691,7 +770,10 @@ pub async fn start_listen<T: InvokeUiCM>(
                 restart,
                 recording,
                 block_input,
+                annotation,
+                quick_actions,
                 from_switch,
+                fingerprint,
                 ..
             }) => {
                 current_id = id;
@@ -711,7 +793,10 @@ pub async fn start_listen<T: InvokeUiCM>(
                     restart,
                     recording,
                     block_input,
+                    annotation,
+                    quick_actions,
                     from_switch,
+                    fingerprint,
                     tx.clone(),
                 );
             }
@@ -733,6 +818,9 @@ pub async fn start_listen<T: InvokeUiCM>(
             Some(Data::CloseVoiceCall(reason)) => {
                 cm.voice_call_closed(current_id, reason.as_str());
             }
+            Some(Data::VirtualMic(live)) => {
+                cm.virtual_mic_changed(current_id, live);
+            }
             None => {
                 break;
             }
@@ -766,6 +854,43 @@ async fn handle_fs(
         } => {
             read_dir(&dir, include_hidden, tx).await;
         }
+        ipc::FS::ReadDirPaged {
+            conn_id,
+            id,
+            dir,
+            include_hidden,
+            page_size,
+            filter,
+            token,
+        } => {
+            // Not awaited, so the listing can be cancelled while the directory is being read.
+            let req = crate::server::dir_listing::PageRequest {
+                conn_id,
+                id,
+                dir,
+                include_hidden,
+                page_size,
+                filter,
+                token,
+            };
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match spawn_blocking(move || crate::server::dir_listing::read_page(&req)).await {
+                    Ok(Ok(Some(fd))) => {
+                        let mut msg_out = Message::new();
+                        let mut file_response = FileResponse::new();
+                        file_response.set_dir(fd);
+                        msg_out.set_file_response(file_response);
+                        send_raw(msg_out, &tx);
+                    }
+                    Ok(Err(err)) => log::error!("Failed to read the dir: {}", err),
+                    _ => {}
+                }
+            });
+        }
+        ipc::FS::CancelReadDir { conn_id, id } => {
+            crate::server::dir_listing::cancel(conn_id, id);
+        }
         ipc::FS::RemoveDir {
             path,
             id,
@@ -788,6 +913,19 @@ async fn handle_fs(
             total_size,
             conn_id,
         } => {
+            // The files are written to the session temporary directory until they are scanned.
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            let path = if crate::server::file_scan::is_enabled() {
+                match crate::server::file_scan::stage(conn_id, id, &path, total_size) {
+                    Ok(dir) => get_string(&dir),
+                    Err(err) => {
+                        send_raw(fs::new_error(id, err, 0), tx);
+                        return;
+                    }
+                }
+            } else {
+                path
+            };
             // cm has no show_hidden context
             // dummy remote, show_hidden, is_remote
             let mut job = fs::TransferJob::new_write(
@@ -815,6 +953,8 @@ async fn handle_fs(
         ipc::FS::CancelWrite { id } => {
             if let Some(job) = fs::remove_job(id, write_jobs) {
                 job.remove_download_file();
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                crate::server::file_scan::cancel(job.conn_id, id);
                 tx_log.map(|tx: &UnboundedSender<String>| {
                     tx.send(serialize_transfer_job(&job, false, true, ""))
                 });
@@ -823,12 +963,48 @@ async fn handle_fs(
         ipc::FS::WriteDone { id, file_num } => {
             if let Some(job) = fs::remove_job(id, write_jobs) {
                 job.modify_time();
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                if crate::server::file_scan::destination(job.conn_id, id).is_some() {
+                    // Not awaited, the other jobs go on while the files are being scanned.
+                    let tx = tx.clone();
+                    let tx_log = tx_log.cloned();
+                    tokio::spawn(async move {
+                        let (conn_id, files) = (job.conn_id, job.files().to_vec());
+                        let blocked = spawn_blocking(move || {
+                            crate::server::file_scan::finish(conn_id, id, &files)
+                        })
+                        .await
+                        .unwrap_or_default();
+                        if blocked.is_empty() {
+                            send_raw(fs::new_done(id, file_num), &tx);
+                            tx_log.map(|tx| tx.send(serialize_transfer_job(&job, true, false, "")));
+                        } else {
+                            let err = format!(
+                                "{}: {}",
+                                crate::server::file_scan::BLOCKED_BY_POLICY,
+                                blocked
+                                    .iter()
+                                    .map(|(path, _)| path.as_str())
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            );
+                            tx_log.map(|tx| {
+                                tx.send(serialize_transfer_job(&job, false, false, &err))
+                            });
+                            send_raw(fs::new_error(id, err, file_num), &tx);
+                            allow_err!(tx.send(Data::FileScanBlocked(blocked)));
+                        }
+                    });
+                    return;
+                }
                 send_raw(fs::new_done(id, file_num), tx);
                 tx_log.map(|tx| tx.send(serialize_transfer_job(&job, true, false, "")));
             }
         }
         ipc::FS::WriteError { id, file_num, err } => {
             if let Some(job) = fs::remove_job(id, write_jobs) {
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                crate::server::file_scan::cancel(job.conn_id, id);
                 tx_log.map(|tx| tx.send(serialize_transfer_job(&job, false, false, &err)));
                 send_raw(fs::new_error(job.id(), err, file_num), tx);
             }
@@ -851,6 +1027,21 @@ async fn handle_fs(
                     .await
                 {
                     send_raw(fs::new_error(id, err, file_num), &tx);
+                    return;
+                }
+                // The declared size of a staged job is not trusted, the written bytes are.
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                if let Err(err) =
+                    crate::server::file_scan::on_written(job.conn_id, id, job.finished_size())
+                {
+                    let conn_id = job.conn_id;
+                    if let Some(job) = fs::remove_job(id, write_jobs) {
+                        tx_log.map(|tx| {
+                            tx.send(serialize_transfer_job(&job, false, false, &err.to_string()))
+                        });
+                    }
+                    crate::server::file_scan::cancel(conn_id, id);
+                    send_raw(fs::new_error(id, err, file_num), &tx);
                 }
             }
         }
@@ -877,6 +1068,10 @@ async fn handle_fs(
                 };
                 if let Some(file) = job.files().get(file_num as usize) {
                     if let fs::DataSource::FilePath(p) = &job.data_source {
+                        // Compare with the destination, not the staging directory.
+                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                        let p = &crate::server::file_scan::destination(job.conn_id, id)
+                            .unwrap_or(p.clone());
                         let path = get_string(&fs::TransferJob::join(p, &file.name));
                         match is_write_need_confirmation(&path, &digest) {
                             Ok(digest_result) => {
@@ -1079,6 +1274,26 @@ pub fn handle_incoming_voice_call(id: i32, accept: bool) {
     };
 }
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+#[inline]
+pub fn handle_process_kill(id: i32, pid: u32, approved: bool) {
+    if let Some(client) = CLIENTS.read().unwrap().get(&id) {
+        allow_err!(client.tx.send(Data::ProcessKillResponse((pid, approved))));
+    };
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+#[inline]
+pub fn confirm_sensitive_event(id: i32, event: &str, confirmed: bool) {
+    let Some(event) = crate::server::sensitive_events::Event::from_name(event) else {
+        return;
+    };
+    if let Some(client) = CLIENTS.read().unwrap().get(&id) {
+        let data = Data::SensitiveEventConfirm((event, confirmed));
+        allow_err!(client.tx.send(data));
+    };
+}
+
 #[cfg(any(target_os = "android", target_os = "ios", feature = "flutter"))]
 #[inline]
 pub fn close_voice_call(id: i32) {
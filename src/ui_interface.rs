@@ -375,6 +375,43 @@ pub fn get_sound_inputs() -> Vec<String> {
     a
 }
 
+#[inline]
+#[cfg(feature = "flutter")]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn get_sound_outputs() -> Vec<String> {
+    #[cfg(not(target_os = "linux"))]
+    {
+        fn get_sound_outputs_() -> Vec<String> {
+            let mut out = Vec::new();
+            use cpal::traits::{DeviceTrait, HostTrait};
+            let host = cpal::default_host();
+            if let Ok(devices) = host.output_devices() {
+                for device in devices {
+                    if device.default_output_config().is_err() {
+                        continue;
+                    }
+                    if let Ok(name) = device.name() {
+                        out.push(name);
+                    }
+                }
+            }
+            out
+        }
+
+        // can not call below in UI thread, because conflict with sciter sound com initialization
+        std::thread::spawn(get_sound_outputs_)
+            .join()
+            .unwrap_or_default()
+    }
+    #[cfg(target_os = "linux")]
+    {
+        crate::platform::linux::get_pa_sinks()
+            .drain(..)
+            .map(|x| x.1)
+            .collect()
+    }
+}
+
 #[inline]
 pub fn set_options(m: HashMap<String, String>) {
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -1163,7 +1200,11 @@ async fn check_connect_status_(reconnect: bool, rx: mpsc::UnboundedReceiver<ipc:
                     res = c.next() => {
                         match res {
                             Err(err) => {
-                                log::error!("ipc connection closed: {}", err);
+                                crate::common::log_throttled(
+                                    "check-connect-status-ipc",
+                                    log::Level::Error,
+                                    format!("ipc connection closed: {}", err),
+                                );
                                 if is_cm {
                                     crate::ui_cm_interface::quit_cm();
                                 }
@@ -1522,6 +1563,64 @@ pub fn clear_trusted_devices() {
     ipc::clear_trusted_devices();
 }
 
+// Json array of [peer id, remaining seconds].
+#[cfg(feature = "flutter")]
+pub fn get_elevation_grants() -> String {
+    #[cfg(target_os = "windows")]
+    let grants = ipc::get_elevation_grants().unwrap_or_default();
+    #[cfg(not(target_os = "windows"))]
+    let grants: Vec<(String, u64)> = vec![];
+    serde_json::to_string(&grants).unwrap_or_default()
+}
+
+// Json array of [peer id, remaining seconds].
+#[cfg(feature = "flutter")]
+pub fn get_session_restores() -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    let records = ipc::get_session_restores().unwrap_or_default();
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    let records: Vec<(String, u64)> = vec![];
+    serde_json::to_string(&records).unwrap_or_default()
+}
+
+// Json of `audio_service::AudioStatus`, the service runs in the server process except on Android.
+#[cfg(feature = "flutter")]
+pub fn get_audio_status() -> String {
+    #[cfg(target_os = "android")]
+    let status = crate::audio_service::status();
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    let status = ipc::get_audio_status().unwrap_or_default();
+    #[cfg(target_os = "ios")]
+    return "".to_owned();
+    #[cfg(not(target_os = "ios"))]
+    serde_json::to_string(&status).unwrap_or_default()
+}
+
+// Json of `audio_exclusion::CaptureExclusions`, `names` is a comma separated list to set it.
+#[cfg(feature = "flutter")]
+pub fn audio_capture_exclusions(_names: Option<String>) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    {
+        let names = _names.map(|s| s.split(',').map(|x| x.to_owned()).collect());
+        let value = ipc::audio_capture_exclusions(names).unwrap_or_default();
+        return serde_json::to_string(&value).unwrap_or_default();
+    }
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    "".to_owned()
+}
+
+#[cfg(feature = "flutter")]
+pub fn revoke_elevation_grant(_peer_id: String) {
+    #[cfg(target_os = "windows")]
+    allow_err!(ipc::set_data(&ipc::Data::RevokeElevationGrant(_peer_id)));
+}
+
+#[cfg(feature = "flutter")]
+pub fn clear_session_restore(_peer_id: String) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    allow_err!(ipc::set_data(&ipc::Data::ClearSessionRestore(_peer_id)));
+}
+
 #[cfg(feature = "flutter")]
 pub fn max_encrypt_len() -> usize {
     hbb_common::config::ENCRYPT_MAX_LEN
@@ -1,5 +1,5 @@
 use crate::{
-    common::{get_supported_keyboard_modes, is_keyboard_mode_supported},
+    common::{get_supported_keyboard_modes, is_keyboard_mode_supported, Capability},
     input::{MOUSE_BUTTON_LEFT, MOUSE_TYPE_DOWN, MOUSE_TYPE_UP, MOUSE_TYPE_WHEEL},
     ui_interface::use_texture_render,
 };
@@ -44,6 +44,7 @@ use crate::keyboard;
 use crate::{client::Data, client::Interface};
 
 const CHANGE_RESOLUTION_VALID_TIMEOUT_SECS: u64 = 15;
+const UNSUPPORTED_BY_PEER: &str = "Unsupported by peer";
 
 #[derive(Clone, Default)]
 pub struct Session<T: InvokeUiSession> {
@@ -59,6 +60,9 @@ pub struct Session<T: InvokeUiSession> {
     pub last_change_display: Arc<Mutex<ChangeDisplayRecord>>,
     pub connection_round_state: Arc<Mutex<ConnectionRoundState>>,
     pub printer_names: Arc<RwLock<HashMap<i32, String>>>,
+    // The scroll gesture not sent yet as whole wheel notches, for the peers without
+    // `Capability::ScrollGesture`.
+    pub scroll_gesture_rest: Arc<Mutex<(i32, i32)>>,
 }
 
 #[derive(Clone)]
@@ -347,6 +351,12 @@ impl<T: InvokeUiSession> Session<T> {
     }
 
     pub fn toggle_option(&self, name: String) {
+        if name == "low-delay-audio"
+            && !self.get_toggle_option(name.clone())
+            && !self.check_peer_supports(Capability::LowDelayAudio)
+        {
+            return;
+        }
         let msg = self.lc.write().unwrap().toggle_option(name.clone());
         #[cfg(all(target_os = "windows", not(feature = "flutter")))]
         if name == keys::OPTION_ENABLE_FILE_COPY_PASTE {
@@ -415,6 +425,20 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg_out));
     }
 
+    pub fn set_virtual_display_mode(&self, index: i32, width: u32, height: u32, refresh_rate: u32) {
+        let mut misc = Misc::new();
+        misc.set_virtual_display_mode(VirtualDisplayMode {
+            display: index,
+            width,
+            height,
+            refresh_rate,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
     #[cfg(not(feature = "flutter"))]
     pub fn refresh_video(&self, _display: i32) {
         self.send(Data::Message(LoginConfigHandler::refresh()));
@@ -425,10 +449,14 @@ impl<T: InvokeUiSession> Session<T> {
     }
 
     pub fn is_screenshot_supported(&self) -> bool {
-        crate::common::is_support_screenshot_num(self.lc.read().unwrap().version)
+        self.lc.read().unwrap().supports(Capability::Screenshot)
     }
 
     pub fn take_screenshot(&self, display: i32, sid: String) {
+        if !self.is_screenshot_supported() {
+            self.handle_screenshot_resp(sid, UNSUPPORTED_BY_PEER.to_owned());
+            return;
+        }
         self.send(Data::TakeScreenshot((display, sid)));
     }
 
@@ -469,6 +497,11 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg));
     }
 
+    pub fn set_clipboard_line_ending(&self, value: String) {
+        let msg = self.lc.write().unwrap().set_clipboard_line_ending(value);
+        self.send(Data::Message(msg));
+    }
+
     pub fn get_remember(&self) -> bool {
         self.lc.read().unwrap().remember
     }
@@ -531,6 +564,98 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg));
     }
 
+    /// The host replies with a token, the request is sent again with it when the user confirms,
+    /// see `confirm_power_action`.
+    pub fn power_action(&self, action: String) {
+        let msg = self.lc.read().unwrap().power_action_request(&action, 0);
+        self.send(Data::Message(msg));
+    }
+
+    pub fn confirm_power_action(&self, confirmed: bool) {
+        let mut lc = self.lc.write().unwrap();
+        let Some((action, token)) = lc.power_action_pending.take() else {
+            return;
+        };
+        if confirmed {
+            let msg = lc.power_action_request(&action, token);
+            drop(lc);
+            self.send(Data::Message(msg));
+        }
+    }
+
+    /// Page 0 refreshes the process list on the host, at most once every 2 seconds.
+    pub fn request_process_list(&self, page: u32, page_size: u32) {
+        let msg = self
+            .lc
+            .read()
+            .unwrap()
+            .process_list_request(page, page_size);
+        self.send(Data::Message(msg));
+    }
+
+    /// The token is 0 in the first request, the reply has the token to confirm the kill.
+    pub fn kill_process(&self, pid: u32, token: u64) {
+        let msg = self.lc.read().unwrap().kill_process_request(pid, token);
+        self.send(Data::Message(msg));
+    }
+
+    /// See `session_fingerprint`, to compare with the one shown on the host.
+    pub fn get_session_fingerprint(&self) -> String {
+        self.lc
+            .read()
+            .unwrap()
+            .session_fingerprint
+            .clone()
+            .unwrap_or_else(|| crate::session_fingerprint::UNVERIFIED.to_owned())
+    }
+
+    /// The host replies with the directory of the capture, see `receive_diagnostic_capture`.
+    pub fn request_diagnostic_capture(&self, display: i32) {
+        let msg = self.lc.read().unwrap().diagnostic_capture_request(display);
+        self.send(Data::Message(msg));
+    }
+
+    /// Read the capture of the host into the download directory, returns the local directory.
+    pub fn receive_diagnostic_capture(&self, path: String) -> String {
+        let name = path
+            .rsplit(|c| c == '/' || c == '\\')
+            .find(|s| !s.is_empty())
+            .unwrap_or("diagnostic_capture")
+            .to_owned();
+        // The download directory of the user, as configured on the platform.
+        let dir = hbb_common::directories_next::UserDirs::new()
+            .and_then(|dirs| dirs.download_dir().map(|d| d.to_path_buf()))
+            .unwrap_or_else(|| {
+                std::path::PathBuf::from(hbb_common::fs::get_home_as_string()).join("Downloads")
+            });
+        let to = dir
+            .join(name)
+            .to_string_lossy()
+            .to_string();
+        self.send(Data::SendFiles((
+            hbb_common::fs::get_next_job_id(),
+            hbb_common::fs::JobType::Generic,
+            path,
+            to.clone(),
+            0,
+            false,
+            true,
+        )));
+        to
+    }
+
+    pub fn request_quick_actions(&self) {
+        let msg = self.lc.read().unwrap().quick_action_list_request();
+        self.send(Data::Message(msg));
+    }
+
+    /// The token is 0 in the first request, the reply has the token if the action needs the
+    /// confirmation.
+    pub fn run_quick_action(&self, name: String, token: u64) {
+        let msg = self.lc.read().unwrap().quick_action_request(&name, token);
+        self.send(Data::Message(msg));
+    }
+
     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     pub fn send_plugin_request(&self, request: PluginRequest) {
@@ -743,6 +868,84 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg_out));
     }
 
+    /// Ask the peer for its clipboard, it's only sent on request if the peer is in the manual mode.
+    pub fn request_clipboard(&self) {
+        let mut misc = Misc::new();
+        misc.set_request_clipboard(true);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    /// Request or release the control token, the peer only injects the input of the holder
+    /// in the exclusive control mode.
+    pub fn request_control_token(&self, request: bool) {
+        let mut misc = Misc::new();
+        misc.set_control_token_request(request);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    /// Change the text scaling of the peer for the session in percent, 0 restores it.
+    pub fn set_host_text_scale(&self, percent: u32) {
+        let mut misc = Misc::new();
+        misc.set_text_scale(percent);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    /// Drawn on the display of the peer, the points are the x and y in the pixels of the display
+    /// one after another, see `annotation`.
+    pub fn send_annotation(
+        &self,
+        display: i32,
+        shape: i32,
+        points: Vec<i32>,
+        color: u32,
+        width: u32,
+        text: String,
+        font_size: u32,
+    ) {
+        let mut misc = Misc::new();
+        misc.set_annotation(Annotation {
+            display,
+            shape: annotation::Shape::from_i32(shape)
+                .unwrap_or(annotation::Shape::Clear)
+                .into(),
+            points: points
+                .chunks_exact(2)
+                .map(|p| AnnotationPoint {
+                    x: p[0],
+                    y: p[1],
+                    ..Default::default()
+                })
+                .collect(),
+            color,
+            width,
+            text,
+            font_size,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    // The window is selected by the id, or by the title if the id is 0.
+    pub fn capture_window(&self, id: u64, title: String) {
+        let mut misc = Misc::new();
+        misc.set_capture_window(CaptureWindow {
+            id,
+            title,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
     pub fn send_chat(&self, text: String) {
         let mut misc = Misc::new();
         misc.set_chat_message(ChatMessage {
@@ -850,6 +1053,53 @@ impl<T: InvokeUiSession> Session<T> {
         keyboard::client::change_grab_status(GrabState::Wait, &keyboard_mode);
     }
 
+    // The window of the session gains or loses the focus, not the pointer entering or leaving the
+    // view. Let the peer release the keys still held down, e.g. Ctrl of Ctrl+Alt+Tab.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn send_client_focus(&self, focused: bool) {
+        if !self.lc.read().unwrap().supports(Capability::ClientFocus) {
+            return;
+        }
+        let modifiers = if focused {
+            keyboard::client::get_pressed_modifiers()
+                .into_iter()
+                .map(|x| x.into())
+                .collect()
+        } else {
+            vec![]
+        };
+        let mut misc = Misc::new();
+        misc.set_client_focus(ClientFocus {
+            focused,
+            modifiers,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    // Let the peer lower the fps while nobody is watching.
+    pub fn send_client_visibility(&self, visible: bool, minimized: bool) {
+        if !self
+            .lc
+            .read()
+            .unwrap()
+            .supports(Capability::ClientVisibility)
+        {
+            return;
+        }
+        let mut misc = Misc::new();
+        misc.set_client_visibility(ClientVisibility {
+            visible,
+            minimized,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
     // flutter only TODO new input
     pub fn input_key(
         &self,
@@ -1108,6 +1358,9 @@ impl<T: InvokeUiSession> Session<T> {
     }
 
     pub fn send_touch_scale(&self, scale: i32, alt: bool, ctrl: bool, shift: bool, command: bool) {
+        if !self.lc.read().unwrap().supports(Capability::TouchInput) {
+            return;
+        }
         let scale_evt = TouchScaleUpdate {
             scale,
             ..Default::default()
@@ -1129,6 +1382,13 @@ impl<T: InvokeUiSession> Session<T> {
         shift: bool,
         command: bool,
     ) {
+        if !self.lc.read().unwrap().supports(Capability::TouchInput) {
+            // Only once per gesture.
+            if event == "pan_start" {
+                self.check_peer_supports(Capability::TouchInput);
+            }
+            return;
+        }
         let mut touch_evt = TouchEvent::new();
         match event {
             "pan_start" => {
@@ -1163,6 +1423,79 @@ impl<T: InvokeUiSession> Session<T> {
         send_pointer_device_event(evt, alt, ctrl, shift, command, self);
     }
 
+    /// Send a scroll gesture of a touch or pen, `phase` is "begin", "update", "end" or "cancel".
+    /// The deltas and the velocities (per second) are in the wheel units, 120 for one notch.
+    pub fn send_scroll_gesture(
+        &self,
+        phase: &str,
+        dx: i32,
+        dy: i32,
+        vx: i32,
+        vy: i32,
+        alt: bool,
+        ctrl: bool,
+        shift: bool,
+        command: bool,
+    ) {
+        const WHEEL_UNIT: i32 = 120;
+        let phase = match phase {
+            "begin" => scroll_gesture::Phase::Begin,
+            "update" => scroll_gesture::Phase::Update,
+            "end" => scroll_gesture::Phase::End,
+            "cancel" => scroll_gesture::Phase::Cancel,
+            _ => {
+                log::warn!("unknown scroll gesture phase: {}", phase);
+                return;
+            }
+        };
+        if !self.lc.read().unwrap().supports(Capability::ScrollGesture) {
+            // Only the whole notches, the host can't scroll less or flick.
+            let (x, y) = {
+                let mut rest = self.scroll_gesture_rest.lock().unwrap();
+                if phase == scroll_gesture::Phase::Begin {
+                    *rest = (0, 0);
+                }
+                rest.0 += dx;
+                rest.1 += dy;
+                let notches = (rest.0 / WHEEL_UNIT, rest.1 / WHEEL_UNIT);
+                rest.0 -= notches.0 * WHEEL_UNIT;
+                rest.1 -= notches.1 * WHEEL_UNIT;
+                notches
+            };
+            if x != 0 || y != 0 {
+                self.send_mouse(MOUSE_TYPE_WHEEL, x, y, alt, ctrl, shift, command);
+            }
+            return;
+        }
+        let (dx, dy) = self.get_scroll_xy((dx, dy));
+        let (velocity_x, velocity_y) = self.get_scroll_xy((vx, vy));
+        let mut evt = PointerDeviceEvent::new();
+        evt.set_scroll_gesture(ScrollGesture {
+            phase: phase.into(),
+            dx,
+            dy,
+            velocity_x,
+            velocity_y,
+            ..Default::default()
+        });
+        send_pointer_device_event(evt, alt, ctrl, shift, command, self);
+    }
+
+    /// Tell the user instead of sending a message the peer can not handle.
+    fn check_peer_supports(&self, cap: Capability) -> bool {
+        if self.lc.read().unwrap().supports(cap) {
+            return true;
+        }
+        log::warn!("{:?} is unsupported by peer", cap);
+        self.msgbox(
+            "custom-nook-nocancel-hasclose",
+            "Error",
+            UNSUPPORTED_BY_PEER,
+            "",
+        );
+        false
+    }
+
     #[inline]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     fn is_scroll_reverse_mode(&self) -> bool {
@@ -1627,6 +1960,15 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
         is_local: bool,
         only_count: bool,
     );
+    // A page of a paged listing of a remote directory, the ui collects them until `done`.
+    fn update_folder_files_page(
+        &self,
+        entries: &Vec<FileEntry>,
+        path: String,
+        offset: u64,
+        total: u64,
+        done: bool,
+    );
     fn confirm_delete_files(&self, id: i32, i: i32, name: String);
     fn override_file_confirm(
         &self,
@@ -1660,6 +2002,12 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
     fn is_multi_ui_session(&self) -> bool;
     fn update_record_status(&self, start: bool);
     fn update_empty_dirs(&self, _res: ReadEmptyDirsResponse) {}
+    fn update_control_token(&self, _holding: bool, _holder: String, _queued: bool) {}
+    fn update_process_list(&self, _list: ProcessList) {}
+    fn on_kill_process_response(&self, _res: KillProcessResponse) {}
+    fn update_quick_actions(&self, _list: QuickActionList) {}
+    fn on_quick_action_response(&self, _res: QuickActionResponse) {}
+    fn on_quick_action_output(&self, _output: QuickActionOutput) {}
     fn printer_request(&self, id: i32, path: String);
     fn handle_screenshot_resp(&self, sid: String, msg: String);
     fn handle_terminal_response(&self, response: TerminalResponse);
@@ -1817,6 +2165,12 @@ impl<T: InvokeUiSession> Interface for Session<T> {
             self.update_quality_status(QualityStatus {
                 delay: Some(t.last_delay as _),
                 target_bitrate: Some(t.target_bitrate as _),
+                display_target_bitrates: t
+                    .display_target_bitrates
+                    .iter()
+                    .map(|(k, v)| (*k as usize, *v as i32))
+                    .collect(),
+                frame_drops: t.frame_drops.clone(),
                 ..Default::default()
             });
             handle_test_delay(t, peer).await;
@@ -1,4 +1,8 @@
-use hbb_common::{bail, platform::windows::is_windows_version_or_greater, ResultType};
+use hbb_common::{
+    bail, lazy_static, log, platform::windows::is_windows_version_or_greater, ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Mutex;
 
 // This string is defined here.
 //  https://github.com/rustdesk-org/RustDeskIddDriver/blob/b370aad3f50028b039aad211df60c8051c4a64d6/RustDeskIddDriver/RustDeskIddDriver.inf#LL73C1-L73C40
@@ -10,6 +14,21 @@ const IDD_IMPL_RUSTDESK: &str = "rustdesk_idd";
 const IDD_IMPL_AMYUNI: &str = "amyuni_idd";
 const IDD_PLUG_OUT_ALL_INDEX: i32 = -1;
 
+lazy_static::lazy_static! {
+    // The last error when talking to the virtual display driver.
+    // It is reported to the peer and the IPC status query, instead of only being logged.
+    static ref LAST_ERROR: Mutex<Option<String>> = Default::default();
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VirtualDisplayStatus {
+    pub idd_impl: String,
+    pub supported: bool,
+    pub driver_installed: bool,
+    pub count: usize,
+    pub last_error: Option<String>,
+}
+
 pub fn is_amyuni_idd() -> bool {
     IDD_IMPL == IDD_IMPL_AMYUNI
 }
@@ -34,13 +53,92 @@ pub fn is_virtual_display_supported() -> bool {
 }
 
 pub fn plug_in_headless() -> ResultType<()> {
-    match IDD_IMPL {
+    let res = match IDD_IMPL {
         IDD_IMPL_RUSTDESK => rustdesk_idd::plug_in_headless(),
         IDD_IMPL_AMYUNI => amyuni_idd::plug_in_headless(),
         _ => bail!("Unsupported virtual display implementation."),
+    };
+    record_result(res)
+}
+
+pub fn is_driver_installed() -> bool {
+    match IDD_IMPL {
+        IDD_IMPL_RUSTDESK => rustdesk_idd::is_driver_installed(),
+        IDD_IMPL_AMYUNI => amyuni_idd::is_driver_installed(),
+        _ => false,
     }
 }
 
+pub fn get_virtual_display_count() -> usize {
+    match IDD_IMPL {
+        IDD_IMPL_RUSTDESK => rustdesk_idd::get_virtual_display_count(),
+        IDD_IMPL_AMYUNI => amyuni_idd::get_monitor_count(),
+        _ => 0,
+    }
+}
+
+pub fn get_status() -> VirtualDisplayStatus {
+    let supported = is_virtual_display_supported();
+    VirtualDisplayStatus {
+        idd_impl: IDD_IMPL.to_owned(),
+        supported,
+        driver_installed: supported && is_driver_installed(),
+        count: if supported {
+            get_virtual_display_count()
+        } else {
+            0
+        },
+        last_error: LAST_ERROR.lock().unwrap().clone(),
+    }
+}
+
+#[inline]
+pub fn get_last_error() -> Option<String> {
+    LAST_ERROR.lock().unwrap().clone()
+}
+
+// Keep the last driver error, so that it can be reported to the peer or queried by IPC.
+fn record_result<T>(res: ResultType<T>) -> ResultType<T> {
+    match res {
+        Ok(v) => {
+            *LAST_ERROR.lock().unwrap() = None;
+            Ok(v)
+        }
+        Err(e) => {
+            let err = e.to_string();
+            log::error!("Virtual display driver error: {}", &err);
+            *LAST_ERROR.lock().unwrap() = Some(err.clone());
+            bail!(err)
+        }
+    }
+}
+
+// Translate the driver error to a message that tells the user what to do.
+pub fn get_actionable_error(e: &str) -> String {
+    if !is_virtual_display_supported() {
+        return "idd_not_support_under_win10_2004_tip".to_owned();
+    }
+    if !crate::platform::is_installed() {
+        return "Virtual display is only available in the installed version. Please install RustDesk first.".to_owned();
+    }
+    if !is_driver_installed() {
+        return format!(
+            "The virtual display driver is not installed or failed to load ({}). Please reinstall RustDesk, or install the driver manually, and try again.",
+            e
+        );
+    }
+    if e.contains("managed by other processes") {
+        return format!(
+            "{}. Please plug out the virtual display in the application that created it.",
+            e.trim_end_matches('.')
+        );
+    }
+    format!(
+        "Failed to talk to the virtual display driver: {}. Please check the driver status in the Device Manager, or restart the RustDesk service.",
+        e.trim_end_matches('.')
+    )
+}
+
 pub fn get_platform_additions() -> serde_json::Map<String, serde_json::Value> {
     let mut map = serde_json::Map::new();
     if !crate::platform::windows::is_self_service_running() {
@@ -68,16 +166,39 @@ pub fn get_platform_additions() -> serde_json::Map<String, serde_json::Value> {
     map
 }
 
+// The indices of the plugged in virtual displays, 0..count for amyuni.
+pub fn get_virtual_displays() -> Vec<u32> {
+    match IDD_IMPL {
+        IDD_IMPL_RUSTDESK => rustdesk_idd::get_virtual_displays(),
+        IDD_IMPL_AMYUNI => (0..amyuni_idd::get_monitor_count() as u32).collect(),
+        _ => vec![],
+    }
+}
+
 #[inline]
 pub fn plug_in_monitor(idx: u32, modes: Vec<virtual_display::MonitorMode>) -> ResultType<()> {
-    match IDD_IMPL {
+    let res = match IDD_IMPL {
         IDD_IMPL_RUSTDESK => rustdesk_idd::plug_in_index_modes(idx, modes),
         IDD_IMPL_AMYUNI => amyuni_idd::plug_in_monitor(),
         _ => bail!("Unsupported virtual display implementation."),
-    }
+    };
+    record_result(res)
 }
 
 pub fn plug_out_monitor(index: i32, force_all: bool, force_one: bool) -> ResultType<()> {
+    record_result(plug_out_monitor_(index, force_all, force_one))
+}
+
+pub fn set_monitor_mode(idx: u32, mode: virtual_display::MonitorMode) -> ResultType<()> {
+    let res = match IDD_IMPL {
+        IDD_IMPL_RUSTDESK => rustdesk_idd::set_index_mode(idx, mode),
+        IDD_IMPL_AMYUNI => bail!("This virtual display doesn't support setting the mode."),
+        _ => bail!("Unsupported virtual display implementation."),
+    };
+    record_result(res)
+}
+
+fn plug_out_monitor_(index: i32, force_all: bool, force_one: bool) -> ResultType<()> {
     match IDD_IMPL {
         IDD_IMPL_RUSTDESK => {
             let indices = if index == IDD_PLUG_OUT_ALL_INDEX {
@@ -185,6 +306,18 @@ pub mod rustdesk_idd {
         }
     }
 
+    pub fn is_driver_installed() -> bool {
+        VIRTUAL_DISPLAY_MANAGER.lock().unwrap().is_driver_installed
+            || windows::get_display_drivers()
+                .iter()
+                .any(|(s, c)| s == super::RUSTDESK_IDD_DEVICE_STRING && *c == 0)
+    }
+
+    pub fn get_virtual_display_count() -> usize {
+        let manager = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
+        manager.peer_index_name.len() + manager.headless_index_name.iter().count()
+    }
+
     pub fn install_update_driver() -> ResultType<()> {
         VIRTUAL_DISPLAY_MANAGER
             .lock()
@@ -364,6 +497,16 @@ pub mod rustdesk_idd {
         }
     }
 
+    pub fn set_index_mode(idx: u32, mode: virtual_display::MonitorMode) -> ResultType<()> {
+        let manager = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
+        let plugged_in = manager.peer_index_name.contains_key(&idx)
+            || matches!(&manager.headless_index_name, Some((i, _)) if *i == idx);
+        if !plugged_in {
+            bail!("The virtual display {} is not plugged in.", idx);
+        }
+        virtual_display::update_monitor_modes(idx, &[mode])
+    }
+
     pub fn change_resolution_if_is_virtual_display(name: &str, w: u32, h: u32) -> Option<bool> {
         let lock = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
         if let Some((index, device_name)) = &lock.headless_index_name {
@@ -727,6 +870,13 @@ pub mod amyuni_idd {
         windows::get_device_names(Some(super::AMYUNI_IDD_DEVICE_STRING)).len()
     }
 
+    #[inline]
+    pub fn is_driver_installed() -> bool {
+        windows::get_display_drivers()
+            .iter()
+            .any(|(s, c)| s == super::AMYUNI_IDD_DEVICE_STRING && *c == 0)
+    }
+
     #[inline]
     pub fn is_my_display(name: &str) -> bool {
         windows::get_device_names(Some(super::AMYUNI_IDD_DEVICE_STRING))